@@ -0,0 +1,150 @@
+use crate::level::MIN_DBFS;
+
+#[derive(Clone, Debug)]
+pub struct AgcConfig {
+    /// RMS level, in dBFS, the gain steers towards.
+    pub target_dbfs: f32,
+    /// Time constant for lowering the gain when the input gets louder.
+    pub attack_ms: u32,
+    /// Time constant for raising the gain when the input gets quieter.
+    pub release_ms: u32,
+    /// Upper bound of the gain, in dB, so noise between sentences isn't blown up.
+    pub max_gain_db: f32,
+    /// Input below this RMS level, in dBFS, is treated as silence and leaves the gain as it
+    /// is.
+    pub silence_dbfs: f32,
+}
+
+impl Default for AgcConfig {
+    fn default() -> Self {
+        AgcConfig {
+            target_dbfs: -20.0,
+            attack_ms: 50,
+            release_ms: 2000,
+            max_gain_db: 30.0,
+            silence_dbfs: -60.0,
+        }
+    }
+}
+
+/// Automatic gain control steering the RMS level of audio towards a target.
+///
+/// Each buffer is measured before the gain is applied, and the gain moves towards the one
+/// that would bring the buffer to the target with an exponential curve, fast when it has to
+/// come down and slowly when it has to go up. During silence the gain is frozen instead of
+/// creeping up to the cap.
+pub struct Agc {
+    config: AgcConfig,
+    gain_db: f32,
+}
+
+impl Agc {
+    pub fn new(config: AgcConfig) -> Self {
+        Agc {
+            config,
+            gain_db: 0.0,
+        }
+    }
+
+    pub fn gain_db(&self) -> f32 {
+        self.gain_db
+    }
+
+    /// Measures a buffer of mono samples at `sample_rate` and returns the gain to apply to
+    /// it, in dB.
+    pub fn update(&mut self, samples: &[f32], sample_rate: u32) -> f32 {
+        let level = rms_dbfs(samples);
+        if level < self.config.silence_dbfs {
+            return self.gain_db;
+        }
+        let wanted = (self.config.target_dbfs - level).min(self.config.max_gain_db);
+        let time_constant = if wanted < self.gain_db {
+            self.config.attack_ms
+        } else {
+            self.config.release_ms
+        };
+        let elapsed_ms = samples.len() as f32 * 1000.0 / sample_rate as f32;
+        let step = 1.0 - (-elapsed_ms / time_constant.max(1) as f32).exp();
+        self.gain_db += (wanted - self.gain_db) * step;
+        self.gain_db
+    }
+}
+
+fn rms_dbfs(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return MIN_DBFS;
+    }
+    let sum = samples.iter().map(|&s| s * s).sum::<f32>();
+    (10.0 * (sum / samples.len() as f32).log10()).max(MIN_DBFS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 16000;
+
+    /// `seconds` of a 440 Hz sine with the given RMS level, in 10 ms buffers.
+    fn sine(rms_dbfs: f32, seconds: f32) -> Vec<Vec<f32>> {
+        let amplitude = 10f32.powf(rms_dbfs / 20.0) * std::f32::consts::SQRT_2;
+        let samples = (0..(RATE as f32 * seconds) as usize)
+            .map(|i| {
+                let t = i as f32 / RATE as f32;
+                (2.0 * std::f32::consts::PI * 440.0 * t).sin() * amplitude
+            })
+            .collect::<Vec<_>>();
+        samples.chunks(160).map(|chunk| chunk.to_vec()).collect()
+    }
+
+    /// Runs the buffers through `agc`, returning the RMS level of the last one after gain.
+    fn run(agc: &mut Agc, buffers: &[Vec<f32>]) -> f32 {
+        let mut output = vec![];
+        for buffer in buffers {
+            let gain = 10f32.powf(agc.update(buffer, RATE) / 20.0);
+            output = buffer.iter().map(|s| s * gain).collect();
+        }
+        rms_dbfs(&output)
+    }
+
+    #[test]
+    fn converges_on_quiet_then_loud_input() {
+        let mut agc = Agc::new(AgcConfig::default());
+        // Quiet input is slowly brought up to the target.
+        let level = run(&mut agc, &sine(-40.0, 10.0));
+        assert!((level - -20.0).abs() < 0.5, "quiet input at {} dBFS", level);
+        assert!((agc.gain_db() - 20.0).abs() < 0.5);
+
+        // A loud sine right after is brought down within a fraction of a second.
+        let loud = sine(-6.0, 1.0);
+        let level = run(&mut agc, &loud[..30]);
+        assert!((level - -20.0).abs() < 1.0, "loud input at {} dBFS", level);
+        let level = run(&mut agc, &loud[30..]);
+        assert!((level - -20.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn releases_slowly() {
+        let mut agc = Agc::new(AgcConfig::default());
+        run(&mut agc, &sine(-30.0, 0.5));
+        // Half a second into a 2 s release, the gain is still well short of +10 dB.
+        assert!(agc.gain_db() > 1.0 && agc.gain_db() < 4.0);
+    }
+
+    #[test]
+    fn freezes_on_silence() {
+        let mut agc = Agc::new(AgcConfig::default());
+        run(&mut agc, &sine(-30.0, 10.0));
+        let gain = agc.gain_db();
+        run(&mut agc, &vec![vec![0.0; 160]; 500]);
+        run(&mut agc, &sine(-70.0, 5.0));
+        assert_eq!(agc.gain_db(), gain);
+    }
+
+    #[test]
+    fn caps_the_gain() {
+        let mut agc = Agc::new(AgcConfig::default());
+        run(&mut agc, &sine(-58.0, 30.0));
+        assert!(agc.gain_db() <= 30.0);
+        assert!(agc.gain_db() > 29.0);
+    }
+}
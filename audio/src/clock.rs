@@ -0,0 +1,78 @@
+use cpal::StreamInstant;
+
+/// Converts the capture instants a stream reports to wall-clock time.
+///
+/// Stream instants come from the device clock, which is monotonic but has an arbitrary
+/// epoch. The first buffer anchors it to the wall clock, taking the latency between capture
+/// and callback into account; later buffers are placed relative to that anchor, so the
+/// times follow the device instead of when the callbacks happened to run.
+#[derive(Default)]
+pub struct StreamClock {
+    anchor: Option<(StreamInstant, u64)>,
+}
+
+impl StreamClock {
+    pub fn new() -> Self {
+        StreamClock::default()
+    }
+
+    /// Returns the capture time, in milliseconds since the Unix epoch, of a buffer captured
+    /// at `capture` and delivered at `callback`, with `now_ms` the wall-clock time of the
+    /// callback.
+    pub fn capture_time(
+        &mut self,
+        capture: StreamInstant,
+        callback: StreamInstant,
+        now_ms: u64,
+    ) -> u64 {
+        let (anchor, anchor_ms) = *self.anchor.get_or_insert_with(|| {
+            let latency = callback.duration_since(&capture).unwrap_or_default();
+            (capture, now_ms.saturating_sub(latency.as_millis() as u64))
+        });
+        match capture.duration_since(&anchor) {
+            Some(elapsed) => anchor_ms + elapsed.as_millis() as u64,
+            None => {
+                let before = anchor.duration_since(&capture).unwrap_or_default();
+                anchor_ms.saturating_sub(before.as_millis() as u64)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instant(ms: u64) -> StreamInstant {
+        StreamInstant::new((ms / 1000) as i64, (ms % 1000) as u32 * 1_000_000)
+    }
+
+    #[test]
+    fn anchors_the_first_buffer_before_its_callback() {
+        let mut clock = StreamClock::new();
+        // Captured 30 ms before the callback ran.
+        assert_eq!(
+            clock.capture_time(instant(500), instant(530), 10_000),
+            9_970
+        );
+    }
+
+    #[test]
+    fn follows_the_device_clock() {
+        let mut clock = StreamClock::new();
+        clock.capture_time(instant(500), instant(530), 10_000);
+        // The wall clock at the callback is late by scheduling jitter; the device isn't.
+        assert_eq!(
+            clock.capture_time(instant(510), instant(545), 10_090),
+            9_980
+        );
+        assert_eq!(
+            clock.capture_time(instant(520), instant(530), 10_000),
+            9_990
+        );
+        assert_eq!(
+            clock.capture_time(instant(490), instant(520), 10_000),
+            9_960
+        );
+    }
+}
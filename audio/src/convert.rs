@@ -0,0 +1,134 @@
+//! Conversion of f32 samples to i16, the format all audio leaves the recorder in.
+//!
+//! Samples are clamped to full scale before scaling, so drivers delivering peaks beyond
+//! 1.0 saturate instead of wrapping around, and NaN becomes silence.
+
+/// Full scale of i16 as a float; -1.0 maps to `i16::MIN`, 1.0 saturates at `i16::MAX`.
+const SCALE: f32 = 32768.0;
+
+/// Converts one sample, rounding to the nearest step.
+pub fn f32_to_i16(sample: f32) -> i16 {
+    quantize(clamp(sample) * SCALE)
+}
+
+/// Converts `input` into `output`, which must have the same length, adding dither if
+/// given.
+///
+/// Panics if the lengths differ.
+pub fn f32_to_i16_slice(input: &[f32], output: &mut [i16], dither: Option<&mut Dither>) {
+    assert_eq!(input.len(), output.len(), "buffers differ in length");
+    match dither {
+        Some(dither) => {
+            for (output, &sample) in output.iter_mut().zip(input) {
+                *output = quantize(clamp(sample) * SCALE + dither.sample());
+            }
+        }
+        None => {
+            for (output, &sample) in output.iter_mut().zip(input) {
+                *output = f32_to_i16(sample);
+            }
+        }
+    }
+}
+
+fn clamp(sample: f32) -> f32 {
+    if sample.is_nan() {
+        0.0
+    } else {
+        sample.clamp(-1.0, 1.0)
+    }
+}
+
+fn quantize(scaled: f32) -> i16 {
+    // Float to int casts saturate.
+    scaled.round() as i16
+}
+
+/// Triangular (TPDF) dither of up to one step either way, which turns the error of
+/// rounding to 16 bits into a faint noise floor instead of distortion that follows the
+/// signal. Quiet passages, which otherwise only touch the lowest few bits, benefit most.
+pub struct Dither {
+    // xorshift32 state; never zero.
+    state: u32,
+}
+
+impl Default for Dither {
+    fn default() -> Self {
+        Dither::new(0x9e37_79b9)
+    }
+}
+
+impl Dither {
+    pub fn new(seed: u32) -> Self {
+        Dither { state: seed.max(1) }
+    }
+
+    /// Next dither value in steps of the output, between -1.0 and 1.0.
+    fn sample(&mut self) -> f32 {
+        self.uniform() + self.uniform() - 1.0
+    }
+
+    /// Uniform value from 0.0 up to 1.0.
+    fn uniform(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saturates_out_of_range_input() {
+        assert_eq!(f32_to_i16(1.5), i16::MAX);
+        assert_eq!(f32_to_i16(-1.5), i16::MIN);
+        assert_eq!(f32_to_i16(f32::INFINITY), i16::MAX);
+        assert_eq!(f32_to_i16(f32::NAN), 0);
+    }
+
+    #[test]
+    fn full_scale_is_exact() {
+        assert_eq!(f32_to_i16(1.0), i16::MAX);
+        assert_eq!(f32_to_i16(-1.0), i16::MIN);
+        assert_eq!(f32_to_i16(0.5), 16384);
+        assert_eq!(f32_to_i16(-0.25), -8192);
+        assert_eq!(f32_to_i16(0.0), 0);
+    }
+
+    #[test]
+    fn converts_slices() {
+        let mut output = [0; 4];
+        f32_to_i16_slice(&[1.5, -1.5, 0.5, 0.0], &mut output, None);
+        assert_eq!(output, [i16::MAX, i16::MIN, 16384, 0]);
+        // Dither can't push full scale past the limits either.
+        f32_to_i16_slice(
+            &[1.0, -1.0, 1.5, -1.5],
+            &mut output,
+            Some(&mut Dither::default()),
+        );
+        assert!(output[0] >= i16::MAX - 1 && output[1] <= i16::MIN + 1);
+        assert!(output[2] >= i16::MAX - 1 && output[3] <= i16::MIN + 1);
+    }
+
+    #[test]
+    fn dither_spreads_steps_but_keeps_the_mean() {
+        // A level 0.3 steps above 100, which plain rounding always turns into 100.
+        let level = 100.3 / SCALE;
+        let input = vec![level; 100_000];
+        let mut output = vec![0; input.len()];
+
+        f32_to_i16_slice(&input, &mut output, None);
+        assert!(output.iter().all(|&s| s == 100));
+
+        f32_to_i16_slice(&input, &mut output, Some(&mut Dither::default()));
+        assert!(output.iter().all(|&s| (99..=102).contains(&s)));
+        for step in [99, 100, 101] {
+            assert!(output.contains(&step), "no sample at {}", step);
+        }
+        let mean = output.iter().map(|&s| s as f64).sum::<f64>() / output.len() as f64;
+        assert!((mean - 100.3).abs() < 0.02, "mean {}", mean);
+    }
+}
@@ -0,0 +1,161 @@
+//! Noise suppression with RNNoise, through its pure Rust port nnnoiseless.
+
+use nnnoiseless::DenoiseState;
+
+/// Sample rate RNNoise works at.
+pub const DENOISE_SAMPLE_RATE: u32 = 48000;
+
+/// Samples RNNoise processes at a time, 10 ms at 48 kHz.
+pub const DENOISE_FRAME_LEN: usize = DenoiseState::FRAME_SIZE;
+
+/// Removes stationary noise like fans and hum, and to some degree keyboard clatter, from
+/// mono 48 kHz audio.
+///
+/// Audio is processed in frames of `DENOISE_FRAME_LEN`; samples that don't fill a frame
+/// are held for the next call, so output lags input by less than one frame.
+pub struct Denoiser {
+    state: Box<DenoiseState<'static>>,
+    // Input of the frame being filled, in the i16 range RNNoise expects.
+    input: Vec<f32>,
+    output: Vec<f32>,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser::new()
+    }
+}
+
+impl Denoiser {
+    pub fn new() -> Self {
+        Denoiser {
+            state: DenoiseState::new(),
+            input: Vec::with_capacity(DENOISE_FRAME_LEN),
+            output: vec![0.0; DENOISE_FRAME_LEN],
+        }
+    }
+
+    /// Number of samples held back waiting for a frame to fill.
+    pub fn buffered(&self) -> usize {
+        self.input.len()
+    }
+
+    /// Denoises `samples`, returning the samples of every frame completed by them.
+    pub fn process(&mut self, samples: &[i16]) -> Vec<i16> {
+        let mut denoised = Vec::with_capacity(samples.len() + DENOISE_FRAME_LEN);
+        for &sample in samples {
+            self.input.push(sample as f32);
+            if self.input.len() == DENOISE_FRAME_LEN {
+                self.state.process_frame(&mut self.output, &self.input);
+                self.input.clear();
+                denoised.extend(self.output.iter().map(|&s| s.round() as i16));
+            }
+        }
+        denoised
+    }
+
+    /// Denoises the samples held back, padding them to a frame with silence.
+    pub fn flush(&mut self) -> Vec<i16> {
+        let len = self.input.len();
+        if len == 0 {
+            return vec![];
+        }
+        self.input.resize(DENOISE_FRAME_LEN, 0.0);
+        self.state.process_frame(&mut self.output, &self.input);
+        self.input.clear();
+        self.output[..len]
+            .iter()
+            .map(|&s| s.round() as i16)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: usize = DENOISE_SAMPLE_RATE as usize;
+
+    /// Two seconds of a voiced, speech-like sound: harmonics of a gliding 150 Hz pitch
+    /// with syllable-rate amplitude modulation.
+    fn speech() -> Vec<f32> {
+        (0..RATE * 2)
+            .map(|i| {
+                let t = i as f32 / RATE as f32;
+                let pitch = 150.0 + 30.0 * (2.0 * std::f32::consts::PI * 0.5 * t).sin();
+                let envelope = 0.5 + 0.5 * (2.0 * std::f32::consts::PI * 4.0 * t).sin();
+                let voice = (1..=8)
+                    .map(|h| {
+                        let phase = 2.0 * std::f32::consts::PI * pitch * h as f32 * t;
+                        phase.sin() / h as f32
+                    })
+                    .sum::<f32>();
+                voice * envelope * 3000.0
+            })
+            .collect()
+    }
+
+    /// White noise of the given peak amplitude, from a fixed seed.
+    fn noise(len: usize, amplitude: f32) -> Vec<f32> {
+        let mut state = 0x1234_5678u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state as f32 / u32::MAX as f32 * 2.0 - 1.0) * amplitude
+            })
+            .collect()
+    }
+
+    fn snr_db(clean: &[f32], signal: &[f32]) -> f32 {
+        let power = clean.iter().map(|s| s * s).sum::<f32>();
+        let error = clean
+            .iter()
+            .zip(signal)
+            .map(|(c, s)| (c - s) * (c - s))
+            .sum::<f32>();
+        10.0 * (power / error).log10()
+    }
+
+    #[test]
+    fn improves_snr_of_noisy_speech() {
+        let clean = speech();
+        let noisy = clean
+            .iter()
+            .zip(noise(clean.len(), 2000.0))
+            .map(|(s, n)| (s + n) as i16)
+            .collect::<Vec<_>>();
+        let mut denoiser = Denoiser::new();
+        let mut denoised = denoiser.process(&noisy);
+        denoised.extend(denoiser.flush());
+        assert_eq!(denoised.len(), noisy.len());
+
+        // The model needs a moment to estimate the noise; judge the second half.
+        let half = clean.len() / 2;
+        let noisy = noisy[half..].iter().map(|&s| s as f32).collect::<Vec<_>>();
+        let denoised = denoised[half..]
+            .iter()
+            .map(|&s| s as f32)
+            .collect::<Vec<_>>();
+        let before = snr_db(&clean[half..], &noisy);
+        let after = snr_db(&clean[half..], &denoised);
+        assert!(
+            after > before + 3.0,
+            "SNR {} dB before, {} dB after",
+            before,
+            after
+        );
+    }
+
+    #[test]
+    fn holds_less_than_a_frame() {
+        let mut denoiser = Denoiser::new();
+        assert!(denoiser.process(&[0; 300]).is_empty());
+        assert_eq!(denoiser.buffered(), 300);
+        assert_eq!(denoiser.process(&[0; 300]).len(), DENOISE_FRAME_LEN);
+        assert_eq!(denoiser.buffered(), 600 - DENOISE_FRAME_LEN);
+        assert_eq!(denoiser.flush().len(), 600 - DENOISE_FRAME_LEN);
+        assert_eq!(denoiser.buffered(), 0);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::convert::f32_to_i16;
+use cpal::{FromSample, Sample};
+
+/// Converts interleaved samples of any format to mono i16.
+///
+/// Frames are either averaged over all channels or reduced to one selected channel. A
+/// partial frame at the end of a buffer is kept and completed by the next call to
+/// `process`, so buffers don't need to be a multiple of the channel count.
+pub struct Downmixer {
+    channels: usize,
+    channel: Option<usize>,
+    /// Samples of the partial frame left from the last buffer, kept to reuse its allocation.
+    pending: Vec<f32>,
+}
+
+impl Downmixer {
+    /// Creates a downmixer averaging all `channels`, or keeping only `channel` if set.
+    ///
+    /// Panics if `channels` is zero or `channel` is out of range.
+    pub fn new(channels: u16, channel: Option<u16>) -> Self {
+        assert!(channels > 0, "channel count must be positive");
+        if let Some(channel) = channel {
+            assert!(
+                channel < channels,
+                "channel {} out of range for {} channels",
+                channel,
+                channels
+            );
+        }
+        Downmixer {
+            channels: channels as usize,
+            channel: channel.map(|channel| channel as usize),
+            pending: Vec::with_capacity(channels as usize),
+        }
+    }
+
+    /// Downmixes `data`, clipping out of range samples.
+    pub fn process<T>(&mut self, data: &[T]) -> Vec<i16>
+    where
+        T: Sample,
+        f32: FromSample<T>,
+    {
+        self.process_f32(data).into_iter().map(f32_to_i16).collect()
+    }
+
+    /// Downmixes `data` to f32 samples in the range -1.0 to 1.0, leaving the conversion to
+    /// i16 to the caller.
+    pub fn process_f32<T>(&mut self, data: &[T]) -> Vec<f32>
+    where
+        T: Sample,
+        f32: FromSample<T>,
+    {
+        let mut output = vec![];
+        self.process_f32_into(data, &mut output);
+        output
+    }
+
+    /// Like `process_f32`, replacing the contents of `output` so its allocation is reused.
+    pub fn process_f32_into<T>(&mut self, data: &[T], output: &mut Vec<f32>)
+    where
+        T: Sample,
+        f32: FromSample<T>,
+    {
+        self.pending
+            .extend(data.iter().map(|&s| f32::from_sample(s)));
+        let frames = self.pending.chunks_exact(self.channels);
+        let downmixed = self.pending.len() - frames.remainder().len();
+        output.clear();
+        output.extend(frames.map(|frame| match self.channel {
+            Some(channel) => frame[channel],
+            None => frame.iter().sum::<f32>() / self.channels as f32,
+        }));
+        self.pending.drain(..downmixed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_each_format_to_i16() {
+        let mut mono = Downmixer::new(1, None);
+        assert_eq!(
+            mono.process(&[0.0f32, 0.5, -0.5, -1.0]),
+            [0, 16384, -16384, -32768]
+        );
+        assert_eq!(
+            mono.process(&[0.0f64, 0.5, -0.5, -1.0]),
+            [0, 16384, -16384, -32768]
+        );
+        assert_eq!(
+            mono.process(&[0i16, 1, -1, i16::MIN, i16::MAX]),
+            [0, 1, -1, i16::MIN, i16::MAX]
+        );
+        assert_eq!(
+            mono.process(&[32768u16, 0, u16::MAX]),
+            [0, i16::MIN, i16::MAX]
+        );
+        assert_eq!(
+            mono.process(&[0i32, 1 << 16, i32::MIN, i32::MAX]),
+            [0, 1, i16::MIN, i16::MAX]
+        );
+    }
+
+    #[test]
+    fn clips_out_of_range_floats() {
+        let mut mono = Downmixer::new(1, None);
+        assert_eq!(
+            mono.process(&[1.0f32, 1.5, -1.5, 100.0]),
+            [i16::MAX, i16::MAX, i16::MIN, i16::MAX]
+        );
+        assert_eq!(mono.process(&[1.0f64, -2.0]), [i16::MAX, i16::MIN]);
+    }
+
+    #[test]
+    fn averages_all_channels() {
+        let cases: [(u16, &[f32], &[i16]); 4] = [
+            (1, &[0.5, -0.25], &[16384, -8192]),
+            (2, &[0.5, 0.25, -0.5, -0.25], &[12288, -12288]),
+            (4, &[0.5, 0.5, 0.5, -0.5, 0.0, 0.0, 1.0, -1.0], &[8192, 0]),
+            (
+                6,
+                &[
+                    0.5, 0.5, 0.5, 0.5, 0.5, 0.5, 0.75, 0.0, 0.0, 0.0, 0.0, -0.75,
+                ],
+                &[16384, 0],
+            ),
+        ];
+        for (channels, input, expected) in cases {
+            // One output sample per frame, whatever the channel count.
+            let output = Downmixer::new(channels, None).process(input);
+            assert_eq!(output, expected, "{} channels", channels);
+        }
+    }
+
+    #[test]
+    fn selects_a_single_channel() {
+        let input = [
+            0.5, 0.0, 0.0, 0.0, 0.0, -0.5, -0.25, 0.0, 0.0, 0.0, 0.0, 0.25,
+        ];
+        assert_eq!(Downmixer::new(6, Some(0)).process(&input), [16384, -8192]);
+        assert_eq!(Downmixer::new(6, Some(5)).process(&input), [-16384, 8192]);
+    }
+
+    #[test]
+    fn carries_partial_frames_over() {
+        let input = [0.5, 0.25, 0.0, 0.0, -0.5, 0.0, 0.0, 0.0, 1.0, 1.0, 0.5, 0.5];
+        let mut whole = Downmixer::new(4, None);
+        let expected = whole.process(&input);
+
+        let mut split = Downmixer::new(4, None);
+        let mut output = split.process(&input[..5]);
+        assert_eq!(output.len(), 1);
+        output.extend(split.process(&input[5..7]));
+        output.extend(split.process(&input[7..]));
+        assert_eq!(output, expected);
+    }
+}
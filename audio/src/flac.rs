@@ -0,0 +1,486 @@
+//! Lossless FLAC encoding of i16 audio, for recordings that are kept around.
+//!
+//! Each block of samples is coded with whichever of the fixed polynomial predictors of
+//! order 0 to 4 leaves the smallest Rice coded residual, or as a constant for silence. That
+//! halves the size of typical speech compared to PCM without the cost of LPC analysis.
+
+use crate::recorder::{OutputFormat, SampleData};
+use crate::sink::{AudioSink, SinkResult};
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Samples per channel in each frame.
+const BLOCK_SIZE: usize = 4096;
+const BITS_PER_SAMPLE: u32 = 16;
+/// Largest Rice parameter; 15 is reserved for escaped partitions.
+const MAX_RICE_PARAMETER: u32 = 14;
+/// Offset of the STREAMINFO block, after the stream marker and the block header.
+const STREAMINFO_OFFSET: u64 = 8;
+
+/// A FLAC file being written from interleaved 16-bit samples.
+pub struct Flac {
+    writer: BufWriter<File>,
+    format: OutputFormat,
+    path: PathBuf,
+    channels: usize,
+    // Interleaved samples of the block being filled.
+    block: Vec<i16>,
+    frames: u64,
+    // Samples per channel written so far.
+    total: u64,
+    min_frame_bytes: u32,
+    max_frame_bytes: u32,
+}
+
+impl Flac {
+    /// Creates the file at `path` for audio in `format`, which must be 16-bit with one to
+    /// eight channels.
+    pub fn new(path: impl AsRef<Path>, format: &OutputFormat) -> io::Result<Self> {
+        if format.sample_format != cpal::SampleFormat::I16 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "FLAC output takes i16 samples, not {}",
+                    format.sample_format
+                ),
+            ));
+        }
+        if !(1..=8).contains(&format.channels) || !(1..1 << 20).contains(&format.sample_rate) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "FLAC can't hold {} channels at {} Hz",
+                    format.channels, format.sample_rate
+                ),
+            ));
+        }
+        let mut flac = Flac {
+            writer: BufWriter::new(File::create(path.as_ref())?),
+            format: format.clone(),
+            path: path.as_ref().to_path_buf(),
+            channels: format.channels as usize,
+            block: Vec::with_capacity(BLOCK_SIZE * format.channels as usize),
+            frames: 0,
+            total: 0,
+            min_frame_bytes: u32::MAX,
+            max_frame_bytes: 0,
+        };
+        flac.writer.write_all(b"fLaC")?;
+        // The only metadata block, completed by `finalize`.
+        flac.writer.write_all(&[0x80, 0, 0, 34])?;
+        let streaminfo = flac.streaminfo();
+        flac.writer.write_all(&streaminfo)?;
+        Ok(flac)
+    }
+
+    /// Queues `samples`, interleaved if there are several channels, encoding every block
+    /// they complete. Returns the number of samples taken.
+    pub fn write_samples(&mut self, samples: &[i16]) -> io::Result<usize> {
+        let block_len = BLOCK_SIZE * self.channels;
+        let mut rest = samples;
+        while !rest.is_empty() {
+            let take = (block_len - self.block.len()).min(rest.len());
+            self.block.extend_from_slice(&rest[..take]);
+            rest = &rest[take..];
+            if self.block.len() == block_len {
+                self.encode_block()?;
+            }
+        }
+        Ok(samples.len())
+    }
+
+    /// Encodes what is left and completes the stream header. A trailing partial frame of a
+    /// multi-channel stream is dropped.
+    pub fn finalize(mut self) -> io::Result<()> {
+        self.block
+            .truncate(self.block.len() / self.channels * self.channels);
+        if !self.block.is_empty() {
+            self.encode_block()?;
+        }
+        let streaminfo = self.streaminfo();
+        self.writer.seek(SeekFrom::Start(STREAMINFO_OFFSET))?;
+        self.writer.write_all(&streaminfo)?;
+        self.writer.flush()
+    }
+
+    fn streaminfo(&self) -> Vec<u8> {
+        let mut bits = BitWriter::default();
+        bits.write(BLOCK_SIZE as u64, 16);
+        bits.write(BLOCK_SIZE as u64, 16);
+        let (min_frame, max_frame) = match self.max_frame_bytes {
+            0 => (0, 0),
+            max => (self.min_frame_bytes, max),
+        };
+        bits.write(min_frame as u64, 24);
+        bits.write(max_frame as u64, 24);
+        bits.write(self.format.sample_rate as u64, 20);
+        bits.write(self.channels as u64 - 1, 3);
+        bits.write(BITS_PER_SAMPLE as u64 - 1, 5);
+        bits.write(self.total, 36);
+        // No MD5 signature of the audio, which decoders take as unknown.
+        bits.write(0, 64);
+        bits.write(0, 64);
+        bits.into_bytes()
+    }
+
+    fn encode_block(&mut self) -> io::Result<()> {
+        let len = self.block.len() / self.channels;
+        let mut bits = BitWriter::default();
+        // Sync code, fixed block size, 16-bit block size at the end of the header, sample
+        // rate from STREAMINFO, independent channels, 16 bits per sample.
+        bits.write(0xfff8, 16);
+        bits.write(0x70, 8);
+        bits.write(((self.channels as u64 - 1) << 4) | 0x08, 8);
+        for byte in utf8_number(self.frames) {
+            bits.write(byte as u64, 8);
+        }
+        bits.write(len as u64 - 1, 16);
+        let crc = crc8(bits.bytes());
+        bits.write(crc as u64, 8);
+
+        let mut channel = Vec::with_capacity(len);
+        for index in 0..self.channels {
+            channel.clear();
+            channel.extend(
+                self.block
+                    .iter()
+                    .skip(index)
+                    .step_by(self.channels)
+                    .map(|&s| s as i64),
+            );
+            write_subframe(&mut bits, &channel);
+        }
+        bits.align();
+        let crc = crc16(bits.bytes());
+        bits.write(crc as u64, 16);
+
+        let frame = bits.into_bytes();
+        self.writer.write_all(&frame)?;
+        self.min_frame_bytes = self.min_frame_bytes.min(frame.len() as u32);
+        self.max_frame_bytes = self.max_frame_bytes.max(frame.len() as u32);
+        self.frames += 1;
+        self.total += len as u64;
+        self.block.clear();
+        Ok(())
+    }
+}
+
+impl AudioSink for Flac {
+    fn format(&self) -> &OutputFormat {
+        &self.format
+    }
+
+    fn write(&mut self, frame: &SampleData) -> SinkResult<()> {
+        self.write_samples(&frame.data)?;
+        Ok(())
+    }
+
+    fn finalize(self: Box<Self>) -> SinkResult<()> {
+        Ok(Flac::finalize(*self)?)
+    }
+
+    fn files(&self) -> Vec<PathBuf> {
+        vec![self.path.clone()]
+    }
+}
+
+fn write_subframe(bits: &mut BitWriter, samples: &[i64]) {
+    if samples.iter().all(|&s| s == samples[0]) {
+        bits.write(0x00, 8);
+        bits.write_signed(samples[0], BITS_PER_SAMPLE);
+        return;
+    }
+    let verbatim_bits = samples.len() as u64 * BITS_PER_SAMPLE as u64;
+    let best = (0..=4usize)
+        .filter(|&order| order < samples.len())
+        .map(|order| {
+            let residual = fixed_residual(samples, order);
+            let (parameter, residual_bits) = rice_parameter(&residual);
+            (order, residual, parameter, residual_bits)
+        })
+        .min_by_key(|(order, _, _, residual_bits)| {
+            *order as u64 * BITS_PER_SAMPLE as u64 + residual_bits
+        });
+    match best {
+        Some((order, residual, parameter, residual_bits))
+            if (order as u64 * BITS_PER_SAMPLE as u64 + residual_bits + 10) < verbatim_bits =>
+        {
+            bits.write((0x08 | order as u64) << 1, 8);
+            for &sample in &samples[..order] {
+                bits.write_signed(sample, BITS_PER_SAMPLE);
+            }
+            // Rice coding with a 4-bit parameter, one partition.
+            bits.write(0, 2);
+            bits.write(0, 4);
+            bits.write(parameter as u64, 4);
+            for &value in &residual {
+                let folded = fold(value);
+                bits.write_unary(folded >> parameter);
+                bits.write(folded & ((1 << parameter) - 1), parameter);
+            }
+        }
+        _ => {
+            bits.write(0x02, 8);
+            for &sample in samples {
+                bits.write_signed(sample, BITS_PER_SAMPLE);
+            }
+        }
+    }
+}
+
+/// Residual of the fixed predictor of `order`, for every sample after the warm-up.
+fn fixed_residual(samples: &[i64], order: usize) -> Vec<i64> {
+    (order..samples.len())
+        .map(|i| {
+            let s = |back: usize| samples[i - back];
+            match order {
+                0 => s(0),
+                1 => s(0) - s(1),
+                2 => s(0) - 2 * s(1) + s(2),
+                3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+                _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+            }
+        })
+        .collect()
+}
+
+/// Maps signed values to unsigned ones, small magnitudes first.
+fn fold(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Rice parameter coding `residual` in the fewest bits, and that number of bits.
+fn rice_parameter(residual: &[i64]) -> (u32, u64) {
+    let folded = residual.iter().map(|&r| fold(r)).collect::<Vec<_>>();
+    (0..=MAX_RICE_PARAMETER)
+        .map(|parameter| {
+            let bits = folded
+                .iter()
+                .map(|&u| (u >> parameter) + 1 + parameter as u64)
+                .sum::<u64>();
+            (parameter, bits)
+        })
+        .min_by_key(|(_, bits)| *bits)
+        .unwrap()
+}
+
+/// Frame number in the UTF-8 like coding FLAC uses for it.
+fn utf8_number(value: u64) -> Vec<u8> {
+    if value < 0x80 {
+        return vec![value as u8];
+    }
+    let mut continuation = vec![];
+    let mut value = value;
+    // Payload bits the leading byte still has room for.
+    let mut room = 6;
+    while value >= 1 << room {
+        continuation.push(0x80 | (value & 0x3f) as u8);
+        value >>= 6;
+        room -= 1;
+    }
+    let len = continuation.len() + 1;
+    let marker = !(0xffu8 >> len);
+    let mut bytes = vec![marker | value as u8];
+    bytes.extend(continuation.into_iter().rev());
+    bytes
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in bytes {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in bytes {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Packs values most significant bit first.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    pending: u64,
+    pending_bits: u32,
+}
+
+impl BitWriter {
+    /// Appends the low `bits` bits of `value`, up to 32 at a time.
+    fn write(&mut self, value: u64, bits: u32) {
+        if bits > 32 {
+            self.write(value >> 32, bits - 32);
+            self.write(value & 0xffff_ffff, 32);
+            return;
+        }
+        if bits == 0 {
+            return;
+        }
+        self.pending = (self.pending << bits) | (value & ((1 << bits) - 1));
+        self.pending_bits += bits;
+        while self.pending_bits >= 8 {
+            self.pending_bits -= 8;
+            self.bytes.push((self.pending >> self.pending_bits) as u8);
+        }
+        self.pending &= (1 << self.pending_bits) - 1;
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    /// Appends `value` zeros followed by a one.
+    fn write_unary(&mut self, value: u64) {
+        let mut zeros = value;
+        while zeros >= 32 {
+            self.write(0, 32);
+            zeros -= 32;
+        }
+        self.write(1, zeros as u32 + 1);
+    }
+
+    /// Pads with zeros to a whole byte.
+    fn align(&mut self) {
+        if self.pending_bits > 0 {
+            self.write(0, 8 - self.pending_bits);
+        }
+    }
+
+    /// The whole bytes written so far.
+    fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(channels: u16, sample_rate: u32) -> OutputFormat {
+        OutputFormat {
+            channels,
+            sample_rate,
+            sample_format: cpal::SampleFormat::I16,
+        }
+    }
+
+    /// A tone with a little noise, with a stretch of silence and a full-scale burst.
+    fn signal(sample_rate: u32, len: usize) -> Vec<i16> {
+        let mut state = 0x2545_f491u32;
+        (0..len)
+            .map(|i| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                let t = i as f32 / sample_rate as f32;
+                let tone = (2.0 * std::f32::consts::PI * 220.0 * t).sin() * 8000.0;
+                match i * 10 / len {
+                    3 => 0,
+                    7 => {
+                        if i % 2 == 0 {
+                            i16::MAX
+                        } else {
+                            i16::MIN
+                        }
+                    }
+                    _ => (tone + (state % 200) as f32 - 100.0) as i16,
+                }
+            })
+            .collect()
+    }
+
+    fn round_trip(name: &str, format: OutputFormat, input: &[i16]) -> u64 {
+        let path = std::env::temp_dir().join(format!("st-{}-{}.flac", name, std::process::id()));
+        let mut flac = Flac::new(&path, &format).unwrap();
+        // Uneven chunks, so blocks fill across calls.
+        for chunk in input.chunks(1000 * format.channels as usize) {
+            assert_eq!(flac.write_samples(chunk).unwrap(), chunk.len());
+        }
+        flac.finalize().unwrap();
+        let size = std::fs::metadata(&path).unwrap().len();
+
+        let mut reader = claxon::FlacReader::open(&path).unwrap();
+        let info = reader.streaminfo();
+        assert_eq!(info.sample_rate, format.sample_rate);
+        assert_eq!(info.channels, format.channels as u32);
+        assert_eq!(info.bits_per_sample, 16);
+        assert_eq!(
+            info.samples,
+            Some((input.len() / format.channels as usize) as u64)
+        );
+        let output = reader
+            .samples()
+            .map(|sample| sample.unwrap() as i16)
+            .collect::<Vec<_>>();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(output, input);
+        size
+    }
+
+    #[test]
+    fn round_trips_mono_at_16_khz() {
+        let input = signal(16000, 16000 * 3 + 123);
+        let size = round_trip("flac-16k", format(1, 16000), &input);
+        // Well below the two bytes per sample of PCM.
+        assert!(size < input.len() as u64 * 2 * 3 / 4, "{} bytes", size);
+    }
+
+    #[test]
+    fn round_trips_mono_at_48_khz() {
+        let input = signal(48000, 48000 * 2 + 7);
+        round_trip("flac-48k", format(1, 48000), &input);
+    }
+
+    #[test]
+    fn round_trips_stereo_and_short_streams() {
+        let input = signal(48000, 48000)
+            .chunks(2)
+            .flat_map(|pair| [pair[0], pair[0] / 2])
+            .collect::<Vec<_>>();
+        round_trip("flac-stereo", format(2, 48000), &input);
+        round_trip("flac-short", format(1, 16000), &[1, -1, 3]);
+    }
+
+    #[test]
+    fn codes_large_frame_numbers() {
+        assert_eq!(utf8_number(0x7f), [0x7f]);
+        assert_eq!(utf8_number(0x80), [0xc2, 0x80]);
+        assert_eq!(utf8_number(0x800), [0xe0, 0xa0, 0x80]);
+        assert_eq!(utf8_number(0x10000), [0xf0, 0x90, 0x80, 0x80]);
+    }
+
+    #[test]
+    fn rejects_formats_flac_cannot_hold() {
+        let path = std::env::temp_dir().join("st-flac-rejected.flac");
+        let float = OutputFormat {
+            sample_format: cpal::SampleFormat::F32,
+            ..format(1, 16000)
+        };
+        assert!(Flac::new(&path, &float).is_err());
+        assert!(Flac::new(&path, &format(9, 16000)).is_err());
+    }
+}
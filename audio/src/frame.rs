@@ -0,0 +1,132 @@
+use crate::recorder::SampleData;
+
+/// Cuts mono audio delivered in buffers of any size into frames of a fixed duration.
+///
+/// Samples that don't fill a frame are kept for the next call to `push`. Each frame is
+/// stamped with the capture time of its first sample, counted on from the start of the
+/// buffer it began in, so timestamps only ever increase.
+pub struct Framer {
+    sample_rate: u32,
+    frame_len: usize,
+    buffer: Vec<i16>,
+    // Capture time of `buffer[0]` is `start` plus `offset` samples.
+    start: u64,
+    offset: u64,
+}
+
+impl Framer {
+    /// Creates a framer emitting `frame_ms` of audio at `sample_rate` per frame.
+    ///
+    /// Panics if a frame would hold no samples.
+    pub fn new(sample_rate: u32, frame_ms: u32) -> Self {
+        let frame_len = (sample_rate as u64 * frame_ms as u64 / 1000) as usize;
+        assert!(frame_len > 0, "frames of {} ms are empty", frame_ms);
+        Framer {
+            sample_rate,
+            frame_len,
+            buffer: Vec::with_capacity(frame_len),
+            start: 0,
+            offset: 0,
+        }
+    }
+
+    pub fn frame_len(&self) -> usize {
+        self.frame_len
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Queues `samples`, captured from `timestamp` (milliseconds since the Unix epoch), and
+    /// returns the frames completed by them.
+    pub fn push(&mut self, samples: &[i16], timestamp: u64) -> Vec<SampleData> {
+        if self.buffer.is_empty() {
+            self.start = timestamp.max(self.timestamp());
+            self.offset = 0;
+        }
+        let mut frames = vec![];
+        let mut samples = samples;
+        while !samples.is_empty() {
+            let take = (self.frame_len - self.buffer.len()).min(samples.len());
+            self.buffer.extend_from_slice(&samples[..take]);
+            samples = &samples[take..];
+            if self.buffer.len() == self.frame_len {
+                frames.extend(self.flush());
+            }
+        }
+        frames
+    }
+
+    /// Returns the partial frame still queued, if any.
+    pub fn flush(&mut self) -> Option<SampleData> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        let frame = SampleData {
+            data: std::mem::replace(&mut self.buffer, Vec::with_capacity(self.frame_len)),
+            timestamp: self.timestamp(),
+        };
+        self.offset += frame.data.len() as u64;
+        Some(frame)
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.start + self.offset * 1000 / self.sample_rate as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emits_constant_frames_from_irregular_buffers() {
+        let mut framer = Framer::new(16000, 100);
+        let mut frames = vec![];
+        let mut timestamp = 1_000_000;
+        let mut value = 0i16;
+        for size in [120, 4096, 333, 1, 1600, 2048, 7, 999].repeat(4) {
+            let samples = (0..size)
+                .map(|_| {
+                    value = value.wrapping_add(1);
+                    value
+                })
+                .collect::<Vec<_>>();
+            frames.extend(framer.push(&samples, timestamp));
+            timestamp += size as u64 * 1000 / 16000;
+        }
+
+        assert_eq!(frames.len(), 9204 * 4 / 1600);
+        assert!(frames.iter().all(|frame| frame.data.len() == 1600));
+        assert!(
+            frames
+                .windows(2)
+                .all(|pair| pair[0].timestamp < pair[1].timestamp)
+        );
+        // No samples are lost or reordered between frames.
+        let samples = frames.iter().flat_map(|frame| &frame.data).copied();
+        assert!(samples.enumerate().all(|(i, s)| s == (i + 1) as i16));
+        assert_eq!(frames[0].timestamp, 1_000_000);
+        assert_eq!(frames[1].timestamp, 1_000_100);
+    }
+
+    #[test]
+    fn flush_emits_the_partial_frame() {
+        let mut framer = Framer::new(8000, 100);
+        assert_eq!(framer.push(&[1; 1000], 5000).len(), 1);
+        let partial = framer.flush().unwrap();
+        assert_eq!(partial.data, [1; 200]);
+        assert_eq!(partial.timestamp, 5100);
+        assert!(framer.flush().is_none());
+    }
+
+    #[test]
+    fn timestamps_never_go_back() {
+        let mut framer = Framer::new(8000, 100);
+        framer.push(&[0; 800], 5000);
+        // A callback reporting an earlier time than the audio already framed.
+        let frames = framer.push(&[0; 800], 5050);
+        assert_eq!(frames[0].timestamp, 5100);
+    }
+}
@@ -0,0 +1,121 @@
+use crate::convert::{Dither, f32_to_i16_slice};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+pub fn db_to_linear(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Scales `samples` by the linear `gain` and converts them to i16, with dither if given.
+/// Samples pushed beyond full scale saturate instead of wrapping around. Returns the
+/// converted samples and how many of them were clipped.
+pub fn apply(samples: &[f32], gain: f32, dither: Option<&mut Dither>) -> (Vec<i16>, u64) {
+    let mut output = vec![];
+    let clipped = apply_into(&mut samples.to_vec(), gain, dither, &mut output);
+    (output, clipped)
+}
+
+/// Like `apply`, scaling `samples` in place and replacing the contents of `output`, so
+/// neither needs allocating. Returns how many samples were clipped.
+pub fn apply_into(
+    samples: &mut [f32],
+    gain: f32,
+    dither: Option<&mut Dither>,
+    output: &mut Vec<i16>,
+) -> u64 {
+    for sample in samples.iter_mut() {
+        *sample *= gain;
+    }
+    let clipped = samples
+        .iter()
+        .filter(|sample| !(-1.0..=1.0).contains(*sample))
+        .count() as u64;
+    output.clear();
+    output.resize(samples.len(), 0);
+    f32_to_i16_slice(samples, output, dither);
+    clipped
+}
+
+/// Gain shared between the recorder, which can change it at any time, and the capture
+/// callbacks applying it.
+pub(crate) struct GainControl {
+    gain_db: AtomicU32,
+    clipped_samples: AtomicU64,
+}
+
+impl GainControl {
+    pub(crate) fn new(gain_db: f32) -> Self {
+        GainControl {
+            gain_db: AtomicU32::new(gain_db.to_bits()),
+            clipped_samples: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn gain_db(&self) -> f32 {
+        f32::from_bits(self.gain_db.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn set_gain_db(&self, gain_db: f32) {
+        self.gain_db.store(gain_db.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Applies the current gain as `apply_into` does, counting clipped samples.
+    pub(crate) fn process_into(
+        &self,
+        samples: &mut [f32],
+        dither: Option<&mut Dither>,
+        output: &mut Vec<i16>,
+    ) {
+        let clipped = apply_into(samples, db_to_linear(self.gain_db()), dither, output);
+        if clipped > 0 {
+            self.clipped_samples.fetch_add(clipped, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn clipped_samples(&self) -> u64 {
+        self.clipped_samples.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_decibels() {
+        assert_eq!(db_to_linear(0.0), 1.0);
+        assert!((db_to_linear(6.0) - 1.995).abs() < 0.001);
+        assert!((db_to_linear(-20.0) - 0.1).abs() < 0.0001);
+    }
+
+    #[test]
+    fn applies_gain() {
+        let (output, clipped) = apply(&[0.25, -0.125, 0.0], 2.0, None);
+        assert_eq!(output, [16384, -8192, 0]);
+        assert_eq!(clipped, 0);
+    }
+
+    #[test]
+    fn saturates_full_scale_input() {
+        let (output, clipped) = apply(&[1.0, -1.0, 0.75, -0.75, 0.25], db_to_linear(12.0), None);
+        assert_eq!(output[..4], [i16::MAX, i16::MIN, i16::MAX, i16::MIN]);
+        assert_eq!(clipped, 4);
+        // Unity gain leaves full scale untouched.
+        let (output, clipped) = apply(&[1.0, -1.0], 1.0, None);
+        assert_eq!(output, [i16::MAX, i16::MIN]);
+        assert_eq!(clipped, 0);
+    }
+
+    #[test]
+    fn control_counts_clipped_samples() {
+        let control = GainControl::new(0.0);
+        let mut output = vec![];
+        control.process_into(&mut [0.9; 10], None, &mut output);
+        assert_eq!(control.clipped_samples(), 0);
+        control.set_gain_db(6.0);
+        assert_eq!(control.gain_db(), 6.0);
+        control.process_into(&mut [0.9; 10], None, &mut output);
+        control.process_into(&mut [0.1; 10], None, &mut output);
+        assert_eq!(control.clipped_samples(), 10);
+        assert_eq!(output.len(), 10);
+    }
+}
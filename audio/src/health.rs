@@ -0,0 +1,222 @@
+use std::fmt;
+
+#[derive(Clone, Debug)]
+pub struct HealthConfig {
+    /// Consecutive full-scale samples that count as clipping. Single samples at full
+    /// scale happen in healthy loud audio.
+    pub clip_run: usize,
+    /// Runs of exact zeros at least this long, with audio on both sides, count as a
+    /// dropout.
+    pub dropout_ms: u32,
+    /// Least time between two warnings of the same kind. Problems found in between are
+    /// summed up in the next one.
+    pub warning_interval_ms: u32,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig {
+            clip_run: 3,
+            dropout_ms: 30,
+            warning_interval_ms: 10_000,
+        }
+    }
+}
+
+/// A problem with the captured signal rather than with the device.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SignalWarning {
+    /// The input hit full scale for `samples` samples since the last warning.
+    ClippingDetected { samples: u64 },
+    /// The input fell to exact silence `gaps` times since the last warning, typically a
+    /// flaky connection dropping buffers.
+    DropoutDetected { gaps: u32, longest_ms: u64 },
+}
+
+impl fmt::Display for SignalWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignalWarning::ClippingDetected { samples } => {
+                write!(f, "input is clipping, {} samples at full scale", samples)
+            }
+            SignalWarning::DropoutDetected { gaps, longest_ms } => write!(
+                f,
+                "input dropped out {} times, longest gap {} ms",
+                gaps, longest_ms
+            ),
+        }
+    }
+}
+
+/// Watches captured audio for clipping and dropouts.
+///
+/// Time is counted in samples, so warnings are spaced by the audio seen rather than by
+/// the wall clock. Exact silence that never ends, like an output device with nothing
+/// playing, isn't a dropout; only gaps followed by audio are.
+pub struct HealthMonitor {
+    config: HealthConfig,
+    sample_rate: u32,
+    // Samples seen so far.
+    position: u64,
+    clip_run: usize,
+    clipped: u64,
+    zero_run: u64,
+    // Whether any audio was heard yet.
+    heard: bool,
+    gaps: u32,
+    longest_gap: u64,
+    // Position of the last warning of each kind.
+    clipping_warned: Option<u64>,
+    dropout_warned: Option<u64>,
+}
+
+impl HealthMonitor {
+    pub fn new(config: HealthConfig, sample_rate: u32) -> Self {
+        HealthMonitor {
+            config,
+            sample_rate,
+            position: 0,
+            clip_run: 0,
+            clipped: 0,
+            zero_run: 0,
+            heard: false,
+            gaps: 0,
+            longest_gap: 0,
+            clipping_warned: None,
+            dropout_warned: None,
+        }
+    }
+
+    /// Checks a buffer of mono samples, returning the warnings that are due.
+    pub fn process(&mut self, samples: &[i16]) -> Vec<SignalWarning> {
+        let min_gap = self.config.dropout_ms as u64 * self.sample_rate as u64 / 1000;
+        for &sample in samples {
+            if sample == i16::MAX || sample == i16::MIN {
+                self.clip_run += 1;
+                if self.clip_run == self.config.clip_run {
+                    self.clipped += self.clip_run as u64;
+                } else if self.clip_run > self.config.clip_run {
+                    self.clipped += 1;
+                }
+            } else {
+                self.clip_run = 0;
+            }
+            if sample == 0 {
+                self.zero_run += 1;
+                continue;
+            }
+            if self.heard && self.zero_run >= min_gap {
+                self.gaps += 1;
+                self.longest_gap = self.longest_gap.max(self.zero_run);
+            }
+            self.zero_run = 0;
+            self.heard = true;
+        }
+        self.position += samples.len() as u64;
+
+        let mut warnings = vec![];
+        if self.clipped > 0 && self.due(self.clipping_warned) {
+            warnings.push(SignalWarning::ClippingDetected {
+                samples: std::mem::take(&mut self.clipped),
+            });
+            self.clipping_warned = Some(self.position);
+        }
+        if self.gaps > 0 && self.due(self.dropout_warned) {
+            warnings.push(SignalWarning::DropoutDetected {
+                gaps: std::mem::take(&mut self.gaps),
+                longest_ms: std::mem::take(&mut self.longest_gap) * 1000 / self.sample_rate as u64,
+            });
+            self.dropout_warned = Some(self.position);
+        }
+        warnings
+    }
+
+    fn due(&self, warned: Option<u64>) -> bool {
+        let interval = self.config.warning_interval_ms as u64 * self.sample_rate as u64 / 1000;
+        warned.is_none_or(|warned| self.position - warned >= interval)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 16000;
+
+    fn tone(len: usize) -> Vec<i16> {
+        (0..len)
+            .map(|i| if i % 2 == 0 { 1000 } else { -1000 })
+            .collect()
+    }
+
+    fn monitor() -> HealthMonitor {
+        HealthMonitor::new(HealthConfig::default(), RATE)
+    }
+
+    #[test]
+    fn healthy_audio_raises_nothing() {
+        let mut monitor = monitor();
+        let mut audio = tone(RATE as usize);
+        // Isolated peaks and short zero runs are normal.
+        audio[10] = i16::MAX;
+        audio[20..30].fill(0);
+        assert!(monitor.process(&audio).is_empty());
+    }
+
+    #[test]
+    fn detects_clipping() {
+        let mut monitor = monitor();
+        let mut audio = tone(1600);
+        audio[100..110].fill(i16::MAX);
+        audio[200..205].fill(i16::MIN);
+        assert_eq!(
+            monitor.process(&audio),
+            [SignalWarning::ClippingDetected { samples: 15 }]
+        );
+    }
+
+    #[test]
+    fn detects_dropouts() {
+        let mut monitor = monitor();
+        let mut audio = tone(RATE as usize);
+        // 50 ms and 100 ms gaps.
+        audio[1000..1800].fill(0);
+        audio[5000..6600].fill(0);
+        assert_eq!(
+            monitor.process(&audio),
+            [SignalWarning::DropoutDetected {
+                gaps: 2,
+                longest_ms: 100
+            }]
+        );
+    }
+
+    #[test]
+    fn endless_silence_is_not_a_dropout() {
+        let mut monitor = monitor();
+        // Nor is silence before the first audio.
+        assert!(monitor.process(&vec![0; RATE as usize * 5]).is_empty());
+        assert!(monitor.process(&tone(1600)).is_empty());
+        let mut audio = tone(1600);
+        audio.extend(vec![0; RATE as usize * 5]);
+        assert!(monitor.process(&audio).is_empty());
+    }
+
+    #[test]
+    fn warnings_are_spaced_by_the_interval() {
+        let mut monitor = monitor();
+        let mut clipped = tone(RATE as usize);
+        clipped[100..104].fill(i16::MAX);
+
+        assert_eq!(monitor.process(&clipped).len(), 1);
+        // Clipping keeps going, but the next nine seconds stay quiet.
+        for _ in 0..9 {
+            assert!(monitor.process(&clipped).is_empty());
+        }
+        // Ten seconds after the first warning, the next one sums up everything since.
+        assert_eq!(
+            monitor.process(&clipped),
+            [SignalWarning::ClippingDetected { samples: 40 }]
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Lowest level reported, standing in for silence.
+pub const MIN_DBFS: f32 = -90.0;
+
+/// Peak and RMS level of a frame of audio, in dBFS. A full-scale square wave is 0 dB on
+/// both, a full-scale sine 0 dB peak and about -3 dB RMS.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Level {
+    pub peak_dbfs: f32,
+    pub rms_dbfs: f32,
+}
+
+impl Level {
+    pub const SILENCE: Level = Level {
+        peak_dbfs: MIN_DBFS,
+        rms_dbfs: MIN_DBFS,
+    };
+
+    pub fn of(samples: &[i16]) -> Self {
+        Level {
+            peak_dbfs: peak_dbfs(samples),
+            rms_dbfs: rms_dbfs(samples),
+        }
+    }
+}
+
+fn to_dbfs(amplitude: f64) -> f32 {
+    ((20.0 * amplitude.log10()) as f32).max(MIN_DBFS)
+}
+
+/// Peak level of `samples` in dBFS, clamped to `MIN_DBFS`.
+pub fn peak_dbfs(samples: &[i16]) -> f32 {
+    let peak = samples.iter().map(|&s| (s as i32).abs()).max().unwrap_or(0);
+    to_dbfs(peak as f64 / 32768.0)
+}
+
+/// RMS level of `samples` in dBFS, clamped to `MIN_DBFS`.
+pub fn rms_dbfs(samples: &[i16]) -> f32 {
+    if samples.is_empty() {
+        return MIN_DBFS;
+    }
+    let sum = samples
+        .iter()
+        .map(|&s| (s as f64 / 32768.0).powi(2))
+        .sum::<f64>();
+    to_dbfs((sum / samples.len() as f64).sqrt())
+}
+
+/// The most recent `Level`, written by the capture callback and read from anywhere.
+pub(crate) struct LevelMeter {
+    peak: AtomicU32,
+    rms: AtomicU32,
+}
+
+impl LevelMeter {
+    pub(crate) fn new() -> Self {
+        LevelMeter {
+            peak: AtomicU32::new(MIN_DBFS.to_bits()),
+            rms: AtomicU32::new(MIN_DBFS.to_bits()),
+        }
+    }
+
+    pub(crate) fn store(&self, level: Level) {
+        self.peak
+            .store(level.peak_dbfs.to_bits(), Ordering::Relaxed);
+        self.rms.store(level.rms_dbfs.to_bits(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn load(&self) -> Level {
+        Level {
+            peak_dbfs: f32::from_bits(self.peak.load(Ordering::Relaxed)),
+            rms_dbfs: f32::from_bits(self.rms.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(amplitude: f32) -> Vec<i16> {
+        (0..16000)
+            .map(|i| {
+                let t = i as f32 / 16000.0;
+                ((2.0 * std::f32::consts::PI * 440.0 * t).sin() * amplitude * 32767.0) as i16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn full_scale_sine() {
+        let level = Level::of(&sine(1.0));
+        assert!(level.peak_dbfs.abs() < 0.01);
+        assert!((level.rms_dbfs - -3.01).abs() < 0.05);
+    }
+
+    #[test]
+    fn scales_with_amplitude() {
+        let level = Level::of(&sine(0.1));
+        assert!((level.peak_dbfs - -20.0).abs() < 0.05);
+        assert!((level.rms_dbfs - -23.01).abs() < 0.05);
+    }
+
+    #[test]
+    fn silence_is_clamped() {
+        assert_eq!(Level::of(&[0; 1600]), Level::SILENCE);
+        assert_eq!(Level::of(&[]), Level::SILENCE);
+        assert_eq!(peak_dbfs(&[i16::MIN]), 0.0);
+    }
+
+    #[test]
+    fn meter_keeps_the_latest_level() {
+        let meter = LevelMeter::new();
+        assert_eq!(meter.load(), Level::SILENCE);
+        let level = Level::of(&sine(0.5));
+        meter.store(level);
+        assert_eq!(meter.load(), level);
+    }
+}
@@ -1,12 +1,34 @@
-
+pub mod agc;
+pub mod clock;
+pub mod convert;
+#[cfg(feature = "denoise")]
+pub mod denoise;
+pub mod downmix;
+#[cfg(feature = "flac")]
+pub mod flac;
+pub mod frame;
+pub mod gain;
+pub mod health;
+pub mod level;
+pub mod mix;
+#[cfg(target_os = "linux")]
+pub mod monitor;
+pub mod multi;
+pub mod permission;
+pub mod preroll;
+pub mod queue;
+pub mod raw;
 pub mod recorder;
+pub mod resample;
+pub mod sink;
+pub mod source;
+pub mod stream_config;
+pub mod vad;
 pub mod wav;
 
 #[cfg(test)]
 mod tests {
 
     #[test]
-    fn it_works() {
-       
-    }
+    fn it_works() {}
 }
@@ -0,0 +1,139 @@
+use std::collections::VecDeque;
+
+/// Mixes several mono i16 streams running at the same rate into one.
+///
+/// Sources deliver audio in buffers of their own size and timing, so each source is queued
+/// and samples are only mixed once every source has delivered them. A source that falls
+/// more than `jitter` samples behind the others is treated as silent for the missing part,
+/// so a device that stops delivering audio can't hold back the rest.
+pub struct Mixer {
+    sources: Vec<MixerSource>,
+    jitter: usize,
+}
+
+struct MixerSource {
+    gain: f32,
+    buffer: VecDeque<i16>,
+}
+
+impl Mixer {
+    /// Creates a mixer with one source per entry of `gains`.
+    pub fn new(gains: &[f32], jitter: usize) -> Self {
+        Mixer {
+            sources: gains
+                .iter()
+                .map(|&gain| MixerSource {
+                    gain,
+                    buffer: VecDeque::new(),
+                })
+                .collect(),
+            jitter,
+        }
+    }
+
+    /// Queues audio from `source` and returns whatever can be mixed now. The sum of the
+    /// sources is clipped to the i16 range.
+    pub fn push(&mut self, source: usize, samples: &[i16]) -> Vec<i16> {
+        let mut output = vec![];
+        self.push_into(source, samples, &mut output);
+        output
+    }
+
+    /// Like `push`, replacing the contents of `output` so its allocation is reused.
+    pub fn push_into(&mut self, source: usize, samples: &[i16], output: &mut Vec<i16>) {
+        self.sources[source].buffer.extend(samples);
+        let lengths = self.sources.iter().map(|source| source.buffer.len());
+        let ready = lengths.clone().min().unwrap_or(0);
+        let longest = lengths.max().unwrap_or(0);
+        let count = ready.max(longest.saturating_sub(self.jitter));
+        output.clear();
+        output.extend((0..count).map(|_| {
+            let sum = self
+                .sources
+                .iter_mut()
+                .map(|source| source.buffer.pop_front().unwrap_or(0) as f32 * source.gain)
+                .sum::<f32>();
+            sum.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+        }));
+    }
+
+    /// Discards all queued audio, e.g. when capture is paused.
+    pub fn clear(&mut self) {
+        for source in &mut self.sources {
+            source.buffer.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sums_sources_with_gain() {
+        let mut mixer = Mixer::new(&[1.0, 0.5], 100);
+        assert!(mixer.push(0, &[100, 200, 300]).is_empty());
+        assert_eq!(mixer.push(1, &[1000, -1000]), [600, -300]);
+        assert_eq!(mixer.push(1, &[40, 60]), [320]);
+        assert_eq!(mixer.push(0, &[10]), [40]);
+    }
+
+    #[test]
+    fn reuses_the_output_buffer() {
+        let mut mixer = Mixer::new(&[1.0, 1.0], 100);
+        let mut output = Vec::with_capacity(16);
+        mixer.push_into(0, &[1, 2, 3], &mut output);
+        assert!(output.is_empty());
+        mixer.push_into(1, &[10, 20], &mut output);
+        assert_eq!(output, [11, 22]);
+        let allocation = output.as_ptr();
+        mixer.push_into(1, &[30], &mut output);
+        assert_eq!(output, [33]);
+        assert_eq!(output.as_ptr(), allocation);
+    }
+
+    #[test]
+    fn clips_the_sum() {
+        let mut mixer = Mixer::new(&[1.0, 1.0], 100);
+        mixer.push(0, &[30000, -30000, 1000]);
+        assert_eq!(
+            mixer.push(1, &[30000, -30000, 1000]),
+            [i16::MAX, i16::MIN, 2000]
+        );
+    }
+
+    #[test]
+    fn aligns_buffers_of_different_sizes() {
+        let signal = (0..1000).map(|i| (i % 100) as i16).collect::<Vec<_>>();
+        let mut mixer = Mixer::new(&[1.0, 1.0], 1000);
+        let mut output = vec![];
+        let mut a = signal.chunks(48);
+        let mut b = signal.chunks(160);
+        loop {
+            match (a.next(), b.next()) {
+                (None, None) => break,
+                (chunk_a, chunk_b) => {
+                    if let Some(chunk) = chunk_a {
+                        output.extend(mixer.push(0, chunk));
+                    }
+                    if let Some(chunk) = chunk_b {
+                        output.extend(mixer.push(1, chunk));
+                    }
+                }
+            }
+        }
+        let expected = signal.iter().map(|s| s * 2).collect::<Vec<_>>();
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn silent_source_does_not_starve_the_other() {
+        let mut mixer = Mixer::new(&[1.0, 1.0], 100);
+        assert!(mixer.push(0, &[5; 80]).is_empty());
+        // Everything beyond the jitter buffer is mixed with silence.
+        assert_eq!(mixer.push(0, &[5; 80]), [5; 60]);
+        assert_eq!(mixer.push(0, &[5; 50]), [5; 50]);
+        // Audio arriving late is mixed with what is still queued.
+        assert_eq!(mixer.push(1, &[1; 10]), [6; 10]);
+    }
+}
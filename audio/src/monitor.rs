@@ -0,0 +1,235 @@
+//! Finding the PulseAudio or PipeWire monitor source that carries what a sink plays, which
+//! is how system audio is captured on Linux, and recording it when it isn't listed as a
+//! device.
+
+use log::debug;
+use std::io::{self, Read};
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+
+const MONITOR_SUFFIX: &str = ".monitor";
+
+/// Picks a monitor source out of `sources`. With a `name`, the first monitor containing it
+/// case-insensitively; otherwise the monitor of `default_sink`, or any monitor.
+pub fn select(sources: &[String], default_sink: Option<&str>, name: Option<&str>) -> Option<usize> {
+    let monitors = || {
+        sources
+            .iter()
+            .enumerate()
+            .filter(|(_, source)| source.ends_with(MONITOR_SUFFIX))
+    };
+    let found = match name {
+        Some(name) => {
+            let name = name.to_lowercase();
+            monitors().find(|(_, source)| source.to_lowercase().contains(&name))
+        }
+        None => default_sink
+            .and_then(|sink| {
+                let monitor = format!("{}{}", sink, MONITOR_SUFFIX);
+                monitors().find(|(_, source)| **source == monitor)
+            })
+            .or_else(|| monitors().next()),
+    };
+    found.map(|(index, _)| index)
+}
+
+/// Source names from the output of `pactl list short sources`.
+pub fn parse_sources(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| line.split('\t').nth(1))
+        .map(|name| name.to_string())
+        .collect()
+}
+
+/// Sources known to the sound server, or none if `pactl` isn't available.
+pub fn list_sources() -> Vec<String> {
+    pactl(&["list", "short", "sources"])
+        .map(|output| parse_sources(&output))
+        .unwrap_or_default()
+}
+
+pub fn default_sink() -> Option<String> {
+    pactl(&["get-default-sink"])
+        .map(|output| output.trim().to_string())
+        .filter(|sink| !sink.is_empty())
+}
+
+/// `parec` recording `source` as raw interleaved s16le samples. The source is passed to the
+/// child alone: pointing the PulseAudio ALSA plugin at it through `PULSE_SOURCE` would
+/// redirect every other stream of this process too, such as the microphone of a mixed
+/// capture.
+pub fn record_command(source: &str, sample_rate: u32, channels: u16) -> Command {
+    let mut command = Command::new("parec");
+    command
+        .arg(format!("--device={}", source))
+        .args(["--raw", "--format=s16le", "--latency-msec=20"])
+        .arg(format!("--rate={}", sample_rate))
+        .arg(format!("--channels={}", channels))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    command
+}
+
+/// A source recorded by a child process, whose output is read on a thread of its own. The
+/// child is killed when this is dropped.
+pub struct SourceCapture {
+    child: Child,
+    stopping: Arc<AtomicBool>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl SourceCapture {
+    /// Runs `command` and hands the s16le samples it writes to `on_data`, `samples` at a
+    /// time. `on_end` runs if the output ends before the capture is dropped, e.g. when the
+    /// source goes away.
+    pub fn start(
+        mut command: Command,
+        samples: usize,
+        mut on_data: impl FnMut(&[i16]) + Send + 'static,
+        on_end: impl FnOnce() + Send + 'static,
+    ) -> io::Result<Self> {
+        let mut child = command.spawn()?;
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let stopping = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let stopping = stopping.clone();
+            thread::spawn(move || {
+                let mut bytes = vec![0; samples * 2];
+                let mut data = Vec::with_capacity(samples);
+                while stdout.read_exact(&mut bytes).is_ok() {
+                    data.clear();
+                    data.extend(
+                        bytes
+                            .chunks_exact(2)
+                            .map(|sample| i16::from_le_bytes([sample[0], sample[1]])),
+                    );
+                    on_data(&data);
+                }
+                if !stopping.load(Ordering::Relaxed) {
+                    on_end();
+                }
+            })
+        };
+        Ok(SourceCapture {
+            child,
+            stopping,
+            reader: Some(reader),
+        })
+    }
+}
+
+impl Drop for SourceCapture {
+    fn drop(&mut self) {
+        self.stopping.store(true, Ordering::Relaxed);
+        if let Err(e) = self.child.kill() {
+            debug!("Failed to stop recording: {}", e);
+        }
+        let _ = self.child.wait();
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+    }
+}
+
+fn pactl(args: &[&str]) -> Option<String> {
+    match Command::new("pactl").args(args).output() {
+        Ok(output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).into_owned())
+        }
+        Ok(output) => {
+            debug!("pactl {} failed: {}", args.join(" "), output.status);
+            None
+        }
+        Err(e) => {
+            debug!("Failed to run pactl: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCES: &str = "\
+49\talsa_output.pci-0000_00_1f.3.analog-stereo.monitor\tPipeWire\ts32le 2ch 48000Hz\tSUSPENDED
+50\talsa_input.pci-0000_00_1f.3.analog-stereo\tPipeWire\ts32le 2ch 48000Hz\tSUSPENDED
+73\tbluez_output.AA_BB_CC_DD_EE_FF.1.monitor\tPipeWire\ts16le 2ch 48000Hz\tRUNNING
+";
+
+    #[test]
+    fn parses_source_names() {
+        assert_eq!(
+            parse_sources(SOURCES),
+            [
+                "alsa_output.pci-0000_00_1f.3.analog-stereo.monitor",
+                "alsa_input.pci-0000_00_1f.3.analog-stereo",
+                "bluez_output.AA_BB_CC_DD_EE_FF.1.monitor",
+            ]
+        );
+        assert!(parse_sources("").is_empty());
+    }
+
+    #[test]
+    fn prefers_the_default_sink_monitor() {
+        let sources = parse_sources(SOURCES);
+        let sink = "bluez_output.AA_BB_CC_DD_EE_FF.1";
+        assert_eq!(select(&sources, Some(sink), None), Some(2));
+        // An unknown default sink falls back to the first monitor.
+        assert_eq!(select(&sources, Some("gone"), None), Some(0));
+        assert_eq!(select(&sources, None, None), Some(0));
+    }
+
+    #[test]
+    fn selects_a_monitor_by_name() {
+        let sources = parse_sources(SOURCES);
+        assert_eq!(select(&sources, None, Some("BLUEZ")), Some(2));
+        // Only monitors are considered, even if another source matches.
+        assert_eq!(select(&sources, None, Some("alsa_input")), None);
+    }
+
+    #[test]
+    fn records_the_source_in_the_child_alone() {
+        let command = record_command("bluez_output.AA.1.monitor", 16000, 2);
+        assert_eq!(command.get_program(), "parec");
+        let args = command
+            .get_args()
+            .map(|arg| arg.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert!(args.contains(&"--device=bluez_output.AA.1.monitor"));
+        assert!(args.contains(&"--rate=16000"));
+        assert!(args.contains(&"--channels=2"));
+        assert_eq!(command.get_envs().count(), 0);
+    }
+
+    #[test]
+    fn reads_what_the_child_writes() {
+        // Three little-endian samples, 1, -2 and 256, then a half sample that is dropped.
+        let mut command = Command::new("printf");
+        command
+            .arg(r"\001\000\376\377\000\001\007")
+            .stdout(Stdio::piped());
+        let (data_tx, data_rx) = std::sync::mpsc::channel();
+        let (end_tx, end_rx) = std::sync::mpsc::channel();
+        let capture = SourceCapture::start(
+            command,
+            1,
+            move |data| data_tx.send(data.to_vec()).unwrap(),
+            move || end_tx.send(()).unwrap(),
+        )
+        .unwrap();
+        end_rx.recv().unwrap();
+        drop(capture);
+        assert_eq!(data_rx.iter().collect::<Vec<_>>(), [[1], [-2], [256]]);
+    }
+
+    #[test]
+    fn no_monitor_sources() {
+        let sources = vec!["alsa_input.usb-mic".to_string()];
+        assert_eq!(select(&sources, Some("alsa_output.usb"), None), None);
+    }
+}
@@ -0,0 +1,307 @@
+use crate::recorder::{
+    CpalRecorder, OutputFormat, RecorderConfig, RecorderError, RecorderEvent, RecorderResult,
+    SampleData,
+};
+use crate::source::SampleSource;
+use futures_util::future::select_all;
+use log::warn;
+use std::collections::VecDeque;
+
+/// Index of a source within a `MultiSource`.
+pub type SourceId = usize;
+
+/// Several sources running side by side, each with its own pipeline, whose events are
+/// tagged with the source they came from.
+///
+/// A source that fails to start, fails later or runs dry is left out from then on, while
+/// the others keep going.
+pub struct MultiSource {
+    sources: Vec<Option<Box<dyn SampleSource>>>,
+    formats: Vec<Option<OutputFormat>>,
+    // Audio of retired sources that still has to be handed out.
+    pending: VecDeque<(SourceId, RecorderEvent)>,
+}
+
+impl MultiSource {
+    pub fn new(sources: Vec<Box<dyn SampleSource>>) -> Self {
+        MultiSource {
+            formats: sources
+                .iter()
+                .map(|source| Some(source.output_format()))
+                .collect(),
+            sources: sources.into_iter().map(Some).collect(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Starts a recorder for each configuration, in order, returning those that failed to
+    /// start with their error. Fails only if none of them starts.
+    pub fn start(
+        configs: Vec<RecorderConfig>,
+    ) -> RecorderResult<(Self, Vec<(SourceId, RecorderError)>)> {
+        MultiSource::started(
+            configs
+                .into_iter()
+                .map(|config| {
+                    let recorder = CpalRecorder::with_config(config).start()?;
+                    Ok(Box::new(recorder) as Box<dyn SampleSource>)
+                })
+                .collect(),
+        )
+    }
+
+    /// Keeps the sources that started under their id, leaving the place of the others
+    /// empty. Fails with the first error if none started.
+    fn started(
+        results: Vec<RecorderResult<Box<dyn SampleSource>>>,
+    ) -> RecorderResult<(Self, Vec<(SourceId, RecorderError)>)> {
+        let mut sources = MultiSource {
+            sources: Vec::with_capacity(results.len()),
+            formats: Vec::with_capacity(results.len()),
+            pending: VecDeque::new(),
+        };
+        let mut failures = vec![];
+        for (id, result) in results.into_iter().enumerate() {
+            match result {
+                Ok(source) => {
+                    sources.formats.push(Some(source.output_format()));
+                    sources.sources.push(Some(source));
+                }
+                Err(e) => {
+                    sources.formats.push(None);
+                    sources.sources.push(None);
+                    failures.push((id, e));
+                }
+            }
+        }
+        if sources.formats.iter().all(Option::is_none) && !failures.is_empty() {
+            return Err(failures.remove(0).1);
+        }
+        Ok((sources, failures))
+    }
+
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// The format of the source with the given id, unless it failed to start.
+    pub fn output_format(&self, source: SourceId) -> Option<&OutputFormat> {
+        self.formats[source].as_ref()
+    }
+
+    /// The source with the given id, unless it has been retired.
+    pub fn source(&mut self, source: SourceId) -> Option<&mut dyn SampleSource> {
+        Some(self.sources.get_mut(source)?.as_mut()?.as_mut())
+    }
+
+    /// Returns the next event of any source, or `None` once all of them have run dry.
+    /// `DeviceLost` and `Fatal` retire their source, after the audio it still held back.
+    pub async fn next_event(&mut self) -> Option<(SourceId, RecorderEvent)> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(event);
+            }
+            let events = self
+                .sources
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(id, source)| {
+                    let source = source.as_mut()?;
+                    Some(Box::pin(async move { (id, source.next_event().await) }))
+                })
+                .collect::<Vec<_>>();
+            if events.is_empty() {
+                return None;
+            }
+            let ((id, event), _, _) = select_all(events).await;
+            match event {
+                Some(event @ (RecorderEvent::DeviceLost | RecorderEvent::Fatal(_))) => {
+                    self.retire(id);
+                    self.pending.push_back((id, event));
+                }
+                Some(event) => return Some((id, event)),
+                None => self.retire(id),
+            }
+        }
+    }
+
+    fn retire(&mut self, id: SourceId) {
+        let Some(source) = self.sources[id].take() else {
+            return;
+        };
+        match source.stop() {
+            Ok(remaining) => self.pending.extend(
+                remaining
+                    .into_iter()
+                    .map(|sample_data| (id, RecorderEvent::Sample(sample_data))),
+            ),
+            Err(e) => warn!("Failed to stop source {}: {}", id, e),
+        }
+    }
+
+    /// Stops every source, returning the audio not yet received through `next_event`.
+    pub fn stop(mut self) -> RecorderResult<Vec<(SourceId, SampleData)>> {
+        for id in 0..self.sources.len() {
+            if let Some(source) = self.sources[id].take() {
+                let remaining = source.stop()?;
+                self.pending.extend(
+                    remaining
+                        .into_iter()
+                        .map(|sample_data| (id, RecorderEvent::Sample(sample_data))),
+                );
+            }
+        }
+        Ok(self
+            .pending
+            .into_iter()
+            .filter_map(|(id, event)| match event {
+                RecorderEvent::Sample(sample_data) => Some((id, sample_data)),
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::SineSource;
+    use async_trait::async_trait;
+    use std::time::Duration;
+
+    /// Hands out a fixed list of events, then holds back one frame until stopped.
+    struct ScriptedSource {
+        events: VecDeque<RecorderEvent>,
+    }
+
+    fn frame(value: i16) -> SampleData {
+        SampleData {
+            data: vec![value; 160],
+            timestamp: 0,
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl SampleSource for ScriptedSource {
+        fn output_format(&self) -> OutputFormat {
+            OutputFormat {
+                channels: 1,
+                sample_rate: 16000,
+                sample_format: cpal::SampleFormat::I16,
+            }
+        }
+
+        async fn next_event(&mut self) -> Option<RecorderEvent> {
+            self.events.pop_front()
+        }
+
+        fn stop(self: Box<Self>) -> RecorderResult<Vec<SampleData>> {
+            Ok(vec![frame(-1)])
+        }
+    }
+
+    #[tokio::test]
+    async fn tags_events_with_their_source() {
+        let mut sources = MultiSource::new(vec![
+            Box::new(SineSource::new(
+                16000,
+                440.0,
+                0.5,
+                Duration::from_millis(300),
+            )),
+            Box::new(SineSource::silence(16000, Duration::from_millis(500))),
+        ]);
+        assert_eq!(sources.len(), 2);
+        let mut counts = [0; 2];
+        while let Some((id, event)) = sources.next_event().await {
+            assert!(matches!(event, RecorderEvent::Sample(_)));
+            counts[id] += 1;
+        }
+        assert_eq!(counts, [3, 5]);
+        assert!(sources.source(0).is_none());
+    }
+
+    #[tokio::test]
+    async fn a_failing_source_leaves_the_others_running() {
+        let failing = ScriptedSource {
+            events: VecDeque::from([
+                RecorderEvent::Sample(frame(1)),
+                RecorderEvent::Fatal("unplugged".to_string()),
+            ]),
+        };
+        let mut sources = MultiSource::new(vec![
+            Box::new(failing),
+            Box::new(SineSource::silence(16000, Duration::from_secs(1))),
+        ]);
+        let mut failing_events = vec![];
+        let mut frames = 0;
+        while let Some((id, event)) = sources.next_event().await {
+            match id {
+                0 => failing_events.push(event),
+                _ => frames += 1,
+            }
+        }
+        assert_eq!(frames, 10);
+        // The frame held back by the failed source comes before the failure.
+        assert!(matches!(
+            failing_events.as_slice(),
+            [
+                RecorderEvent::Sample(first),
+                RecorderEvent::Sample(held_back),
+                RecorderEvent::Fatal(_),
+            ] if first.data[0] == 1 && held_back.data[0] == -1
+        ));
+    }
+
+    #[tokio::test]
+    async fn a_source_failing_to_start_leaves_the_others_running() {
+        let (mut sources, failures) = MultiSource::started(vec![
+            Err(RecorderError::InvalidFrameDuration(0)),
+            Ok(Box::new(SineSource::silence(
+                16000,
+                Duration::from_millis(300),
+            ))),
+        ])
+        .unwrap();
+        assert!(matches!(
+            failures.as_slice(),
+            [(0, RecorderError::InvalidFrameDuration(0))]
+        ));
+        assert_eq!(sources.len(), 2);
+        assert!(sources.output_format(0).is_none());
+        assert_eq!(sources.output_format(1).unwrap().sample_rate, 16000);
+        let mut ids = vec![];
+        while let Some((id, _)) = sources.next_event().await {
+            ids.push(id);
+        }
+        assert_eq!(ids, [1, 1, 1]);
+    }
+
+    #[test]
+    fn fails_when_no_source_starts() {
+        let result = MultiSource::started(vec![
+            Err(RecorderError::InvalidFrameDuration(1)),
+            Err(RecorderError::InvalidFrameDuration(2)),
+        ]);
+        // The first failure is the one reported.
+        assert!(matches!(
+            result,
+            Err(RecorderError::InvalidFrameDuration(1))
+        ));
+    }
+
+    #[tokio::test]
+    async fn stop_returns_what_sources_held_back() {
+        let scripted = || ScriptedSource {
+            events: VecDeque::new(),
+        };
+        let sources = MultiSource::new(vec![Box::new(scripted()), Box::new(scripted())]);
+        let remaining = sources.stop().unwrap();
+        let ids = remaining.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        assert_eq!(ids, [0, 1]);
+    }
+}
@@ -0,0 +1,112 @@
+//! Capture permissions. On macOS a host the process may not capture from starts its streams
+//! all the same and delivers no audio, so a capture that stays without audio is put down to
+//! the permission the host needs. Elsewhere no host needs one, and the checks are left out.
+
+use crate::recorder::RecorderHostId;
+#[cfg(target_os = "macos")]
+use log::warn;
+use std::fmt;
+
+/// A permission the system grants the process to capture audio.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Permission {
+    /// Screen and system audio recording, which ScreenCaptureKit needs on macOS.
+    ScreenRecording,
+    /// Microphone access, which input devices need on macOS.
+    Microphone,
+}
+
+impl Permission {
+    /// Where the permission is granted.
+    pub fn guidance(&self) -> &'static str {
+        match self {
+            Permission::ScreenRecording => {
+                "allow the terminal under System Settings > Privacy & Security > \
+                 Screen & System Audio Recording, then restart it"
+            }
+            Permission::Microphone => {
+                "allow the terminal under System Settings > Privacy & Security > \
+                 Microphone, then restart it"
+            }
+        }
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Permission::ScreenRecording => write!(f, "screen and system audio recording"),
+            Permission::Microphone => write!(f, "microphone"),
+        }
+    }
+}
+
+/// The permission capturing from a device of `host` needs, if any.
+#[cfg(target_os = "macos")]
+pub fn needed(host: RecorderHostId) -> Option<Permission> {
+    match host {
+        cpal::HostId::ScreenCaptureKit => Some(Permission::ScreenRecording),
+        cpal::HostId::CoreAudio => Some(Permission::Microphone),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
+/// The permission capturing from a device of `host` needs, if any.
+#[cfg(not(target_os = "macos"))]
+pub fn needed(_host: RecorderHostId) -> Option<Permission> {
+    None
+}
+
+/// Asks for the permission capturing from a device of `host` needs, before its streams are
+/// opened, which shows the system prompt the first time. Opening an input device asks for
+/// the microphone itself; screen recording has to be asked for.
+#[cfg(target_os = "macos")]
+pub(crate) fn request(host: RecorderHostId) {
+    #[link(name = "CoreGraphics", kind = "framework")]
+    unsafe extern "C" {
+        fn CGPreflightScreenCaptureAccess() -> bool;
+        fn CGRequestScreenCaptureAccess() -> bool;
+    }
+
+    if needed(host) == Some(Permission::ScreenRecording)
+        // SAFETY: neither takes arguments, and both may be called from any thread.
+        && !unsafe { CGPreflightScreenCaptureAccess() }
+        && !unsafe { CGRequestScreenCaptureAccess() }
+    {
+        warn!(
+            "No permission for {}: {}",
+            Permission::ScreenRecording,
+            Permission::ScreenRecording.guidance()
+        );
+    }
+}
+
+/// Asks for the permission capturing from a device of `host` needs. None is needed here.
+#[cfg(not(target_os = "macos"))]
+pub(crate) fn request(_host: RecorderHostId) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tells_where_to_grant_it() {
+        assert_eq!(
+            Permission::ScreenRecording.to_string(),
+            "screen and system audio recording"
+        );
+        assert!(
+            Permission::Microphone
+                .guidance()
+                .contains("Privacy & Security > Microphone")
+        );
+        #[cfg(not(target_os = "macos"))]
+        assert_eq!(needed(cpal::default_host().id()), None);
+        #[cfg(target_os = "macos")]
+        assert_eq!(
+            needed(cpal::HostId::ScreenCaptureKit),
+            Some(Permission::ScreenRecording)
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use crate::recorder::SampleData;
+use std::collections::VecDeque;
+
+/// Ring of the most recent frames, holding at most a fixed number of samples.
+///
+/// Once full, the oldest audio is dropped to make room, trimming the oldest frame if only
+/// part of it has to go, so the ring always holds exactly the newest audio up to its
+/// capacity.
+pub struct Preroll {
+    sample_rate: u32,
+    capacity: usize,
+    frames: VecDeque<SampleData>,
+    len: usize,
+}
+
+impl Preroll {
+    /// Creates a ring keeping the last `duration_ms` of audio at `sample_rate`.
+    pub fn new(sample_rate: u32, duration_ms: u32) -> Self {
+        Preroll {
+            sample_rate,
+            capacity: (sample_rate as u64 * duration_ms as u64 / 1000) as usize,
+            frames: VecDeque::new(),
+            len: 0,
+        }
+    }
+
+    /// Number of samples held.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push(&mut self, frame: SampleData) {
+        self.len += frame.data.len();
+        self.frames.push_back(frame);
+        while self.len > self.capacity {
+            let excess = self.len - self.capacity;
+            let oldest = self.frames.front_mut().unwrap();
+            if oldest.data.len() <= excess {
+                self.len -= oldest.data.len();
+                self.frames.pop_front();
+            } else {
+                oldest.data.drain(..excess);
+                oldest.timestamp += excess as u64 * 1000 / self.sample_rate as u64;
+                self.len -= excess;
+            }
+        }
+    }
+
+    /// Takes the frames held, oldest first.
+    pub fn take(&mut self) -> Vec<SampleData> {
+        self.len = 0;
+        self.frames.drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(timestamp: u64, len: usize) -> SampleData {
+        SampleData {
+            data: vec![timestamp as i16; len],
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn keeps_exactly_the_newest_audio() {
+        let mut preroll = Preroll::new(16000, 1000);
+        // 2.5 seconds in 100 ms frames.
+        for i in 0..25 {
+            preroll.push(frame(i * 100, 1600));
+        }
+        assert_eq!(preroll.len(), 16000);
+        let frames = preroll.take();
+        assert_eq!(frames.len(), 10);
+        assert_eq!(frames[0].timestamp, 1500);
+        assert_eq!(frames[9].timestamp, 2400);
+        assert!(preroll.is_empty());
+    }
+
+    #[test]
+    fn trims_the_oldest_frame() {
+        let mut preroll = Preroll::new(16000, 250);
+        for i in 0..5 {
+            preroll.push(frame(i * 100, 1600));
+        }
+        let frames = preroll.take();
+        let lens = frames.iter().map(|f| f.data.len()).collect::<Vec<_>>();
+        assert_eq!(lens, [800, 1600, 1600]);
+        // The trimmed frame starts later, keeping timestamps in order.
+        assert_eq!(frames[0].timestamp, 250);
+        assert_eq!(frames[0].data[0], 200);
+        assert!(
+            frames
+                .windows(2)
+                .all(|pair| pair[0].timestamp < pair[1].timestamp)
+        );
+    }
+}
@@ -0,0 +1,281 @@
+use crate::recorder::RecorderEvent;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// What to do with captured audio when the consumer falls behind and the queue is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Discard the oldest queued audio to make room, keeping latency bounded.
+    #[default]
+    DropOldest,
+    /// Discard the audio that doesn't fit.
+    DropNewest,
+    /// Wait for the consumer. The capture callback must never block, so this drops the audio
+    /// that doesn't fit like `DropNewest`. Drops are counted for the consumer to report, as
+    /// `CpalRecorder::dropped_samples`.
+    Block,
+}
+
+struct Inner {
+    events: VecDeque<RecorderEvent>,
+    queued_samples: usize,
+    closed: bool,
+}
+
+/// Queue between the capture callbacks and the async consumer, bounded by the number of
+/// samples it holds rather than the number of buffers. Events other than samples don't
+/// count towards the capacity and are never dropped.
+pub(crate) struct SampleQueue {
+    inner: Mutex<Inner>,
+    notify: Notify,
+    capacity: usize,
+    policy: OverflowPolicy,
+    dropped_samples: AtomicU64,
+    senders: AtomicUsize,
+}
+
+/// Producer side of a `SampleQueue`, shared between the stream callbacks. The queue closes
+/// once every sender is dropped.
+pub(crate) struct SampleSender(Arc<SampleQueue>);
+
+impl SampleQueue {
+    pub(crate) fn new(capacity: usize, policy: OverflowPolicy) -> (SampleSender, Arc<Self>) {
+        let queue = Arc::new(SampleQueue {
+            inner: Mutex::new(Inner {
+                events: VecDeque::new(),
+                queued_samples: 0,
+                closed: false,
+            }),
+            notify: Notify::new(),
+            capacity,
+            policy,
+            dropped_samples: AtomicU64::new(0),
+            senders: AtomicUsize::new(0),
+        });
+        (queue.sender(), queue)
+    }
+
+    /// Creates another sender, e.g. for streams replacing those of a lost device.
+    pub(crate) fn sender(self: &Arc<Self>) -> SampleSender {
+        self.senders.fetch_add(1, Ordering::Relaxed);
+        SampleSender(self.clone())
+    }
+
+    /// Waits for the next event, returning `None` once the sender is gone and the queue drained.
+    pub(crate) async fn pop(&self) -> Option<RecorderEvent> {
+        loop {
+            {
+                let mut inner = self.inner.lock().unwrap();
+                if let Some(event) = inner.events.pop_front() {
+                    inner.queued_samples -= sample_count(&event);
+                    return Some(event);
+                }
+                if inner.closed {
+                    return None;
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    /// Takes every queued event without waiting.
+    pub(crate) fn drain(&self) -> Vec<RecorderEvent> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.queued_samples = 0;
+        inner.events.drain(..).collect()
+    }
+
+    pub(crate) fn dropped_samples(&self) -> u64 {
+        self.dropped_samples.load(Ordering::Relaxed)
+    }
+
+    fn drop_samples(&self, count: usize) {
+        self.dropped_samples
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+}
+
+fn sample_count(event: &RecorderEvent) -> usize {
+    match event {
+        RecorderEvent::Sample(sample_data) => sample_data.data.len(),
+        _ => 0,
+    }
+}
+
+impl SampleSender {
+    /// Queues an event without blocking, applying the overflow policy to samples that don't
+    /// fit.
+    pub(crate) fn push(&self, event: RecorderEvent) {
+        let queue = &self.0;
+        {
+            let mut inner = queue.inner.lock().unwrap();
+            let len = sample_count(&event);
+            if len > 0 && inner.queued_samples + len > queue.capacity {
+                match queue.policy {
+                    OverflowPolicy::DropOldest => {
+                        while inner.queued_samples + len > queue.capacity {
+                            let Some(index) = inner
+                                .events
+                                .iter()
+                                .position(|event| matches!(event, RecorderEvent::Sample(_)))
+                            else {
+                                break;
+                            };
+                            let oldest = inner.events.remove(index).unwrap();
+                            inner.queued_samples -= sample_count(&oldest);
+                            queue.drop_samples(sample_count(&oldest));
+                        }
+                    }
+                    // Logging here would format and lock on the realtime thread.
+                    OverflowPolicy::DropNewest | OverflowPolicy::Block => {
+                        queue.drop_samples(len);
+                        return;
+                    }
+                }
+            }
+            inner.queued_samples += len;
+            inner.events.push_back(event);
+        }
+        queue.notify.notify_one();
+    }
+}
+
+impl Drop for SampleSender {
+    fn drop(&mut self) {
+        if self.0.senders.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.0.inner.lock().unwrap().closed = true;
+            self.0.notify.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recorder::SampleData;
+
+    fn chunk(value: i16) -> RecorderEvent {
+        RecorderEvent::Sample(SampleData {
+            data: vec![value; 100],
+            timestamp: value as u64,
+        })
+    }
+
+    fn first_sample(event: RecorderEvent) -> i16 {
+        match event {
+            RecorderEvent::Sample(sample_data) => sample_data.data[0],
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    /// Pushes ten 100-sample chunks into a queue holding 500 samples, as a consumer that
+    /// stalls would see, then drains it.
+    async fn overflow(policy: OverflowPolicy) -> (Vec<i16>, u64) {
+        let (sender, queue) = SampleQueue::new(500, policy);
+        for value in 0..10 {
+            sender.push(chunk(value));
+        }
+        drop(sender);
+        let mut received = vec![];
+        while let Some(event) = queue.pop().await {
+            received.push(first_sample(event));
+        }
+        (received, queue.dropped_samples())
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_latest_audio() {
+        let (received, dropped) = overflow(OverflowPolicy::DropOldest).await;
+        assert_eq!(received, [5, 6, 7, 8, 9]);
+        assert_eq!(dropped, 500);
+    }
+
+    #[tokio::test]
+    async fn drop_newest_keeps_earliest_audio() {
+        let (received, dropped) = overflow(OverflowPolicy::DropNewest).await;
+        assert_eq!(received, [0, 1, 2, 3, 4]);
+        assert_eq!(dropped, 500);
+    }
+
+    #[tokio::test]
+    async fn block_never_blocks_the_producer() {
+        let (received, dropped) = overflow(OverflowPolicy::Block).await;
+        assert_eq!(received, [0, 1, 2, 3, 4]);
+        assert_eq!(dropped, 500);
+    }
+
+    #[tokio::test]
+    async fn events_are_never_dropped() {
+        let (sender, queue) = SampleQueue::new(200, OverflowPolicy::DropOldest);
+        sender.push(chunk(0));
+        sender.push(RecorderEvent::Error("glitch".to_string()));
+        for value in 1..5 {
+            sender.push(chunk(value));
+        }
+        drop(sender);
+
+        assert!(matches!(queue.pop().await, Some(RecorderEvent::Error(_))));
+        assert_eq!(first_sample(queue.pop().await.unwrap()), 3);
+        assert_eq!(first_sample(queue.pop().await.unwrap()), 4);
+        assert!(queue.pop().await.is_none());
+        assert_eq!(queue.dropped_samples(), 300);
+    }
+
+    #[tokio::test]
+    async fn stays_open_while_a_replacement_sender_exists() {
+        let (sender, queue) = SampleQueue::new(500, OverflowPolicy::DropOldest);
+        sender.push(chunk(0));
+        let replacement = queue.sender();
+        drop(sender);
+        replacement.push(chunk(1));
+
+        assert_eq!(first_sample(queue.pop().await.unwrap()), 0);
+        assert_eq!(first_sample(queue.pop().await.unwrap()), 1);
+        drop(replacement);
+        assert!(queue.pop().await.is_none());
+    }
+
+    #[test]
+    fn drain_takes_everything_queued() {
+        let (sender, queue) = SampleQueue::new(500, OverflowPolicy::DropOldest);
+        for value in 0..3 {
+            sender.push(chunk(value));
+        }
+        let drained = queue
+            .drain()
+            .into_iter()
+            .map(first_sample)
+            .collect::<Vec<_>>();
+        assert_eq!(drained, [0, 1, 2]);
+        assert!(queue.drain().is_empty());
+        // The drained samples no longer count towards the capacity.
+        for value in 0..5 {
+            sender.push(chunk(value));
+        }
+        assert_eq!(queue.dropped_samples(), 0);
+    }
+
+    #[tokio::test]
+    async fn consumer_keeping_up_loses_nothing() {
+        let (sender, queue) = SampleQueue::new(200, OverflowPolicy::DropNewest);
+        let consumer = tokio::spawn({
+            let queue = queue.clone();
+            async move {
+                let mut count = 0;
+                while queue.pop().await.is_some() {
+                    count += 1;
+                }
+                count
+            }
+        });
+        for value in 0..50 {
+            sender.push(chunk(value));
+            tokio::task::yield_now().await;
+        }
+        drop(sender);
+        assert_eq!(consumer.await.unwrap(), 50);
+        assert_eq!(queue.dropped_samples(), 0);
+    }
+}
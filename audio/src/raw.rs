@@ -0,0 +1,142 @@
+use crate::recorder::{OutputFormat, SampleData};
+use crate::sink::{AudioSink, SinkResult};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Writes headerless s16le samples, the way they go over the wire, for tools like ffmpeg
+/// (`-f s16le -ar <rate> -ac <channels>`).
+///
+/// A JSON sidecar next to the file, named like it with `.json` appended, describes the
+/// format, the capture time of the first sample and how many samples there are. It is
+/// written when the file is created and rewritten by `finalize`.
+pub struct RawPcmSink {
+    writer: BufWriter<File>,
+    format: OutputFormat,
+    path: PathBuf,
+    sidecar: PathBuf,
+    start_ms: Option<u64>,
+    // Samples written, counting every channel.
+    samples: u64,
+}
+
+impl RawPcmSink {
+    /// Creates the file at `path` and its sidecar, for i16 audio in `format`.
+    pub fn new(path: impl AsRef<Path>, format: &OutputFormat) -> io::Result<Self> {
+        if format.sample_format != cpal::SampleFormat::I16 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "raw PCM output takes i16 samples, not {}",
+                    format.sample_format
+                ),
+            ));
+        }
+        let path = path.as_ref().to_path_buf();
+        let sink = RawPcmSink {
+            writer: BufWriter::new(File::create(&path)?),
+            format: format.clone(),
+            sidecar: RawPcmSink::sidecar_path(&path),
+            path,
+            start_ms: None,
+            samples: 0,
+        };
+        sink.write_sidecar()?;
+        Ok(sink)
+    }
+
+    /// Path of the sidecar describing the file at `path`.
+    pub fn sidecar_path(path: impl AsRef<Path>) -> PathBuf {
+        let mut sidecar = path.as_ref().as_os_str().to_owned();
+        sidecar.push(".json");
+        PathBuf::from(sidecar)
+    }
+
+    fn write_sidecar(&self) -> io::Result<()> {
+        let start_ms = match self.start_ms {
+            Some(start_ms) => start_ms.to_string(),
+            None => "null".to_string(),
+        };
+        let json = format!(
+            "{{\"format\":\"s16le\",\"sample_rate\":{},\"channels\":{},\"start_ms\":{},\"samples\":{}}}\n",
+            self.format.sample_rate, self.format.channels, start_ms, self.samples
+        );
+        std::fs::write(&self.sidecar, json)
+    }
+}
+
+impl AudioSink for RawPcmSink {
+    fn format(&self) -> &OutputFormat {
+        &self.format
+    }
+
+    fn write(&mut self, frame: &SampleData) -> SinkResult<()> {
+        self.start_ms.get_or_insert(frame.timestamp);
+        let bytes = frame
+            .data
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect::<Vec<_>>();
+        self.writer.write_all(&bytes)?;
+        self.samples += frame.data.len() as u64;
+        Ok(())
+    }
+
+    fn finalize(mut self: Box<Self>) -> SinkResult<()> {
+        self.writer.flush()?;
+        self.write_sidecar()?;
+        Ok(())
+    }
+
+    fn files(&self) -> Vec<PathBuf> {
+        vec![self.path.clone(), self.sidecar.clone()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_the_bytes_sent_and_describes_them() {
+        let path = std::env::temp_dir().join(format!("st-raw-{}.pcm", std::process::id()));
+        let format = OutputFormat {
+            channels: 1,
+            sample_rate: 16000,
+            sample_format: cpal::SampleFormat::I16,
+        };
+        let mut sink = Box::new(RawPcmSink::new(&path, &format).unwrap());
+        let sidecar = RawPcmSink::sidecar_path(&path);
+        assert!(
+            std::fs::read_to_string(&sidecar)
+                .unwrap()
+                .contains("\"samples\":0")
+        );
+
+        let frames = [vec![0, 1, -1, i16::MAX], vec![i16::MIN, 256, -256]];
+        for (i, data) in frames.iter().enumerate() {
+            let frame = SampleData {
+                data: data.clone(),
+                timestamp: 1_700_000_000_000 + i as u64 * 100,
+            };
+            sink.write(&frame).unwrap();
+        }
+        assert_eq!(sink.files(), [path.clone(), sidecar.clone()]);
+        sink.finalize().unwrap();
+
+        // Encoded like the audio sent to the backend.
+        let expected = frames
+            .concat()
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect::<Vec<u8>>();
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+        assert_eq!(
+            std::fs::read_to_string(&sidecar).unwrap(),
+            "{\"format\":\"s16le\",\"sample_rate\":16000,\"channels\":1,\
+             \"start_ms\":1700000000000,\"samples\":7}\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&sidecar).unwrap();
+    }
+}
@@ -1,20 +1,92 @@
-use cpal::Sample;
+use crate::agc::{Agc, AgcConfig};
+use crate::clock::StreamClock;
+use crate::convert::Dither;
+#[cfg(feature = "denoise")]
+use crate::denoise::{DENOISE_SAMPLE_RATE, Denoiser};
+use crate::downmix::Downmixer;
+use crate::frame::Framer;
+use crate::gain::GainControl;
+use crate::health::{HealthConfig, HealthMonitor, SignalWarning};
+use crate::level::{Level, LevelMeter};
+use crate::mix::Mixer;
+#[cfg(target_os = "linux")]
+use crate::monitor;
+use crate::permission::{self, Permission};
+use crate::preroll::Preroll;
+use crate::queue::{OverflowPolicy, SampleQueue, SampleSender};
+use crate::resample::Resampler;
+use crate::stream_config;
+use crate::vad::{Vad, VadConfig};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use log::{debug, error};
-use std::time::SystemTime;
+use cpal::{FromSample, Sample, SizedSample};
+use log::{debug, error, info, warn};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 use thiserror::Error;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use tokio::select;
+use tokio::sync::watch;
+use tokio::time::{Instant, sleep, sleep_until, timeout};
 
 #[derive(Error, Debug)]
 pub enum RecorderError {
     #[error("Failed to find host: {0}")]
     HostUnavailable(#[from] cpal::HostUnavailable),
+    #[error("Failed to enumerate audio devices: {0}")]
+    DevicesError(#[from] cpal::DevicesError),
+    #[error(
+        "No audio device matching \"{0}\", available devices: {available}",
+        available = .1.join(", ")
+    )]
+    DeviceNotFound(String, Vec<String>),
+    #[error("Channel {0} is out of range for a device with {1} channels")]
+    InvalidChannel(u16, RecorderChannelCount),
+    #[error("Unsupported sample rate {0} Hz, expected one of {SUPPORTED_SAMPLE_RATES:?}")]
+    UnsupportedSampleRate(RecorderSampleRate),
+    #[error("System audio capture is unsupported: {0}")]
+    LoopbackUnsupported(String),
+    #[error("Frames of {0} ms hold no samples")]
+    InvalidFrameDuration(u32),
+    #[error("No default {0} device found")]
+    NoDevice(DeviceKind),
+    #[error("{0} has no {1} config to capture from")]
+    NoSupportedConfig(String, DeviceKind),
+    #[error("{name} takes buffers of {min} to {max} frames, not {requested}")]
+    UnsupportedBufferSize {
+        name: String,
+        requested: u32,
+        min: u32,
+        max: u32,
+    },
+    #[error("Unsupported input sample format: {0}")]
+    UnsupportedSampleFormat(RecorderSampleFormat),
+    #[error("Failed to get device config: {0}")]
+    DefaultStreamConfigError(#[from] cpal::DefaultStreamConfigError),
     #[error("Failed to initialize audio recorder: {0}")]
     BuildStreamError(#[from] cpal::BuildStreamError),
     #[error("Failed to start audio recorder: {0}")]
     PlayStreamError(#[from] cpal::PlayStreamError),
     #[error("Failed to stop audio recorder: {0}")]
     PauseStreamError(#[from] cpal::PauseStreamError),
+    #[error("Noise suppression needs the recorder built with the denoise feature")]
+    DenoiseUnavailable,
+    #[error("Noise suppression needs a target sample rate of 48000 Hz, got {0} Hz")]
+    DenoiseSampleRate(RecorderSampleRate),
+    #[error("Failed to read WAV file: {0}")]
+    WavError(#[from] hound::Error),
+    #[error("No audio from {0} within {secs} s, {NO_AUDIO_HINT}", secs = .1.as_secs_f64())]
+    NoAudio(String, Duration),
+    #[error(
+        "No audio from {device} within {secs} s, likely without {permission} permission: {}",
+        permission.guidance(),
+        secs = waited.as_secs_f64()
+    )]
+    PermissionDenied {
+        device: String,
+        permission: Permission,
+        waited: Duration,
+    },
     #[error("Failed to send audio data: {0}")]
     SenderError(#[from] std::sync::mpsc::SendError<Vec<i16>>),
     #[error("Unknown error")]
@@ -23,9 +95,14 @@ pub enum RecorderError {
 
 pub type RecorderResult<T> = std::result::Result<T, RecorderError>;
 
+/// What usually keeps a device that started from delivering audio.
+const NO_AUDIO_HINT: &str =
+    "check that it may be captured from and isn't held by another application";
+
 pub type RecorderChannelCount = u16;
 pub type RecorderSampleRate = u32;
 pub type RecorderSampleFormat = cpal::SampleFormat;
+pub type RecorderHostId = cpal::HostId;
 
 #[derive(Clone, Debug)]
 pub struct OutputFormat {
@@ -34,139 +111,2321 @@ pub struct OutputFormat {
     pub sample_format: RecorderSampleFormat,
 }
 
+/// Whether a device captures audio itself or plays it back and is captured as loopback.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceKind {
+    Input,
+    Output,
+}
+
+impl fmt::Display for DeviceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceKind::Input => write!(f, "input"),
+            DeviceKind::Output => write!(f, "output"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub host: RecorderHostId,
+    pub kind: DeviceKind,
+    pub default_sample_rate: Option<RecorderSampleRate>,
+    pub channels: Option<RecorderChannelCount>,
+    pub sample_formats: Vec<RecorderSampleFormat>,
+    /// Whether the default host uses it by default for its kind.
+    pub is_default: bool,
+}
+
+impl DeviceInfo {
+    fn new(host: RecorderHostId, device: &cpal::Device, kind: DeviceKind) -> Self {
+        let (default_config, supported_configs) = match kind {
+            DeviceKind::Input => (
+                device.default_input_config().ok(),
+                device
+                    .supported_input_configs()
+                    .map(|configs| configs.collect::<Vec<_>>())
+                    .unwrap_or_default(),
+            ),
+            DeviceKind::Output => (
+                device.default_output_config().ok(),
+                device
+                    .supported_output_configs()
+                    .map(|configs| configs.collect::<Vec<_>>())
+                    .unwrap_or_default(),
+            ),
+        };
+        let mut sample_formats = vec![];
+        for config in supported_configs {
+            if !sample_formats.contains(&config.sample_format()) {
+                sample_formats.push(config.sample_format());
+            }
+        }
+        DeviceInfo {
+            name: device.name().unwrap_or_else(|_| "Unknown".to_string()),
+            host,
+            kind,
+            default_sample_rate: default_config.as_ref().map(|config| config.sample_rate().0),
+            channels: default_config.as_ref().map(|config| config.channels()),
+            sample_formats,
+            is_default: false,
+        }
+    }
+
+    /// Whether audio can be captured from the device, as loopback for output devices.
+    pub fn can_capture(&self) -> bool {
+        check_loopback(self).is_ok()
+    }
+}
+
+/// Picks a device out of the list returned by `CpalRecorder::list_devices`.
+#[derive(Clone, Debug)]
+pub enum DeviceSelector {
+    /// Case-insensitive substring of the device name.
+    ByName(String),
+    /// Position in the device list.
+    ByIndex(usize),
+}
+
+impl DeviceSelector {
+    /// Returns the index of the selected device. When several devices match a name, one of
+    /// the `preferred` kind wins.
+    pub fn select(&self, devices: &[DeviceInfo], preferred: DeviceKind) -> RecorderResult<usize> {
+        let found = match self {
+            DeviceSelector::ByName(name) => {
+                let name = name.to_lowercase();
+                let matches = || {
+                    devices
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, device)| device.name.to_lowercase().contains(&name))
+                };
+                matches()
+                    .find(|(_, device)| device.kind == preferred)
+                    .or_else(|| matches().next())
+                    .map(|(index, _)| index)
+            }
+            DeviceSelector::ByIndex(index) => Some(*index).filter(|index| *index < devices.len()),
+        };
+        found.ok_or_else(|| {
+            let selector = match self {
+                DeviceSelector::ByName(name) => name.clone(),
+                DeviceSelector::ByIndex(index) => format!("#{}", index),
+            };
+            let available = devices
+                .iter()
+                .enumerate()
+                .map(|(index, device)| format!("#{} {} ({:?})", index, device.name, device.kind))
+                .collect();
+            RecorderError::DeviceNotFound(selector, available)
+        })
+    }
+}
+
+/// What the recorder captures.
+#[derive(Clone, Debug, Default)]
+pub enum CaptureSource {
+    /// Audio played by the system, through ScreenCaptureKit on macOS and loopback of an
+    /// output device elsewhere.
+    #[default]
+    SystemAudio,
+    /// An input device of the default host, matched by name like `DeviceSelector::ByName`,
+    /// or the default input device.
+    Microphone { device: Option<String> },
+    /// System audio and a microphone mixed into one stream, e.g. both sides of a call. The
+    /// system audio device is chosen like for `SystemAudio`, the microphone like for
+    /// `Microphone`. Each source is scaled by its gain before mixing.
+    Mixed {
+        microphone: Option<String>,
+        system_gain: f32,
+        microphone_gain: f32,
+    },
+}
+
+/// How far, in milliseconds, mixed sources may drift apart before the one lagging behind is
+/// treated as silent.
+const MIX_JITTER_MS: u32 = 100;
+
+/// Sample rates the recorder can emit.
+pub const SUPPORTED_SAMPLE_RATES: [RecorderSampleRate; 5] = [8000, 16000, 24000, 44100, 48000];
+
+#[derive(Clone, Debug)]
+pub struct RecorderConfig {
+    pub source: CaptureSource,
+    /// System audio device to capture from, or `None` for the platform default. Also used
+    /// for the system side of `CaptureSource::Mixed`.
+    pub device: Option<DeviceSelector>,
+    /// Gain applied to captured audio, in dB, on every device. Samples pushed beyond full
+    /// scale are clipped.
+    pub gain_db: f32,
+    /// Adjust the gain automatically instead of applying `gain_db`.
+    pub agc: Option<AgcConfig>,
+    /// Add TPDF dither when converting to 16 bits, trading distortion of quiet audio for
+    /// a faint noise floor.
+    pub dither: bool,
+    /// Watch the captured signal for clipping and dropouts, reported as
+    /// `RecorderEvent::Warning`.
+    pub health: Option<HealthConfig>,
+    /// Suppress background noise with RNNoise before audio is framed. Needs the `denoise`
+    /// feature and a `target_sample_rate` of 48000, and delays audio by up to 10 ms.
+    pub denoise: bool,
+    /// Capture only this channel of the device instead of averaging all of them. Applies to
+    /// both devices of `CaptureSource::Mixed`.
+    pub channel: Option<u16>,
+    /// Frames per buffer to ask the devices for, trading CPU load for capture latency.
+    /// `None` leaves it to the host, which can add tens of milliseconds. A device that
+    /// refuses a size within its advertised range falls back to its default.
+    pub buffer_size: Option<u32>,
+    /// Rate audio is resampled to, one of `SUPPORTED_SAMPLE_RATES`.
+    pub target_sample_rate: RecorderSampleRate,
+    /// How much audio, in milliseconds, is held for a consumer that falls behind.
+    pub buffer_ms: u32,
+    pub overflow_policy: OverflowPolicy,
+    /// Play silence on the system audio device while capturing it. WASAPI loopback only
+    /// delivers audio while something is playing, so this is on by default on Windows and
+    /// off elsewhere. Some devices hiss or show the app as playing while it runs.
+    pub enable_silent_output: bool,
+    /// Duration of each emitted `SampleData`, in milliseconds.
+    pub frame_ms: u32,
+    /// Drop frames while voice activity detection hears silence, emitting
+    /// `SilenceStarted` and `SilenceEnded` around the gap instead.
+    pub skip_silence: bool,
+    pub vad: VadConfig,
+    /// Reopen the devices when one disappears or the default device changes.
+    pub recovery: Option<RecoveryConfig>,
+    /// Start armed, keeping only the last this many milliseconds of audio until
+    /// `take_preroll` hands them over and audio starts flowing to `recv_event`.
+    pub preroll_ms: Option<u32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct RecoveryConfig {
+    /// Delay between attempts to reopen the devices.
+    pub retry_interval: Duration,
+    /// Attempts before giving up with `RecorderEvent::Fatal`.
+    pub max_attempts: u32,
+    /// How often to check whether the default device changed, for devices that follow it.
+    pub poll_interval: Duration,
+}
+
+impl Default for RecoveryConfig {
+    fn default() -> Self {
+        RecoveryConfig {
+            retry_interval: Duration::from_secs(1),
+            max_attempts: 10,
+            poll_interval: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RecorderConfig {
+    /// Format the recorder is asked to emit. Once started, `CpalRecorder::output_format`
+    /// tells what it actually does.
+    pub fn requested_format(&self) -> OutputFormat {
+        OutputFormat {
+            channels: 1,
+            sample_rate: self.target_sample_rate,
+            sample_format: cpal::SampleFormat::I16,
+        }
+    }
+}
+
+impl Default for RecorderConfig {
+    fn default() -> Self {
+        RecorderConfig {
+            source: CaptureSource::default(),
+            device: None,
+            channel: None,
+            gain_db: 0.0,
+            agc: None,
+            dither: false,
+            health: None,
+            denoise: false,
+            buffer_size: None,
+            target_sample_rate: 48000,
+            buffer_ms: 5000,
+            overflow_policy: OverflowPolicy::default(),
+            enable_silent_output: cfg!(target_os = "windows"),
+            frame_ms: 100,
+            skip_silence: false,
+            vad: VadConfig::default(),
+            recovery: None,
+            preroll_ms: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct SampleData {
     pub data: Vec<i16>,
+    /// Capture time of the first sample, in milliseconds since the Unix epoch. It follows
+    /// the device clock from the moment the stream started, so it can drift slightly from
+    /// the system clock, and never decreases, even across device recovery.
     pub timestamp: u64,
 }
 
+/// Everything the recorder delivers to its consumer, in capture order.
+#[derive(Clone, Debug)]
+pub enum RecorderEvent {
+    Sample(SampleData),
+    /// The stream reported an error but may keep running.
+    Error(String),
+    /// The device went away and the stream will produce no more audio. The recorder can be
+    /// stopped and started again to pick a device anew, or does so itself with recovery
+    /// enabled.
+    DeviceLost,
+    /// Capture moved to other devices after one was lost or the default device changed.
+    /// Only sent with recovery enabled, in place of `DeviceLost`.
+    DeviceChanged {
+        old: String,
+        new: String,
+    },
+    /// Recovery gave up; the recorder will produce no more audio.
+    Fatal(String),
+    /// The captured signal looks broken. Only sent with `RecorderConfig::health`.
+    Warning(SignalWarning),
+    /// No speech is heard, so no samples follow until `SilenceEnded`. Only sent with
+    /// `RecorderConfig::skip_silence`.
+    SilenceStarted,
+    SilenceEnded,
+}
+
 pub struct Started {
-    input_stream: cpal::Stream,
-    output_stream: cpal::Stream,
-    sample_data_receiver: UnboundedReceiver<SampleData>,
+    streams: Streams,
+    sample_data_receiver: Arc<SampleQueue>,
+    capture: CaptureState,
+    next_device_check: Instant,
+    // Set while reopening lost devices, so a cancelled `recv_event` picks up where it left.
+    recovery_attempts: Option<u32>,
+    // When the streams started.
+    started_at: std::time::Instant,
+}
+
+/// A device a started recorder captures from.
+#[derive(Clone, Debug)]
+pub struct ActiveDevice {
+    pub name: String,
+    pub host: RecorderHostId,
+    /// Format the device delivers, before it is converted to the output format.
+    pub format: OutputFormat,
+    /// Frames per buffer the stream was opened with, or `None` if the host picked them.
+    pub buffer_size: Option<u32>,
+}
+
+impl ActiveDevice {
+    /// Latency the buffer size adds to capture, when known.
+    pub fn buffer_latency(&self) -> Option<Duration> {
+        let frames = self.buffer_size? as u64;
+        Some(Duration::from_micros(
+            frames * 1_000_000 / self.format.sample_rate as u64,
+        ))
+    }
+}
+
+/// A stream of the recorder, capturing or playing silence.
+enum Stream {
+    Cpal(cpal::Stream),
+    /// A monitor source recorded by `parec`, until it is dropped. It keeps running while
+    /// capture is paused, and the input handler drops its audio meanwhile.
+    #[cfg(target_os = "linux")]
+    Source {
+        _recording: monitor::SourceCapture,
+    },
+}
+
+impl Stream {
+    fn play(&self) -> RecorderResult<()> {
+        match self {
+            Stream::Cpal(stream) => Ok(stream.play()?),
+            #[cfg(target_os = "linux")]
+            Stream::Source { .. } => Ok(()),
+        }
+    }
+
+    fn pause(&self) -> RecorderResult<()> {
+        match self {
+            Stream::Cpal(stream) => Ok(stream.pause()?),
+            #[cfg(target_os = "linux")]
+            Stream::Source { .. } => Ok(()),
+        }
+    }
+}
+
+/// The streams capturing the configured source.
+#[derive(Default)]
+struct Streams {
+    devices: Vec<ActiveDevice>,
+    // Started in order and paused in reverse, so silent output streams feeding loopback
+    // capture run before the input streams.
+    streams: Vec<Stream>,
+}
+
+impl Streams {
+    fn add(&mut self, (device, streams): (ActiveDevice, Vec<Stream>)) {
+        self.devices.push(device);
+        self.streams.extend(streams);
+    }
+
+    fn description(&self) -> String {
+        self.devices
+            .iter()
+            .map(|device| device.name.as_str())
+            .collect::<Vec<_>>()
+            .join(" + ")
+    }
+
+    fn play(&self) -> RecorderResult<()> {
+        for stream in &self.streams {
+            stream.play()?;
+        }
+        Ok(())
+    }
+
+    fn pause(&self) -> RecorderResult<()> {
+        for stream in self.streams.iter().rev() {
+            stream.pause()?;
+        }
+        Ok(())
+    }
 }
 
 pub struct Stopped;
 
 pub struct CpalRecorder<State = Stopped> {
     state: State,
+    config: RecorderConfig,
 }
 
 impl Default for CpalRecorder {
     fn default() -> Self {
-        CpalRecorder { state: Stopped }
+        CpalRecorder::with_config(RecorderConfig::default())
+    }
+}
+
+// System audio is captured from an input device on macOS (ScreenCaptureKit) and Linux (a
+// monitor source), and as loopback of an output device elsewhere, through WASAPI on Windows.
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+const SYSTEM_AUDIO_KIND: DeviceKind = DeviceKind::Input;
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+const SYSTEM_AUDIO_KIND: DeviceKind = DeviceKind::Output;
+
+/// Fails for devices whose audio can't be captured. Output devices are opened as loopback,
+/// which WASAPI supports and ALSA doesn't.
+fn check_loopback(info: &DeviceInfo) -> RecorderResult<()> {
+    let supported = match info.kind {
+        DeviceKind::Input => true,
+        #[cfg(target_os = "windows")]
+        DeviceKind::Output => info.host == cpal::HostId::Wasapi,
+        #[cfg(target_os = "linux")]
+        DeviceKind::Output => false,
+        #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+        DeviceKind::Output => true,
+    };
+    if supported {
+        Ok(())
+    } else {
+        Err(RecorderError::LoopbackUnsupported(format!(
+            "{} of {} can't be opened as loopback",
+            info.name,
+            info.host.name()
+        )))
+    }
+}
+
+/// Host microphones are captured from.
+pub fn default_host_id() -> RecorderHostId {
+    cpal::default_host().id()
+}
+
+/// Host `CpalRecorder::get_default_device` picks the system audio device from.
+fn system_audio_host() -> RecorderHostId {
+    #[cfg(target_os = "macos")]
+    {
+        cpal::HostId::ScreenCaptureKit
+    }
+    #[cfg(target_os = "windows")]
+    {
+        cpal::HostId::Wasapi
+    }
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    {
+        cpal::default_host().id()
+    }
+}
+
+/// A device picked for capture, the config to open it with and the host it belongs to.
+type SelectedDevice = (cpal::Device, cpal::SupportedStreamConfig, RecorderHostId);
+
+/// Where system audio is captured from.
+enum SystemAudio {
+    Device(SelectedDevice),
+    /// A PulseAudio or PipeWire monitor source that isn't listed as a device, recorded by
+    /// `parec`.
+    #[cfg(target_os = "linux")]
+    Source(String),
+}
+
+/// A monitor source found by `CpalRecorder::find_monitor`.
+#[cfg(target_os = "linux")]
+enum Monitor {
+    Device(cpal::Device, cpal::SupportedStreamConfig),
+    Source(String),
+}
+
+/// Default config of `device` in the direction of `kind`. A device that doesn't support
+/// that direction at all fails with `NoSupportedConfig`.
+fn default_config(
+    device: &cpal::Device,
+    kind: DeviceKind,
+) -> RecorderResult<cpal::SupportedStreamConfig> {
+    let config = match kind {
+        DeviceKind::Input => device.default_input_config(),
+        DeviceKind::Output => device.default_output_config(),
+    };
+    match config {
+        Err(cpal::DefaultStreamConfigError::StreamTypeNotSupported) => {
+            Err(RecorderError::NoSupportedConfig(
+                device.name().unwrap_or_else(|_| "Unknown".to_string()),
+                kind,
+            ))
+        }
+        config => Ok(config?),
+    }
+}
+
+/// Config to capture from `device` in the direction of `kind` with: for an input device,
+/// the one closest to the format `config` asks for, as `stream_config::closest` picks it,
+/// and `default` otherwise. Loopback of an output device keeps the mix format.
+fn capture_config(
+    device: &cpal::Device,
+    kind: DeviceKind,
+    default: cpal::SupportedStreamConfig,
+    config: &RecorderConfig,
+) -> cpal::SupportedStreamConfig {
+    if kind == DeviceKind::Output {
+        return default;
+    }
+    let ranges = device
+        .supported_input_configs()
+        .map(|configs| configs.collect::<Vec<_>>())
+        .unwrap_or_default();
+    // A channel picked out of the device has to be captured.
+    let min_channels = config.channel.map_or(1, |channel| channel + 1);
+    let wanted = config.requested_format();
+    match stream_config::closest(&ranges, &wanted, min_channels) {
+        Some(closest) => {
+            debug!(
+                "Capturing {} channels, {} Hz, {:?} over the default {} channels, {} Hz, {:?}",
+                closest.channels(),
+                closest.sample_rate().0,
+                closest.sample_format(),
+                default.channels(),
+                default.sample_rate().0,
+                default.sample_format()
+            );
+            closest
+        }
+        None => {
+            debug!(
+                "No config captures at {} Hz, keeping the default {} channels, {} Hz, {:?}",
+                wanted.sample_rate,
+                default.channels(),
+                default.sample_rate().0,
+                default.sample_format()
+            );
+            default
+        }
     }
 }
 
 impl CpalRecorder {
+    pub fn with_config(config: RecorderConfig) -> Self {
+        CpalRecorder {
+            state: Stopped,
+            config,
+        }
+    }
+
     pub fn get_default_device() -> RecorderResult<(cpal::Device, cpal::SupportedStreamConfig)> {
         #[cfg(target_os = "macos")]
         {
             let host = cpal::host_from_id(cpal::HostId::ScreenCaptureKit)?;
             let device = host
                 .default_input_device()
-                .expect("No output devices found");
-
-            let config = device
-                .default_input_config()
-                .expect("Not found default input config");
-            return Ok((device, config));
+                .ok_or(RecorderError::NoDevice(DeviceKind::Input))?;
+            let config = default_config(&device, DeviceKind::Input)?;
+            Ok((device, config))
+        }
+        #[cfg(target_os = "windows")]
+        {
+            // Always WASAPI, even with other hosts enabled: cpal opens a WASAPI output device
+            // used for input in loopback mode, capturing what it plays in its mix format
+            // without needing a "Stereo Mix" input.
+            let host = cpal::host_from_id(cpal::HostId::Wasapi)?;
+            let device = host
+                .default_output_device()
+                .ok_or(RecorderError::NoDevice(DeviceKind::Output))?;
+            let config = default_config(&device, DeviceKind::Output)?;
+            Ok((device, config))
+        }
+        #[cfg(target_os = "linux")]
+        {
+            match CpalRecorder::find_monitor(None)? {
+                Monitor::Device(device, config) => Ok((device, config)),
+                Monitor::Source(source) => Err(RecorderError::LoopbackUnsupported(format!(
+                    "monitor source {} is recorded by parec, not opened as a device",
+                    source
+                ))),
+            }
         }
-        #[cfg(not(target_os = "macos"))]
+        #[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "linux")))]
         {
             let host = cpal::default_host();
             let device = host
                 .default_output_device()
-                .expect("No output devices found");
-            let config = device
-                .default_output_config()
-                .expect("Not found default output config");
-            return Ok((device, config));
+                .ok_or(RecorderError::NoDevice(DeviceKind::Output))?;
+            let config = default_config(&device, DeviceKind::Output)?;
+            Ok((device, config))
         }
     }
-    pub fn output_format() -> OutputFormat {
-        OutputFormat {
-            channels: 1,
-            sample_rate: 48000,
-            sample_format: cpal::SampleFormat::I16,
+
+    /// Lists the input and output devices of every available host. A device supporting both
+    /// directions is listed once for each.
+    pub fn list_devices() -> RecorderResult<Vec<DeviceInfo>> {
+        Ok(CpalRecorder::enumerate_devices()?
+            .into_iter()
+            .map(|(info, _)| info)
+            .collect())
+    }
+
+    fn enumerate_devices() -> RecorderResult<Vec<(DeviceInfo, cpal::Device)>> {
+        let default_host = cpal::default_host();
+        let defaults = [
+            (
+                DeviceKind::Input,
+                default_host
+                    .default_input_device()
+                    .and_then(|d| d.name().ok()),
+            ),
+            (
+                DeviceKind::Output,
+                default_host
+                    .default_output_device()
+                    .and_then(|d| d.name().ok()),
+            ),
+        ];
+        let mut devices = vec![];
+        for host_id in cpal::available_hosts() {
+            let host = match cpal::host_from_id(host_id) {
+                Ok(host) => host,
+                Err(e) => {
+                    debug!("Skipping host {}: {}", host_id.name(), e);
+                    continue;
+                }
+            };
+            for device in host.input_devices()? {
+                devices.push((DeviceInfo::new(host_id, &device, DeviceKind::Input), device));
+            }
+            for device in host.output_devices()? {
+                devices.push((
+                    DeviceInfo::new(host_id, &device, DeviceKind::Output),
+                    device,
+                ));
+            }
+        }
+        for (info, _) in &mut devices {
+            info.is_default = info.host == default_host.id()
+                && defaults.contains(&(info.kind, Some(info.name.clone())));
         }
+        Ok(devices)
+    }
+
+    fn find_device(
+        selector: &DeviceSelector,
+        config: &RecorderConfig,
+    ) -> RecorderResult<SelectedDevice> {
+        let mut devices = CpalRecorder::enumerate_devices()?;
+        let infos = devices
+            .iter()
+            .map(|(info, _)| info.clone())
+            .collect::<Vec<_>>();
+        let index = selector.select(&infos, SYSTEM_AUDIO_KIND)?;
+        let (info, device) = devices.swap_remove(index);
+        check_loopback(&info)?;
+        let default = default_config(&device, info.kind)?;
+        let config = capture_config(&device, info.kind, default, config);
+        Ok((device, config, info.host))
+    }
+
+    /// Finds the monitor source of a PulseAudio or PipeWire sink, picked as described in
+    /// `monitor::select`.
+    #[cfg(target_os = "linux")]
+    fn find_monitor(name: Option<&str>) -> RecorderResult<Monitor> {
+        let host = cpal::default_host();
+        let mut devices = host.input_devices()?.collect::<Vec<_>>();
+        let names = devices
+            .iter()
+            .map(|device| device.name().unwrap_or_default())
+            .collect::<Vec<_>>();
+        let default_sink = monitor::default_sink();
+        // Some setups list monitor sources as devices of their own.
+        if let Some(index) = monitor::select(&names, default_sink.as_deref(), name) {
+            let device = devices.swap_remove(index);
+            let config = default_config(&device, DeviceKind::Input)?;
+            return Ok(Monitor::Device(device, config));
+        }
+        // Otherwise it is recorded by parec, which PipeWire provides as well.
+        let mut sources = monitor::list_sources();
+        let Some(source) = monitor::select(&sources, default_sink.as_deref(), name) else {
+            return Err(RecorderError::LoopbackUnsupported(match name {
+                Some(name) => format!("no monitor source matching \"{}\"", name),
+                None => "no PulseAudio or PipeWire monitor source found".to_string(),
+            }));
+        };
+        Ok(Monitor::Source(sources.swap_remove(source)))
+    }
+
+    fn find_microphone(
+        name: Option<&str>,
+        config: &RecorderConfig,
+    ) -> RecorderResult<SelectedDevice> {
+        let host = cpal::default_host();
+        let device = match name {
+            Some(name) => {
+                let mut devices = host.input_devices()?.collect::<Vec<_>>();
+                let infos = devices
+                    .iter()
+                    .map(|device| DeviceInfo::new(host.id(), device, DeviceKind::Input))
+                    .collect::<Vec<_>>();
+                let index =
+                    DeviceSelector::ByName(name.to_string()).select(&infos, DeviceKind::Input)?;
+                devices.swap_remove(index)
+            }
+            None => host
+                .default_input_device()
+                .ok_or(RecorderError::NoDevice(DeviceKind::Input))?,
+        };
+        let default = default_config(&device, DeviceKind::Input)?;
+        let config = capture_config(&device, DeviceKind::Input, default, config);
+        Ok((device, config, host.id()))
     }
 }
 
 impl CpalRecorder<Stopped> {
     pub fn start(self) -> RecorderResult<CpalRecorder<Started>> {
-        let (device, config) = CpalRecorder::get_default_device()?;
+        let output_format = self.config.requested_format();
+        if !SUPPORTED_SAMPLE_RATES.contains(&output_format.sample_rate) {
+            return Err(RecorderError::UnsupportedSampleRate(
+                output_format.sample_rate,
+            ));
+        }
+        if output_format.sample_rate as u64 * self.config.frame_ms as u64 / 1000 == 0 {
+            return Err(RecorderError::InvalidFrameDuration(self.config.frame_ms));
+        }
+        #[cfg(not(feature = "denoise"))]
+        if self.config.denoise {
+            return Err(RecorderError::DenoiseUnavailable);
+        }
+        #[cfg(feature = "denoise")]
+        if self.config.denoise && output_format.sample_rate != DENOISE_SAMPLE_RATE {
+            return Err(RecorderError::DenoiseSampleRate(output_format.sample_rate));
+        }
+        let capacity = self.config.buffer_ms as usize * output_format.sample_rate as usize / 1000;
+        let (tx, rx) = SampleQueue::new(capacity, self.config.overflow_policy);
+        let capture = CaptureState {
+            paused: Arc::new(AtomicBool::new(false)),
+            framer: Arc::new(Mutex::new(Framer::new(
+                output_format.sample_rate,
+                self.config.frame_ms,
+            ))),
+            vad: self.config.skip_silence.then(|| {
+                Arc::new(Mutex::new(Vad::new(
+                    self.config.vad.clone(),
+                    output_format.sample_rate,
+                )))
+            }),
+            level: Arc::new(LevelMeter::new()),
+            gain: Arc::new(GainControl::new(self.config.gain_db)),
+            agc: (self.config.agc.clone()).map(|config| Arc::new(Mutex::new(Agc::new(config)))),
+            health: (self.config.health.clone()).map(|config| {
+                Arc::new(Mutex::new(HealthMonitor::new(
+                    config,
+                    output_format.sample_rate,
+                )))
+            }),
+            latency_us: Arc::new(AtomicU64::new(UNKNOWN_LATENCY)),
+            first_frame: Arc::new(watch::Sender::new(None)),
+            preroll: Arc::new(Mutex::new(
+                self.config
+                    .preroll_ms
+                    .map(|preroll_ms| Preroll::new(output_format.sample_rate, preroll_ms)),
+            )),
+            #[cfg(feature = "denoise")]
+            denoiser: (self.config.denoise).then(|| Arc::new(Mutex::new(Denoiser::new()))),
+        };
+        let started_at = std::time::Instant::now();
+        let streams = self.open_streams(tx, &capture)?;
+        let next_device_check = Instant::now()
+            + self
+                .config
+                .recovery
+                .as_ref()
+                .map_or(Duration::ZERO, |recovery| recovery.poll_interval);
+        let state = Started {
+            streams,
+            sample_data_receiver: rx,
+            capture,
+            next_device_check,
+            recovery_attempts: None,
+            started_at,
+        };
+        Ok(CpalRecorder {
+            state,
+            config: self.config,
+        })
+    }
+}
+
+impl<State> CpalRecorder<State> {
+    /// Selects the configured devices and starts streaming their audio into the queue behind
+    /// `tx`. The streams are left paused if capture is paused.
+    fn open_streams(&self, tx: SampleSender, capture: &CaptureState) -> RecorderResult<Streams> {
+        let sample_rate = self.config.target_sample_rate;
+        let tx = Arc::new(tx);
+        let mut streams = Streams::default();
+        match &self.config.source {
+            CaptureSource::SystemAudio => {
+                let selected = self.system_audio_device()?;
+                streams.add(self.open_system_audio(&selected, &tx, capture, None)?);
+            }
+            CaptureSource::Microphone { device } => {
+                let selected = CpalRecorder::find_microphone(device.as_deref(), &self.config)?;
+                streams.add(self.open_device(&selected, false, &tx, capture, None)?);
+            }
+            CaptureSource::Mixed {
+                microphone,
+                system_gain,
+                microphone_gain,
+            } => {
+                let jitter = (MIX_JITTER_MS * sample_rate / 1000) as usize;
+                let mixer = Arc::new(Mutex::new(Mixer::new(
+                    &[*system_gain, *microphone_gain],
+                    jitter,
+                )));
+                let selected = self.system_audio_device()?;
+                let system_input = MixerInput {
+                    mixer: mixer.clone(),
+                    source: 0,
+                };
+                streams.add(self.open_system_audio(&selected, &tx, capture, Some(system_input))?);
+                let selected = CpalRecorder::find_microphone(microphone.as_deref(), &self.config)?;
+                let microphone_input = MixerInput { mixer, source: 1 };
+                streams.add(self.open_device(
+                    &selected,
+                    false,
+                    &tx,
+                    capture,
+                    Some(microphone_input),
+                )?);
+            }
+        }
+        if !capture.paused.load(Ordering::Relaxed) {
+            streams.play()?;
+        }
+        Ok(streams)
+    }
+
+    /// Names of the devices the platform currently considers the default, for each device
+    /// of the source in `Streams::devices` order. `None` where the configuration names
+    /// a device instead of following the default.
+    fn default_device_names(&self) -> Vec<Option<String>> {
+        let system_audio = || match &self.config.device {
+            Some(_) => None,
+            #[cfg(target_os = "linux")]
+            None => match CpalRecorder::find_monitor(None).ok()? {
+                Monitor::Device(device, _) => device.name().ok(),
+                Monitor::Source(source) => Some(source),
+            },
+            #[cfg(not(target_os = "linux"))]
+            None => CpalRecorder::get_default_device()
+                .ok()
+                .and_then(|(device, _)| device.name().ok()),
+        };
+        let microphone = |name: &Option<String>| match name {
+            Some(_) => None,
+            None => cpal::default_host()
+                .default_input_device()
+                .and_then(|device| device.name().ok()),
+        };
+        match &self.config.source {
+            CaptureSource::SystemAudio => vec![system_audio()],
+            CaptureSource::Microphone { device } => vec![microphone(device)],
+            CaptureSource::Mixed {
+                microphone: name, ..
+            } => vec![system_audio(), microphone(name)],
+        }
+    }
+
+    /// Whether to play silence on a device captured through `loopback`. Microphones never
+    /// need it.
+    fn wants_silent_output(&self, loopback: bool) -> bool {
+        loopback && self.config.enable_silent_output
+    }
+
+    fn system_audio_device(&self) -> RecorderResult<SystemAudio> {
+        // A name picks a monitor source on Linux, or failing that any device.
+        #[cfg(target_os = "linux")]
+        {
+            let monitor = match &self.config.device {
+                Some(DeviceSelector::ByName(name)) => {
+                    match CpalRecorder::find_monitor(Some(name)) {
+                        Err(RecorderError::LoopbackUnsupported(_)) => None,
+                        result => Some(result?),
+                    }
+                }
+                Some(DeviceSelector::ByIndex(_)) => None,
+                None => Some(CpalRecorder::find_monitor(None)?),
+            };
+            match monitor {
+                Some(Monitor::Device(device, config)) => {
+                    return Ok(SystemAudio::Device(
+                        self.system_audio_config((device, config)),
+                    ));
+                }
+                Some(Monitor::Source(source)) => return Ok(SystemAudio::Source(source)),
+                None => {}
+            }
+        }
+        match &self.config.device {
+            Some(selector) => CpalRecorder::find_device(selector, &self.config),
+            None => CpalRecorder::get_default_device()
+                .map(|selected| self.system_audio_config(selected)),
+        }
+        .map(SystemAudio::Device)
+    }
+
+    /// The system audio device picked by default, with the config to capture from it with.
+    fn system_audio_config(
+        &self,
+        (device, default): (cpal::Device, cpal::SupportedStreamConfig),
+    ) -> SelectedDevice {
+        let config = capture_config(&device, SYSTEM_AUDIO_KIND, default, &self.config);
+        (device, config, system_audio_host())
+    }
+
+    /// Builds the streams capturing system audio from `selected`, like `open_device`.
+    fn open_system_audio(
+        &self,
+        selected: &SystemAudio,
+        tx: &Arc<SampleSender>,
+        capture: &CaptureState,
+        mixer: Option<MixerInput>,
+    ) -> RecorderResult<(ActiveDevice, Vec<Stream>)> {
+        match selected {
+            SystemAudio::Device(selected) => self.open_device(selected, true, tx, capture, mixer),
+            #[cfg(target_os = "linux")]
+            SystemAudio::Source(source) => {
+                self.open_source(source, monitor::record_command, tx, capture, mixer)
+            }
+        }
+    }
+
+    /// Records the monitor `source` with the command `record` builds for it, which delivers
+    /// it at the output rate.
+    #[cfg(target_os = "linux")]
+    fn open_source(
+        &self,
+        source: &str,
+        record: fn(&str, RecorderSampleRate, RecorderChannelCount) -> std::process::Command,
+        tx: &Arc<SampleSender>,
+        capture: &CaptureState,
+        mixer: Option<MixerInput>,
+    ) -> RecorderResult<(ActiveDevice, Vec<Stream>)> {
+        let format = OutputFormat {
+            channels: 2,
+            sample_rate: self.config.target_sample_rate,
+            sample_format: cpal::SampleFormat::I16,
+        };
+        debug!("Recording monitor source {} with parec", source);
+        let channel = self.config.channel;
+        if let Some(channel) = channel.filter(|channel| *channel >= format.channels) {
+            return Err(RecorderError::InvalidChannel(channel, format.channels));
+        }
+        let mut handler = self.input_handler(&format, tx, capture, mixer);
+        let error_handler = ErrorHandler { tx: tx.clone() };
+        // Buffers of 10 ms, timed by the samples read so far.
+        let rate = format.sample_rate as u64;
+        let frames = rate / 100;
+        let instant = move |frames: u64| {
+            cpal::StreamInstant::new(
+                (frames / rate) as i64,
+                ((frames % rate) * 1_000_000_000 / rate) as u32,
+            )
+        };
+        let mut position = 0;
+        let recording = monitor::SourceCapture::start(
+            record(source, format.sample_rate, format.channels),
+            frames as usize * format.channels as usize,
+            move |data| {
+                let timestamp = cpal::InputStreamTimestamp {
+                    callback: instant(position + frames),
+                    capture: instant(position),
+                };
+                position += frames;
+                handler.on_data(data, timestamp);
+            },
+            move || error_handler.on_error(cpal::StreamError::DeviceNotAvailable),
+        )
+        .map_err(|e| {
+            RecorderError::LoopbackUnsupported(format!(
+                "failed to run parec to record {}: {}",
+                source, e
+            ))
+        })?;
+        let device = ActiveDevice {
+            name: source.to_string(),
+            host: cpal::default_host().id(),
+            format,
+            buffer_size: None,
+        };
+        Ok((
+            device,
+            vec![Stream::Source {
+                _recording: recording,
+            }],
+        ))
+    }
+
+    /// The handler converting the audio of a device delivering `format` and queueing it on
+    /// `tx`, through `mixer` if set.
+    fn input_handler(
+        &self,
+        format: &OutputFormat,
+        tx: &Arc<SampleSender>,
+        capture: &CaptureState,
+        mixer: Option<MixerInput>,
+    ) -> InputHandler {
+        InputHandler {
+            converter: Converter {
+                downmixer: Downmixer::new(format.channels, self.config.channel),
+                agc: capture.agc.clone(),
+                gain: capture.gain.clone(),
+                dither: self.config.dither.then(Dither::default),
+                resampler: Resampler::new(format.sample_rate, self.config.target_sample_rate),
+                buffers: Buffers::default(),
+            },
+            mixer,
+            mixed: vec![],
+            clock: StreamClock::new(),
+            tx: tx.clone(),
+            capture: capture.clone(),
+        }
+    }
+
+    /// Builds the streams capturing the `selected` device, in the order they should be
+    /// started. Converted audio goes to `tx`, through `mixer` if set.
+    fn open_device(
+        &self,
+        (device, config, host): &SelectedDevice,
+        loopback: bool,
+        tx: &Arc<SampleSender>,
+        capture: &CaptureState,
+        mixer: Option<MixerInput>,
+    ) -> RecorderResult<(ActiveDevice, Vec<Stream>)> {
+        let name = device.name().unwrap_or_else(|_| "Unknown".to_string());
         debug!(
             "Using device: {} config: {} channels, {} Hz, {:?}",
-            device.name().unwrap_or_else(|_| "Unknown".to_string()),
+            name,
             config.channels(),
             config.sample_rate().0,
             config.sample_format()
         );
-        let output_config = device.default_output_config().unwrap();
-        let output_stream = device.build_output_stream(
-            &output_config.config(),
-            move |data: &mut [f32], _| {
-                for sample in data {
-                    *sample = 0.0;
+        permission::request(*host);
+        let mut streams = vec![];
+        if self.wants_silent_output(loopback) {
+            match device.default_output_config() {
+                Ok(output_config) => streams.push(Stream::Cpal(build_silent_output_stream(
+                    device,
+                    &output_config,
+                )?)),
+                Err(e) => debug!("Not playing silence on {}: {}", name, e),
+            }
+        }
+        let channel = self.config.channel;
+        if let Some(channel) = channel.filter(|channel| *channel >= config.channels()) {
+            return Err(RecorderError::InvalidChannel(channel, config.channels()));
+        }
+        let format = OutputFormat {
+            channels: config.channels(),
+            sample_rate: config.sample_rate().0,
+            sample_format: config.sample_format(),
+        };
+        // Every device gets its own converter, so audio from a device replacing a lost one is
+        // still resampled to the advertised output rate.
+        let handler = || self.input_handler(&format, tx, capture, mixer.clone());
+        let error_handler = || ErrorHandler { tx: tx.clone() };
+        let mut buffer_size = input_buffer_size(&name, config, self.config.buffer_size)?;
+        let stream =
+            match build_input_stream(device, config, buffer_size, handler(), error_handler()) {
+                Err(RecorderError::BuildStreamError(e))
+                    if let cpal::BufferSize::Fixed(frames) = buffer_size =>
+                {
+                    warn!(
+                        "{} refused buffers of {} frames, using its default: {}",
+                        name, frames, e
+                    );
+                    buffer_size = cpal::BufferSize::Default;
+                    build_input_stream(device, config, buffer_size, handler(), error_handler())?
                 }
+                result => result?,
+            };
+        streams.push(Stream::Cpal(stream));
+        let device = ActiveDevice {
+            name,
+            host: *host,
+            format,
+            buffer_size: match buffer_size {
+                cpal::BufferSize::Fixed(frames) => Some(frames),
+                cpal::BufferSize::Default => None,
             },
-            |_| {},
-            None,
-        )?;
-        let (tx, rx) = unbounded_channel();
-        let stream = device.build_input_stream(
-            &config.config(),
-            move |data: &[f32], _| {
-                let mut data = data.to_vec();
-                // If config is multi-channel, need to convert to single-channel
-                if config.channels() > 1 {
-                    data = data
-                        .chunks_exact(2) // 每2个样本为一组（左、右声道）
-                        .map(|chunk| (chunk[0] + chunk[1]) / 2.0) // 取平均值
-                        .collect();
+        };
+        Ok((device, streams))
+    }
+}
+
+/// Turns device buffers into the recorder's output format.
+struct Converter {
+    downmixer: Downmixer,
+    agc: Option<Arc<Mutex<Agc>>>,
+    gain: Arc<GainControl>,
+    dither: Option<Dither>,
+    resampler: Resampler,
+    buffers: Buffers,
+}
+
+/// Buffers the stages of a `Converter` write into, reused from one callback to the next so
+/// converting allocates nothing once they have grown to the size of a buffer.
+#[derive(Default)]
+struct Buffers {
+    downmixed: Vec<f32>,
+    converted: Vec<i16>,
+    resampled: Vec<i16>,
+}
+
+impl Converter {
+    fn process<T>(&mut self, data: &[T]) -> &[i16]
+    where
+        T: Sample,
+        f32: FromSample<T>,
+    {
+        let buffers = &mut self.buffers;
+        self.downmixer
+            .process_f32_into(data, &mut buffers.downmixed);
+        if let Some(agc) = &self.agc {
+            let gain_db = agc
+                .lock()
+                .unwrap()
+                .update(&buffers.downmixed, self.resampler.from_rate());
+            self.gain.set_gain_db(gain_db);
+        }
+        self.gain.process_into(
+            &mut buffers.downmixed,
+            self.dither.as_mut(),
+            &mut buffers.converted,
+        );
+        self.resampler
+            .process_into(&buffers.converted, &mut buffers.resampled);
+        &buffers.resampled
+    }
+}
+
+/// One device's input to a `Mixer` shared by several streams.
+#[derive(Clone)]
+struct MixerInput {
+    mixer: Arc<Mutex<Mixer>>,
+    source: usize,
+}
+
+/// State shared between the recorder and the data callbacks of all its streams.
+#[derive(Clone)]
+struct CaptureState {
+    paused: Arc<AtomicBool>,
+    framer: Arc<Mutex<Framer>>,
+    vad: Option<Arc<Mutex<Vad>>>,
+    level: Arc<LevelMeter>,
+    gain: Arc<GainControl>,
+    agc: Option<Arc<Mutex<Agc>>>,
+    health: Option<Arc<Mutex<HealthMonitor>>>,
+    /// Delay between capture and callback of the latest buffer, in microseconds.
+    latency_us: Arc<AtomicU64>,
+    /// When the first frame was finished, held back or not.
+    first_frame: Arc<watch::Sender<Option<std::time::Instant>>>,
+    /// Holds the frames instead of the queue while armed.
+    preroll: Arc<Mutex<Option<Preroll>>>,
+    #[cfg(feature = "denoise")]
+    denoiser: Option<Arc<Mutex<Denoiser>>>,
+}
+
+const UNKNOWN_LATENCY: u64 = u64::MAX;
+
+impl CaptureState {
+    /// Meters a finished frame and queues it, through voice activity detection if enabled.
+    fn emit(&self, tx: &SampleSender, frame: SampleData) {
+        self.first_frame.send_if_modified(|at| {
+            let first = at.is_none();
+            at.get_or_insert_with(std::time::Instant::now);
+            first
+        });
+        self.level.store(Level::of(&frame.data));
+        if let Some(health) = &self.health {
+            for warning in health.lock().unwrap().process(&frame.data) {
+                tx.push(RecorderEvent::Warning(warning));
+            }
+        }
+        if let Some(preroll) = self.preroll.lock().unwrap().as_mut() {
+            preroll.push(frame);
+            return;
+        }
+        match &self.vad {
+            Some(vad) => {
+                for event in vad.lock().unwrap().process(frame) {
+                    tx.push(event);
                 }
-                // Process audio data here
-                let raw_sample_data = data
-                    .iter()
-                    .map(|&s| {
-                        return i16::from_sample(s.clone());
-                    })
-                    .collect::<Vec<i16>>();
-                let sample_data = SampleData {
-                    data: raw_sample_data,
-                    timestamp: SystemTime::now()
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .unwrap()
-                        .as_millis() as u64,
-                };
-                tx.send(sample_data)
-                    .expect("Failed to send data to channel");
-            },
-            |err| {
-                error!("Error occurred on input stream: {}", err);
-            },
+            }
+            None => tx.push(RecorderEvent::Sample(frame)),
+        }
+    }
+}
+
+/// State owned by the input stream's data callback.
+struct InputHandler {
+    converter: Converter,
+    mixer: Option<MixerInput>,
+    /// Mixed audio, reused from one callback to the next like the converter's buffers.
+    mixed: Vec<i16>,
+    clock: StreamClock,
+    tx: Arc<SampleSender>,
+    capture: CaptureState,
+}
+
+impl InputHandler {
+    fn on_data<T>(&mut self, data: &[T], timestamp: cpal::InputStreamTimestamp)
+    where
+        T: Sample,
+        f32: FromSample<T>,
+    {
+        // Some hosts keep delivering buffers for a moment after the stream is paused.
+        if self.capture.paused.load(Ordering::Relaxed) {
+            if let Some(input) = &self.mixer {
+                input.mixer.lock().unwrap().clear();
+            }
+            return;
+        }
+        let latency = timestamp
+            .callback
+            .duration_since(&timestamp.capture)
+            .unwrap_or_default();
+        self.capture
+            .latency_us
+            .store(latency.as_micros() as u64, Ordering::Relaxed);
+        let captured_ms = self
+            .clock
+            .capture_time(timestamp.capture, timestamp.callback, now_ms());
+        let mut data = self.converter.process(data);
+        if let Some(input) = &self.mixer {
+            let mut mixer = input.mixer.lock().unwrap();
+            mixer.push_into(input.source, data, &mut self.mixed);
+            if self.mixed.is_empty() {
+                return;
+            }
+            data = &self.mixed;
+        }
+        #[cfg(feature = "denoise")]
+        let denoised;
+        #[cfg(feature = "denoise")]
+        let (data, captured_ms) = match &self.capture.denoiser {
+            Some(denoiser) => {
+                let mut denoiser = denoiser.lock().unwrap();
+                // Denoised audio starts with the samples held back from the last buffer.
+                let held_ms = denoiser.buffered() as u64 * 1000 / DENOISE_SAMPLE_RATE as u64;
+                denoised = denoiser.process(data);
+                if denoised.is_empty() {
+                    return;
+                }
+                (&denoised[..], captured_ms.saturating_sub(held_ms))
+            }
+            None => (data, captured_ms),
+        };
+        // The framer only moves timestamps forward, which keeps them monotonic when a
+        // replacement device brings a clock of its own.
+        let mut framer = self.capture.framer.lock().unwrap();
+        for frame in framer.push(data, captured_ms) {
+            self.capture.emit(&self.tx, frame);
+        }
+    }
+}
+
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// State owned by the input stream's error callback.
+struct ErrorHandler {
+    tx: Arc<SampleSender>,
+}
+
+impl ErrorHandler {
+    fn on_error(&self, err: cpal::StreamError) {
+        error!("Error occurred on input stream: {}", err);
+        let event = match err {
+            cpal::StreamError::DeviceNotAvailable => RecorderEvent::DeviceLost,
+            err => RecorderEvent::Error(err.to_string()),
+        };
+        self.tx.push(event);
+    }
+}
+
+/// Fills an output buffer with silence, which is the midpoint for unsigned formats.
+fn fill_silence<T: Sample>(data: &mut [T]) {
+    data.fill(T::EQUILIBRIUM);
+}
+
+fn build_silent_output_stream(
+    device: &cpal::Device,
+    config: &cpal::SupportedStreamConfig,
+) -> RecorderResult<cpal::Stream> {
+    fn build<T: SizedSample>(
+        device: &cpal::Device,
+        config: &cpal::SupportedStreamConfig,
+    ) -> RecorderResult<cpal::Stream> {
+        let stream = device.build_output_stream(
+            &config.config(),
+            move |data: &mut [T], _| fill_silence(data),
+            |err| debug!("Error occurred on silent output stream: {}", err),
             None,
         )?;
-        output_stream.play()?;
-        stream.play()?;
-        let state = Started {
-            input_stream: stream,
-            output_stream: output_stream,
-            sample_data_receiver: rx,
-        };
-        Ok(CpalRecorder { state })
+        Ok(stream)
+    }
+    match config.sample_format() {
+        cpal::SampleFormat::F32 => build::<f32>(device, config),
+        cpal::SampleFormat::I16 => build::<i16>(device, config),
+        cpal::SampleFormat::U16 => build::<u16>(device, config),
+        cpal::SampleFormat::I32 => build::<i32>(device, config),
+        cpal::SampleFormat::F64 => build::<f64>(device, config),
+        sample_format => Err(RecorderError::UnsupportedSampleFormat(sample_format)),
+    }
+}
+
+/// Buffer size to open `config` of the device `name` with, checked against the sizes it
+/// advertises.
+fn input_buffer_size(
+    name: &str,
+    config: &cpal::SupportedStreamConfig,
+    requested: Option<u32>,
+) -> RecorderResult<cpal::BufferSize> {
+    let Some(requested) = requested else {
+        return Ok(cpal::BufferSize::Default);
+    };
+    match *config.buffer_size() {
+        cpal::SupportedBufferSize::Range { min, max } if !(min..=max).contains(&requested) => {
+            Err(RecorderError::UnsupportedBufferSize {
+                name: name.to_string(),
+                requested,
+                min,
+                max,
+            })
+        }
+        _ => Ok(cpal::BufferSize::Fixed(requested)),
+    }
+}
+
+fn build_input_stream(
+    device: &cpal::Device,
+    config: &cpal::SupportedStreamConfig,
+    buffer_size: cpal::BufferSize,
+    handler: InputHandler,
+    error_handler: ErrorHandler,
+) -> RecorderResult<cpal::Stream> {
+    let stream_config = cpal::StreamConfig {
+        buffer_size,
+        ..config.config()
+    };
+    match config.sample_format() {
+        cpal::SampleFormat::F32 => {
+            build_typed_input_stream::<f32>(device, &stream_config, handler, error_handler)
+        }
+        cpal::SampleFormat::I16 => {
+            build_typed_input_stream::<i16>(device, &stream_config, handler, error_handler)
+        }
+        cpal::SampleFormat::U16 => {
+            build_typed_input_stream::<u16>(device, &stream_config, handler, error_handler)
+        }
+        cpal::SampleFormat::I32 => {
+            build_typed_input_stream::<i32>(device, &stream_config, handler, error_handler)
+        }
+        cpal::SampleFormat::F64 => {
+            build_typed_input_stream::<f64>(device, &stream_config, handler, error_handler)
+        }
+        sample_format => Err(RecorderError::UnsupportedSampleFormat(sample_format)),
     }
 }
 
+fn build_typed_input_stream<T>(
+    device: &cpal::Device,
+    stream_config: &cpal::StreamConfig,
+    mut handler: InputHandler,
+    error_handler: ErrorHandler,
+) -> RecorderResult<cpal::Stream>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let stream = device.build_input_stream(
+        stream_config,
+        move |data: &[T], info: &cpal::InputCallbackInfo| handler.on_data(data, info.timestamp()),
+        move |err| error_handler.on_error(err),
+        None,
+    )?;
+    Ok(stream)
+}
+
 impl CpalRecorder<Started> {
+    /// Waits for the next chunk of audio, skipping over other events. Use `recv_event` to see
+    /// stream errors as well.
     pub async fn reveice_sample_data(&mut self) -> Option<SampleData> {
-        return self.state.sample_data_receiver.recv().await;
+        loop {
+            match self.recv_event().await? {
+                RecorderEvent::Sample(sample_data) => return Some(sample_data),
+                event => debug!("Skipping recorder event: {:?}", event),
+            }
+        }
+    }
+
+    /// Waits up to `timeout` for the first frame of audio, which some hosts take a moment
+    /// to deliver after starting, like ScreenCaptureKit on macOS. Returns the warm-up, how
+    /// long after starting it came. Frames held back, by the preroll or while silent, count
+    /// as well, and none is taken from the recorder.
+    ///
+    /// Without audio, a device of a host that needs a permission is taken to lack it, as
+    /// those start without audio rather than fail, and `PermissionDenied` tells where it's
+    /// granted.
+    pub async fn wait_for_first_frame(&self, wait: Duration) -> RecorderResult<Duration> {
+        let mut first_frame = self.state.capture.first_frame.subscribe();
+        if let Ok(Ok(_)) = timeout(wait, first_frame.wait_for(Option::is_some)).await {
+            return Ok(self.warm_up().expect("the first frame came"));
+        }
+        let device = match self.state.streams.description() {
+            description if description.is_empty() => "the device".to_string(),
+            description => description,
+        };
+        let devices = &self.state.streams.devices;
+        match devices
+            .iter()
+            .find_map(|device| permission::needed(device.host))
+        {
+            Some(permission) => Err(RecorderError::PermissionDenied {
+                device,
+                permission,
+                waited: wait,
+            }),
+            None => Err(RecorderError::NoAudio(device, wait)),
+        }
+    }
+
+    /// How long after starting the first frame of audio came, once it did.
+    pub fn warm_up(&self) -> Option<Duration> {
+        let at = (*self.state.capture.first_frame.borrow())?;
+        Some(at.saturating_duration_since(self.state.started_at))
+    }
+
+    /// Waits for the next sample or stream event. Returns `None` once the streams are gone.
+    ///
+    /// With `RecorderConfig::recovery` set, a lost device or a change of the default device
+    /// is handled here: the devices are reopened and `DeviceChanged` returned, or `Fatal`
+    /// once every attempt failed.
+    pub async fn recv_event(&mut self) -> Option<RecorderEvent> {
+        let Some(recovery) = self.config.recovery.clone() else {
+            return self.state.sample_data_receiver.pop().await;
+        };
+        if self.state.recovery_attempts.is_some() {
+            return Some(self.recover(&recovery).await);
+        }
+        let queue = self.state.sample_data_receiver.clone();
+        loop {
+            select! {
+                event = queue.pop() => {
+                    return match event {
+                        Some(RecorderEvent::DeviceLost) => {
+                            warn!("Capture device lost: {}", self.state.streams.description());
+                            Some(self.recover(&recovery).await)
+                        }
+                        event => event,
+                    };
+                }
+                _ = sleep_until(self.state.next_device_check) => {
+                    self.state.next_device_check = Instant::now() + recovery.poll_interval;
+                    let changed = self
+                        .default_device_names()
+                        .iter()
+                        .zip(&self.state.streams.devices)
+                        .any(|(default, current)| {
+                            default.as_ref().is_some_and(|default| *default != current.name)
+                        });
+                    if changed {
+                        info!("Default capture device changed");
+                        return Some(self.recover(&recovery).await);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Replaces the streams with ones opened anew, retrying as configured.
+    async fn recover(&mut self, recovery: &RecoveryConfig) -> RecorderEvent {
+        loop {
+            let attempt = self.state.recovery_attempts.unwrap_or(0) + 1;
+            self.state.recovery_attempts = Some(attempt);
+            // The new streams share the queue, so the consumer keeps receiving on it.
+            let tx = self.state.sample_data_receiver.sender();
+            match self.open_streams(tx, &self.state.capture) {
+                Ok(streams) => {
+                    self.state.recovery_attempts = None;
+                    let old = std::mem::replace(&mut self.state.streams, streams);
+                    return RecorderEvent::DeviceChanged {
+                        old: old.description(),
+                        new: self.state.streams.description(),
+                    };
+                }
+                Err(e) if attempt >= recovery.max_attempts => {
+                    self.state.recovery_attempts = None;
+                    return RecorderEvent::Fatal(format!(
+                        "Failed to reopen capture device after {} attempts: {}",
+                        attempt, e
+                    ));
+                }
+                Err(e) => {
+                    warn!("Attempt {} to reopen capture device failed: {}", attempt, e);
+                    sleep(recovery.retry_interval).await;
+                }
+            }
+        }
+    }
+
+    /// Stops capturing without tearing down the streams. The frame in progress is queued
+    /// as it is, so the audio before the pause isn't held back. No audio is queued while
+    /// paused; `reveice_sample_data` keeps waiting until the recorder is resumed.
+    pub fn pause(&mut self) -> RecorderResult<()> {
+        self.state.capture.paused.store(true, Ordering::Relaxed);
+        self.state.streams.pause()?;
+        let frame = self.state.capture.framer.lock().unwrap().flush();
+        if let Some(frame) = frame {
+            let tx = self.state.sample_data_receiver.sender();
+            self.state.capture.emit(&tx, frame);
+        }
+        Ok(())
+    }
+
+    pub fn resume(&mut self) -> RecorderResult<()> {
+        self.state.streams.play()?;
+        self.state.capture.paused.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.capture.paused.load(Ordering::Relaxed)
+    }
+
+    /// Changes the gain applied to captured audio, taking effect with the next buffer. With
+    /// automatic gain control the change only lasts until the next buffer adjusts it.
+    pub fn set_gain_db(&self, gain_db: f32) {
+        self.state.capture.gain.set_gain_db(gain_db);
+    }
+
+    /// Gain currently applied, as configured or as chosen by automatic gain control.
+    pub fn gain_db(&self) -> f32 {
+        self.state.capture.gain.gain_db()
+    }
+
+    /// Number of samples clipped so far because the gain pushed them beyond full scale.
+    pub fn clipped_samples(&self) -> u64 {
+        self.state.capture.gain.clipped_samples()
+    }
+
+    /// Delay between the device capturing audio and the recorder receiving it, as reported
+    /// by the host for the latest buffer. `None` until the first buffer arrives.
+    pub fn capture_latency(&self) -> Option<Duration> {
+        match self.state.capture.latency_us.load(Ordering::Relaxed) {
+            UNKNOWN_LATENCY => None,
+            latency_us => Some(Duration::from_micros(latency_us)),
+        }
+    }
+
+    /// Format of the emitted `SampleData`, as the pipeline produces it: whatever the devices
+    /// deliver is downmixed to mono, resampled to the rate frames are cut at and converted
+    /// to i16.
+    pub fn output_format(&self) -> OutputFormat {
+        OutputFormat {
+            channels: 1,
+            sample_rate: self.state.capture.framer.lock().unwrap().sample_rate(),
+            sample_format: cpal::SampleFormat::I16,
+        }
+    }
+
+    /// Devices captured from, in the order of the configured source: the system audio
+    /// device before the microphone for `CaptureSource::Mixed`. Changes when the recorder
+    /// recovers from a lost device.
+    pub fn devices(&self) -> &[ActiveDevice] {
+        &self.state.streams.devices
+    }
+
+    /// Name of the device captured from, the system audio device for
+    /// `CaptureSource::Mixed`.
+    pub fn device_name(&self) -> &str {
+        &self.devices()[0].name
+    }
+
+    /// Format the device captured from delivers, before conversion to `output_format`.
+    pub fn device_config(&self) -> &OutputFormat {
+        &self.devices()[0].format
+    }
+
+    /// Host of the device captured from.
+    pub fn host_id(&self) -> RecorderHostId {
+        self.devices()[0].host
+    }
+
+    /// Ends the armed state of `RecorderConfig::preroll_ms`, returning the audio kept so
+    /// far, oldest first. Later audio goes to `recv_event`, continuing right after it. Empty
+    /// without pre-roll or once taken.
+    pub fn take_preroll(&self) -> Vec<SampleData> {
+        match self.state.capture.preroll.lock().unwrap().take() {
+            Some(mut preroll) => preroll.take(),
+            None => vec![],
+        }
     }
 
-    pub fn stop(self) -> RecorderResult<CpalRecorder<Stopped>> {
+    /// Level of the latest frame, including frames dropped as silence. Cheap enough to poll
+    /// for a meter.
+    pub fn current_level(&self) -> Level {
+        self.state.capture.level.load()
+    }
+
+    /// Number of samples discarded so far because the consumer fell behind.
+    pub fn dropped_samples(&self) -> u64 {
+        self.state.sample_data_receiver.dropped_samples()
+    }
+
+    /// Stops capturing, returning the audio not yet received through `recv_event` along
+    /// with the stopped recorder: frames still queued, followed by the final partial frame
+    /// unless it falls into skipped silence.
+    pub fn stop(self) -> RecorderResult<(CpalRecorder<Stopped>, Vec<SampleData>)> {
         debug!("Stopping recorder...");
-        self.state.input_stream.pause()?;
-        self.state.output_stream.pause()?;
-        Ok(CpalRecorder { state: Stopped })
+        self.state.streams.pause()?;
+        // Dropping the streams drops their senders, so nothing is queued behind the drain.
+        drop(self.state.streams);
+        let mut remaining = self
+            .state
+            .sample_data_receiver
+            .drain()
+            .into_iter()
+            .filter_map(|event| match event {
+                RecorderEvent::Sample(sample_data) => Some(sample_data),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let silent =
+            (self.state.capture.vad.as_ref()).is_some_and(|vad| vad.lock().unwrap().is_silent());
+        let last_frame = self.state.capture.framer.lock().unwrap().flush();
+        remaining.extend(last_frame.filter(|_| !silent));
+        let recorder = CpalRecorder {
+            state: Stopped,
+            config: self.config,
+        };
+        Ok((recorder, remaining))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(name: &str, kind: DeviceKind) -> DeviceInfo {
+        DeviceInfo {
+            name: name.to_string(),
+            host: cpal::default_host().id(),
+            kind,
+            default_sample_rate: Some(48000),
+            channels: Some(2),
+            sample_formats: vec![cpal::SampleFormat::F32],
+            is_default: false,
+        }
+    }
+
+    fn devices() -> Vec<DeviceInfo> {
+        vec![
+            device("Built-in Microphone", DeviceKind::Input),
+            device("USB Audio Interface", DeviceKind::Input),
+            device("Built-in Speakers", DeviceKind::Output),
+            device("USB Audio Interface", DeviceKind::Output),
+        ]
+    }
+
+    #[test]
+    fn converts_device_audio_to_output_rate() {
+        // One second of a 440 Hz stereo sine at 44.1 kHz, in 10 ms device buffers.
+        let input = (0..44100)
+            .flat_map(|i| {
+                let t = i as f32 / 44100.0;
+                let sample = (2.0 * std::f32::consts::PI * 440.0 * t).sin() * 0.5;
+                [sample, sample]
+            })
+            .collect::<Vec<f32>>();
+        let mut converter = Converter {
+            downmixer: Downmixer::new(2, None),
+            agc: None,
+            gain: Arc::new(GainControl::new(0.0)),
+            dither: None,
+            resampler: Resampler::new(44100, 48000),
+            buffers: Buffers::default(),
+        };
+        let output = input
+            .chunks(882)
+            .flat_map(|buffer| converter.process(buffer).to_vec())
+            .collect::<Vec<i16>>();
+
+        assert!((output.len() as i64 - 48000).abs() <= 2);
+        let zero_crossings = output
+            .windows(2)
+            .filter(|pair| (pair[0] < 0) != (pair[1] < 0))
+            .count();
+        // A 440 Hz sine crosses zero 880 times per second.
+        assert!((zero_crossings as i64 - 880).abs() <= 2);
+    }
+
+    fn capture_state(frame_ms: u32) -> CaptureState {
+        CaptureState {
+            paused: Arc::new(AtomicBool::new(false)),
+            framer: Arc::new(Mutex::new(Framer::new(48000, frame_ms))),
+            vad: None,
+            level: Arc::new(LevelMeter::new()),
+            gain: Arc::new(GainControl::new(0.0)),
+            agc: None,
+            health: None,
+            latency_us: Arc::new(AtomicU64::new(UNKNOWN_LATENCY)),
+            first_frame: Arc::new(watch::Sender::new(None)),
+            preroll: Arc::new(Mutex::new(None)),
+            #[cfg(feature = "denoise")]
+            denoiser: None,
+        }
+    }
+
+    /// Callback timestamp of a buffer captured at `capture_ms` on the stream clock and
+    /// delivered 20 ms later.
+    fn at(capture_ms: u64) -> cpal::InputStreamTimestamp {
+        let instant =
+            |ms: u64| cpal::StreamInstant::new((ms / 1000) as i64, (ms % 1000) as u32 * 1_000_000);
+        cpal::InputStreamTimestamp {
+            callback: instant(capture_ms + 20),
+            capture: instant(capture_ms),
+        }
+    }
+
+    fn handler(tx: SampleSender, capture: &CaptureState) -> InputHandler {
+        InputHandler {
+            converter: Converter {
+                downmixer: Downmixer::new(1, None),
+                agc: capture.agc.clone(),
+                gain: capture.gain.clone(),
+                dither: None,
+                resampler: Resampler::new(48000, 48000),
+                buffers: Buffers::default(),
+            },
+            mixer: None,
+            mixed: vec![],
+            clock: StreamClock::new(),
+            tx: Arc::new(tx),
+            capture: capture.clone(),
+        }
+    }
+
+    #[tokio::test]
+    async fn paused_handler_queues_nothing() {
+        let (tx, queue) = SampleQueue::new(48000, OverflowPolicy::DropNewest);
+        let capture = capture_state(10);
+        let paused = capture.paused.clone();
+        let mut handler = handler(tx, &capture);
+
+        handler.on_data(&[0.5f32; 480], at(0));
+        paused.store(true, Ordering::Relaxed);
+        handler.on_data(&[0.25f32; 480], at(10));
+        paused.store(false, Ordering::Relaxed);
+        handler.on_data(&[-0.5f32; 480], at(20));
+        drop(handler);
+
+        let mut received = vec![];
+        while let Some(RecorderEvent::Sample(sample_data)) = queue.pop().await {
+            received.push(sample_data.data[0]);
+        }
+        assert_eq!(received, [16384, -16384]);
+    }
+
+    #[tokio::test]
+    async fn handler_applies_live_gain() {
+        let (tx, queue) = SampleQueue::new(48000, OverflowPolicy::DropNewest);
+        let capture = capture_state(10);
+        let mut handler = handler(tx, &capture);
+
+        handler.on_data(&[0.25f32; 480], at(0));
+        capture.gain.set_gain_db(20.0);
+        handler.on_data(&[0.25f32; 480], at(10));
+        drop(handler);
+
+        let mut received = vec![];
+        while let Some(RecorderEvent::Sample(sample_data)) = queue.pop().await {
+            received.push(sample_data.data[0]);
+        }
+        assert_eq!(received, [8192, i16::MAX]);
+        assert_eq!(capture.gain.clipped_samples(), 480);
+    }
+
+    #[test]
+    fn handler_outlives_the_consumer() {
+        let (tx, queue) = SampleQueue::new(4800, OverflowPolicy::DropOldest);
+        let capture = capture_state(10);
+        let mut handler = handler(tx, &capture);
+        drop(queue);
+
+        // With nobody receiving, audio keeps being metered and dropped once the queue is
+        // full, instead of the audio thread panicking.
+        for index in 0..100 {
+            handler.on_data(&[0.5f32; 480], at(index * 10));
+        }
+        assert!(capture.level.load().peak_dbfs > -7.0);
+    }
+
+    #[tokio::test]
+    async fn handler_passes_audio_through_untouched_without_denoise() {
+        let (tx, queue) = SampleQueue::new(48000, OverflowPolicy::DropNewest);
+        let capture = capture_state(10);
+        let mut handler = handler(tx, &capture);
+
+        // Samples that convert to i16 exactly, at arbitrary buffer sizes.
+        let input = (0..960)
+            .map(|i| ((i * 37) % 2001 - 1000) as i16)
+            .collect::<Vec<_>>();
+        let floats = input
+            .iter()
+            .map(|&s| s as f32 / 32768.0)
+            .collect::<Vec<_>>();
+        handler.on_data(&floats[..300], at(0));
+        handler.on_data(&floats[300..], at(6));
+        drop(handler);
+
+        let mut received = vec![];
+        while let Some(RecorderEvent::Sample(sample_data)) = queue.pop().await {
+            received.extend(sample_data.data);
+        }
+        assert_eq!(received, input);
+    }
+
+    #[tokio::test]
+    async fn handler_emits_fixed_frames() {
+        let (tx, queue) = SampleQueue::new(48000, OverflowPolicy::DropNewest);
+        let capture = capture_state(100);
+        let mut handler = handler(tx, &capture);
+
+        let mut capture_ms = 0;
+        for size in [512, 1024, 3000, 7, 441] {
+            handler.on_data(&vec![0.5f32; size], at(capture_ms));
+            capture_ms += size as u64 * 1000 / 48000;
+        }
+        drop(handler);
+
+        let mut frames = vec![];
+        while let Some(RecorderEvent::Sample(sample_data)) = queue.pop().await {
+            frames.push(sample_data);
+        }
+        assert_eq!(frames.len(), 4984 / 4800);
+        assert_eq!(frames[0].data.len(), 4800);
+        let last_frame = capture.framer.lock().unwrap().flush().unwrap();
+        assert_eq!(last_frame.data.len(), 4984 - 4800);
+        assert!(last_frame.timestamp > frames[0].timestamp);
+    }
+
+    /// A started recorder without streams, fed through handlers instead.
+    fn started(queue: Arc<SampleQueue>, capture: CaptureState) -> CpalRecorder<Started> {
+        CpalRecorder {
+            state: Started {
+                streams: Streams::default(),
+                sample_data_receiver: queue,
+                capture,
+                next_device_check: Instant::now(),
+                recovery_attempts: None,
+                started_at: std::time::Instant::now(),
+            },
+            config: RecorderConfig::default(),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn mixes_a_recorded_monitor_without_redirecting_the_microphone() {
+        let (tx, queue) = SampleQueue::new(48000, OverflowPolicy::DropNewest);
+        let tx = Arc::new(tx);
+        let capture = capture_state(10);
+        let recorder = CpalRecorder::with_config(RecorderConfig {
+            source: CaptureSource::Mixed {
+                microphone: None,
+                system_gain: 1.0,
+                microphone_gain: 1.0,
+            },
+            ..Default::default()
+        });
+        let mixer = Arc::new(Mutex::new(Mixer::new(&[1.0, 1.0], 4800)));
+        let input = |source| {
+            Some(MixerInput {
+                mixer: mixer.clone(),
+                source,
+            })
+        };
+        let microphone = OutputFormat {
+            channels: 1,
+            sample_rate: 48000,
+            sample_format: cpal::SampleFormat::F32,
+        };
+        let mut microphone = recorder.input_handler(&microphone, &tx, &capture, input(1));
+        microphone.on_data(&[0.25f32; 480], at(0));
+
+        // 10 ms of the monitor, in stereo samples of 4096, standing in for parec.
+        fn record(
+            _: &str,
+            _: RecorderSampleRate,
+            _: RecorderChannelCount,
+        ) -> std::process::Command {
+            let mut command = std::process::Command::new("sh");
+            command
+                .arg("-c")
+                .arg(r#"i=0; while [ $i -lt 960 ]; do printf '\000\020'; i=$((i+1)); done"#)
+                .stdout(std::process::Stdio::piped());
+            command
+        }
+        let (device, _streams) = recorder
+            .open_source("sink.monitor", record, &tx, &capture, input(0))
+            .unwrap();
+        assert_eq!(device.name, "sink.monitor");
+
+        let Some(RecorderEvent::Sample(frame)) = queue.pop().await else {
+            panic!("expected a frame");
+        };
+        assert_eq!(frame.data.len(), 480);
+        assert!(frame.data.iter().all(|&sample| (sample - 12288).abs() <= 2));
+        assert!(matches!(queue.pop().await, Some(RecorderEvent::DeviceLost)));
+        // The source went to parec alone, so a microphone opened through the PulseAudio ALSA
+        // plugin stays on the default source.
+        assert!(std::env::var_os("PULSE_SOURCE").is_none());
+    }
+
+    #[tokio::test]
+    async fn waits_for_the_first_frame() {
+        let (tx, queue) = SampleQueue::new(48000, OverflowPolicy::DropNewest);
+        let capture = capture_state(10);
+        *capture.preroll.lock().unwrap() = Some(Preroll::new(48000, 50));
+        let mut handler = handler(tx, &capture);
+        let recorder = started(queue.clone(), capture);
+
+        // A device that never delivers.
+        let error = recorder
+            .wait_for_first_frame(Duration::from_millis(50))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, RecorderError::NoAudio(_, wait) if wait.as_millis() == 50));
+        assert!(
+            error
+                .to_string()
+                .starts_with("No audio from the device within 0.05 s, check"),
+            "{}",
+            error
+        );
+        assert_eq!(recorder.warm_up(), None);
+
+        // A frame ends the wait, though the preroll holds it back.
+        handler.on_data(&[0.5f32; 480], at(0));
+        let warm_up = recorder
+            .wait_for_first_frame(Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert!(warm_up >= Duration::from_millis(50));
+        assert_eq!(recorder.warm_up(), Some(warm_up));
+        assert!(queue.drain().is_empty());
+        // Later frames don't move it.
+        handler.on_data(&[0.5f32; 480], at(10));
+        assert_eq!(recorder.warm_up(), Some(warm_up));
+        assert_eq!(recorder.take_preroll().len(), 2);
+    }
+
+    #[test]
+    fn stop_returns_queued_frames() {
+        let (tx, queue) = SampleQueue::new(48000, OverflowPolicy::DropNewest);
+        let capture = capture_state(10);
+        let mut handler = handler(tx, &capture);
+        for (index, value) in [0.125f32, 0.25, 0.5].into_iter().enumerate() {
+            handler.on_data(&[value; 480], at(index as u64 * 10));
+        }
+        handler.on_data(&[-0.5f32; 100], at(30));
+        let recorder = started(queue, capture);
+        // Queued frames outlive the streams' senders.
+        drop(handler);
+
+        let (_, remaining) = recorder.stop().unwrap();
+        let received = remaining
+            .iter()
+            .map(|frame| (frame.data.len(), frame.data[0]))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            received,
+            [(480, 4096), (480, 8192), (480, 16384), (100, -16384)]
+        );
+    }
+
+    #[tokio::test]
+    async fn preroll_keeps_the_newest_audio_until_taken() {
+        let (tx, queue) = SampleQueue::new(48000, OverflowPolicy::DropNewest);
+        let capture = capture_state(10);
+        *capture.preroll.lock().unwrap() = Some(Preroll::new(48000, 50));
+        let mut handler = handler(tx, &capture);
+        let recorder = started(queue.clone(), capture);
+
+        // A second of 10 ms frames while armed, each a step louder.
+        for index in 0..100 {
+            handler.on_data(&[index as f32 / 1000.0; 480], at(index * 10));
+        }
+        assert!(queue.drain().is_empty());
+        let preroll = recorder.take_preroll();
+        let levels = preroll
+            .iter()
+            .map(|frame| frame.data[0])
+            .collect::<Vec<_>>();
+        // Frames 95 to 99.
+        assert_eq!(levels, [3113, 3146, 3178, 3211, 3244]);
+        assert!(recorder.take_preroll().is_empty());
+
+        handler.on_data(&[0.5f32; 480], at(1000));
+        drop(handler);
+        let mut frames = preroll;
+        while let Some(RecorderEvent::Sample(sample_data)) = queue.pop().await {
+            frames.push(sample_data);
+        }
+        assert_eq!(frames.len(), 6);
+        assert!(
+            frames
+                .windows(2)
+                .all(|pair| pair[0].timestamp < pair[1].timestamp)
+        );
+    }
+
+    #[tokio::test]
+    async fn handler_timestamps_follow_capture_time() {
+        let (tx, queue) = SampleQueue::new(48000, OverflowPolicy::DropNewest);
+        let capture = capture_state(10);
+        let mut first = handler(tx, &capture);
+        let mut replacement = handler(queue.sender(), &capture);
+
+        // Callbacks arrive late and irregularly, the capture times are steady.
+        first.on_data(&[0.5f32; 480], at(1000));
+        first.on_data(&[0.5f32; 480], at(1010));
+        first.on_data(&[0.5f32; 480], at(1020));
+        // A capture time that jumps back doesn't move the next frame back.
+        first.on_data(&[0.5f32; 480], at(1005));
+        // Neither does a replacement device whose clock starts over.
+        replacement.on_data(&[0.5f32; 480], at(0));
+        replacement.on_data(&[0.5f32; 480], at(10));
+        assert_eq!(
+            capture.latency_us.load(Ordering::Relaxed),
+            Duration::from_millis(20).as_micros() as u64
+        );
+        drop(first);
+        drop(replacement);
+
+        let mut timestamps = vec![];
+        while let Some(RecorderEvent::Sample(sample_data)) = queue.pop().await {
+            timestamps.push(sample_data.timestamp);
+        }
+        assert_eq!(timestamps.len(), 6);
+        assert_eq!(timestamps[1] - timestamps[0], 10);
+        assert_eq!(timestamps[2] - timestamps[1], 10);
+        assert!(timestamps.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[tokio::test]
+    async fn stream_errors_reach_the_consumer() {
+        let (tx, queue) = SampleQueue::new(48000, OverflowPolicy::DropOldest);
+        let error_handler = ErrorHandler { tx: Arc::new(tx) };
+        let callback = move |err| error_handler.on_error(err);
+
+        callback(cpal::StreamError::BackendSpecific {
+            err: cpal::BackendSpecificError {
+                description: "buffer overrun".to_string(),
+            },
+        });
+        callback(cpal::StreamError::DeviceNotAvailable);
+        drop(callback);
+
+        match queue.pop().await {
+            Some(RecorderEvent::Error(message)) => assert!(message.contains("buffer overrun")),
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert!(matches!(queue.pop().await, Some(RecorderEvent::DeviceLost)));
+        assert!(queue.pop().await.is_none());
+    }
+
+    #[test]
+    fn requested_format_is_the_target_rate() {
+        let config = RecorderConfig {
+            target_sample_rate: 16000,
+            ..Default::default()
+        };
+        assert_eq!(config.requested_format().sample_rate, 16000);
+        assert_eq!(config.requested_format().channels, 1);
+        assert_eq!(
+            RecorderConfig::default().requested_format().sample_rate,
+            48000
+        );
+    }
+
+    #[tokio::test]
+    async fn output_format_matches_emitted_audio() {
+        let (tx, queue) = SampleQueue::new(96000, OverflowPolicy::DropNewest);
+        let capture = capture_state(100);
+        // A 44.1 kHz stereo device.
+        let mut handler = InputHandler {
+            converter: Converter {
+                downmixer: Downmixer::new(2, None),
+                agc: None,
+                gain: capture.gain.clone(),
+                dither: None,
+                resampler: Resampler::new(44100, 48000),
+                buffers: Buffers::default(),
+            },
+            ..handler(tx, &capture)
+        };
+        let recorder = started(queue.clone(), capture);
+
+        for index in 0..10 {
+            handler.on_data(&[0.25f32; 8820], at(index * 100));
+        }
+        drop(handler);
+        let mut frames = vec![];
+        while let Some(RecorderEvent::Sample(sample_data)) = queue.pop().await {
+            frames.push(sample_data);
+        }
+
+        let format = recorder.output_format();
+        assert_eq!(format.channels, 1);
+        assert_eq!(format.sample_rate, 48000);
+        // Frames of 100 ms at the reported rate, one second of audio in all.
+        assert!(frames.iter().all(|frame| frame.data.len() == 4800));
+        let len = frames.len() * 4800
+            + recorder
+                .stop()
+                .unwrap()
+                .1
+                .iter()
+                .map(|frame| frame.data.len())
+                .sum::<usize>();
+        assert!((len as i64 - 48000).abs() < 100, "{} samples", len);
+    }
+
+    #[test]
+    fn reports_the_devices_captured_from() {
+        let (_, queue) = SampleQueue::new(48000, OverflowPolicy::DropNewest);
+        let mut recorder = started(queue, capture_state(100));
+        let device = |name: &str, channels, sample_rate| ActiveDevice {
+            name: name.to_string(),
+            host: cpal::default_host().id(),
+            format: OutputFormat {
+                channels,
+                sample_rate,
+                sample_format: cpal::SampleFormat::F32,
+            },
+            buffer_size: None,
+        };
+        recorder
+            .state
+            .streams
+            .add((device("Speakers", 2, 44100), vec![]));
+        recorder
+            .state
+            .streams
+            .add((device("Headset", 1, 16000), vec![]));
+
+        assert_eq!(recorder.device_name(), "Speakers");
+        assert_eq!(recorder.device_config().sample_rate, 44100);
+        assert_eq!(recorder.device_config().channels, 2);
+        assert_eq!(recorder.host_id(), cpal::default_host().id());
+        assert_eq!(recorder.devices().len(), 2);
+        assert_eq!(recorder.state.streams.description(), "Speakers + Headset");
+    }
+
+    #[test]
+    fn checks_buffer_sizes_against_the_device() {
+        let config = |buffer_size| {
+            cpal::SupportedStreamConfig::new(
+                2,
+                cpal::SampleRate(48000),
+                buffer_size,
+                cpal::SampleFormat::F32,
+            )
+        };
+        let range = config(cpal::SupportedBufferSize::Range { min: 64, max: 4096 });
+        assert_eq!(
+            input_buffer_size("Mic", &range, None).unwrap(),
+            cpal::BufferSize::Default
+        );
+        assert_eq!(
+            input_buffer_size("Mic", &range, Some(480)).unwrap(),
+            cpal::BufferSize::Fixed(480)
+        );
+        match input_buffer_size("Mic", &range, Some(16)) {
+            Err(e @ RecorderError::UnsupportedBufferSize { .. }) => {
+                assert_eq!(
+                    e.to_string(),
+                    "Mic takes buffers of 64 to 4096 frames, not 16"
+                );
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+        // Without a range to check against, the host gets to decide.
+        let unknown = config(cpal::SupportedBufferSize::Unknown);
+        assert_eq!(
+            input_buffer_size("Mic", &unknown, Some(16)).unwrap(),
+            cpal::BufferSize::Fixed(16)
+        );
+    }
+
+    #[test]
+    fn buffer_latency_follows_the_device_rate() {
+        let device = ActiveDevice {
+            name: "Mic".to_string(),
+            host: cpal::default_host().id(),
+            format: OutputFormat {
+                channels: 1,
+                sample_rate: 48000,
+                sample_format: cpal::SampleFormat::F32,
+            },
+            buffer_size: Some(480),
+        };
+        assert_eq!(device.buffer_latency(), Some(Duration::from_millis(10)));
+        let default = ActiveDevice {
+            buffer_size: None,
+            ..device
+        };
+        assert_eq!(default.buffer_latency(), None);
+    }
+
+    #[test]
+    fn silent_output_fills_every_format() {
+        let mut f32_data = [0.5f32; 4];
+        fill_silence(&mut f32_data);
+        assert_eq!(f32_data, [0.0; 4]);
+        let mut i16_data = [100i16; 4];
+        fill_silence(&mut i16_data);
+        assert_eq!(i16_data, [0; 4]);
+        let mut u16_data = [0u16; 4];
+        fill_silence(&mut u16_data);
+        assert_eq!(u16_data, [32768; 4]);
+    }
+
+    #[test]
+    fn silent_output_only_for_loopback() {
+        let recorder = CpalRecorder::with_config(RecorderConfig {
+            enable_silent_output: true,
+            ..Default::default()
+        });
+        assert!(recorder.wants_silent_output(true));
+        assert!(!recorder.wants_silent_output(false));
+        let recorder = CpalRecorder::with_config(RecorderConfig {
+            enable_silent_output: false,
+            ..Default::default()
+        });
+        assert!(!recorder.wants_silent_output(true));
+        assert_eq!(
+            RecorderConfig::default().enable_silent_output,
+            cfg!(target_os = "windows")
+        );
+    }
+
+    #[test]
+    fn start_rejects_unsupported_rates() {
+        let recorder = CpalRecorder::with_config(RecorderConfig {
+            target_sample_rate: 22050,
+            ..Default::default()
+        });
+        match recorder.start() {
+            Err(RecorderError::UnsupportedSampleRate(22050)) => {}
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("recorder started at an unsupported rate"),
+        }
+    }
+
+    #[test]
+    fn start_rejects_denoise_it_cannot_run() {
+        let recorder = CpalRecorder::with_config(RecorderConfig {
+            target_sample_rate: 16000,
+            denoise: true,
+            ..Default::default()
+        });
+        match recorder.start() {
+            #[cfg(feature = "denoise")]
+            Err(RecorderError::DenoiseSampleRate(16000)) => {}
+            #[cfg(not(feature = "denoise"))]
+            Err(RecorderError::DenoiseUnavailable) => {}
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("recorder started with denoise it cannot run"),
+        }
+    }
+
+    #[test]
+    fn selects_by_case_insensitive_substring() {
+        let selector = DeviceSelector::ByName("speakers".to_string());
+        assert_eq!(selector.select(&devices(), DeviceKind::Output).unwrap(), 2);
+    }
+
+    #[test]
+    fn prefers_the_requested_kind() {
+        let selector = DeviceSelector::ByName("usb audio".to_string());
+        assert_eq!(selector.select(&devices(), DeviceKind::Output).unwrap(), 3);
+        assert_eq!(selector.select(&devices(), DeviceKind::Input).unwrap(), 1);
+        let selector = DeviceSelector::ByName("microphone".to_string());
+        assert_eq!(selector.select(&devices(), DeviceKind::Output).unwrap(), 0);
+    }
+
+    #[test]
+    fn selects_by_index() {
+        let selector = DeviceSelector::ByIndex(1);
+        assert_eq!(selector.select(&devices(), DeviceKind::Output).unwrap(), 1);
+        assert!(
+            DeviceSelector::ByIndex(4)
+                .select(&devices(), DeviceKind::Output)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn unknown_name_lists_available_devices() {
+        let selector = DeviceSelector::ByName("headset".to_string());
+        match selector.select(&devices(), DeviceKind::Output) {
+            Err(e @ RecorderError::DeviceNotFound(..)) => {
+                let message = e.to_string();
+                assert!(message.contains("\"headset\""));
+                assert!(message.contains("Built-in Speakers"));
+                assert!(message.contains("USB Audio Interface"));
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
     }
 }
@@ -0,0 +1,127 @@
+/// Streaming linear-interpolation resampler for mono i16 audio.
+///
+/// State is carried between calls to `process`, so audio can be fed in chunks of any size
+/// without discontinuities at the chunk boundaries.
+pub struct Resampler {
+    from_rate: u32,
+    to_rate: u32,
+    // Position of the next output sample, in input samples relative to the current chunk.
+    // -1.0 refers to the last sample of the previous chunk.
+    position: f64,
+    last: Option<i16>,
+}
+
+impl Resampler {
+    pub fn new(from_rate: u32, to_rate: u32) -> Self {
+        Resampler {
+            from_rate,
+            to_rate,
+            position: 0.0,
+            last: None,
+        }
+    }
+
+    pub fn from_rate(&self) -> u32 {
+        self.from_rate
+    }
+
+    pub fn to_rate(&self) -> u32 {
+        self.to_rate
+    }
+
+    pub fn process(&mut self, input: &[i16]) -> Vec<i16> {
+        let mut output = vec![];
+        self.process_into(input, &mut output);
+        output
+    }
+
+    /// Like `process`, replacing the contents of `output` so its allocation is reused.
+    pub fn process_into(&mut self, input: &[i16], output: &mut Vec<i16>) {
+        output.clear();
+        if self.from_rate == self.to_rate {
+            output.extend_from_slice(input);
+            return;
+        }
+        if input.is_empty() {
+            return;
+        }
+        let step = self.from_rate as f64 / self.to_rate as f64;
+        let sample_at = |index: isize| -> f64 {
+            if index < 0 {
+                self.last.unwrap_or(input[0]) as f64
+            } else {
+                input[index as usize] as f64
+            }
+        };
+        let last_index = (input.len() - 1) as f64;
+        output.reserve((input.len() as f64 / step) as usize + 1);
+        while self.position < last_index {
+            let index = self.position.floor();
+            let fraction = self.position - index;
+            let a = sample_at(index as isize);
+            let b = sample_at(index as isize + 1);
+            output.push((a + (b - a) * fraction).round() as i16);
+            self.position += step;
+        }
+        self.position -= input.len() as f64;
+        self.last = input.last().copied();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    fn sine(frequency: f64, sample_rate: u32, seconds: f64) -> Vec<i16> {
+        (0..(sample_rate as f64 * seconds) as usize)
+            .map(|i| {
+                let t = i as f64 / sample_rate as f64;
+                ((2.0 * PI * frequency * t).sin() * 16000.0) as i16
+            })
+            .collect()
+    }
+
+    fn zero_crossings(samples: &[i16]) -> usize {
+        samples
+            .windows(2)
+            .filter(|pair| (pair[0] < 0) != (pair[1] < 0))
+            .count()
+    }
+
+    fn resample_in_chunks(resampler: &mut Resampler, input: &[i16], chunk: usize) -> Vec<i16> {
+        input
+            .chunks(chunk)
+            .flat_map(|chunk| resampler.process(chunk))
+            .collect()
+    }
+
+    #[test]
+    fn same_rate_is_passthrough() {
+        let input = sine(440.0, 48000, 0.1);
+        let mut resampler = Resampler::new(48000, 48000);
+        assert_eq!(resampler.process(&input), input);
+    }
+
+    #[test]
+    fn output_length_follows_rate_ratio() {
+        let input = sine(440.0, 48000, 1.0);
+        let mut resampler = Resampler::new(48000, 24000);
+        let output = resample_in_chunks(&mut resampler, &input, 480);
+        assert!((output.len() as i64 - 24000).abs() <= 1);
+
+        let input = sine(440.0, 44100, 1.0);
+        let mut resampler = Resampler::new(44100, 48000);
+        let output = resample_in_chunks(&mut resampler, &input, 441);
+        assert!((output.len() as i64 - 48000).abs() <= 2);
+    }
+
+    #[test]
+    fn frequency_is_preserved() {
+        let input = sine(440.0, 44100, 1.0);
+        let mut resampler = Resampler::new(44100, 48000);
+        let output = resample_in_chunks(&mut resampler, &input, 137);
+        // A 440 Hz sine crosses zero 880 times per second.
+        assert!((zero_crossings(&output) as i64 - 880).abs() <= 2);
+    }
+}
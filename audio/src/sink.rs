@@ -0,0 +1,295 @@
+use crate::recorder::{OutputFormat, SampleData};
+use log::warn;
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SinkError {
+    #[error("Failed to write WAV file: {0}")]
+    Wav(#[from] hound::Error),
+    #[error("Failed to write audio: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(
+        "Sink takes {} channels at {} Hz, not {} channels at {} Hz",
+        .expected.channels, .expected.sample_rate, .found.channels, .found.sample_rate
+    )]
+    FormatMismatch {
+        expected: OutputFormat,
+        found: OutputFormat,
+    },
+}
+
+pub type SinkResult<T> = std::result::Result<T, SinkError>;
+
+/// Somewhere frames of audio end up, like a file on disk.
+///
+/// Frames hold i16 samples, interleaved if the format has several channels; a sink
+/// converts them to whatever it stores.
+pub trait AudioSink: Send {
+    /// Format of the frames the sink takes.
+    fn format(&self) -> &OutputFormat;
+
+    fn write(&mut self, frame: &SampleData) -> SinkResult<()>;
+
+    /// Flushes what is buffered and completes the output.
+    fn finalize(self: Box<Self>) -> SinkResult<()>;
+
+    /// Files written to so far, for sinks that write files.
+    fn files(&self) -> Vec<PathBuf> {
+        vec![]
+    }
+}
+
+/// What a `MultiSink` does when one of its sinks fails.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// Fail the write, leaving the sinks after the failed one without the frame.
+    #[default]
+    Stop,
+    /// Finalize and drop the failed sink, and keep writing to the others. Writes only fail
+    /// when the last sink does.
+    Continue,
+}
+
+/// Writes every frame to several sinks, in the order they were added.
+pub struct MultiSink {
+    format: OutputFormat,
+    policy: FailurePolicy,
+    sinks: Vec<Box<dyn AudioSink>>,
+    // What dropped sinks failed with and the files they wrote.
+    failures: Vec<SinkError>,
+    dropped_files: Vec<PathBuf>,
+}
+
+impl MultiSink {
+    pub fn new(format: &OutputFormat, policy: FailurePolicy) -> Self {
+        MultiSink {
+            format: format.clone(),
+            policy,
+            sinks: vec![],
+            failures: vec![],
+            dropped_files: vec![],
+        }
+    }
+
+    /// Adds a sink, which must take the same channels and sample rate. The sample format
+    /// may differ, each sink converts frames itself.
+    pub fn add(&mut self, sink: Box<dyn AudioSink>) -> SinkResult<()> {
+        let found = sink.format();
+        if found.channels != self.format.channels || found.sample_rate != self.format.sample_rate {
+            return Err(SinkError::FormatMismatch {
+                expected: self.format.clone(),
+                found: found.clone(),
+            });
+        }
+        self.sinks.push(sink);
+        Ok(())
+    }
+
+    /// Number of sinks still written to.
+    pub fn len(&self) -> usize {
+        self.sinks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    /// Errors of the sinks dropped under `FailurePolicy::Continue`, in the order they
+    /// failed.
+    pub fn failures(&self) -> &[SinkError] {
+        &self.failures
+    }
+}
+
+impl AudioSink for MultiSink {
+    fn format(&self) -> &OutputFormat {
+        &self.format
+    }
+
+    fn write(&mut self, frame: &SampleData) -> SinkResult<()> {
+        let mut index = 0;
+        while index < self.sinks.len() {
+            let Err(e) = self.sinks[index].write(frame) else {
+                index += 1;
+                continue;
+            };
+            if self.policy == FailurePolicy::Stop {
+                return Err(e);
+            }
+            let sink = self.sinks.remove(index);
+            self.dropped_files.extend(sink.files());
+            // Keeps what the sink wrote before failing, if it still can.
+            if let Err(e) = sink.finalize() {
+                warn!("Failed to finalize a failed audio sink: {}", e);
+            }
+            if self.sinks.is_empty() {
+                return Err(e);
+            }
+            warn!("Audio sink failed, writing to the others: {}", e);
+            self.failures.push(e);
+        }
+        Ok(())
+    }
+
+    /// Finalizes every sink, returning the first error.
+    fn finalize(self: Box<Self>) -> SinkResult<()> {
+        let mut result = Ok(());
+        for sink in self.sinks {
+            let finalized = sink.finalize();
+            if result.is_ok() {
+                result = finalized;
+            }
+        }
+        result
+    }
+
+    fn files(&self) -> Vec<PathBuf> {
+        let mut files = self.dropped_files.clone();
+        files.extend(self.sinks.iter().flat_map(|sink| sink.files()));
+        files
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn format() -> OutputFormat {
+        OutputFormat {
+            channels: 1,
+            sample_rate: 16000,
+            sample_format: cpal::SampleFormat::I16,
+        }
+    }
+
+    fn frame(timestamp: u64) -> SampleData {
+        SampleData {
+            data: vec![timestamp as i16; 160],
+            timestamp,
+        }
+    }
+
+    /// What a `MemorySink` was given.
+    #[derive(Default)]
+    struct Memory {
+        written: Vec<u64>,
+        finalized: bool,
+    }
+
+    /// Records the timestamps of the frames written, failing once it holds `capacity`.
+    struct MemorySink {
+        format: OutputFormat,
+        memory: Arc<Mutex<Memory>>,
+        capacity: usize,
+    }
+
+    impl MemorySink {
+        fn new(capacity: usize) -> (Box<Self>, Arc<Mutex<Memory>>) {
+            let memory = Arc::new(Mutex::new(Memory::default()));
+            let sink = MemorySink {
+                format: format(),
+                memory: memory.clone(),
+                capacity,
+            };
+            (Box::new(sink), memory)
+        }
+    }
+
+    impl AudioSink for MemorySink {
+        fn format(&self) -> &OutputFormat {
+            &self.format
+        }
+
+        fn write(&mut self, frame: &SampleData) -> SinkResult<()> {
+            let mut memory = self.memory.lock().unwrap();
+            if memory.written.len() == self.capacity {
+                return Err(std::io::Error::from(std::io::ErrorKind::StorageFull).into());
+            }
+            memory.written.push(frame.timestamp);
+            Ok(())
+        }
+
+        fn finalize(self: Box<Self>) -> SinkResult<()> {
+            self.memory.lock().unwrap().finalized = true;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writes_every_frame_to_every_sink() {
+        let mut multi = MultiSink::new(&format(), FailurePolicy::Stop);
+        let (a, a_memory) = MemorySink::new(usize::MAX);
+        let (b, b_memory) = MemorySink::new(usize::MAX);
+        multi.add(a).unwrap();
+        multi.add(b).unwrap();
+        for timestamp in 0..5 {
+            multi.write(&frame(timestamp)).unwrap();
+        }
+        Box::new(multi).finalize().unwrap();
+        for memory in [a_memory, b_memory] {
+            let memory = memory.lock().unwrap();
+            assert_eq!(memory.written, [0, 1, 2, 3, 4]);
+            assert!(memory.finalized);
+        }
+    }
+
+    #[test]
+    fn keeps_going_when_a_sink_fails() {
+        let mut multi = MultiSink::new(&format(), FailurePolicy::Continue);
+        let (full, full_memory) = MemorySink::new(2);
+        let (ok, ok_memory) = MemorySink::new(usize::MAX);
+        multi.add(full).unwrap();
+        multi.add(ok).unwrap();
+        for timestamp in 0..5 {
+            multi.write(&frame(timestamp)).unwrap();
+        }
+        assert_eq!(full_memory.lock().unwrap().written, [0, 1]);
+        assert_eq!(ok_memory.lock().unwrap().written, [0, 1, 2, 3, 4]);
+        // The failed sink is finalized right away, keeping what it has.
+        assert!(full_memory.lock().unwrap().finalized);
+        assert_eq!(multi.len(), 1);
+        assert_eq!(multi.failures().len(), 1);
+    }
+
+    #[test]
+    fn fails_when_the_last_sink_does() {
+        let mut multi = MultiSink::new(&format(), FailurePolicy::Continue);
+        let (a, _) = MemorySink::new(1);
+        let (b, _) = MemorySink::new(2);
+        multi.add(a).unwrap();
+        multi.add(b).unwrap();
+        multi.write(&frame(0)).unwrap();
+        multi.write(&frame(1)).unwrap();
+        assert!(multi.write(&frame(2)).is_err());
+        assert!(multi.is_empty());
+    }
+
+    #[test]
+    fn stops_at_the_first_error() {
+        let mut multi = MultiSink::new(&format(), FailurePolicy::Stop);
+        let (full, _) = MemorySink::new(1);
+        let (ok, ok_memory) = MemorySink::new(usize::MAX);
+        multi.add(full).unwrap();
+        multi.add(ok).unwrap();
+        multi.write(&frame(0)).unwrap();
+        assert!(matches!(multi.write(&frame(1)), Err(SinkError::Io(_))));
+        assert_eq!(ok_memory.lock().unwrap().written, [0]);
+        assert_eq!(multi.len(), 2);
+    }
+
+    #[test]
+    fn rejects_sinks_of_another_format() {
+        let stereo = OutputFormat {
+            channels: 2,
+            ..format()
+        };
+        let mut multi = MultiSink::new(&stereo, FailurePolicy::Stop);
+        let (sink, _) = MemorySink::new(usize::MAX);
+        assert!(matches!(
+            multi.add(sink),
+            Err(SinkError::FormatMismatch { .. })
+        ));
+    }
+}
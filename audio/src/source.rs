@@ -0,0 +1,257 @@
+use crate::frame::Framer;
+use crate::recorder::{
+    CpalRecorder, OutputFormat, RecorderEvent, RecorderResult, SampleData, Started, now_ms,
+};
+use crate::wav::WavReader;
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::path::Path;
+use std::time::Duration;
+use tokio::time::{Instant, sleep_until};
+
+/// Duration of the frames produced by sources that don't capture from a device.
+const FRAME_MS: u32 = 100;
+
+/// Anything producing mono i16 audio in frames, live from a device or from elsewhere.
+///
+/// Cpal streams can't be sent between threads, so neither can sources.
+#[async_trait(?Send)]
+pub trait SampleSource {
+    fn output_format(&self) -> OutputFormat;
+
+    /// Returns the next event, or `None` once the source has run dry.
+    async fn next_event(&mut self) -> Option<RecorderEvent>;
+
+    /// Returns the next frame of audio, skipping other events, or `None` once the source has
+    /// run dry.
+    async fn next_frame(&mut self) -> Option<SampleData> {
+        loop {
+            if let RecorderEvent::Sample(sample_data) = self.next_event().await? {
+                return Some(sample_data);
+            }
+        }
+    }
+
+    /// Waits up to `timeout` for audio to flow, returning how long after starting the source
+    /// delivered its first frame. Sources that don't capture from a device have audio from
+    /// the start.
+    async fn wait_for_first_frame(&mut self, _timeout: Duration) -> RecorderResult<Duration> {
+        Ok(Duration::ZERO)
+    }
+
+    /// Stops the source, returning audio it still held back.
+    fn stop(self: Box<Self>) -> RecorderResult<Vec<SampleData>>;
+
+    /// The recorder behind a source capturing from a device, for controlling the capture.
+    fn recorder(&mut self) -> Option<&mut CpalRecorder<Started>> {
+        None
+    }
+}
+
+#[async_trait(?Send)]
+impl SampleSource for CpalRecorder<Started> {
+    fn output_format(&self) -> OutputFormat {
+        CpalRecorder::output_format(self)
+    }
+
+    async fn next_event(&mut self) -> Option<RecorderEvent> {
+        self.recv_event().await
+    }
+
+    async fn wait_for_first_frame(&mut self, timeout: Duration) -> RecorderResult<Duration> {
+        CpalRecorder::wait_for_first_frame(self, timeout).await
+    }
+
+    fn stop(self: Box<Self>) -> RecorderResult<Vec<SampleData>> {
+        let (_, remaining) = CpalRecorder::stop(*self)?;
+        Ok(remaining)
+    }
+
+    fn recorder(&mut self) -> Option<&mut CpalRecorder<Started>> {
+        Some(self)
+    }
+}
+
+/// Frames prepared up front.
+struct Frames {
+    frames: VecDeque<SampleData>,
+}
+
+impl Frames {
+    fn new(sample_rate: u32, samples: &[i16]) -> Self {
+        let mut framer = Framer::new(sample_rate, FRAME_MS);
+        let mut frames = VecDeque::from(framer.push(samples, now_ms()));
+        frames.extend(framer.flush());
+        Frames { frames }
+    }
+
+    async fn next(&mut self) -> Option<SampleData> {
+        self.frames.pop_front()
+    }
+}
+
+/// Audio streamed from a WAV file, converted to mono i16 at a target rate.
+pub struct WavFileSource {
+    reader: WavReader,
+    // When reading started, and how many times faster than it would be recorded.
+    pace: Option<(Instant, f64)>,
+}
+
+impl WavFileSource {
+    /// Opens the file at `path`. With a `speed`, frames are handed out that many times
+    /// faster than they would be recorded, otherwise as fast as they are asked for.
+    pub fn open(
+        path: impl AsRef<Path>,
+        sample_rate: u32,
+        speed: Option<f64>,
+    ) -> RecorderResult<Self> {
+        Ok(WavFileSource {
+            reader: WavReader::open(path)?.with_output(sample_rate, FRAME_MS),
+            pace: speed.map(|speed| (Instant::now(), speed)),
+        })
+    }
+
+    /// Length of the file.
+    pub fn duration(&self) -> Duration {
+        self.reader.duration()
+    }
+}
+
+#[async_trait(?Send)]
+impl SampleSource for WavFileSource {
+    fn output_format(&self) -> OutputFormat {
+        self.reader.output_format()
+    }
+
+    /// A file that turns out to be corrupt part way through ends with `Fatal`.
+    async fn next_event(&mut self) -> Option<RecorderEvent> {
+        match self.reader.next()? {
+            Ok(frame) => {
+                if let Some((start, speed)) = self.pace {
+                    let offset = (frame.timestamp - self.reader.start_ms()) as f64 / speed;
+                    sleep_until(start + Duration::from_secs_f64(offset / 1000.0)).await;
+                }
+                Some(RecorderEvent::Sample(frame))
+            }
+            Err(e) => Some(RecorderEvent::Fatal(format!(
+                "Failed to read WAV file: {}",
+                e
+            ))),
+        }
+    }
+
+    fn stop(self: Box<Self>) -> RecorderResult<Vec<SampleData>> {
+        Ok(vec![])
+    }
+}
+
+/// A sine tone of fixed length, for testing without a device.
+pub struct SineSource {
+    sample_rate: u32,
+    frames: Frames,
+}
+
+impl SineSource {
+    /// `duration` of a sine at `frequency` Hz with a peak `amplitude` of up to 1.0.
+    pub fn new(sample_rate: u32, frequency: f32, amplitude: f32, duration: Duration) -> Self {
+        let len = (sample_rate as u128 * duration.as_millis() / 1000) as usize;
+        let samples = (0..len)
+            .map(|i| {
+                let t = i as f32 / sample_rate as f32;
+                ((2.0 * PI * frequency * t).sin() * amplitude * i16::MAX as f32) as i16
+            })
+            .collect::<Vec<_>>();
+        SineSource {
+            sample_rate,
+            frames: Frames::new(sample_rate, &samples),
+        }
+    }
+
+    pub fn silence(sample_rate: u32, duration: Duration) -> Self {
+        SineSource::new(sample_rate, 0.0, 0.0, duration)
+    }
+}
+
+#[async_trait(?Send)]
+impl SampleSource for SineSource {
+    fn output_format(&self) -> OutputFormat {
+        mono(self.sample_rate)
+    }
+
+    async fn next_event(&mut self) -> Option<RecorderEvent> {
+        self.frames.next().await.map(RecorderEvent::Sample)
+    }
+
+    fn stop(self: Box<Self>) -> RecorderResult<Vec<SampleData>> {
+        Ok(vec![])
+    }
+}
+
+fn mono(sample_rate: u32) -> OutputFormat {
+    OutputFormat {
+        channels: 1,
+        sample_rate,
+        sample_format: cpal::SampleFormat::I16,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn collect(mut source: Box<dyn SampleSource>) -> Vec<SampleData> {
+        let mut frames = vec![];
+        while let Some(frame) = source.next_frame().await {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    #[tokio::test]
+    async fn sine_source_frames_its_duration() {
+        let source = SineSource::new(16000, 440.0, 0.5, Duration::from_millis(250));
+        assert_eq!(source.output_format().sample_rate, 16000);
+        let frames = collect(Box::new(source)).await;
+        let sizes = frames.iter().map(|f| f.data.len()).collect::<Vec<_>>();
+        assert_eq!(sizes, [1600, 1600, 800]);
+        assert_eq!(frames[1].timestamp - frames[0].timestamp, 100);
+        let peak = frames.iter().flat_map(|f| &f.data).max().unwrap();
+        assert!((*peak as i32 - i16::MAX as i32 / 2).abs() < 10);
+
+        let frames = collect(Box::new(SineSource::silence(16000, Duration::from_secs(1)))).await;
+        assert_eq!(frames.len(), 10);
+        assert!(frames.iter().all(|f| f.data.iter().all(|&s| s == 0)));
+    }
+
+    #[tokio::test]
+    async fn wav_source_converts_to_the_target_format() {
+        let path = std::env::temp_dir().join(format!("st-source-{}.wav", std::process::id()));
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 48000,
+            bits_per_sample: 24,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        // Half a second of a constant at half scale on the left, silence on the right.
+        for _ in 0..24000 {
+            writer.write_sample(1 << 22).unwrap();
+            writer.write_sample(0).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let source = WavFileSource::open(&path, 16000, None).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(source.duration(), Duration::from_millis(500));
+        let frames = collect(Box::new(source)).await;
+        let samples = frames.iter().flat_map(|f| &f.data).collect::<Vec<_>>();
+        assert_eq!(samples.len(), 8000);
+        assert_eq!(*samples[4000], 8192);
+    }
+
+    #[tokio::test]
+    async fn wav_source_reports_missing_files() {
+        assert!(WavFileSource::open("/nonexistent/input.wav", 16000, None).is_err());
+    }
+}
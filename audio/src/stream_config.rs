@@ -0,0 +1,156 @@
+//! Picks the config to open an input device with, out of those it supports, so audio the
+//! device can deliver in the output format isn't downmixed, converted or resampled on the
+//! way.
+
+use crate::recorder::OutputFormat;
+use cpal::{SampleFormat, SampleRate, SupportedStreamConfig, SupportedStreamConfigRange};
+
+/// Sample formats the recorder reads.
+const READABLE: [SampleFormat; 5] = [
+    SampleFormat::F32,
+    SampleFormat::I16,
+    SampleFormat::U16,
+    SampleFormat::I32,
+    SampleFormat::F64,
+];
+
+/// Of the `ranges` a device supports, the config closest to `wanted`: one at its sample
+/// rate, preferring its channel count, then fewer channels, then its sample format. Configs
+/// with fewer than `min_channels` are passed over. `None` if no range covers the rate in a
+/// sample format the recorder reads, for the device's default config to be used instead.
+pub fn closest(
+    ranges: &[SupportedStreamConfigRange],
+    wanted: &OutputFormat,
+    min_channels: u16,
+) -> Option<SupportedStreamConfig> {
+    let rate = SampleRate(wanted.sample_rate);
+    ranges
+        .iter()
+        .filter(|range| range.min_sample_rate() <= rate && rate <= range.max_sample_rate())
+        .filter(|range| range.channels() >= min_channels)
+        .filter(|range| READABLE.contains(&range.sample_format()))
+        .copied()
+        .min_by_key(|range| {
+            (
+                range.channels() != wanted.channels,
+                range.channels(),
+                range.sample_format() != wanted.sample_format,
+            )
+        })
+        .map(|range| range.with_sample_rate(rate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpal::SupportedBufferSize;
+
+    fn range(
+        channels: u16,
+        min: u32,
+        max: u32,
+        format: SampleFormat,
+    ) -> SupportedStreamConfigRange {
+        SupportedStreamConfigRange::new(
+            channels,
+            SampleRate(min),
+            SampleRate(max),
+            SupportedBufferSize::Unknown,
+            format,
+        )
+    }
+
+    fn wanted(sample_rate: u32) -> OutputFormat {
+        OutputFormat {
+            channels: 1,
+            sample_rate,
+            sample_format: SampleFormat::I16,
+        }
+    }
+
+    /// Channels, rate and format of the config picked.
+    fn picked(
+        ranges: &[SupportedStreamConfigRange],
+        sample_rate: u32,
+        min_channels: u16,
+    ) -> Option<(u16, u32, SampleFormat)> {
+        closest(ranges, &wanted(sample_rate), min_channels).map(|config| {
+            (
+                config.channels(),
+                config.sample_rate().0,
+                config.sample_format(),
+            )
+        })
+    }
+
+    #[test]
+    fn prefers_the_rate_then_mono_then_16_bit() {
+        // An interface defaulting to stereo 44.1 kHz f32, capturing mono 48 kHz i16 as well.
+        let interface = [
+            range(2, 44100, 44100, SampleFormat::F32),
+            range(2, 48000, 48000, SampleFormat::F32),
+            range(1, 48000, 48000, SampleFormat::F32),
+            range(1, 48000, 48000, SampleFormat::I16),
+            range(1, 44100, 44100, SampleFormat::I16),
+        ];
+        assert_eq!(
+            picked(&interface, 48000, 1),
+            Some((1, 48000, SampleFormat::I16))
+        );
+        // The rate first: stereo at the rate over mono at another.
+        let stereo = [
+            range(1, 44100, 44100, SampleFormat::I16),
+            range(2, 16000, 16000, SampleFormat::F32),
+        ];
+        assert_eq!(
+            picked(&stereo, 16000, 1),
+            Some((2, 16000, SampleFormat::F32))
+        );
+        // Fewer channels over another format.
+        let surround = [
+            range(6, 48000, 48000, SampleFormat::I16),
+            range(2, 48000, 48000, SampleFormat::F32),
+        ];
+        assert_eq!(
+            picked(&surround, 48000, 1),
+            Some((2, 48000, SampleFormat::F32))
+        );
+    }
+
+    #[test]
+    fn picks_the_rate_out_of_a_range() {
+        let ranges = [
+            range(2, 8000, 192000, SampleFormat::F32),
+            range(1, 8000, 96000, SampleFormat::I32),
+        ];
+        assert_eq!(
+            picked(&ranges, 16000, 1),
+            Some((1, 16000, SampleFormat::I32))
+        );
+        assert_eq!(
+            picked(&ranges, 192000, 1),
+            Some((2, 192000, SampleFormat::F32))
+        );
+    }
+
+    #[test]
+    fn falls_back_without_a_match() {
+        // No range at the rate.
+        let ranges = [range(1, 44100, 44100, SampleFormat::I16)];
+        assert_eq!(picked(&ranges, 48000, 1), None);
+        assert_eq!(picked(&[], 48000, 1), None);
+        // Formats the recorder doesn't read.
+        let ranges = [range(1, 48000, 48000, SampleFormat::I8)];
+        assert_eq!(picked(&ranges, 48000, 1), None);
+        // A channel picked out of a stereo device needs both.
+        let ranges = [
+            range(1, 48000, 48000, SampleFormat::I16),
+            range(2, 48000, 48000, SampleFormat::F32),
+        ];
+        assert_eq!(
+            picked(&ranges, 48000, 2),
+            Some((2, 48000, SampleFormat::F32))
+        );
+        assert_eq!(picked(&ranges[..1], 48000, 2), None);
+    }
+}
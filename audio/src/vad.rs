@@ -0,0 +1,168 @@
+use crate::level::rms_dbfs;
+use crate::recorder::{RecorderEvent, SampleData};
+
+#[derive(Clone, Debug)]
+pub struct VadConfig {
+    /// RMS level, in dBFS, above which a frame counts as speech.
+    pub threshold_dbfs: f32,
+    /// How long the level must stay above the threshold before silence ends. Frames heard
+    /// during this time are held back and emitted once it ends, so nothing is cut off.
+    pub attack_ms: u32,
+    /// How long the level must stay below the threshold before silence starts, so pauses
+    /// between words and quiet word endings go through.
+    pub hang_ms: u32,
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        VadConfig {
+            threshold_dbfs: -50.0,
+            attack_ms: 0,
+            hang_ms: 800,
+        }
+    }
+}
+
+/// Energy based voice activity detection gating a stream of frames.
+///
+/// Frames are passed through while speech is detected. Once the level has stayed below the
+/// threshold for the hang time, `SilenceStarted` is emitted and frames are dropped until
+/// the level has stayed above it for the attack time, which emits `SilenceEnded` followed by
+/// the frames held back meanwhile. The gate starts open.
+pub struct Vad {
+    config: VadConfig,
+    sample_rate: u32,
+    silent: bool,
+    // Samples below the threshold while open, or above it while closed.
+    run: u64,
+    held: Vec<SampleData>,
+}
+
+impl Vad {
+    pub fn new(config: VadConfig, sample_rate: u32) -> Self {
+        Vad {
+            config,
+            sample_rate,
+            silent: false,
+            run: 0,
+            held: vec![],
+        }
+    }
+
+    pub fn is_silent(&self) -> bool {
+        self.silent
+    }
+
+    /// Feeds one frame through the gate, returning the events to deliver in its place.
+    pub fn process(&mut self, frame: SampleData) -> Vec<RecorderEvent> {
+        let loud = rms_dbfs(&frame.data) >= self.config.threshold_dbfs;
+        let len = frame.data.len() as u64;
+        if !self.silent {
+            if loud {
+                self.run = 0;
+            } else {
+                self.run += len;
+                if self.run >= self.samples(self.config.hang_ms) {
+                    self.silent = true;
+                    self.run = 0;
+                    return vec![RecorderEvent::SilenceStarted];
+                }
+            }
+            return vec![RecorderEvent::Sample(frame)];
+        }
+        if !loud {
+            self.run = 0;
+            self.held.clear();
+            return vec![];
+        }
+        self.run += len;
+        self.held.push(frame);
+        if self.run < self.samples(self.config.attack_ms) {
+            return vec![];
+        }
+        self.silent = false;
+        self.run = 0;
+        let mut events = vec![RecorderEvent::SilenceEnded];
+        events.extend(self.held.drain(..).map(RecorderEvent::Sample));
+        events
+    }
+
+    fn samples(&self, ms: u32) -> u64 {
+        self.sample_rate as u64 * ms as u64 / 1000
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 16000;
+
+    fn frame(level: f32) -> SampleData {
+        // 100 ms of a 440 Hz sine with the given peak amplitude, relative to full scale.
+        SampleData {
+            data: (0..RATE / 10)
+                .map(|i| {
+                    let t = i as f32 / RATE as f32;
+                    ((2.0 * std::f32::consts::PI * 440.0 * t).sin() * level * 32767.0) as i16
+                })
+                .collect(),
+            timestamp: 0,
+        }
+    }
+
+    /// Runs a sequence of frames through the gate, describing each output event as `S` for
+    /// a sample, `[` for silence starting and `]` for silence ending.
+    fn gate(vad: &mut Vad, levels: &[f32]) -> String {
+        levels
+            .iter()
+            .flat_map(|&level| vad.process(frame(level)))
+            .map(|event| match event {
+                RecorderEvent::Sample(_) => 'S',
+                RecorderEvent::SilenceStarted => '[',
+                RecorderEvent::SilenceEnded => ']',
+                event => panic!("unexpected event {:?}", event),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gates_silence_after_the_hang_time() {
+        let mut vad = Vad::new(VadConfig::default(), RATE);
+        // Speech, then 800 ms of silence: the first seven silent frames still go through.
+        let mut levels = vec![0.5; 3];
+        levels.extend([0.0; 10]);
+        assert_eq!(gate(&mut vad, &levels), "SSSSSSSSSS[");
+        assert!(vad.is_silent());
+        assert_eq!(gate(&mut vad, &[0.5, 0.5]), "]SS");
+    }
+
+    #[test]
+    fn short_pauses_keep_the_gate_open() {
+        let mut vad = Vad::new(VadConfig::default(), RATE);
+        let levels = [0.5, 0.0, 0.0, 0.0, 0.0, 0.5, 0.0, 0.0, 0.0, 0.0, 0.0, 0.5];
+        assert_eq!(gate(&mut vad, &levels), "S".repeat(12));
+    }
+
+    #[test]
+    fn quiet_speech_passes_the_threshold() {
+        let mut vad = Vad::new(VadConfig::default(), RATE);
+        gate(&mut vad, &[0.0; 8]);
+        // A peak of 1% of full scale is about -43 dBFS RMS.
+        assert_eq!(gate(&mut vad, &[0.01]), "]S");
+    }
+
+    #[test]
+    fn attack_holds_frames_until_speech_is_confirmed() {
+        let config = VadConfig {
+            attack_ms: 200,
+            ..Default::default()
+        };
+        let mut vad = Vad::new(config, RATE);
+        gate(&mut vad, &[0.0; 8]);
+        // A single click doesn't end the silence.
+        assert_eq!(gate(&mut vad, &[0.5, 0.0]), "");
+        assert_eq!(gate(&mut vad, &[0.5]), "");
+        assert_eq!(gate(&mut vad, &[0.5]), "]SS");
+    }
+}
@@ -1,10 +1,26 @@
-use std::fs::File;
-use std::io::BufWriter;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write as _};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use cpal::{FromSample, Sample};
 use hound::WavWriter;
 
-use crate::recorder::OutputFormat;
+use crate::convert::f32_to_i16_slice;
+use crate::downmix::Downmixer;
+use crate::frame::Framer;
+use crate::recorder::{OutputFormat, SampleData, now_ms};
+use crate::resample::Resampler;
+use crate::sink::{AudioSink, SinkResult};
+
+/// Audio written between updates of the header, so a file cut short by a crash still opens
+/// with all but the last moments in it.
+const HEADER_UPDATE_MS: u64 = 1000;
+
+/// Duration of the frames `WavReader` yields unless told otherwise.
+const DEFAULT_FRAME_MS: u32 = 100;
 
 fn sample_format(format: cpal::SampleFormat) -> hound::SampleFormat {
     if format.is_float() {
@@ -23,34 +39,695 @@ fn wav_spec_from_config(config: &OutputFormat) -> hound::WavSpec {
     }
 }
 
+/// A WAV file being written.
+///
+/// The header is brought up to date after every second of audio, so a process that dies
+/// without calling `save` leaves a file that opens with what was written up to then. `repair`
+/// recovers the rest.
 pub struct Wav {
     writer: WavWriter<BufWriter<File>>,
+    format: OutputFormat,
+    path: PathBuf,
+    // Samples between header updates, and written since the last one.
+    update_interval: u64,
+    since_update: u64,
 }
 
 impl Wav {
-    pub fn new(path: &str, config: &OutputFormat) -> Self {
+    /// Creates the file at `path` for audio in `config`, which must be 8, 16 or 32-bit
+    /// integer or 32-bit float samples.
+    pub fn new(path: impl AsRef<Path>, config: &OutputFormat) -> hound::Result<Self> {
+        use cpal::SampleFormat::*;
+        if !matches!(config.sample_format, I8 | I16 | I32 | F32) {
+            return Err(hound::Error::Unsupported);
+        }
         let wav_spec = wav_spec_from_config(config);
-        let file = File::create(path).expect("Failed to create WAV file");
-        let file = BufWriter::new(file);
-        let writer = hound::WavWriter::new(file, wav_spec).expect("Failed to create WAV writer");
+        let file = BufWriter::new(File::create(path.as_ref())?);
+        let writer = hound::WavWriter::new(file, wav_spec)?;
+        let samples_per_second = config.sample_rate as u64 * config.channels as u64;
+        Ok(Wav {
+            writer,
+            format: config.clone(),
+            path: path.as_ref().to_path_buf(),
+            update_interval: (samples_per_second * HEADER_UPDATE_MS / 1000).max(1),
+            since_update: 0,
+        })
+    }
 
-        Wav { writer: writer }
+    /// Fixes up the header of a WAV file that was never saved, counting every whole sample
+    /// that made it to disk. A trailing partial sample is cut off. Returns the number of
+    /// samples in the repaired file.
+    pub fn repair(path: impl AsRef<Path>) -> hound::Result<u64> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut riff = [0; 12];
+        file.read_exact(&mut riff)?;
+        if &riff[..4] != b"RIFF" || &riff[8..] != b"WAVE" {
+            return Err(hound::Error::FormatError("no RIFF WAVE header"));
+        }
+        let mut block_align = None;
+        let mut channels = 0;
+        loop {
+            let mut header = [0; 8];
+            file.read_exact(&mut header)
+                .map_err(|_| hound::Error::FormatError("no data chunk"))?;
+            let size = u32::from_le_bytes(header[4..].try_into().unwrap()) as u64;
+            let offset = file.stream_position()?;
+            match &header[..4] {
+                b"fmt " => {
+                    let mut fmt = [0; 14];
+                    file.read_exact(&mut fmt)?;
+                    channels = u16::from_le_bytes([fmt[2], fmt[3]]) as u64;
+                    block_align = Some(u16::from_le_bytes([fmt[12], fmt[13]]) as u64);
+                }
+                b"data" => {
+                    let block_align = block_align
+                        .filter(|align| *align > 0)
+                        .ok_or(hound::Error::FormatError("no fmt chunk before data"))?;
+                    let data_len = (file_len - offset) / block_align * block_align;
+                    file.set_len(offset + data_len)?;
+                    file.seek(SeekFrom::Start(4))?;
+                    file.write_all(&((offset + data_len - 8) as u32).to_le_bytes())?;
+                    file.seek(SeekFrom::Start(offset - 4))?;
+                    file.write_all(&(data_len as u32).to_le_bytes())?;
+                    file.sync_all()?;
+                    return Ok(data_len / block_align * channels);
+                }
+                _ => {}
+            }
+            // Chunks are padded to an even size.
+            file.seek(SeekFrom::Start(offset + size + size % 2))?;
+        }
     }
 
-    pub fn write<T, U>(&mut self, input: &[T]) -> hound::Result<()>
+    /// Writes `input` converted to the sample format of the file, returning the number of
+    /// samples written. Stops at the first error; samples before it may still be buffered
+    /// rather than on disk.
+    pub fn write<T>(&mut self, input: &[T]) -> hound::Result<usize>
+    where
+        T: Sample,
+        i8: FromSample<T>,
+        i16: FromSample<T>,
+        i32: FromSample<T>,
+        f32: FromSample<T>,
+    {
+        match self.format.sample_format {
+            cpal::SampleFormat::I8 => self.write_as::<T, i8>(input),
+            cpal::SampleFormat::I16 => self.write_as::<T, i16>(input),
+            cpal::SampleFormat::I32 => self.write_as::<T, i32>(input),
+            _ => self.write_as::<T, f32>(input),
+        }
+    }
+
+    fn write_as<T, U>(&mut self, input: &[T]) -> hound::Result<usize>
     where
         T: Sample,
         U: Sample + hound::Sample + FromSample<T>,
     {
         for &sample in input.iter() {
             let sample: U = U::from_sample(sample);
-            self.writer.write_sample(sample).ok();
+            self.writer.write_sample(sample)?;
+        }
+        self.wrote(input.len())?;
+        Ok(input.len())
+    }
+
+    /// Writes float samples, converted the way the recorder converts them if the file is
+    /// 16-bit.
+    pub fn write_f32(&mut self, input: &[f32]) -> hound::Result<usize> {
+        if self.format.sample_format != cpal::SampleFormat::I16 {
+            return self.write(input);
+        }
+        let mut output = vec![0i16; input.len()];
+        f32_to_i16_slice(input, &mut output, None);
+        for sample in output {
+            self.writer.write_sample(sample)?;
+        }
+        self.wrote(input.len())?;
+        Ok(input.len())
+    }
+
+    /// Updates the header once enough audio was written since the last update.
+    fn wrote(&mut self, samples: usize) -> hound::Result<()> {
+        self.since_update += samples as u64;
+        if self.since_update >= self.update_interval {
+            self.writer.flush()?;
+            self.since_update = 0;
         }
         Ok(())
     }
 
+    /// Completes the header and flushes the file. Until then, the file isn't a valid WAV.
     pub fn save(self) -> hound::Result<()> {
-        self.writer.finalize().unwrap();
+        self.writer.finalize()
+    }
+}
+
+impl AudioSink for Wav {
+    fn format(&self) -> &OutputFormat {
+        &self.format
+    }
+
+    fn write(&mut self, frame: &SampleData) -> SinkResult<()> {
+        Wav::write(self, &frame.data)?;
+        Ok(())
+    }
+
+    fn finalize(self: Box<Self>) -> SinkResult<()> {
+        Ok(self.save()?)
+    }
+
+    fn files(&self) -> Vec<PathBuf> {
+        vec![self.path.clone()]
+    }
+}
+
+/// Size of the header hound writes ahead of the samples.
+const HEADER_BYTES: u64 = 44;
+
+#[derive(Clone, Debug)]
+pub struct RotationConfig {
+    /// Path of each file, formatted with chrono's strftime syntax from the local time the
+    /// file is opened at. Names taken earlier in the same recording get a counter appended.
+    pub template: String,
+    /// Longest audio a file holds.
+    pub max_duration: Option<Duration>,
+    /// Largest size a file grows to, header included.
+    pub max_bytes: Option<u64>,
+}
+
+impl Default for RotationConfig {
+    fn default() -> Self {
+        RotationConfig {
+            template: "capture-%Y%m%d-%H%M%S.wav".to_string(),
+            max_duration: Some(Duration::from_secs(30 * 60)),
+            max_bytes: None,
+        }
+    }
+}
+
+/// Writes i16 audio to a series of WAV files, starting the next one whenever the current
+/// one reaches a configured duration or size.
+///
+/// Each file is finalized as soon as it is full, so a crash loses at most the file being
+/// written. Files split at a sample boundary: their contents joined are exactly the
+/// samples written.
+pub struct RotatingWav {
+    config: RotationConfig,
+    format: OutputFormat,
+    // Samples a file holds at most, a multiple of the channel count.
+    capacity: u64,
+    current: Option<Wav>,
+    written: u64,
+    files: Vec<PathBuf>,
+}
+
+impl RotatingWav {
+    /// Prepares to write audio in `format`. The first file is created by the first write.
+    pub fn new(config: RotationConfig, format: &OutputFormat) -> Self {
+        let channels = format.channels.max(1) as u64;
+        let by_duration = config.max_duration.map(|duration| {
+            duration.as_millis() as u64 * format.sample_rate as u64 / 1000 * channels
+        });
+        let sample_bytes = format.sample_format.sample_size() as u64;
+        let by_size = config
+            .max_bytes
+            .map(|bytes| bytes.saturating_sub(HEADER_BYTES) / sample_bytes);
+        let capacity = match (by_duration, by_size) {
+            (Some(a), Some(b)) => a.min(b),
+            (a, b) => a.or(b).unwrap_or(u64::MAX),
+        };
+        RotatingWav {
+            config,
+            format: format.clone(),
+            // Whole frames only, and never nothing, so every file makes progress.
+            capacity: (capacity / channels * channels).max(channels),
+            current: None,
+            written: 0,
+            files: vec![],
+        }
+    }
+
+    /// Files opened so far, in order, including the one being written.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Writes `samples`, interleaved if the format has several channels, rotating to a new
+    /// file wherever the current one fills up.
+    pub fn write(&mut self, samples: &[i16]) -> hound::Result<usize> {
+        let len = samples.len();
+        let mut samples = samples;
+        while !samples.is_empty() {
+            if self.current.is_none() {
+                self.open_next()?;
+            }
+            let take = ((self.capacity - self.written) as usize).min(samples.len());
+            let wav = self.current.as_mut().unwrap();
+            wav.write(&samples[..take])?;
+            self.written += take as u64;
+            samples = &samples[take..];
+            if self.written == self.capacity {
+                self.close()?;
+            }
+        }
+        Ok(len)
+    }
+
+    /// Finalizes the file being written, returning every file written.
+    pub fn finish(mut self) -> hound::Result<Vec<PathBuf>> {
+        self.close()?;
+        Ok(self.files)
+    }
+
+    fn open_next(&mut self) -> hound::Result<()> {
+        let mut name = String::new();
+        write!(
+            name,
+            "{}",
+            chrono::Local::now().format(&self.config.template)
+        )
+        .map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid file name template {}", self.config.template),
+            )
+        })?;
+        let mut path = PathBuf::from(&name);
+        let mut counter = 1;
+        while self.files.contains(&path) {
+            let stem = Path::new(&name)
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy();
+            let file_name = match Path::new(&name).extension() {
+                Some(extension) => {
+                    format!("{}-{}.{}", stem, counter, extension.to_string_lossy())
+                }
+                None => format!("{}-{}", stem, counter),
+            };
+            path = Path::new(&name).with_file_name(file_name);
+            counter += 1;
+        }
+        self.current = Some(Wav::new(&path, &self.format)?);
+        self.written = 0;
+        self.files.push(path);
         Ok(())
     }
+
+    fn close(&mut self) -> hound::Result<()> {
+        match self.current.take() {
+            Some(wav) => wav.save(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl AudioSink for RotatingWav {
+    fn format(&self) -> &OutputFormat {
+        &self.format
+    }
+
+    fn write(&mut self, frame: &SampleData) -> SinkResult<()> {
+        RotatingWav::write(self, &frame.data)?;
+        Ok(())
+    }
+
+    fn finalize(self: Box<Self>) -> SinkResult<()> {
+        self.finish()?;
+        Ok(())
+    }
+
+    fn files(&self) -> Vec<PathBuf> {
+        self.files.clone()
+    }
+}
+
+/// Streams a WAV file as mono i16 frames, whatever the format of the file.
+///
+/// Samples are read a frame at a time, downmixed, converted to i16 and resampled the way
+/// the recorder treats device audio. Frames are stamped as if the file was being recorded
+/// from the moment it was opened.
+pub struct WavReader {
+    reader: hound::WavReader<BufReader<File>>,
+    spec: hound::WavSpec,
+    downmixer: Downmixer,
+    resampler: Resampler,
+    framer: Framer,
+    frames: VecDeque<SampleData>,
+    start_ms: u64,
+    done: bool,
+}
+
+impl WavReader {
+    /// Opens the file at `path`, yielding 100 ms frames at the file's own rate.
+    pub fn open(path: impl AsRef<Path>) -> hound::Result<Self> {
+        let reader = hound::WavReader::open(path)?;
+        let spec = reader.spec();
+        Ok(WavReader {
+            reader,
+            spec,
+            downmixer: Downmixer::new(spec.channels, None),
+            resampler: Resampler::new(spec.sample_rate, spec.sample_rate),
+            framer: Framer::new(spec.sample_rate, DEFAULT_FRAME_MS),
+            frames: VecDeque::new(),
+            start_ms: now_ms(),
+            done: false,
+        })
+    }
+
+    /// Resamples to `sample_rate` and yields frames of `frame_ms` instead.
+    ///
+    /// Panics if a frame would hold no samples.
+    pub fn with_output(mut self, sample_rate: u32, frame_ms: u32) -> Self {
+        self.resampler = Resampler::new(self.spec.sample_rate, sample_rate);
+        self.framer = Framer::new(sample_rate, frame_ms);
+        self
+    }
+
+    /// Format of the file, as stored.
+    pub fn format(&self) -> OutputFormat {
+        let sample_format = match (self.spec.sample_format, self.spec.bits_per_sample) {
+            (hound::SampleFormat::Float, 64) => cpal::SampleFormat::F64,
+            (hound::SampleFormat::Float, _) => cpal::SampleFormat::F32,
+            (hound::SampleFormat::Int, 8) => cpal::SampleFormat::U8,
+            (hound::SampleFormat::Int, 16) => cpal::SampleFormat::I16,
+            (hound::SampleFormat::Int, 24) => cpal::SampleFormat::I24,
+            (hound::SampleFormat::Int, _) => cpal::SampleFormat::I32,
+        };
+        OutputFormat {
+            channels: self.spec.channels,
+            sample_rate: self.spec.sample_rate,
+            sample_format,
+        }
+    }
+
+    /// Format of the frames yielded.
+    pub fn output_format(&self) -> OutputFormat {
+        OutputFormat {
+            channels: 1,
+            sample_rate: self.framer.sample_rate(),
+            sample_format: cpal::SampleFormat::I16,
+        }
+    }
+
+    /// Length of the file.
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.reader.duration() as f64 / self.spec.sample_rate as f64)
+    }
+
+    /// Capture time the first frame is stamped with.
+    pub fn start_ms(&self) -> u64 {
+        self.start_ms
+    }
+
+    /// Reads samples for about one frame, as f32 in the range -1.0 to 1.0.
+    fn read_chunk(&mut self) -> hound::Result<Vec<f32>> {
+        let len = self.framer.frame_len() as u64 * self.spec.sample_rate as u64
+            / self.framer.sample_rate() as u64
+            * self.spec.channels as u64;
+        let len = len.max(self.spec.channels as u64) as usize;
+        match self.spec.sample_format {
+            hound::SampleFormat::Float => self.reader.samples::<f32>().take(len).collect(),
+            hound::SampleFormat::Int => {
+                let scale = 1.0 / (1u64 << (self.spec.bits_per_sample - 1)) as f32;
+                self.reader
+                    .samples::<i32>()
+                    .take(len)
+                    .map(|sample| sample.map(|sample| sample as f32 * scale))
+                    .collect()
+            }
+        }
+    }
+}
+
+impl Iterator for WavReader {
+    type Item = hound::Result<SampleData>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.frames.is_empty() && !self.done {
+            let chunk = match self.read_chunk() {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            };
+            if chunk.is_empty() {
+                self.done = true;
+                self.frames.extend(self.framer.flush());
+                break;
+            }
+            let samples = self.resampler.process(&self.downmixer.process(&chunk));
+            self.frames
+                .extend(self.framer.push(&samples, self.start_ms));
+        }
+        self.frames.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format() -> OutputFormat {
+        OutputFormat {
+            channels: 1,
+            sample_rate: 16000,
+            sample_format: cpal::SampleFormat::I16,
+        }
+    }
+
+    #[test]
+    fn writes_and_reads_back() {
+        let path = std::env::temp_dir().join(format!("st-wav-{}.wav", std::process::id()));
+        let mut wav = Wav::new(&path, &format()).unwrap();
+        assert_eq!(wav.write(&[1i16, -2, 3]).unwrap(), 3);
+        assert_eq!(wav.write_f32(&[0.5]).unwrap(), 1);
+        wav.save().unwrap();
+
+        let samples = hound::WavReader::open(&path)
+            .unwrap()
+            .into_samples::<i16>()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(samples, [1, -2, 3, 16384]);
+    }
+
+    fn read_all(reader: WavReader) -> Vec<SampleData> {
+        reader.collect::<hound::Result<Vec<_>>>().unwrap()
+    }
+
+    #[test]
+    fn converts_to_the_sample_format_of_the_file() {
+        let path = std::env::temp_dir().join(format!("st-wav-float-{}.wav", std::process::id()));
+        let float = OutputFormat {
+            sample_format: cpal::SampleFormat::F32,
+            ..format()
+        };
+        let mut wav: Box<dyn AudioSink> = Box::new(Wav::new(&path, &float).unwrap());
+        let frame = SampleData {
+            data: vec![i16::MIN, 0, 16384],
+            timestamp: 0,
+        };
+        wav.write(&frame).unwrap();
+        assert_eq!(wav.files(), std::slice::from_ref(&path));
+        wav.finalize().unwrap();
+
+        let samples = hound::WavReader::open(&path)
+            .unwrap()
+            .into_samples::<f32>()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(samples, [-1.0, 0.0, 0.5]);
+    }
+
+    #[test]
+    fn round_trips_through_the_reader() {
+        let path = std::env::temp_dir().join(format!("st-wav-rt-{}.wav", std::process::id()));
+        let input = (0..4000)
+            .map(|i| ((i * 7919) % 65536 - 32768) as i16)
+            .collect::<Vec<_>>();
+        let mut wav = Wav::new(&path, &format()).unwrap();
+        wav.write(&input).unwrap();
+        wav.save().unwrap();
+
+        let reader = WavReader::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let format = reader.format();
+        assert_eq!(format.channels, 1);
+        assert_eq!(format.sample_rate, 16000);
+        assert_eq!(format.sample_format, cpal::SampleFormat::I16);
+        let frames = read_all(reader);
+        let sizes = frames.iter().map(|f| f.data.len()).collect::<Vec<_>>();
+        assert_eq!(sizes, [1600, 1600, 800]);
+        assert_eq!(frames[1].timestamp - frames[0].timestamp, 100);
+        // 16-bit audio comes back bit for bit.
+        let output = frames.into_iter().flat_map(|f| f.data).collect::<Vec<_>>();
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn converts_float_stereo_to_the_output_format() {
+        let path = std::env::temp_dir().join(format!("st-wav-f32-{}.wav", std::process::id()));
+        let format = OutputFormat {
+            channels: 2,
+            sample_rate: 44100,
+            sample_format: cpal::SampleFormat::F32,
+        };
+        // One second at a quarter of full scale on the left, silence on the right.
+        let mut wav = Wav::new(&path, &format).unwrap();
+        let input = [0.25f32, 0.0].repeat(44100);
+        wav.write(&input).unwrap();
+        wav.save().unwrap();
+
+        let reader = WavReader::open(&path).unwrap().with_output(16000, 250);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(reader.format().sample_format, cpal::SampleFormat::F32);
+        assert_eq!(reader.output_format().sample_rate, 16000);
+        let frames = read_all(reader);
+        assert!(frames[..3].iter().all(|f| f.data.len() == 4000));
+        let len = frames.iter().map(|f| f.data.len()).sum::<usize>();
+        assert!((len as i64 - 16000).abs() <= 2, "{} samples", len);
+        assert_eq!(frames[1].data[0], 4096);
+    }
+
+    fn rotation(name: &str) -> RotationConfig {
+        let dir = std::env::temp_dir().join(format!("st-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        RotationConfig {
+            template: dir
+                .join("capture-%H%M%S.wav")
+                .to_string_lossy()
+                .into_owned(),
+            max_duration: None,
+            max_bytes: None,
+        }
+    }
+
+    /// Reads every file back, checking each is a finalized WAV of at most `max_len` samples.
+    fn read_segments(files: &[PathBuf], max_len: usize) -> Vec<i16> {
+        let mut samples = vec![];
+        for path in files {
+            let mut reader = hound::WavReader::open(path).unwrap();
+            let segment = reader
+                .samples::<i16>()
+                .collect::<hound::Result<Vec<_>>>()
+                .unwrap();
+            // A header that was never finalized claims no samples.
+            assert_eq!(reader.duration() as usize, segment.len());
+            assert!(segment.len() <= max_len);
+            samples.extend(segment);
+        }
+        std::fs::remove_dir_all(files[0].parent().unwrap()).unwrap();
+        samples
+    }
+
+    #[test]
+    fn rotates_by_duration_without_losing_samples() {
+        let config = RotationConfig {
+            max_duration: Some(Duration::from_secs(1)),
+            ..rotation("rotate-duration")
+        };
+        let mut wav = RotatingWav::new(config, &format());
+        // 2.5 seconds in frames that straddle the boundaries.
+        let input = (0..40000).map(|i| (i % 30011) as i16).collect::<Vec<_>>();
+        for frame in input.chunks(1500) {
+            assert_eq!(wav.write(frame).unwrap(), frame.len());
+        }
+        let files = wav.finish().unwrap();
+        assert_eq!(files.len(), 3);
+        // Files opened within the same second still get names of their own.
+        let mut names = files.clone();
+        names.dedup();
+        assert_eq!(names.len(), 3);
+        assert_eq!(read_segments(&files, 16000), input);
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let config = RotationConfig {
+            max_bytes: Some(HEADER_BYTES + 2 * 5000),
+            ..rotation("rotate-size")
+        };
+        let mut wav = RotatingWav::new(config, &format());
+        let input = vec![7i16; 12000];
+        wav.write(&input).unwrap();
+        assert_eq!(wav.files().len(), 3);
+        let files = wav.finish().unwrap();
+        for path in &files {
+            assert!(std::fs::metadata(path).unwrap().len() <= HEADER_BYTES + 2 * 5000);
+        }
+        assert_eq!(read_segments(&files, 5000), input);
+    }
+
+    /// Writes 2.5 seconds in 100 ms frames, then dies without saving.
+    fn crash_mid_stream(name: &str) -> (PathBuf, Vec<i16>) {
+        let path = std::env::temp_dir().join(format!("st-{}-{}.wav", name, std::process::id()));
+        let input = (0..40000).map(|i| (i % 20011) as i16).collect::<Vec<_>>();
+        let mut wav = Wav::new(&path, &format()).unwrap();
+        for frame in input.chunks(1600) {
+            wav.write(frame).unwrap();
+        }
+        // Neither the header nor the buffer get another chance, as after a crash.
+        std::mem::forget(wav);
+        (path, input)
+    }
+
+    #[test]
+    fn unsaved_file_opens_up_to_the_last_header_update() {
+        let (path, input) = crash_mid_stream("wav-crash");
+        let samples = hound::WavReader::open(&path)
+            .unwrap()
+            .into_samples::<i16>()
+            .collect::<hound::Result<Vec<_>>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(samples, input[..32000]);
+    }
+
+    #[test]
+    fn repair_recovers_everything_on_disk() {
+        let (path, input) = crash_mid_stream("wav-repair");
+        let on_disk = (std::fs::metadata(&path).unwrap().len() - HEADER_BYTES) / 2;
+        assert_eq!(Wav::repair(&path).unwrap(), on_disk);
+        let samples = hound::WavReader::open(&path)
+            .unwrap()
+            .into_samples::<i16>()
+            .collect::<hound::Result<Vec<_>>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(samples.len() as u64, on_disk);
+        assert!(on_disk >= 32000);
+        assert_eq!(samples, input[..samples.len()]);
+    }
+
+    #[test]
+    fn repair_rejects_other_files() {
+        let path = std::env::temp_dir().join(format!("st-not-wav-{}.wav", std::process::id()));
+        std::fs::write(&path, b"not a wav file at all").unwrap();
+        let result = Wav::repair(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(hound::Error::FormatError(_))));
+    }
+
+    #[test]
+    fn creating_in_a_missing_directory_fails() {
+        let path = std::env::temp_dir().join("st-missing-dir").join("out.wav");
+        assert!(matches!(
+            Wav::new(&path, &format()),
+            Err(hound::Error::IoError(_))
+        ));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn write_errors_reach_the_caller() {
+        // Every write to /dev/full fails once the buffer is flushed.
+        let mut wav = Wav::new("/dev/full", &format()).unwrap();
+        let result = (0..16).try_for_each(|_| wav.write(&[0i16; 4096]).map(|_| ()));
+        assert!(matches!(result, Err(hound::Error::IoError(_))));
+    }
 }
@@ -0,0 +1,342 @@
+//! A local WebSocket server speaking enough of the Gummy protocol for tests.
+//!
+//! By default every binary frame received is answered with a final `result-generated` event
+//! whose text is the frame's content, so tests can tell which connection a result came from.
+//! A [`Script`] replaces the echo with a fixed sequence of results, typically loaded from a
+//! JSON fixture, and can inject delays, slow reading and faults. A `run-task` with a sample rate below
+//! 8000 Hz is always rejected with `task-failed`. `task-finished` bills the audio received
+//! as 16-bit mono at the task's sample rate, rounded up to the second.
+
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::time::sleep;
+use tokio_tungstenite::accept_hdr_async;
+use tungstenite::Message;
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::StatusCode;
+
+/// Receive buffer of connections reading slowly, in bytes.
+const SLOW_READ_BUFFER: u32 = 64 * 1024;
+
+/// Scripted server behaviour for a connection.
+///
+/// Each binary frame received is answered with the next entry of `results`, used as the
+/// `payload.output` of a `result-generated` event. Frames arriving after the script has run
+/// out get no reply.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Script {
+    #[serde(default)]
+    pub results: Vec<Value>,
+    /// Sends the next result each time this much more audio was received, in milliseconds
+    /// at the task's sample rate, rather than one per frame. However the client frames the
+    /// audio, the same results then come after the same audio.
+    #[serde(default)]
+    pub audio_ms_per_result: Option<u64>,
+    /// Delay before every reply, in milliseconds.
+    #[serde(default)]
+    pub delay_ms: u64,
+    /// Pause after each audio frame before reading on, in milliseconds, like a server on a
+    /// congested link. The socket buffers little meanwhile, so the client's
+    /// writes back up.
+    #[serde(default)]
+    pub read_delay_ms: u64,
+    #[serde(default)]
+    pub fault: Option<Fault>,
+    /// How many connections, from the first, the fault is injected in; all of them if
+    /// unset. Later connections run the script without it, like a server recovering.
+    #[serde(default)]
+    pub faulty_connections: Option<usize>,
+    /// The only API key accepted, the handshake being refused with 401 Unauthorized for
+    /// others. Any key is accepted if unset.
+    #[serde(default)]
+    pub api_key: Option<String>,
+}
+
+/// A fault injected once `after` scripted results have been sent.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Fault {
+    /// Sends a `task-failed` event.
+    TaskFailed {
+        after: usize,
+        code: String,
+        message: String,
+    },
+    /// Sends a close frame and stops reading.
+    Close { after: usize },
+    /// Drops the TCP connection without a closing handshake.
+    Disconnect { after: usize },
+}
+
+impl Fault {
+    fn after(&self) -> usize {
+        match self {
+            Fault::TaskFailed { after, .. }
+            | Fault::Close { after }
+            | Fault::Disconnect { after } => *after,
+        }
+    }
+}
+
+impl Script {
+    /// Loads a script from a JSON fixture file, panicking if it can't be read.
+    pub fn from_file(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Failed to read fixture {}: {}", path.display(), e));
+        serde_json::from_str(&content)
+            .unwrap_or_else(|e| panic!("Invalid fixture {}: {}", path.display(), e))
+    }
+}
+
+pub struct MockServer {
+    addr: SocketAddr,
+    run_tasks: Arc<Mutex<Vec<Value>>>,
+}
+
+impl MockServer {
+    /// Starts a server echoing every audio frame back as a final sentence.
+    pub async fn start() -> Self {
+        MockServer::listen(None).await
+    }
+
+    /// Starts a server replaying `script` on every connection.
+    pub async fn with_script(script: Script) -> Self {
+        MockServer::listen(Some(Arc::new(script))).await
+    }
+
+    async fn listen(script: Option<Arc<Script>>) -> Self {
+        let socket = TcpSocket::new_v4().unwrap();
+        if script
+            .as_ref()
+            .is_some_and(|script| script.read_delay_ms > 0)
+        {
+            // Accepted connections take the buffer size of the listener.
+            socket.set_recv_buffer_size(SLOW_READ_BUFFER).unwrap();
+        }
+        socket.bind(([127, 0, 0, 1], 0).into()).unwrap();
+        let listener = socket.listen(1024).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let run_tasks = Arc::new(Mutex::new(vec![]));
+        let received = run_tasks.clone();
+        tokio::spawn(async move {
+            let mut connections = 0;
+            while let Ok((stream, _)) = listener.accept().await {
+                let received = received.clone();
+                tokio::spawn(handle_connection(
+                    stream,
+                    script.clone(),
+                    connections,
+                    received,
+                ));
+                connections += 1;
+            }
+        });
+        MockServer { addr, run_tasks }
+    }
+
+    pub fn url(&self) -> String {
+        format!("ws://{}", self.addr)
+    }
+
+    /// The `run-task` requests received so far, on every connection, in order.
+    pub fn run_tasks(&self) -> Vec<Value> {
+        self.run_tasks.lock().unwrap().clone()
+    }
+}
+
+fn event(event: &str, task_id: &str, output: Value) -> Message {
+    let message = json!({
+        "header": {
+            "task_id": task_id,
+            "event": event,
+            "attributes": {},
+        },
+        "payload": {
+            "output": output,
+        },
+    });
+    Message::Text(message.to_string().into())
+}
+
+fn task_finished(task_id: &str, duration: u64) -> Message {
+    let message = json!({
+        "header": {
+            "task_id": task_id,
+            "event": "task-finished",
+            "attributes": {},
+        },
+        "payload": {
+            "output": {},
+            "usage": { "duration": duration },
+        },
+    });
+    Message::Text(message.to_string().into())
+}
+
+fn task_failed(task_id: &str, code: &str, message: &str) -> Message {
+    let message = json!({
+        "header": {
+            "task_id": task_id,
+            "event": "task-failed",
+            "error_code": code,
+            "error_message": message,
+            "attributes": {},
+        },
+        "payload": {},
+    });
+    Message::Text(message.to_string().into())
+}
+
+fn echo(task_id: &str, sentence_id: u64, data: &[u8]) -> Message {
+    event(
+        "result-generated",
+        task_id,
+        json!({
+            "transcription": {
+                "sentence_id": sentence_id,
+                "begin_time": sentence_id * 1000,
+                "end_time": sentence_id * 1000 + 1000,
+                "text": String::from_utf8_lossy(data),
+                "sentence_end": true,
+            },
+        }),
+    )
+}
+
+/// Serves connection number `connection`, counting from 0, keeping its `run-task` requests
+/// in `run_tasks`.
+async fn handle_connection(
+    stream: TcpStream,
+    script: Option<Arc<Script>>,
+    connection: usize,
+    run_tasks: Arc<Mutex<Vec<Value>>>,
+) {
+    let api_key = script.as_ref().and_then(|script| script.api_key.clone());
+    // Refusals are responses, as tungstenite has the callback return them.
+    #[allow(clippy::result_large_err)]
+    let authorize = |request: &Request, response: Response| {
+        let given = request
+            .headers()
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok());
+        match &api_key {
+            Some(key) if given != Some(&format!("Bearer {}", key)) => {
+                let mut refusal = ErrorResponse::new(Some("Invalid API key".to_string()));
+                *refusal.status_mut() = StatusCode::UNAUTHORIZED;
+                Err(refusal)
+            }
+            _ => Ok(response),
+        }
+    };
+    let Ok(mut ws) = accept_hdr_async(stream, authorize).await else {
+        return;
+    };
+    let mut task_id = String::new();
+    let mut sent = 0;
+    // Audio received by the task, and its rate.
+    let mut received = 0u64;
+    let mut sample_rate = 0;
+    let delay = Duration::from_millis(script.as_ref().map_or(0, |script| script.delay_ms));
+    let read_delay =
+        Duration::from_millis(script.as_ref().map_or(0, |script| script.read_delay_ms));
+    let mut fault = script
+        .as_ref()
+        .filter(|script| {
+            script
+                .faulty_connections
+                .is_none_or(|connections| connection < connections)
+        })
+        .and_then(|script| script.fault.clone());
+    let mut audio = false;
+    loop {
+        // Paused after every audio frame, answered or not.
+        if audio && !read_delay.is_zero() {
+            sleep(read_delay).await;
+        }
+        let Some(Ok(message)) = ws.next().await else {
+            break;
+        };
+        audio = message.is_binary();
+        let replies = match message {
+            Message::Text(text) => {
+                let request: Value = serde_json::from_str(&text).unwrap();
+                task_id = request["header"]["task_id"].as_str().unwrap().to_string();
+                match request["header"]["action"].as_str().unwrap() {
+                    "run-task" => {
+                        run_tasks.lock().unwrap().push(request.clone());
+                        sample_rate = request["payload"]["parameters"]["sample_rate"]
+                            .as_u64()
+                            .unwrap_or(0);
+                        received = 0;
+                        if sample_rate < 8000 {
+                            vec![task_failed(
+                                &task_id,
+                                "InvalidParameter",
+                                "invalid sample_rate",
+                            )]
+                        } else {
+                            vec![event("task-started", &task_id, json!({}))]
+                        }
+                    }
+                    "finish-task" => vec![task_finished(
+                        &task_id,
+                        received.div_ceil(sample_rate.max(1) * 2),
+                    )],
+                    _ => continue,
+                }
+            }
+            Message::Binary(data) => {
+                received += data.len() as u64;
+                match &script {
+                    Some(script) => {
+                        // Results due by the audio received, or else the next one.
+                        let due = match script.audio_ms_per_result {
+                            Some(per_result) => {
+                                let received_ms = received * 1000 / (sample_rate.max(1) * 2);
+                                (received_ms / per_result.max(1)) as usize
+                            }
+                            None => sent + 1,
+                        };
+                        (script.results.iter().take(due).skip(sent))
+                            .map(|output| event("result-generated", &task_id, output.clone()))
+                            .collect()
+                    }
+                    None => vec![echo(&task_id, sent as u64, &data)],
+                }
+            }
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        // Audio frames are only answered with results.
+        for reply in replies {
+            if audio {
+                sent += 1;
+            }
+            // A sleep of nothing still waits for the timer to tick, a millisecond per reply.
+            if !delay.is_zero() {
+                sleep(delay).await;
+            }
+            if ws.send(reply).await.is_err() {
+                return;
+            }
+            match fault.take_if(|fault| fault.after() == sent) {
+                Some(Fault::TaskFailed { code, message, .. }) => {
+                    let _ = ws.send(task_failed(&task_id, &code, &message)).await;
+                }
+                Some(Fault::Close { .. }) => {
+                    let _ = ws.close(None).await;
+                    return;
+                }
+                Some(Fault::Disconnect { .. }) => return,
+                None => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! Encoding a second of 16 kHz audio in 100 ms chunks: collected into a new buffer for each
+//! chunk, as before, against appended to one buffer reused from chunk to chunk.
+
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use std::hint::black_box;
+
+const CHUNK: usize = 1600;
+
+fn chunks() -> Vec<Vec<i16>> {
+    (0..10)
+        .map(|chunk| {
+            (0..CHUNK)
+                .map(|i| ((chunk * CHUNK + i) as f32 * 0.1).sin() as i16 * 8000)
+                .collect()
+        })
+        .collect()
+}
+
+fn encode(c: &mut Criterion) {
+    let chunks = chunks();
+    let mut group = c.benchmark_group("encode a second of audio");
+    group.bench_function("collected per chunk", |b| {
+        b.iter(|| {
+            for chunk in &chunks {
+                let bytes: Vec<u8> = chunk.iter().flat_map(|s| s.to_le_bytes()).collect();
+                black_box(bytes);
+            }
+        })
+    });
+    group.bench_function("appended to a reused buffer", |b| {
+        b.iter_batched_ref(
+            || Vec::with_capacity(CHUNK * 2),
+            |bytes| {
+                for chunk in &chunks {
+                    bytes.clear();
+                    gummy::extend_le_bytes(bytes, chunk);
+                    black_box(&bytes);
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("borrowed", |b| {
+        b.iter(|| {
+            for chunk in &chunks {
+                black_box(gummy::le_bytes(chunk));
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, encode);
+criterion_main!(benches);
@@ -0,0 +1,51 @@
+//! Transcribes a 16-bit mono WAV file.
+//!
+//! ```sh
+//! API_KEY=... cargo run -p gummy --example transcribe_wav -- speech.wav
+//! ```
+
+use gummy::Gummy;
+use std::env;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+    let path = env::args()
+        .nth(1)
+        .ok_or("usage: transcribe_wav <file.wav>")?;
+    let api_key = env::var("API_KEY")?;
+
+    let mut reader = hound::WavReader::open(&path)?;
+    let spec = reader.spec();
+    if spec.channels != 1 || spec.bits_per_sample != 16 {
+        return Err("expected a 16-bit mono WAV file".into());
+    }
+
+    let mut gummy = Gummy::new(&api_key)
+        .connect(None)
+        .await?
+        .start(Some("pcm"), Some(spec.sample_rate), None, None)
+        .await?;
+
+    // Send 100 ms of audio per message.
+    let samples = reader.samples::<i16>().collect::<Result<Vec<_>, _>>()?;
+    for chunk in samples.chunks(spec.sample_rate as usize / 10) {
+        let data = chunk
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect::<Vec<u8>>();
+        gummy.send(&data).await?;
+    }
+
+    let gummy = gummy.finish().await?;
+    for transcription in gummy.get_result() {
+        println!(
+            "[{} - {}] {}",
+            transcription.begin_time, transcription.end_time, transcription.text
+        );
+        if let Some(translated_text) = transcription.translated_text {
+            println!("    {}", translated_text);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,665 @@
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{FutureExt, SinkExt, StreamExt};
+use log::{debug, warn};
+use std::result::Result::Ok;
+use std::time::SystemTime;
+use std::vec;
+use tokio::select;
+use tokio_tungstenite::{WebSocketStream, connect_async_tls_with_config};
+use tungstenite::Message;
+use tungstenite::client::IntoClientRequest;
+use tungstenite::protocol::WebSocketConfig;
+
+use crate::error::{GummyError, GummyResult};
+use crate::language::{self, LanguageError};
+use crate::outbound::Outbound;
+use crate::pcm::le_bytes;
+use crate::request;
+use crate::response::{parse_header, parse_result, parse_task_failed, parse_usage};
+use crate::transcription::{TaskUsage, Transcription, TranscriptionEvent, update_result};
+
+/// Model that tasks recognize and translate speech with.
+pub const MODEL: &str = "gummy-realtime-v1";
+
+type WSWriter =
+    SplitSink<WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>, Message>;
+
+type WSReader =
+    SplitStream<WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>>;
+
+/// The public DashScope inference endpoint, connected to unless `ConnectOptions::url` says
+/// otherwise.
+pub const DEFAULT_URL: &str = "wss://dashscope.aliyuncs.com/api-ws/v1/inference";
+
+/// Largest message taken from the server by default, in bytes. Results are a few
+/// kilobytes at most.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 << 20;
+
+/// Options used when opening the WebSocket connection.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectOptions {
+    /// WebSocket endpoint, defaults to `DEFAULT_URL`.
+    pub url: Option<String>,
+    /// Largest message, and frame, taken from the server in bytes, defaults to
+    /// `DEFAULT_MAX_MESSAGE_SIZE`. A larger one fails with `GummyError::MessageTooLarge`
+    /// rather than being read into memory.
+    pub max_message_size: Option<usize>,
+}
+
+/// Options used when starting a recognition task. Fields left as `None` fall back to the
+/// same defaults as `Gummy::start`. Languages go through `normalize_language`, and starting
+/// with one `MODEL` doesn't support fails with `GummyError::Language`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StartOptions {
+    /// Audio format of the data that will be sent, defaults to `pcm`.
+    pub format: Option<String>,
+    /// Sample rate of the audio in Hz, defaults to 48000.
+    pub sample_rate: Option<u32>,
+    /// Language spoken in the audio, defaults to `auto`.
+    pub source_language: Option<String>,
+    /// Languages to translate into, defaults to `zh` alone. Sentences carry the
+    /// translation into the first one.
+    pub target_languages: Vec<String>,
+    /// Final sentences kept in memory once they were delivered as events, the last ones,
+    /// for long tasks; all of them if `None`. Earlier sentences are then only in the
+    /// events, and the results of the task hold what's kept.
+    pub retained_finals: Option<usize>,
+}
+
+/// What a task was started with, to tell it apart in the service's records, as when
+/// reporting a problem with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionInfo {
+    /// ID of the task, as the service's support asks for it.
+    pub task_id: String,
+    /// Model recognizing the speech.
+    pub model: String,
+    /// Sample rate of the audio in Hz.
+    pub sample_rate: u32,
+    /// Language spoken in the audio, `auto` when it's detected.
+    pub source_language: String,
+    /// Languages the sentences are translated into.
+    pub target_languages: Vec<String>,
+    /// When the task started.
+    pub started_at: SystemTime,
+}
+
+impl StartOptions {
+    /// Options of the parameters `Gummy::start` takes.
+    fn of(
+        format: Option<&str>,
+        sample_rate: Option<u32>,
+        source_language: Option<&str>,
+        target_language: Option<&str>,
+    ) -> Self {
+        StartOptions {
+            format: format.map(str::to_string),
+            sample_rate,
+            source_language: source_language.map(str::to_string),
+            target_languages: target_language.map(str::to_string).into_iter().collect(),
+            retained_finals: None,
+        }
+    }
+
+    /// The options with their languages normalized, if `MODEL` supports them.
+    fn normalized(&self) -> Result<Self, LanguageError> {
+        let source_language = match &self.source_language {
+            Some(code) => Some(language::source_language(MODEL, code)?),
+            None => None,
+        };
+        let target_languages = self
+            .target_languages
+            .iter()
+            .map(|code| language::target_language(MODEL, code))
+            .collect::<Result<_, _>>()?;
+        Ok(StartOptions {
+            source_language,
+            target_languages,
+            ..self.clone()
+        })
+    }
+}
+
+/// State of a client that is not connected.
+pub struct Closed;
+
+/// State of a client with an open connection but no running task.
+pub struct Connected {
+    writer: WSWriter,
+    reader: WSReader,
+}
+
+/// State of a client with a running task that accepts audio.
+pub struct Converting {
+    writer: WSWriter,
+    reader: WSReader,
+    outbound: Outbound,
+    options: StartOptions,
+    info: SessionInfo,
+    result: Vec<Transcription>,
+    evicted: Evicted,
+    finished: bool,
+    usage: Option<TaskUsage>,
+}
+
+/// Sentences let go of under `StartOptions::retained_finals`.
+#[derive(Clone, Copy, Debug, Default)]
+struct Evicted {
+    sentences: usize,
+    // Id of the last sentence let go of; later updates of it and those before are late.
+    through: Option<u64>,
+}
+
+impl Evicted {
+    fn contains(&self, sentence_id: u64) -> bool {
+        self.through.is_some_and(|through| sentence_id <= through)
+    }
+}
+
+/// State of a client whose task has finished, holding the final results.
+pub struct Finished {
+    writer: WSWriter,
+    reader: WSReader,
+    options: StartOptions,
+    info: SessionInfo,
+    result: Vec<Transcription>,
+    evicted: Evicted,
+    usage: Option<TaskUsage>,
+}
+
+/// Gummy client, see the crate documentation for the lifecycle of its states.
+pub struct Gummy<State = Closed> {
+    api_key: String,
+    state: State,
+}
+
+impl Gummy {
+    /// Creates a client that authenticates with `api_key`.
+    pub fn new(api_key: &str) -> Self {
+        Gummy {
+            api_key: api_key.to_string(),
+            state: Closed,
+        }
+    }
+    /// Drops the connection, if any, keeping the API key.
+    pub fn close(self) -> Gummy<Closed> {
+        Gummy {
+            api_key: self.api_key,
+            state: Closed,
+        }
+    }
+}
+
+impl Gummy<Closed> {
+    /// Opens the WebSocket connection to `url`, or the public endpoint when `None`.
+    pub async fn connect(self, url: Option<&str>) -> GummyResult<Gummy<Connected>> {
+        let options = ConnectOptions {
+            url: url.map(str::to_string),
+            ..Default::default()
+        };
+        self.connect_with(&options).await
+    }
+
+    /// Like `connect`, taking the endpoint and limits from `options`.
+    pub async fn connect_with(self, options: &ConnectOptions) -> GummyResult<Gummy<Connected>> {
+        let url = options.url.as_deref().unwrap_or(DEFAULT_URL);
+        let mut request = url.into_client_request()?;
+        request
+            .headers_mut()
+            .insert("Authorization", format!("Bearer {}", self.api_key).parse()?);
+        request.headers_mut().insert("user-agent", "app".parse()?);
+        request
+            .headers_mut()
+            .insert("X-DashScope-WorkSpace", "llm-hxfupix3oo63uw6d".parse()?);
+        request
+            .headers_mut()
+            .insert("X-DashScope-DataInspection", "enable".parse()?);
+        let max_size = options.max_message_size.unwrap_or(DEFAULT_MAX_MESSAGE_SIZE);
+        let config = WebSocketConfig::default()
+            .max_message_size(Some(max_size))
+            .max_frame_size(Some(max_size));
+        let (stream, _) = connect_async_tls_with_config(request, Some(config), false, None).await?;
+        let (writer, reader) = stream.split();
+        let state = Connected { writer, reader };
+        Ok(Gummy {
+            api_key: self.api_key,
+            state,
+        })
+    }
+}
+
+impl Gummy<Connected> {
+    /// Starts a recognition task and waits until the server reports it as started.
+    pub async fn start(
+        self,
+        format: Option<&str>,
+        sample_rate: Option<u32>,
+        source_language: Option<&str>,
+        target_language: Option<&str>,
+    ) -> GummyResult<Gummy<Converting>> {
+        let options = StartOptions::of(format, sample_rate, source_language, target_language);
+        self.start_with(&options).await
+    }
+
+    /// Like `start`, taking the parameters from `options`.
+    pub async fn start_with(mut self, options: &StartOptions) -> GummyResult<Gummy<Converting>> {
+        let options = options.normalized()?;
+        let start_message = request::StartMessage::new(
+            options.format.as_deref(),
+            options.sample_rate,
+            options.source_language.as_deref(),
+            options.target_languages.clone(),
+        );
+        let started = self.wait_started(&start_message).await;
+        started.map_err(|e| e.in_task(start_message.id()))?;
+        let state = Converting {
+            writer: self.state.writer,
+            reader: self.state.reader,
+            outbound: Outbound::new(start_message.sample_rate()),
+            options,
+            info: start_message.session_info(SystemTime::now()),
+            result: vec![],
+            evicted: Evicted::default(),
+            finished: false,
+            usage: None,
+        };
+        Ok(Gummy {
+            api_key: self.api_key,
+            state,
+        })
+    }
+
+    /// Sends `start_message` and waits until the server reports the task as started.
+    async fn wait_started(&mut self, start_message: &request::StartMessage) -> GummyResult<()> {
+        self.state
+            .writer
+            .send(Message::Text(
+                serde_json::to_string(&start_message).unwrap().into(),
+            ))
+            .await?;
+        loop {
+            let message = self
+                .state
+                .reader
+                .next()
+                .await
+                .ok_or(GummyError::ConnectionClosed)??;
+            match message {
+                Message::Text(text) => {
+                    debug!(
+                        "[{}] Received message: {}",
+                        chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                        text
+                    );
+                    let response: serde_json::Value = serde_json::from_str(&text)?;
+                    let (event, task_id) = parse_header(&response)?;
+                    if task_id != start_message.id() {
+                        continue;
+                    }
+                    match event {
+                        "task-started" => {
+                            debug!("Task started with ID: {}", start_message.id());
+                            break;
+                        }
+                        "task-failed" => return Err(parse_task_failed(&response)),
+                        _ => {}
+                    }
+                }
+                Message::Close(frame) => {
+                    debug!("Connection closed while starting task: {:?}", frame);
+                    return Err(GummyError::ConnectionClosed);
+                }
+                Message::Binary(data) => dropped_binary(&data),
+                _ => {
+                    debug!("Received non-text message, ignoring.");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Gummy<Converting> {
+    /// Sends a chunk of audio in the format the task was started with, after what was
+    /// queued, waiting until the connection took it all.
+    pub async fn send(&mut self, data: &[u8]) -> GummyResult<()> {
+        self.queue(data.to_vec())?;
+        self.write_queued().await
+    }
+
+    /// Sends `samples` to a task started with the "pcm" format, without packing them into
+    /// bytes first.
+    pub async fn send_samples(&mut self, samples: &[i16]) -> GummyResult<()> {
+        self.send(&le_bytes(samples)).await
+    }
+
+    /// Sends a chunk of audio as far as the connection takes it without waiting, queuing
+    /// the rest. What's queued is written while `next_event` waits for events, so a
+    /// connection slow to take audio doesn't hold up the results. Past 30 s of audio
+    /// queued, the oldest is dropped.
+    pub fn queue(&mut self, data: Vec<u8>) -> GummyResult<()> {
+        let dropped = self.state.outbound.push(data);
+        if dropped > 0 {
+            warn!(
+                "Dropped {} bytes of audio the connection fell behind on",
+                dropped
+            );
+        }
+        if let Some(written) = self.write_queued().now_or_never() {
+            written?;
+        }
+        Ok(())
+    }
+
+    /// Sends `samples` like `queue`, for a task started with the "pcm" format.
+    pub fn queue_samples(&mut self, samples: &[i16]) -> GummyResult<()> {
+        self.queue(le_bytes(samples).into_owned())
+    }
+
+    /// Bytes of audio queued that the connection didn't take yet.
+    pub fn queued_bytes(&self) -> usize {
+        self.state.outbound.bytes()
+    }
+
+    /// ID of the task, as the service's support asks for it.
+    pub fn task_id(&self) -> &str {
+        &self.state.info.task_id
+    }
+
+    /// What the task was started with.
+    pub fn session_info(&self) -> &SessionInfo {
+        &self.state.info
+    }
+
+    /// Options the task was started with, as given.
+    pub fn options(&self) -> &StartOptions {
+        &self.state.options
+    }
+
+    /// Sentences recognized so far and kept in memory, sorted by id.
+    pub fn retained(&self) -> &[Transcription] {
+        &self.state.result
+    }
+
+    /// Sentences let go of under `StartOptions::retained_finals`.
+    pub fn evicted_sentences(&self) -> usize {
+        self.state.evicted.sentences
+    }
+
+    /// Waits until the connection took all the audio queued.
+    async fn write_queued(&mut self) -> GummyResult<()> {
+        let written = self.state.outbound.write(&mut self.state.writer).await;
+        self.in_task(written.map_err(GummyError::from))
+    }
+
+    /// `result`, with its error told apart as one of this task.
+    fn in_task<T>(&self, result: GummyResult<T>) -> GummyResult<T> {
+        result.map_err(|e| e.in_task(&self.state.info.task_id))
+    }
+
+    /// Waits for the next server event belonging to this task, writing the audio queued
+    /// meanwhile. Returns `None` once the task has finished or the server closed the
+    /// connection.
+    pub async fn next_event(&mut self) -> GummyResult<Option<TranscriptionEvent>> {
+        let event = self.read_event().await;
+        self.in_task(event)
+    }
+
+    async fn read_event(&mut self) -> GummyResult<Option<TranscriptionEvent>> {
+        if self.state.finished {
+            return Ok(None);
+        }
+        loop {
+            // Results are read first, the audio queued written while none are coming.
+            let message = select! {
+                biased;
+                message = self.state.reader.next() => message,
+                written = self.state.outbound.write(&mut self.state.writer),
+                    if !self.state.outbound.is_idle() =>
+                {
+                    written?;
+                    continue;
+                }
+            };
+            let Some(message) = message else {
+                break;
+            };
+            match message {
+                Ok(Message::Text(text)) => {
+                    if let Some(event) = self.handle_text(&text)? {
+                        return Ok(Some(event));
+                    }
+                }
+                Ok(Message::Close(frame)) => {
+                    debug!("Connection closed: {:?}", frame);
+                    return Ok(None);
+                }
+                Ok(Message::Binary(data)) => dropped_binary(&data),
+                Err(e) => return Err(e.into()),
+                _ => {
+                    debug!("Received non-text message, ignoring.");
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Waits for the next event and returns all sentences recognized so far, or those kept
+    /// under `StartOptions::retained_finals`.
+    pub async fn receive(&mut self) -> GummyResult<Vec<Transcription>> {
+        self.next_event().await?;
+        Ok(self.state.result.clone())
+    }
+
+    /// Asks the server to finish the task and waits for the remaining results.
+    pub async fn finish(mut self) -> GummyResult<Gummy<Finished>> {
+        if !self.state.finished {
+            self.send_finish_task().await?;
+            while let Some(event) = self.next_event().await? {
+                if let TranscriptionEvent::Finished = event {
+                    break;
+                }
+            }
+        }
+
+        let state = Finished {
+            options: self.state.options,
+            info: self.state.info,
+            result: self.state.result,
+            evicted: self.state.evicted,
+            usage: self.state.usage,
+            writer: self.state.writer,
+            reader: self.state.reader,
+        };
+
+        Ok(Gummy {
+            api_key: self.api_key,
+            state,
+        })
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        self.state.finished
+    }
+
+    pub(crate) async fn send_finish_task(&mut self) -> GummyResult<()> {
+        self.write_queued().await?;
+        let message = request::FinishMessage::new(&self.state.info.task_id);
+        let sent = self
+            .state
+            .writer
+            .send(Message::Text(
+                serde_json::to_string(&message).unwrap().into(),
+            ))
+            .await;
+        self.in_task(sent.map_err(GummyError::from))
+    }
+
+    fn handle_text(&mut self, text: &str) -> GummyResult<Option<TranscriptionEvent>> {
+        let response: serde_json::Value = serde_json::from_str(text)?;
+        let (event, task_id) = parse_header(&response)?;
+        if task_id != self.state.info.task_id {
+            return Ok(None);
+        }
+        if event == "task-failed" {
+            self.state.finished = true;
+            return Err(parse_task_failed(&response));
+        }
+        if event == "result-generated" {
+            let transcription = parse_result(&response)?;
+            let sentence_id = transcription.sentence_id;
+            // Late updates of sentences let go of are passed on, but not kept.
+            if !self.state.evicted.contains(sentence_id) {
+                update_result(&mut self.state.result, transcription.clone());
+                self.evict();
+            }
+            if transcription.is_final {
+                debug!("Sentence {} ended.", sentence_id);
+                return Ok(Some(TranscriptionEvent::Final(transcription)));
+            }
+            return Ok(Some(TranscriptionEvent::Partial(transcription)));
+        }
+        if event == "task-finished" {
+            debug!("Task finished with ID: {}", task_id);
+            self.state.finished = true;
+            self.state.usage = parse_usage(&response);
+            return Ok(Some(TranscriptionEvent::Finished));
+        }
+        Ok(None)
+    }
+
+    /// Lets go of the earliest sentences past the final ones kept, along with the partial
+    /// ones before them, which won't be finalized anymore.
+    fn evict(&mut self) {
+        let Some(kept) = self.state.options.retained_finals else {
+            return;
+        };
+        let result = &mut self.state.result;
+        let finals = result.iter().filter(|t| t.is_final).count();
+        if finals <= kept {
+            return;
+        }
+        let last = result
+            .iter()
+            .filter(|t| t.is_final)
+            .nth(finals - kept - 1)
+            .expect("enough final sentences are kept")
+            .sentence_id;
+        let evicted = result.partition_point(|t| t.sentence_id <= last);
+        result.drain(..evicted);
+        self.state.evicted.sentences += evicted;
+        self.state.evicted.through = Some(last);
+    }
+}
+
+impl Gummy<Finished> {
+    /// Starts a new task on the same connection, like `Gummy<Connected>::start`. Parameters
+    /// left as `None` fall back to the defaults, not to those of the task before; `restart`
+    /// reuses those.
+    pub async fn start(
+        self,
+        format: Option<&str>,
+        sample_rate: Option<u32>,
+        source_language: Option<&str>,
+        target_language: Option<&str>,
+    ) -> GummyResult<Gummy<Converting>> {
+        let options = StartOptions::of(format, sample_rate, source_language, target_language);
+        self.restart_with(&options).await
+    }
+
+    /// Starts a new task on the same connection with the options of the task before, and
+    /// waits until the server reports it as started.
+    pub async fn restart(self) -> GummyResult<Gummy<Converting>> {
+        let options = self.state.options.clone();
+        self.restart_with(&options).await
+    }
+
+    /// Like `restart`, with `options` instead of those of the task before.
+    pub async fn restart_with(self, options: &StartOptions) -> GummyResult<Gummy<Converting>> {
+        let connected = Gummy {
+            api_key: self.api_key,
+            state: Connected {
+                writer: self.state.writer,
+                reader: self.state.reader,
+            },
+        };
+        connected.start_with(options).await
+    }
+
+    /// ID of the task, as the service's support asks for it.
+    pub fn task_id(&self) -> &str {
+        &self.state.info.task_id
+    }
+
+    /// What the task was started with.
+    pub fn session_info(&self) -> &SessionInfo {
+        &self.state.info
+    }
+
+    /// Options the task was started with, as given.
+    pub fn options(&self) -> &StartOptions {
+        &self.state.options
+    }
+
+    /// All sentences recognized by the finished task, or those kept under
+    /// `StartOptions::retained_finals`, the others being only in the events.
+    pub fn get_result(&self) -> Vec<Transcription> {
+        self.state.result.clone()
+    }
+
+    /// What the finished task is billed for, if the server reported it.
+    pub fn usage(&self) -> Option<TaskUsage> {
+        self.state.usage
+    }
+
+    /// Sentences let go of under `StartOptions::retained_finals`.
+    pub fn evicted_sentences(&self) -> usize {
+        self.state.evicted.sentences
+    }
+
+    /// Like `get_result`, but drops sentences whose confidence is below `min_confidence`.
+    pub fn get_filtered_result(&self, min_confidence: f64) -> Vec<Transcription> {
+        self.state
+            .result
+            .iter()
+            .filter(|transcription| transcription.meets_confidence(min_confidence))
+            .cloned()
+            .collect()
+    }
+}
+
+/// The service only sends text; binary messages are dropped.
+fn dropped_binary(data: &[u8]) {
+    warn!(
+        "Dropped a binary message of {} bytes from the server",
+        data.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gummy_mock::MockServer;
+
+    #[tokio::test]
+    async fn start_fails_on_task_failed() {
+        let server = MockServer::start().await;
+        let result = Gummy::new("test-key")
+            .connect(Some(&server.url()))
+            .await
+            .unwrap()
+            .start(None, Some(1), None, None)
+            .await;
+        let Err(GummyError::Task { task_id, error }) = result else {
+            panic!("start didn't fail in the task with an invalid sample rate");
+        };
+        assert!(!task_id.is_empty());
+        match *error {
+            GummyError::TaskFailed { code, message } => {
+                assert_eq!(code, "InvalidParameter");
+                assert_eq!(message, "invalid sample_rate");
+            }
+            e => panic!("unexpected error {}", e),
+        }
+    }
+}
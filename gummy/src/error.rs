@@ -0,0 +1,127 @@
+use crate::language::LanguageError;
+use thiserror::Error;
+
+/// Errors returned by the Gummy client.
+#[derive(Error, Debug)]
+pub enum GummyError {
+    /// The WebSocket connection failed or could not be established.
+    #[error("WebSocket error: {0}")]
+    WebSocket(Box<tungstenite::Error>),
+    /// A request header (usually the API key) contained invalid characters.
+    #[error("Invalid header value: {0}")]
+    InvalidHeader(#[from] tungstenite::http::header::InvalidHeaderValue),
+    /// A message from the server was not valid JSON.
+    #[error("Failed to parse response: {0}")]
+    Json(#[from] serde_json::Error),
+    /// A message from the server was valid JSON but missing required fields.
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
+    /// The server rejected or aborted the task.
+    #[error("Task failed ({code}): {message}")]
+    TaskFailed {
+        /// Error code reported by the server.
+        code: String,
+        /// Human readable error message reported by the server.
+        message: String,
+    },
+    /// A message from the server was larger than `ConnectOptions::max_message_size`.
+    #[error("Message of {size} bytes from the server exceeds the limit of {max_size} bytes")]
+    MessageTooLarge {
+        /// Size of the message, as far as it was read.
+        size: usize,
+        /// The limit it went over.
+        max_size: usize,
+    },
+    /// The server closed the connection.
+    #[error("Connection closed by server")]
+    ConnectionClosed,
+    /// The session task has already stopped.
+    #[error("Session is no longer running")]
+    SessionClosed,
+    /// The session task panicked or was aborted.
+    #[error("Session task failed: {0}")]
+    Join(#[from] tokio::task::JoinError),
+    /// `error` happened to a task, which the service knows by `task_id`.
+    #[error("{error} (task {task_id})")]
+    Task {
+        /// ID of the task, as the service's support asks for it.
+        task_id: String,
+        /// What went wrong.
+        error: Box<GummyError>,
+    },
+    /// A task was to be started with a language its model doesn't support.
+    #[error(transparent)]
+    Language(#[from] LanguageError),
+}
+
+impl GummyError {
+    /// Whether a new connection may get past the error: dropped connections and failing
+    /// servers may recover, while rejected handshakes, failed tasks and malformed responses
+    /// would happen again.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            GummyError::Task { error, .. } => error.is_retryable(),
+            GummyError::WebSocket(error) => match error.as_ref() {
+                tungstenite::Error::Http(response) => response.status().is_server_error(),
+                tungstenite::Error::Url(_) | tungstenite::Error::HttpFormat(_) => false,
+                _ => true,
+            },
+            GummyError::ConnectionClosed | GummyError::SessionClosed => true,
+            GummyError::InvalidHeader(_)
+            | GummyError::Json(_)
+            | GummyError::InvalidResponse(_)
+            | GummyError::MessageTooLarge { .. }
+            | GummyError::TaskFailed { .. }
+            | GummyError::Language(_)
+            | GummyError::Join(_) => false,
+        }
+    }
+
+    /// Whether the server refused the API key.
+    pub fn is_auth(&self) -> bool {
+        match self {
+            GummyError::Task { error, .. } => error.is_auth(),
+            GummyError::WebSocket(error) => matches!(
+                error.as_ref(),
+                tungstenite::Error::Http(response)
+                    if matches!(response.status().as_u16(), 401 | 403)
+            ),
+            GummyError::TaskFailed { code, .. } => code == "InvalidApiKey",
+            _ => false,
+        }
+    }
+
+    /// ID of the task the error happened to, if it happened to one.
+    pub fn task_id(&self) -> Option<&str> {
+        match self {
+            GummyError::Task { task_id, .. } => Some(task_id),
+            _ => None,
+        }
+    }
+
+    /// The error, told apart from those of other tasks by `task_id`.
+    pub(crate) fn in_task(self, task_id: &str) -> Self {
+        match self {
+            GummyError::Task { .. } => self,
+            error => GummyError::Task {
+                task_id: task_id.to_string(),
+                error: Box::new(error),
+            },
+        }
+    }
+}
+
+impl From<tungstenite::Error> for GummyError {
+    fn from(error: tungstenite::Error) -> Self {
+        match error {
+            tungstenite::Error::Capacity(tungstenite::error::CapacityError::MessageTooLong {
+                size,
+                max_size,
+            }) => GummyError::MessageTooLarge { size, max_size },
+            error => GummyError::WebSocket(Box::new(error)),
+        }
+    }
+}
+
+/// Result type used throughout the Gummy client.
+pub type GummyResult<T> = std::result::Result<T, GummyError>;
@@ -0,0 +1,158 @@
+//! Checking that the audio sent to a task is what the task was started for, as audio at
+//! another rate or layout than the task expects is transcribed as garbage rather than
+//! refused.
+
+use crate::client::StartOptions;
+use std::fmt;
+use thiserror::Error;
+
+/// Encoding tasks are started for without one, as `StartOptions` documents.
+pub(crate) const DEFAULT_FORMAT: &str = "pcm";
+/// Rate tasks are started at without one, as `StartOptions` documents.
+pub(crate) const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+/// How the samples of audio are stored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SampleFormat {
+    /// 16-bit signed integers, the samples of PCM.
+    I16,
+    /// Samples of another kind, by name, like `f32`.
+    Other(String),
+}
+
+impl fmt::Display for SampleFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SampleFormat::I16 => write!(f, "i16"),
+            SampleFormat::Other(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Layout of the audio sent to a task.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AudioFormat {
+    /// Sample rate of the audio in Hz.
+    pub sample_rate: u32,
+    /// Channels of the audio, interleaved.
+    pub channels: u16,
+    /// How the samples are stored.
+    pub sample_format: SampleFormat,
+}
+
+/// How audio differs from what a task was started for.
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The task takes another encoding than the raw PCM sent.
+    #[error("The task is started for {0} audio, but PCM is sent")]
+    Encoding(String),
+    /// The audio has more than one channel.
+    #[error("The audio has {0} channels, but the task takes mono")]
+    Channels(u16),
+    /// The samples aren't 16-bit integers.
+    #[error("The audio has {0} samples, but the task takes 16-bit integers")]
+    SampleFormat(SampleFormat),
+    /// The audio is at another rate than the task is started at.
+    #[error("The audio is at {audio} Hz, but the task is started at {task} Hz")]
+    SampleRate {
+        /// Rate of the audio in Hz.
+        audio: u32,
+        /// Rate the task is started at in Hz.
+        task: u32,
+    },
+}
+
+/// Whether audio in `format` can be sent to a task started with `options`: 16-bit mono PCM
+/// at the rate the task is started at.
+pub fn check_format(format: &AudioFormat, options: &StartOptions) -> Result<(), Mismatch> {
+    if let Some(encoding) = (options.format.as_deref()).filter(|&f| f != DEFAULT_FORMAT) {
+        return Err(Mismatch::Encoding(encoding.to_string()));
+    }
+    if format.channels != 1 {
+        return Err(Mismatch::Channels(format.channels));
+    }
+    if format.sample_format != SampleFormat::I16 {
+        return Err(Mismatch::SampleFormat(format.sample_format.clone()));
+    }
+    let task = options.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+    if format.sample_rate != task {
+        return Err(Mismatch::SampleRate {
+            audio: format.sample_rate,
+            task,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(sample_rate: u32) -> AudioFormat {
+        AudioFormat {
+            sample_rate,
+            channels: 1,
+            sample_format: SampleFormat::I16,
+        }
+    }
+
+    fn options(sample_rate: Option<u32>) -> StartOptions {
+        StartOptions {
+            format: Some("pcm".to_string()),
+            sample_rate,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn accepts_16_bit_mono_pcm_at_the_rate_of_the_task() {
+        assert_eq!(check_format(&format(16000), &options(Some(16000))), Ok(()));
+        assert_eq!(check_format(&format(24000), &options(Some(24000))), Ok(()));
+        // Tasks started without a rate or format take 48 kHz PCM.
+        assert_eq!(
+            check_format(&format(48000), &StartOptions::default()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn tells_each_mismatch() {
+        assert_eq!(
+            check_format(&format(16000), &options(Some(48000))),
+            Err(Mismatch::SampleRate {
+                audio: 16000,
+                task: 48000
+            })
+        );
+        assert_eq!(
+            check_format(&format(16000), &StartOptions::default()),
+            Err(Mismatch::SampleRate {
+                audio: 16000,
+                task: 48000
+            })
+        );
+        let mut opus = options(Some(16000));
+        opus.format = Some("opus".to_string());
+        assert_eq!(
+            check_format(&format(16000), &opus),
+            Err(Mismatch::Encoding("opus".to_string()))
+        );
+        let stereo = AudioFormat {
+            channels: 2,
+            ..format(16000)
+        };
+        assert_eq!(
+            check_format(&stereo, &options(Some(16000))),
+            Err(Mismatch::Channels(2))
+        );
+        let float = AudioFormat {
+            sample_format: SampleFormat::Other("f32".to_string()),
+            ..format(16000)
+        };
+        let mismatch = check_format(&float, &options(Some(16000))).unwrap_err();
+        assert_eq!(
+            mismatch.to_string(),
+            "The audio has f32 samples, but the task takes 16-bit integers"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Language codes of tasks. Common aliases are normalized, and codes a model doesn't
+//! support are refused before a task is started with them, rather than failing it with a
+//! message that doesn't tell which.
+
+use crate::client::MODEL;
+use std::fmt;
+use thiserror::Error;
+
+/// Languages a model recognizes and translates into.
+struct Languages {
+    model: &'static str,
+    source: &'static [&'static str],
+    target: &'static [&'static str],
+}
+
+const MODELS: &[Languages] = &[Languages {
+    model: MODEL,
+    source: &["zh", "en", "ja", "ko", "yue", "de", "fr", "ru", "it", "es"],
+    target: &["zh", "en", "ja", "ko", "de", "fr", "ru", "it", "es"],
+}];
+
+/// Names and tags given for a language, lowercase, with its code. Other tags with a region,
+/// like `de-AT`, go by the language before it.
+const ALIASES: &[(&str, &str)] = &[
+    ("chinese", "zh"),
+    ("mandarin", "zh"),
+    ("cmn", "zh"),
+    ("zh-hans", "zh"),
+    ("cantonese", "yue"),
+    ("zh-hk", "yue"),
+    ("english", "en"),
+    ("japanese", "ja"),
+    ("jp", "ja"),
+    ("korean", "ko"),
+    ("kr", "ko"),
+    ("german", "de"),
+    ("french", "fr"),
+    ("russian", "ru"),
+    ("italian", "it"),
+    ("spanish", "es"),
+];
+
+/// Whether a language is the one spoken or one translated into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LanguageRole {
+    /// The language spoken in the audio.
+    Source,
+    /// A language the sentences are translated into.
+    Target,
+}
+
+impl fmt::Display for LanguageRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LanguageRole::Source => write!(f, "source"),
+            LanguageRole::Target => write!(f, "target"),
+        }
+    }
+}
+
+/// A language code a model doesn't support.
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error(
+    "{model} doesn't support \"{code}\" as a {role} language, only {}",
+    supported.join(", ")
+)]
+pub struct LanguageError {
+    /// The code as given.
+    pub code: String,
+    /// Whether it was given as the language spoken or one translated into.
+    pub role: LanguageRole,
+    /// The model it was checked against.
+    pub model: String,
+    /// Codes the model supports in that role.
+    pub supported: Vec<&'static str>,
+}
+
+/// `code` lowercase, with `_` separating its subtags as `-`, and common aliases replaced
+/// by the code they stand for, like `zh-CN` by `zh` and `jp` by `ja`.
+pub fn normalize_language(code: &str) -> String {
+    let code = code.trim().to_lowercase().replace('_', "-");
+    if let Some((_, normalized)) = ALIASES.iter().find(|(alias, _)| *alias == code) {
+        return normalized.to_string();
+    }
+    match code.split_once('-') {
+        Some((language, _)) => language.to_string(),
+        None => code,
+    }
+}
+
+/// `code` normalized, as the language spoken to a task of `model`. `auto`, for the language
+/// to be detected, is taken too.
+pub fn source_language(model: &str, code: &str) -> Result<String, LanguageError> {
+    let normalized = normalize_language(code);
+    if normalized == "auto" {
+        return Ok(normalized);
+    }
+    check(model, code, normalized, LanguageRole::Source)
+}
+
+/// `code` normalized, as a language a task of `model` translates into.
+pub fn target_language(model: &str, code: &str) -> Result<String, LanguageError> {
+    check(model, code, normalize_language(code), LanguageRole::Target)
+}
+
+/// `normalized`, if `model` supports it as `role`. Models without a table take any code.
+fn check(
+    model: &str,
+    code: &str,
+    normalized: String,
+    role: LanguageRole,
+) -> Result<String, LanguageError> {
+    let Some(languages) = MODELS.iter().find(|languages| languages.model == model) else {
+        return Ok(normalized);
+    };
+    let supported = match role {
+        LanguageRole::Source => languages.source,
+        LanguageRole::Target => languages.target,
+    };
+    if supported.contains(&normalized.as_str()) {
+        return Ok(normalized);
+    }
+    Err(LanguageError {
+        code: code.to_string(),
+        role,
+        model: model.to_string(),
+        supported: supported.to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_aliases_and_case() {
+        assert_eq!(normalize_language("zh-CN"), "zh");
+        assert_eq!(normalize_language("zh_cn"), "zh");
+        assert_eq!(normalize_language("en-US"), "en");
+        assert_eq!(normalize_language("EN"), "en");
+        assert_eq!(normalize_language("jp"), "ja");
+        assert_eq!(normalize_language("Chinese"), "zh");
+        assert_eq!(normalize_language("zh-HK"), "yue");
+        assert_eq!(normalize_language(" de-AT "), "de");
+        // Codes it doesn't know are kept, lowercase.
+        assert_eq!(normalize_language("Klingon"), "klingon");
+    }
+
+    #[test]
+    fn takes_the_languages_of_the_model() {
+        assert_eq!(source_language(MODEL, "AUTO").unwrap(), "auto");
+        assert_eq!(source_language(MODEL, "en-GB").unwrap(), "en");
+        assert_eq!(source_language(MODEL, "cantonese").unwrap(), "yue");
+        assert_eq!(target_language(MODEL, "ja-JP").unwrap(), "ja");
+        // Models without a table take any code, normalized.
+        assert_eq!(target_language("other", "pt-BR").unwrap(), "pt");
+    }
+
+    #[test]
+    fn refuses_languages_the_model_does_not_support() {
+        let error = target_language(MODEL, "pt-BR").unwrap_err();
+        assert_eq!(error.code, "pt-BR");
+        assert_eq!(error.role, LanguageRole::Target);
+        assert_eq!(
+            error.to_string(),
+            "gummy-realtime-v1 doesn't support \"pt-BR\" as a target language, \
+             only zh, en, ja, ko, de, fr, ru, it, es"
+        );
+        // Cantonese is recognized, but not translated into.
+        assert!(target_language(MODEL, "yue").is_err());
+        // The language has to be known to translate into it.
+        assert!(target_language(MODEL, "auto").is_err());
+        assert!(source_language(MODEL, "klingon").is_err());
+    }
+}
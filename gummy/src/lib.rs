@@ -0,0 +1,37 @@
+//! Client for the DashScope Gummy real-time speech recognition and translation service.
+//!
+//! The connection is modelled as a typestate: [`Gummy::new`] gives a closed client,
+//! [`Gummy::connect`] opens the WebSocket, `start` begins a recognition task that audio can
+//! be sent to, and `finish` ends the task and collects the final [`Transcription`]s.
+//! [`GummySessionManager`] runs several such sessions concurrently in background tasks.
+//!
+//! Tasks keep every sentence they recognize for their result, unless started with
+//! [`StartOptions::retained_finals`]: long tasks then keep the last few final sentences
+//! alone, and the whole transcript is only in the events they delivered.
+#![deny(missing_docs)]
+
+mod client;
+mod error;
+mod format;
+mod language;
+mod manager;
+mod outbound;
+mod pcm;
+mod request;
+mod response;
+mod transcription;
+
+pub use client::{
+    Closed, ConnectOptions, Connected, Converting, DEFAULT_MAX_MESSAGE_SIZE, DEFAULT_URL, Finished,
+    Gummy, MODEL, SessionInfo, StartOptions,
+};
+pub use error::{GummyError, GummyResult};
+pub use format::{AudioFormat, Mismatch, SampleFormat, check_format};
+pub use language::{
+    LanguageError, LanguageRole, normalize_language, source_language, target_language,
+};
+pub use manager::{GummySessionHandle, GummySessionManager};
+pub use pcm::{extend_le_bytes, le_bytes};
+pub use transcription::{
+    TaskUsage, Transcription, TranscriptionEvent, Translation, Word, merge_sessions, update_result,
+};
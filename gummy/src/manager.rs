@@ -0,0 +1,256 @@
+use crate::client::{ConnectOptions, Converting, Gummy, StartOptions};
+use crate::error::{GummyError, GummyResult};
+use crate::pcm::le_bytes;
+use crate::transcription::{Transcription, TranscriptionEvent};
+use log::{debug, error};
+use std::sync::Mutex;
+use tokio::select;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use tokio::task::{AbortHandle, JoinHandle};
+
+enum SessionCommand {
+    Audio(Vec<u8>),
+    Finish,
+}
+
+/// Spawns and owns independent Gummy sessions that share one API key and connect options.
+///
+/// Each session gets its own WebSocket connection and runs in its own task. Dropping the
+/// manager aborts every session that is still running.
+pub struct GummySessionManager {
+    api_key: String,
+    connect_options: ConnectOptions,
+    sessions: Mutex<Vec<AbortHandle>>,
+}
+
+/// Handle to a session spawned by [`GummySessionManager`].
+pub struct GummySessionHandle {
+    commands: UnboundedSender<SessionCommand>,
+    events: UnboundedReceiver<TranscriptionEvent>,
+    task: JoinHandle<GummyResult<Vec<Transcription>>>,
+}
+
+impl GummySessionManager {
+    /// Creates a manager whose sessions authenticate with `api_key`.
+    pub fn new(api_key: &str, connect_options: ConnectOptions) -> Self {
+        GummySessionManager {
+            api_key: api_key.to_string(),
+            connect_options,
+            sessions: Mutex::new(vec![]),
+        }
+    }
+
+    /// Connects, starts a task with `options` and waits for it to be running.
+    pub async fn spawn_session(&self, options: StartOptions) -> GummyResult<GummySessionHandle> {
+        self.spawn(options, None).await
+    }
+
+    /// Like `spawn_session`, with the sentences of the session labelled `label` as their
+    /// `source_label`, to tell them apart once merged with those of other sessions.
+    pub async fn spawn_labelled_session(
+        &self,
+        options: StartOptions,
+        label: &str,
+    ) -> GummyResult<GummySessionHandle> {
+        self.spawn(options, Some(label.to_string())).await
+    }
+
+    async fn spawn(
+        &self,
+        options: StartOptions,
+        label: Option<String>,
+    ) -> GummyResult<GummySessionHandle> {
+        let gummy = Gummy::new(&self.api_key)
+            .connect_with(&self.connect_options)
+            .await?
+            .start_with(&options)
+            .await?;
+        let (command_tx, command_rx) = unbounded_channel();
+        let (event_tx, event_rx) = unbounded_channel();
+        let task = tokio::spawn(run_session(gummy, label, command_rx, event_tx));
+
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|session| !session.is_finished());
+        sessions.push(task.abort_handle());
+
+        Ok(GummySessionHandle {
+            commands: command_tx,
+            events: event_rx,
+            task,
+        })
+    }
+
+    /// Aborts every running session. Their connections are dropped without finishing the task.
+    pub fn shutdown(&self) {
+        let mut sessions = self.sessions.lock().unwrap();
+        for session in sessions.drain(..) {
+            session.abort();
+        }
+    }
+}
+
+impl Drop for GummySessionManager {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl GummySessionHandle {
+    /// Queues audio data to be sent by the session task.
+    pub fn send(&self, data: &[u8]) -> GummyResult<()> {
+        self.commands
+            .send(SessionCommand::Audio(data.to_vec()))
+            .map_err(|_| GummyError::SessionClosed)
+    }
+
+    /// Queues samples to be sent by the session task, as `Gummy::send_samples` sends them.
+    pub fn send_samples(&self, samples: &[i16]) -> GummyResult<()> {
+        self.commands
+            .send(SessionCommand::Audio(le_bytes(samples).into_owned()))
+            .map_err(|_| GummyError::SessionClosed)
+    }
+
+    /// Events produced by this session, in the order the server sent them.
+    pub fn events(&mut self) -> &mut UnboundedReceiver<TranscriptionEvent> {
+        &mut self.events
+    }
+
+    /// Finishes the task and returns the final transcriptions.
+    pub async fn finish(self) -> GummyResult<Vec<Transcription>> {
+        // The task may already have stopped on its own, its result is reported below.
+        let _ = self.commands.send(SessionCommand::Finish);
+        self.task.await?
+    }
+}
+
+/// `event` with its sentence labelled `label`.
+fn labelled(event: TranscriptionEvent, label: &Option<String>) -> TranscriptionEvent {
+    match event {
+        TranscriptionEvent::Partial(mut t) => {
+            t.source_label.clone_from(label);
+            TranscriptionEvent::Partial(t)
+        }
+        TranscriptionEvent::Final(mut t) => {
+            t.source_label.clone_from(label);
+            TranscriptionEvent::Final(t)
+        }
+        TranscriptionEvent::Finished => TranscriptionEvent::Finished,
+    }
+}
+
+async fn run_session(
+    mut gummy: Gummy<Converting>,
+    label: Option<String>,
+    mut commands: UnboundedReceiver<SessionCommand>,
+    events: UnboundedSender<TranscriptionEvent>,
+) -> GummyResult<Vec<Transcription>> {
+    loop {
+        select! {
+            command = commands.recv() => {
+                match command {
+                    Some(SessionCommand::Audio(data)) => gummy.queue(data)?,
+                    Some(SessionCommand::Finish) | None => break,
+                }
+            },
+            event = gummy.next_event() => {
+                match event? {
+                    Some(event) => {
+                        let finished = matches!(event, TranscriptionEvent::Finished);
+                        // The handle may have stopped listening, keep the session going anyway.
+                        let _ = events.send(labelled(event, &label));
+                        if finished {
+                            break;
+                        }
+                    }
+                    None => {
+                        error!("Connection closed before the task finished");
+                        return Err(GummyError::ConnectionClosed);
+                    }
+                }
+            }
+        }
+    }
+
+    if !gummy.is_finished() {
+        debug!("Finishing session");
+        gummy.send_finish_task().await?;
+        while let Some(event) = gummy.next_event().await? {
+            let _ = events.send(labelled(event, &label));
+        }
+    }
+    let gummy = gummy.finish().await?;
+    let mut result = gummy.get_result();
+    for t in &mut result {
+        t.source_label.clone_from(&label);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gummy_mock::MockServer;
+
+    #[tokio::test]
+    async fn concurrent_sessions_keep_results_separate() {
+        let server = MockServer::start().await;
+        let manager = GummySessionManager::new(
+            "test-key",
+            ConnectOptions {
+                url: Some(server.url()),
+                ..Default::default()
+            },
+        );
+        let (mut meeting, mic) = tokio::try_join!(
+            manager.spawn_labelled_session(StartOptions::default(), "Others"),
+            manager.spawn_session(StartOptions::default())
+        )
+        .unwrap();
+
+        for _ in 0..3 {
+            meeting.send(b"meeting").unwrap();
+            mic.send(b"mic").unwrap();
+        }
+        for _ in 0..3 {
+            match meeting.events().recv().await.unwrap() {
+                TranscriptionEvent::Final(transcription) => {
+                    assert_eq!(transcription.text, "meeting");
+                    assert_eq!(transcription.source_label.as_deref(), Some("Others"));
+                }
+                event => panic!("unexpected event {:?}", event),
+            }
+        }
+
+        let (meeting, mic) = tokio::try_join!(meeting.finish(), mic.finish()).unwrap();
+        assert_eq!(meeting.len(), 3);
+        assert_eq!(mic.len(), 3);
+        assert!(
+            meeting
+                .iter()
+                .all(|t| t.text == "meeting" && t.source_label.as_deref() == Some("Others"))
+        );
+        assert!(
+            mic.iter()
+                .all(|t| t.text == "mic" && t.source_label.is_none())
+        );
+    }
+
+    #[tokio::test]
+    async fn dropping_manager_aborts_sessions() {
+        let server = MockServer::start().await;
+        let manager = GummySessionManager::new(
+            "test-key",
+            ConnectOptions {
+                url: Some(server.url()),
+                ..Default::default()
+            },
+        );
+        let session = manager
+            .spawn_session(StartOptions::default())
+            .await
+            .unwrap();
+        drop(manager);
+
+        assert!(session.finish().await.is_err());
+    }
+}
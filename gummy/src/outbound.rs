@@ -0,0 +1,117 @@
+//! Audio waiting to be written to the connection. It is written while waiting for events, so a
+//! connection slow to take it doesn't hold up the results coming back.
+
+use futures_util::{Sink, SinkExt};
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::task::{Poll, ready};
+use std::time::Duration;
+use tungstenite::Message;
+
+/// Most audio queued, as 16-bit mono at the task's sample rate. Beyond it the oldest is
+/// dropped, the connection having fallen too far behind for it to still be of use.
+const QUEUE_LIMIT: Duration = Duration::from_secs(30);
+
+/// Chunks of audio queued for the connection, up to a limit.
+pub(crate) struct Outbound {
+    chunks: VecDeque<Vec<u8>>,
+    bytes: usize,
+    limit: usize,
+    /// Whether chunks were handed to the connection without being flushed yet.
+    unflushed: bool,
+}
+
+impl Outbound {
+    /// A queue holding at most `QUEUE_LIMIT` of audio at `sample_rate`.
+    pub(crate) fn new(sample_rate: u32) -> Self {
+        Outbound::with_limit(QUEUE_LIMIT.as_secs() as usize * sample_rate as usize * 2)
+    }
+
+    fn with_limit(limit: usize) -> Self {
+        Outbound {
+            chunks: VecDeque::new(),
+            bytes: 0,
+            limit,
+            unflushed: false,
+        }
+    }
+
+    /// Queues `chunk`, dropping the oldest chunks past the limit, though never the newest.
+    /// Returns how many bytes were dropped.
+    pub(crate) fn push(&mut self, chunk: Vec<u8>) -> usize {
+        self.bytes += chunk.len();
+        self.chunks.push_back(chunk);
+        let mut dropped = 0;
+        while self.bytes > self.limit && self.chunks.len() > 1 {
+            let chunk = self
+                .chunks
+                .pop_front()
+                .expect("more than one chunk is queued");
+            self.bytes -= chunk.len();
+            dropped += chunk.len();
+        }
+        dropped
+    }
+
+    /// Bytes queued and not handed to the connection yet.
+    pub(crate) fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Whether everything queued was written and flushed.
+    pub(crate) fn is_idle(&self) -> bool {
+        self.chunks.is_empty() && !self.unflushed
+    }
+
+    /// Writes the queued chunks to `sink` and flushes it. It can be cancelled without
+    /// losing audio, chunks leaving the queue only once `sink` took them.
+    pub(crate) async fn write<S>(&mut self, sink: &mut S) -> Result<(), S::Error>
+    where
+        S: Sink<Message> + Unpin,
+    {
+        poll_fn(|cx| {
+            while !self.chunks.is_empty() {
+                ready!(sink.poll_ready_unpin(cx))?;
+                let chunk = self.chunks.pop_front().expect("a chunk is queued");
+                self.bytes -= chunk.len();
+                sink.start_send_unpin(Message::Binary(chunk.into()))?;
+                self.unflushed = true;
+            }
+            ready!(sink.poll_flush_unpin(cx))?;
+            self.unflushed = false;
+            Poll::Ready(Ok(()))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::sink;
+    use std::convert::Infallible;
+    use std::pin::pin;
+
+    #[tokio::test]
+    async fn drops_the_oldest_audio_past_the_limit() {
+        let mut outbound = Outbound::with_limit(10);
+        assert_eq!(outbound.push(vec![0; 4]), 0);
+        assert_eq!(outbound.push(vec![1; 4]), 0);
+        assert_eq!(outbound.push(vec![2; 4]), 4);
+        assert_eq!(outbound.bytes(), 8);
+        // A chunk bigger than the limit still goes out.
+        assert_eq!(outbound.push(vec![3; 16]), 8);
+        assert_eq!(outbound.push(vec![4; 2]), 16);
+        assert!(!outbound.is_idle());
+
+        let mut written = vec![];
+        let mut sink = pin!(sink::unfold(&mut written, |written, message| async move {
+            written.push(message);
+            Ok::<_, Infallible>(written)
+        }));
+        outbound.write(&mut sink).await.unwrap();
+        assert_eq!(written, [Message::Binary(vec![4; 2].into())]);
+        assert_eq!(outbound.bytes(), 0);
+        assert!(outbound.is_idle());
+    }
+}
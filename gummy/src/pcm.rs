@@ -0,0 +1,72 @@
+//! Samples as the little-endian 16-bit PCM of tasks started with the "pcm" format.
+//!
+//! On little-endian targets the samples already are that in memory and are sent as they
+//! are, elsewhere each one is swapped on the way.
+
+use std::borrow::Cow;
+
+/// `samples` as little-endian bytes, borrowed from them on little-endian targets.
+pub fn le_bytes(samples: &[i16]) -> Cow<'_, [u8]> {
+    if cfg!(target_endian = "little") {
+        Cow::Borrowed(bytemuck::cast_slice(samples))
+    } else {
+        let mut bytes = Vec::with_capacity(size_of_val(samples));
+        swap_into(&mut bytes, samples);
+        Cow::Owned(bytes)
+    }
+}
+
+/// Appends `samples` to `bytes` as little-endian bytes, so a buffer reused from one chunk
+/// of audio to the next takes them without allocating.
+pub fn extend_le_bytes(bytes: &mut Vec<u8>, samples: &[i16]) {
+    if cfg!(target_endian = "little") {
+        bytes.extend_from_slice(bytemuck::cast_slice(samples));
+    } else {
+        swap_into(bytes, samples);
+    }
+}
+
+/// Appends `samples` as little-endian bytes one at a time, as on big-endian targets.
+fn swap_into(bytes: &mut Vec<u8>, samples: &[i16]) {
+    bytes.reserve(size_of_val(samples));
+    for sample in samples {
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How chunks were encoded before, a collected iterator of the bytes of each sample.
+    fn collected(samples: &[i16]) -> Vec<u8> {
+        samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn encodes_samples_as_they_were_before_on_either_endianness() {
+        let samples = [0, 1, -1, 0x0102, i16::MIN, i16::MAX, -12345, 31000];
+        let expected = collected(&samples);
+        assert_eq!(
+            expected[..8],
+            [0x00, 0x00, 0x01, 0x00, 0xff, 0xff, 0x02, 0x01]
+        );
+        assert_eq!(*le_bytes(&samples), expected);
+        // The other target's encoding, which only one of the branches above runs.
+        let mut swapped = vec![];
+        swap_into(&mut swapped, &samples);
+        assert_eq!(swapped, expected);
+        if cfg!(target_endian = "little") {
+            assert!(matches!(le_bytes(&samples), Cow::Borrowed(_)));
+            assert_eq!(bytemuck::cast_slice::<i16, u8>(&samples), expected);
+        }
+
+        // Appending reuses the buffer across chunks.
+        let mut bytes = Vec::with_capacity(64);
+        let buffer = bytes.as_ptr();
+        extend_le_bytes(&mut bytes, &samples[..3]);
+        extend_le_bytes(&mut bytes, &samples[3..]);
+        assert_eq!(bytes, expected);
+        assert_eq!(bytes.as_ptr(), buffer);
+    }
+}
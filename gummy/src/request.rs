@@ -0,0 +1,139 @@
+//! Messages sent to the Gummy service.
+
+use crate::client::{MODEL, SessionInfo};
+use crate::format::{DEFAULT_FORMAT, DEFAULT_SAMPLE_RATE};
+use serde::Deserialize;
+use serde::Serialize;
+use std::time::SystemTime;
+
+#[derive(Serialize, Deserialize)]
+pub struct Header {
+    task_id: String,
+    action: String,
+    streaming: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Parameters {
+    sample_rate: u32,
+    format: String,
+    source_language: Option<String>,
+    transcription_enabled: bool,
+    translation_enabled: bool,
+    translation_target_languages: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Input {}
+
+#[derive(Serialize, Deserialize)]
+pub struct Payload {
+    model: Option<String>,
+    parameters: Option<Parameters>,
+    input: Input,
+    task: Option<String>,
+    task_group: Option<String>,
+    function: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StartMessage {
+    header: Header,
+    payload: Payload,
+}
+
+impl StartMessage {
+    pub fn new(
+        format: Option<&str>,
+        sample_rate: Option<u32>,
+        source_language: Option<&str>,
+        target_languages: Vec<String>,
+    ) -> Self {
+        let task_id = uuid::Uuid::new_v4().to_string();
+        let format = format.unwrap_or(DEFAULT_FORMAT).to_string();
+        let sample_rate = sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+        let source_language = source_language
+            .map(|s| s.to_string())
+            .unwrap_or("auto".to_string());
+        let target_languages = match target_languages.is_empty() {
+            true => vec!["zh".to_string()],
+            false => target_languages,
+        };
+        StartMessage {
+            header: Header {
+                task_id: task_id.to_string(),
+                action: "run-task".to_string(),
+                streaming: "duplex".to_string(),
+            },
+            payload: Payload {
+                model: Some(MODEL.to_string()),
+                parameters: Some(Parameters {
+                    sample_rate,
+                    format,
+                    source_language: Some(source_language),
+                    transcription_enabled: true,
+                    translation_enabled: true,
+                    translation_target_languages: target_languages,
+                }),
+                input: Input {},
+                task: Some("asr".to_string()),
+                task_group: Some("audio".to_string()),
+                function: Some("recognition".to_string()),
+            },
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.header.task_id
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.parameters().sample_rate
+    }
+
+    /// What the task was started with, if it started at `started_at`.
+    pub fn session_info(&self, started_at: SystemTime) -> SessionInfo {
+        let parameters = self.parameters();
+        SessionInfo {
+            task_id: self.id().to_string(),
+            model: self.payload.model.clone().unwrap_or_default(),
+            sample_rate: parameters.sample_rate,
+            source_language: parameters.source_language.clone().unwrap_or_default(),
+            target_languages: parameters.translation_target_languages.clone(),
+            started_at,
+        }
+    }
+
+    fn parameters(&self) -> &Parameters {
+        self.payload
+            .parameters
+            .as_ref()
+            .expect("start messages have parameters")
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FinishMessage {
+    header: Header,
+    payload: Payload,
+}
+
+impl FinishMessage {
+    pub fn new(task_id: &str) -> Self {
+        FinishMessage {
+            header: Header {
+                task_id: task_id.to_string(),
+                action: "finish-task".to_string(),
+                streaming: "duplex".to_string(),
+            },
+            payload: Payload {
+                model: None,
+                parameters: None,
+                input: Input {},
+                task: None,
+                task_group: None,
+                function: None,
+            },
+        }
+    }
+}
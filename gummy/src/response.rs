@@ -0,0 +1,310 @@
+//! Parsing of events received from the Gummy service.
+
+use crate::error::{GummyError, GummyResult};
+use crate::transcription::{TaskUsage, Transcription, Translation, Word};
+
+/// Returns the event name and task id from a response header.
+pub(crate) fn parse_header(response: &serde_json::Value) -> GummyResult<(&str, &str)> {
+    let event = response["header"]["event"]
+        .as_str()
+        .ok_or_else(|| GummyError::InvalidResponse("Missing event in response".to_string()))?;
+    let task_id = response["header"]["task_id"]
+        .as_str()
+        .ok_or_else(|| GummyError::InvalidResponse("Missing task_id in response".to_string()))?;
+    Ok((event, task_id))
+}
+
+pub(crate) fn parse_task_failed(response: &serde_json::Value) -> GummyError {
+    let field = |name: &str| {
+        response["header"][name]
+            .as_str()
+            .unwrap_or("unknown")
+            .to_string()
+    };
+    GummyError::TaskFailed {
+        code: field("error_code"),
+        message: field("error_message"),
+    }
+}
+
+/// Parses the usage a `task-finished` response bills the task for, if it has any.
+pub(crate) fn parse_usage(response: &serde_json::Value) -> Option<TaskUsage> {
+    let duration = response["payload"]["usage"]["duration"].as_u64()?;
+    Some(TaskUsage { duration })
+}
+
+/// The error of a response missing `field`, or holding a value of another type there.
+fn missing(field: &str) -> GummyError {
+    GummyError::InvalidResponse(format!("Missing {} in response", field))
+}
+
+/// Parses a `result-generated` response into the transcription it carries, final once the
+/// sentence has ended.
+pub(crate) fn parse_result(response: &serde_json::Value) -> GummyResult<Transcription> {
+    let transcription_json = response["payload"]["output"]["transcription"]
+        .as_object()
+        .ok_or_else(|| missing("transcription"))?;
+    let number = |field| {
+        transcription_json
+            .get(field)
+            .and_then(|value| value.as_u64())
+            .ok_or_else(|| missing(field))
+    };
+    let sentence_id = number("sentence_id")?;
+    let begin_time = number("begin_time")?;
+    let end_time = number("end_time")?;
+    let text = transcription_json
+        .get("text")
+        .and_then(|text| text.as_str())
+        .ok_or_else(|| missing("text"))?
+        .to_string();
+    let sentence_end = transcription_json
+        .get("sentence_end")
+        .and_then(|sentence_end| sentence_end.as_bool())
+        .ok_or_else(|| missing("sentence_end"))?;
+    let confidence = parse_confidence(&response["payload"]["output"]["transcription"]);
+    let words = transcription_json
+        .get("words")
+        .and_then(|words| words.as_array())
+        .map(|words| words.iter().map(parse_word).collect())
+        .unwrap_or_default();
+    let translations = response["payload"]["output"]["translations"]
+        .as_array()
+        .map(|translations| {
+            translations
+                .iter()
+                .map(|translation| {
+                    Ok(Translation {
+                        lang: translation["lang"].as_str().unwrap_or_default().to_string(),
+                        text: translation["text"]
+                            .as_str()
+                            .ok_or_else(|| missing("text of a translation"))?
+                            .to_string(),
+                    })
+                })
+                .collect::<GummyResult<Vec<_>>>()
+        })
+        .transpose()?
+        .unwrap_or_default();
+    let translated_text = translations
+        .first()
+        .map(|translation| translation.text.clone());
+    Ok(Transcription {
+        sentence_id,
+        begin_time,
+        end_time,
+        text,
+        is_final: sentence_end,
+        translated_text,
+        translations,
+        words,
+        confidence,
+        source_label: None,
+    })
+}
+
+/// Parses a word of a transcription, with the punctuation after it appended.
+fn parse_word(word: &serde_json::Value) -> Word {
+    let text = word["text"].as_str().unwrap_or_default();
+    let punctuation = word["punctuation"].as_str().unwrap_or_default();
+    Word {
+        begin_time: word["begin_time"].as_u64().unwrap_or_default(),
+        end_time: word["end_time"].as_u64().unwrap_or_default(),
+        text: format!("{}{}", text, punctuation),
+        confidence: parse_confidence(word),
+    }
+}
+
+/// Parses the `confidence` of a sentence or a word, which some results call `score`.
+fn parse_confidence(value: &serde_json::Value) -> Option<f64> {
+    value
+        .get("confidence")
+        .or_else(|| value.get("score"))
+        .and_then(|confidence| confidence.as_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn result_generated(transcription: serde_json::Value) -> serde_json::Value {
+        json!({
+            "header": { "task_id": "task", "event": "result-generated" },
+            "payload": { "output": { "transcription": transcription } },
+        })
+    }
+
+    #[test]
+    fn parses_confidence_when_present() {
+        let response = result_generated(json!({
+            "sentence_id": 0,
+            "begin_time": 100,
+            "end_time": 900,
+            "text": "hello",
+            "sentence_end": true,
+            "confidence": 0.87,
+        }));
+        let transcription = parse_result(&response).unwrap();
+        assert!(transcription.is_final);
+        assert_eq!(transcription.confidence, Some(0.87));
+    }
+
+    #[test]
+    fn parses_score_as_confidence() {
+        let response = result_generated(json!({
+            "sentence_id": 0,
+            "begin_time": 100,
+            "end_time": 900,
+            "text": "hello",
+            "sentence_end": false,
+            "score": 0.5,
+        }));
+        let transcription = parse_result(&response).unwrap();
+        assert_eq!(transcription.confidence, Some(0.5));
+    }
+
+    #[test]
+    fn confidence_defaults_to_none() {
+        let response = result_generated(json!({
+            "sentence_id": 3,
+            "begin_time": 100,
+            "end_time": 900,
+            "text": "hello",
+            "sentence_end": true,
+        }));
+        let transcription = parse_result(&response).unwrap();
+        assert_eq!(transcription.sentence_id, 3);
+        assert_eq!(transcription.confidence, None);
+    }
+
+    #[test]
+    fn parses_words_and_translations() {
+        let mut response = result_generated(json!({
+            "sentence_id": 0,
+            "begin_time": 100,
+            "end_time": 900,
+            "text": "Hello world.",
+            "sentence_end": true,
+            "words": [
+                { "begin_time": 100, "end_time": 400, "text": "Hello", "punctuation": "" },
+                { "begin_time": 400, "end_time": 900, "text": "world", "punctuation": "." },
+            ],
+        }));
+        response["payload"]["output"]["translations"] = json!([
+            { "sentence_id": 0, "lang": "zh", "text": "你好世界。" },
+            { "sentence_id": 0, "lang": "ja", "text": "こんにちは世界。" },
+        ]);
+        let transcription = parse_result(&response).unwrap();
+        assert_eq!(transcription.translated_text.as_deref(), Some("你好世界。"));
+        assert_eq!(transcription.translations.len(), 2);
+        assert_eq!(transcription.translations[1].lang, "ja");
+        assert_eq!(
+            transcription.words[1],
+            Word {
+                begin_time: 400,
+                end_time: 900,
+                text: "world.".to_string(),
+                confidence: None,
+            }
+        );
+    }
+
+    #[test]
+    fn parses_the_confidence_of_words() {
+        let response = result_generated(json!({
+            "sentence_id": 0,
+            "begin_time": 100,
+            "end_time": 900,
+            "text": "Hello world.",
+            "sentence_end": true,
+            "confidence": 0.8,
+            "words": [
+                { "begin_time": 100, "end_time": 400, "text": "Hello", "confidence": 0.95 },
+                { "begin_time": 400, "end_time": 600, "text": "big", "score": 0.4 },
+                { "begin_time": 600, "end_time": 900, "text": "world", "punctuation": "." },
+            ],
+        }));
+        let transcription = parse_result(&response).unwrap();
+        let confidences = transcription
+            .words
+            .iter()
+            .map(|word| word.confidence)
+            .collect::<Vec<_>>();
+        assert_eq!(confidences, [Some(0.95), Some(0.4), None]);
+        // The sentence keeps its own.
+        assert_eq!(transcription.confidence, Some(0.8));
+    }
+
+    #[test]
+    fn rejects_a_result_missing_a_field() {
+        let sentence = json!({
+            "sentence_id": 0,
+            "begin_time": 100,
+            "end_time": 900,
+            "text": "hello",
+            "sentence_end": true,
+        });
+        for field in [
+            "sentence_id",
+            "begin_time",
+            "end_time",
+            "text",
+            "sentence_end",
+        ] {
+            let mut transcription = sentence.clone();
+            transcription.as_object_mut().unwrap().remove(field);
+            let result = parse_result(&result_generated(transcription));
+            assert!(
+                matches!(&result, Err(GummyError::InvalidResponse(message)) if message.contains(field)),
+                "{}: {:?}",
+                field,
+                result
+            );
+        }
+        let mut response = result_generated(sentence);
+        response["payload"]["output"]["translations"] = json!([{ "lang": "zh" }]);
+        assert!(matches!(
+            parse_result(&response),
+            Err(GummyError::InvalidResponse(_))
+        ));
+        let response = json!({
+            "header": { "task_id": "task", "event": "result-generated" },
+            "payload": { "output": {} },
+        });
+        assert!(matches!(
+            parse_result(&response),
+            Err(GummyError::InvalidResponse(_))
+        ));
+    }
+
+    #[test]
+    fn parses_the_usage_of_a_finished_task() {
+        let response = json!({
+            "header": { "task_id": "task", "event": "task-finished" },
+            "payload": { "output": {}, "usage": { "duration": 42 } },
+        });
+        assert_eq!(parse_usage(&response), Some(TaskUsage { duration: 42 }));
+        let response = json!({
+            "header": { "task_id": "task", "event": "task-finished" },
+            "payload": { "output": {} },
+        });
+        assert_eq!(parse_usage(&response), None);
+    }
+
+    #[test]
+    fn min_confidence_keeps_unscored_sentences() {
+        let response = result_generated(json!({
+            "sentence_id": 0,
+            "begin_time": 0,
+            "end_time": 1,
+            "text": "hello",
+            "sentence_end": true,
+        }));
+        let mut transcription = parse_result(&response).unwrap();
+        assert!(transcription.meets_confidence(0.9));
+        transcription.confidence = Some(0.2);
+        assert!(!transcription.meets_confidence(0.9));
+        assert!(transcription.meets_confidence(0.2));
+    }
+}
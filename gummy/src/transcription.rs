@@ -0,0 +1,160 @@
+use serde::{Deserialize, Serialize};
+
+/// A recognized sentence.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Transcription {
+    /// Index of the sentence within its task.
+    pub sentence_id: u64,
+    /// Start of the sentence in milliseconds, relative to the start of the task.
+    pub begin_time: u64,
+    /// End of the sentence in milliseconds, relative to the start of the task.
+    pub end_time: u64,
+    /// Recognized text in the source language.
+    pub text: String,
+    /// Whether the sentence reached its final form; partial sentences may still change.
+    pub is_final: bool,
+    /// Translation into the target language, when translation is enabled.
+    pub translated_text: Option<String>,
+    /// Translations into each target language; `translated_text` is the first of them.
+    #[serde(default)]
+    pub translations: Vec<Translation>,
+    /// Words of the sentence with their timings, when the service reports them.
+    #[serde(default)]
+    pub words: Vec<Word>,
+    /// Sentence-level confidence in `0.0..=1.0`, when the service reports one.
+    pub confidence: Option<f64>,
+    /// Who the sentence is from, like `Me`, as the session it came from is labelled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_label: Option<String>,
+}
+
+/// A sentence translated into one of the target languages.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Translation {
+    /// Code of the language, like `zh`.
+    pub lang: String,
+    /// Translated text.
+    pub text: String,
+}
+
+/// What a finished task is billed for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskUsage {
+    /// Audio billed, in whole seconds.
+    pub duration: u64,
+}
+
+/// A recognized word.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Word {
+    /// Start of the word in milliseconds, relative to the start of the task.
+    pub begin_time: u64,
+    /// End of the word in milliseconds, relative to the start of the task.
+    pub end_time: u64,
+    /// The word, followed by the punctuation after it.
+    pub text: String,
+    /// Word-level confidence in `0.0..=1.0`, when the service reports one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f64>,
+}
+
+impl Transcription {
+    /// Whether this sentence should be kept under `min_confidence`. Sentences without a
+    /// reported confidence are always kept.
+    pub fn meets_confidence(&self, min_confidence: f64) -> bool {
+        self.confidence
+            .is_none_or(|confidence| confidence >= min_confidence)
+    }
+}
+
+/// Takes `transcription` into `result`, the sentences of a task so far sorted by id,
+/// replacing the earlier version of the sentence if there is one.
+pub fn update_result(result: &mut Vec<Transcription>, transcription: Transcription) {
+    // Sentences can be updated out of order, so keep the result sorted by id.
+    match result.binary_search_by_key(&transcription.sentence_id, |existing| existing.sentence_id) {
+        Ok(index) => result[index] = transcription,
+        Err(index) => result.insert(index, transcription),
+    }
+}
+
+/// Interleaves the final sentences of several sessions, each sorted by id, into one
+/// transcript in the order they start. Sentences starting together go by which ends first,
+/// then by the order of `sessions`. Sentences are numbered again in the order they come in,
+/// as ids of different sessions collide.
+pub fn merge_sessions(sessions: Vec<Vec<Transcription>>) -> Vec<Transcription> {
+    let mut merged = sessions
+        .into_iter()
+        .enumerate()
+        .flat_map(|(session, result)| result.into_iter().map(move |t| (session, t)))
+        .collect::<Vec<_>>();
+    merged.sort_by_key(|(session, t)| (t.begin_time, t.end_time, *session, t.sentence_id));
+    merged
+        .into_iter()
+        .enumerate()
+        .map(|(id, (_, mut t))| {
+            t.sentence_id = id as u64;
+            t
+        })
+        .collect()
+}
+
+/// An update produced while a task is running.
+#[derive(Debug, Clone)]
+pub enum TranscriptionEvent {
+    /// A sentence was recognized or refined and may still change.
+    Partial(Transcription),
+    /// A sentence reached its final form.
+    Final(Transcription),
+    /// The task finished; no more events will follow.
+    Finished,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sentence(sentence_id: u64, begin_time: u64, end_time: u64, label: &str) -> Transcription {
+        Transcription {
+            sentence_id,
+            begin_time,
+            end_time,
+            text: format!("{} {}", label, sentence_id),
+            is_final: true,
+            translated_text: None,
+            translations: vec![],
+            words: vec![],
+            confidence: None,
+            source_label: Some(label.to_string()),
+        }
+    }
+
+    #[test]
+    fn merges_sessions_in_the_order_sentences_start() {
+        let me = vec![
+            sentence(0, 0, 2_000, "Me"),
+            sentence(1, 3_000, 5_000, "Me"),
+            sentence(2, 8_000, 9_000, "Me"),
+        ];
+        let others = vec![
+            // Talking over the first sentence.
+            sentence(0, 1_000, 4_000, "Others"),
+            // Starting together with a sentence of the first session, ending sooner.
+            sentence(1, 3_000, 4_500, "Others"),
+            // Starting and ending together, after the first session's.
+            sentence(2, 8_000, 9_000, "Others"),
+        ];
+        let merged = merge_sessions(vec![me, others]);
+        let texts = merged.iter().map(|t| t.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            ["Me 0", "Others 0", "Others 1", "Me 1", "Me 2", "Others 2"]
+        );
+        let labels = merged
+            .iter()
+            .map(|t| t.source_label.as_deref().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(labels, ["Me", "Others", "Others", "Me", "Me", "Others"]);
+        let ids = merged.iter().map(|t| t.sentence_id).collect::<Vec<_>>();
+        assert_eq!(ids, [0, 1, 2, 3, 4, 5]);
+    }
+}
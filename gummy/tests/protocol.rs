@@ -0,0 +1,304 @@
+//! Exercises the client against the scripted mock server in `gummy-mock`.
+
+use gummy::{
+    ConnectOptions, Converting, Gummy, GummyError, StartOptions, TaskUsage, TranscriptionEvent,
+};
+use gummy_mock::{MockServer, Script};
+use std::time::Duration;
+use tokio::time::timeout;
+
+fn fixture(name: &str) -> Script {
+    Script::from_file(format!(
+        "{}/tests/fixtures/{}.json",
+        env!("CARGO_MANIFEST_DIR"),
+        name
+    ))
+}
+
+async fn start(server: &MockServer) -> Gummy<Converting> {
+    Gummy::new("test-key")
+        .connect(Some(&server.url()))
+        .await
+        .unwrap()
+        .start(None, None, None, None)
+        .await
+        .unwrap()
+}
+
+/// Sends one audio frame per scripted result and collects the events they produce.
+async fn replay(gummy: &mut Gummy<Converting>, frames: usize) -> Vec<TranscriptionEvent> {
+    let mut events = vec![];
+    for _ in 0..frames {
+        gummy.send(&[0; 3200]).await.unwrap();
+        events.push(gummy.next_event().await.unwrap().unwrap());
+    }
+    events
+}
+
+#[tokio::test]
+async fn happy_path() {
+    let server = MockServer::with_script(fixture("happy_path")).await;
+    let mut gummy = start(&server).await;
+    let info = gummy.session_info().clone();
+    assert_eq!(info.task_id, gummy.task_id());
+    assert_eq!(info.model, gummy::MODEL);
+    assert_eq!(info.sample_rate, 48000);
+    assert_eq!(info.source_language, "auto");
+    assert_eq!(info.target_languages, ["zh"]);
+
+    let events = replay(&mut gummy, 3).await;
+    assert!(matches!(&events[0], TranscriptionEvent::Partial(t) if t.text == "Hello"));
+    assert!(matches!(&events[1], TranscriptionEvent::Final(t) if t.text == "Hello world."));
+    assert!(matches!(&events[2], TranscriptionEvent::Final(t) if t.text == "Goodbye."));
+
+    let finished = gummy.finish().await.unwrap();
+    assert_eq!(finished.session_info(), &info);
+    // 9600 bytes at 48 kHz, billed as a whole second.
+    assert_eq!(finished.usage(), Some(TaskUsage { duration: 1 }));
+    let result = finished.get_result();
+    assert_eq!(result.len(), 2);
+    assert_eq!(result[0].text, "Hello world.");
+    assert_eq!(result[0].translated_text.as_deref(), Some("你好世界。"));
+    assert_eq!(result[1].begin_time, 1500);
+    assert_eq!(result[1].translated_text.as_deref(), Some("再见。"));
+}
+
+#[tokio::test]
+async fn out_of_order_sentence_updates() {
+    let server = MockServer::with_script(fixture("out_of_order")).await;
+    let mut gummy = start(&server).await;
+
+    replay(&mut gummy, 3).await;
+
+    let result = gummy.finish().await.unwrap().get_result();
+    let texts = result.iter().map(|t| t.text.as_str()).collect::<Vec<_>>();
+    assert_eq!(texts, ["First.", "Second."]);
+    assert_eq!(result[0].sentence_id, 0);
+    assert_eq!(result[1].sentence_id, 1);
+}
+
+#[tokio::test]
+async fn missing_translation() {
+    let server = MockServer::with_script(fixture("missing_translation")).await;
+    let mut gummy = start(&server).await;
+
+    replay(&mut gummy, 2).await;
+
+    let result = gummy.finish().await.unwrap().get_result();
+    assert_eq!(result.len(), 2);
+    assert!(result.iter().all(|t| t.translated_text.is_none()));
+}
+
+#[tokio::test]
+async fn results_flow_while_audio_backs_up() {
+    let server = MockServer::with_script(fixture("slow_reader")).await;
+    let mut gummy = Gummy::new("test-key")
+        .connect(Some(&server.url()))
+        .await
+        .unwrap()
+        .start(None, Some(192000), None, None)
+        .await
+        .unwrap();
+    // More than the sockets buffer, which the server reads a frame of every half second.
+    for _ in 0..40 {
+        gummy.queue(vec![0; 256 * 1024]).unwrap();
+    }
+    for text in ["Still here.", "And here."] {
+        let event = timeout(Duration::from_secs(1), gummy.next_event())
+            .await
+            .expect("results are held up by the audio")
+            .unwrap()
+            .unwrap();
+        assert!(matches!(&event, TranscriptionEvent::Final(t) if t.text == text));
+        assert!(gummy.queued_bytes() > 0);
+    }
+}
+
+#[tokio::test]
+async fn restarts_with_the_options_of_the_task_before() {
+    let server = MockServer::start().await;
+    let options = StartOptions {
+        format: Some("pcm".to_string()),
+        sample_rate: Some(16000),
+        source_language: Some("en".to_string()),
+        target_languages: vec!["zh".to_string(), "ja".to_string()],
+        retained_finals: None,
+    };
+    let mut gummy = Gummy::new("test-key")
+        .connect(Some(&server.url()))
+        .await
+        .unwrap()
+        .start_with(&options)
+        .await
+        .unwrap();
+    let mut task_ids = vec![gummy.task_id().to_string()];
+    gummy.send(b"first").await.unwrap();
+    let finished = gummy.finish().await.unwrap();
+    assert_eq!(finished.options(), &options);
+
+    let mut gummy = finished.restart().await.unwrap();
+    task_ids.push(gummy.task_id().to_string());
+    // Events of the new task come through.
+    gummy.send(b"second").await.unwrap();
+    let event = gummy.next_event().await.unwrap().unwrap();
+    assert!(matches!(&event, TranscriptionEvent::Final(t) if t.text == "second"));
+    let finished = gummy.finish().await.unwrap();
+    assert_eq!(finished.options(), &options);
+
+    let mut other = options.clone();
+    other.sample_rate = Some(24000);
+    let gummy = finished.restart_with(&other).await.unwrap();
+    task_ids.push(gummy.task_id().to_string());
+    assert_eq!(gummy.options(), &other);
+
+    // The requests differ in their task ID alone, but for the sample rate overridden.
+    let mut run_tasks = server.run_tasks();
+    assert_eq!(run_tasks.len(), 3);
+    for (run_task, task_id) in run_tasks.iter_mut().zip(&task_ids) {
+        assert_eq!(run_task["header"]["task_id"].take(), task_id.as_str());
+    }
+    assert_ne!(task_ids[0], task_ids[1]);
+    assert_eq!(run_tasks[0], run_tasks[1]);
+    assert_eq!(run_tasks[2]["payload"]["parameters"]["sample_rate"], 24000);
+    run_tasks[2]["payload"]["parameters"]["sample_rate"] = 16000.into();
+    assert_eq!(run_tasks[0], run_tasks[2]);
+}
+
+#[tokio::test]
+async fn long_tasks_keep_the_last_sentences_alone() {
+    const SENTENCES: usize = 10_000;
+    const KEPT: usize = 50;
+    let server = MockServer::start().await;
+    let mut gummy = Gummy::new("test-key")
+        .connect(Some(&server.url()))
+        .await
+        .unwrap()
+        .start_with(&StartOptions {
+            retained_finals: Some(KEPT),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    // The echo server answers each frame with a final sentence of its content.
+    for n in 0..SENTENCES {
+        gummy.queue(n.to_string().into_bytes()).unwrap();
+    }
+    let mut most = 0;
+    for n in 0..SENTENCES {
+        let event = gummy.next_event().await.unwrap().unwrap();
+        // Every sentence is delivered, however few are kept.
+        assert!(matches!(&event, TranscriptionEvent::Final(t) if t.text == n.to_string()));
+        most = most.max(gummy.retained().len());
+    }
+    assert_eq!(most, KEPT);
+    assert_eq!(gummy.evicted_sentences(), SENTENCES - KEPT);
+
+    let finished = gummy.finish().await.unwrap();
+    let result = finished.get_result();
+    assert_eq!(result.len(), KEPT);
+    assert_eq!(result[0].text, (SENTENCES - KEPT).to_string());
+    assert_eq!(finished.evicted_sentences(), SENTENCES - KEPT);
+}
+
+#[tokio::test]
+async fn server_initiated_close() {
+    let server = MockServer::with_script(fixture("server_close")).await;
+    let mut gummy = start(&server).await;
+
+    let events = replay(&mut gummy, 1).await;
+    assert!(matches!(&events[0], TranscriptionEvent::Final(t) if t.text == "Cut off."));
+    assert!(gummy.next_event().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn task_failed_mid_stream() {
+    let server = MockServer::with_script(fixture("task_failed")).await;
+    let mut gummy = start(&server).await;
+
+    replay(&mut gummy, 1).await;
+    let error = gummy.next_event().await.unwrap_err();
+    // Failed tasks aren't retried.
+    assert!(!error.is_retryable());
+    // Errors tell the task they happened to, for the service's support.
+    assert_eq!(error.task_id(), Some(gummy.task_id()));
+    assert!(
+        error
+            .to_string()
+            .ends_with(&format!("(task {})", gummy.task_id()))
+    );
+    let GummyError::Task { error, .. } = error else {
+        panic!("error without its task {:?}", error);
+    };
+    match *error {
+        GummyError::TaskFailed { code, message } => {
+            assert_eq!(code, "InternalError");
+            assert_eq!(message, "model crashed");
+        }
+        error => panic!("unexpected error {:?}", error),
+    }
+    // The task is over, so finishing returns what was recognized without waiting.
+    let result = gummy.finish().await.unwrap().get_result();
+    assert_eq!(result.len(), 1);
+}
+
+#[tokio::test]
+async fn abrupt_disconnect() {
+    let server = MockServer::with_script(fixture("disconnect")).await;
+    let mut gummy = start(&server).await;
+
+    let error = gummy.next_event().await.unwrap_err();
+    assert!(error.is_retryable(), "{}", error);
+}
+
+#[tokio::test]
+async fn refused_api_key() {
+    let server = MockServer::with_script(Script {
+        api_key: Some("right-key".to_string()),
+        ..Script::default()
+    })
+    .await;
+    let error = match Gummy::new("wrong-key").connect(Some(&server.url())).await {
+        Ok(_) => panic!("connected with a wrong key"),
+        Err(error) => error,
+    };
+    assert!(error.is_auth(), "{}", error);
+    assert!(!error.is_retryable());
+}
+
+#[tokio::test]
+async fn oversized_message() {
+    let text = "a".repeat(4096);
+    let server = MockServer::with_script(Script {
+        results: vec![serde_json::json!({
+            "transcription": {
+                "sentence_id": 0, "begin_time": 0, "end_time": 1200, "text": text,
+                "sentence_end": true
+            }
+        })],
+        ..Script::default()
+    })
+    .await;
+    let options = ConnectOptions {
+        url: Some(server.url()),
+        max_message_size: Some(1024),
+    };
+    let mut gummy = Gummy::new("test-key")
+        .connect_with(&options)
+        .await
+        .unwrap()
+        .start(None, None, None, None)
+        .await
+        .unwrap();
+
+    gummy.send(&[0; 3200]).await.unwrap();
+    let error = gummy.next_event().await.unwrap_err();
+    assert!(!error.is_retryable());
+    let GummyError::Task { error, .. } = error else {
+        panic!("error without its task {:?}", error);
+    };
+    assert!(
+        matches!(*error, GummyError::MessageTooLarge { size, max_size: 1024 } if size > 1024),
+        "{:?}",
+        error
+    );
+}
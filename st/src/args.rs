@@ -0,0 +1,1129 @@
+use crate::cleanup::Cleanup;
+use crate::clipboard::ClipboardMode;
+use crate::config::{self, ApiKey, Settings};
+use crate::console::DisplayMode;
+use crate::control::Request;
+use crate::emit::EmitFormat;
+use crate::failure::ErrorFormat;
+use crate::output::{
+    MAX_PARAGRAPH_CHARS, SubtitleOptions, SubtitleTranslation, TRACK_TEMPLATE, TextOptions,
+    TextTranslation, Timestamps, TranscriptFormat,
+};
+use crate::summary::Pricing;
+use crate::timeline::Clock;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use std::env::var;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Transcribes and translates system audio or the microphone as it plays.
+#[derive(Debug, Parser)]
+#[command(name = "st", version, about)]
+pub struct Args {
+    #[command(subcommand)]
+    pub action: Option<Action>,
+    /// Config file with defaults for the options below [default: ~/.config/st/config.toml]
+    #[arg(long, value_name = "FILE", global = true)]
+    pub config: Option<PathBuf>,
+    /// Speech recognition backend.
+    #[arg(long, value_enum, default_value_t = Backend::Gummy)]
+    pub backend: Backend,
+    /// API key of the backend [default: $API_KEY, or $OPENAI_API_KEY for openai]
+    #[arg(long, value_name = "KEY", conflicts_with = "api_key_file")]
+    pub api_key: Option<String>,
+    /// File holding the API key of the backend.
+    #[arg(long, value_name = "FILE")]
+    pub api_key_file: Option<PathBuf>,
+    /// Endpoint of the backend, overriding its default.
+    #[arg(long)]
+    pub url: Option<String>,
+    /// Language spoken in the audio [default: auto]
+    #[arg(long, value_name = "LANG")]
+    pub source_lang: Option<String>,
+    /// Language to translate into, repeat for several [default: zh]
+    #[arg(long, value_name = "LANG")]
+    pub target_lang: Vec<String>,
+    /// Path to a ggml model file, used by the whisper backend.
+    #[arg(long, value_name = "PATH")]
+    pub model_path: Option<String>,
+    /// Print the available audio devices and exit.
+    #[arg(long)]
+    pub list_devices: bool,
+    /// Capture device name or index from `--list-devices`.
+    #[arg(long, value_name = "NAME|INDEX")]
+    pub device: Option<String>,
+    /// What to capture; `separate` transcribes system audio and the microphone apart.
+    #[arg(long, value_enum, default_value_t = Source::System)]
+    pub source: Source,
+    /// Where `separate` writes a transcript per source, and `conversation.txt` merging
+    /// them with each sentence labelled by its source.
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    pub transcript_dir: String,
+    /// File to write the transcript to, repeat for several or give `-` for stdout
+    /// alongside files [default: stdout]
+    #[arg(long, short, value_name = "FILE", global = true)]
+    pub output: Vec<PathBuf>,
+    /// Print the events of the session to stdout for other programs to read, instead of
+    /// captions.
+    #[arg(long, value_enum, value_name = "FORMAT", conflicts_with = "tui")]
+    pub emit: Option<EmitFormat>,
+    /// Format of the transcripts [default: from the extension of each `--output`, or txt]
+    #[arg(long, value_enum, global = true)]
+    pub format: Option<TranscriptFormat>,
+    /// Show the translation of each sentence in subtitles, under it or in a file per
+    /// language next to `--output`.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "WHERE",
+        num_args = 0..=1,
+        default_missing_value = "inline"
+    )]
+    pub subtitle_translation: Option<SubtitleTranslation>,
+    /// Pause between sentences that starts a new paragraph of text transcripts.
+    #[arg(long, value_name = "MS", default_value_t = 2_000)]
+    pub paragraph_gap_ms: u64,
+    /// Times shown in text transcripts.
+    #[arg(long, value_enum, value_name = "WHICH", default_value_t = Timestamps::None)]
+    pub timestamps: Timestamps,
+    /// Clock the times of transcripts are on: the task's, counting from the first audio
+    /// sent, the wall clock, or the audio of `--save-audio`.
+    #[arg(long, value_enum, value_name = "CLOCK", default_value_t = Clock::Task)]
+    pub clock: Clock,
+    /// Show the translation of text transcripts under each paragraph, or in a file per
+    /// language next to `--output`.
+    #[arg(long, value_enum, value_name = "WHERE", default_value_t = TextTranslation::Interleaved)]
+    pub text_translation: TextTranslation,
+    /// Name of the file per language of translations, from the `{stem}`, `{lang}` and
+    /// `{ext}` of `--output`.
+    #[arg(long, value_name = "TEMPLATE", default_value = TRACK_TEMPLATE)]
+    pub track_template: String,
+    /// Longest a subtitle cue lasts before it's split between words, when the backend
+    /// reports word timings.
+    #[arg(
+        long = "max-cue",
+        value_name = "SECONDS",
+        default_value = "7",
+        value_parser = parse_seconds
+    )]
+    pub max_cue_ms: u32,
+    /// Shortest silence marked in text and JSON transcripts, as
+    /// `[silence 00:02:14–00:05:47]`, or 0 to mark none.
+    #[arg(
+        long = "silence-marker",
+        value_name = "SECONDS",
+        default_value = "30",
+        value_parser = parse_seconds
+    )]
+    pub silence_marker_ms: u32,
+    /// Mark silences in subtitles too, with a cue lasting as long as each.
+    #[arg(long)]
+    pub silence_cues: bool,
+    /// Rate to record at, overriding the backend's preferred rate.
+    #[arg(long, value_name = "HZ")]
+    pub sample_rate: Option<u32>,
+    /// Start even when the audio sent isn't what the transcription task is started for,
+    /// with a warning rather than an error.
+    #[arg(long)]
+    pub force: bool,
+    /// Frames per device buffer, overriding the host's choice to lower latency.
+    #[arg(long, value_name = "FRAMES")]
+    pub buffer_size: Option<u32>,
+    /// Don't send audio while no speech is heard.
+    #[arg(long)]
+    pub skip_silence: bool,
+    /// Show the capture level on stderr.
+    #[arg(long)]
+    pub meter: bool,
+    /// Show the session on a dashboard in the terminal: p pauses the capture, c copies the
+    /// last sentence, a the whole transcript and q quits. Keep the transcript with `--output`.
+    #[arg(long, conflicts_with = "meter")]
+    pub tui: bool,
+    /// Serve the live transcript over HTTP at this address, like `127.0.0.1:7979`: a page
+    /// with the captions at `/`, the transcript as JSON at `/transcript`, its sentences as
+    /// Server-Sent Events at `/events` and their latency at `/metrics`.
+    #[arg(long, value_name = "ADDR")]
+    pub serve: Option<SocketAddr>,
+    /// Send caption events to WebSocket clients at this address, like `127.0.0.1:7980`,
+    /// for an overlay like the one `--serve` has at `/overlay`.
+    #[arg(long, value_name = "ADDR")]
+    pub broadcast_ws: Option<SocketAddr>,
+    /// Keep the current captions in this file, for text sources that read one: the last
+    /// final sentences and the one being recognized.
+    #[arg(long, value_name = "FILE")]
+    pub caption_file: Option<PathBuf>,
+    /// Keep the translations of the current captions in this file.
+    #[arg(long, value_name = "FILE")]
+    pub caption_file_translated: Option<PathBuf>,
+    /// Final sentences kept in caption files.
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    pub caption_lines: usize,
+    /// Text of sentences shown in printed captions, the dashboard and `--caption-file`:
+    /// what was said, its translation, or both. Sentences without a translation fall back
+    /// to what was said, marked [default: both, the text alone in `--caption-file`]
+    #[arg(long, value_enum, value_name = "TEXT")]
+    pub display: Option<DisplayMode>,
+    /// Least time between two updates shown of the sentence being recognized, in printed
+    /// captions, caption files and `--broadcast-ws`. Updates that change nothing are never
+    /// shown again.
+    #[arg(long = "partial-interval", value_name = "MS", default_value_t = 0)]
+    pub partial_interval_ms: u64,
+    /// Keep the last final sentence, or its translation, on the clipboard.
+    #[arg(long, value_name = "WHAT")]
+    pub clipboard: Option<ClipboardMode>,
+    /// Key that pauses or resumes the capture from any window, like `ctrl+alt+KeyP`.
+    #[arg(long, value_name = "KEYS")]
+    pub hotkey_pause: Option<String>,
+    /// Key that marks the moment in the transcript from any window.
+    #[arg(long, value_name = "KEYS")]
+    pub hotkey_marker: Option<String>,
+    /// Key that finishes the session from any window.
+    #[arg(long, value_name = "KEYS")]
+    pub hotkey_stop: Option<String>,
+    /// Show desktop notifications as the session starts, loses the connection, fails and
+    /// finishes.
+    #[arg(long)]
+    pub notify: bool,
+    /// How the text of sentences is cleaned up for transcripts and captions, by `cleanup`
+    /// in the config file.
+    #[arg(skip)]
+    pub cleanup: Option<Cleanup>,
+    /// How a failure that ends the run is reported on stderr.
+    #[arg(long, value_enum, default_value_t = ErrorFormat::Text)]
+    pub error_format: ErrorFormat,
+    /// Print how far behind the audio the final sentences came back when the session ends.
+    #[arg(long)]
+    pub latency_report: bool,
+    /// Price of a second of audio billed, for the cost in the summary of the session.
+    #[arg(long, value_name = "PRICE")]
+    pub price_per_second: Option<f64>,
+    /// Currency of `--price-per-second`.
+    #[arg(long, default_value = "CNY")]
+    pub currency: String,
+    /// Capture gain in dB.
+    #[arg(
+        long = "gain",
+        value_name = "DB",
+        default_value_t = 0.0,
+        allow_negative_numbers = true
+    )]
+    pub gain_db: f32,
+    /// Adjust the capture gain automatically.
+    #[arg(long)]
+    pub agc: bool,
+    /// Suppress background noise before transcription.
+    #[arg(long)]
+    pub denoise: bool,
+    /// Audio from before the transcription session is ready to keep, in seconds.
+    #[arg(long = "preroll", value_name = "SECONDS", value_parser = parse_seconds)]
+    pub preroll_ms: Option<u32>,
+    /// WAV file to save the audio sent for transcription to, or a FLAC file if it ends in
+    /// `.flac`. With rotation, a strftime template naming each of the WAV files.
+    #[arg(long, value_name = "FILE")]
+    pub save_audio: Option<String>,
+    /// Start a new `--save-audio` file after this many minutes.
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "save_audio",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub rotate_minutes: Option<u64>,
+    /// Start a new `--save-audio` file before it grows past this many megabytes.
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "save_audio",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub rotate_mb: Option<u64>,
+    /// Keep everything a run saves in a directory of its own under this one, named after
+    /// when it started: the audio, the transcript, the events of the session as
+    /// `events.jsonl` and what the session was as `meta.json`.
+    #[arg(long, value_name = "DIR")]
+    pub session_dir: Option<PathBuf>,
+    /// File to save the exact audio sent for transcription to, as headerless s16le PCM
+    /// with a JSON sidecar describing it.
+    #[arg(long, value_name = "FILE")]
+    pub save_raw: Option<String>,
+    /// WAV file to transcribe instead of capturing from a device.
+    #[arg(long, value_name = "FILE")]
+    pub input: Option<String>,
+    /// How many times faster than real time to feed `--input`, 0 for as fast as it can be
+    /// read. Live services may fall behind or drop the session when fed too fast.
+    #[arg(
+        long,
+        value_name = "FACTOR",
+        default_value_t = 1.0,
+        requires = "input",
+        value_parser = parse_speed
+    )]
+    pub speed: f64,
+    /// How many times faster than real time to send the audio held while reconnecting to
+    /// the new task, with the audio captured meanwhile behind it, until it's caught up. The
+    /// service fails tasks sent audio much faster than it plays.
+    #[arg(
+        long,
+        value_name = "FACTOR",
+        default_value_t = 3.0,
+        value_parser = parse_catch_up_speed
+    )]
+    pub catch_up_speed: f64,
+    /// Audio gathered into each message sent for transcription, in milliseconds. Audio
+    /// short of a message goes out anyway once it's 300 ms old.
+    #[arg(
+        long = "send-frame",
+        value_name = "MS",
+        default_value_t = 100,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub send_frame_ms: u32,
+    /// Finish the session after this long, like `90`, `120s`, `55m` or `1h30m`.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub duration: Option<Duration>,
+    /// Finish the session once no speech has been heard for this long.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub stop_after_silence: Option<Duration>,
+    /// Don't ask anything, like which device to capture from, and go with the defaults.
+    #[arg(long, short)]
+    pub yes: bool,
+    /// Don't remember the device picked when asked in the config file.
+    #[arg(long)]
+    pub no_save_choice: bool,
+    /// Log what is going on, like `RUST_LOG=debug`.
+    #[arg(long, short)]
+    pub verbose: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Action {
+    /// Manage the config file.
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Run headless, starting and stopping sessions as `st ctl` says.
+    Daemon {
+        /// Socket to listen on [default: $XDG_RUNTIME_DIR/st.sock or ~/.config/st/daemon.sock,
+        /// \\.\pipe\st-<user> on Windows]
+        #[arg(long, value_name = "PATH")]
+        socket: Option<PathBuf>,
+    },
+    /// Send a request to `st daemon` and print its answer, a JSON object per line.
+    Ctl {
+        #[arg(value_enum)]
+        request: Request,
+        /// Socket the daemon listens on [default: that of `st daemon`]
+        #[arg(long, value_name = "PATH")]
+        socket: Option<PathBuf>,
+    },
+    /// Write the transcripts of `--output` again from the `events.jsonl` of a session
+    /// directory, without audio or the backend.
+    Replay {
+        #[arg(value_name = "EVENTS")]
+        events: PathBuf,
+        /// Feed the events as far apart as they came in, instead of all at once.
+        #[arg(long)]
+        realtime: bool,
+    },
+    /// Check the API key, the network, audio capture and the output directories, and tell
+    /// how to fix what fails.
+    Doctor,
+    /// Transcribe a sentence of a session directory again, or a stretch of it, from the audio
+    /// it sent, and put the result in place of the old one in its transcripts.
+    Retranscribe {
+        #[arg(value_name = "SESSION_DIR")]
+        session: PathBuf,
+        /// ID of the sentence, as in JSON transcripts
+        #[arg(
+            long,
+            value_name = "ID",
+            required_unless_present = "range",
+            conflicts_with = "range"
+        )]
+        sentence: Option<u64>,
+        /// Stretch of the audio sent, like `00:12:03-00:12:20`, on the clock of the task
+        #[arg(long, value_name = "FROM-TO", value_parser = parse_time_range)]
+        range: Option<Range<u64>>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigAction {
+    /// Write a config file with every setting commented out.
+    Init {
+        /// Replace the config file if there is one.
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    Gummy,
+    Openai,
+    Whisper,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Source {
+    System,
+    Mic,
+    Mixed,
+    Separate,
+}
+
+/// Parses a duration in seconds into milliseconds.
+fn parse_seconds(value: &str) -> Result<u32, String> {
+    value
+        .parse::<f32>()
+        .ok()
+        .filter(|seconds| *seconds >= 0.0)
+        .map(|seconds| (seconds * 1000.0) as u32)
+        .ok_or_else(|| format!("invalid number of seconds: {}", value))
+}
+
+/// Parses a duration like `90`, `120s`, `55m` or `1h30m`, seconds unless a unit is given.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration: {}", value);
+    let mut rest = value.trim();
+    let mut seconds = 0.0;
+    while !rest.is_empty() {
+        let unit = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let number = rest[..unit].parse::<f64>().map_err(|_| invalid())?;
+        rest = &rest[unit..];
+        let next = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        seconds += number
+            * match &rest[..next] {
+                "" | "s" => 1.0,
+                "m" => 60.0,
+                "h" => 3600.0,
+                _ => return Err(invalid()),
+            };
+        rest = &rest[next..];
+    }
+    (seconds > 0.0)
+        .then(|| Duration::from_secs_f64(seconds))
+        .ok_or_else(invalid)
+}
+
+/// Parses a stretch of time like `00:12:03-00:12:20`, `12:03-12:20.5` or `5-9` into
+/// milliseconds.
+fn parse_time_range(value: &str) -> Result<Range<u64>, String> {
+    let invalid = || format!("invalid stretch of time, like 00:12:03-00:12:20: {}", value);
+    let time = |time: &str| -> Option<u64> {
+        let parts = time.trim().split(':').collect::<Vec<_>>();
+        if parts.len() > 3 {
+            return None;
+        }
+        let (seconds, minutes) = parts.split_last()?;
+        let seconds = seconds
+            .parse::<f64>()
+            .ok()
+            .filter(|s| s.is_finite() && *s >= 0.0)?;
+        let minutes = minutes.iter().try_fold(0, |total, part| {
+            part.parse::<u64>().ok().map(|part| total * 60 + part)
+        })?;
+        Some(minutes * 60_000 + (seconds * 1000.0).round() as u64)
+    };
+    let (from, to) = value.split_once('-').ok_or_else(invalid)?;
+    match (time(from), time(to)) {
+        (Some(from), Some(to)) if from < to => Ok(from..to),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_speed(value: &str) -> Result<f64, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|speed| speed.is_finite() && *speed >= 0.0)
+        .ok_or_else(|| format!("invalid speed: {}", value))
+}
+
+fn parse_catch_up_speed(value: &str) -> Result<f64, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|speed| speed.is_finite() && *speed > 1.0)
+        .ok_or_else(|| format!("invalid speed, faster than real time: {}", value))
+}
+
+impl Args {
+    /// Parses the process arguments, exiting with a usage message and status 2 when they
+    /// are invalid.
+    ///
+    /// Options not given on the command line are taken from the environment, then from the
+    /// config file.
+    pub fn parse() -> Self {
+        let matches = Args::command().get_matches();
+        let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        if matches!(
+            args.action,
+            None | Some(
+                Action::Daemon { .. }
+                    | Action::Replay { .. }
+                    | Action::Doctor
+                    | Action::Retranscribe { .. }
+            )
+        ) {
+            let file = args.config_file().unwrap_or_else(|message| {
+                Args::command()
+                    .error(ErrorKind::InvalidValue, message)
+                    .exit()
+            });
+            let cli = Settings::from_matches(&matches);
+            args.apply(config::layer(cli, |name| var(name).ok(), file));
+        }
+        if let Err(message) = args.normalize_languages() {
+            Args::command()
+                .error(ErrorKind::InvalidValue, message)
+                .exit();
+        }
+        if let Err(message) = args.validate() {
+            Args::command()
+                .error(ErrorKind::ArgumentConflict, message)
+                .exit();
+        }
+        args
+    }
+
+    /// `config`, or the default config file.
+    pub fn config_path(&self) -> Option<PathBuf> {
+        self.config.clone().or_else(config::default_path)
+    }
+
+    /// Settings of the config file, warning about keys it doesn't know. Only a file named
+    /// with `--config` has to exist.
+    fn config_file(&self) -> Result<Settings, String> {
+        let Some(path) = self.config_path() else {
+            return Ok(Settings::default());
+        };
+        let (settings, unknown) = config::load(&path, self.config.is_some())?;
+        for key in unknown {
+            eprintln!("Warning: unknown key `{}` in {}", key, path.display());
+        }
+        Ok(settings)
+    }
+
+    /// Takes the options from layered `settings`, which include those given on the command
+    /// line.
+    fn apply(&mut self, settings: Settings) {
+        if let Some(backend) = settings.backend {
+            self.backend = backend;
+        }
+        (self.api_key, self.api_key_file) = match settings.api_key {
+            Some(ApiKey::Value(key)) => (Some(key), None),
+            Some(ApiKey::File(path)) => (None, Some(path)),
+            None => (None, None),
+        };
+        self.url = settings.url;
+        self.source_lang = settings.source_lang;
+        if let Some(languages) = settings.target_lang {
+            self.target_lang = languages;
+        }
+        if let Some(source) = settings.source {
+            self.source = source;
+        }
+        self.device = settings.device;
+        self.sample_rate = settings.sample_rate;
+        if let Some(gain_db) = settings.gain_db {
+            self.gain_db = gain_db;
+        }
+        self.output = settings.output.unwrap_or_default();
+        self.format = settings.format;
+        self.model_path = settings.model_path;
+        self.price_per_second = settings.price_per_second;
+        if let Some(currency) = settings.currency {
+            self.currency = currency;
+        }
+        if let Some(notify) = settings.notify {
+            self.notify = notify;
+        }
+        self.cleanup = settings.cleanup;
+    }
+
+    /// The price from `--price-per-second`, if any.
+    pub fn pricing(&self) -> Option<Pricing> {
+        Some(Pricing {
+            per_second: self.price_per_second?,
+            currency: self.currency.clone(),
+        })
+    }
+
+    /// Normalizes the language codes, like `zh-CN` to `zh`, and checks the Gummy model
+    /// supports them. Other backends take any code.
+    fn normalize_languages(&mut self) -> Result<(), String> {
+        let backend = self.backend;
+        let source = |code: &String| match backend {
+            Backend::Gummy => gummy::source_language(gummy::MODEL, code),
+            Backend::Openai | Backend::Whisper => Ok(gummy::normalize_language(code)),
+        };
+        let target = |code: &String| match backend {
+            Backend::Gummy => gummy::target_language(gummy::MODEL, code),
+            Backend::Openai | Backend::Whisper => Ok(gummy::normalize_language(code)),
+        };
+        self.source_lang = self
+            .source_lang
+            .as_ref()
+            .map(source)
+            .transpose()
+            .map_err(|e| e.to_string())?;
+        self.target_lang = self
+            .target_lang
+            .iter()
+            .map(target)
+            .collect::<Result<_, _>>()
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Checks what clap can't express.
+    fn validate(&self) -> Result<(), String> {
+        if self.saves_flac() {
+            if !cfg!(feature = "flac") {
+                return Err("saving FLAC needs st built with the flac feature".to_string());
+            }
+            if self.rotate_minutes.is_some() || self.rotate_mb.is_some() {
+                return Err("only WAV files can be rotated".to_string());
+            }
+        }
+        if self.subtitle_translation == Some(SubtitleTranslation::Track)
+            && !self
+                .transcripts()
+                .iter()
+                .any(|(path, format)| path.is_some() && format.is_subtitles())
+        {
+            return Err("translation tracks need subtitles written to `--output`".to_string());
+        }
+        if self.text_translation == TextTranslation::File
+            && !self
+                .transcripts()
+                .iter()
+                .any(|(path, format)| path.is_some() && *format == TranscriptFormat::Txt)
+        {
+            return Err("translation files need text written to `--output`".to_string());
+        }
+        if !self.track_template.contains("{lang}") {
+            return Err("`--track-template` needs `{lang}` to tell languages apart".to_string());
+        }
+        if self.output.iter().filter(|path| is_stdout(path)).count() > 1 {
+            return Err("stdout can only be given once to `--output`".to_string());
+        }
+        if self.tui && self.source == Source::Separate {
+            return Err("the dashboard shows a single source".to_string());
+        }
+        if (self.serve.is_some()
+            || self.broadcast_ws.is_some()
+            || self.caption_file.is_some()
+            || self.caption_file_translated.is_some())
+            && self.source == Source::Separate
+        {
+            return Err("live captions are shared for a single source only".to_string());
+        }
+        if self.emit.is_some() {
+            if self.source == Source::Separate {
+                return Err("events are emitted for a single source only".to_string());
+            }
+            if self.output.iter().any(|path| is_stdout(path)) {
+                return Err("stdout carries the events of `--emit`".to_string());
+            }
+        }
+        if matches!(self.action, Some(Action::Daemon { .. }))
+            && (self.source == Source::Separate || self.input.is_some() || self.tui)
+        {
+            return Err("the daemon captures a single source, headless".to_string());
+        }
+        if matches!(
+            self.action,
+            Some(Action::Replay { .. } | Action::Retranscribe { .. })
+        ) && self.tui
+        {
+            return Err("replays go to the transcripts, not the dashboard".to_string());
+        }
+        if self.clock != Clock::Task
+            && (self.source == Source::Separate
+                || matches!(
+                    self.action,
+                    Some(Action::Replay { .. } | Action::Retranscribe { .. })
+                ))
+        {
+            return Err(
+                "only the times of a single source captured now can be put on another clock"
+                    .to_string(),
+            );
+        }
+        if self.clock == Clock::Audio
+            && (self.save_audio.is_none() && self.session_dir.is_none()
+                || matches!(self.action, Some(Action::Daemon { .. })))
+        {
+            return Err("audio times need the audio saved with `--save-audio`".to_string());
+        }
+        if self.session_dir.is_some() && self.source == Source::Separate {
+            return Err("a session directory holds a single source".to_string());
+        }
+        if self.latency_report && self.source == Source::Separate {
+            return Err("latency is measured for a single source only".to_string());
+        }
+        if (self.hotkey_pause.is_some()
+            || self.hotkey_marker.is_some()
+            || self.hotkey_stop.is_some())
+            && !cfg!(feature = "hotkeys")
+        {
+            return Err("hotkeys need st built with the hotkeys feature".to_string());
+        }
+        if self.notify && !cfg!(feature = "notify") {
+            return Err("notifications need st built with the notify feature".to_string());
+        }
+        if self
+            .cleanup
+            .as_ref()
+            .is_some_and(|cleanup| cleanup.chinese.is_some())
+            && !cfg!(feature = "chinese")
+        {
+            return Err("converting Chinese needs st built with the chinese feature".to_string());
+        }
+        if self.backend == Backend::Whisper {
+            if !cfg!(feature = "whisper") {
+                return Err(
+                    "the whisper backend needs st built with the whisper feature".to_string(),
+                );
+            }
+            if self.model_path.is_none() {
+                return Err("the whisper backend needs `--model-path`".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Where transcripts are written, with their format: each `output`, or stdout without
+    /// any unless it carries the events of `emit`. Stdout is `None`.
+    pub fn transcripts(&self) -> Vec<(Option<&Path>, TranscriptFormat)> {
+        if self.output.is_empty() && self.emit.is_none() {
+            return vec![(None, self.transcript_format(None))];
+        }
+        self.output
+            .iter()
+            .map(|path| {
+                let path = Some(path.as_path()).filter(|path| !is_stdout(path));
+                (path, self.transcript_format(path))
+            })
+            .collect()
+    }
+
+    /// `format`, or the format `output` is named after.
+    pub fn transcript_format(&self, output: Option<&Path>) -> TranscriptFormat {
+        self.format
+            .or_else(|| {
+                let extension = output?.extension()?.to_str()?;
+                TranscriptFormat::from_str(extension, true).ok()
+            })
+            .unwrap_or(TranscriptFormat::Txt)
+    }
+
+    pub fn subtitle_options(&self) -> SubtitleOptions {
+        SubtitleOptions {
+            translation: self.subtitle_translation == Some(SubtitleTranslation::Inline),
+            max_cue_ms: self.max_cue_ms as u64,
+            silences: self.silence_cues,
+        }
+    }
+
+    /// Shortest silence marked in transcripts, or `None` to mark none.
+    pub fn min_silence_ms(&self) -> Option<u64> {
+        Some(self.silence_marker_ms as u64).filter(|&ms| ms > 0)
+    }
+
+    pub fn text_options(&self) -> TextOptions {
+        TextOptions {
+            paragraph_gap_ms: self.paragraph_gap_ms,
+            max_paragraph_chars: MAX_PARAGRAPH_CHARS,
+            timestamps: self.timestamps,
+            translation: self.text_translation == TextTranslation::Interleaved,
+        }
+    }
+
+    /// Whether transcripts in `format` have their translations in a file per language.
+    pub fn translation_files(&self, format: TranscriptFormat) -> bool {
+        match format {
+            TranscriptFormat::Txt => self.text_translation == TextTranslation::File,
+            TranscriptFormat::Srt | TranscriptFormat::Vtt => {
+                self.subtitle_translation == Some(SubtitleTranslation::Track)
+            }
+            TranscriptFormat::Json | TranscriptFormat::Jsonl => false,
+        }
+    }
+
+    /// Whether `save_audio` names a FLAC file.
+    pub fn saves_flac(&self) -> bool {
+        self.save_audio.as_ref().is_some_and(|path| {
+            Path::new(path)
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("flac"))
+        })
+    }
+
+    /// Least time between two updates shown of a sentence being recognized.
+    pub fn partial_interval(&self) -> Duration {
+        Duration::from_millis(self.partial_interval_ms)
+    }
+}
+
+/// Whether `path` given to `--output` stands for stdout.
+pub fn is_stdout(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, clap::Error> {
+        Args::try_parse_from(["st"].iter().chain(args))
+    }
+
+    #[test]
+    fn command_is_well_formed() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn parses_languages_and_output() {
+        let args = parse(&[
+            "--source-lang",
+            "en",
+            "--target-lang",
+            "zh",
+            "--target-lang",
+            "ja",
+            "--output",
+            "talk.txt",
+            "--format",
+            "srt",
+            "--gain",
+            "-6",
+            "--preroll",
+            "1.5",
+            "--input",
+            "talk.wav",
+            "--speed",
+            "4",
+        ])
+        .unwrap();
+        assert_eq!(args.source_lang.as_deref(), Some("en"));
+        assert_eq!(args.target_lang, ["zh", "ja"]);
+        assert_eq!(args.output, [PathBuf::from("talk.txt")]);
+        assert_eq!(
+            args.transcripts(),
+            [(Some(Path::new("talk.txt")), TranscriptFormat::Srt)]
+        );
+        assert_eq!(args.gain_db, -6.0);
+        assert_eq!(args.preroll_ms, Some(1500));
+        assert_eq!(args.speed, 4.0);
+        assert_eq!(args.duration, None);
+        assert_eq!(args.source, Source::System);
+        assert_eq!(
+            args.subtitle_options(),
+            SubtitleOptions {
+                translation: false,
+                max_cue_ms: 7_000,
+                silences: false,
+            }
+        );
+
+        let args = parse(&[
+            "-o",
+            "talk.txt",
+            "--paragraph-gap-ms",
+            "1500",
+            "--timestamps",
+            "paragraph",
+            "--text-translation",
+            "file",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.text_options(),
+            TextOptions {
+                paragraph_gap_ms: 1_500,
+                max_paragraph_chars: MAX_PARAGRAPH_CHARS,
+                timestamps: Timestamps::Paragraph,
+                translation: false,
+            }
+        );
+        assert!(args.translation_files(TranscriptFormat::Txt));
+        assert!(args.validate().is_ok());
+        let args = parse(&[
+            "-o",
+            "talk.txt",
+            "--track-template",
+            "{stem}-translated.{ext}",
+        ]);
+        assert!(args.unwrap().validate().is_err());
+        assert_eq!(parse(&[]).unwrap().text_options(), TextOptions::default());
+
+        let args = parse(&[
+            "-o",
+            "talk.vtt",
+            "--subtitle-translation",
+            "--max-cue",
+            "4.5",
+            "--silence-cues",
+            "--silence-marker",
+            "0",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.subtitle_options(),
+            SubtitleOptions {
+                translation: true,
+                max_cue_ms: 4_500,
+                silences: true,
+            }
+        );
+        assert_eq!(args.min_silence_ms(), None);
+        assert_eq!(parse(&[]).unwrap().min_silence_ms(), Some(30_000));
+    }
+
+    #[test]
+    fn takes_the_transcript_format_from_the_output() {
+        for (args, format) in [
+            (&["-o", "talk.SRT"][..], TranscriptFormat::Srt),
+            (&["-o", "talk.vtt"], TranscriptFormat::Vtt),
+            (&["-o", "talk.json"], TranscriptFormat::Json),
+            (&["-o", "talk.jsonl"], TranscriptFormat::Jsonl),
+            (&["-o", "talk.log"], TranscriptFormat::Txt),
+            (&[], TranscriptFormat::Txt),
+        ] {
+            let args = parse(args).unwrap();
+            assert_eq!(args.transcripts()[0].1, format, "{:?}", args.output);
+        }
+    }
+
+    #[test]
+    fn writes_a_transcript_per_output() {
+        let args = parse(&["-o", "-", "-o", "talk.srt", "--output", "talk.jsonl"]).unwrap();
+        assert_eq!(
+            args.transcripts(),
+            [
+                (None, TranscriptFormat::Txt),
+                (Some(Path::new("talk.srt")), TranscriptFormat::Srt),
+                (Some(Path::new("talk.jsonl")), TranscriptFormat::Jsonl),
+            ]
+        );
+        assert!(args.validate().is_ok());
+        let args = parse(&[
+            "-o",
+            "talk.srt",
+            "-o",
+            "talk.txt",
+            "--subtitle-translation",
+            "track",
+        ]);
+        assert!(args.unwrap().validate().is_ok());
+    }
+
+    #[test]
+    fn normalizes_languages_for_the_backend() {
+        let mut args = parse(&["--source-lang", "en-US", "--target-lang", "ZH-cn"]).unwrap();
+        args.normalize_languages().unwrap();
+        assert_eq!(args.source_lang.as_deref(), Some("en"));
+        assert_eq!(args.target_lang, ["zh"]);
+
+        let mut args = parse(&["--target-lang", "pt"]).unwrap();
+        let error = args.normalize_languages().unwrap_err();
+        assert!(error.contains("\"pt\" as a target language"), "{}", error);
+        let mut args = parse(&["--backend", "whisper", "--source-lang", "pt-BR"]).unwrap();
+        args.normalize_languages().unwrap();
+        assert_eq!(args.source_lang.as_deref(), Some("pt"));
+    }
+
+    #[test]
+    fn parses_durations() {
+        for (value, seconds) in [
+            ("90", 90),
+            ("120s", 120),
+            ("55m", 3300),
+            ("1h30m", 5400),
+            ("1.5h", 5400),
+        ] {
+            let args = parse(&["--duration", value]).unwrap();
+            assert_eq!(
+                args.duration,
+                Some(Duration::from_secs(seconds)),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn parses_stretches_of_time() {
+        let range = |value: &str| match parse(&["retranscribe", "dir", "--range", value]) {
+            Ok(Args {
+                action: Some(Action::Retranscribe { range, .. }),
+                ..
+            }) => range,
+            other => panic!("{}: {:?}", value, other),
+        };
+        assert_eq!(range("00:12:03-00:12:20"), Some(723_000..740_000));
+        assert_eq!(range("12:03-12:20.5"), Some(723_000..740_500));
+        assert_eq!(range("5-9"), Some(5000..9000));
+        for value in ["12:20-12:03", "5", "1:2:3:4-5", "a-b"] {
+            assert!(
+                parse(&["retranscribe", "dir", "--range", value]).is_err(),
+                "{}",
+                value
+            );
+        }
+        // A sentence or a stretch, not both.
+        assert!(parse(&["retranscribe", "dir"]).is_err());
+        assert!(parse(&["retranscribe", "dir", "--sentence", "3", "--range", "5-9"]).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        for args in [
+            &["--source", "speakers"][..],
+            &["--format", "doc"],
+            &["--sample-rate", "fast"],
+            &["--rotate-minutes", "0", "--save-audio", "a.wav"],
+            &["--rotate-mb", "5"],
+            &["--api-key", "k", "--api-key-file", "key.txt"],
+            &["--tui", "--meter"],
+            &["--preroll", "-1"],
+            &["--speed", "2"],
+            &["--input", "talk.wav", "--speed", "-1"],
+            &["--catch-up-speed", "1"],
+            &["--duration", "0"],
+            &["--duration", "5d"],
+            &["--stop-after-silence", "m"],
+            &["--serve", "7979"],
+            &["--emit", "ndjson", "--tui"],
+            &["--emit", "xml"],
+        ] {
+            let error = parse(args).expect_err("accepted invalid arguments");
+            // Usage errors exit with status 2.
+            assert_eq!(error.exit_code(), 2, "{:?}", args);
+        }
+        for args in [
+            &["--save-audio", "a.flac", "--rotate-mb", "5"][..],
+            &["--subtitle-translation", "track"],
+            &["-o", "talk.txt", "--subtitle-translation", "track"],
+            &[
+                "-o",
+                "-",
+                "--format",
+                "srt",
+                "--subtitle-translation",
+                "track",
+            ],
+            &["-o", "-", "-o", "talk.txt", "-o", "-"],
+            &["--text-translation", "file"],
+            &["-o", "talk.srt", "--text-translation", "file"],
+            &["--tui", "--source", "separate"],
+            &["--serve", "127.0.0.1:7979", "--source", "separate"],
+            &["--broadcast-ws", "127.0.0.1:7980", "--source", "separate"],
+            &["--caption-file", "captions.txt", "--source", "separate"],
+            &["--emit", "ndjson", "--source", "separate"],
+            &["--emit", "ndjson", "-o", "-"],
+            &["--clock", "wall", "--source", "separate"],
+            &["--clock", "audio"],
+            &["--tui", "replay", "events.jsonl"],
+            &["--clock", "wall", "replay", "events.jsonl"],
+            // Without the feature or a model.
+            &["--backend", "whisper"],
+        ] {
+            assert!(parse(args).unwrap().validate().is_err(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn fills_in_options_from_the_config_file() {
+        let (file, _) = config::parse(
+            r#"
+            backend = "openai"
+            api_key_file = "/etc/st/key"
+            device = "USB"
+            target_lang = ["ja"]
+            gain = -3.0
+            format = "vtt"
+            "#,
+        )
+        .unwrap();
+        let matches = Args::command()
+            .try_get_matches_from(["st", "--device", "2", "--gain", "6", "-o", "a.srt"])
+            .unwrap();
+        let cli = Settings::from_matches(&matches);
+        // Only what was given on the command line.
+        assert_eq!(
+            cli,
+            Settings {
+                device: Some("2".to_string()),
+                gain_db: Some(6.0),
+                output: Some(vec![PathBuf::from("a.srt")]),
+                ..Settings::default()
+            }
+        );
+
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        args.apply(config::layer(cli, |_| None, file));
+        assert_eq!(args.backend, Backend::Openai);
+        assert_eq!(args.api_key_file, Some(PathBuf::from("/etc/st/key")));
+        assert_eq!(args.device.as_deref(), Some("2"));
+        assert_eq!(args.target_lang, ["ja"]);
+        assert_eq!(args.gain_db, 6.0);
+        assert_eq!(args.output, [PathBuf::from("a.srt")]);
+        // The format in the file wins over the extension of `--output`.
+        assert_eq!(args.transcripts()[0].1, TranscriptFormat::Vtt);
+        assert_eq!(args.source, Source::System);
+        assert!(args.validate().is_ok());
+
+        let matches = Args::command()
+            .try_get_matches_from([
+                "st",
+                "--backend",
+                "gummy",
+                "--api-key",
+                "k",
+                "--url",
+                "ws://localhost",
+                "--source-lang",
+                "en",
+                "--target-lang",
+                "zh",
+                "--source",
+                "mic",
+                "--sample-rate",
+                "16000",
+                "--format",
+                "json",
+                "--model-path",
+                "model.bin",
+            ])
+            .unwrap();
+        let cli = Settings::from_matches(&matches);
+        assert_eq!(
+            cli,
+            Settings {
+                backend: Some(Backend::Gummy),
+                api_key: Some(ApiKey::Value("k".to_string())),
+                url: Some("ws://localhost".to_string()),
+                source_lang: Some("en".to_string()),
+                target_lang: Some(vec!["zh".to_string()]),
+                source: Some(Source::Mic),
+                sample_rate: Some(16000),
+                format: Some(TranscriptFormat::Json),
+                model_path: Some("model.bin".to_string()),
+                ..Settings::default()
+            }
+        );
+    }
+}
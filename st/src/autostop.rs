@@ -0,0 +1,130 @@
+//! Ending a session on its own, after `--duration` or `--stop-after-silence`.
+
+use crate::args::Args;
+use audio::level::Level;
+use audio::vad::VadConfig;
+use std::fmt;
+use std::future::pending;
+use std::time::Duration;
+use tokio::time::{Instant, sleep_until};
+
+/// Why a session stopped on its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reason {
+    Duration(Duration),
+    Silence(Duration),
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::Duration(duration) => write!(f, "Ran for {} s", duration.as_secs()),
+            Reason::Silence(duration) => {
+                write!(f, "No speech heard for {} s", duration.as_secs())
+            }
+        }
+    }
+}
+
+/// Deadlines of a session on the monotonic clock, starting when it's created.
+///
+/// Frames count as speech when they are as loud as the recorder's voice activity detection
+/// requires, and push the silence deadline back.
+pub struct AutoStop {
+    duration: Option<(Duration, Instant)>,
+    silence: Option<Duration>,
+    last_speech: Instant,
+    threshold_dbfs: f32,
+}
+
+impl AutoStop {
+    pub fn new(duration: Option<Duration>, silence: Option<Duration>) -> Self {
+        let now = Instant::now();
+        AutoStop {
+            duration: duration.map(|duration| (duration, now + duration)),
+            silence,
+            last_speech: now,
+            threshold_dbfs: VadConfig::default().threshold_dbfs,
+        }
+    }
+
+    pub fn from_args(args: &Args) -> Self {
+        AutoStop::new(args.duration, args.stop_after_silence)
+    }
+
+    /// Takes in a frame sent for transcription.
+    pub fn hear(&mut self, samples: &[i16]) {
+        if Level::of(samples).rms_dbfs >= self.threshold_dbfs {
+            self.last_speech = Instant::now();
+        }
+    }
+
+    /// Waits for the first deadline to pass, forever if there is none.
+    pub async fn wait(&self) -> Reason {
+        let silence = self
+            .silence
+            .map(|silence| (Reason::Silence(silence), self.last_speech + silence));
+        let duration = self
+            .duration
+            .map(|(duration, deadline)| (Reason::Duration(duration), deadline));
+        match silence
+            .into_iter()
+            .chain(duration)
+            .min_by_key(|(_, at)| *at)
+        {
+            Some((reason, at)) => {
+                sleep_until(at).await;
+                reason
+            }
+            None => pending().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{advance, timeout};
+
+    const SPEECH: [i16; 1600] = [8000; 1600];
+    const SILENCE: [i16; 1600] = [0; 1600];
+
+    async fn stopped(auto_stop: &AutoStop) -> Option<Reason> {
+        timeout(Duration::ZERO, auto_stop.wait()).await.ok()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_after_silence_unless_speech_is_heard() {
+        let mut auto_stop =
+            AutoStop::new(Some(Duration::from_secs(60)), Some(Duration::from_secs(10)));
+        advance(Duration::from_secs(8)).await;
+        auto_stop.hear(&SPEECH);
+        advance(Duration::from_secs(8)).await;
+        auto_stop.hear(&SILENCE);
+        assert_eq!(stopped(&auto_stop).await, None);
+        advance(Duration::from_secs(2)).await;
+        assert_eq!(
+            stopped(&auto_stop).await,
+            Some(Reason::Silence(Duration::from_secs(10)))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_after_the_duration_whatever_is_heard() {
+        let mut auto_stop = AutoStop::new(Some(Duration::from_secs(60)), None);
+        for _ in 0..59 {
+            advance(Duration::from_secs(1)).await;
+            auto_stop.hear(&SPEECH);
+        }
+        assert_eq!(stopped(&auto_stop).await, None);
+        advance(Duration::from_secs(1)).await;
+        assert_eq!(
+            stopped(&auto_stop).await,
+            Some(Reason::Duration(Duration::from_secs(60)))
+        );
+
+        let auto_stop = AutoStop::new(None, None);
+        advance(Duration::from_secs(3600)).await;
+        assert_eq!(stopped(&auto_stop).await, None);
+    }
+}
@@ -0,0 +1,282 @@
+//! `--caption-file`: the current captions in a text file, for text sources that read one,
+//! like OBS's.
+
+use crate::args::Args;
+use crate::console::DisplayMode;
+use crate::diff::TranscriptDiff;
+use gummy::{Transcription, TranscriptionEvent};
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::future::pending;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time::{Instant, sleep_until};
+
+/// Least time between two writes of the files.
+const MIN_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Keeps the last final sentences and the partial one in a file, their text or what
+/// `DisplayMode` picks of it, and their translations in another.
+///
+/// Files are replaced as a whole, so readers never see one half-written. Changes are
+/// written right away unless the files were written less than `MIN_INTERVAL` ago; they're
+/// then written once `due` returns.
+pub struct CaptionFile {
+    text: Option<PathBuf>,
+    translation: Option<PathBuf>,
+    lines: usize,
+    sentences: VecDeque<Transcription>,
+    seen: HashSet<u64>,
+    partial: Option<Transcription>,
+    written: Option<Instant>,
+    changed: bool,
+    diff: TranscriptDiff,
+    display: DisplayMode,
+}
+
+impl CaptionFile {
+    /// Writes the last `lines` final sentences to `text` and their translations to
+    /// `translation`, and the partial one no sooner than `partial_interval` after it last
+    /// changed in them.
+    pub fn new(
+        text: Option<PathBuf>,
+        translation: Option<PathBuf>,
+        lines: usize,
+        partial_interval: Duration,
+    ) -> Self {
+        CaptionFile {
+            text,
+            translation,
+            lines,
+            sentences: VecDeque::new(),
+            seen: HashSet::new(),
+            partial: None,
+            written: None,
+            changed: false,
+            diff: TranscriptDiff::new(partial_interval),
+            display: DisplayMode::Source,
+        }
+    }
+
+    /// Writes the text of sentences `display` picks to `text`.
+    pub fn with_display(mut self, display: DisplayMode) -> Self {
+        self.display = display;
+        self
+    }
+
+    pub fn from_args(args: &Args) -> Option<Self> {
+        (args.caption_file.is_some() || args.caption_file_translated.is_some()).then(|| {
+            let file = CaptionFile::new(
+                args.caption_file.clone(),
+                args.caption_file_translated.clone(),
+                args.caption_lines,
+                args.partial_interval(),
+            );
+            match args.display {
+                Some(display) => file.with_display(display),
+                None => file,
+            }
+        })
+    }
+
+    pub fn update(&mut self, event: &TranscriptionEvent) -> io::Result<()> {
+        if !self.diff.admit(event, Instant::now().into_std()) {
+            return Ok(());
+        }
+        match event {
+            TranscriptionEvent::Partial(t) => self.partial = Some(t.clone()),
+            TranscriptionEvent::Final(t) => self.add(t),
+            TranscriptionEvent::Finished => return Ok(()),
+        }
+        self.changed = true;
+        match self.written {
+            Some(written) if written.elapsed() < MIN_INTERVAL => Ok(()),
+            _ => self.write(),
+        }
+    }
+
+    /// Waits until changes held back can be written, forever if there are none.
+    pub async fn due(&self) {
+        match (self.changed, self.written) {
+            (true, Some(written)) => sleep_until(written + MIN_INTERVAL).await,
+            (true, None) => {}
+            (false, _) => pending().await,
+        }
+    }
+
+    pub fn write(&mut self) -> io::Result<()> {
+        if let Some(path) = &self.text {
+            let display = self.display;
+            replace(path, &self.render(|t| display.lines(t)))?;
+        }
+        if let Some(path) = &self.translation {
+            replace(path, &self.render(|t| t.translated_text.as_deref()))?;
+        }
+        self.written = Some(Instant::now());
+        self.changed = false;
+        Ok(())
+    }
+
+    /// Writes the final sentences of the session result, without the partial one, which
+    /// won't be finalized anymore.
+    pub fn finish(mut self, result: &[Transcription]) -> io::Result<()> {
+        for transcription in result {
+            self.add(transcription);
+        }
+        self.partial = None;
+        self.write()
+    }
+
+    fn add(&mut self, transcription: &Transcription) {
+        if self
+            .partial
+            .as_ref()
+            .is_some_and(|partial| partial.sentence_id == transcription.sentence_id)
+        {
+            self.partial = None;
+        }
+        if !self.seen.insert(transcription.sentence_id) {
+            return;
+        }
+        if self.sentences.len() == self.lines {
+            self.sentences.pop_front();
+        }
+        if self.lines > 0 {
+            self.sentences.push_back(transcription.clone());
+        }
+    }
+
+    fn render<'a, L>(&'a self, lines: impl Fn(&'a Transcription) -> L) -> String
+    where
+        L: IntoIterator,
+        L::Item: ToString,
+    {
+        self.sentences
+            .iter()
+            .chain(&self.partial)
+            .flat_map(lines)
+            .map(|line| line.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Replaces the file at `path` with `content` by renaming a file written next to it.
+fn replace(path: &Path, content: &str) -> io::Result<()> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    let temp = path.with_file_name(name);
+    fs::write(&temp, content)?;
+    fs::rename(&temp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{advance, timeout};
+
+    fn sentence(sentence_id: u64, text: &str) -> Transcription {
+        Transcription {
+            sentence_id,
+            begin_time: 0,
+            end_time: 1000,
+            text: text.to_string(),
+            is_final: true,
+            translated_text: Some(text.to_uppercase()),
+            translations: vec![],
+            words: vec![],
+            confidence: None,
+            source_label: None,
+        }
+    }
+
+    fn paths(name: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("st-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        (dir.join("captions.txt"), dir.join("translated.txt"))
+    }
+
+    async fn is_due(file: &CaptionFile) -> bool {
+        timeout(Duration::ZERO, file.due()).await.is_ok()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keeps_the_last_lines_and_the_partial_sentence() {
+        let (text, translation) = paths("caption-lines");
+        let mut file = CaptionFile::new(
+            Some(text.clone()),
+            Some(translation.clone()),
+            2,
+            Duration::ZERO,
+        );
+        for (sentence_id, words) in ["one.", "two.", "three."].into_iter().enumerate() {
+            let sentence_id = sentence_id as u64;
+            file.update(&TranscriptionEvent::Partial(sentence(sentence_id, "...")))
+                .unwrap();
+            file.update(&TranscriptionEvent::Final(sentence(sentence_id, words)))
+                .unwrap();
+            advance(MIN_INTERVAL).await;
+        }
+        file.update(&TranscriptionEvent::Partial(sentence(3, "fo")))
+            .unwrap();
+        assert_eq!(fs::read_to_string(&text).unwrap(), "two.\nthree.\nfo");
+        assert_eq!(
+            fs::read_to_string(&translation).unwrap(),
+            "TWO.\nTHREE.\nFO"
+        );
+        // No temporary file is left behind.
+        assert_eq!(fs::read_dir(text.parent().unwrap()).unwrap().count(), 2);
+
+        // The partial sentence is dropped, and sentences aren't repeated.
+        file.finish(&[sentence(2, "three."), sentence(3, "four.")])
+            .unwrap();
+        assert_eq!(fs::read_to_string(&text).unwrap(), "three.\nfour.");
+        fs::remove_dir_all(text.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn writes_the_text_the_display_picks() {
+        let (text, _) = paths("caption-display");
+        let mut file = CaptionFile::new(Some(text.clone()), None, 2, Duration::ZERO)
+            .with_display(DisplayMode::Translation);
+        file.update(&TranscriptionEvent::Final(sentence(0, "one.")))
+            .unwrap();
+        advance(MIN_INTERVAL).await;
+        let mut untranslated = sentence(1, "two.");
+        untranslated.translated_text = None;
+        file.update(&TranscriptionEvent::Final(untranslated))
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&text).unwrap(),
+            format!("ONE.\n{}two.", crate::console::UNTRANSLATED)
+        );
+        fs::remove_dir_all(text.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn writes_at_most_every_interval() {
+        let (text, _) = paths("caption-throttle");
+        let mut file = CaptionFile::new(Some(text.clone()), None, 2, Duration::ZERO);
+        assert!(!is_due(&file).await);
+        file.update(&TranscriptionEvent::Partial(sentence(0, "He")))
+            .unwrap();
+        assert_eq!(fs::read_to_string(&text).unwrap(), "He");
+        assert!(!is_due(&file).await);
+
+        advance(MIN_INTERVAL / 2).await;
+        file.update(&TranscriptionEvent::Partial(sentence(0, "Hell")))
+            .unwrap();
+        file.update(&TranscriptionEvent::Partial(sentence(0, "Hello")))
+            .unwrap();
+        assert_eq!(fs::read_to_string(&text).unwrap(), "He");
+        assert!(!is_due(&file).await);
+
+        advance(MIN_INTERVAL / 2).await;
+        assert!(is_due(&file).await);
+        file.write().unwrap();
+        assert_eq!(fs::read_to_string(&text).unwrap(), "Hello");
+        assert!(!is_due(&file).await);
+        fs::remove_dir_all(text.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+//! Paces the audio held while reconnecting as it's sent to the new task. Sent at once, a
+//! backlog of many seconds gets the task failed for taking audio faster than the service
+//! allows, so it goes out at `--catch-up-speed` times real time, with the audio captured
+//! meanwhile queued behind it, until the task is caught up with the capture.
+
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Most audio sent at once, as much as plays in this long, which the service takes.
+const BURST: Duration = Duration::from_secs(1);
+/// Least audio waited for between sends, as much as plays in this long.
+const STEP: Duration = Duration::from_millis(100);
+
+/// How much audio may be sent to a task catching up, at `speed` times real time after a
+/// burst of `BURST`.
+pub struct Pacer {
+    speed: f64,
+    bytes_per_second: f64,
+    // Bytes that may be sent, as of `at`.
+    allowance: f64,
+    at: Instant,
+}
+
+impl Pacer {
+    /// Paces audio of `bytes_per_second` at `speed` times real time, from `now` on.
+    pub fn new(speed: f64, bytes_per_second: u64, now: Instant) -> Self {
+        let bytes_per_second = bytes_per_second as f64;
+        Pacer {
+            speed,
+            bytes_per_second,
+            allowance: BURST.as_secs_f64() * bytes_per_second,
+            at: now,
+        }
+    }
+
+    /// Bytes that may be sent at `now`, whole samples of 16-bit audio.
+    pub fn allowed(&mut self, now: Instant) -> usize {
+        let rate = self.speed * self.bytes_per_second;
+        let burst = BURST.as_secs_f64() * self.bytes_per_second;
+        let elapsed = now.saturating_duration_since(self.at).as_secs_f64();
+        self.allowance = (self.allowance + elapsed * rate).min(burst);
+        self.at = now;
+        self.allowance as usize / 2 * 2
+    }
+
+    /// Takes `bytes` sent out of the allowance.
+    pub fn spent(&mut self, bytes: usize) {
+        self.allowance -= bytes as f64;
+    }
+
+    /// When another `STEP` of audio may be sent.
+    pub fn next_at(&self) -> Instant {
+        let step = STEP.as_secs_f64() * self.bytes_per_second;
+        let wait = (step - self.allowance).max(0.0) / (self.speed * self.bytes_per_second);
+        self.at + Duration::from_secs_f64(wait)
+    }
+
+    /// How long until `unsent` bytes are sent, with audio coming in at real time meanwhile.
+    pub fn remaining(&self, unsent: usize) -> Duration {
+        let catching_up = (self.speed - 1.0) * self.bytes_per_second;
+        Duration::from_secs_f64(unsent as f64 / catching_up)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A second of 16-bit audio at 16 kHz.
+    const SECOND: usize = 32000;
+
+    /// Sends a backlog of `backlog` bytes with 100 ms frames captured meanwhile, each sent
+    /// as much as the pacer allows as it comes in. Returns the bytes sent by each frame, and
+    /// when the backlog was caught up with.
+    fn catch_up(speed: f64, backlog: usize) -> (Vec<usize>, Duration) {
+        let start = Instant::now();
+        let mut pacer = Pacer::new(speed, SECOND as u64, start);
+        let mut unsent = backlog;
+        let mut sent = vec![];
+        for frame in 0.. {
+            let now = start + STEP * frame;
+            if frame > 0 {
+                unsent += SECOND / 10;
+            }
+            let bytes = pacer.allowed(now).min(unsent);
+            pacer.spent(bytes);
+            unsent -= bytes;
+            sent.push(bytes);
+            if unsent == 0 {
+                return (sent, now - start);
+            }
+        }
+        unreachable!()
+    }
+
+    #[test]
+    fn catches_up_at_the_speed_after_a_burst() {
+        // 10 s held, sent at 3 times real time: a second at once, then 300 ms for each
+        // 100 ms frame, catching up on 200 ms of the backlog each time.
+        let (sent, caught_up) = catch_up(3.0, 10 * SECOND);
+        assert_eq!(sent[0], SECOND);
+        assert!(sent[1..].iter().all(|&bytes| bytes == 3 * SECOND / 10));
+        assert_eq!(caught_up, Duration::from_millis(4500));
+
+        // Never faster than the speed allows, past the burst.
+        let mut total = 0;
+        for (frame, bytes) in catch_up(1.5, 20 * SECOND).0.into_iter().enumerate() {
+            total += bytes;
+            let elapsed = (STEP * frame as u32).as_secs_f64();
+            assert!(total as f64 <= SECOND as f64 * (1.0 + 1.5 * elapsed) + 1.0);
+        }
+    }
+
+    #[test]
+    fn sends_a_short_backlog_at_once() {
+        let (sent, caught_up) = catch_up(3.0, SECOND / 2);
+        assert_eq!(sent, [SECOND / 2]);
+        assert_eq!(caught_up, Duration::ZERO);
+    }
+
+    #[test]
+    fn tells_when_to_send_and_how_long_catching_up_takes() {
+        let start = Instant::now();
+        let mut pacer = Pacer::new(2.0, SECOND as u64, start);
+        assert_eq!(pacer.next_at(), start);
+        let bytes = pacer.allowed(start);
+        pacer.spent(bytes);
+        // 100 ms of audio may be sent 50 ms later, at twice real time.
+        assert_eq!(pacer.next_at(), start + Duration::from_millis(50));
+        assert_eq!(
+            pacer.allowed(start + Duration::from_millis(25)),
+            SECOND / 20
+        );
+        // Gaining a second on the capture every second.
+        assert_eq!(pacer.remaining(3 * SECOND), Duration::from_secs(3));
+    }
+}
@@ -0,0 +1,525 @@
+//! `cleanup` in the config file: the text of sentences tidied up for transcripts and
+//! captions, with filler words taken out, repeated punctuation collapsed, whitespace
+//! trimmed and Chinese converted between scripts. Only the copies fed to the sinks are
+//! cleaned up; the session result and the events of a session directory keep the text as
+//! it was recognized.
+
+use gummy::{Transcription, TranscriptionEvent, Word};
+#[cfg(feature = "chinese")]
+use log::warn;
+use std::collections::BTreeMap;
+
+/// Punctuation a filler word takes along when it's taken out: the pause after it, or
+/// before it at the end of a sentence.
+const PAUSES: [char; 5] = [',', '，', '、', ';', '；'];
+/// Punctuation that ends a sentence.
+const ENDS: [char; 6] = ['.', '。', '?', '？', '!', '！'];
+/// Punctuation of CJK text, which `char::is_ascii_punctuation` leaves out.
+const CJK_PUNCTUATION: [char; 16] = [
+    '，', '。', '、', '？', '！', '；', '：', '…', '“', '”', '‘', '’', '（', '）', '《', '》',
+];
+
+/// The script Chinese text is converted to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChineseScript {
+    Simplified,
+    Traditional,
+}
+
+impl ChineseScript {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "simplified" => Some(ChineseScript::Simplified),
+            "traditional" => Some(ChineseScript::Traditional),
+            _ => None,
+        }
+    }
+}
+
+/// How the text of sentences is cleaned up. The default leaves it as it is.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Cleanup {
+    /// Filler words taken out where they stand on their own, by the language of the text
+    /// they're taken out of. Words are matched regardless of ASCII case.
+    pub fillers: BTreeMap<String, Vec<String>>,
+    /// Whether runs of the same punctuation mark, like `??` or `。。`, are made one.
+    /// Ellipses are kept.
+    pub collapse_punctuation: bool,
+    /// Whether whitespace around the text is trimmed.
+    pub trim: bool,
+    /// The script Chinese text is converted to, with the `chinese` feature.
+    pub chinese: Option<ChineseScript>,
+}
+
+impl Cleanup {
+    /// `event` with the text of its sentence cleaned up, which is in `language`, or in a
+    /// language detected when `None`. Chinese is converted in final sentences only, which
+    /// come far less often than partial ones.
+    pub async fn event(
+        &self,
+        event: &TranscriptionEvent,
+        language: Option<&str>,
+    ) -> TranscriptionEvent {
+        match event {
+            TranscriptionEvent::Partial(t) => TranscriptionEvent::Partial(self.tidy(t, language)),
+            TranscriptionEvent::Final(t) => {
+                TranscriptionEvent::Final(self.transcription(t, language).await)
+            }
+            TranscriptionEvent::Finished => TranscriptionEvent::Finished,
+        }
+    }
+
+    /// `transcription` with its text, its words and its translations cleaned up.
+    pub async fn transcription(
+        &self,
+        transcription: &Transcription,
+        language: Option<&str>,
+    ) -> Transcription {
+        let mut cleaned = self.tidy(transcription, language);
+        if let Some(script) = self.chinese {
+            let mut texts = vec![];
+            if is_chinese(language, &cleaned.text) {
+                texts.push(&mut cleaned.text);
+                texts.extend(cleaned.words.iter_mut().map(|word| &mut word.text));
+            }
+            for translation in &mut cleaned.translations {
+                if is_chinese(Some(&translation.lang), &translation.text) {
+                    texts.push(&mut translation.text);
+                }
+            }
+            // The translation into the first target language, the first of the translations.
+            let translated_lang = transcription.translations.first().map(|t| t.lang.as_str());
+            if let Some(translated) = &mut cleaned.translated_text
+                && translated_lang.is_some_and(|lang| is_chinese(Some(lang), translated))
+            {
+                texts.push(translated);
+            }
+            convert_chinese(texts, script).await;
+        }
+        cleaned
+    }
+
+    /// `transcription` cleaned up but for converting Chinese.
+    fn tidy(&self, transcription: &Transcription, language: Option<&str>) -> Transcription {
+        let mut cleaned = transcription.clone();
+        let fillers = self.fillers(language);
+        cleaned.text = self.text(&cleaned.text, &fillers);
+        cleaned.words = cleaned
+            .words
+            .into_iter()
+            .filter(|word| !is_filler(&word.text, &fillers))
+            .map(|word| Word {
+                text: self.text(&word.text, &[]),
+                ..word
+            })
+            .collect();
+        for translation in &mut cleaned.translations {
+            let fillers = self.fillers(Some(&translation.lang));
+            translation.text = self.text(&translation.text, &fillers);
+        }
+        // The translation into the first target language, the first of the translations.
+        let translated_lang = transcription.translations.first().map(|t| t.lang.as_str());
+        if let Some(translated) = &cleaned.translated_text {
+            let fillers = self.fillers(translated_lang);
+            cleaned.translated_text = Some(self.text(translated, &fillers));
+        }
+        cleaned
+    }
+
+    /// The fillers of `language`, or of every language when it isn't known.
+    fn fillers(&self, language: Option<&str>) -> Vec<&str> {
+        let mut fillers: Vec<&str> = match language {
+            Some(language) => self
+                .fillers
+                .get(language)
+                .into_iter()
+                .flatten()
+                .map(String::as_str)
+                .collect(),
+            None => self
+                .fillers
+                .values()
+                .flatten()
+                .map(String::as_str)
+                .collect(),
+        };
+        // Longer first, so `um` doesn't take the start of `umm`.
+        fillers.sort_by_key(|filler| std::cmp::Reverse(filler.len()));
+        fillers
+    }
+
+    /// `text` cleaned up, taking out `fillers`. Chinese is converted apart, a sentence at a
+    /// time.
+    fn text(&self, text: &str, fillers: &[&str]) -> String {
+        let mut text = remove_fillers(text, fillers);
+        if self.collapse_punctuation {
+            text = collapse_punctuation(&text);
+        }
+        if self.trim {
+            text = text.trim().to_string();
+        }
+        text
+    }
+}
+
+/// Whether `text`, in `language` or in one detected when `None`, is Chinese. Detected
+/// text with kana is Japanese, whose kanji aren't converted.
+fn is_chinese(language: Option<&str>, text: &str) -> bool {
+    match language {
+        Some(language) => language == "zh" || language.starts_with("zh-"),
+        None => !text.chars().any(|c| matches!(c, '\u{3040}'..='\u{30ff}')),
+    }
+}
+
+fn is_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation() || CJK_PUNCTUATION.contains(&c)
+}
+
+/// The length of the filler `text` starts with, if it stands on its own there, up to
+/// whitespace, punctuation or the end.
+fn filler_at(text: &str, fillers: &[&str]) -> Option<usize> {
+    let stands_alone = |rest: &str| {
+        rest.chars()
+            .next()
+            .is_none_or(|c| c.is_whitespace() || is_punctuation(c))
+    };
+    fillers
+        .iter()
+        .find(|filler| {
+            text.get(..filler.len())
+                .is_some_and(|start| start.eq_ignore_ascii_case(filler))
+                && stands_alone(&text[filler.len()..])
+        })
+        .map(|filler| filler.len())
+}
+
+/// Whether `word` is one of `fillers`, punctuation aside.
+fn is_filler(word: &str, fillers: &[&str]) -> bool {
+    let word = word.trim().trim_end_matches(is_punctuation);
+    !word.is_empty() && filler_at(word, fillers) == Some(word.len())
+}
+
+/// `text` without the `fillers` standing on their own in it, each with the pauses around
+/// it.
+fn remove_fillers(text: &str, fillers: &[&str]) -> String {
+    if fillers.is_empty() {
+        return text.to_string();
+    }
+    let pause = |c: char| c.is_whitespace() || PAUSES.contains(&c);
+    let mut cleaned = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let after_break = cleaned
+            .chars()
+            .last()
+            .is_none_or(|last| last.is_whitespace() || is_punctuation(last));
+        if after_break && let Some(len) = filler_at(rest, fillers) {
+            let after = rest[len..].trim_start_matches(pause);
+            let kept = cleaned.trim_end_matches(pause).len();
+            // The pauses around it go, leaving a space between words that had one.
+            let spaced = cleaned[kept..].contains(char::is_whitespace)
+                || rest[len..rest.len() - after.len()].contains(char::is_whitespace);
+            cleaned.truncate(kept);
+            rest = after;
+            if spaced && !cleaned.is_empty() && !rest.is_empty() && !rest.starts_with(ENDS) {
+                cleaned.push(' ');
+            }
+            continue;
+        }
+        cleaned.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    cleaned
+}
+
+/// `text` with runs of the same punctuation mark made one. An ellipsis of three or more
+/// dots is written `...`, and `……` is kept.
+fn collapse_punctuation(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let mut run = 1;
+        while is_punctuation(c) && chars.next_if_eq(&c).is_some() {
+            run += 1;
+        }
+        let kept = match c {
+            '.' if run >= 3 => 3,
+            '…' => run.min(2),
+            _ => 1,
+        };
+        collapsed.extend(std::iter::repeat_n(c, kept));
+    }
+    collapsed
+}
+
+/// Converts `texts` to `script` in place, through the `opencc` command of OpenCC, run once
+/// for all of them on a blocking thread. Texts that can't be converted are kept as they
+/// are, with a warning the first time.
+#[cfg(feature = "chinese")]
+async fn convert_chinese(texts: Vec<&mut String>, script: ChineseScript) {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static WARNED: AtomicBool = AtomicBool::new(false);
+    // A line per text, which OpenCC converts line by line.
+    if texts.is_empty() || texts.iter().any(|text| text.contains('\n')) {
+        return;
+    }
+    let config = match script {
+        ChineseScript::Simplified => "t2s.json",
+        ChineseScript::Traditional => "s2t.json",
+    };
+    let input = texts
+        .iter()
+        .map(|text| text.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let output = tokio::task::spawn_blocking(move || opencc(config, input))
+        .await
+        .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+    let converted = match &output {
+        Ok(output) if output.status.success() => String::from_utf8(output.stdout.clone()).ok(),
+        _ => None,
+    };
+    let lines = converted
+        .as_deref()
+        .map(|converted| {
+            converted
+                .trim_end_matches('\n')
+                .split('\n')
+                .collect::<Vec<_>>()
+        })
+        .filter(|lines| lines.len() == texts.len());
+    match lines {
+        Some(lines) => {
+            for (text, line) in texts.into_iter().zip(lines) {
+                *text = line.to_string();
+            }
+        }
+        None => {
+            if !WARNED.swap(true, Ordering::Relaxed) {
+                let reason = match output {
+                    Ok(output) => output.status.to_string(),
+                    Err(e) => e.to_string(),
+                };
+                warn!(
+                    "Failed to convert Chinese text with opencc, keeping it: {}",
+                    reason
+                );
+            }
+        }
+    }
+}
+
+/// Runs `opencc` with `config` on `input`.
+#[cfg(feature = "chinese")]
+fn opencc(config: &str, input: String) -> std::io::Result<std::process::Output> {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let mut opencc = Command::new("opencc")
+        .args(["-c", config])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdin = opencc.stdin.take().unwrap();
+    // Written from a thread of its own while the output is read, as opencc stops reading
+    // once the pipe of its output is full.
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = opencc.wait_with_output()?;
+    writer
+        .join()
+        .unwrap_or_else(|_| Err(std::io::Error::other("writing to opencc panicked")))?;
+    Ok(output)
+}
+
+/// Converting Chinese needs the `chinese` feature, which `Args::validate` checks.
+#[cfg(not(feature = "chinese"))]
+async fn convert_chinese(_texts: Vec<&mut String>, _script: ChineseScript) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gummy::Translation;
+
+    fn cleanup(fillers: &[(&str, &[&str])]) -> Cleanup {
+        Cleanup {
+            fillers: fillers
+                .iter()
+                .map(|(lang, words)| {
+                    (
+                        lang.to_string(),
+                        words.iter().map(|word| word.to_string()).collect(),
+                    )
+                })
+                .collect(),
+            ..Cleanup::default()
+        }
+    }
+
+    fn text(cleanup: &Cleanup, language: &str, text: &str) -> String {
+        cleanup.text(text, &cleanup.fillers(Some(language)))
+    }
+
+    #[test]
+    fn takes_out_filler_words_standing_on_their_own() {
+        let cleanup = cleanup(&[("en", &["uh", "um", "umm"]), ("zh", &["嗯", "呃"])]);
+        for (language, before, after) in [
+            (
+                "en",
+                "Uh, I think we should, um, start.",
+                "I think we should start.",
+            ),
+            ("en", "So that's it, umm.", "So that's it."),
+            // Only where they stand on their own.
+            ("en", "The umbrella is humming.", "The umbrella is humming."),
+            ("en", "Um um, well.", "well."),
+            ("zh", "嗯，我觉得，呃，可以开始了。", "我觉得可以开始了。"),
+            ("zh", "好的，嗯。", "好的。"),
+            ("zh", "我嗯觉得", "我嗯觉得"),
+            // The fillers of one language aren't taken out of another.
+            ("zh", "uh，好的。", "uh，好的。"),
+        ] {
+            assert_eq!(text(&cleanup, language, before), after, "{}", before);
+        }
+    }
+
+    #[test]
+    fn collapses_repeated_punctuation() {
+        let cleanup = Cleanup {
+            collapse_punctuation: true,
+            ..Cleanup::default()
+        };
+        for (before, after) in [
+            ("Really??", "Really?"),
+            ("Wait,, what!!!", "Wait, what!"),
+            ("And then.....", "And then..."),
+            ("Well... okay.", "Well... okay."),
+            ("好的。。", "好的。"),
+            ("真的吗？？！！", "真的吗？！"),
+            ("然后……………", "然后……"),
+        ] {
+            assert_eq!(text(&cleanup, "en", before), after, "{}", before);
+        }
+    }
+
+    #[test]
+    fn trims_whitespace() {
+        let cleanup = Cleanup {
+            trim: true,
+            ..Cleanup::default()
+        };
+        assert_eq!(text(&cleanup, "en", "  Hello world. \n"), "Hello world.");
+        assert_eq!(text(&cleanup, "zh", "\u{3000}你好。 "), "你好。");
+        // Left alone by default.
+        assert_eq!(text(&Cleanup::default(), "en", " Hello. "), " Hello. ");
+    }
+
+    #[cfg(feature = "chinese")]
+    #[tokio::test]
+    async fn converts_chinese_between_scripts() {
+        // Converting needs OpenCC installed.
+        if std::process::Command::new("opencc")
+            .arg("--version")
+            .output()
+            .is_err()
+        {
+            eprintln!("opencc isn't installed, not converting Chinese");
+            return;
+        }
+        let cleanup = |script| Cleanup {
+            chinese: Some(script),
+            ..Cleanup::default()
+        };
+        let sentence = |text: &str, translation: &str| Transcription {
+            sentence_id: 0,
+            begin_time: 0,
+            end_time: 1_000,
+            text: text.to_string(),
+            is_final: true,
+            translated_text: None,
+            translations: vec![Translation {
+                lang: "zh".to_string(),
+                text: translation.to_string(),
+            }],
+            words: vec![],
+            confidence: None,
+            source_label: None,
+        };
+        let traditional = cleanup(ChineseScript::Traditional)
+            .transcription(&sentence("Simplified Chinese.", "简体中文。"), Some("en"))
+            .await;
+        assert_eq!(traditional.text, "Simplified Chinese.");
+        assert_eq!(traditional.translations[0].text, "簡體中文。");
+        let simplified = cleanup(ChineseScript::Simplified)
+            .transcription(&sentence("繁體中文", ""), None)
+            .await;
+        assert_eq!(simplified.text, "繁体中文");
+        // Japanese keeps its kanji.
+        let japanese = cleanup(ChineseScript::Simplified)
+            .transcription(&sentence("日本語を話します", ""), None)
+            .await;
+        assert_eq!(japanese.text, "日本語を話します");
+        // Partial sentences are left in their script.
+        let partial = TranscriptionEvent::Partial(sentence("繁體", ""));
+        let TranscriptionEvent::Partial(partial) = cleanup(ChineseScript::Simplified)
+            .event(&partial, None)
+            .await
+        else {
+            panic!("not a partial sentence");
+        };
+        assert_eq!(partial.text, "繁體");
+    }
+
+    #[tokio::test]
+    async fn cleans_up_a_copy_of_the_sentence() {
+        let cleanup = Cleanup {
+            trim: true,
+            ..cleanup(&[("en", &["uh"]), ("zh", &["嗯"])])
+        };
+        let word = |text: &str| Word {
+            begin_time: 0,
+            end_time: 0,
+            text: text.to_string(),
+            confidence: None,
+        };
+        let raw = Transcription {
+            sentence_id: 0,
+            begin_time: 0,
+            end_time: 1_000,
+            text: "Uh, hello. ".to_string(),
+            is_final: true,
+            translated_text: Some("嗯，你好。".to_string()),
+            translations: vec![Translation {
+                lang: "zh".to_string(),
+                text: "嗯，你好。".to_string(),
+            }],
+            words: vec![word("Uh,"), word("hello.")],
+            confidence: None,
+            source_label: None,
+        };
+        let event = TranscriptionEvent::Final(raw.clone());
+        let TranscriptionEvent::Final(cleaned) = cleanup.event(&event, Some("en")).await else {
+            panic!("not a final sentence");
+        };
+        let TranscriptionEvent::Final(kept) = event else {
+            unreachable!();
+        };
+        assert_eq!(cleaned.text, "hello.");
+        assert_eq!(cleaned.translated_text.as_deref(), Some("你好。"));
+        assert_eq!(cleaned.translations[0].text, "你好。");
+        assert_eq!(cleaned.words, [word("hello.")]);
+        // The sentence itself is left as it was recognized.
+        assert_eq!(kept, raw);
+        // With the language detected, the fillers of every language are taken out.
+        let cleaned = cleanup
+            .transcription(
+                &Transcription {
+                    text: "嗯，好。".to_string(),
+                    ..cleaned
+                },
+                None,
+            )
+            .await;
+        assert_eq!(cleaned.text, "好。");
+    }
+}
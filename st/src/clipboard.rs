@@ -0,0 +1,199 @@
+//! `--clipboard` and the dashboard's copy of the whole transcript, on the system clipboard.
+
+use crate::console::{INDENT, timestamp};
+use clap::ValueEnum;
+use gummy::{Transcription, TranscriptionEvent};
+use log::{debug, warn};
+use std::collections::HashSet;
+
+/// Where text copied goes.
+pub trait Clipboard {
+    fn set_text(&mut self, text: &str) -> anyhow::Result<()>;
+}
+
+/// The clipboard of the desktop, opened on first use.
+///
+/// On X11 and Wayland copied text is served by this process, so it stays on the clipboard
+/// as long as this is kept.
+#[derive(Default)]
+pub struct SystemClipboard(Option<arboard::Clipboard>);
+
+impl Clipboard for SystemClipboard {
+    fn set_text(&mut self, text: &str) -> anyhow::Result<()> {
+        let clipboard = match &mut self.0 {
+            Some(clipboard) => clipboard,
+            None => self.0.insert(arboard::Clipboard::new()?),
+        };
+        clipboard.set_text(text)?;
+        Ok(())
+    }
+}
+
+/// What `--clipboard` keeps on the clipboard.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ClipboardMode {
+    /// The last final sentence.
+    Last,
+    /// The translation of the last final sentence.
+    LastTranslation,
+}
+
+/// Follows the final sentences of a session to copy the last of them as they come in, or
+/// all of them when asked.
+///
+/// Copying fails without a desktop session, and then only costs a warning.
+pub struct ClipboardSync {
+    clipboard: Box<dyn Clipboard>,
+    mode: Option<ClipboardMode>,
+    sentences: Vec<Transcription>,
+    seen: HashSet<u64>,
+    warned: bool,
+}
+
+impl ClipboardSync {
+    pub fn new(clipboard: Box<dyn Clipboard>, mode: Option<ClipboardMode>) -> Self {
+        ClipboardSync {
+            clipboard,
+            mode,
+            sentences: vec![],
+            seen: HashSet::new(),
+            warned: false,
+        }
+    }
+
+    pub fn update(&mut self, event: &TranscriptionEvent) {
+        let TranscriptionEvent::Final(t) = event else {
+            return;
+        };
+        if !self.seen.insert(t.sentence_id) {
+            return;
+        }
+        self.sentences.push(t.clone());
+        let text = match self.mode {
+            Some(ClipboardMode::Last) => Some(t.text.trim()),
+            Some(ClipboardMode::LastTranslation) => t.translated_text.as_deref().map(str::trim),
+            None => None,
+        };
+        if let Some(text) = text.filter(|text| !text.is_empty())
+            && let Err(e) = self.clipboard.set_text(text)
+        {
+            // Once is enough when there's no clipboard at all.
+            match self.warned {
+                false => warn!("Failed to copy the last sentence: {}", e),
+                true => debug!("Failed to copy the last sentence: {}", e),
+            }
+            self.warned = true;
+        }
+    }
+
+    /// Copies the transcript so far. Returns the number of sentences copied.
+    pub fn copy_all(&mut self) -> anyhow::Result<usize> {
+        self.clipboard.set_text(&plain_text(&self.sentences))?;
+        Ok(self.sentences.len())
+    }
+}
+
+/// `transcriptions` as printed to the terminal: a line per sentence after its start time,
+/// with its translation under it.
+pub fn plain_text(transcriptions: &[Transcription]) -> String {
+    let mut text = String::new();
+    for t in transcriptions {
+        if t.text.trim().is_empty() {
+            continue;
+        }
+        text.push_str(&format!("{} {}\n", timestamp(t.begin_time), t.text.trim()));
+        if let Some(translated) = &t.translated_text {
+            text.push_str(&format!("{}{}\n", INDENT, translated.trim()));
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Keeps what's copied, or fails like a headless session.
+    #[derive(Clone, Default)]
+    struct FakeClipboard {
+        copied: Arc<Mutex<Vec<String>>>,
+        broken: bool,
+    }
+
+    impl Clipboard for FakeClipboard {
+        fn set_text(&mut self, text: &str) -> anyhow::Result<()> {
+            if self.broken {
+                anyhow::bail!("no display");
+            }
+            self.copied.lock().unwrap().push(text.to_string());
+            Ok(())
+        }
+    }
+
+    fn sentence(sentence_id: u64, begin_time: u64, text: &str) -> Transcription {
+        Transcription {
+            sentence_id,
+            begin_time,
+            end_time: begin_time + 1000,
+            text: text.to_string(),
+            is_final: true,
+            translated_text: (sentence_id != 1).then(|| format!("({})", text.trim())),
+            translations: vec![],
+            words: vec![],
+            confidence: None,
+            source_label: None,
+        }
+    }
+
+    #[test]
+    fn formats_the_transcript_as_plain_text() {
+        let transcriptions = [
+            sentence(0, 1_500, " Hello. "),
+            sentence(1, 61_000, "Untranslated."),
+            sentence(2, 3_725_000, ""),
+            sentence(3, 3_726_000, "Bye."),
+        ];
+        assert_eq!(
+            plain_text(&transcriptions),
+            "[00:01] Hello.\n\
+             \x20       (Hello.)\n\
+             [01:01] Untranslated.\n\
+             [62:06] Bye.\n\
+             \x20       (Bye.)\n"
+        );
+        assert_eq!(plain_text(&[]), "");
+    }
+
+    #[test]
+    fn copies_the_last_sentence_and_the_whole_transcript() {
+        let clipboard = FakeClipboard::default();
+        let copied = clipboard.copied.clone();
+        let mut sync =
+            ClipboardSync::new(Box::new(clipboard), Some(ClipboardMode::LastTranslation));
+        sync.update(&TranscriptionEvent::Partial(sentence(0, 0, "Hel")));
+        sync.update(&TranscriptionEvent::Final(sentence(0, 0, "Hello.")));
+        sync.update(&TranscriptionEvent::Final(sentence(0, 0, "Hello.")));
+        // Nothing to copy without a translation.
+        sync.update(&TranscriptionEvent::Final(sentence(1, 1000, "World.")));
+        assert_eq!(*copied.lock().unwrap(), ["(Hello.)"]);
+
+        assert_eq!(sync.copy_all().unwrap(), 2);
+        assert_eq!(
+            copied.lock().unwrap()[1],
+            "[00:00] Hello.\n        (Hello.)\n[00:01] World.\n"
+        );
+    }
+
+    #[test]
+    fn carries_on_without_a_clipboard() {
+        let clipboard = FakeClipboard {
+            broken: true,
+            ..FakeClipboard::default()
+        };
+        let mut sync = ClipboardSync::new(Box::new(clipboard), Some(ClipboardMode::Last));
+        sync.update(&TranscriptionEvent::Final(sentence(0, 0, "Hello.")));
+        sync.update(&TranscriptionEvent::Final(sentence(1, 0, "World.")));
+        assert!(sync.copy_all().is_err());
+    }
+}
@@ -0,0 +1,151 @@
+//! The audio sent for transcription gathered into frames of about 100 ms, so a source
+//! handing out a few milliseconds at a time doesn't cost a WebSocket message each.
+
+use std::future::pending;
+use std::time::Duration;
+use tokio::time::{Instant, sleep_until};
+
+/// Longest audio is held before it's sent, whether it makes a whole frame or not.
+pub const MAX_STALENESS: Duration = Duration::from_millis(300);
+
+/// Gathers audio into a buffer reused from one frame to the next, which is ready once it
+/// holds `duration` of audio, or once its first samples are `max_staleness` old. Without
+/// the deadline, the end of what was said would wait for a device that stopped delivering
+/// in silence, as WASAPI loopback does, to deliver again.
+pub struct FrameCoalescer {
+    frame_len: usize,
+    max_staleness: Duration,
+    samples: Vec<i16>,
+    /// When the audio held was captured, in milliseconds, and when it came in.
+    held: Option<(u64, Instant)>,
+    /// Whether `samples` was handed out, and is cleared before audio is taken in again.
+    taken: bool,
+}
+
+impl FrameCoalescer {
+    /// Creates a coalescer of frames of `duration` of audio at `sample_rate`.
+    pub fn new(sample_rate: u32, duration: Duration, max_staleness: Duration) -> Self {
+        let frame_len = (sample_rate as u128 * duration.as_millis() / 1000).max(1) as usize;
+        FrameCoalescer {
+            frame_len,
+            max_staleness,
+            samples: Vec::with_capacity(frame_len * 2),
+            held: None,
+            taken: false,
+        }
+    }
+
+    /// Takes in `samples` captured at `captured_ms`.
+    pub fn push(&mut self, samples: &[i16], captured_ms: u64) {
+        if samples.is_empty() {
+            return;
+        }
+        if self.taken {
+            self.samples.clear();
+            self.taken = false;
+        }
+        self.held.get_or_insert((captured_ms, Instant::now()));
+        self.samples.extend_from_slice(samples);
+    }
+
+    /// Whether the audio held makes a whole frame.
+    pub fn is_full(&self) -> bool {
+        self.held.is_some() && self.samples.len() >= self.frame_len
+    }
+
+    /// Waits for the audio held to go stale, forever while none is held.
+    pub async fn stale(&self) {
+        match self.held {
+            Some((_, since)) => sleep_until(since + self.max_staleness).await,
+            None => pending().await,
+        }
+    }
+
+    /// Hands out the audio held, with when it was captured, whether it makes a whole frame
+    /// or not. The samples are valid until audio is taken in again.
+    pub fn take(&mut self) -> Option<(&[i16], u64)> {
+        let (captured_ms, _) = self.held.take()?;
+        self.taken = true;
+        Some((&self.samples, captured_ms))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{advance, timeout};
+
+    /// 100 ms frames at 16 kHz.
+    fn coalescer() -> FrameCoalescer {
+        FrameCoalescer::new(16000, Duration::from_millis(100), MAX_STALENESS)
+    }
+
+    async fn is_stale(coalescer: &FrameCoalescer) -> bool {
+        timeout(Duration::ZERO, coalescer.stale()).await.is_ok()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn is_full_once_it_holds_a_frame() {
+        let mut coalescer = coalescer();
+        // Callbacks of 2.5 ms.
+        for callback in 0..39 {
+            coalescer.push(&[callback; 40], 1_000 + callback as u64);
+            assert!(!coalescer.is_full());
+        }
+        coalescer.push(&[39; 40], 1_039);
+        assert!(coalescer.is_full());
+        let (samples, captured_ms) = coalescer.take().unwrap();
+        assert_eq!(samples.len(), 1600);
+        assert_eq!((samples[0], samples[1599]), (0, 39));
+        // Stamped with the capture of its first samples.
+        assert_eq!(captured_ms, 1_000);
+        assert!(coalescer.take().is_none());
+        assert!(!coalescer.is_full());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn goes_stale_after_the_deadline_short_of_a_frame() {
+        let mut coalescer = coalescer();
+        assert!(!is_stale(&coalescer).await);
+        coalescer.push(&[1; 400], 0);
+        advance(Duration::from_millis(200)).await;
+        // Audio coming in doesn't push the deadline back.
+        coalescer.push(&[2; 400], 200);
+        advance(Duration::from_millis(99)).await;
+        assert!(!is_stale(&coalescer).await);
+        advance(Duration::from_millis(1)).await;
+        assert!(is_stale(&coalescer).await);
+        assert!(!coalescer.is_full());
+        let (samples, captured_ms) = coalescer.take().unwrap();
+        assert_eq!((samples.len(), captured_ms), (800, 0));
+        // Nothing held, nothing to go stale.
+        advance(Duration::from_secs(10)).await;
+        assert!(!is_stale(&coalescer).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn counts_the_deadline_from_the_audio_taken_in_after_a_frame() {
+        let mut coalescer = coalescer();
+        coalescer.push(&[1; 1600], 0);
+        advance(Duration::from_millis(250)).await;
+        coalescer.take().unwrap();
+        coalescer.push(&[2; 160], 250);
+        advance(Duration::from_millis(250)).await;
+        assert!(!is_stale(&coalescer).await);
+        advance(Duration::from_millis(50)).await;
+        assert!(is_stale(&coalescer).await);
+        assert_eq!(coalescer.take().unwrap(), (&[2; 160][..], 250));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reuses_its_buffer() {
+        let mut coalescer = coalescer();
+        coalescer.push(&[1; 1600], 0);
+        let buffer = coalescer.take().unwrap().0.as_ptr();
+        coalescer.push(&[2; 1600], 100);
+        assert_eq!(coalescer.take().unwrap().0.as_ptr(), buffer);
+        // Nothing taken in leaves nothing to hand out.
+        coalescer.push(&[], 200);
+        assert!(coalescer.take().is_none());
+    }
+}
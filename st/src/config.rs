@@ -0,0 +1,636 @@
+//! Settings from a TOML config file, layered under the command line and the environment.
+
+use crate::args::{Backend, Source};
+use crate::cleanup::{ChineseScript, Cleanup};
+use crate::output::TranscriptFormat;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, ValueEnum};
+use std::collections::BTreeMap;
+use std::env::var_os;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// Written by `st config init`, every setting commented out.
+pub const TEMPLATE: &str = r#"# Settings for st, overridden by the command line and the environment.
+# Uncomment what you want to set; `st --help` describes each of them.
+
+# Speech recognition backend: gummy, openai or whisper.
+# backend = "gummy"
+
+# File holding the API key, used unless --api-key, --api-key-file, $API_KEY or
+# $OPENAI_API_KEY is given.
+# api_key_file = "~/.config/st/api_key"
+
+# Endpoint of the backend, overriding its default.
+# url = "wss://dashscope.aliyuncs.com/api-ws/v1/inference"
+
+# Language spoken in the audio, and the languages to translate into.
+# source_lang = "en"
+# target_lang = ["zh"]
+
+# What to capture: system, mic, mixed or separate.
+# source = "system"
+
+# Capture device name or index from --list-devices.
+# device = "default"
+
+# Rate to record at, overriding the backend's preferred rate.
+# sample_rate = 16000
+
+# Capture gain in dB.
+# gain = 0.0
+
+# File to write the transcript to, or a list of them, and its format: txt, srt, vtt,
+# json or jsonl.
+# output = "transcript.srt"
+# format = "srt"
+
+# Path to a ggml model file, used by the whisper backend.
+# model_path = "~/models/ggml-base.bin"
+
+# Price of a second of audio billed, and its currency, for the cost in the summary at the
+# end of a session. Check the current price of the backend.
+# price_per_second = 0.00015
+# currency = "CNY"
+
+# Show desktop notifications as sessions start, lose the connection, fail and finish.
+# notify = true
+
+# Clean up the text of transcripts and captions: take out filler words, listed by
+# language, collapse repeated punctuation like "??", trim whitespace, and convert Chinese
+# to "simplified" or "traditional" script with `chinese`, which needs the chinese feature
+# and OpenCC.
+# cleanup = { fillers = { en = ["uh", "um"], zh = ["嗯", "呃"] }, collapse_punctuation = true, trim = true }
+"#;
+
+/// Keys of the config file.
+const KEYS: [&str; 16] = [
+    "backend",
+    "api_key_file",
+    "url",
+    "source_lang",
+    "target_lang",
+    "source",
+    "device",
+    "sample_rate",
+    "gain",
+    "output",
+    "format",
+    "model_path",
+    "price_per_second",
+    "currency",
+    "notify",
+    "cleanup",
+];
+
+/// Where the API key comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ApiKey {
+    Value(String),
+    File(PathBuf),
+}
+
+/// Settings of one layer, `None` where it leaves them to the layers under it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Settings {
+    pub backend: Option<Backend>,
+    pub api_key: Option<ApiKey>,
+    pub url: Option<String>,
+    pub source_lang: Option<String>,
+    pub target_lang: Option<Vec<String>>,
+    pub source: Option<Source>,
+    pub device: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub gain_db: Option<f32>,
+    pub output: Option<Vec<PathBuf>>,
+    pub format: Option<TranscriptFormat>,
+    pub model_path: Option<String>,
+    pub price_per_second: Option<f64>,
+    pub currency: Option<String>,
+    pub notify: Option<bool>,
+    pub cleanup: Option<Cleanup>,
+}
+
+impl Settings {
+    /// What was given on the command line.
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        let api_key = match given(matches, "api_key") {
+            Some(key) => Some(ApiKey::Value(key)),
+            None => given(matches, "api_key_file").map(ApiKey::File),
+        };
+        Settings {
+            backend: given(matches, "backend"),
+            api_key,
+            url: given(matches, "url"),
+            source_lang: given(matches, "source_lang"),
+            target_lang: (matches.value_source("target_lang") == Some(ValueSource::CommandLine))
+                .then(|| matches.get_many::<String>("target_lang"))
+                .flatten()
+                .map(|languages| languages.cloned().collect()),
+            source: given(matches, "source"),
+            device: given(matches, "device"),
+            sample_rate: given(matches, "sample_rate"),
+            gain_db: given(matches, "gain_db"),
+            output: (matches.value_source("output") == Some(ValueSource::CommandLine))
+                .then(|| matches.get_many::<PathBuf>("output"))
+                .flatten()
+                .map(|paths| paths.cloned().collect()),
+            format: given(matches, "format"),
+            model_path: given(matches, "model_path"),
+            price_per_second: given(matches, "price_per_second"),
+            currency: given(matches, "currency"),
+            notify: given(matches, "notify"),
+            // Only set in the config file.
+            cleanup: None,
+        }
+    }
+
+    /// `self`, with what it leaves unset taken from `other`.
+    fn or(self, other: Settings) -> Settings {
+        Settings {
+            backend: self.backend.or(other.backend),
+            api_key: self.api_key.or(other.api_key),
+            url: self.url.or(other.url),
+            source_lang: self.source_lang.or(other.source_lang),
+            target_lang: self.target_lang.or(other.target_lang),
+            source: self.source.or(other.source),
+            device: self.device.or(other.device),
+            sample_rate: self.sample_rate.or(other.sample_rate),
+            gain_db: self.gain_db.or(other.gain_db),
+            output: self.output.or(other.output),
+            format: self.format.or(other.format),
+            model_path: self.model_path.or(other.model_path),
+            price_per_second: self.price_per_second.or(other.price_per_second),
+            currency: self.currency.or(other.currency),
+            notify: self.notify.or(other.notify),
+            cleanup: self.cleanup.or(other.cleanup),
+        }
+    }
+}
+
+/// The value of the argument `id`, if it was given on the command line.
+fn given<T: Clone + Send + Sync + 'static>(matches: &ArgMatches, id: &str) -> Option<T> {
+    if matches.value_source(id) != Some(ValueSource::CommandLine) {
+        return None;
+    }
+    matches.get_one::<T>(id).cloned()
+}
+
+/// Variable holding the API key of `backend`.
+pub fn api_key_var(backend: Backend) -> &'static str {
+    match backend {
+        Backend::Openai => "OPENAI_API_KEY",
+        _ => "API_KEY",
+    }
+}
+
+/// Layers the command line over the environment, read through `env`, over the config
+/// file. Whatever none of them sets keeps its default.
+pub fn layer(cli: Settings, env: impl Fn(&str) -> Option<String>, file: Settings) -> Settings {
+    let backend = cli.backend.or(file.backend).unwrap_or(Backend::Gummy);
+    let env = Settings {
+        api_key: env(api_key_var(backend)).map(ApiKey::Value),
+        ..Settings::default()
+    };
+    cli.or(env).or(file)
+}
+
+/// `~/.config/st/config.toml`, under `$XDG_CONFIG_HOME` instead of `~/.config` if it's set.
+pub fn default_path() -> Option<PathBuf> {
+    let config = match var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(var_os("HOME")?).join(".config"),
+    };
+    Some(config.join("st").join("config.toml"))
+}
+
+/// Reads the config file at `path`. A missing file sets nothing, unless `required`.
+///
+/// Unknown keys are returned alongside the settings rather than rejected, so a config
+/// file written for a newer version still works.
+pub fn load(path: &Path, required: bool) -> Result<(Settings, Vec<String>), String> {
+    match fs::read_to_string(path) {
+        Ok(content) => parse(&content).map_err(|e| format!("{}: {}", path.display(), e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => {
+            Ok((Settings::default(), vec![]))
+        }
+        Err(e) => Err(format!("failed to read {}: {}", path.display(), e)),
+    }
+}
+
+/// Parses the content of a config file into its settings and the keys it doesn't know.
+pub fn parse(content: &str) -> Result<(Settings, Vec<String>), String> {
+    let mut table = content
+        .parse::<Table>()
+        .map_err(|e| e.message().to_string())?;
+    let unknown = table
+        .keys()
+        .filter(|key| !KEYS.contains(&key.as_str()))
+        .cloned()
+        .collect();
+    let mut take = |key: &str| table.remove(key);
+    let settings = Settings {
+        backend: value(take("backend"), "backend")?,
+        api_key: string(take("api_key_file"), "api_key_file")?
+            .map(|path| ApiKey::File(expand_home(&path))),
+        url: string(take("url"), "url")?,
+        source_lang: string(take("source_lang"), "source_lang")?,
+        target_lang: match take("target_lang") {
+            None => None,
+            // A single language may be given as a string.
+            Some(Value::String(language)) => Some(vec![language]),
+            Some(Value::Array(languages)) => Some(
+                languages
+                    .into_iter()
+                    .map(|language| string(Some(language), "target_lang").map(Option::unwrap))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Some(_) => return Err(invalid("target_lang", "a list of languages")),
+        },
+        source: value(take("source"), "source")?,
+        device: match take("device") {
+            // An index from `--list-devices`.
+            Some(Value::Integer(index)) => Some(index.to_string()),
+            device => string(device, "device")?,
+        },
+        sample_rate: match take("sample_rate") {
+            None => None,
+            Some(Value::Integer(rate)) => {
+                Some(u32::try_from(rate).map_err(|_| invalid("sample_rate", "a rate in Hz"))?)
+            }
+            Some(_) => return Err(invalid("sample_rate", "a rate in Hz")),
+        },
+        gain_db: match take("gain") {
+            None => None,
+            Some(Value::Float(gain)) => Some(gain as f32),
+            Some(Value::Integer(gain)) => Some(gain as f32),
+            Some(_) => return Err(invalid("gain", "a number of dB")),
+        },
+        output: match take("output") {
+            None => None,
+            // A single file may be given as a string.
+            Some(Value::String(path)) => Some(vec![expand_home(&path)]),
+            Some(Value::Array(paths)) => Some(
+                paths
+                    .into_iter()
+                    .map(|path| {
+                        string(Some(path), "output").map(|path| expand_home(&path.unwrap()))
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            Some(_) => return Err(invalid("output", "a file or a list of files")),
+        },
+        format: value(take("format"), "format")?,
+        model_path: string(take("model_path"), "model_path")?
+            .map(|path| expand_home(&path).to_string_lossy().into_owned()),
+        price_per_second: match take("price_per_second") {
+            None => None,
+            Some(Value::Float(price)) if price >= 0.0 => Some(price),
+            Some(Value::Integer(price)) if price >= 0 => Some(price as f64),
+            Some(_) => return Err(invalid("price_per_second", "a price of at least 0")),
+        },
+        currency: string(take("currency"), "currency")?,
+        notify: match take("notify") {
+            None => None,
+            Some(Value::Boolean(notify)) => Some(notify),
+            Some(_) => return Err(invalid("notify", "true or false")),
+        },
+        cleanup: match take("cleanup") {
+            None => None,
+            Some(Value::Table(rules)) => Some(cleanup(rules)?),
+            Some(_) => return Err(invalid("cleanup", "a table of rules")),
+        },
+    };
+    Ok((settings, unknown))
+}
+
+/// The rules of the `cleanup` table, which has to know all of them.
+fn cleanup(mut rules: Table) -> Result<Cleanup, String> {
+    let mut take = |key: &str| rules.remove(key);
+    let flag = |value: Option<Value>, key: &str| match value {
+        None => Ok(false),
+        Some(Value::Boolean(flag)) => Ok(flag),
+        Some(_) => Err(invalid(key, "true or false")),
+    };
+    let words = |language: String, words: Value| {
+        let key = format!("cleanup.fillers.{}", language);
+        let Value::Array(words) = words else {
+            return Err(invalid(&key, "a list of words"));
+        };
+        let words = words
+            .into_iter()
+            .map(|word| string(Some(word), &key).map(Option::unwrap))
+            .collect::<Result<_, _>>()?;
+        Ok((gummy::normalize_language(&language), words))
+    };
+    let cleanup = Cleanup {
+        fillers: match take("fillers") {
+            None => BTreeMap::new(),
+            Some(Value::Table(languages)) => languages
+                .into_iter()
+                .map(|(language, list)| words(language, list))
+                .collect::<Result<_, _>>()?,
+            Some(_) => return Err(invalid("cleanup.fillers", "a table of words by language")),
+        },
+        collapse_punctuation: flag(take("collapse_punctuation"), "cleanup.collapse_punctuation")?,
+        trim: flag(take("trim"), "cleanup.trim")?,
+        chinese: match string(take("chinese"), "cleanup.chinese")? {
+            None => None,
+            Some(script) => Some(
+                ChineseScript::from_name(&script)
+                    .ok_or_else(|| invalid("cleanup.chinese", "simplified or traditional"))?,
+            ),
+        },
+    };
+    match rules.keys().next() {
+        Some(key) => Err(format!("unknown key `cleanup.{}`", key)),
+        None => Ok(cleanup),
+    }
+}
+
+fn invalid(key: &str, expected: &str) -> String {
+    format!("`{}` should be {}", key, expected)
+}
+
+fn string(value: Option<Value>, key: &str) -> Result<Option<String>, String> {
+    match value {
+        None => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value)),
+        Some(_) => Err(invalid(key, "a string")),
+    }
+}
+
+/// One of the values the command line takes for `key`.
+fn value<T: ValueEnum>(value: Option<Value>, key: &str) -> Result<Option<T>, String> {
+    let Some(value) = string(value, key)? else {
+        return Ok(None);
+    };
+    T::from_str(&value, true)
+        .map(Some)
+        .map_err(|_| format!("invalid value `{}` for `{}`", value, key))
+}
+
+/// `path` with a leading `~` replaced by the home directory, as a shell would.
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Writes the template to `path`, creating its directory. An existing file is only
+/// replaced with `force`.
+pub fn init(path: &Path, force: bool) -> io::Result<()> {
+    if !force && path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "{} already exists, pass --force to replace it",
+                path.display()
+            ),
+        ));
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, TEMPLATE)
+}
+
+/// Sets `device` in the config file at `path`, keeping the rest of the file. Without a
+/// file, one is written from the template.
+pub fn save_device(path: &Path, device: &str) -> io::Result<()> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            TEMPLATE.to_string()
+        }
+        Err(e) => return Err(e),
+    };
+    fs::write(path, set(&content, "device", device))
+}
+
+/// `content` with `key` set to the string `value`: on the line setting it, or else under
+/// the commented-out example of the template, or else at the top, where top-level keys
+/// belong.
+fn set(content: &str, key: &str, value: &str) -> String {
+    let setting = format!("{} = {}", key, Value::String(value.to_string()));
+    let sets = |line: &str| {
+        line.split_once('=')
+            .is_some_and(|(name, _)| name.trim() == key)
+    };
+    let mut lines: Vec<&str> = content.lines().collect();
+    if let Some(i) = lines.iter().position(|line| sets(line)) {
+        lines[i] = &setting;
+    } else if let Some(i) = lines
+        .iter()
+        .position(|line| line.strip_prefix('#').is_some_and(sets))
+    {
+        lines.insert(i + 1, &setting);
+    } else {
+        lines.insert(0, &setting);
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn file(content: &str) -> Settings {
+        let (settings, unknown) = parse(content).unwrap();
+        assert!(unknown.is_empty(), "{:?}", unknown);
+        settings
+    }
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>();
+        move |name| vars.get(name).cloned()
+    }
+
+    fn key(value: &str) -> Option<ApiKey> {
+        Some(ApiKey::Value(value.to_string()))
+    }
+
+    fn key_file(path: &str) -> Option<ApiKey> {
+        Some(ApiKey::File(PathBuf::from(path)))
+    }
+
+    #[test]
+    fn layers_the_command_line_over_the_environment_over_the_file() {
+        let cli = Settings {
+            api_key: key("cli"),
+            ..Settings::default()
+        };
+        let file = file(r#"api_key_file = "/etc/st/key""#);
+        let from_env = env(&[("API_KEY", "env")]);
+        let none = env(&[]);
+
+        for (cli, env, file, expected) in [
+            (&cli, &from_env, &file, key("cli")),
+            (&cli, &from_env, &Settings::default(), key("cli")),
+            (&cli, &none, &file, key("cli")),
+            (&cli, &none, &Settings::default(), key("cli")),
+            (&Settings::default(), &from_env, &file, key("env")),
+            (
+                &Settings::default(),
+                &from_env,
+                &Settings::default(),
+                key("env"),
+            ),
+            (&Settings::default(), &none, &file, key_file("/etc/st/key")),
+            (&Settings::default(), &none, &Settings::default(), None),
+        ] {
+            assert_eq!(layer(cli.clone(), env, file.clone()).api_key, expected);
+        }
+    }
+
+    #[test]
+    fn layers_the_command_line_over_the_file() {
+        let file = file(
+            r#"
+            backend = "openai"
+            device = 2
+            target_lang = ["zh", "ja"]
+            gain = -6
+            format = "SRT"
+            "#,
+        );
+        let cli = Settings {
+            device: Some("USB".to_string()),
+            gain_db: Some(3.0),
+            ..Settings::default()
+        };
+        let settings = layer(cli, env(&[]), file.clone());
+        assert_eq!(settings.device.as_deref(), Some("USB"));
+        assert_eq!(settings.gain_db, Some(3.0));
+        assert_eq!(settings.backend, Some(Backend::Openai));
+        assert_eq!(settings.target_lang, Some(vec!["zh".into(), "ja".into()]));
+        assert_eq!(settings.format, Some(TranscriptFormat::Srt));
+        // Left to the defaults.
+        assert_eq!(settings.source, None);
+        assert_eq!(settings.output, None);
+
+        // The variable read depends on the backend, wherever that's chosen.
+        let settings = layer(
+            Settings::default(),
+            env(&[("OPENAI_API_KEY", "openai")]),
+            file,
+        );
+        assert_eq!(settings.api_key, key("openai"));
+        let cli = Settings {
+            backend: Some(Backend::Gummy),
+            ..Settings::default()
+        };
+        let settings = layer(
+            cli,
+            env(&[("OPENAI_API_KEY", "openai")]),
+            Settings::default(),
+        );
+        assert_eq!(settings.api_key, None);
+    }
+
+    #[test]
+    fn reports_unknown_keys_and_rejects_invalid_values() {
+        let (settings, unknown) = parse("device = \"USB\"\nlanguage = \"en\"\n").unwrap();
+        assert_eq!(settings.device.as_deref(), Some("USB"));
+        assert_eq!(unknown, ["language"]);
+
+        for content in [
+            "backend = \"azure\"",
+            "sample_rate = -1",
+            "sample_rate = \"16k\"",
+            "target_lang = 3",
+            "gain = \"loud\"",
+            "price_per_second = -0.1",
+            "notify = \"yes\"",
+            "device = [",
+            "cleanup = true",
+            "cleanup = { trim = \"yes\" }",
+            "cleanup = { fillers = { en = \"uh\" } }",
+            "cleanup = { chinese = \"cantonese\" }",
+            "cleanup = { stem = true }",
+        ] {
+            assert!(parse(content).is_err(), "{}", content);
+        }
+    }
+
+    #[test]
+    fn reads_the_cleanup_rules() {
+        let settings = file(
+            r#"
+            [cleanup]
+            fillers = { en = ["uh", "um"], zh-CN = ["嗯"] }
+            trim = true
+            chinese = "traditional"
+            "#,
+        );
+        let cleanup = settings.cleanup.unwrap();
+        assert_eq!(cleanup.fillers["en"], ["uh", "um"]);
+        assert_eq!(cleanup.fillers["zh"], ["嗯"]);
+        assert!(cleanup.trim);
+        assert!(!cleanup.collapse_punctuation);
+        assert_eq!(cleanup.chinese, Some(ChineseScript::Traditional));
+        // Off unless the file has the table.
+        assert_eq!(file("notify = true").cleanup, None);
+    }
+
+    #[test]
+    fn remembers_the_device_in_the_file() {
+        // Under the example of the template.
+        let content = set(TEMPLATE, "device", "Monitor of \"Speakers\"");
+        assert!(
+            content.contains("# device = \"default\"\ndevice = 'Monitor of \"Speakers\"'\n"),
+            "{}",
+            content
+        );
+        assert_eq!(
+            file(&content).device.as_deref(),
+            Some("Monitor of \"Speakers\"")
+        );
+        // Over the setting, keeping the rest.
+        let content = set("gain = 3\ndevice = 2 # picked\n", "device", "USB");
+        assert_eq!(content, "gain = 3\ndevice = \"USB\"\n");
+        // At the top, away from any table.
+        assert_eq!(
+            set("[extra]\n", "device", "USB"),
+            "device = \"USB\"\n[extra]\n"
+        );
+
+        let dir = std::env::temp_dir().join(format!("st-config-{}", std::process::id()));
+        let path = dir.join("st/config.toml");
+        save_device(&path, "USB").unwrap();
+        save_device(&path, "Speakers").unwrap();
+        let (settings, _) = load(&path, true).unwrap();
+        assert_eq!(settings.device.as_deref(), Some("Speakers"));
+        assert!(
+            fs::read_to_string(&path)
+                .unwrap()
+                .starts_with("# Settings for st")
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn template_covers_every_key() {
+        assert_eq!(file(TEMPLATE), Settings::default());
+        // Uncommented, every setting is known and valid.
+        let uncommented = TEMPLATE
+            .lines()
+            .filter_map(|line| line.strip_prefix("# "))
+            .filter(|line| line.contains(" = "))
+            .collect::<Vec<_>>();
+        let settings = file(&uncommented.join("\n"));
+        assert_eq!(settings.backend, Some(Backend::Gummy));
+        assert_eq!(settings.sample_rate, Some(16000));
+        assert_eq!(uncommented.len(), KEYS.len());
+    }
+}
@@ -0,0 +1,332 @@
+//! Captions printed to the terminal as sentences are recognized.
+
+use crate::diff::TranscriptDiff;
+use clap::ValueEnum;
+use gummy::{Transcription, TranscriptionEvent};
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, IsTerminal, Stdout, Write};
+use std::time::{Duration, Instant};
+
+/// Characters of a partial sentence shown, from its end, so the line it's rewritten on
+/// doesn't wrap.
+const PARTIAL_CHARS: usize = 70;
+/// Lines translations up with their sentence, after the timestamp.
+pub const INDENT: &str = "        ";
+/// Moves to the start of the line and clears it.
+const CLEAR_LINE: &str = "\r\x1b[2K";
+/// Colors a translation under its sentence on a terminal, and back.
+const TRANSLATION_COLOR: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+/// Marks a sentence shown in place of the translation it lacks.
+pub const UNTRANSLATED: &str = "(untranslated) ";
+
+/// Which text of sentences `--display` shows in printed captions, the caption file and the
+/// dashboard. Transcripts keep what their format has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum DisplayMode {
+    /// The text as spoken.
+    Source,
+    /// The translation, or the text marked as untranslated without one.
+    Translation,
+    /// The text, with the translation under it.
+    Both,
+}
+
+/// A line shown of a sentence.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShownLine<'a> {
+    Source(&'a str),
+    Translation(&'a str),
+    /// The text, standing in for a translation missing.
+    Untranslated(&'a str),
+}
+
+impl fmt::Display for ShownLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShownLine::Source(text) | ShownLine::Translation(text) => f.write_str(text),
+            ShownLine::Untranslated(text) => write!(f, "{}{}", UNTRANSLATED, text),
+        }
+    }
+}
+
+impl DisplayMode {
+    /// The lines `t` is shown on, its translation under its text with `Both`.
+    pub fn lines(self, t: &Transcription) -> Vec<ShownLine<'_>> {
+        let text = t.text.trim();
+        let translation = (t.translated_text.as_deref())
+            .map(str::trim)
+            .filter(|translation| !translation.is_empty());
+        match (self, translation) {
+            (DisplayMode::Source, _) => vec![ShownLine::Source(text)],
+            (DisplayMode::Translation, Some(translation)) => {
+                vec![ShownLine::Translation(translation)]
+            }
+            (DisplayMode::Translation, None) => vec![ShownLine::Untranslated(text)],
+            (DisplayMode::Both, Some(translation)) => {
+                vec![ShownLine::Source(text), ShownLine::Translation(translation)]
+            }
+            (DisplayMode::Both, None) => vec![ShownLine::Source(text)],
+        }
+    }
+
+    /// The one line a sentence being recognized is shown on: its translation with
+    /// `Translation`, its text otherwise.
+    pub fn line(self, t: &Transcription) -> ShownLine<'_> {
+        match self {
+            DisplayMode::Translation => self.lines(t)[0],
+            DisplayMode::Source | DisplayMode::Both => ShownLine::Source(t.text.trim()),
+        }
+    }
+}
+
+/// Prints final sentences with their start time, and their translation as `DisplayMode`
+/// has it, in color on a terminal. There the sentence being recognized is shown too, on a
+/// line rewritten as it changes.
+///
+/// Anywhere else only final sentences are printed, without control codes, so piping the
+/// output to a file stays clean.
+pub struct ConsoleRenderer<W: Write> {
+    out: W,
+    // Whether partial sentences are shown.
+    live: bool,
+    // Whether the last line holds a partial sentence, to clear before printing over it.
+    partial: bool,
+    printed: HashSet<u64>,
+    diff: TranscriptDiff,
+    display: DisplayMode,
+}
+
+impl ConsoleRenderer<Stdout> {
+    /// Prints to stdout, live if it's a terminal.
+    pub fn stdout(partial_interval: Duration) -> Self {
+        let out = io::stdout();
+        let live = out.is_terminal();
+        ConsoleRenderer::new(out, live, partial_interval)
+    }
+}
+
+impl<W: Write> ConsoleRenderer<W> {
+    /// Shows a partial sentence again no sooner than `partial_interval` after the last time.
+    pub fn new(out: W, live: bool, partial_interval: Duration) -> Self {
+        ConsoleRenderer {
+            out,
+            live,
+            partial: false,
+            printed: HashSet::new(),
+            diff: TranscriptDiff::new(partial_interval),
+            display: DisplayMode::Both,
+        }
+    }
+
+    /// Shows the text of sentences `display` picks.
+    pub fn with_display(mut self, display: DisplayMode) -> Self {
+        self.display = display;
+        self
+    }
+
+    /// Whether partial sentences are shown.
+    pub fn is_live(&self) -> bool {
+        self.live
+    }
+
+    pub fn render(&mut self, event: &TranscriptionEvent) -> io::Result<()> {
+        if !self.diff.admit(event, Instant::now()) {
+            return Ok(());
+        }
+        match event {
+            TranscriptionEvent::Partial(t)
+                if self.live && !self.printed.contains(&t.sentence_id) =>
+            {
+                write!(
+                    self.out,
+                    "{}{} {}",
+                    CLEAR_LINE,
+                    timestamp(t.begin_time),
+                    tail(&self.display.line(t).to_string(), PARTIAL_CHARS)
+                )?;
+                self.partial = true;
+            }
+            TranscriptionEvent::Partial(_) => return Ok(()),
+            TranscriptionEvent::Final(t) => self.print(t)?,
+            TranscriptionEvent::Finished => self.clear()?,
+        }
+        self.out.flush()
+    }
+
+    /// Prints the sentences of the session result that weren't printed yet, typically
+    /// those finalized while the session finished.
+    pub fn finish(&mut self, result: &[Transcription]) -> io::Result<()> {
+        for transcription in result {
+            self.print(transcription)?;
+        }
+        self.clear()?;
+        self.out.flush()
+    }
+
+    /// Prints `t` for good, unless it was before.
+    fn print(&mut self, t: &Transcription) -> io::Result<()> {
+        if t.text.trim().is_empty() || !self.printed.insert(t.sentence_id) {
+            return Ok(());
+        }
+        self.clear()?;
+        let mut lines = self.display.lines(t).into_iter();
+        if let Some(line) = lines.next() {
+            writeln!(self.out, "{} {}", timestamp(t.begin_time), line)?;
+        }
+        for line in lines {
+            match self.live {
+                true => writeln!(self.out, "{}{}{}{}", INDENT, TRANSLATION_COLOR, line, RESET)?,
+                false => writeln!(self.out, "{}{}", INDENT, line)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        if self.partial {
+            self.out.write_all(CLEAR_LINE.as_bytes())?;
+            self.partial = false;
+        }
+        Ok(())
+    }
+}
+
+/// `[mm:ss]`, with minutes counting on past the hour.
+pub fn timestamp(ms: u64) -> String {
+    format!("[{:02}:{:02}]", ms / 60_000, ms / 1000 % 60)
+}
+
+/// The last `chars` characters of `text`, starting with an ellipsis if it's cut.
+fn tail(text: &str, chars: usize) -> String {
+    let count = text.chars().count();
+    if count <= chars {
+        return text.to_string();
+    }
+    let mut tail = "…".to_string();
+    tail.extend(text.chars().skip(count - chars + 1));
+    tail
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sentence(text: &str, is_final: bool) -> Transcription {
+        Transcription {
+            sentence_id: 0,
+            begin_time: 83_400,
+            end_time: 85_000,
+            text: text.to_string(),
+            is_final,
+            translated_text: is_final.then(|| "你好，世界。".to_string()),
+            translations: vec![],
+            words: vec![],
+            confidence: None,
+            source_label: None,
+        }
+    }
+
+    fn render(live: bool, display: DisplayMode) -> String {
+        let mut out = vec![];
+        let mut console =
+            ConsoleRenderer::new(&mut out, live, Duration::ZERO).with_display(display);
+        for event in [
+            TranscriptionEvent::Partial(sentence("Hello", false)),
+            TranscriptionEvent::Partial(sentence("Hello world", false)),
+            TranscriptionEvent::Final(sentence("Hello world.", true)),
+            // Late updates of a printed sentence are dropped.
+            TranscriptionEvent::Partial(sentence("Hello world", false)),
+            TranscriptionEvent::Final(sentence("Hello world.", true)),
+            TranscriptionEvent::Finished,
+        ] {
+            console.render(&event).unwrap();
+        }
+        let mut last = sentence("Bye.", true);
+        last.sentence_id = 1;
+        last.begin_time = 3_725_000;
+        last.translated_text = None;
+        console
+            .finish(&[sentence("Hello world.", true), last])
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn rewrites_partial_sentences_in_place() {
+        assert_eq!(
+            render(true, DisplayMode::Both),
+            "\r\x1b[2K[01:23] Hello\
+             \r\x1b[2K[01:23] Hello world\
+             \r\x1b[2K[01:23] Hello world.\n        \x1b[36m你好，世界。\x1b[0m\n\
+             [62:05] Bye.\n"
+        );
+    }
+
+    #[test]
+    fn prints_only_final_sentences_off_a_terminal() {
+        assert_eq!(
+            render(false, DisplayMode::Both),
+            "[01:23] Hello world.\n        你好，世界。\n[62:05] Bye.\n"
+        );
+    }
+
+    #[test]
+    fn shows_the_text_the_display_picks() {
+        assert_eq!(
+            render(false, DisplayMode::Source),
+            "[01:23] Hello world.\n[62:05] Bye.\n"
+        );
+        assert_eq!(
+            render(false, DisplayMode::Translation),
+            "[01:23] 你好，世界。\n[62:05] (untranslated) Bye.\n"
+        );
+        // The sentence being recognized has no translation yet.
+        assert!(render(true, DisplayMode::Translation).starts_with(
+            "\r\x1b[2K[01:23] (untranslated) Hello\r\x1b[2K[01:23] (untranslated) Hello world"
+        ));
+    }
+
+    #[test]
+    fn falls_back_to_the_text_without_a_translation() {
+        let mut t = sentence(" Hello world. ", true);
+        assert_eq!(
+            DisplayMode::Both.lines(&t),
+            [
+                ShownLine::Source("Hello world."),
+                ShownLine::Translation("你好，世界。")
+            ]
+        );
+        assert_eq!(
+            DisplayMode::Translation.line(&t),
+            ShownLine::Translation("你好，世界。")
+        );
+        assert_eq!(
+            DisplayMode::Both.line(&t),
+            ShownLine::Source("Hello world.")
+        );
+        for translation in [None, Some(" ".to_string())] {
+            t.translated_text = translation;
+            assert_eq!(
+                DisplayMode::Translation.lines(&t),
+                [ShownLine::Untranslated("Hello world.")]
+            );
+            assert_eq!(
+                DisplayMode::Both.lines(&t),
+                [ShownLine::Source("Hello world.")]
+            );
+        }
+        assert_eq!(
+            ShownLine::Untranslated("Hello world.").to_string(),
+            "(untranslated) Hello world."
+        );
+    }
+
+    #[test]
+    fn shows_the_end_of_long_partial_sentences() {
+        assert_eq!(tail("Hello world", 20), "Hello world");
+        assert_eq!(tail("Hello world", 6), "…world");
+        assert_eq!(tail("你好世界", 3), "…世界");
+    }
+}
@@ -0,0 +1,427 @@
+//! The protocol `st daemon` speaks on its socket, and `st ctl` with it: a JSON object per
+//! line each way. Clients send requests like `{"cmd":"status"}` and get a response like
+//! `{"ok":true,"status":{"state":"idle","sentences":0}}` for each, or
+//! `{"ok":false,"error":".."}`. After `subscribe` the connection carries the events of the
+//! sessions, like `{"event":"final","sentence":{..}}`, until the client disconnects.
+
+use crate::serve::Transcript;
+use async_trait::async_trait;
+use clap::ValueEnum;
+use gummy::Transcription;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::select;
+use tokio::sync::broadcast;
+
+/// A request to the daemon.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Request {
+    /// Tell whether a session is running.
+    Status,
+    /// Start a session.
+    Start,
+    /// Finish the session.
+    Stop,
+    /// Pause the capture of the session.
+    Pause,
+    /// Resume the capture of the session.
+    Resume,
+    /// Get the transcript of the session, or of the last one.
+    GetTranscript,
+    /// Follow the events of the sessions until interrupted.
+    Subscribe,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Idle,
+    Running,
+    Paused,
+}
+
+/// What the daemon is up to.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    pub state: State,
+    /// Device captured from, while a session runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// Final sentences of the session, or of the last one.
+    pub sentences: usize,
+    /// How long until a new task is caught up with the audio held while reconnecting, while
+    /// it's sent, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub catch_up_ms: Option<u64>,
+}
+
+/// The answer to a request.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transcript: Option<Transcript>,
+}
+
+impl Response {
+    pub fn ok() -> Self {
+        Response {
+            ok: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn error(message: impl ToString) -> Self {
+        Response {
+            error: Some(message.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+/// An event of the sessions, for subscribers.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Started { device: String },
+    Partial { sentence: Transcription },
+    Final { sentence: Transcription },
+    Paused,
+    Resumed,
+    Stopped { sentences: usize },
+}
+
+/// What answers the requests of clients.
+#[async_trait]
+pub trait Controller: Send + Sync {
+    async fn handle(&self, request: Request) -> Response;
+
+    /// Events from now on.
+    fn subscribe(&self) -> broadcast::Receiver<Event>;
+}
+
+async fn write_line(out: &mut (impl AsyncWrite + Unpin), value: &impl Serialize) -> io::Result<()> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    out.write_all(&line).await?;
+    out.flush().await
+}
+
+/// Answers the requests coming over `stream` with `controller` until the client
+/// disconnects.
+pub async fn serve(
+    stream: impl AsyncRead + AsyncWrite + Unpin,
+    controller: &dyn Controller,
+) -> io::Result<()> {
+    let (read, mut write) = tokio::io::split(stream);
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                write_line(
+                    &mut write,
+                    &Response::error(format!("invalid request: {}", e)),
+                )
+                .await?;
+                continue;
+            }
+        };
+        if request != Request::Subscribe {
+            write_line(&mut write, &controller.handle(request).await).await?;
+            continue;
+        }
+        // Subscribed before answering, so no event after the answer is missed.
+        let mut events = controller.subscribe();
+        write_line(&mut write, &Response::ok()).await?;
+        loop {
+            select! {
+                event = events.recv() => match event {
+                    Ok(event) => write_line(&mut write, &event).await?,
+                    // A slow client misses what it fell behind on; `get_transcript` has it all.
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                },
+                // What a subscriber sends is ignored until it disconnects.
+                line = lines.next_line() => {
+                    if line?.is_none() {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sends `request` over `stream` and copies the lines that come back to `out`: the
+/// response, and for `subscribe` the events after it until the daemon goes away. Returns
+/// the response.
+pub async fn call(
+    stream: impl AsyncRead + AsyncWrite + Unpin,
+    request: Request,
+    mut out: impl Write,
+) -> io::Result<Response> {
+    let (read, mut write) = tokio::io::split(stream);
+    write_line(&mut write, &request).await?;
+    let mut lines = BufReader::new(read).lines();
+    let Some(line) = lines.next_line().await? else {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "the daemon hung up without answering",
+        ));
+    };
+    writeln!(out, "{}", line)?;
+    let response = serde_json::from_str::<Response>(&line)?;
+    if request == Request::Subscribe && response.ok {
+        while let Some(line) = lines.next_line().await? {
+            writeln!(out, "{}", line)?;
+            out.flush()?;
+        }
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, DuplexStream, duplex};
+
+    fn sentence(sentence_id: u64, text: &str) -> Transcription {
+        Transcription {
+            sentence_id,
+            begin_time: 0,
+            end_time: 900,
+            text: text.to_string(),
+            is_final: true,
+            translated_text: None,
+            translations: vec![],
+            words: vec![],
+            confidence: None,
+            source_label: None,
+        }
+    }
+
+    /// Runs sessions in name only, and records the requests it answers.
+    struct MockController {
+        running: Mutex<bool>,
+        requests: Mutex<Vec<Request>>,
+        events: broadcast::Sender<Event>,
+    }
+
+    impl MockController {
+        fn new() -> Self {
+            MockController {
+                running: Mutex::new(false),
+                requests: Mutex::default(),
+                events: broadcast::channel(16).0,
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Controller for MockController {
+        async fn handle(&self, request: Request) -> Response {
+            self.requests.lock().unwrap().push(request);
+            let mut running = self.running.lock().unwrap();
+            match request {
+                Request::Start if *running => Response::error("a session is already running"),
+                Request::Start => {
+                    *running = true;
+                    Response::ok()
+                }
+                Request::Status => Response {
+                    status: Some(Status {
+                        state: if *running {
+                            State::Running
+                        } else {
+                            State::Idle
+                        },
+                        device: running.then(|| "Speakers".to_string()),
+                        sentences: 0,
+                        catch_up_ms: None,
+                    }),
+                    ..Response::ok()
+                },
+                Request::GetTranscript => Response {
+                    transcript: Some(Transcript {
+                        sentences: vec![sentence(0, "Hello.")],
+                        partial: None,
+                    }),
+                    ..Response::ok()
+                },
+                _ => Response::ok(),
+            }
+        }
+
+        fn subscribe(&self) -> broadcast::Receiver<Event> {
+            self.events.subscribe()
+        }
+    }
+
+    /// Serves `controller` over an in-memory stream, returning the client's end.
+    fn connect(controller: &'static MockController) -> DuplexStream {
+        let (client, server) = duplex(4096);
+        tokio::spawn(async move { serve(server, controller).await.unwrap() });
+        client
+    }
+
+    /// Sends `lines` and reads a line of JSON per line sent.
+    async fn exchange(client: &mut DuplexStream, lines: &[&str]) -> Vec<serde_json::Value> {
+        let mut reader = BufReader::new(client);
+        let mut responses = vec![];
+        for line in lines {
+            reader
+                .get_mut()
+                .write_all(format!("{}\n", line).as_bytes())
+                .await
+                .unwrap();
+            let mut response = String::new();
+            reader.read_line(&mut response).await.unwrap();
+            responses.push(serde_json::from_str(&response).unwrap());
+        }
+        responses
+    }
+
+    #[tokio::test]
+    async fn answers_a_line_per_request() {
+        let controller: &MockController = Box::leak(Box::new(MockController::new()));
+        let mut client = connect(controller);
+        let responses = exchange(
+            &mut client,
+            &[
+                r#"{"cmd":"status"}"#,
+                r#"{"cmd":"start"}"#,
+                r#"{"cmd":"start"}"#,
+                r#"{"cmd":"status"}"#,
+                r#"{"cmd":"get_transcript"}"#,
+            ],
+        )
+        .await;
+        assert_eq!(
+            responses[..4],
+            [
+                serde_json::json!({
+                    "ok": true,
+                    "status": { "state": "idle", "sentences": 0 },
+                }),
+                serde_json::json!({ "ok": true }),
+                serde_json::json!({ "ok": false, "error": "a session is already running" }),
+                serde_json::json!({
+                    "ok": true,
+                    "status": { "state": "running", "device": "Speakers", "sentences": 0 },
+                }),
+            ]
+        );
+        assert_eq!(responses[4]["transcript"]["sentences"][0]["text"], "Hello.");
+        assert_eq!(
+            responses[4]["transcript"]["partial"],
+            serde_json::Value::Null
+        );
+        assert_eq!(
+            *controller.requests.lock().unwrap(),
+            [
+                Request::Status,
+                Request::Start,
+                Request::Start,
+                Request::Status,
+                Request::GetTranscript,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn tells_invalid_requests() {
+        let controller: &MockController = Box::leak(Box::new(MockController::new()));
+        let mut client = connect(controller);
+        let responses = exchange(&mut client, &["status", r#"{"cmd":"rewind"}"#, "{}"]).await;
+        for response in responses {
+            assert_eq!(response["ok"], false);
+            assert!(
+                response["error"]
+                    .as_str()
+                    .unwrap()
+                    .starts_with("invalid request: "),
+                "{}",
+                response
+            );
+        }
+        assert!(controller.requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn streams_events_to_subscribers_until_they_disconnect() {
+        let controller: &MockController = Box::leak(Box::new(MockController::new()));
+        let (client, server) = duplex(4096);
+        let served = tokio::spawn(serve(server, controller));
+        let (read, mut write) = tokio::io::split(client);
+        write.write_all(b"{\"cmd\":\"subscribe\"}\n").await.unwrap();
+        let mut lines = BufReader::new(read).lines();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), r#"{"ok":true}"#);
+
+        controller
+            .events
+            .send(Event::Started {
+                device: "Speakers".to_string(),
+            })
+            .unwrap();
+        controller
+            .events
+            .send(Event::Final {
+                sentence: sentence(0, "Hello."),
+            })
+            .unwrap();
+        assert_eq!(
+            lines.next_line().await.unwrap().unwrap(),
+            r#"{"event":"started","device":"Speakers"}"#
+        );
+        let event: serde_json::Value =
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(event["event"], "final");
+        assert_eq!(event["sentence"]["text"], "Hello.");
+
+        write.shutdown().await.unwrap();
+        served.await.unwrap().unwrap();
+        assert_eq!(controller.events.receiver_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn calls_with_a_request_and_copies_the_response() {
+        let controller: &MockController = Box::leak(Box::new(MockController::new()));
+        let mut out = vec![];
+        let response = call(connect(controller), Request::Stop, &mut out)
+            .await
+            .unwrap();
+        assert_eq!(response, Response::ok());
+        assert_eq!(String::from_utf8(out).unwrap(), "{\"ok\":true}\n");
+
+        // Events are copied until the daemon hangs up.
+        let (client, mut server) = duplex(4096);
+        let daemon = tokio::spawn(async move {
+            let mut request = [0; 20];
+            server.read_exact(&mut request).await.unwrap();
+            assert_eq!(&request, b"{\"cmd\":\"subscribe\"}\n");
+            server
+                .write_all(b"{\"ok\":true}\n{\"event\":\"paused\"}\n")
+                .await
+                .unwrap();
+        });
+        let mut out = vec![];
+        call(client, Request::Subscribe, &mut out).await.unwrap();
+        daemon.await.unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"ok\":true}\n{\"event\":\"paused\"}\n"
+        );
+    }
+}
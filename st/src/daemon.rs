@@ -0,0 +1,737 @@
+//! `st daemon`: capture and transcription run headless, controlled over a Unix socket, or a
+//! named pipe on Windows, with the protocol of `control`. `st ctl` is its client.
+//!
+//! A session at a time runs, from `start` to `stop`, capturing as the options say. Each
+//! `--output` file is written anew for each session.
+
+use crate::args::Args;
+use crate::control::{self, Controller, Event, Request, Response, State, Status};
+use crate::failure::{Failure, Kind, fail};
+use crate::latency::LatencyMeter;
+use crate::notify::{Notice, Notifications};
+use crate::output::{SessionInfo, Usage};
+use crate::serve::Transcript;
+use crate::sink::Sinks;
+use crate::summary::{self, Summary};
+use crate::supervisor::SupervisedSession;
+use crate::timeline::Timeline;
+use crate::transcriber::Transcriber;
+use crate::{
+    FINISH_TIMEOUT, FIRST_FRAME_TIMEOUT, KEEPALIVE_INTERVAL, Resampling, apply_args, audio_ms,
+    now_ms, print_capture_devices, recorder_config, shutdown_signal, start_options,
+    transcript_files, verify_format,
+};
+use async_trait::async_trait;
+use audio::health::SignalWarning;
+use audio::recorder::{CpalRecorder, RecorderEvent, SampleData, Started};
+use gummy::TranscriptionEvent;
+use log::{debug, error, info, warn};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::select;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::time::{Interval, interval, timeout};
+
+/// Events kept for subscribers that fall behind, before they miss some.
+const EVENT_BACKLOG: usize = 256;
+
+/// Where the daemon listens without `--socket`: in the runtime directory, or next to the
+/// config file.
+#[cfg(unix)]
+pub fn default_socket() -> Option<PathBuf> {
+    match std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        Some(dir) => Some(PathBuf::from(dir).join("st.sock")),
+        None => Some(crate::config::default_path()?.with_file_name("daemon.sock")),
+    }
+}
+
+/// Where the daemon listens without `--socket`: a pipe named after the user.
+#[cfg(windows)]
+pub fn default_socket() -> Option<PathBuf> {
+    let user = std::env::var("USERNAME").unwrap_or_default();
+    Some(PathBuf::from(format!(r"\\.\pipe\st-{}", user)))
+}
+
+/// `socket`, or the default one.
+fn socket_path(socket: Option<&Path>) -> PathBuf {
+    socket
+        .map(Path::to_path_buf)
+        .or_else(default_socket)
+        .unwrap_or_else(|| {
+            fail(
+                Kind::Config,
+                "No home directory for the socket, pass --socket",
+            )
+        })
+}
+
+/// Sends `request` to the daemon listening on `socket` and prints what comes back, failing
+/// when the daemon turns it down.
+pub async fn ctl(request: Request, socket: Option<&Path>) {
+    let path = socket_path(socket);
+    #[cfg(unix)]
+    let stream = tokio::net::UnixStream::connect(&path).await;
+    #[cfg(windows)]
+    let stream = tokio::net::windows::named_pipe::ClientOptions::new().open(&path);
+    let stream = stream.unwrap_or_else(|e| {
+        fail(
+            Kind::Other,
+            format!("No daemon listening on {}: {}", path.display(), e),
+        )
+    });
+    match control::call(stream, request, std::io::stdout()).await {
+        Ok(response) if response.ok => {}
+        Ok(response) => fail(Kind::Other, response.error.unwrap_or_default()),
+        Err(e) => fail(Kind::Other, format!("Failed to talk to the daemon: {}", e)),
+    }
+}
+
+/// Hands the requests of clients over to the daemon, which can't leave the task it runs
+/// on since capture streams can't be sent between threads.
+struct Handle {
+    requests: mpsc::Sender<(Request, oneshot::Sender<Response>)>,
+    events: broadcast::Sender<Event>,
+}
+
+#[async_trait]
+impl Controller for Handle {
+    async fn handle(&self, request: Request) -> Response {
+        let (reply, response) = oneshot::channel();
+        if self.requests.send((request, reply)).await.is_err() {
+            return Response::error("the daemon is shutting down");
+        }
+        response
+            .await
+            .unwrap_or_else(|_| Response::error("the daemon is shutting down"))
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+}
+
+/// Listens on `path`, replacing a socket left behind by a daemon that's gone. Only the user
+/// running the daemon can connect.
+#[cfg(unix)]
+fn listen(path: &Path) -> std::io::Result<tokio::net::UnixListener> {
+    use std::fs::{Permissions, set_permissions};
+    use std::io;
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let listener = match tokio::net::UnixListener::bind(path) {
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    "a daemon is already listening",
+                ));
+            }
+            std::fs::remove_file(path)?;
+            tokio::net::UnixListener::bind(path)?
+        }
+        listener => listener?,
+    };
+    set_permissions(path, Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Serves the clients connecting to `listener`, turning away other users than the owner of
+/// the socket in case its permissions are loosened.
+#[cfg(unix)]
+async fn accept(listener: tokio::net::UnixListener, path: PathBuf, handle: Arc<Handle>) {
+    use std::os::unix::fs::MetadataExt;
+
+    let owner = match std::fs::metadata(&path) {
+        Ok(metadata) => metadata.uid(),
+        Err(e) => {
+            error!("Failed to read the owner of {}: {}", path.display(), e);
+            return;
+        }
+    };
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("Failed to accept a connection: {}", e);
+                continue;
+            }
+        };
+        match stream.peer_cred() {
+            Ok(peer) if peer.uid() == owner => {}
+            Ok(peer) => {
+                warn!("Turned away a connection from user {}", peer.uid());
+                continue;
+            }
+            Err(e) => {
+                warn!("Failed to tell who connected: {}", e);
+                continue;
+            }
+        }
+        let handle = handle.clone();
+        tokio::spawn(async move {
+            if let Err(e) = control::serve(stream, handle.as_ref()).await {
+                debug!("Client connection failed: {}", e);
+            }
+        });
+    }
+}
+
+/// Serves the clients connecting to the pipe at `path`. A pipe only lets the user who
+/// created it, administrators and the system write to it unless told otherwise, so other
+/// users can't send requests.
+#[cfg(windows)]
+async fn accept(path: PathBuf, handle: Arc<Handle>) {
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let mut server = match ServerOptions::new()
+        .first_pipe_instance(true)
+        .reject_remote_clients(true)
+        .create(&path)
+    {
+        Ok(server) => server,
+        Err(e) => fail(
+            Kind::Other,
+            format!("Failed to listen on {}: {}", path.display(), e),
+        ),
+    };
+    loop {
+        if let Err(e) = server.connect().await {
+            warn!("Failed to accept a connection: {}", e);
+            continue;
+        }
+        let next = match ServerOptions::new()
+            .reject_remote_clients(true)
+            .create(&path)
+        {
+            Ok(next) => next,
+            Err(e) => {
+                error!("Failed to listen on {}: {}", path.display(), e);
+                return;
+            }
+        };
+        let stream = std::mem::replace(&mut server, next);
+        let handle = handle.clone();
+        tokio::spawn(async move {
+            if let Err(e) = control::serve(stream, handle.as_ref()).await {
+                debug!("Client connection failed: {}", e);
+            }
+        });
+    }
+}
+
+/// What a running session waits for.
+enum Step {
+    Recorder(Option<RecorderEvent>),
+    Session(anyhow::Result<Option<TranscriptionEvent>>),
+    Keepalive,
+}
+
+/// A running session and the capture feeding it.
+struct Capture {
+    recorder: CpalRecorder<Started>,
+    session: SupervisedSession,
+    resampling: Resampling,
+    sample_rate: u32,
+    session_info: SessionInfo,
+    sinks: Sinks,
+    latency: LatencyMeter,
+    timeline: Timeline,
+    // Bytes of audio sent, and of the silence among them sent to keep the connection open.
+    sent_bytes: usize,
+    keepalive_bytes: usize,
+    // While silence is skipped or the capture is paused, a short frame of silence now and
+    // then keeps the backend from timing out the session.
+    keepalive: Interval,
+    silent: bool,
+    started: Instant,
+    // Whether the session was connected when last checked, to notify when it changes.
+    connected: bool,
+}
+
+impl Capture {
+    /// Waits for what the session has to be fed or has to say.
+    async fn next(&mut self) -> Step {
+        let idle = self.silent || self.recorder.is_paused();
+        select! {
+            _ = self.keepalive.tick(), if idle => Step::Keepalive,
+            event = self.recorder.recv_event() => Step::Recorder(event),
+            event = self.session.next_event() => Step::Session(event),
+        }
+    }
+
+    /// Sends `sample_data`, captured unless it's silence to keep the connection open.
+    async fn send(&mut self, sample_data: &SampleData, captured: bool) -> anyhow::Result<usize> {
+        let samples = self.resampling.process(&sample_data.data);
+        self.session.send_samples(samples).await?;
+        let sent_ms = audio_ms(self.sent_bytes, self.sample_rate);
+        self.latency.sent(sent_ms, sample_data.timestamp);
+        self.timeline.sent(sent_ms, sample_data.timestamp, captured);
+        self.sent_bytes += size_of_val(samples);
+        Ok(size_of_val(samples))
+    }
+
+    async fn send_keepalive(&mut self) {
+        let silence = SampleData {
+            data: vec![0; self.recorder.output_format().sample_rate as usize / 10],
+            timestamp: now_ms(),
+        };
+        match self.send(&silence, false).await {
+            Ok(sent) => self.keepalive_bytes += sent,
+            Err(e) => warn!("Failed to send keepalive: {}", e),
+        }
+    }
+
+    /// Takes in an event of the recorder, failing when capture can't go on.
+    async fn record(&mut self, event: Option<RecorderEvent>) -> Result<(), Failure> {
+        match event {
+            Some(RecorderEvent::Sample(sample_data)) => {
+                if let Err(e) = self.send(&sample_data, true).await {
+                    return Err(Failure::new(
+                        Kind::of_session(&e),
+                        format!("Failed to send audio: {:#}", e),
+                    ));
+                }
+            }
+            Some(RecorderEvent::SilenceStarted) => {
+                debug!("Silence started");
+                self.silent = true;
+                self.keepalive.reset();
+            }
+            Some(RecorderEvent::SilenceEnded) => {
+                debug!("Silence ended");
+                self.silent = false;
+            }
+            Some(RecorderEvent::Error(e)) => warn!("Recorder error: {}", e),
+            Some(RecorderEvent::Warning(warning)) => {
+                warn!("{}", warning);
+                let kind = match warning {
+                    SignalWarning::ClippingDetected { .. } => "clipping",
+                    SignalWarning::DropoutDetected { .. } => "dropout",
+                };
+                self.sinks.warning(kind, &warning.to_string());
+            }
+            Some(RecorderEvent::DeviceChanged { old, new }) => {
+                info!("Capture moved from {} to {}", old, new);
+            }
+            Some(RecorderEvent::DeviceLost) => {
+                return Err(Failure::new(Kind::Device, "Capture device lost"));
+            }
+            Some(RecorderEvent::Fatal(e)) => {
+                return Err(Failure::new(
+                    Kind::Device,
+                    format!("Recorder failed: {}", e),
+                ));
+            }
+            None => return Err(Failure::new(Kind::Device, "The capture stopped")),
+        }
+        Ok(())
+    }
+}
+
+/// The daemon: the session running if any, and the transcript of the latest one.
+struct Daemon<'a> {
+    args: &'a Args,
+    transcriber: Arc<dyn Transcriber>,
+    capture: Option<Capture>,
+    transcript: Transcript,
+    events: broadcast::Sender<Event>,
+    notifications: Option<Notifications>,
+}
+
+impl Daemon<'_> {
+    async fn handle(&mut self, request: Request) -> Response {
+        let result = match request {
+            Request::Status => {
+                return Response {
+                    status: Some(self.status()),
+                    ..Response::ok()
+                };
+            }
+            Request::GetTranscript => {
+                return Response {
+                    transcript: Some(self.transcript.clone()),
+                    ..Response::ok()
+                };
+            }
+            Request::Start => self.start().await,
+            Request::Stop if self.capture.is_none() => Err("no session is running".to_string()),
+            Request::Stop => {
+                self.stop(None).await;
+                Ok(())
+            }
+            Request::Pause => self.pause(true),
+            Request::Resume => self.pause(false),
+            // Subscriptions are taken care of by the protocol.
+            Request::Subscribe => Ok(()),
+        };
+        match result {
+            Ok(()) => Response::ok(),
+            Err(message) => Response::error(message),
+        }
+    }
+
+    fn status(&mut self) -> Status {
+        let state = match &self.capture {
+            Some(capture) if capture.recorder.is_paused() => State::Paused,
+            Some(_) => State::Running,
+            None => State::Idle,
+        };
+        Status {
+            state,
+            device: self
+                .capture
+                .as_ref()
+                .map(|capture| capture.recorder.device_name().to_string()),
+            sentences: self.transcript.sentences.len(),
+            catch_up_ms: self.capture.as_ref().and_then(|capture| {
+                let remaining = capture.session.catch_up_remaining()?;
+                Some(remaining.as_millis() as u64)
+            }),
+        }
+    }
+
+    async fn start(&mut self) -> Result<(), String> {
+        if self.capture.is_some() {
+            return Err("a session is already running".to_string());
+        }
+        let args = self.args;
+        let mut config = recorder_config(args);
+        apply_args(&mut config, args, self.transcriber.as_ref());
+        let recorder = CpalRecorder::with_config(config)
+            .start()
+            .map_err(|e| format!("Failed to start recorder: {}", e))?;
+        print_capture_devices(recorder.devices());
+        let recorder_format = recorder.output_format();
+        let recorder_rate = recorder_format.sample_rate;
+        let sample_rate = self
+            .transcriber
+            .preferred_sample_rate()
+            .unwrap_or(recorder_rate);
+        let options = start_options(args, sample_rate);
+        verify_format(&recorder_format, sample_rate, &options, args.force)?;
+        let warm_up = recorder
+            .wait_for_first_frame(FIRST_FRAME_TIMEOUT)
+            .await
+            .map_err(|e| e.to_string())?;
+        info!(
+            "Audio came {} ms after starting the capture",
+            warm_up.as_millis()
+        );
+        let session = self
+            .transcriber
+            .start(options.clone())
+            .await
+            .map_err(|e| format!("Failed to start the transcription session: {:#}", e))?;
+        let session = SupervisedSession::new(
+            self.transcriber.clone(),
+            options.clone(),
+            session,
+            sample_rate,
+            args.catch_up_speed,
+        );
+        if let Some(task_id) = session.task_ids().first() {
+            info!("Started task {}", task_id);
+        }
+        let mut sinks = Sinks::default();
+        let timeline = Timeline::default();
+        for (path, format) in args.transcripts() {
+            // Stdout isn't watched by anyone.
+            let Some(path) = path else { continue };
+            match transcript_files(args, Some(path), format, &timeline) {
+                Ok(files) => {
+                    for file in files {
+                        sinks.push(file);
+                    }
+                }
+                Err(message) => warn!("{}", message),
+            }
+        }
+        if let Some(cleanup) = &args.cleanup {
+            let language = options.source_language.clone();
+            sinks.clean_up(cleanup.clone(), language.filter(|l| l != "auto"));
+        }
+        let session_info = SessionInfo {
+            started_at_ms: now_ms(),
+            sample_rate,
+            source_language: options
+                .source_language
+                .filter(|language| language != "auto"),
+            target_languages: options.target_languages,
+            model: self.transcriber.model(),
+            task_ids: session.task_ids().to_vec(),
+            usage: Usage::default(),
+            summary: None,
+        };
+        let device = recorder.device_name().to_string();
+        sinks.start(&session_info, &device);
+        let mut capture = Capture {
+            resampling: Resampling::new(recorder_rate, sample_rate),
+            recorder,
+            session,
+            sample_rate,
+            session_info,
+            sinks,
+            latency: LatencyMeter::default(),
+            timeline,
+            sent_bytes: 0,
+            keepalive_bytes: 0,
+            keepalive: interval(KEEPALIVE_INTERVAL),
+            silent: false,
+            started: Instant::now(),
+            connected: true,
+        };
+        for sample_data in capture.recorder.take_preroll() {
+            if let Err(e) = capture.send(&sample_data, true).await {
+                warn!("Failed to send the pre-roll: {}", e);
+                break;
+            }
+        }
+        info!("Session started, capturing from {}", device);
+        if let Some(notifications) = &mut self.notifications {
+            let device = device.clone();
+            notifications.notify(Notice::Started { device }, Instant::now());
+        }
+        self.capture = Some(capture);
+        self.transcript = Transcript::default();
+        let _ = self.events.send(Event::Started { device });
+        Ok(())
+    }
+
+    /// Pauses the capture of the session, or resumes it.
+    fn pause(&mut self, pause: bool) -> Result<(), String> {
+        let Some(capture) = &mut self.capture else {
+            return Err("no session is running".to_string());
+        };
+        if pause {
+            capture
+                .recorder
+                .pause()
+                .map_err(|e| format!("Failed to pause recorder: {}", e))?;
+            capture.keepalive.reset();
+            info!("Capture paused");
+            let _ = self.events.send(Event::Paused);
+        } else {
+            capture
+                .recorder
+                .resume()
+                .map_err(|e| format!("Failed to resume recorder: {}", e))?;
+            info!("Capture resumed");
+            let _ = self.events.send(Event::Resumed);
+        }
+        Ok(())
+    }
+
+    /// Takes in what the running session waited for.
+    async fn step(&mut self, step: Step) {
+        let Some(capture) = &mut self.capture else {
+            return;
+        };
+        match step {
+            Step::Keepalive => capture.send_keepalive().await,
+            Step::Recorder(event) => {
+                if let Err(failure) = capture.record(event).await {
+                    self.stop(Some(failure)).await;
+                    return;
+                }
+            }
+            Step::Session(Ok(Some(event))) => {
+                debug!("Message: {:?}", event);
+                capture.latency.update(&event, now_ms());
+                if let Some(sent_ms) = capture.session.reconnected_at() {
+                    capture.timeline.reconnected(sent_ms);
+                }
+                capture.sinks.on_event(&event).await;
+                self.transcribed(&event);
+            }
+            Step::Session(Ok(None)) => return self.stop(None).await,
+            Step::Session(Err(e)) => {
+                let failure = Failure::new(
+                    Kind::of_session(&e),
+                    format!("Transcription failed: {:#}", e),
+                );
+                return self.stop(Some(failure)).await;
+            }
+        }
+        if let Some(capture) = &mut self.capture
+            && let Some(notifications) = &mut self.notifications
+            && capture.session.is_connected() != capture.connected
+        {
+            capture.connected = !capture.connected;
+            let notice = match capture.connected {
+                true => Notice::Reconnected,
+                false => Notice::Reconnecting,
+            };
+            notifications.notify(notice, Instant::now());
+        }
+    }
+
+    fn transcribed(&mut self, event: &TranscriptionEvent) {
+        if !self.transcript.update(event) {
+            return;
+        }
+        let event = match event {
+            TranscriptionEvent::Partial(sentence) => Event::Partial {
+                sentence: sentence.clone(),
+            },
+            TranscriptionEvent::Final(sentence) => Event::Final {
+                sentence: sentence.clone(),
+            },
+            TranscriptionEvent::Finished => return,
+        };
+        let _ = self.events.send(event);
+    }
+
+    /// Finishes the running session, which ended early with `failure` if any, and writes its
+    /// transcripts.
+    async fn stop(&mut self, failure: Option<Failure>) {
+        let Some(mut capture) = self.capture.take() else {
+            return;
+        };
+        // Audio captured just before stopping is still queued.
+        let remaining = match capture.recorder.stop() {
+            Ok((_, remaining)) => remaining,
+            Err(e) => {
+                warn!("Failed to stop recorder: {}", e);
+                vec![]
+            }
+        };
+        let mut resampling = capture.resampling;
+        let mut session = capture.session;
+        let task_ids = session.task_ids().to_vec();
+        if let Some(sent_ms) = session.reconnected_at() {
+            capture.timeline.reconnected(sent_ms);
+        }
+        for sample_data in remaining {
+            let samples = resampling.process(&sample_data.data);
+            if let Err(e) = session.send_samples(samples).await {
+                debug!("Failed to send the remaining audio: {}", e);
+                break;
+            }
+            capture.sent_bytes += size_of_val(samples);
+        }
+        let (result, usage) = match timeout(FINISH_TIMEOUT, session.finish()).await {
+            Ok(Ok((result, usage))) => {
+                for transcription in &result {
+                    let event = TranscriptionEvent::Final(transcription.clone());
+                    capture.sinks.on_event(&event).await;
+                    self.transcribed(&event);
+                }
+                capture.sinks.on_event(&TranscriptionEvent::Finished).await;
+                (result, usage)
+            }
+            Ok(Err(e)) => {
+                warn!("Failed to finish the session: {}", e);
+                (vec![], None)
+            }
+            Err(_) => {
+                warn!(
+                    "The session didn't finish within {} s, the last sentences may be missing",
+                    FINISH_TIMEOUT.as_secs()
+                );
+                (vec![], None)
+            }
+        };
+        let sample_rate = capture.sample_rate;
+        let mut session_info = capture.session_info;
+        session_info.task_ids = task_ids;
+        session_info.usage = Usage {
+            audio_ms: audio_ms(capture.sent_bytes, sample_rate),
+        };
+        let summary = Summary::new(
+            audio_ms(capture.sent_bytes - capture.keepalive_bytes, sample_rate),
+            summary::billed_ms(usage, capture.sent_bytes as u64, sample_rate as u64 * 2),
+            &result,
+            self.args.pricing().as_ref(),
+            capture.latency.average(),
+        );
+        session_info.summary = Some(summary.clone());
+        capture.sinks.finalize(&result, &session_info).await;
+        eprintln!("{}", summary);
+        if let Some(failure) = &failure {
+            error!("{}", failure.message);
+        }
+        if let Some(notifications) = &mut self.notifications {
+            let notice = match failure {
+                Some(failure) => Notice::Failed {
+                    kind: failure.kind,
+                    reason: failure.message,
+                },
+                None => Notice::Finished {
+                    duration: capture.started.elapsed(),
+                    output: self
+                        .args
+                        .transcripts()
+                        .into_iter()
+                        .find_map(|(path, _)| path.map(Path::to_path_buf)),
+                },
+            };
+            notifications.notify(notice, Instant::now());
+        }
+        let _ = self.events.send(Event::Stopped {
+            sentences: self.transcript.sentences.len(),
+        });
+    }
+}
+
+/// Runs the daemon on `socket` until interrupted, finishing the session running then.
+pub async fn run(args: &Args, transcriber: Arc<dyn Transcriber>, socket: Option<&Path>) {
+    let path = socket_path(socket);
+    let (requests, mut incoming) = mpsc::channel(16);
+    let (events, _) = broadcast::channel(EVENT_BACKLOG);
+    let handle = Arc::new(Handle {
+        requests,
+        events: events.clone(),
+    });
+    #[cfg(unix)]
+    {
+        let listener = listen(&path).unwrap_or_else(|e| {
+            fail(
+                Kind::Other,
+                format!("Failed to listen on {}: {}", path.display(), e),
+            )
+        });
+        tokio::spawn(accept(listener, path.clone(), handle));
+    }
+    #[cfg(windows)]
+    tokio::spawn(accept(path.clone(), handle));
+    eprintln!("Listening on {}", path.display());
+
+    let mut daemon = Daemon {
+        args,
+        transcriber,
+        capture: None,
+        transcript: Transcript::default(),
+        events,
+        notifications: Notifications::from_args(args),
+    };
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        select! {
+            Some((request, reply)) = incoming.recv() => {
+                debug!("Request: {:?}", request);
+                let _ = reply.send(daemon.handle(request).await);
+            },
+            step = async { Some(daemon.capture.as_mut()?.next().await) },
+                if daemon.capture.is_some() =>
+            {
+                if let Some(step) = step {
+                    daemon.step(step).await;
+                }
+            },
+            _ = &mut shutdown => {
+                info!("Interrupted, finishing the session");
+                break;
+            },
+        }
+    }
+    daemon.stop(None).await;
+    #[cfg(unix)]
+    if let Err(e) = std::fs::remove_file(&path) {
+        warn!("Failed to remove {}: {}", path.display(), e);
+    }
+}
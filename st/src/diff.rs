@@ -0,0 +1,141 @@
+//! `--partial-interval`: which updates of the sentences being recognized are shown, so
+//! captions don't flicker through every partial result the backend sends.
+
+use gummy::{Transcription, TranscriptionEvent};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// The last update of a sentence let through.
+struct Emitted {
+    text: String,
+    translated_text: Option<String>,
+    is_final: bool,
+    at: Instant,
+}
+
+impl Emitted {
+    fn of(t: &Transcription, is_final: bool, at: Instant) -> Self {
+        Emitted {
+            text: t.text.clone(),
+            translated_text: t.translated_text.clone(),
+            is_final,
+            at,
+        }
+    }
+
+    fn differs(&self, t: &Transcription) -> bool {
+        self.text != t.text || self.translated_text != t.translated_text
+    }
+}
+
+/// Lets through the updates of a sentence that change it: the first final one, a final one
+/// correcting it, and partial ones with new text or translation, no sooner than `interval`
+/// after the last one let through. Partial updates of a final sentence are late, and never
+/// let through.
+pub struct TranscriptDiff {
+    interval: Duration,
+    sentences: HashMap<u64, Emitted>,
+}
+
+impl TranscriptDiff {
+    pub fn new(interval: Duration) -> Self {
+        TranscriptDiff {
+            interval,
+            sentences: HashMap::new(),
+        }
+    }
+
+    /// Whether `event`, coming in at `now`, is shown. Those let through are remembered.
+    pub fn admit(&mut self, event: &TranscriptionEvent, now: Instant) -> bool {
+        let t = match event {
+            TranscriptionEvent::Partial(t) | TranscriptionEvent::Final(t) => t,
+            TranscriptionEvent::Finished => return true,
+        };
+        let is_final = matches!(event, TranscriptionEvent::Final(_));
+        let admitted = match self.sentences.get(&t.sentence_id) {
+            None => true,
+            Some(last) if is_final => !last.is_final || last.differs(t),
+            Some(last) => {
+                !last.is_final && last.differs(t) && now.duration_since(last.at) >= self.interval
+            }
+        };
+        if admitted {
+            self.sentences
+                .insert(t.sentence_id, Emitted::of(t, is_final, now));
+        }
+        admitted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sentence(sentence_id: u64, text: &str) -> Transcription {
+        Transcription {
+            sentence_id,
+            begin_time: 0,
+            end_time: 0,
+            text: text.to_string(),
+            is_final: false,
+            translated_text: None,
+            translations: vec![],
+            words: vec![],
+            confidence: None,
+            source_label: None,
+        }
+    }
+
+    fn partial(sentence_id: u64, text: &str) -> TranscriptionEvent {
+        TranscriptionEvent::Partial(sentence(sentence_id, text))
+    }
+
+    fn final_(sentence_id: u64, text: &str) -> TranscriptionEvent {
+        let mut t = sentence(sentence_id, text);
+        t.is_final = true;
+        TranscriptionEvent::Final(t)
+    }
+
+    /// Which of `updates`, each coming in that many milliseconds into the session, are
+    /// let through with `interval_ms` between partial updates.
+    fn admitted(interval_ms: u64, updates: &[(u64, TranscriptionEvent)]) -> Vec<usize> {
+        let start = Instant::now();
+        let mut diff = TranscriptDiff::new(Duration::from_millis(interval_ms));
+        updates
+            .iter()
+            .enumerate()
+            .filter(|(_, (ms, event))| diff.admit(event, start + Duration::from_millis(*ms)))
+            .map(|(n, _)| n)
+            .collect()
+    }
+
+    #[test]
+    fn lets_through_changes_no_more_often_than_the_interval() {
+        let mut translated = sentence(0, "Hello world");
+        translated.translated_text = Some("你好世界".to_string());
+        let updates = [
+            (0, partial(0, "Hel")),
+            // Too soon after the last one.
+            (100, partial(0, "Hello")),
+            // Unchanged.
+            (300, partial(0, "Hel")),
+            (350, partial(0, "Hello world")),
+            // Other sentences keep their own time.
+            (400, partial(1, "And")),
+            (500, partial(1, "And then")),
+            (700, TranscriptionEvent::Partial(translated)),
+            // Final, however soon and whatever the text.
+            (720, final_(0, "Hello world")),
+            // Late: the sentence is final.
+            (1_000, partial(0, "Hello world, again")),
+            (1_100, final_(0, "Hello world")),
+            // A correction of the final sentence.
+            (1_200, final_(0, "Hello, world.")),
+            (1_300, final_(1, "And then.")),
+            (1_310, TranscriptionEvent::Finished),
+        ];
+        assert_eq!(admitted(250, &updates), [0, 3, 4, 6, 7, 10, 11, 12]);
+        // Without an interval only unchanged and late updates are held back.
+        assert_eq!(admitted(0, &updates), [0, 1, 2, 3, 4, 5, 6, 7, 10, 11, 12]);
+    }
+}
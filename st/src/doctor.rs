@@ -0,0 +1,674 @@
+//! `st doctor`: checks, one after the other, what a session needs — an API key, the backend
+//! within reach, audio to capture and somewhere to write to — and tells how to fix what
+//! fails. Each check is a function of its own returning a `Check`. The network and the
+//! audio system are reached through `Network` and `Audio`, for the checks to be tried on
+//! stand-ins.
+
+use crate::args::{Args, Backend, Source, is_stdout};
+use crate::{FIRST_FRAME_TIMEOUT, config, openai, recorder_config, system_device};
+use async_trait::async_trait;
+use audio::level::rms_dbfs;
+use audio::recorder::{CaptureSource, CpalRecorder, RecorderConfig};
+use audio::source::SampleSource;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::{Instant, timeout, timeout_at};
+use tungstenite::http::Uri;
+
+/// How long the endpoint gets to take a connection and shake hands over TLS.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How much audio the capture check listens to.
+const CAPTURE_DURATION: Duration = Duration::from_secs(3);
+
+/// What a check found.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Check {
+    pub name: &'static str,
+    pub passed: bool,
+    /// Whether st can't work while the check fails. Other failures are warnings, like
+    /// silence captured while nothing plays.
+    pub hard: bool,
+    /// What was found, or what went wrong.
+    pub detail: String,
+    /// How to fix a failure.
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Check {
+            name,
+            passed: true,
+            hard: false,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Check {
+            name,
+            passed: false,
+            hard: true,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Check {
+            hard: false,
+            ..Check::fail(name, detail, hint)
+        }
+    }
+
+    /// Whether the check failed in a way st can't work with.
+    pub fn failed_hard(&self) -> bool {
+        !self.passed && self.hard
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mark = if self.passed { "✓" } else { "✗" };
+        write!(f, "{} {}: {}", mark, self.name, self.detail)?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n  {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reaches the backend's endpoint.
+#[async_trait]
+pub trait Network {
+    /// Connects to `host` on `port`, shakes hands over TLS with `tls`, and hangs up.
+    async fn connect(&self, host: &str, port: u16, tls: bool) -> Result<(), String>;
+}
+
+/// The network the system is on.
+pub struct Internet;
+
+#[async_trait]
+impl Network for Internet {
+    async fn connect(&self, host: &str, port: u16, tls: bool) -> Result<(), String> {
+        let stream = TcpStream::connect((host, port))
+            .await
+            .map_err(|e| format!("Failed to connect to {}:{}: {}", host, port, e))?;
+        if tls {
+            let connector = tokio_native_tls::native_tls::TlsConnector::new()
+                .map_err(|e| format!("Failed to set up TLS: {}", e))?;
+            tokio_native_tls::TlsConnector::from(connector)
+                .connect(host, stream)
+                .await
+                .map_err(|e| format!("TLS handshake with {} failed: {}", host, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// The audio system captured from.
+pub trait Audio {
+    /// Names of the hosts with devices, with how many each has.
+    fn hosts(&self) -> Result<Vec<(String, usize)>, String>;
+
+    /// Starts capturing from the devices a session would capture from.
+    fn open(&self) -> Result<Box<dyn SampleSource>, String>;
+}
+
+/// The audio system of the machine, capturing as the options of a session would.
+pub struct System {
+    config: RecorderConfig,
+}
+
+impl System {
+    pub fn new(args: &Args) -> Self {
+        let mut config = match args.source {
+            // Separate sources capture from the devices a mixed one does, each on its own.
+            Source::Separate => RecorderConfig {
+                source: CaptureSource::Mixed {
+                    microphone: None,
+                    system_gain: 1.0,
+                    microphone_gain: 1.0,
+                },
+                device: system_device(args),
+                ..Default::default()
+            },
+            _ => recorder_config(args),
+        };
+        if let Some(sample_rate) = args.sample_rate {
+            config.target_sample_rate = sample_rate;
+        }
+        config.buffer_size = args.buffer_size;
+        config.gain_db = args.gain_db;
+        System { config }
+    }
+}
+
+impl Audio for System {
+    fn hosts(&self) -> Result<Vec<(String, usize)>, String> {
+        let devices = CpalRecorder::list_devices().map_err(|e| e.to_string())?;
+        let mut hosts: Vec<(String, usize)> = vec![];
+        for device in devices {
+            let name = device.host.name();
+            match hosts.iter_mut().find(|(host, _)| host == name) {
+                Some((_, count)) => *count += 1,
+                None => hosts.push((name.to_string(), 1)),
+            }
+        }
+        Ok(hosts)
+    }
+
+    fn open(&self) -> Result<Box<dyn SampleSource>, String> {
+        match CpalRecorder::with_config(self.config.clone()).start() {
+            Ok(recorder) => Ok(Box::new(recorder)),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+/// `key` with all but its ends hidden.
+fn masked(key: &str) -> String {
+    let chars = key.chars().collect::<Vec<_>>();
+    if chars.len() < 12 {
+        return "…".to_string();
+    }
+    let start = chars[..3].iter().collect::<String>();
+    let end = chars[chars.len() - 4..].iter().collect::<String>();
+    format!("{}…{}", start, end)
+}
+
+/// Whether `key` looks like a key of DashScope or OpenAI: `sk-` and a few dozen letters,
+/// digits, dashes or underscores.
+fn plausible(key: &str) -> bool {
+    key.len() >= 20
+        && key.starts_with("sk-")
+        && (key.chars()).all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Whether there's an API key for the backend, from `--api-key` or `--api-key-file`, and
+/// whether it looks like one.
+pub fn check_api_key(args: &Args) -> Check {
+    const NAME: &str = "API key";
+    if args.backend == Backend::Whisper {
+        return Check::pass(NAME, "not needed by the whisper backend");
+    }
+    let key = match (&args.api_key, &args.api_key_file) {
+        (Some(key), _) => key.clone(),
+        (None, Some(path)) => match std::fs::read_to_string(path) {
+            Ok(key) => key.trim().to_string(),
+            Err(e) => {
+                return Check::fail(
+                    NAME,
+                    format!("Failed to read {}: {}", path.display(), e),
+                    "point --api-key-file, or api_key_file in the config file, at the file \
+                     holding the key",
+                );
+            }
+        },
+        (None, None) => {
+            return Check::fail(
+                NAME,
+                "none given",
+                format!(
+                    "pass --api-key or --api-key-file, set {}, or set api_key_file in the \
+                     config file",
+                    config::api_key_var(args.backend)
+                ),
+            );
+        }
+    };
+    if key.is_empty() {
+        return Check::fail(NAME, "empty", "put the key itself in, without quotes");
+    }
+    let detail = format!("{} ({} characters)", masked(&key), key.chars().count());
+    if !plausible(&key) {
+        return Check::warn(
+            NAME,
+            format!("{} doesn't look like a key", detail),
+            "keys start with sk- followed by letters and digits, check it was copied whole \
+             and without quotes or spaces",
+        );
+    }
+    Check::pass(NAME, detail)
+}
+
+/// The endpoint of the backend, `None` for one running locally.
+pub fn endpoint(args: &Args) -> Option<String> {
+    let default = match args.backend {
+        Backend::Gummy => gummy::DEFAULT_URL,
+        Backend::Openai => openai::DEFAULT_URL,
+        Backend::Whisper => return None,
+    };
+    Some(args.url.clone().unwrap_or_else(|| default.to_string()))
+}
+
+/// Whether the host of `url` takes a connection, over TLS for `wss` and `https`, without
+/// starting a task.
+pub async fn check_endpoint(network: &dyn Network, url: &str) -> Check {
+    const NAME: &str = "Network";
+    let hint = "check --url, or url in the config file";
+    let uri = match url.parse::<Uri>() {
+        Ok(uri) => uri,
+        Err(e) => return Check::fail(NAME, format!("{} isn't a URL: {}", url, e), hint),
+    };
+    let Some(host) = uri.host() else {
+        return Check::fail(NAME, format!("{} names no host", url), hint);
+    };
+    let tls = matches!(uri.scheme_str(), Some("wss" | "https"));
+    let port = uri.port_u16().unwrap_or(if tls { 443 } else { 80 });
+    let hint = format!(
+        "check the connection, and that no firewall or proxy keeps st from {}:{}",
+        host, port
+    );
+    match timeout(CONNECT_TIMEOUT, network.connect(host, port, tls)).await {
+        Ok(Ok(())) if tls => Check::pass(NAME, format!("{}:{} reachable over TLS", host, port)),
+        Ok(Ok(())) => Check::pass(NAME, format!("{}:{} reachable", host, port)),
+        Ok(Err(e)) => Check::fail(NAME, e, hint),
+        Err(_) => Check::fail(
+            NAME,
+            format!(
+                "No answer from {}:{} in {} s",
+                host,
+                port,
+                CONNECT_TIMEOUT.as_secs()
+            ),
+            hint,
+        ),
+    }
+}
+
+/// Whether any audio host has devices to capture from.
+pub fn check_audio_host(audio: &dyn Audio) -> Check {
+    const NAME: &str = "Audio host";
+    let hint = "check the sound system is running and st may use it, like PipeWire or \
+                PulseAudio on Linux";
+    match audio.hosts() {
+        Ok(hosts) if hosts.is_empty() => Check::fail(NAME, "No audio devices found", hint),
+        Ok(hosts) => Check::pass(
+            NAME,
+            hosts
+                .iter()
+                .map(|(host, count)| format!("{} ({} devices)", host, count))
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+        Err(e) => Check::fail(NAME, e, hint),
+    }
+}
+
+/// Whether the devices a session would capture from open.
+pub fn check_device(audio: &dyn Audio) -> Check {
+    const NAME: &str = "Audio device";
+    let mut source = match audio.open() {
+        Ok(source) => source,
+        Err(e) => {
+            return Check::fail(
+                NAME,
+                e,
+                "list the devices with `st --list-devices` and pick one with --device",
+            );
+        }
+    };
+    let detail = match source.recorder() {
+        Some(recorder) => recorder
+            .devices()
+            .iter()
+            .map(|device| {
+                format!(
+                    "{} ({} Hz, {} ch)",
+                    device.name, device.format.sample_rate, device.format.channels
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", "),
+        None => "opened".to_string(),
+    };
+    let _ = source.stop();
+    Check::pass(NAME, detail)
+}
+
+/// Whether `duration` of audio captured from the devices a session would capture from
+/// holds anything but silence.
+pub async fn check_capture(audio: &dyn Audio, duration: Duration) -> Check {
+    const NAME: &str = "Capture";
+    let hint = "check the device isn't muted, and that something plays or speaks into it \
+                while checking";
+    let mut source = match audio.open() {
+        Ok(source) => source,
+        Err(e) => return Check::fail(NAME, e, hint),
+    };
+    if let Err(e) = source.wait_for_first_frame(FIRST_FRAME_TIMEOUT).await {
+        let _ = source.stop();
+        return Check::fail(NAME, e.to_string(), hint);
+    }
+    let sample_rate = source.output_format().sample_rate;
+    let wanted = (sample_rate as u128 * duration.as_millis() / 1000) as usize;
+    let mut samples = Vec::with_capacity(wanted);
+    let deadline = Instant::now() + duration + FIRST_FRAME_TIMEOUT;
+    while samples.len() < wanted {
+        match timeout_at(deadline, source.next_frame()).await {
+            Ok(Some(frame)) => samples.extend(frame.data),
+            _ => break,
+        }
+    }
+    let _ = source.stop();
+    let seconds = samples.len() as f32 / sample_rate as f32;
+    if samples.is_empty() {
+        return Check::fail(NAME, "No audio came in", hint);
+    }
+    if samples.iter().all(|&sample| sample == 0) {
+        return Check::warn(NAME, format!("{:.1} s of silence", seconds), hint);
+    }
+    Check::pass(
+        NAME,
+        format!("{:.0} dBFS RMS over {:.1} s", rms_dbfs(&samples), seconds),
+    )
+}
+
+/// Directories written to: those of each `--output`, and the session directory, which st
+/// creates when it's missing.
+fn output_dirs(args: &Args) -> Vec<(PathBuf, bool)> {
+    let mut dirs: Vec<(PathBuf, bool)> = vec![];
+    for path in args.output.iter().filter(|path| !is_stdout(path)) {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        if !dirs.iter().any(|(known, _)| *known == dir) {
+            dirs.push((dir, false));
+        }
+    }
+    if let Some(base) = &args.session_dir {
+        dirs.push((base.clone(), true));
+    }
+    dirs
+}
+
+/// Whether a file can be written to `dir`, or with `created`, to the closest directory
+/// above it st would create it in.
+pub fn check_output_dir(dir: &Path, created: bool) -> Check {
+    const NAME: &str = "Output directory";
+    let mut writable = dir;
+    if created {
+        while !writable.exists()
+            && let Some(parent) = writable.parent()
+        {
+            writable = match parent.as_os_str().is_empty() {
+                true => Path::new("."),
+                false => parent,
+            };
+        }
+    }
+    if !writable.is_dir() {
+        return Check::fail(
+            NAME,
+            format!("{} isn't a directory", dir.display()),
+            format!("create it with `mkdir -p {}`", dir.display()),
+        );
+    }
+    let probe = writable.join(format!(".st-doctor-{}", std::process::id()));
+    let written = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+        .map(|_| std::fs::remove_file(&probe));
+    match written {
+        Ok(_) if writable == dir => Check::pass(NAME, format!("{} is writable", dir.display())),
+        Ok(_) => Check::pass(
+            NAME,
+            format!("{} can be created in {}", dir.display(), writable.display()),
+        ),
+        Err(e) => Check::fail(
+            NAME,
+            format!("Failed to write to {}: {}", writable.display(), e),
+            "write somewhere else, or give yourself write access to it",
+        ),
+    }
+}
+
+/// Prints `check`, returning whether it failed in a way st can't work with.
+fn report(check: Check) -> bool {
+    println!("{}", check);
+    check.failed_hard()
+}
+
+/// Runs every check in turn, printing each as it's done, and exits with status 1 if any
+/// failed in a way st can't work with.
+pub async fn run(args: &Args) {
+    let audio = System::new(args);
+    let mut failed = report(check_api_key(args));
+    failed |= report(match endpoint(args) {
+        Some(url) => check_endpoint(&Internet, &url).await,
+        None => Check::pass("Network", "not needed by the whisper backend"),
+    });
+    failed |= report(check_audio_host(&audio));
+    failed |= report(check_device(&audio));
+    failed |= report(check_capture(&audio, CAPTURE_DURATION).await);
+    let dirs = output_dirs(args);
+    if dirs.is_empty() {
+        report(Check::pass(
+            "Output directory",
+            "none, transcripts go to stdout",
+        ));
+    }
+    for (dir, created) in dirs {
+        failed |= report(check_output_dir(&dir, created));
+    }
+    if failed {
+        exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use audio::source::SineSource;
+    use clap::Parser;
+    use std::sync::Mutex;
+
+    fn parse(args: &[&str]) -> Args {
+        Args::try_parse_from(["st"].iter().chain(args)).unwrap()
+    }
+
+    /// A network reaching whatever it's asked to with `reachable`, remembering where.
+    struct MockNetwork {
+        reachable: bool,
+        connected: Mutex<Vec<(String, u16, bool)>>,
+    }
+
+    impl MockNetwork {
+        fn new(reachable: bool) -> Self {
+            MockNetwork {
+                reachable,
+                connected: Mutex::new(vec![]),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Network for MockNetwork {
+        async fn connect(&self, host: &str, port: u16, tls: bool) -> Result<(), String> {
+            self.connected
+                .lock()
+                .unwrap()
+                .push((host.to_string(), port, tls));
+            match self.reachable {
+                true => Ok(()),
+                false => Err(format!("Failed to connect to {}:{}: refused", host, port)),
+            }
+        }
+    }
+
+    /// An audio system with the hosts of `hosts`, capturing a second of a tone of
+    /// `amplitude`, or failing to open without one.
+    struct MockAudio {
+        hosts: Result<Vec<(String, usize)>, String>,
+        amplitude: Option<f32>,
+    }
+
+    impl Audio for MockAudio {
+        fn hosts(&self) -> Result<Vec<(String, usize)>, String> {
+            self.hosts.clone()
+        }
+
+        fn open(&self) -> Result<Box<dyn SampleSource>, String> {
+            match self.amplitude {
+                Some(amplitude) => Ok(Box::new(SineSource::new(
+                    16000,
+                    440.0,
+                    amplitude,
+                    Duration::from_secs(1),
+                ))),
+                None => Err("No default input device found".to_string()),
+            }
+        }
+    }
+
+    fn audio(amplitude: Option<f32>) -> MockAudio {
+        MockAudio {
+            hosts: Ok(vec![("ALSA".to_string(), 2)]),
+            amplitude,
+        }
+    }
+
+    #[test]
+    fn prints_a_line_per_check_with_its_hint() {
+        let passed = Check::pass("API key", "sk-…abcd (35 characters)");
+        assert_eq!(passed.to_string(), "✓ API key: sk-…abcd (35 characters)");
+        let failed = Check::fail("API key", "none given", "pass --api-key");
+        assert_eq!(
+            failed.to_string(),
+            "✗ API key: none given\n  pass --api-key"
+        );
+        assert!(failed.failed_hard());
+        assert!(!passed.failed_hard());
+        assert!(!Check::warn("Capture", "silence", "play something").failed_hard());
+    }
+
+    #[test]
+    fn checks_the_api_key() {
+        let key = "sk-0123456789abcdef0123456789abcdef";
+        let check = check_api_key(&parse(&["--api-key", key]));
+        assert_eq!(check, Check::pass("API key", "sk-…cdef (35 characters)"));
+
+        let check = check_api_key(&parse(&[]));
+        assert!(check.failed_hard());
+        assert!(check.hint.unwrap().contains("API_KEY"));
+        let check = check_api_key(&parse(&["--backend", "openai"]));
+        assert!(check.hint.unwrap().contains("OPENAI_API_KEY"));
+
+        // Of the wrong shape, as with quotes copied along.
+        let check = check_api_key(&parse(&["--api-key", &format!("\"{}\"", key)]));
+        assert!(!check.passed);
+        assert!(!check.hard);
+        let check = check_api_key(&parse(&["--api-key", "sk-short"]));
+        assert_eq!(check.detail, "… (8 characters) doesn't look like a key");
+
+        let file = std::env::temp_dir().join(format!("st-{}-doctor-key", std::process::id()));
+        std::fs::write(&file, format!("{}\n", key)).unwrap();
+        let check = check_api_key(&parse(&["--api-key-file", file.to_str().unwrap()]));
+        assert!(check.passed);
+        std::fs::remove_file(&file).unwrap();
+        let check = check_api_key(&parse(&["--api-key-file", file.to_str().unwrap()]));
+        assert!(check.failed_hard());
+        assert!(check.detail.starts_with("Failed to read"));
+    }
+
+    #[tokio::test]
+    async fn checks_the_endpoint() {
+        let network = MockNetwork::new(true);
+        let check = check_endpoint(&network, gummy::DEFAULT_URL).await;
+        assert_eq!(
+            check,
+            Check::pass("Network", "dashscope.aliyuncs.com:443 reachable over TLS")
+        );
+        check_endpoint(&network, "ws://localhost:8080/ws").await;
+        assert_eq!(
+            *network.connected.lock().unwrap(),
+            [
+                ("dashscope.aliyuncs.com".to_string(), 443, true),
+                ("localhost".to_string(), 8080, false),
+            ]
+        );
+
+        let check = check_endpoint(&MockNetwork::new(false), gummy::DEFAULT_URL).await;
+        assert!(check.failed_hard());
+        assert!(check.hint.unwrap().contains("dashscope.aliyuncs.com:443"));
+        let check = check_endpoint(&network, "not a url").await;
+        assert!(check.failed_hard());
+        assert_eq!(network.connected.lock().unwrap().len(), 2);
+
+        assert_eq!(
+            endpoint(&parse(&["--backend", "openai"])).as_deref(),
+            Some(openai::DEFAULT_URL)
+        );
+        assert_eq!(
+            endpoint(&parse(&["--url", "ws://localhost"])).as_deref(),
+            Some("ws://localhost")
+        );
+    }
+
+    #[test]
+    fn checks_the_audio_host_and_device() {
+        let check = check_audio_host(&audio(Some(0.5)));
+        assert_eq!(check, Check::pass("Audio host", "ALSA (2 devices)"));
+        let none = MockAudio {
+            hosts: Ok(vec![]),
+            amplitude: None,
+        };
+        assert!(check_audio_host(&none).failed_hard());
+        let broken = MockAudio {
+            hosts: Err("Failed to find host".to_string()),
+            amplitude: None,
+        };
+        assert!(check_audio_host(&broken).failed_hard());
+
+        assert!(check_device(&audio(Some(0.5))).passed);
+        let check = check_device(&audio(None));
+        assert!(check.failed_hard());
+        assert!(check.hint.unwrap().contains("--list-devices"));
+    }
+
+    #[tokio::test]
+    async fn checks_the_capture_holds_audio() {
+        let duration = Duration::from_millis(500);
+        let check = check_capture(&audio(Some(0.5)), duration).await;
+        assert_eq!(check, Check::pass("Capture", "-9 dBFS RMS over 0.5 s"));
+        // Silence only warns, as when nothing plays.
+        let check = check_capture(&audio(Some(0.0)), duration).await;
+        assert!(!check.passed);
+        assert!(!check.hard);
+        assert!(check_capture(&audio(None), duration).await.failed_hard());
+    }
+
+    #[test]
+    fn checks_the_output_directories() {
+        let args = parse(&["-o", "-", "-o", "a.srt", "-o", "out/b.srt", "-o", "c.txt"]);
+        assert_eq!(
+            output_dirs(&args),
+            [(PathBuf::from("."), false), (PathBuf::from("out"), false)]
+        );
+
+        let dir = std::env::temp_dir();
+        let check = check_output_dir(&dir, false);
+        assert_eq!(
+            check,
+            Check::pass("Output directory", format!("{} is writable", dir.display()))
+        );
+        let missing = dir.join(format!("st-{}-doctor/sessions", std::process::id()));
+        assert!(check_output_dir(&missing, false).failed_hard());
+        // A session directory is created where it's missing.
+        let check = check_output_dir(&missing, true);
+        assert_eq!(
+            check.detail,
+            format!("{} can be created in {}", missing.display(), dir.display())
+        );
+        assert!(!missing.parent().unwrap().exists());
+    }
+}
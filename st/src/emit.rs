@@ -0,0 +1,210 @@
+//! `--emit ndjson`: the events of a session on stdout, a JSON object per line, for
+//! programs to read from a pipe.
+
+use crate::output::{Marker, SessionInfo, Usage};
+use crate::sink::TranscriptSink;
+use crate::summary::Summary;
+use clap::ValueEnum;
+use gummy::{Transcription, TranscriptionEvent};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::io::{self, Stdout, Write};
+
+/// Version of the lines written, raised when they change in ways readers would notice.
+pub const VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum EmitFormat {
+    /// A JSON object per line and event.
+    Ndjson,
+}
+
+/// A line of `--emit ndjson`.
+#[derive(Serialize)]
+struct Line<'a> {
+    v: u32,
+    #[serde(flatten)]
+    event: Event<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum Event<'a> {
+    SessionStarted {
+        session: &'a SessionInfo,
+        /// Capture device, or the file transcribed.
+        device: &'a str,
+    },
+    Partial {
+        sentence: &'a Transcription,
+    },
+    SentenceFinal {
+        sentence: &'a Transcription,
+    },
+    Marker {
+        marker: &'a Marker,
+    },
+    Warning {
+        kind: &'a str,
+        message: &'a str,
+    },
+    TaskFinished,
+    SessionEnded {
+        usage: Usage,
+        /// Final sentences of the session.
+        sentences: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        summary: Option<&'a Summary>,
+    },
+}
+
+/// Writes the events of a session as JSON lines, flushing after each so readers get them
+/// as they happen. Final sentences are written once, however often they're reported.
+pub struct NdjsonEmitter<W: Write> {
+    out: W,
+    finals: HashSet<u64>,
+}
+
+impl NdjsonEmitter<Stdout> {
+    pub fn stdout() -> Self {
+        NdjsonEmitter::new(io::stdout())
+    }
+}
+
+impl<W: Write> NdjsonEmitter<W> {
+    pub fn new(out: W) -> Self {
+        NdjsonEmitter {
+            out,
+            finals: HashSet::new(),
+        }
+    }
+
+    fn emit(&mut self, event: Event) -> io::Result<()> {
+        let line = Line { v: VERSION, event };
+        serde_json::to_writer(&mut self.out, &line)?;
+        self.out.write_all(b"\n")?;
+        self.out.flush()
+    }
+
+    fn sentence_final(&mut self, sentence: &Transcription) -> io::Result<()> {
+        if !self.finals.insert(sentence.sentence_id) {
+            return Ok(());
+        }
+        self.emit(Event::SentenceFinal { sentence })
+    }
+}
+
+impl<W: Write> TranscriptSink for NdjsonEmitter<W> {
+    fn name(&self) -> String {
+        "the events to stdout".to_string()
+    }
+
+    fn start(&mut self, session: &SessionInfo, device: &str) -> io::Result<()> {
+        self.emit(Event::SessionStarted { session, device })
+    }
+
+    fn on_event(&mut self, event: &TranscriptionEvent) -> io::Result<()> {
+        match event {
+            TranscriptionEvent::Partial(sentence) => self.emit(Event::Partial { sentence }),
+            TranscriptionEvent::Final(sentence) => self.sentence_final(sentence),
+            TranscriptionEvent::Finished => self.emit(Event::TaskFinished),
+        }
+    }
+
+    fn mark(&mut self, marker: &Marker) -> io::Result<()> {
+        self.emit(Event::Marker { marker })
+    }
+
+    fn warning(&mut self, kind: &str, message: &str) -> io::Result<()> {
+        self.emit(Event::Warning { kind, message })
+    }
+
+    fn finalize(&mut self, result: &[Transcription], session: &SessionInfo) -> io::Result<()> {
+        for sentence in result {
+            self.sentence_final(sentence)?;
+        }
+        self.emit(Event::SessionEnded {
+            usage: session.usage,
+            sentences: self.finals.len(),
+            summary: session.summary.as_ref(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_a_versioned_object_per_line() {
+        let sentence = Transcription {
+            sentence_id: 0,
+            begin_time: 0,
+            end_time: 900,
+            text: "Hello.".to_string(),
+            is_final: true,
+            translated_text: Some("你好。".to_string()),
+            translations: vec![],
+            words: vec![],
+            confidence: None,
+            source_label: None,
+        };
+        let session = SessionInfo {
+            started_at_ms: 1_700_000_000_000,
+            sample_rate: 16000,
+            source_language: None,
+            target_languages: vec!["zh".to_string()],
+            model: "gummy-realtime-v1".to_string(),
+            task_ids: vec![],
+            usage: Usage { audio_ms: 1_000 },
+            summary: None,
+        };
+        let mut out = vec![];
+        let mut emitter = NdjsonEmitter::new(&mut out);
+        emitter.start(&session, "Speakers").unwrap();
+        emitter
+            .warning("silent", "no sound from Speakers for 5 s")
+            .unwrap();
+        emitter
+            .on_event(&TranscriptionEvent::Final(sentence.clone()))
+            .unwrap();
+        emitter.on_event(&TranscriptionEvent::Finished).unwrap();
+        emitter.finalize(&[sentence], &session).unwrap();
+
+        let lines = String::from_utf8(out).unwrap();
+        let lines = lines
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                serde_json::json!({
+                    "v": 1,
+                    "type": "session-started",
+                    "session": session,
+                    "device": "Speakers",
+                }),
+                serde_json::json!({
+                    "v": 1,
+                    "type": "warning",
+                    "kind": "silent",
+                    "message": "no sound from Speakers for 5 s",
+                }),
+                serde_json::json!({
+                    "v": 1,
+                    "type": "sentence-final",
+                    "sentence": lines[2]["sentence"],
+                }),
+                serde_json::json!({ "v": 1, "type": "task-finished" }),
+                serde_json::json!({
+                    "v": 1,
+                    "type": "session-ended",
+                    "usage": { "audio_ms": 1_000 },
+                    "sentences": 1,
+                }),
+            ]
+        );
+        assert_eq!(lines[2]["sentence"]["translated_text"], "你好。");
+    }
+}
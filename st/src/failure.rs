@@ -0,0 +1,144 @@
+//! How a run that can't go on ends: a line on stderr saying why, or a JSON object with
+//! `--error-format json`, and an exit status telling scripts what kind of failure it was.
+
+use clap::ValueEnum;
+use gummy::GummyError;
+use serde::Serialize;
+use std::fmt::Display;
+use std::process::exit;
+use std::sync::OnceLock;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ErrorFormat {
+    /// A line of text.
+    #[default]
+    Text,
+    /// A JSON object on a line, like `{"error":{"kind":"auth","code":4,"message":".."}}`.
+    Json,
+}
+
+static FORMAT: OnceLock<ErrorFormat> = OnceLock::new();
+
+/// Has failures reported in `format` from now on.
+pub fn set_format(format: ErrorFormat) {
+    let _ = FORMAT.set(format);
+}
+
+/// What went wrong, each kind exiting with a status of its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// Anything else, like a file that can't be written.
+    Other,
+    /// The options or the config file don't add up, as without an API key.
+    Config,
+    /// The audio can't be captured or read.
+    Device,
+    /// The backend refused the API key.
+    Auth,
+    /// The backend can't be reached, or broke off the session, after retrying.
+    Network,
+}
+
+impl Kind {
+    pub fn code(self) -> i32 {
+        match self {
+            Kind::Other => 1,
+            Kind::Config => 2,
+            Kind::Device => 3,
+            Kind::Auth => 4,
+            Kind::Network => 5,
+        }
+    }
+
+    /// The kind of an error starting or running a transcription session.
+    pub fn of_session(error: &anyhow::Error) -> Self {
+        let refused = error.chain().any(|cause| {
+            if let Some(error) = cause.downcast_ref::<GummyError>() {
+                return error.is_auth();
+            }
+            matches!(
+                cause.downcast_ref::<tungstenite::Error>(),
+                Some(tungstenite::Error::Http(response))
+                    if matches!(response.status().as_u16(), 401 | 403)
+            )
+        });
+        if refused { Kind::Auth } else { Kind::Network }
+    }
+}
+
+/// A failure that ends the run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Failure {
+    pub kind: Kind,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(kind: Kind, message: impl Display) -> Self {
+        Failure {
+            kind,
+            message: message.to_string(),
+        }
+    }
+
+    /// The failure as reported in `format`.
+    fn report(&self, format: ErrorFormat) -> String {
+        match format {
+            ErrorFormat::Text => self.message.clone(),
+            ErrorFormat::Json => serde_json::json!({
+                "error": {
+                    "kind": self.kind,
+                    "code": self.kind.code(),
+                    "message": self.message,
+                },
+            })
+            .to_string(),
+        }
+    }
+
+    /// Reports the failure on stderr and exits with the status of its kind.
+    pub fn exit(&self) -> ! {
+        eprintln!("{}", self.report(*FORMAT.get_or_init(ErrorFormat::default)));
+        exit(self.kind.code());
+    }
+}
+
+/// Ends the run with a failure of `kind`.
+pub fn fail(kind: Kind, message: impl Display) -> ! {
+    Failure::new(kind, message).exit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_a_line_of_text_or_json() {
+        let failure = Failure::new(Kind::Auth, "the backend refused the API key");
+        assert_eq!(
+            failure.report(ErrorFormat::Text),
+            "the backend refused the API key"
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&failure.report(ErrorFormat::Json)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "error": { "kind": "auth", "code": 4, "message": "the backend refused the API key" },
+            })
+        );
+    }
+
+    #[test]
+    fn tells_refused_keys_from_connection_failures() {
+        let refused = anyhow::Error::new(GummyError::TaskFailed {
+            code: "InvalidApiKey".to_string(),
+            message: "Invalid API-key provided.".to_string(),
+        });
+        assert_eq!(Kind::of_session(&refused), Kind::Auth);
+        let closed =
+            anyhow::Error::new(GummyError::ConnectionClosed).context("failed to reconnect");
+        assert_eq!(Kind::of_session(&closed), Kind::Network);
+    }
+}
@@ -0,0 +1,100 @@
+//! `--hotkey-pause`, `--hotkey-marker` and `--hotkey-stop`: keys that control the session
+//! from any window, with st built with the hotkeys feature.
+//!
+//! Keys are written like `ctrl+alt+KeyP`, modifiers first. They're grabbed from X11 on
+//! Linux; macOS and Windows only deliver them to a thread running an event loop, which st
+//! doesn't have.
+
+use crate::args::Args;
+use tokio::sync::mpsc;
+
+/// What a hotkey asks of the session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HotkeyAction {
+    /// Pause the capture, or resume it.
+    Pause,
+    /// Mark the moment in the transcript.
+    Marker,
+    /// Finish the session and exit.
+    Stop,
+}
+
+#[cfg(feature = "hotkeys")]
+type Manager = global_hotkey::GlobalHotKeyManager;
+#[cfg(not(feature = "hotkeys"))]
+type Manager = ();
+
+/// The hotkeys of a session, registered as long as this is kept.
+pub struct Hotkeys {
+    actions: mpsc::UnboundedReceiver<HotkeyAction>,
+    _manager: Manager,
+}
+
+impl Hotkeys {
+    /// Registers the hotkeys of `args`, if there are any.
+    pub fn from_args(args: &Args) -> Result<Option<Self>, String> {
+        let bindings: Vec<_> = [
+            (HotkeyAction::Pause, &args.hotkey_pause),
+            (HotkeyAction::Marker, &args.hotkey_marker),
+            (HotkeyAction::Stop, &args.hotkey_stop),
+        ]
+        .into_iter()
+        .filter_map(|(action, keys)| Some((action, keys.as_deref()?)))
+        .collect();
+        if bindings.is_empty() {
+            return Ok(None);
+        }
+        let (sender, actions) = mpsc::unbounded_channel();
+        Ok(Some(Hotkeys {
+            actions,
+            _manager: register(&bindings, sender)?,
+        }))
+    }
+
+    /// Waits for a hotkey to be pressed.
+    pub async fn next(&mut self) -> Option<HotkeyAction> {
+        self.actions.recv().await
+    }
+}
+
+/// Registers `bindings`, sending the action of each key pressed to `sender`.
+#[cfg(feature = "hotkeys")]
+fn register(
+    bindings: &[(HotkeyAction, &str)],
+    sender: mpsc::UnboundedSender<HotkeyAction>,
+) -> Result<Manager, String> {
+    use global_hotkey::hotkey::HotKey;
+    use global_hotkey::{GlobalHotKeyEvent, GlobalHotKeyManager, HotKeyState};
+
+    let mut hotkeys = vec![];
+    for &(action, keys) in bindings {
+        let hotkey: HotKey = keys
+            .parse()
+            .map_err(|e| format!("invalid hotkey `{}`: {}", keys, e))?;
+        hotkeys.push((hotkey, action));
+    }
+    let manager = GlobalHotKeyManager::new().map_err(|e| e.to_string())?;
+    for (&(_, keys), (hotkey, _)) in bindings.iter().zip(&hotkeys) {
+        manager
+            .register(*hotkey)
+            .map_err(|e| format!("failed to register `{}`: {}", keys, e))?;
+    }
+    // Events come in on a thread of the hotkey manager.
+    GlobalHotKeyEvent::set_event_handler(Some(move |event: GlobalHotKeyEvent| {
+        if event.state() == HotKeyState::Pressed
+            && let Some((_, action)) = hotkeys.iter().find(|(hotkey, _)| hotkey.id() == event.id())
+        {
+            let _ = sender.send(*action);
+        }
+    }));
+    Ok(manager)
+}
+
+/// Never called, `Args::validate` rejects hotkeys without the feature.
+#[cfg(not(feature = "hotkeys"))]
+fn register(
+    _: &[(HotkeyAction, &str)],
+    _: mpsc::UnboundedSender<HotkeyAction>,
+) -> Result<Manager, String> {
+    Err("hotkeys need st built with the hotkeys feature".to_string())
+}
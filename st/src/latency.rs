@@ -0,0 +1,239 @@
+//! How far behind the audio the captions are: the time from when the end of a sentence was
+//! captured to when the sentence came back final, for the dashboard, `/metrics` of
+//! `--serve` and `--latency-report`.
+
+use gummy::{Transcription, TranscriptionEvent};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Latest latencies the rolling percentiles are taken over.
+const WINDOW: usize = 100;
+/// Difference between the capture clock and the audio sent that is put down to jitter,
+/// rather than to a gap in the audio.
+const JITTER_MS: u64 = 50;
+
+/// A point where the audio sent lines up with the capture clock: the audio `audio_ms` into
+/// the session was captured at `captured_ms`, in milliseconds since the Unix epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Anchor {
+    pub audio_ms: u64,
+    pub captured_ms: u64,
+}
+
+/// Latency of a sentence ending `end_ms` into the audio sent and received at
+/// `received_ms`, given the `anchors` of the audio in the order it was sent.
+///
+/// Audio is taken to run in real time from an anchor to the next, so a gap in the capture,
+/// like a pause, moves the clock forward at the anchor after it. Positions past the last
+/// anchor, as for audio still queued, run on from it. A sentence that by the capture clock
+/// came back before its audio ended, which clock drift can make happen, has no latency.
+/// `None` before any audio was sent.
+pub fn latency(anchors: &[Anchor], end_ms: u64, received_ms: u64) -> Option<u64> {
+    let index = anchors.partition_point(|anchor| anchor.audio_ms <= end_ms);
+    let anchor = anchors[..index].last().or(anchors.first())?;
+    let expected_ms = (anchor.captured_ms + end_ms).saturating_sub(anchor.audio_ms);
+    Some(received_ms.saturating_sub(expected_ms))
+}
+
+/// Percentiles of the latencies of a session, in milliseconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct LatencyStats {
+    /// Sentences measured.
+    pub sentences: usize,
+    pub p50_ms: u64,
+    pub p95_ms: u64,
+    pub max_ms: u64,
+}
+
+impl LatencyStats {
+    /// Statistics of `latencies`, `None` without any.
+    fn of(latencies: impl IntoIterator<Item = u64>) -> Option<Self> {
+        let mut latencies: Vec<_> = latencies.into_iter().collect();
+        latencies.sort_unstable();
+        let max_ms = *latencies.last()?;
+        let percentile = |p: usize| latencies[(latencies.len() * p).div_ceil(100) - 1];
+        Some(LatencyStats {
+            sentences: latencies.len(),
+            p50_ms: percentile(50),
+            p95_ms: percentile(95),
+            max_ms,
+        })
+    }
+}
+
+impl fmt::Display for LatencyStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = |ms: u64| ms as f64 / 1000.0;
+        write!(
+            f,
+            "median {:.1} s, 95th percentile {:.1} s, max {:.1} s over {} sentences",
+            seconds(self.p50_ms),
+            seconds(self.p95_ms),
+            seconds(self.max_ms),
+            self.sentences
+        )
+    }
+}
+
+/// Follows the audio sent and the final sentences of a session to measure their latency.
+#[derive(Default)]
+pub struct LatencyMeter {
+    anchors: Vec<Anchor>,
+    latencies: Vec<u64>,
+    seen: HashSet<u64>,
+}
+
+impl LatencyMeter {
+    /// Takes in audio sent from `audio_ms` into the session on, captured at `captured_ms`.
+    pub fn sent(&mut self, audio_ms: u64, captured_ms: u64) {
+        let in_line = self.anchors.last().is_some_and(|last| {
+            (last.captured_ms + audio_ms)
+                .saturating_sub(last.audio_ms)
+                .abs_diff(captured_ms)
+                <= JITTER_MS
+        });
+        if !in_line {
+            self.anchors.push(Anchor {
+                audio_ms,
+                captured_ms,
+            });
+        }
+    }
+
+    /// Takes in an event of the session received at `received_ms`.
+    pub fn update(&mut self, event: &TranscriptionEvent, received_ms: u64) {
+        if let TranscriptionEvent::Final(Transcription {
+            sentence_id,
+            end_time,
+            ..
+        }) = event
+            && self.seen.insert(*sentence_id)
+            && let Some(latency) = latency(&self.anchors, *end_time, received_ms)
+        {
+            self.latencies.push(latency);
+        }
+    }
+
+    /// Percentiles of the latest sentences, `None` before any.
+    pub fn recent(&self) -> Option<LatencyStats> {
+        let start = self.latencies.len().saturating_sub(WINDOW);
+        LatencyStats::of(self.latencies[start..].iter().copied())
+    }
+
+    /// Average over the whole session, `None` without any sentence.
+    pub fn average(&self) -> Option<u64> {
+        let count = self.latencies.len() as u64;
+        (count > 0).then(|| self.latencies.iter().sum::<u64>() / count)
+    }
+
+    /// Percentiles of the whole session, `None` without any sentence.
+    pub fn summary(&self) -> Option<LatencyStats> {
+        LatencyStats::of(self.latencies.iter().copied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: u64 = 1_700_000_000_000;
+
+    fn sentence(sentence_id: u64, end_time: u64) -> TranscriptionEvent {
+        TranscriptionEvent::Final(Transcription {
+            sentence_id,
+            begin_time: end_time.saturating_sub(1000),
+            end_time,
+            text: "Hello.".to_string(),
+            is_final: true,
+            translated_text: None,
+            translations: vec![],
+            words: vec![],
+            confidence: None,
+            source_label: None,
+        })
+    }
+
+    /// Sends 100 ms frames captured from `START` on, with the capture paused from 10 s to
+    /// 40 s in.
+    fn paused_session() -> LatencyMeter {
+        let mut meter = LatencyMeter::default();
+        let captured = (0..100).chain(400..500).map(|frame| START + frame * 100);
+        for (frame, captured_ms) in captured.enumerate() {
+            // The capture clock jitters a little.
+            meter.sent(frame as u64 * 100, captured_ms + frame as u64 % 3 * 10);
+        }
+        meter
+    }
+
+    #[test]
+    fn anchors_the_audio_after_a_pause() {
+        let meter = paused_session();
+        assert_eq!(
+            meter.anchors,
+            [
+                Anchor {
+                    audio_ms: 0,
+                    captured_ms: START
+                },
+                Anchor {
+                    audio_ms: 10_000,
+                    captured_ms: START + 40_000 + 10
+                },
+            ]
+        );
+        let latency = |end_ms, received_ms| latency(&meter.anchors, end_ms, START + received_ms);
+        // Before the pause, the audio is where the first frame puts it.
+        assert_eq!(latency(9_000, 10_500), Some(1_500));
+        // After it, 30 s later.
+        assert_eq!(latency(12_000, 42_800), Some(790));
+        // A sentence across the pause ends after it.
+        assert_eq!(latency(10_500, 41_000), Some(490));
+        // Audio not sent yet runs on from the last anchor.
+        assert_eq!(latency(25_000, 56_000), Some(990));
+        // Drift can't make a sentence come back early.
+        assert_eq!(latency(5_000, 4_900), Some(0));
+        assert_eq!(super::latency(&[], 5_000, START), None);
+    }
+
+    #[test]
+    fn measures_each_final_sentence_once() {
+        let mut meter = paused_session();
+        assert_eq!(meter.summary(), None);
+        for (sentence_id, received_ms) in (0..20).zip((0..).step_by(100)) {
+            meter.update(&sentence(sentence_id, 2_000), START + 2_000 + received_ms);
+            meter.update(&sentence(sentence_id, 2_000), START + 60_000);
+        }
+        meter.update(&TranscriptionEvent::Finished, START + 60_000);
+        assert_eq!(
+            meter.summary(),
+            Some(LatencyStats {
+                sentences: 20,
+                p50_ms: 900,
+                p95_ms: 1800,
+                max_ms: 1900,
+            })
+        );
+        assert_eq!(
+            meter.summary().unwrap().to_string(),
+            "median 0.9 s, 95th percentile 1.8 s, max 1.9 s over 20 sentences"
+        );
+    }
+
+    #[test]
+    fn keeps_the_recent_percentiles_rolling() {
+        let mut meter = paused_session();
+        // Slow sentences, then as many quick ones as the window holds.
+        for sentence_id in 0..WINDOW as u64 * 2 {
+            let lag = if sentence_id < WINDOW as u64 {
+                5_000
+            } else {
+                500
+            };
+            meter.update(&sentence(sentence_id, 1_000), START + 1_000 + lag);
+        }
+        let recent = meter.recent().unwrap();
+        assert_eq!((recent.sentences, recent.p95_ms), (WINDOW, 500));
+        assert_eq!(meter.summary().unwrap().p95_ms, 5_000);
+    }
+}
@@ -1,64 +1,1465 @@
-use audio::recorder::CpalRecorder;
-use audio::wav::Wav;
-use env_logger;
-use gummy::Gummy;
-use log::{debug, error};
-use std::env::var;
-use std::fs;
-use std::io::Write;
-use std::sync::{Arc, Mutex};
-use std::thread::sleep;
-use std::time::Duration;
-use std::{sync::mpsc::channel, thread::spawn};
-use tokio::runtime::Builder;
+use args::{Action, Args, Backend, ConfigAction, Source};
+use audio::agc::AgcConfig;
+#[cfg(feature = "flac")]
+use audio::flac::Flac;
+use audio::health::{HealthConfig, SignalWarning};
+use audio::level::Level;
+use audio::raw::RawPcmSink;
+use audio::recorder::{
+    ActiveDevice, CaptureSource, CpalRecorder, DeviceInfo, DeviceSelector, OutputFormat,
+    RecorderConfig, RecorderEvent, RecorderSampleFormat, RecoveryConfig,
+};
+use audio::resample::Resampler;
+use audio::source::{SampleSource, WavFileSource};
+use audio::wav::{RotatingWav, RotationConfig};
+use autostop::AutoStop;
+use caption_file::CaptionFile;
+use clipboard::{ClipboardSync, SystemClipboard};
+use coalesce::FrameCoalescer;
+use console::ConsoleRenderer;
+use emit::{EmitFormat, NdjsonEmitter};
+use env_logger::Target;
+use failure::{Failure, Kind, fail};
+use gummy::{AudioFormat, ConnectOptions, SampleFormat, StartOptions, TranscriptionEvent};
+use hotkeys::{HotkeyAction, Hotkeys};
+use latency::LatencyMeter;
+use log::{LevelFilter, debug, info, warn};
+use notify::{Notice, Notifications};
+use openai::OpenAiTranscriber;
+use output::{Marker, SessionInfo, TranscriptFormat, TranscriptWriter, Usage};
+use progress::Progress;
+use session_dir::{Meta, MetaFile, SessionDir, Status};
+use sink::{Sinks, TranscriptFile};
+use std::fs::File;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::process::exit;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use summary::Summary;
+use supervisor::SupervisedSession;
+use tee::AudioTee;
+use timeline::Timeline;
+use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::select;
+use tokio::signal::ctrl_c;
+#[cfg(unix)]
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::time::{interval, timeout};
+use transcriber::{GummyTranscriber, Transcriber};
+use tui::{Command, Connection, LastLine, REDRAW_INTERVAL, Tui};
+use watchdog::Watchdog;
 
-mod gummy;
+mod args;
+mod autostop;
+mod caption_file;
+mod catch_up;
+mod cleanup;
+mod clipboard;
+mod coalesce;
+mod config;
+mod console;
+mod control;
+mod daemon;
+mod diff;
+mod doctor;
+mod emit;
+mod failure;
+mod hotkeys;
+mod latency;
+mod notify;
+mod openai;
+mod output;
+mod overlay;
+mod picker;
+mod progress;
+mod replay;
+mod retranscribe;
+mod separate;
+mod serve;
+mod session_dir;
+mod sink;
+mod summary;
+mod supervisor;
+mod tee;
+mod timeline;
+mod transcriber;
+mod tui;
+mod watchdog;
+#[cfg(feature = "whisper")]
+mod whisper;
+
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+const METER_INTERVAL: Duration = Duration::from_millis(500);
+const CLIP_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+const METER_WIDTH: usize = 40;
+/// Level shown as an empty meter.
+const METER_FLOOR_DBFS: f32 = -60.0;
+/// Time a session gets to finalize its last sentences once capture stopped.
+const FINISH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time the capture gets to deliver audio once started, before the session is.
+const FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Resolves on Ctrl+C, or on SIGTERM on unix.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+        select! {
+            _ = ctrl_c() => {},
+            _ = terminate.recv() => {},
+        }
+        return;
+    }
+    let _ = ctrl_c().await;
+}
+
+/// Exits at once on the next shutdown signal, for when finishing takes too long, noting it
+/// in the `meta` of a session directory.
+fn exit_on_second_signal(meta: Option<MetaFile>) {
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        eprintln!("\rInterrupted again, exiting without finishing");
+        if let Some(meta) = meta {
+            let _ = meta.end(Status::Interrupted, now_ms());
+        }
+        exit(130);
+    });
+}
+
+/// The API key from `--api-key` or `--api-key-file`, either of which may have come from the
+/// environment or the config file.
+fn api_key(args: &Args) -> String {
+    if let Some(api_key) = &args.api_key {
+        return api_key.clone();
+    }
+    if let Some(path) = &args.api_key_file {
+        match std::fs::read_to_string(path) {
+            Ok(api_key) => return api_key.trim().to_string(),
+            Err(e) => fail(
+                Kind::Config,
+                format!("Failed to read {}: {}", path.display(), e),
+            ),
+        }
+    }
+    fail(
+        Kind::Config,
+        format!(
+            "No API key, pass --api-key or --api-key-file, set {}, or set api_key_file in the \
+         config file",
+            config::api_key_var(args.backend)
+        ),
+    )
+}
+
+fn transcriber(args: &Args) -> Box<dyn Transcriber> {
+    match args.backend {
+        Backend::Gummy => {
+            let options = ConnectOptions {
+                url: args.url.clone(),
+                ..Default::default()
+            };
+            Box::new(GummyTranscriber::new(&api_key(args), options))
+        }
+        Backend::Openai => Box::new(OpenAiTranscriber::new(&api_key(args), args.url.as_deref())),
+        #[cfg(feature = "whisper")]
+        Backend::Whisper => {
+            // Checked by `Args::validate`.
+            let model_path = args.model_path.as_deref().unwrap();
+            Box::new(
+                whisper::WhisperTranscriber::new(model_path).unwrap_or_else(|e| {
+                    fail(Kind::Config, format!("Failed to load whisper model: {}", e))
+                }),
+            )
+        }
+        // Rejected by the argument parser.
+        #[cfg(not(feature = "whisper"))]
+        Backend::Whisper => unreachable!(),
+    }
+}
+
+/// Options of every transcription session, at `sample_rate`.
+fn start_options(args: &Args, sample_rate: u32) -> StartOptions {
+    StartOptions {
+        format: Some("pcm".to_string()),
+        sample_rate: Some(sample_rate),
+        source_language: args.source_lang.clone(),
+        target_languages: args.target_lang.clone(),
+        retained_finals: None,
+    }
+}
+
+/// The audio of a recorder emitting `recorder`, as sent once resampled to `sample_rate`.
+fn sent_format(recorder: &OutputFormat, sample_rate: u32) -> AudioFormat {
+    AudioFormat {
+        sample_rate,
+        channels: recorder.channels,
+        sample_format: match recorder.sample_format {
+            RecorderSampleFormat::I16 => SampleFormat::I16,
+            other => SampleFormat::Other(other.to_string()),
+        },
+    }
+}
+
+/// Checks the audio of `recorder`, resampled to `sample_rate`, against `options`. A
+/// mismatch fails unless `--force`, when it's only warned about.
+fn verify_format(
+    recorder: &OutputFormat,
+    sample_rate: u32,
+    options: &StartOptions,
+    force: bool,
+) -> Result<(), String> {
+    match gummy::check_format(&sent_format(recorder, sample_rate), options) {
+        Ok(()) => Ok(()),
+        Err(mismatch) if force => {
+            eprintln!("Warning: {}, starting anyway", mismatch);
+            Ok(())
+        }
+        Err(mismatch) => Err(format!("{}; pass --force to start anyway", mismatch)),
+    }
+}
+
+fn system_device(args: &Args) -> Option<DeviceSelector> {
+    args.device.as_deref().map(|device| match device.parse() {
+        Ok(index) => DeviceSelector::ByIndex(index),
+        Err(_) => DeviceSelector::ByName(device.to_string()),
+    })
+}
+
+fn recorder_config(args: &Args) -> RecorderConfig {
+    match args.source {
+        Source::System => RecorderConfig {
+            source: CaptureSource::SystemAudio,
+            device: system_device(args),
+            ..Default::default()
+        },
+        Source::Mixed => RecorderConfig {
+            source: CaptureSource::Mixed {
+                microphone: None,
+                system_gain: 1.0,
+                microphone_gain: 1.0,
+            },
+            device: system_device(args),
+            ..Default::default()
+        },
+        Source::Mic => RecorderConfig {
+            source: CaptureSource::Microphone {
+                device: args.device.clone(),
+            },
+            ..Default::default()
+        },
+        Source::Separate => unreachable!("separate sources are configured by separate::run"),
+    }
+}
+
+/// The sinks the transcript goes to: captions or the events of `--emit` on stdout, and a
+/// transcript per `--output`. Plain text for stdout is printed as captions instead, and
+/// nothing goes to stdout under the dashboard. A session directory keeps the transcript,
+/// and captions are printed all the same unless stdout gets something else.
+fn transcript_sinks(args: &Args, session_dir: bool, timeline: &Timeline) -> (Sinks, bool) {
+    let mut sinks = Sinks::default();
+    if let Some(EmitFormat::Ndjson) = args.emit {
+        sinks.push(NdjsonEmitter::stdout());
+    }
+    let transcripts = args.transcripts();
+    let stdout = transcripts
+        .iter()
+        .find_map(|(path, format)| path.is_none().then_some(*format));
+    let mut live_captions = false;
+    let captions = stdout == Some(TranscriptFormat::Txt)
+        || session_dir && stdout.is_none() && args.emit.is_none();
+    if captions && !args.tui {
+        let console = ConsoleRenderer::stdout(args.partial_interval());
+        let console = match args.display {
+            Some(display) => console.with_display(display),
+            None => console,
+        };
+        live_captions = console.is_live();
+        sinks.push(console);
+    }
+    for (path, format) in transcripts {
+        if path.is_none() && (args.tui || format == TranscriptFormat::Txt) {
+            continue;
+        }
+        let files = transcript_files(args, path, format, timeline)
+            .unwrap_or_else(|message| fail(Kind::Other, message));
+        for file in files {
+            sinks.push(file);
+        }
+    }
+    if let Some(cleanup) = &args.cleanup {
+        sinks.clean_up(cleanup.clone(), args.source_lang.clone());
+    }
+    (sinks, live_captions)
+}
+
+/// The transcript of `--output` written to `path`, or to stdout without one, and the files
+/// of its translations when they go in a file per target language, with times on the clock
+/// of `--clock` as `timeline` places them.
+fn transcript_files(
+    args: &Args,
+    path: Option<&Path>,
+    format: TranscriptFormat,
+    timeline: &Timeline,
+) -> Result<Vec<TranscriptFile>, String> {
+    let create = |path: &Path| -> Result<Box<dyn Write>, String> {
+        match File::create(path) {
+            Ok(file) => Ok(Box::new(file)),
+            Err(e) => Err(format!("Failed to create {}: {}", path.display(), e)),
+        }
+    };
+    let writer = |out| {
+        let writer = TranscriptWriter::new(format, args.subtitle_options(), out)
+            .with_text(args.text_options());
+        match args.min_silence_ms() {
+            Some(min_ms) => writer.with_silences(min_ms),
+            None => writer,
+        }
+    };
+    let out = match path {
+        Some(path) => create(path)?,
+        None => Box::new(std::io::stdout()),
+    };
+    let file =
+        |path, out| TranscriptFile::new(path, writer(out)).on_clock(args.clock, timeline.clone());
+    let mut files = vec![file(path.map(Path::to_path_buf), out)];
+    if let Some(path) = path.filter(|_| args.translation_files(format)) {
+        for lang in &args.target_lang {
+            let track = output::track_path(path, &args.track_template, lang);
+            // The template may put them in a directory of their own.
+            if let Some(dir) = track.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                std::fs::create_dir_all(dir)
+                    .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+            }
+            let out = create(&track)?;
+            files.push(file(Some(track), out).in_language(lang));
+        }
+    }
+    Ok(files)
+}
+
+/// Length of `bytes` of 16-bit mono audio at `sample_rate`, in milliseconds.
+fn audio_ms(bytes: usize, sample_rate: u32) -> u64 {
+    bytes as u64 / 2 * 1000 / sample_rate as u64
+}
+
+/// Pauses the capture of `source`, or resumes it.
+fn toggle_pause(source: &mut dyn SampleSource) {
+    if let Some(recorder) = source.recorder() {
+        let toggled = match recorder.is_paused() {
+            true => recorder.resume(),
+            false => recorder.pause(),
+        };
+        if let Err(e) = toggled {
+            warn!("Failed to pause or resume the capture: {}", e);
+        }
+    }
+}
+
+/// Marks the end of the audio sent so far in the transcripts, as marker `number`.
+fn mark(sinks: &mut Sinks, number: u32, sent_bytes: usize, sample_rate: u32) {
+    let marker = Marker {
+        label: format!("Marker {}", number),
+        time: audio_ms(sent_bytes, sample_rate),
+        end: None,
+    };
+    info!("{} {}", console::timestamp(marker.time), marker.label);
+    sinks.mark(&marker);
+}
+
+/// Applies the options shared by every capture source.
+fn apply_args(config: &mut RecorderConfig, args: &Args, transcriber: &dyn Transcriber) {
+    // Record at the backend's rate unless asked otherwise, so audio isn't resampled twice.
+    if let Some(sample_rate) = args.sample_rate.or(transcriber.preferred_sample_rate()) {
+        config.target_sample_rate = sample_rate;
+    }
+    if args.denoise {
+        config.denoise = true;
+        // Noise suppression runs at 48 kHz; sessions resample from there.
+        if args.sample_rate.is_none() {
+            config.target_sample_rate = 48000;
+        }
+    }
+    config.buffer_size = args.buffer_size;
+    config.recovery = Some(RecoveryConfig::default());
+    config.skip_silence = args.skip_silence;
+    config.gain_db = args.gain_db;
+    config.agc = args.agc.then(AgcConfig::default);
+    config.health = Some(HealthConfig::default());
+    config.preroll_ms = args.preroll_ms;
+}
+
+/// Milliseconds since the Unix epoch.
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+/// Resamples recorded audio to the session rate, into a buffer reused from one frame to the
+/// next.
+struct Resampling {
+    resampler: Resampler,
+    samples: Vec<i16>,
+}
+
+impl Resampling {
+    fn new(from_rate: u32, to_rate: u32) -> Self {
+        Resampling {
+            resampler: Resampler::new(from_rate, to_rate),
+            samples: vec![],
+        }
+    }
+
+    /// `samples` at the session rate, until the next call.
+    fn process(&mut self, samples: &[i16]) -> &[i16] {
+        self.resampler.process_into(samples, &mut self.samples);
+        &self.samples
+    }
+
+    /// Like `process`, also copying the resampled audio, captured at `timestamp`, to `raw`
+    /// when what is sent is saved.
+    fn process_and_save(
+        &mut self,
+        raw: Option<&mut AudioTee>,
+        samples: &[i16],
+        timestamp: u64,
+    ) -> &[i16] {
+        self.resampler.process_into(samples, &mut self.samples);
+        if let Some(raw) = raw {
+            raw.write(&self.samples, timestamp);
+        }
+        &self.samples
+    }
+}
+
+/// Sends the audio `coalescer` holds, saving it to `raw` when what is sent is saved, and
+/// marks when it was captured in `latency` and `timeline`. Returns the bytes sent.
+async fn send_coalesced(
+    coalescer: &mut FrameCoalescer,
+    session: &mut SupervisedSession,
+    raw: Option<&mut AudioTee>,
+    latency: &mut LatencyMeter,
+    timeline: &Timeline,
+    sent_bytes: usize,
+    sample_rate: u32,
+) -> anyhow::Result<usize> {
+    let Some((samples, captured_ms)) = coalescer.take() else {
+        return Ok(0);
+    };
+    if let Some(raw) = raw {
+        raw.write(samples, captured_ms);
+    }
+    session.send_samples(samples).await?;
+    latency.sent(audio_ms(sent_bytes, sample_rate), captured_ms);
+    timeline.sent(audio_ms(sent_bytes, sample_rate), captured_ms, true);
+    Ok(size_of_val(samples))
+}
+
+/// Waits for `tee` to write what it was given and says what was saved.
+async fn report_saved(tee: AudioTee, sample_rate: u32) {
+    match tee.finish().await {
+        Ok(saved) => {
+            info!(
+                "Saved {:.1} s of audio",
+                saved.samples as f32 / sample_rate as f32
+            );
+            for path in saved.files {
+                eprintln!("Saved audio to {}", path.display());
+            }
+        }
+        Err(e) => eprintln!("Failed to save audio: {}", e),
+    }
+}
+
+/// Renders `level` as a bar of `METER_WIDTH` cells, filled up to the RMS level and marked
+/// at the peak, followed by the gain applied.
+fn meter_line(level: Level, gain_db: f32) -> String {
+    let cells = |dbfs: f32| {
+        let fraction = (dbfs - METER_FLOOR_DBFS) / -METER_FLOOR_DBFS;
+        (fraction.clamp(0.0, 1.0) * METER_WIDTH as f32).round() as usize
+    };
+    let rms = cells(level.rms_dbfs);
+    let peak = cells(level.peak_dbfs).max(rms);
+    let mut bar = "#".repeat(rms);
+    bar.push_str(&" ".repeat(METER_WIDTH - rms));
+    if peak > rms {
+        bar.replace_range(peak - 1..peak, "|");
+    }
+    format!(
+        "[{}] rms {:6.1} dBFS  peak {:6.1} dBFS  gain {:+5.1} dB",
+        bar, level.rms_dbfs, level.peak_dbfs, gain_db
+    )
+}
+
+fn print_devices(devices: &[DeviceInfo]) {
+    println!(
+        "{:<5} {:<16} {:<6} {:>8} {:>8}  {:<24} NAME",
+        "INDEX", "HOST", "KIND", "RATE", "CHANNELS", "FORMATS"
+    );
+    for (index, device) in devices.iter().enumerate() {
+        let formats = device
+            .sample_formats
+            .iter()
+            .map(|format| format.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        println!(
+            "{:<5} {:<16} {:<6} {:>8} {:>8}  {:<24} {}",
+            index,
+            device.host.name(),
+            format!("{:?}", device.kind),
+            device
+                .default_sample_rate
+                .map_or("-".to_string(), |rate| rate.to_string()),
+            device
+                .channels
+                .map_or("-".to_string(), |channels| channels.to_string()),
+            formats,
+            device.name
+        );
+    }
+}
+
+/// Asks which device to capture from when none is given, stdin is a terminal and there's
+/// more than one to choose from, and remembers the answer in the config file unless
+/// `--no-save-choice`.
+fn pick_device(args: &mut Args) {
+    if args.device.is_some() || args.yes || !std::io::stdin().is_terminal() {
+        return;
+    }
+    let devices = match CpalRecorder::list_devices() {
+        Ok(devices) => devices,
+        Err(e) => {
+            debug!("Not asking for a device: {}", e);
+            return;
+        }
+    };
+    let candidates = picker::candidates(&devices, args.source);
+    if candidates.len() < 2 {
+        return;
+    }
+    // Asked on stderr, which stays with the terminal when stdout is piped.
+    let choice = picker::pick(&candidates, std::io::stdin().lock(), std::io::stderr());
+    let device = match choice {
+        Ok(Some(index)) => candidates[index].name.clone(),
+        Ok(None) => return,
+        Err(e) => {
+            warn!("Failed to ask for a device: {}", e);
+            return;
+        }
+    };
+    if !args.no_save_choice
+        && let Some(path) = args.config_path()
+    {
+        match config::save_device(&path, &device) {
+            Ok(()) => eprintln!("Saved {} as the device in {}", device, path.display()),
+            Err(e) => warn!("Failed to save the device in {}: {}", path.display(), e),
+        }
+    }
+    args.device = Some(device);
+}
+
+/// Tells which devices capture started on, in the format they deliver.
+fn print_capture_devices(devices: &[ActiveDevice]) {
+    for device in devices {
+        let buffer = device
+            .buffer_latency()
+            .map(|latency| format!(", {:.1} ms buffer", latency.as_secs_f32() * 1000.0))
+            .unwrap_or_default();
+        eprintln!(
+            "Capturing from: {} ({} Hz, {} ch{})",
+            device.name, device.format.sample_rate, device.format.channels, buffer
+        );
+    }
+}
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
-    let recorder = CpalRecorder::default();
-    let recorder_format = CpalRecorder::output_format();
+    let mut args = Args::parse();
+    failure::set_format(args.error_format);
+    let mut logger = env_logger::Builder::from_default_env();
+    if args.verbose {
+        logger.filter_level(LevelFilter::Debug);
+    }
+    // The dashboard shows what is logged instead, which would otherwise scribble over it.
+    let log = LastLine::default();
+    if args.tui {
+        logger.target(Target::Pipe(Box::new(log.clone())));
+    }
+    logger.init();
+    if let Some(Action::Config {
+        action: ConfigAction::Init { force },
+    }) = &args.action
+    {
+        let Some(path) = args.config_path() else {
+            fail(
+                Kind::Config,
+                "No home directory to write the config file to, pass --config",
+            )
+        };
+        match config::init(&path, *force) {
+            Ok(()) => eprintln!("Wrote {}", path.display()),
+            Err(e) => fail(
+                Kind::Other,
+                format!("Failed to write {}: {}", path.display(), e),
+            ),
+        }
+        return;
+    }
+    if let Some(Action::Ctl { request, socket }) = &args.action {
+        daemon::ctl(*request, socket.as_deref()).await;
+        return;
+    }
+    if let Some(Action::Replay { events, realtime }) = &args.action {
+        replay::run(&args, events, *realtime).await;
+        return;
+    }
+    if let Some(Action::Doctor) = &args.action {
+        doctor::run(&args).await;
+        return;
+    }
+    if let Some(Action::Retranscribe {
+        session,
+        sentence,
+        range,
+    }) = &args.action
+    {
+        retranscribe::run(&args, session, *sentence, range.clone()).await;
+        return;
+    }
+    if args.list_devices {
+        match CpalRecorder::list_devices() {
+            Ok(devices) => print_devices(&devices),
+            Err(e) => fail(Kind::Device, format!("Failed to list audio devices: {}", e)),
+        }
+        return;
+    }
+    let transcriber: Arc<dyn Transcriber> = transcriber(&args).into();
+    if let Some(Action::Daemon { socket }) = &args.action {
+        daemon::run(&args, transcriber, socket.as_deref()).await;
+        return;
+    }
+
+    if args.source == Source::Separate {
+        separate::run(&args, transcriber.as_ref()).await;
+        return;
+    }
+    let mut session_dir = args.session_dir.clone().map(|base| {
+        let dir = SessionDir::create(&base, &mut args).unwrap_or_else(|e| {
+            fail(
+                Kind::Other,
+                format!(
+                    "Failed to create a session directory in {}: {}",
+                    base.display(),
+                    e
+                ),
+            )
+        });
+        info!("Saving the session to {}", dir.path().display());
+        dir
+    });
+
+    if args.input.is_none() {
+        pick_device(&mut args);
+    }
+    let mut recorder_config = recorder_config(&args);
+    apply_args(&mut recorder_config, &args, transcriber.as_ref());
+    // Length of the input file.
+    let mut input_duration = None;
+    let mut source: Box<dyn SampleSource> = match &args.input {
+        Some(path) => {
+            let speed = (args.speed > 0.0).then_some(args.speed);
+            let file = WavFileSource::open(path, recorder_config.target_sample_rate, speed)
+                .unwrap_or_else(|e| fail(Kind::Device, format!("Failed to open {}: {}", path, e)));
+            input_duration = Some(file.duration());
+            Box::new(file)
+        }
+        None => Box::new(
+            CpalRecorder::with_config(recorder_config)
+                .start()
+                .unwrap_or_else(|e| fail(Kind::Device, format!("Failed to start recorder: {}", e))),
+        ),
+    };
+    if let Some(recorder) = source.recorder() {
+        print_capture_devices(recorder.devices());
+    }
+    let recorder_format = source.output_format();
     debug!("Recorder format: {:?}", recorder_format);
+    let sample_rate = transcriber
+        .preferred_sample_rate()
+        .unwrap_or(recorder_format.sample_rate);
+    let mut resampling = Resampling::new(recorder_format.sample_rate, sample_rate);
+    if sample_rate != recorder_format.sample_rate {
+        info!(
+            "Resampling audio from {} Hz to {} Hz",
+            recorder_format.sample_rate, sample_rate
+        );
+    }
+    // The copy is saved in the recorder's format, before resampling for the backend.
+    let mut tee = args.save_audio.as_ref().map(|path| {
+        #[cfg(feature = "flac")]
+        if args.saves_flac() {
+            let flac = Flac::new(path, &recorder_format)
+                .unwrap_or_else(|e| fail(Kind::Other, format!("Failed to create {}: {}", path, e)));
+            return AudioTee::start(Box::new(flac));
+        }
+        let rotation = RotationConfig {
+            template: path.clone(),
+            max_duration: (args.rotate_minutes).map(|minutes| Duration::from_secs(minutes * 60)),
+            max_bytes: args.rotate_mb.map(|megabytes| megabytes * 1024 * 1024),
+        };
+        AudioTee::start(Box::new(RotatingWav::new(rotation, &recorder_format)))
+    });
+    // The raw copy holds exactly what is sent, after resampling.
+    let mut raw = args.save_raw.as_ref().map(|path| {
+        let format = OutputFormat {
+            sample_rate,
+            ..recorder_format.clone()
+        };
+        let sink = RawPcmSink::new(path, &format)
+            .unwrap_or_else(|e| fail(Kind::Other, format!("Failed to create {}: {}", path, e)));
+        AudioTee::start(Box::new(sink))
+    });
 
-    let mut recorder = recorder.start().expect("Failed to start recorder");
+    // Where the audio sent was captured and saved, for `--clock`.
+    let timeline = Timeline::default();
+    let (mut sinks, live_captions) = transcript_sinks(&args, session_dir.is_some(), &timeline);
+    // Live captions show how far along the file is themselves, and would share the line.
+    let mut progress = input_duration
+        .filter(|_| !(args.tui || args.meter || live_captions))
+        .map(Progress::stderr);
 
-    let api_key = var("API_KEY").expect("API_KEY environment variable not set");
-    let gummy = Gummy::new(&api_key);
-    let gummy = gummy
-        .connect(None)
-        .await
-        .expect("Failed to connect to Gummy WebSocket");
-    let mut gummy = gummy
-        .start(Some("pcm"), Some(recorder_format.sample_rate), None, None)
+    let feed = match args.serve {
+        Some(addr) => {
+            let (feed, addr) = serve::start(addr).await.unwrap_or_else(|e| {
+                fail(Kind::Other, format!("Failed to listen on {}: {}", addr, e))
+            });
+            info!("Serving the transcript on http://{}", addr);
+            Some(feed)
+        }
+        None => None,
+    };
+    let mut caption_file = CaptionFile::from_args(&args);
+    let mut hotkeys = Hotkeys::from_args(&args)
+        .unwrap_or_else(|e| fail(Kind::Other, format!("Failed to set up hotkeys: {}", e)));
+    let mut markers = 0;
+    let mut clipboard = ClipboardSync::new(Box::<SystemClipboard>::default(), args.clipboard);
+    let broadcast = match args.broadcast_ws {
+        Some(addr) => {
+            let (broadcast, addr) = overlay::start(addr, args.partial_interval())
+                .await
+                .unwrap_or_else(|e| {
+                    fail(Kind::Other, format!("Failed to listen on {}: {}", addr, e))
+                });
+            info!("Broadcasting captions on ws://{}", addr);
+            Some(broadcast)
+        }
+        None => None,
+    };
+
+    let options = start_options(&args, sample_rate);
+    verify_format(&recorder_format, sample_rate, &options, args.force)
+        .unwrap_or_else(|message| fail(Kind::Config, message));
+    let mut session_info = SessionInfo {
+        started_at_ms: now_ms(),
+        sample_rate,
+        source_language: options
+            .source_language
+            .clone()
+            .filter(|language| language != "auto"),
+        target_languages: options.target_languages.clone(),
+        model: transcriber.model(),
+        task_ids: vec![],
+        usage: Usage::default(),
+        summary: None,
+    };
+    // Some hosts take a moment to deliver audio once started, which the task would spend
+    // on its silence timeout.
+    match source.wait_for_first_frame(FIRST_FRAME_TIMEOUT).await {
+        Ok(warm_up) if source.recorder().is_some() => {
+            info!(
+                "Audio came {} ms after starting the capture",
+                warm_up.as_millis()
+            )
+        }
+        Ok(_) => {}
+        Err(e) => fail(Kind::Device, e.to_string()),
+    }
+    let session = transcriber
+        .start(options.clone())
         .await
-        .unwrap();
+        .unwrap_or_else(|e| {
+            fail(
+                Kind::of_session(&e),
+                format!("Failed to start the transcription session: {:#}", e),
+            )
+        });
+    // Dropped connections are picked up again, with the audio held meanwhile.
+    let mut session = SupervisedSession::new(
+        transcriber.clone(),
+        options,
+        session,
+        sample_rate,
+        args.catch_up_speed,
+    );
+    if let Some(task_id) = session.task_ids().first() {
+        info!("Started task {}", task_id);
+    }
+    session_info.task_ids = session.task_ids().to_vec();
+    // Bytes of audio sent, for the usage recorded in JSON transcripts, and of the silence
+    // among them sent to keep the connection open rather than captured.
+    let mut sent_bytes = 0;
+    let mut keepalive_bytes = 0;
+    // What ended the session early, reported once everything is saved.
+    let mut failure = None;
+    let mut latency = LatencyMeter::default();
+    // With a pre-roll, the recorder held on to the audio from before the session was ready.
+    if let Some(recorder) = source.recorder() {
+        for sample_data in recorder.take_preroll() {
+            if let Some(tee) = &mut tee {
+                tee.write(&sample_data.data, sample_data.timestamp);
+            }
+            let samples =
+                resampling.process_and_save(raw.as_mut(), &sample_data.data, sample_data.timestamp);
+            if let Err(e) = session.send_samples(samples).await {
+                warn!("Failed to send the pre-roll: {}", e);
+                break;
+            }
+            latency.sent(audio_ms(sent_bytes, sample_rate), sample_data.timestamp);
+            timeline.sent(
+                audio_ms(sent_bytes, sample_rate),
+                sample_data.timestamp,
+                true,
+            );
+            sent_bytes += size_of_val(samples);
+        }
+    }
 
+    let started = Instant::now();
+    let mut auto_stop = AutoStop::from_args(&args);
+    let device = match source.recorder() {
+        Some(recorder) => recorder.device_name().to_string(),
+        None => args.input.clone().unwrap_or_default(),
+    };
+    let mut watchdog = Watchdog::new(source.recorder().map(|_| device.as_str()), started);
+    if let Some(session_dir) = &session_dir {
+        let meta = Meta {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            status: Status::Running,
+            device: device.clone(),
+            session: session_info.clone(),
+            ended_at_ms: None,
+            duration_ms: None,
+            revision: 0,
+        };
+        if let Err(e) = session_dir.begin(meta) {
+            warn!("Failed to write the session metadata: {}", e);
+        }
+    }
+    sinks.start(&session_info, &device);
+    let mut notifications = Notifications::from_args(&args);
+    if let Some(notifications) = &mut notifications {
+        let device = device.clone();
+        notifications.notify(Notice::Started { device }, Instant::now());
+    }
+    // Whether the session was connected when last checked, to notify when it changes.
+    let mut connected = true;
+    let mut watch = interval(WATCH_INTERVAL);
+    let mut tui = args.tui.then(|| {
+        let mut tui = Tui::enter(&device)
+            .unwrap_or_else(|e| fail(Kind::Other, format!("Failed to set up the terminal: {}", e)));
+        if let Some(display) = args.display {
+            tui.dashboard.display = display;
+        }
+        tui
+    });
+    let mut redraw = interval(REDRAW_INTERVAL);
+    // "pause" and "resume" lines on stdin mute and unmute the capture, "gain <db>" changes
+    // the capture gain, "mark" marks the moment in the transcript and "copy" copies the
+    // transcript so far. The dashboard takes keys instead. A file has no capture to control,
+    // and a read of stdin left pending would keep the process from exiting once it's done.
+    let mut commands = BufReader::new(tokio::io::stdin()).lines();
+    let mut commands_open = !args.tui && source.recorder().is_some();
+    let mut dropped_samples = 0;
+    // While silence is skipped, a short frame of silence now and then keeps the backend
+    // from timing out the session.
+    let mut keepalive = interval(KEEPALIVE_INTERVAL);
+    let keepalive_silence = vec![0; recorder_format.sample_rate as usize / 10];
+    let mut silent = false;
+    // However little audio the source hands out at a time, it goes out in frames.
+    let mut coalescer = FrameCoalescer::new(
+        sample_rate,
+        Duration::from_millis(args.send_frame_ms as u64),
+        coalesce::MAX_STALENESS,
+    );
+    let mut meter = interval(METER_INTERVAL);
+    let mut clip_check = interval(CLIP_CHECK_INTERVAL);
+    let mut clipped_samples = 0;
+    // Created once, so a signal arriving while another branch runs isn't missed.
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
     loop {
         select! {
-            sample_data_result= recorder.reveice_sample_data() => {
-                if let Some(sample_data) = sample_data_result {
-                    gummy
-                        .send(
-                            &sample_data.data
-                                .iter()
-                                .map(|s| s.to_le_bytes())
-                                .flatten()
-                                .collect::<Vec<u8>>(),
-                        )
-                        .await
-                        .unwrap();
+            line = commands.next_line(), if commands_open => {
+                match line {
+                    Ok(Some(line)) => match (line.trim(), source.recorder()) {
+                        ("", _) => {}
+                        ("mark", _) => {
+                            markers += 1;
+                            mark(&mut sinks, markers, sent_bytes, sample_rate);
+                        }
+                        ("copy", _) => match clipboard.copy_all() {
+                            Ok(sentences) => info!("Copied {} sentences", sentences),
+                            Err(e) => warn!("Failed to copy the transcript: {}", e),
+                        },
+                        (command, None) => warn!("No capture to control with {}", command),
+                        ("pause", Some(recorder)) => match recorder.pause() {
+                            Ok(()) => info!("Capture paused"),
+                            Err(e) => warn!("Failed to pause recorder: {}", e),
+                        },
+                        ("resume", Some(recorder)) => match recorder.resume() {
+                            Ok(()) => info!("Capture resumed"),
+                            Err(e) => warn!("Failed to resume recorder: {}", e),
+                        },
+                        (command, Some(recorder)) => {
+                            match command.strip_prefix("gain ").map(str::parse::<f32>) {
+                                Some(Ok(gain_db)) => {
+                                    recorder.set_gain_db(gain_db);
+                                    info!("Capture gain set to {} dB", gain_db);
+                                }
+                                _ => warn!("Unknown command: {}", command),
+                            }
+                        }
+                    },
+                    _ => commands_open = false,
+                }
+            },
+            _ = meter.tick(), if args.meter => {
+                if let Some(recorder) = source.recorder() {
+                    eprint!(
+                        "\r{}",
+                        meter_line(recorder.current_level(), recorder.gain_db())
+                    );
+                }
+            },
+            _ = clip_check.tick() => {
+                if let Some(recorder) = source.recorder()
+                    && recorder.clipped_samples() > clipped_samples
+                {
+                    warn!(
+                        "{} samples clipped at {} dB gain",
+                        recorder.clipped_samples() - clipped_samples,
+                        recorder.gain_db()
+                    );
+                    clipped_samples = recorder.clipped_samples();
+                }
+            },
+            _ = keepalive.tick(), if silent => {
+                let timestamp = now_ms();
+                let samples =
+                    resampling.process_and_save(raw.as_mut(), &keepalive_silence, timestamp);
+                match session.send_samples(samples).await {
+                    Ok(()) => {
+                        latency.sent(audio_ms(sent_bytes, sample_rate), timestamp);
+                        timeline.sent(audio_ms(sent_bytes, sample_rate), timestamp, false);
+                        sent_bytes += size_of_val(samples);
+                        keepalive_bytes += size_of_val(samples);
+                    }
+                    Err(e) => warn!("Failed to send keepalive: {}", e),
+                }
+            },
+            _ = coalescer.stale() => {
+                let sent = send_coalesced(
+                    &mut coalescer,
+                    &mut session,
+                    raw.as_mut(),
+                    &mut latency,
+                    &timeline,
+                    sent_bytes,
+                    sample_rate,
+                )
+                .await;
+                match sent {
+                    Ok(bytes) => sent_bytes += bytes,
+                    Err(e) => {
+                        failure = Some(Failure::new(
+                            Kind::of_session(&e),
+                            format!("Failed to send audio: {:#}", e),
+                        ));
+                        break;
+                    }
+                }
+            },
+            recorder_event = source.next_event() => {
+                match recorder_event {
+                    Some(RecorderEvent::Sample(sample_data)) => {
+                        auto_stop.hear(&sample_data.data);
+                        watchdog.hear(Level::of(&sample_data.data).rms_dbfs, Instant::now());
+                        if let Some(recorder) = source.recorder()
+                            && recorder.dropped_samples() > dropped_samples
+                        {
+                            warn!(
+                                "Audio was lost while sending fell behind, {} samples dropped",
+                                recorder.dropped_samples() - dropped_samples
+                            );
+                            dropped_samples = recorder.dropped_samples();
+                        }
+                        if let Some(tee) = &mut tee {
+                            tee.write(&sample_data.data, sample_data.timestamp);
+                        }
+                        // Frames of files are stamped with their position in the file.
+                        let captured_ms = match source.recorder() {
+                            Some(_) => sample_data.timestamp,
+                            None => now_ms(),
+                        };
+                        coalescer.push(resampling.process(&sample_data.data), captured_ms);
+                        if coalescer.is_full() {
+                            let sent = send_coalesced(
+                                &mut coalescer,
+                                &mut session,
+                                raw.as_mut(),
+                                &mut latency,
+                                &timeline,
+                                sent_bytes,
+                                sample_rate,
+                            )
+                            .await;
+                            match sent {
+                                Ok(bytes) => sent_bytes += bytes,
+                                Err(e) => {
+                                    failure = Some(Failure::new(
+                                        Kind::of_session(&e),
+                                        format!("Failed to send audio: {:#}", e),
+                                    ));
+                                    break;
+                                }
+                            }
+                        }
+                        if let Some(feed) = &feed {
+                            feed.queued(audio_ms(session.queued_bytes(), sample_rate));
+                            let catch_up = session.catch_up_remaining();
+                            feed.catch_up(catch_up.map(|remaining| remaining.as_millis() as u64));
+                        }
+                        if let Some(progress) = &mut progress {
+                            let audio = Duration::from_secs_f64(
+                                sample_data.data.len() as f64
+                                    / recorder_format.sample_rate as f64,
+                            );
+                            if let Err(e) = progress.sent(audio) {
+                                warn!("Failed to show the progress: {}", e);
+                            }
+                        }
+                    }
+                    Some(RecorderEvent::SilenceStarted) => {
+                        debug!("Silence started");
+                        silent = true;
+                        keepalive.reset();
+                    }
+                    Some(RecorderEvent::SilenceEnded) => {
+                        debug!("Silence ended");
+                        silent = false;
+                    }
+                    Some(RecorderEvent::Error(e)) => warn!("Recorder error: {}", e),
+                    Some(RecorderEvent::Warning(warning)) => {
+                        match &mut tui {
+                            Some(tui) => tui.dashboard.message = Some(warning.to_string()),
+                            // Printed regardless of the log level, a broken input ruins the
+                            // whole transcript.
+                            None => eprintln!("\rWarning: {}", warning),
+                        }
+                        let kind = match warning {
+                            SignalWarning::ClippingDetected { .. } => "clipping",
+                            SignalWarning::DropoutDetected { .. } => "dropout",
+                        };
+                        sinks.warning(kind, &warning.to_string());
+                    }
+                    Some(RecorderEvent::DeviceChanged { old, new }) => {
+                        info!("Capture moved from {} to {}", old, new);
+                    }
+                    Some(RecorderEvent::DeviceLost) => {
+                        failure = Some(Failure::new(Kind::Device, "Capture device lost"));
+                        break;
+                    }
+                    Some(RecorderEvent::Fatal(e)) => {
+                        failure = Some(Failure::new(Kind::Device, format!("Recorder failed: {}", e)));
+                        break;
+                    }
+                    None => break,
+                }
+            },
+            command = async { tui.as_mut()?.next_command().await }, if tui.is_some() => {
+                match command {
+                    Some(Command::Pause) => toggle_pause(source.as_mut()),
+                    Some(Command::Copy) => {
+                        if let Some(tui) = &mut tui {
+                            tui.copy_last_sentence();
+                        }
+                    }
+                    Some(Command::CopyAll) => {
+                        if let Some(tui) = &mut tui {
+                            tui.dashboard.message = Some(match clipboard.copy_all() {
+                                Ok(sentences) => format!("Copied {} sentences", sentences),
+                                Err(e) => format!("Failed to copy: {}", e),
+                            });
+                        }
+                    }
+                    Some(Command::Quit) | None => {
+                        info!("Quitting, finishing the session");
+                        break;
+                    }
+                }
+            },
+            action = async { hotkeys.as_mut()?.next().await }, if hotkeys.is_some() => {
+                match action {
+                    Some(HotkeyAction::Pause) => toggle_pause(source.as_mut()),
+                    Some(HotkeyAction::Marker) => {
+                        markers += 1;
+                        mark(&mut sinks, markers, sent_bytes, sample_rate);
+                    }
+                    Some(HotkeyAction::Stop) => {
+                        info!("Stopping, finishing the session");
+                        break;
+                    }
+                    None => hotkeys = None,
+                }
+            },
+            _ = watch.tick() => {
+                let paused = source.recorder().is_some_and(|recorder| recorder.is_paused());
+                for alert in watchdog.check(Instant::now(), paused) {
+                    match &mut tui {
+                        Some(tui) => tui.dashboard.message = Some(alert.to_string()),
+                        // Printed regardless of the log level, like signal warnings.
+                        None => eprintln!("\rWarning: {}", alert),
+                    }
+                    if let Some(feed) = &feed {
+                        feed.alert(&alert);
+                    }
+                    sinks.warning(alert.kind(), &alert.to_string());
+                }
+                if let Some(notifications) = &mut notifications
+                    && session.is_connected() != connected
+                {
+                    connected = !connected;
+                    let notice = match connected {
+                        true => Notice::Reconnected,
+                        false => Notice::Reconnecting,
+                    };
+                    notifications.notify(notice, Instant::now());
                 }
+                if let Some(session_dir) = &session_dir {
+                    let usage = Usage {
+                        audio_ms: audio_ms(sent_bytes, sample_rate),
+                    };
+                    session_dir.meta().record(usage, session.task_ids());
+                }
+            },
+            _ = redraw.tick(), if tui.is_some() => {
+                if let Some(tui) = &mut tui {
+                    let dashboard = &mut tui.dashboard;
+                    dashboard.elapsed = started.elapsed();
+                    dashboard.sent_bytes = sent_bytes;
+                    dashboard.connection = match session.catch_up_remaining() {
+                        _ if !session.is_connected() => Connection::Reconnecting,
+                        Some(remaining) => Connection::CatchingUp(remaining),
+                        None => Connection::Connected,
+                    };
+                    if let Some(recorder) = source.recorder() {
+                        dashboard.level = Some(recorder.current_level());
+                        dashboard.gain_db = recorder.gain_db();
+                        dashboard.paused = recorder.is_paused();
+                    }
+                    if let Some(line) = log.take() {
+                        dashboard.message = Some(line);
+                    }
+                    if let Err(e) = tui.draw() {
+                        warn!("Failed to draw the dashboard: {}", e);
+                    }
+                }
+            },
+            _ = &mut shutdown => {
+                info!("Interrupted, finishing the session");
+                break;
+            },
+            Some(()) = async { caption_file.as_ref()?.due().await; Some(()) },
+                if caption_file.is_some() =>
+            {
+                if let Some(caption_file) = &mut caption_file
+                    && let Err(e) = caption_file.write()
+                {
+                    warn!("Failed to write the captions: {}", e);
+                }
+            },
+            reason = auto_stop.wait() => {
+                info!("{}, finishing the session", reason);
+                break;
             },
-            recognition_result = gummy.receive() => {
-                if let Ok(data) = recognition_result {
-                    debug!("Received recognition result: {}", data.len());
-                    debug!("Message: {:?}",  data);
-                    
+            event = session.next_event() => {
+                match event {
+                    Ok(Some(event)) => {
+                        debug!("Message: {:?}", event);
+                        if matches!(
+                            event,
+                            TranscriptionEvent::Partial(_) | TranscriptionEvent::Final(_)
+                        ) {
+                            watchdog.answered();
+                        }
+                        latency.update(&event, now_ms());
+                        if let Some(sent_ms) = session.reconnected_at() {
+                            timeline.reconnected(sent_ms);
+                        }
+                        if let Some(session_dir) = &mut session_dir
+                            && let Err(e) = session_dir.event(&event, now_ms())
+                        {
+                            warn!("Failed to write the session events: {}", e);
+                        }
+                        if let Some(tui) = &mut tui {
+                            tui.dashboard.update(&event);
+                            tui.dashboard.latency = latency.recent();
+                        }
+                        sinks.on_event(&event).await;
+                        if let Some(progress) = &mut progress
+                            && let Err(e) = progress.received(&event)
+                        {
+                            warn!("Failed to show the progress: {}", e);
+                        }
+                        if let Some(feed) = &feed {
+                            feed.send(&event);
+                            feed.latency(latency.recent());
+                        }
+                        if let Some(broadcast) = &broadcast {
+                            broadcast.send(&event);
+                        }
+                        clipboard.update(&event);
+                        if let Some(caption_file) = &mut caption_file
+                            && let Err(e) = caption_file.update(&event)
+                        {
+                            warn!("Failed to write the captions: {}", e);
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        failure = Some(Failure::new(
+                            Kind::of_session(&e),
+                            format!("Transcription failed: {:#}", e),
+                        ));
+                        if let Some(tui) = &mut tui {
+                            tui.dashboard.connection = Connection::Lost;
+                        }
+                        break;
+                    }
                 }
             }
         }
     }
-    recorder.stop().expect("Failed to stop recorder");
+    exit_on_second_signal(session_dir.as_ref().map(SessionDir::meta));
+    if args.meter {
+        eprintln!();
+    }
+    if let Some(tui) = &mut tui {
+        if matches!(
+            tui.dashboard.connection,
+            Connection::Connected | Connection::CatchingUp(_)
+        ) {
+            tui.dashboard.connection = Connection::Finishing;
+        }
+        if let Err(e) = tui.draw() {
+            warn!("Failed to draw the dashboard: {}", e);
+        }
+    }
+    // Audio captured just before stopping is still queued; send it before finishing so the
+    // end of the last sentence isn't lost.
+    let remaining = source.stop().unwrap_or_else(|e| {
+        warn!("Failed to stop recorder: {}", e);
+        vec![]
+    });
+    // Behind the audio held, which goes out short of a frame once nothing is left.
+    let mut remaining = remaining.into_iter();
+    loop {
+        let sample_data = remaining.next();
+        if let Some(sample_data) = &sample_data {
+            if let Some(tee) = &mut tee {
+                tee.write(&sample_data.data, sample_data.timestamp);
+            }
+            coalescer.push(resampling.process(&sample_data.data), sample_data.timestamp);
+            if !coalescer.is_full() {
+                continue;
+            }
+        }
+        let sent = send_coalesced(
+            &mut coalescer,
+            &mut session,
+            raw.as_mut(),
+            &mut latency,
+            &timeline,
+            sent_bytes,
+            sample_rate,
+        )
+        .await;
+        match sent {
+            Ok(bytes) => sent_bytes += bytes,
+            Err(e) => {
+                debug!("Failed to send the remaining audio: {}", e);
+                break;
+            }
+        }
+        if sample_data.is_none() {
+            break;
+        }
+    }
+    session_info.task_ids = session.task_ids().to_vec();
+    if let Some(sent_ms) = session.reconnected_at() {
+        timeline.reconnected(sent_ms);
+    }
+    // Subtitles are written from the sentences seen so far even if this fails.
+    let (result, usage) = match timeout(FINISH_TIMEOUT, session.finish()).await {
+        Ok(Ok((result, usage))) => {
+            // Sentences finalized while finishing came in before the task finished.
+            for transcription in &result {
+                sinks
+                    .on_event(&TranscriptionEvent::Final(transcription.clone()))
+                    .await;
+            }
+            sinks.on_event(&TranscriptionEvent::Finished).await;
+            (result, usage)
+        }
+        Ok(Err(e)) => {
+            warn!("Failed to finish the session: {}", e);
+            (vec![], None)
+        }
+        Err(_) => {
+            warn!(
+                "The session didn't finish within {} s, the last sentences may be missing",
+                FINISH_TIMEOUT.as_secs()
+            );
+            (vec![], None)
+        }
+    };
+    session_info.usage = Usage {
+        audio_ms: audio_ms(sent_bytes, sample_rate),
+    };
+    let summary = Summary::new(
+        audio_ms(sent_bytes - keepalive_bytes, sample_rate),
+        summary::billed_ms(usage, sent_bytes as u64, sample_rate as u64 * 2),
+        &result,
+        args.pricing().as_ref(),
+        latency.average(),
+    );
+    session_info.summary = Some(summary.clone());
+    // Back to the shell for what's left to print.
+    drop(tui);
+    if args.latency_report {
+        match latency.summary() {
+            Some(summary) => eprintln!("Latency: {}", summary),
+            None => eprintln!("Latency: no sentence came back final during the session"),
+        }
+    }
+    if let Some(progress) = progress
+        && let Err(e) = progress.finish(&result)
+    {
+        warn!("Failed to show the progress: {}", e);
+    }
+    if let Some(feed) = feed {
+        feed.finish(&result);
+    }
+    if let Some(caption_file) = caption_file
+        && let Err(e) = caption_file.finish(&result)
+    {
+        warn!("Failed to write the captions: {}", e);
+    }
+    if let Some(broadcast) = broadcast {
+        for transcription in &result {
+            broadcast.send(&TranscriptionEvent::Final(transcription.clone()));
+        }
+    }
+    sinks.finalize(&result, &session_info).await;
+    eprintln!("{}", summary);
+    if let Some(notifications) = &mut notifications {
+        let notice = match &failure {
+            Some(failure) => Notice::Failed {
+                kind: failure.kind,
+                reason: failure.message.clone(),
+            },
+            None => Notice::Finished {
+                duration: started.elapsed(),
+                output: session_dir
+                    .as_ref()
+                    .map(SessionDir::path)
+                    .or_else(|| args.transcripts().into_iter().find_map(|(path, _)| path))
+                    .map(Path::to_path_buf),
+            },
+        };
+        notifications.notify(notice, Instant::now());
+    }
+    if let Some(tee) = tee {
+        report_saved(tee, recorder_format.sample_rate).await;
+    }
+    if let Some(raw) = raw {
+        report_saved(raw, sample_rate).await;
+    }
+    if let Some(session_dir) = session_dir {
+        let meta = session_dir.meta();
+        meta.record(session_info.usage, &session_info.task_ids);
+        meta.summarize(summary);
+        match meta.end(Status::Finished, now_ms()) {
+            Ok(()) => eprintln!("Saved the session to {}", session_dir.path().display()),
+            Err(e) => eprintln!("Failed to write the session metadata: {}", e),
+        }
+    }
+    if let Some(failure) = failure {
+        failure.exit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorder() -> OutputFormat {
+        OutputFormat {
+            channels: 1,
+            sample_rate: 16000,
+            sample_format: RecorderSampleFormat::I16,
+        }
+    }
+
+    fn options(sample_rate: u32) -> StartOptions {
+        StartOptions {
+            format: Some("pcm".to_string()),
+            sample_rate: Some(sample_rate),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn checks_the_audio_as_sent() {
+        // Resampled to the task's rate.
+        assert_eq!(sent_format(&recorder(), 24000).sample_rate, 24000);
+        let float = OutputFormat {
+            sample_format: RecorderSampleFormat::F32,
+            ..recorder()
+        };
+        assert_eq!(
+            sent_format(&float, 16000).sample_format,
+            SampleFormat::Other("f32".to_string())
+        );
+    }
+
+    #[test]
+    fn fails_on_a_mismatch_unless_forced() {
+        let error = verify_format(&recorder(), 16000, &options(48000), false).unwrap_err();
+        assert_eq!(
+            error,
+            "The audio is at 16000 Hz, but the task is started at 48000 Hz; \
+             pass --force to start anyway"
+        );
+        assert_eq!(
+            verify_format(&recorder(), 16000, &options(48000), true),
+            Ok(())
+        );
+        assert_eq!(
+            verify_format(&recorder(), 48000, &options(48000), false),
+            Ok(())
+        );
+    }
 }
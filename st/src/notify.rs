@@ -0,0 +1,268 @@
+//! `--notify`: desktop notifications as a session starts, loses the connection and gets it
+//! back, fails and finishes, for st running out of sight, with st built with the notify
+//! feature.
+//!
+//! Trouble that keeps coming back is notified once a minute at most for each kind of it.
+
+use crate::args::Args;
+use crate::failure::Kind;
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Time before trouble of a kind already notified is notified again.
+const REPEAT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Something worth telling about a session.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Notice {
+    Started {
+        device: String,
+    },
+    /// The connection to the backend dropped, and is being picked up again.
+    Reconnecting,
+    Reconnected,
+    /// The session ended early.
+    Failed {
+        kind: Kind,
+        reason: String,
+    },
+    Finished {
+        duration: Duration,
+        /// Where the transcript went, if anywhere.
+        output: Option<PathBuf>,
+    },
+}
+
+impl Notice {
+    /// The title and the body of the notification.
+    fn text(&self) -> (&'static str, String) {
+        match self {
+            Notice::Started { device } => ("Session started", format!("Capturing from {}", device)),
+            Notice::Reconnecting => (
+                "Connection lost",
+                "Reconnecting, the audio is held meanwhile".to_string(),
+            ),
+            Notice::Reconnected => ("Reconnected", "Transcribing again".to_string()),
+            Notice::Failed { reason, .. } => ("Session failed", reason.clone()),
+            Notice::Finished { duration, output } => {
+                let seconds = duration.as_secs();
+                let mut body = format!(
+                    "Ran for {}:{:02}:{:02}",
+                    seconds / 3600,
+                    seconds / 60 % 60,
+                    seconds % 60
+                );
+                if let Some(output) = output {
+                    body.push_str(&format!(", saved to {}", output.display()));
+                }
+                ("Session finished", body)
+            }
+        }
+    }
+}
+
+/// Kinds of trouble, each notified once a minute at most.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Trouble {
+    Connection,
+    Failure(Kind),
+}
+
+/// Shows notifications.
+pub trait Notifier {
+    fn show(&mut self, title: &str, body: &str) -> Result<(), String>;
+}
+
+/// Decides what's notified, and notifies it. A notification that fails is warned about and
+/// otherwise ignored.
+pub struct Notifications {
+    notifier: Box<dyn Notifier>,
+    notified: HashMap<Trouble, Instant>,
+    // Whether the lost connection was notified, so getting it back is too.
+    reconnecting: bool,
+}
+
+impl Notifications {
+    pub fn new(notifier: Box<dyn Notifier>) -> Self {
+        Notifications {
+            notifier,
+            notified: HashMap::new(),
+            reconnecting: false,
+        }
+    }
+
+    /// Notifications on the desktop, with `--notify`.
+    pub fn from_args(args: &Args) -> Option<Self> {
+        args.notify.then(|| Notifications::new(desktop()))
+    }
+
+    /// Notifies `notice`, unless it's trouble notified less than a minute before `now`.
+    pub fn notify(&mut self, notice: Notice, now: Instant) {
+        let trouble = match &notice {
+            Notice::Reconnecting => Some(Trouble::Connection),
+            Notice::Failed { kind, .. } => Some(Trouble::Failure(*kind)),
+            Notice::Reconnected if !self.reconnecting => return,
+            _ => None,
+        };
+        if let Some(trouble) = trouble {
+            if self
+                .notified
+                .get(&trouble)
+                .is_some_and(|notified| now.duration_since(*notified) < REPEAT_INTERVAL)
+            {
+                debug!("Not notifying {:?} again so soon", notice);
+                return;
+            }
+            self.notified.insert(trouble, now);
+        }
+        match notice {
+            Notice::Reconnecting => self.reconnecting = true,
+            Notice::Reconnected => self.reconnecting = false,
+            _ => {}
+        }
+        let (title, body) = notice.text();
+        if let Err(e) = self.notifier.show(title, &body) {
+            warn!("Failed to show a notification: {}", e);
+        }
+    }
+}
+
+#[cfg(feature = "notify")]
+struct Desktop;
+
+#[cfg(feature = "notify")]
+impl Notifier for Desktop {
+    fn show(&mut self, title: &str, body: &str) -> Result<(), String> {
+        notify_rust::Notification::new()
+            .appname("st")
+            .summary(title)
+            .body(body)
+            .show()
+            .map(drop)
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(feature = "notify")]
+fn desktop() -> Box<dyn Notifier> {
+    Box::new(Desktop)
+}
+
+/// Never called, `Args::validate` rejects `--notify` without the feature.
+#[cfg(not(feature = "notify"))]
+fn desktop() -> Box<dyn Notifier> {
+    unreachable!("notifications need st built with the notify feature")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Records the titles of what it shows, failing with `fail`.
+    struct MockNotifier {
+        shown: Rc<RefCell<Vec<String>>>,
+        fail: bool,
+    }
+
+    impl Notifier for MockNotifier {
+        fn show(&mut self, title: &str, _body: &str) -> Result<(), String> {
+            self.shown.borrow_mut().push(title.to_string());
+            match self.fail {
+                true => Err("no notification daemon".to_string()),
+                false => Ok(()),
+            }
+        }
+    }
+
+    fn notifications(fail: bool) -> (Notifications, Rc<RefCell<Vec<String>>>) {
+        let shown = Rc::default();
+        let notifier = MockNotifier {
+            shown: Rc::clone(&shown),
+            fail,
+        };
+        (Notifications::new(Box::new(notifier)), shown)
+    }
+
+    fn failed(kind: Kind) -> Notice {
+        Notice::Failed {
+            kind,
+            reason: "Capture device lost".to_string(),
+        }
+    }
+
+    #[test]
+    fn notifies_a_session_from_start_to_finish() {
+        let (mut notifications, shown) = notifications(false);
+        let start = Instant::now();
+        notifications.notify(
+            Notice::Started {
+                device: "Speakers".to_string(),
+            },
+            start,
+        );
+        notifications.notify(Notice::Reconnecting, start);
+        notifications.notify(Notice::Reconnected, start);
+        notifications.notify(
+            Notice::Finished {
+                duration: Duration::from_secs(3723),
+                output: Some(PathBuf::from("talk.srt")),
+            },
+            start,
+        );
+        assert_eq!(
+            *shown.borrow(),
+            [
+                "Session started",
+                "Connection lost",
+                "Reconnected",
+                "Session finished"
+            ]
+        );
+        let finished = Notice::Finished {
+            duration: Duration::from_secs(3723),
+            output: Some(PathBuf::from("talk.srt")),
+        };
+        assert_eq!(finished.text().1, "Ran for 1:02:03, saved to talk.srt");
+    }
+
+    #[test]
+    fn notifies_trouble_of_a_kind_once_a_minute() {
+        let (mut notifications, shown) = notifications(false);
+        let start = Instant::now();
+        for seconds in [0, 10, 59] {
+            let now = start + Duration::from_secs(seconds);
+            notifications.notify(Notice::Reconnecting, now);
+            notifications.notify(Notice::Reconnected, now);
+            notifications.notify(failed(Kind::Device), now);
+        }
+        // Another kind of failure is notified all the same.
+        notifications.notify(failed(Kind::Network), start + Duration::from_secs(59));
+        assert_eq!(
+            *shown.borrow(),
+            [
+                "Connection lost",
+                "Reconnected",
+                "Session failed",
+                "Session failed"
+            ]
+        );
+        let later = start + Duration::from_secs(60);
+        notifications.notify(Notice::Reconnecting, later);
+        notifications.notify(failed(Kind::Device), later);
+        assert_eq!(shown.borrow().len(), 6);
+    }
+
+    #[test]
+    fn carries_on_when_notifications_fail() {
+        let (mut notifications, shown) = notifications(true);
+        let start = Instant::now();
+        notifications.notify(Notice::Reconnecting, start);
+        notifications.notify(Notice::Reconnected, start);
+        notifications.notify(failed(Kind::Auth), start);
+        assert_eq!(shown.borrow().len(), 3);
+    }
+}
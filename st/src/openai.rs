@@ -0,0 +1,377 @@
+use crate::transcriber::{Transcriber, TranscriptionSession};
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use futures_util::{SinkExt, StreamExt};
+use gummy::{StartOptions, Transcription, TranscriptionEvent};
+use log::debug;
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async_tls_with_config};
+use tungstenite::Message;
+use tungstenite::client::IntoClientRequest;
+
+pub const DEFAULT_URL: &str = "wss://api.openai.com/v1/realtime?intent=transcription";
+const DEFAULT_MODEL: &str = "gpt-4o-transcribe";
+
+/// The realtime API only accepts 24 kHz mono PCM16 audio.
+pub const SAMPLE_RATE: u32 = 24000;
+
+/// Transcription backend for the OpenAI Realtime API.
+pub struct OpenAiTranscriber {
+    api_key: String,
+    url: Option<String>,
+    model: String,
+}
+
+impl OpenAiTranscriber {
+    pub fn new(api_key: &str, url: Option<&str>) -> Self {
+        OpenAiTranscriber {
+            api_key: api_key.to_string(),
+            url: url.map(|url| url.to_string()),
+            model: DEFAULT_MODEL.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Transcriber for OpenAiTranscriber {
+    fn preferred_sample_rate(&self) -> Option<u32> {
+        Some(SAMPLE_RATE)
+    }
+
+    fn model(&self) -> String {
+        self.model.clone()
+    }
+
+    async fn start(&self, options: StartOptions) -> anyhow::Result<Box<dyn TranscriptionSession>> {
+        if let Some(sample_rate) = options.sample_rate.filter(|rate| *rate != SAMPLE_RATE) {
+            anyhow::bail!(
+                "OpenAI realtime transcription requires {} Hz audio, got {} Hz",
+                SAMPLE_RATE,
+                sample_rate
+            );
+        }
+        let url = self.url.as_deref().unwrap_or(DEFAULT_URL);
+        let mut request = url.into_client_request()?;
+        request
+            .headers_mut()
+            .insert("Authorization", format!("Bearer {}", self.api_key).parse()?);
+        request
+            .headers_mut()
+            .insert("OpenAI-Beta", "realtime=v1".parse()?);
+        let (mut stream, _) = connect_async_tls_with_config(request, None, false, None).await?;
+
+        let mut transcription = json!({ "model": self.model });
+        if let Some(language) = options
+            .source_language
+            .as_deref()
+            .filter(|language| *language != "auto")
+        {
+            transcription["language"] = json!(language);
+        }
+        let update = json!({
+            "type": "transcription_session.update",
+            "session": {
+                "input_audio_format": "pcm16",
+                "input_audio_transcription": transcription,
+                "turn_detection": { "type": "server_vad" },
+            },
+        });
+        stream
+            .send(Message::Text(update.to_string().into()))
+            .await?;
+
+        while let Some(message) = stream.next().await {
+            if let Message::Text(text) = message? {
+                let event: Value = serde_json::from_str(&text)?;
+                match event["type"].as_str() {
+                    Some("transcription_session.updated") => {
+                        debug!("OpenAI transcription session configured");
+                        return Ok(Box::new(OpenAiSession::new(stream)));
+                    }
+                    Some("error") => return Err(parse_error(&event)),
+                    _ => {}
+                }
+            }
+        }
+        anyhow::bail!("Connection closed before the session was configured")
+    }
+}
+
+#[derive(Default)]
+struct OpenAiSession {
+    stream: Option<WebSocketStream<MaybeTlsStream<TcpStream>>>,
+    // Conversation item id to sentence id.
+    items: HashMap<String, u64>,
+    // Items committed to the conversation whose transcription hasn't completed yet.
+    pending: HashSet<String>,
+    result: Vec<Transcription>,
+    finishing: bool,
+    commit_acknowledged: bool,
+    finished: bool,
+}
+
+impl OpenAiSession {
+    fn new(stream: WebSocketStream<MaybeTlsStream<TcpStream>>) -> Self {
+        OpenAiSession {
+            stream: Some(stream),
+            ..Default::default()
+        }
+    }
+
+    fn sentence(&mut self, item_id: &str) -> &mut Transcription {
+        let next_id = self.items.len() as u64;
+        let sentence_id = *self.items.entry(item_id.to_string()).or_insert(next_id);
+        if sentence_id as usize >= self.result.len() {
+            self.result.push(Transcription {
+                sentence_id,
+                begin_time: 0,
+                end_time: 0,
+                text: String::new(),
+                is_final: false,
+                translated_text: None,
+                translations: vec![],
+                words: vec![],
+                confidence: None,
+                source_label: None,
+            });
+        }
+        &mut self.result[sentence_id as usize]
+    }
+
+    fn handle_event(&mut self, event: &Value) -> anyhow::Result<Option<TranscriptionEvent>> {
+        let item_id = event["item_id"].as_str().unwrap_or_default();
+        match event["type"].as_str().unwrap_or_default() {
+            "error" => {
+                // Committing an empty buffer while finishing means there's nothing left.
+                if self.finishing && event["error"]["code"] == "input_audio_buffer_commit_empty" {
+                    self.commit_acknowledged = true;
+                } else {
+                    return Err(parse_error(event));
+                }
+            }
+            "input_audio_buffer.speech_started" => {
+                self.sentence(item_id).begin_time = event["audio_start_ms"].as_u64().unwrap_or(0);
+            }
+            "input_audio_buffer.speech_stopped" => {
+                self.sentence(item_id).end_time = event["audio_end_ms"].as_u64().unwrap_or(0);
+            }
+            "input_audio_buffer.committed" => {
+                self.sentence(item_id);
+                self.pending.insert(item_id.to_string());
+                if self.finishing {
+                    self.commit_acknowledged = true;
+                }
+            }
+            "conversation.item.input_audio_transcription.delta" => {
+                let sentence = self.sentence(item_id);
+                sentence
+                    .text
+                    .push_str(event["delta"].as_str().unwrap_or_default());
+                return Ok(Some(TranscriptionEvent::Partial(sentence.clone())));
+            }
+            "conversation.item.input_audio_transcription.completed" => {
+                let sentence = self.sentence(item_id);
+                sentence.text = event["transcript"].as_str().unwrap_or_default().to_string();
+                sentence.is_final = true;
+                let sentence = sentence.clone();
+                self.pending.remove(item_id);
+                return Ok(Some(TranscriptionEvent::Final(sentence)));
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+}
+
+#[async_trait]
+impl TranscriptionSession for OpenAiSession {
+    async fn send_audio(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        let append = json!({
+            "type": "input_audio_buffer.append",
+            "audio": STANDARD.encode(data),
+        });
+        let stream = self.stream.as_mut().unwrap();
+        stream
+            .send(Message::Text(append.to_string().into()))
+            .await?;
+        Ok(())
+    }
+
+    async fn next_event(&mut self) -> anyhow::Result<Option<TranscriptionEvent>> {
+        if self.finished {
+            return Ok(None);
+        }
+        loop {
+            if self.finishing && self.commit_acknowledged && self.pending.is_empty() {
+                self.finished = true;
+                return Ok(Some(TranscriptionEvent::Finished));
+            }
+            let Some(message) = self.stream.as_mut().unwrap().next().await else {
+                return Ok(None);
+            };
+            match message? {
+                Message::Text(text) => {
+                    let event: Value = serde_json::from_str(&text)?;
+                    let event = self.handle_event(&event)?;
+                    if event.is_some() {
+                        return Ok(event);
+                    }
+                }
+                Message::Close(frame) => {
+                    debug!("Connection closed: {:?}", frame);
+                    return Ok(None);
+                }
+                _ => debug!("Received non-text message, ignoring."),
+            }
+        }
+    }
+
+    async fn finish(mut self: Box<Self>) -> anyhow::Result<Vec<Transcription>> {
+        let commit = json!({ "type": "input_audio_buffer.commit" });
+        let stream = self.stream.as_mut().unwrap();
+        stream
+            .send(Message::Text(commit.to_string().into()))
+            .await?;
+        self.finishing = true;
+        while let Some(event) = self.next_event().await? {
+            if let TranscriptionEvent::Finished = event {
+                break;
+            }
+        }
+        let mut stream = self.stream.take().unwrap();
+        stream.close(None).await.ok();
+        Ok(self.result)
+    }
+}
+
+fn parse_error(event: &Value) -> anyhow::Error {
+    anyhow::anyhow!(
+        "OpenAI error ({}): {}",
+        event["error"]["code"]
+            .as_str()
+            .or(event["error"]["type"].as_str())
+            .unwrap_or("unknown"),
+        event["error"]["message"].as_str().unwrap_or("unknown")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::accept_hdr_async;
+    use tungstenite::handshake::server::{Request, Response};
+
+    fn text(value: Value) -> Message {
+        Message::Text(value.to_string().into())
+    }
+
+    /// Accepts one connection, checks the framing of what the client sends and answers with a
+    /// scripted transcription of a single item.
+    #[allow(clippy::result_large_err)]
+    async fn mock_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = accept_hdr_async(stream, |request: &Request, response: Response| {
+                assert_eq!(request.headers()["Authorization"], "Bearer test-key");
+                assert_eq!(request.headers()["OpenAI-Beta"], "realtime=v1");
+                Ok(response)
+            })
+            .await
+            .unwrap();
+
+            let update: Value = match ws.next().await.unwrap().unwrap() {
+                Message::Text(text) => serde_json::from_str(&text).unwrap(),
+                message => panic!("unexpected message {:?}", message),
+            };
+            assert_eq!(update["type"], "transcription_session.update");
+            assert_eq!(update["session"]["input_audio_format"], "pcm16");
+            assert_eq!(
+                update["session"]["input_audio_transcription"]["language"],
+                "en"
+            );
+            ws.send(text(json!({ "type": "transcription_session.updated" })))
+                .await
+                .unwrap();
+
+            while let Some(Ok(Message::Text(message))) = ws.next().await {
+                let message: Value = serde_json::from_str(&message).unwrap();
+                match message["type"].as_str().unwrap() {
+                    "input_audio_buffer.append" => {
+                        let audio = STANDARD.decode(message["audio"].as_str().unwrap()).unwrap();
+                        assert_eq!(audio, [1, 0, 255, 127]);
+                        for event in [
+                            json!({ "type": "input_audio_buffer.speech_started", "item_id": "a", "audio_start_ms": 100 }),
+                            json!({ "type": "conversation.item.input_audio_transcription.delta", "item_id": "a", "delta": "Hel" }),
+                        ] {
+                            ws.send(text(event)).await.unwrap();
+                        }
+                    }
+                    "input_audio_buffer.commit" => {
+                        for event in [
+                            json!({ "type": "input_audio_buffer.speech_stopped", "item_id": "a", "audio_end_ms": 900 }),
+                            json!({ "type": "input_audio_buffer.committed", "item_id": "a" }),
+                            json!({ "type": "conversation.item.input_audio_transcription.delta", "item_id": "a", "delta": "lo" }),
+                            json!({ "type": "conversation.item.input_audio_transcription.completed", "item_id": "a", "transcript": "Hello." }),
+                        ] {
+                            ws.send(text(event)).await.unwrap();
+                        }
+                    }
+                    other => panic!("unexpected message type {}", other),
+                }
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn transcribes_through_mock_server() {
+        let url = mock_server().await;
+        let transcriber = OpenAiTranscriber::new("test-key", Some(&url));
+        let mut session = transcriber
+            .start(StartOptions {
+                sample_rate: Some(SAMPLE_RATE),
+                source_language: Some("en".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let samples: [i16; 2] = [1, i16::MAX];
+        let data = samples
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect::<Vec<u8>>();
+        session.send_audio(&data).await.unwrap();
+        match session.next_event().await.unwrap() {
+            Some(TranscriptionEvent::Partial(transcription)) => {
+                assert_eq!(transcription.text, "Hel");
+                assert_eq!(transcription.begin_time, 100);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        let result = session.finish().await.unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].text, "Hello.");
+        assert_eq!(result[0].begin_time, 100);
+        assert_eq!(result[0].end_time, 900);
+    }
+
+    #[tokio::test]
+    async fn rejects_other_sample_rates() {
+        let transcriber = OpenAiTranscriber::new("test-key", Some("ws://127.0.0.1:1"));
+        let result = transcriber
+            .start(StartOptions {
+                sample_rate: Some(48000),
+                ..Default::default()
+            })
+            .await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,1248 @@
+//! `--output` and `--format`: the transcript of a session as text, subtitles or JSON.
+
+use crate::summary::Summary;
+use clap::ValueEnum;
+use gummy::{Transcription, Word};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Shortest a subtitle cue is shown for, unless the next one starts sooner.
+const MIN_CUE_MS: u64 = 500;
+/// Longest a paragraph of a text transcript gets before the next sentence starts another,
+/// in characters.
+pub const MAX_PARAGRAPH_CHARS: usize = 600;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TranscriptFormat {
+    /// Paragraphs of sentences split at pauses, each followed by its translation, written
+    /// when the session ends.
+    Txt,
+    /// SubRip subtitles, written when the session ends.
+    Srt,
+    /// WebVTT subtitles, written when the session ends.
+    Vtt,
+    /// A JSON document with the session and its sentences, written when the session ends.
+    Json,
+    /// A JSON object per line and sentence.
+    Jsonl,
+}
+
+impl TranscriptFormat {
+    pub fn is_subtitles(self) -> bool {
+        matches!(self, TranscriptFormat::Srt | TranscriptFormat::Vtt)
+    }
+}
+
+/// What a JSON transcript records about its session.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SessionInfo {
+    /// When the session started, in milliseconds since the Unix epoch.
+    pub started_at_ms: u64,
+    /// Rate of the audio sent, in Hz.
+    pub sample_rate: u32,
+    /// Language spoken, or `None` if the backend detected it.
+    pub source_language: Option<String>,
+    pub target_languages: Vec<String>,
+    pub model: String,
+    /// IDs the backend gave the tasks of the session, a new one for each reconnection, as
+    /// its support asks for them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub task_ids: Vec<String>,
+    pub usage: Usage,
+    /// What the session came to, once it has ended.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<Summary>,
+}
+
+/// What a session used of the backend.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    /// Audio sent for transcription, in milliseconds.
+    pub audio_ms: u64,
+}
+
+/// A moment marked during a session, like with `--hotkey-marker`, or a stretch of it, like
+/// a silence.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Marker {
+    pub label: String,
+    /// When, in milliseconds, relative to the start of the task like sentence times.
+    pub time: u64,
+    /// When the stretch marked ends, for markers of a stretch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<u64>,
+}
+
+/// Label of the markers of silences.
+const SILENCE: &str = "silence";
+
+/// When a sentence was spoken by the wall clock, in ISO 8601 UTC to the millisecond, as
+/// JSON transcripts record it next to its times.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WallClock {
+    pub begin_at: String,
+    pub end_at: String,
+    /// Whether the times are estimates, for sentences of a task started again after the
+    /// connection dropped, which are placed in the session by the audio sent before.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub estimated: bool,
+}
+
+/// A sentence of a JSON transcript, with when it was spoken if the timeline could tell.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TimedSentence {
+    #[serde(flatten)]
+    pub sentence: Transcription,
+    #[serde(flatten)]
+    pub wall_clock: Option<WallClock>,
+}
+
+/// The document of `--format json`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptDocument {
+    pub session: SessionInfo,
+    pub sentences: Vec<TimedSentence>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub markers: Vec<Marker>,
+}
+
+/// Where subtitles show the translations of sentences.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SubtitleTranslation {
+    /// On the line under the text of each cue.
+    Inline,
+    /// In a file per target language next to the subtitles, like `talk.zh.vtt`.
+    Track,
+}
+
+/// How sentences are laid out as subtitle cues.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SubtitleOptions {
+    /// Whether cues show the translation under the text.
+    pub translation: bool,
+    /// Longest a cue lasts before it's split between words, for sentences with word
+    /// timings.
+    pub max_cue_ms: u64,
+    /// Whether silences get a cue, for transcripts that mark them.
+    pub silences: bool,
+}
+
+/// Where text transcripts show the translations of sentences.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TextTranslation {
+    /// Under each paragraph.
+    Interleaved,
+    /// In a file per target language next to the transcript, like `talk.zh.txt`.
+    File,
+}
+
+/// Which times text transcripts show.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Timestamps {
+    #[default]
+    None,
+    /// On a line heading each paragraph.
+    Paragraph,
+    /// Before each sentence.
+    Sentence,
+}
+
+/// How sentences are laid out as paragraphs of text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextOptions {
+    /// Pause between sentences that starts a new paragraph, in milliseconds.
+    pub paragraph_gap_ms: u64,
+    /// Longest a paragraph gets before the next sentence starts another, in characters.
+    pub max_paragraph_chars: usize,
+    pub timestamps: Timestamps,
+    /// Whether paragraphs are followed by their translation.
+    pub translation: bool,
+}
+
+impl Default for TextOptions {
+    fn default() -> Self {
+        TextOptions {
+            paragraph_gap_ms: 2_000,
+            max_paragraph_chars: MAX_PARAGRAPH_CHARS,
+            timestamps: Timestamps::None,
+            translation: true,
+        }
+    }
+}
+
+/// Writes final sentences as they come in, each once, flushing after every sentence so
+/// the transcript can be followed live.
+///
+/// Text, subtitles and JSON documents are the exception and are written by `finish`, from
+/// every sentence of the session: paragraphs end at the pause before the next sentence,
+/// cues are laid out in time order and can't overlap, and a document is only complete
+/// once the session is.
+pub struct TranscriptWriter<W: Write> {
+    format: TranscriptFormat,
+    subtitles: SubtitleOptions,
+    text: TextOptions,
+    out: W,
+    written: HashSet<u64>,
+    // Every sentence written, in the order they came in, and when those the wall clock
+    // could place were spoken.
+    sentences: Vec<Transcription>,
+    wall_clock: HashMap<u64, WallClock>,
+    markers: Vec<Marker>,
+    // Shortest silence marked, if silences are.
+    min_silence_ms: Option<u64>,
+    // Where the session starts and ends, on the clock of the transcript.
+    span: Option<(u64, u64)>,
+}
+
+impl<W: Write> TranscriptWriter<W> {
+    pub fn new(format: TranscriptFormat, subtitles: SubtitleOptions, out: W) -> Self {
+        TranscriptWriter {
+            format,
+            subtitles,
+            text: TextOptions::default(),
+            out,
+            written: HashSet::new(),
+            sentences: vec![],
+            wall_clock: HashMap::new(),
+            markers: vec![],
+            min_silence_ms: None,
+            span: None,
+        }
+    }
+
+    /// Lays text out with `text` rather than the default options.
+    pub fn with_text(mut self, text: TextOptions) -> Self {
+        self.text = text;
+        self
+    }
+
+    /// Marks the silences longer than `min_ms` in text and documents, and in subtitles with
+    /// `SubtitleOptions::silences`.
+    pub fn with_silences(mut self, min_ms: u64) -> Self {
+        self.min_silence_ms = Some(min_ms);
+        self
+    }
+
+    /// Has silences at the start and end of the session found from `begin` to `end`, the
+    /// times it spans on the clock of the transcript, rather than from 0 to the end of the
+    /// audio sent.
+    pub fn set_span(&mut self, begin: u64, end: u64) {
+        self.span = Some((begin, end));
+    }
+
+    /// Writes `transcription`, unless a sentence with its id was written before.
+    pub fn write(&mut self, transcription: &Transcription) -> io::Result<()> {
+        self.write_at(transcription, None)
+    }
+
+    /// Writes `transcription` like `write`, spoken at `wall_clock`, which JSON transcripts
+    /// record.
+    pub fn write_at(
+        &mut self,
+        transcription: &Transcription,
+        wall_clock: Option<WallClock>,
+    ) -> io::Result<()> {
+        if !self.written.insert(transcription.sentence_id) {
+            return Ok(());
+        }
+        self.sentences.push(transcription.clone());
+        if let Some(wall_clock) = &wall_clock {
+            self.wall_clock
+                .insert(transcription.sentence_id, wall_clock.clone());
+        }
+        match self.format {
+            TranscriptFormat::Jsonl => {
+                let line = TimedSentence {
+                    sentence: transcription.clone(),
+                    wall_clock,
+                };
+                writeln!(self.out, "{}", serde_json::to_string(&line)?)?
+            }
+            TranscriptFormat::Txt
+            | TranscriptFormat::Srt
+            | TranscriptFormat::Vtt
+            | TranscriptFormat::Json => return Ok(()),
+        }
+        self.out.flush()
+    }
+
+    /// Writes `marker` after the sentences written so far, or among them by its time in
+    /// text, subtitles and documents.
+    pub fn mark(&mut self, marker: Marker) -> io::Result<()> {
+        match self.format {
+            TranscriptFormat::Jsonl => {
+                writeln!(self.out, "{}", serde_json::json!({ "marker": &marker }))?
+            }
+            TranscriptFormat::Txt
+            | TranscriptFormat::Srt
+            | TranscriptFormat::Vtt
+            | TranscriptFormat::Json => {}
+        }
+        self.markers.push(marker);
+        self.out.flush()
+    }
+
+    /// Writes the sentences of the session result that weren't written yet, typically
+    /// those finalized while the session finished, or the whole text, subtitles file or
+    /// document.
+    ///
+    /// `result` may be empty when the session failed to finish; text, subtitles and
+    /// documents then hold the sentences written before.
+    pub fn finish(&mut self, result: &[Transcription], session: &SessionInfo) -> io::Result<()> {
+        for transcription in result {
+            self.write(transcription)?;
+        }
+        let mut markers = self.markers.clone();
+        let marks_silences = match self.format {
+            TranscriptFormat::Txt | TranscriptFormat::Json => true,
+            TranscriptFormat::Srt | TranscriptFormat::Vtt => self.subtitles.silences,
+            TranscriptFormat::Jsonl => false,
+        };
+        if let Some(min_ms) = self.min_silence_ms.filter(|_| marks_silences) {
+            let (begin, end) = self.span.unwrap_or((0, session.usage.audio_ms));
+            markers.extend(silences(&self.sentences, begin, end, min_ms));
+        }
+        match self.format {
+            TranscriptFormat::Txt => self
+                .out
+                .write_all(text(&self.sentences, &markers, &self.text).as_bytes())?,
+            TranscriptFormat::Srt => self
+                .out
+                .write_all(srt(&self.sentences, &markers, &self.subtitles).as_bytes())?,
+            TranscriptFormat::Vtt => self
+                .out
+                .write_all(vtt(&self.sentences, &markers, &self.subtitles).as_bytes())?,
+            TranscriptFormat::Json => {
+                markers.sort_by_key(|marker| marker.time);
+                let sentences = self
+                    .sentences
+                    .iter()
+                    .map(|sentence| TimedSentence {
+                        sentence: sentence.clone(),
+                        wall_clock: self.wall_clock.get(&sentence.sentence_id).cloned(),
+                    })
+                    .collect();
+                let document = TranscriptDocument {
+                    session: session.clone(),
+                    sentences,
+                    markers,
+                };
+                serde_json::to_writer_pretty(&mut self.out, &document)?;
+                writeln!(self.out)?;
+            }
+            TranscriptFormat::Jsonl => {}
+        }
+        self.out.flush()
+    }
+
+    pub fn format(&self) -> TranscriptFormat {
+        self.format
+    }
+
+    /// Every sentence written, in the order they came in.
+    pub fn sentences(&self) -> &[Transcription] {
+        &self.sentences
+    }
+}
+
+/// The stretches from `begin` to `end` longer than `min_ms` without a sentence, as markers
+/// spanning them. Sentences without text don't break a silence.
+pub fn silences(
+    transcriptions: &[Transcription],
+    begin: u64,
+    end: u64,
+    min_ms: u64,
+) -> Vec<Marker> {
+    let mut sentences = transcriptions
+        .iter()
+        .filter(|t| !t.text.trim().is_empty())
+        .collect::<Vec<_>>();
+    sentences.sort_by_key(|t| (t.begin_time, t.end_time));
+    let mut silences = vec![];
+    let mut said_until = begin;
+    let mut silence = |from: u64, to: u64| {
+        if to.saturating_sub(from) > min_ms {
+            silences.push(Marker {
+                label: SILENCE.to_string(),
+                time: from,
+                end: Some(to),
+            });
+        }
+    };
+    for t in sentences {
+        silence(said_until, t.begin_time);
+        said_until = said_until.max(t.end_time);
+    }
+    silence(said_until, end);
+    silences
+}
+
+/// A block of a text transcript.
+enum Block<'a> {
+    Paragraph(Vec<&'a Transcription>),
+    Marker(&'a Marker),
+}
+
+/// Groups `transcriptions` into paragraphs in the order they start, starting a new one at
+/// each pause longer than `paragraph_gap_ms`, once a paragraph is `max_paragraph_chars`
+/// long and where the source label changes. `markers` go between paragraphs by their time.
+/// Sentences without text are left out.
+fn blocks<'a>(
+    transcriptions: &'a [Transcription],
+    markers: &'a [Marker],
+    options: &TextOptions,
+) -> Vec<Block<'a>> {
+    let mut sentences = transcriptions
+        .iter()
+        .filter(|t| !t.text.trim().is_empty())
+        .collect::<Vec<_>>();
+    sentences.sort_by_key(|t| (t.begin_time, t.end_time));
+    let mut markers = markers.iter().collect::<Vec<_>>();
+    markers.sort_by_key(|marker| marker.time);
+    let mut markers = markers.into_iter().peekable();
+
+    let mut blocks = vec![];
+    let mut paragraph: Vec<&Transcription> = vec![];
+    let mut chars = 0;
+    for t in sentences {
+        let paused = paragraph.last().is_some_and(|last| {
+            t.begin_time.saturating_sub(last.end_time) > options.paragraph_gap_ms
+        });
+        let marked = markers
+            .peek()
+            .is_some_and(|marker| marker.time <= t.begin_time);
+        let relabelled = paragraph
+            .last()
+            .is_some_and(|last| last.source_label != t.source_label);
+        if (paused || marked || relabelled || chars >= options.max_paragraph_chars)
+            && !paragraph.is_empty()
+        {
+            blocks.push(Block::Paragraph(std::mem::take(&mut paragraph)));
+            chars = 0;
+        }
+        while let Some(marker) = markers.next_if(|marker| marker.time <= t.begin_time) {
+            blocks.push(Block::Marker(marker));
+        }
+        chars += t.text.trim().chars().count();
+        paragraph.push(t);
+    }
+    if !paragraph.is_empty() {
+        blocks.push(Block::Paragraph(paragraph));
+    }
+    blocks.extend(markers.map(Block::Marker));
+    blocks
+}
+
+/// Serializes `transcriptions` and `markers` as paragraphs of text separated by blank
+/// lines, each followed by its translation. Paragraphs of labelled sentences start with
+/// the label, as `[Me] …`, and markers of a stretch show it, as
+/// `[silence 00:02:14–00:05:47]`.
+pub fn text(transcriptions: &[Transcription], markers: &[Marker], options: &TextOptions) -> String {
+    let mut out = vec![];
+    for block in blocks(transcriptions, markers, options) {
+        let paragraph = match block {
+            Block::Paragraph(paragraph) => paragraph,
+            Block::Marker(marker) => {
+                out.push(match marker.end {
+                    Some(end) => {
+                        format!("[{} {}–{}]\n", marker.label, clock(marker.time), clock(end))
+                    }
+                    None => format!(
+                        "--- {} at {} ---\n",
+                        marker.label,
+                        timestamp(marker.time, '.')
+                    ),
+                });
+                continue;
+            }
+        };
+        let mut lines = String::new();
+        if options.timestamps == Timestamps::Paragraph {
+            lines.push_str(&format!("[{}]\n", clock(paragraph[0].begin_time)));
+        }
+        let sentences = paragraph
+            .iter()
+            .map(|t| match options.timestamps {
+                Timestamps::Sentence => format!("[{}] {}", clock(t.begin_time), t.text.trim()),
+                Timestamps::None | Timestamps::Paragraph => t.text.trim().to_string(),
+            })
+            .collect::<Vec<_>>();
+        let sentences = join(sentences.iter().map(String::as_str));
+        lines.push_str(&labelled(paragraph[0], &sentences));
+        lines.push('\n');
+        let translations = paragraph
+            .iter()
+            .filter_map(|t| t.translated_text.as_deref().map(str::trim))
+            .filter(|translated| !translated.is_empty())
+            .collect::<Vec<_>>();
+        if options.translation && !translations.is_empty() {
+            lines.push_str(&join(translations));
+            lines.push('\n');
+        }
+        out.push(lines);
+    }
+    out.join("\n")
+}
+
+/// A subtitle cue.
+struct Cue<'a> {
+    begin: u64,
+    end: u64,
+    text: String,
+    translation: Option<&'a str>,
+}
+
+/// Lays `transcriptions` and `markers` out as cues, in the order they start.
+///
+/// Sentences without text are left out and sentences longer than `max_cue_ms` are split
+/// between words where their timings allow, each piece after the label of the sentence.
+/// Markers show their label in brackets, for as long as the stretch they mark. Cues last
+/// at least `MIN_CUE_MS`, and a cue ends where the next one starts rather than
+/// overlapping it, unless both start at the same time.
+fn cues<'a>(
+    transcriptions: &'a [Transcription],
+    markers: &[Marker],
+    options: &SubtitleOptions,
+) -> Vec<Cue<'a>> {
+    let mut sentences = transcriptions
+        .iter()
+        .filter(|t| !t.text.trim().is_empty())
+        .collect::<Vec<_>>();
+    sentences.sort_by_key(|t| (t.begin_time, t.end_time));
+
+    let mut cues = vec![];
+    for t in sentences {
+        let translation = t
+            .translated_text
+            .as_deref()
+            .filter(|_| options.translation)
+            .map(str::trim);
+        for (begin, end, text) in split(t, options.max_cue_ms) {
+            cues.push(Cue {
+                begin,
+                end,
+                text: labelled(t, &text),
+                translation,
+            });
+        }
+    }
+    for marker in markers {
+        cues.push(Cue {
+            begin: marker.time,
+            end: marker.end.unwrap_or(marker.time),
+            text: format!("[{}]", marker.label),
+            translation: None,
+        });
+    }
+    // Stable, so the pieces of a sentence stay in order.
+    cues.sort_by_key(|cue| cue.begin);
+    for i in 0..cues.len() {
+        let begin = cues[i].begin;
+        let mut end = cues[i].end.max(begin + MIN_CUE_MS);
+        if let Some(next) = cues.get(i + 1)
+            && next.begin > begin
+        {
+            end = end.min(next.begin);
+        }
+        cues[i].end = end;
+    }
+    cues
+}
+
+/// Splits a sentence longer than `max_ms` between its words into pieces of at most
+/// `max_ms`, or as short as its words allow. Sentences without word timings are kept
+/// whole.
+fn split(t: &Transcription, max_ms: u64) -> Vec<(u64, u64, String)> {
+    if t.end_time.saturating_sub(t.begin_time) <= max_ms || t.words.is_empty() {
+        return vec![(t.begin_time, t.end_time, t.text.trim().to_string())];
+    }
+    let piece = |words: &[Word]| {
+        (
+            words[0].begin_time,
+            words[words.len() - 1].end_time,
+            join_words(words),
+        )
+    };
+    let mut pieces = vec![];
+    let mut start = 0;
+    for (i, word) in t.words.iter().enumerate().skip(1) {
+        if word.end_time.saturating_sub(t.words[start].begin_time) > max_ms {
+            pieces.push(piece(&t.words[start..i]));
+            start = i;
+        }
+    }
+    pieces.push(piece(&t.words[start..]));
+    pieces
+}
+
+/// `text` of `t` after its source label in brackets, if it has one.
+fn labelled(t: &Transcription, text: &str) -> String {
+    match &t.source_label {
+        Some(label) => format!("[{}] {}", label, text),
+        None => text.to_string(),
+    }
+}
+
+/// Joins words with spaces, except next to CJK characters, which aren't spaced.
+fn join_words(words: &[Word]) -> String {
+    join(words.iter().map(|word| word.text.trim()))
+}
+
+/// Joins pieces of text with spaces, except next to CJK characters, which aren't spaced.
+fn join<'a>(pieces: impl IntoIterator<Item = &'a str>) -> String {
+    let cjk = |c: char| matches!(c, '\u{2e80}'..='\u{9fff}' | '\u{ac00}'..='\u{d7af}' | '\u{f900}'..='\u{faff}' | '\u{ff00}'..='\u{ffef}');
+    let mut text = String::new();
+    for piece in pieces {
+        if let (Some(last), Some(first)) = (text.chars().last(), piece.chars().next())
+            && !cjk(last)
+            && !cjk(first)
+        {
+            text.push(' ');
+        }
+        text.push_str(piece);
+    }
+    text
+}
+
+/// Serializes `transcriptions` and `markers` as SubRip subtitles.
+pub fn srt(
+    transcriptions: &[Transcription],
+    markers: &[Marker],
+    options: &SubtitleOptions,
+) -> String {
+    let mut out = String::new();
+    for (i, cue) in cues(transcriptions, markers, options).iter().enumerate() {
+        out.push_str(&format!(
+            "{}\n{} --> {}\n{}\n",
+            i + 1,
+            timestamp(cue.begin, ','),
+            timestamp(cue.end, ','),
+            cue.text
+        ));
+        if let Some(translation) = cue.translation {
+            out.push_str(translation);
+            out.push('\n');
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Serializes `transcriptions` and `markers` as WebVTT subtitles.
+pub fn vtt(
+    transcriptions: &[Transcription],
+    markers: &[Marker],
+    options: &SubtitleOptions,
+) -> String {
+    // Cue text is markup, where these characters must be escaped.
+    let escape = |text: &str| {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    };
+    let mut out = String::from("WEBVTT\n\n");
+    for cue in cues(transcriptions, markers, options) {
+        out.push_str(&format!(
+            "{} --> {}\n{}\n",
+            timestamp(cue.begin, '.'),
+            timestamp(cue.end, '.'),
+            escape(&cue.text)
+        ));
+        if let Some(translation) = cue.translation {
+            out.push_str(&escape(translation));
+            out.push('\n');
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Name of the file of the translations into a language, next to the transcript:
+/// `talk.vtt` gets `talk.zh.vtt`.
+pub const TRACK_TEMPLATE: &str = "{stem}.{lang}.{ext}";
+
+/// The translation of `transcription` into `lang` as a sentence of its own, timed like the
+/// sentence it translates, or `None` while it isn't translated into `lang`.
+pub fn translated(transcription: &Transcription, lang: &str) -> Option<Transcription> {
+    let translation = transcription.translations.iter().find(|t| t.lang == lang)?;
+    Some(Transcription {
+        text: translation.text.clone(),
+        translated_text: None,
+        translations: vec![],
+        // Timings of the source words, which don't apply.
+        words: vec![],
+        ..transcription.clone()
+    })
+}
+
+/// Path of the translations into `lang` of the transcript at `path`, named after
+/// `template` with `{stem}`, `{lang}` and `{ext}` filled in.
+pub fn track_path(path: &Path, template: &str, lang: &str) -> PathBuf {
+    let part = |part: Option<&std::ffi::OsStr>| {
+        part.map(|part| part.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    let name = template
+        .replace("{stem}", &part(path.file_stem()))
+        .replace("{lang}", lang)
+        .replace("{ext}", &part(path.extension()));
+    // Without an extension, `talk` gets `talk.zh`.
+    path.with_file_name(name.trim_end_matches('.'))
+}
+
+/// `HH:MM:SS`.
+fn clock(ms: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60
+    )
+}
+
+/// `HH:MM:SS` and milliseconds, separated by `separator`.
+fn timestamp(ms: u64, separator: char) -> String {
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        separator,
+        ms % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gummy::Translation;
+
+    const SUBTITLES: SubtitleOptions = SubtitleOptions {
+        translation: false,
+        max_cue_ms: 5_000,
+        silences: false,
+    };
+    const INLINE: SubtitleOptions = SubtitleOptions {
+        translation: true,
+        ..SUBTITLES
+    };
+
+    fn sentence(sentence_id: u64, text: &str, translated_text: Option<&str>) -> Transcription {
+        Transcription {
+            sentence_id,
+            begin_time: sentence_id * 61_500,
+            end_time: sentence_id * 61_500 + 2_250,
+            text: text.to_string(),
+            is_final: true,
+            translated_text: translated_text.map(str::to_string),
+            translations: vec![],
+            words: vec![],
+            confidence: None,
+            source_label: None,
+        }
+    }
+
+    fn session_info() -> SessionInfo {
+        SessionInfo {
+            started_at_ms: 1_700_000_000_000,
+            sample_rate: 16000,
+            source_language: Some("en".to_string()),
+            target_languages: vec!["zh".to_string(), "ja".to_string()],
+            model: "gummy-realtime-v1".to_string(),
+            task_ids: vec![],
+            usage: Usage { audio_ms: 64_250 },
+            summary: None,
+        }
+    }
+
+    fn render(format: TranscriptFormat) -> String {
+        let mut out = vec![];
+        let mut writer = TranscriptWriter::new(format, INLINE, &mut out);
+        writer
+            .write(&sentence(0, "Hello.", Some("你好。")))
+            .unwrap();
+        // Written once, however often it's reported.
+        writer
+            .write(&sentence(0, "Hello.", Some("你好。")))
+            .unwrap();
+        let result = [
+            sentence(0, "Hello.", Some("你好。")),
+            sentence(1, "Bye.", None),
+        ];
+        writer.finish(&result, &session_info()).unwrap();
+        assert_eq!(writer.sentences().len(), 2);
+        String::from_utf8(out).unwrap()
+    }
+
+    /// A session's worth of sentences with the quirks subtitles fix up.
+    fn session() -> Vec<Transcription> {
+        let sentence =
+            |sentence_id, begin_time, end_time, text: &str, translated: &[(&str, &str)]| {
+                let translations = translated
+                    .iter()
+                    .map(|(lang, text)| Translation {
+                        lang: lang.to_string(),
+                        text: text.to_string(),
+                    })
+                    .collect::<Vec<_>>();
+                Transcription {
+                    sentence_id,
+                    begin_time,
+                    end_time,
+                    text: text.to_string(),
+                    is_final: true,
+                    translated_text: translations.first().map(|t| t.text.clone()),
+                    translations,
+                    words: vec![],
+                    confidence: Some(0.9),
+                    source_label: None,
+                }
+            };
+        let mut long = sentence(
+            6,
+            20_000,
+            31_000,
+            "This one goes on for quite a while before it ends.",
+            &[("zh", "这句话说了好一会儿才结束。")],
+        );
+        long.words = [
+            (20_000, 20_400, "This"),
+            (20_400, 20_800, "one"),
+            (20_800, 21_300, "goes"),
+            (21_300, 21_600, "on"),
+            (21_600, 22_000, "for"),
+            (22_000, 23_500, "quite"),
+            (23_500, 23_700, "a"),
+            (23_700, 25_400, "while"),
+            (25_400, 27_200, "before"),
+            (27_200, 29_000, "it"),
+            (29_000, 31_000, "ends."),
+        ]
+        .iter()
+        .map(|&(begin_time, end_time, text)| Word {
+            begin_time,
+            end_time,
+            text: text.to_string(),
+            confidence: None,
+        })
+        .collect();
+        vec![
+            sentence(
+                0,
+                1_200,
+                3_480,
+                "Good morning, everyone.",
+                &[
+                    ("zh", "大家早上好。"),
+                    ("ja", "皆さん、おはようございます。"),
+                ],
+            ),
+            // Overlaps the next sentence.
+            sentence(
+                1,
+                3_900,
+                7_350,
+                "Let's get started.",
+                &[("zh", "我们开始吧。")],
+            ),
+            // Zero length.
+            sentence(2, 7_000, 7_000, "Okay.", &[("zh", "好的。")]),
+            // Out of order, without a translation.
+            sentence(4, 3_723_004, 3_725_100, "That's all.", &[]),
+            sentence(
+                3,
+                9_000,
+                9_200,
+                "Q&A, right?",
+                &[("zh", "问答环节，对吧？")],
+            ),
+            // Nothing was said.
+            sentence(5, 3_726_000, 3_727_000, " ", &[]),
+            // Longer than a cue lasts.
+            long,
+        ]
+    }
+
+    fn fixture(name: &str) -> String {
+        let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn writes_text() {
+        // A minute apart, the sentences are paragraphs of their own.
+        assert_eq!(render(TranscriptFormat::Txt), "Hello.\n你好。\n\nBye.\n");
+    }
+
+    #[test]
+    fn serializes_text_in_paragraphs() {
+        let options = TextOptions::default();
+        assert_eq!(text(&session(), &[], &options), fixture("session.txt"));
+        let headed = TextOptions {
+            timestamps: Timestamps::Paragraph,
+            ..options
+        };
+        assert_eq!(
+            text(&session(), &[], &headed),
+            fixture("session.timestamps.txt")
+        );
+        let stamped = TextOptions {
+            timestamps: Timestamps::Sentence,
+            translation: false,
+            ..options
+        };
+        assert_eq!(
+            text(&session()[..5], &[], &stamped),
+            "[00:00:01] Good morning, everyone. [00:00:03] Let's get started. \
+             [00:00:07] Okay. [00:00:09] Q&A, right?\n\n\
+             [01:02:03] That's all.\n"
+        );
+        // A shorter pause splits the first paragraph at the last sentence, two seconds on.
+        let short = TextOptions {
+            paragraph_gap_ms: 1_999,
+            translation: false,
+            ..options
+        };
+        assert!(
+            text(&session(), &[], &short)
+                .starts_with("Good morning, everyone. Let's get started. Okay.\n\nQ&A, right?\n\n")
+        );
+        // So does the length of a paragraph.
+        let long = TextOptions {
+            max_paragraph_chars: 30,
+            translation: false,
+            ..options
+        };
+        assert!(
+            text(&session(), &[], &long)
+                .starts_with("Good morning, everyone. Let's get started.\n\nOkay. Q&A, right?\n\n")
+        );
+        assert_eq!(text(&[], &[], &options), "");
+    }
+
+    #[test]
+    fn writes_subtitles() {
+        assert_eq!(
+            render(TranscriptFormat::Srt),
+            "1\n00:00:00,000 --> 00:00:02,250\nHello.\n你好。\n\n\
+             2\n00:01:01,500 --> 00:01:03,750\nBye.\n\n"
+        );
+        assert_eq!(
+            render(TranscriptFormat::Vtt),
+            "WEBVTT\n\n\
+             00:00:00.000 --> 00:00:02.250\nHello.\n你好。\n\n\
+             00:01:01.500 --> 00:01:03.750\nBye.\n\n"
+        );
+    }
+
+    #[test]
+    fn writes_json_lines() {
+        let rendered = render(TranscriptFormat::Jsonl);
+        let lines = rendered
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["is_final"], true);
+        assert_eq!(lines[0]["translated_text"], "你好。");
+        assert_eq!(lines[1]["begin_time"], 61_500);
+        assert!(lines[1]["translated_text"].is_null());
+        // Every line reads back as the sentence it was written from.
+        let sentences = rendered
+            .lines()
+            .map(|line| serde_json::from_str::<Transcription>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(sentences[1], sentence(1, "Bye.", None));
+    }
+
+    #[test]
+    fn writes_a_json_document() {
+        let mut out = vec![];
+        let mut writer = TranscriptWriter::new(TranscriptFormat::Json, INLINE, &mut out);
+        writer.finish(&session(), &session_info()).unwrap();
+        let document: TranscriptDocument = serde_json::from_slice(&out).unwrap();
+        assert_eq!(document.session, session_info());
+        // Without a timeline, sentences have no wall clock times.
+        let sentences: Vec<_> = document
+            .sentences
+            .into_iter()
+            .map(|sentence| {
+                assert_eq!(sentence.wall_clock, None);
+                sentence.sentence
+            })
+            .collect();
+        assert_eq!(sentences, session());
+
+        // Field names are what scripts reading transcripts rely on.
+        let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(value["session"]["usage"]["audio_ms"], 64_250);
+        assert_eq!(value["session"]["target_languages"][1], "ja");
+        let long = &value["sentences"][6];
+        assert_eq!(long["sentence_id"], 6);
+        assert_eq!(long["is_final"], true);
+        assert_eq!(long["translations"][0]["lang"], "zh");
+        assert_eq!(long["words"][10]["text"], "ends.");
+        assert_eq!(long["words"][10]["begin_time"], 29_000);
+        assert_eq!(long["confidence"], 0.9);
+        // Only sessions with markers have them.
+        assert!(value.get("markers").is_none());
+    }
+
+    #[test]
+    fn labels_the_sentences_of_merged_sessions() {
+        let labelled = |sentence_id, begin_time, end_time, text: &str, label: &str| Transcription {
+            begin_time,
+            end_time,
+            source_label: Some(label.to_string()),
+            ..sentence(sentence_id, text, None)
+        };
+        let me = vec![
+            labelled(0, 0, 2_000, "Can you hear me?", "Me"),
+            labelled(1, 4_000, 5_000, "Great.", "Me"),
+        ];
+        let others = vec![
+            // Answering before the question ends.
+            labelled(0, 1_500, 3_500, "Yes, loud and clear.", "Others"),
+            labelled(1, 5_000, 6_000, "Let's start.", "Others"),
+        ];
+        let merged = gummy::merge_sessions(vec![me, others]);
+        let options = TextOptions {
+            translation: false,
+            ..TextOptions::default()
+        };
+        assert_eq!(
+            text(&merged, &[], &options),
+            "[Me] Can you hear me?\n\n\
+             [Others] Yes, loud and clear.\n\n\
+             [Me] Great.\n\n\
+             [Others] Let's start.\n"
+        );
+        assert!(srt(&merged, &[], &SUBTITLES).starts_with(
+            "1\n00:00:00,000 --> 00:00:01,500\n[Me] Can you hear me?\n\n\
+             2\n00:00:01,500 --> 00:00:03,500\n[Others] Yes, loud and clear.\n\n"
+        ));
+
+        let mut out = vec![];
+        let mut writer = TranscriptWriter::new(TranscriptFormat::Json, SUBTITLES, &mut out);
+        writer.finish(&merged, &session_info()).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(value["sentences"][1]["source_label"], "Others");
+        assert_eq!(value["sentences"][2]["text"], "Great.");
+        // Sentences without a label leave it out.
+        let rendered = render(TranscriptFormat::Jsonl);
+        assert!(!rendered.contains("source_label"));
+    }
+
+    #[test]
+    fn serializes_srt() {
+        assert_eq!(srt(&session(), &[], &SUBTITLES), fixture("session.srt"));
+        assert_eq!(srt(&session(), &[], &INLINE), fixture("session.inline.srt"));
+    }
+
+    #[test]
+    fn serializes_vtt() {
+        assert_eq!(vtt(&session(), &[], &SUBTITLES), fixture("session.vtt"));
+        assert_eq!(vtt(&session(), &[], &INLINE), fixture("session.inline.vtt"));
+    }
+
+    #[test]
+    fn writes_subtitles_without_a_session_result() {
+        let mut out = vec![];
+        let mut writer = TranscriptWriter::new(TranscriptFormat::Srt, SUBTITLES, &mut out);
+        for transcription in session() {
+            writer.write(&transcription).unwrap();
+        }
+        writer.finish(&[], &session_info()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), fixture("session.srt"));
+    }
+
+    #[test]
+    fn writes_markers_among_the_sentences() {
+        let render = |format| {
+            let mut out = vec![];
+            let mut writer = TranscriptWriter::new(format, INLINE, &mut out);
+            writer.write(&sentence(0, "Hello.", None)).unwrap();
+            // Marked before the sentence it comes after in time was finalized.
+            writer
+                .mark(Marker {
+                    label: "Marker 1".to_string(),
+                    time: 30_000,
+                    end: None,
+                })
+                .unwrap();
+            writer
+                .finish(&[sentence(1, "Bye.", None)], &session_info())
+                .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(
+            render(TranscriptFormat::Txt),
+            "Hello.\n\n--- Marker 1 at 00:00:30.000 ---\n\nBye.\n"
+        );
+        assert_eq!(
+            render(TranscriptFormat::Srt),
+            "1\n00:00:00,000 --> 00:00:02,250\nHello.\n\n\
+             2\n00:00:30,000 --> 00:00:30,500\n[Marker 1]\n\n\
+             3\n00:01:01,500 --> 00:01:03,750\nBye.\n\n"
+        );
+        assert_eq!(
+            render(TranscriptFormat::Vtt),
+            "WEBVTT\n\n\
+             00:00:00.000 --> 00:00:02.250\nHello.\n\n\
+             00:00:30.000 --> 00:00:30.500\n[Marker 1]\n\n\
+             00:01:01.500 --> 00:01:03.750\nBye.\n\n"
+        );
+        let lines = render(TranscriptFormat::Jsonl);
+        let marker: serde_json::Value =
+            serde_json::from_str(lines.lines().nth(1).unwrap()).unwrap();
+        assert_eq!(
+            marker,
+            serde_json::json!({ "marker": { "label": "Marker 1", "time": 30_000 } })
+        );
+        let document: TranscriptDocument =
+            serde_json::from_str(&render(TranscriptFormat::Json)).unwrap();
+        assert_eq!(
+            document.markers,
+            [Marker {
+                label: "Marker 1".to_string(),
+                time: 30_000,
+                end: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn marks_silences_at_the_start_middle_and_end() {
+        let silence = |time, end| Marker {
+            label: "silence".to_string(),
+            time,
+            end: Some(end),
+        };
+        // 1:01.5 to 1:03.75 and 2:03 to 2:05.25.
+        let sentences = [sentence(1, "Hello.", None), sentence(2, "Bye.", None)];
+        assert_eq!(
+            silences(&sentences, 0, 200_000, 30_000),
+            [
+                silence(0, 61_500),
+                silence(63_750, 123_000),
+                silence(125_250, 200_000)
+            ]
+        );
+        // Only longer ones.
+        assert_eq!(
+            silences(&sentences, 0, 200_000, 62_000),
+            [silence(125_250, 200_000)]
+        );
+        // A sentence within another leaves no gap, and one without text doesn't fill one.
+        let mut long = sentence(1, "Hello.", None);
+        long.end_time = 130_000;
+        let mut empty = sentence(3, "", None);
+        empty.begin_time = 0;
+        empty.end_time = 200_000;
+        let overlapping = [long, sentences[1].clone(), empty];
+        assert_eq!(
+            silences(&overlapping, 0, 140_000, 5_000),
+            [silence(0, 61_500), silence(130_000, 140_000)]
+        );
+        assert_eq!(silences(&[], 0, 45_000, 30_000), [silence(0, 45_000)]);
+
+        let render = |format, subtitles| {
+            let mut out = vec![];
+            let mut writer =
+                TranscriptWriter::new(format, subtitles, &mut out).with_silences(30_000);
+            let session = SessionInfo {
+                usage: Usage { audio_ms: 200_000 },
+                ..session_info()
+            };
+            writer.finish(&sentences, &session).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(
+            render(TranscriptFormat::Txt, SUBTITLES),
+            "[silence 00:00:00–00:01:01]\n\n\
+             Hello.\n\n\
+             [silence 00:01:03–00:02:03]\n\n\
+             Bye.\n\n\
+             [silence 00:02:05–00:03:20]\n"
+        );
+        let document: TranscriptDocument =
+            serde_json::from_str(&render(TranscriptFormat::Json, SUBTITLES)).unwrap();
+        assert_eq!(document.markers.len(), 3);
+        assert_eq!(document.markers[1], silence(63_750, 123_000));
+        // Subtitles leave them out unless asked.
+        assert!(!render(TranscriptFormat::Srt, SUBTITLES).contains("silence"));
+        let subtitles = SubtitleOptions {
+            silences: true,
+            ..SUBTITLES
+        };
+        assert_eq!(
+            render(TranscriptFormat::Vtt, subtitles),
+            "WEBVTT\n\n\
+             00:00:00.000 --> 00:01:01.500\n[silence]\n\n\
+             00:01:01.500 --> 00:01:03.750\nHello.\n\n\
+             00:01:03.750 --> 00:02:03.000\n[silence]\n\n\
+             00:02:03.000 --> 00:02:05.250\nBye.\n\n\
+             00:02:05.250 --> 00:03:20.000\n[silence]\n\n"
+        );
+    }
+
+    #[test]
+    fn lays_out_the_translations_into_a_language_as_a_transcript() {
+        let track = |lang| {
+            session()
+                .iter()
+                .filter_map(|t| translated(t, lang))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vtt(&track("zh"), &[], &INLINE), fixture("session.zh.vtt"));
+        assert_eq!(
+            vtt(&track("ja"), &[], &INLINE),
+            "WEBVTT\n\n00:00:01.200 --> 00:00:03.480\n皆さん、おはようございます。\n\n"
+        );
+        // Text tracks are paragraphs of translations.
+        assert_eq!(
+            text(&track("zh"), &[], &TextOptions::default()),
+            "大家早上好。我们开始吧。好的。问答环节，对吧？\n\n这句话说了好一会儿才结束。\n"
+        );
+    }
+
+    #[test]
+    fn names_the_files_of_translations_after_a_template() {
+        let path = Path::new("out/talk.vtt");
+        assert_eq!(
+            track_path(path, TRACK_TEMPLATE, "zh"),
+            Path::new("out/talk.zh.vtt")
+        );
+        assert_eq!(
+            track_path(path, "{lang}/{stem}.{ext}", "ja"),
+            Path::new("out/ja/talk.vtt")
+        );
+        assert_eq!(
+            track_path(Path::new("talk"), TRACK_TEMPLATE, "ko"),
+            Path::new("talk.ko")
+        );
+    }
+
+    #[test]
+    fn joins_words_with_spaces_where_the_language_has_them() {
+        let words = |texts: &[&str]| {
+            texts
+                .iter()
+                .map(|text| Word {
+                    begin_time: 0,
+                    end_time: 0,
+                    text: text.to_string(),
+                    confidence: None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(join_words(&words(&["Hello", "world."])), "Hello world.");
+        assert_eq!(
+            join_words(&words(&["我们", "开始", "吧。"])),
+            "我们开始吧。"
+        );
+        assert_eq!(join_words(&words(&["用", "Rust", "写"])), "用Rust写");
+    }
+}
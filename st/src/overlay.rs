@@ -0,0 +1,250 @@
+//! `--broadcast-ws`: caption events over WebSocket, for overlays like an OBS browser source.
+//!
+//! Every client gets a JSON message per partial and final sentence, and on connecting the
+//! last few final sentences so an overlay isn't empty. `overlay.html`, served at
+//! `/overlay` by `--serve`, renders them.
+
+use crate::diff::TranscriptDiff;
+use futures_util::{SinkExt, StreamExt};
+use gummy::{Transcription, TranscriptionEvent};
+use log::debug;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio_tungstenite::accept_async;
+use tungstenite::{Message, Utf8Bytes};
+
+/// Final sentences replayed to new clients.
+const REPLAYED: usize = 5;
+
+/// Messages kept for clients that fall behind; slower ones miss some.
+const BACKLOG: usize = 64;
+
+/// A message sent to clients.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CaptionEvent<'a> {
+    /// `partial` or `final`.
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub sentence_id: u64,
+    pub text: &'a str,
+    pub translation: Option<&'a str>,
+    pub is_final: bool,
+}
+
+impl<'a> CaptionEvent<'a> {
+    pub fn of(event: &'a TranscriptionEvent) -> Option<Self> {
+        let (kind, t): (_, &Transcription) = match event {
+            TranscriptionEvent::Partial(t) => ("partial", t),
+            TranscriptionEvent::Final(t) => ("final", t),
+            TranscriptionEvent::Finished => return None,
+        };
+        Some(CaptionEvent {
+            kind,
+            sentence_id: t.sentence_id,
+            text: &t.text,
+            translation: t.translated_text.as_deref(),
+            is_final: kind == "final",
+        })
+    }
+}
+
+struct Channel {
+    sender: broadcast::Sender<Utf8Bytes>,
+    replayed: VecDeque<Utf8Bytes>,
+    diff: TranscriptDiff,
+}
+
+/// Sends the events of the session to the clients. Connections are closed when it's
+/// dropped.
+pub struct Broadcast {
+    // Shared with the server, which subscribes new clients and replays to them under the
+    // lock, so they neither miss nor repeat a message.
+    channel: Arc<Mutex<Option<Channel>>>,
+}
+
+impl Broadcast {
+    pub fn send(&self, event: &TranscriptionEvent) {
+        let Some(caption) = CaptionEvent::of(event) else {
+            return;
+        };
+        let mut channel = self.channel.lock().unwrap();
+        let Some(channel) = channel.as_mut() else {
+            return;
+        };
+        if !channel.diff.admit(event, Instant::now()) {
+            return;
+        }
+        let message = Utf8Bytes::from(serde_json::to_string(&caption).expect("captions serialize"));
+        if caption.is_final {
+            if channel.replayed.len() == REPLAYED {
+                channel.replayed.pop_front();
+            }
+            channel.replayed.push_back(message.clone());
+        }
+        // Nobody may be connected, which is fine.
+        let _ = channel.sender.send(message);
+    }
+}
+
+impl Drop for Broadcast {
+    fn drop(&mut self) {
+        self.channel.lock().unwrap().take();
+    }
+}
+
+/// Listens on `addr` and accepts clients in the background, sending a partial sentence
+/// again no sooner than `partial_interval` after the last time. Returns the broadcast and
+/// the address listened on, which tells the port when `addr` has port 0.
+pub async fn start(
+    addr: SocketAddr,
+    partial_interval: Duration,
+) -> io::Result<(Broadcast, SocketAddr)> {
+    let listener = TcpListener::bind(addr).await?;
+    let addr = listener.local_addr()?;
+    let (sender, _) = broadcast::channel(BACKLOG);
+    let channel = Arc::new(Mutex::new(Some(Channel {
+        sender,
+        replayed: VecDeque::new(),
+        diff: TranscriptDiff::new(partial_interval),
+    })));
+    let accepted = channel.clone();
+    tokio::spawn(async move {
+        while let Ok((stream, peer)) = listener.accept().await {
+            let subscribed = accepted.lock().unwrap().as_ref().map(|channel| {
+                let replayed: Vec<_> = channel.replayed.iter().cloned().collect();
+                (replayed, channel.sender.subscribe())
+            });
+            let Some((replayed, receiver)) = subscribed else {
+                break;
+            };
+            tokio::spawn(async move {
+                if let Err(e) = serve(stream, replayed, receiver).await {
+                    debug!("Caption client {} disconnected: {}", peer, e);
+                }
+            });
+        }
+    });
+    Ok((Broadcast { channel }, addr))
+}
+
+async fn serve(
+    stream: TcpStream,
+    replayed: Vec<Utf8Bytes>,
+    mut receiver: broadcast::Receiver<Utf8Bytes>,
+) -> tungstenite::Result<()> {
+    let ws = accept_async(stream).await?;
+    let (mut sink, mut incoming) = ws.split();
+    for message in replayed {
+        sink.send(Message::Text(message)).await?;
+    }
+    loop {
+        tokio::select! {
+            message = receiver.recv() => match message {
+                Ok(message) => sink.send(Message::Text(message)).await?,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    debug!("A caption client fell behind by {} messages", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            // Clients only send control frames, answered by tungstenite.
+            message = incoming.next() => match message {
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e),
+            },
+        }
+    }
+    sink.send(Message::Close(None)).await?;
+    sink.close().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_tungstenite::connect_async;
+
+    fn sentence(sentence_id: u64, text: &str) -> Transcription {
+        Transcription {
+            sentence_id,
+            begin_time: 0,
+            end_time: 1000,
+            text: text.to_string(),
+            is_final: true,
+            translated_text: Some(format!("[{}]", text)),
+            translations: vec![],
+            words: vec![],
+            confidence: None,
+            source_label: None,
+        }
+    }
+
+    async fn received(
+        ws: &mut (impl StreamExt<Item = tungstenite::Result<Message>> + Unpin),
+    ) -> Option<serde_json::Value> {
+        match ws.next().await? {
+            Ok(Message::Text(text)) => Some(serde_json::from_str(&text).unwrap()),
+            Ok(Message::Close(_)) => None,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_the_last_sentences_to_new_clients() {
+        let (broadcast, addr) = start("127.0.0.1:0".parse().unwrap(), Duration::ZERO)
+            .await
+            .unwrap();
+        for sentence_id in 0..7 {
+            let text = format!("Sentence {}.", sentence_id);
+            broadcast.send(&TranscriptionEvent::Partial(sentence(sentence_id, "Sen")));
+            broadcast.send(&TranscriptionEvent::Final(sentence(sentence_id, &text)));
+        }
+        let (mut ws, _) = connect_async(format!("ws://{}", addr)).await.unwrap();
+        for sentence_id in 2..7 {
+            let text = format!("Sentence {}.", sentence_id);
+            assert_eq!(
+                received(&mut ws).await,
+                Some(serde_json::json!({
+                    "type": "final",
+                    "sentence_id": sentence_id,
+                    "text": text,
+                    "translation": format!("[{}]", text),
+                    "is_final": true,
+                }))
+            );
+        }
+
+        broadcast.send(&TranscriptionEvent::Partial(sentence(7, "Eig")));
+        broadcast.send(&TranscriptionEvent::Finished);
+        let partial = received(&mut ws).await.unwrap();
+        assert_eq!(partial["type"], "partial");
+        assert_eq!(partial["text"], "Eig");
+        assert_eq!(partial["is_final"], false);
+
+        // Clients are let go with the session.
+        drop(broadcast);
+        assert_eq!(received(&mut ws).await, None);
+    }
+
+    #[tokio::test]
+    async fn keeps_sending_when_clients_leave() {
+        let (broadcast, addr) = start("127.0.0.1:0".parse().unwrap(), Duration::ZERO)
+            .await
+            .unwrap();
+        let (leaving, _) = connect_async(format!("ws://{}", addr)).await.unwrap();
+        let (mut staying, _) = connect_async(format!("ws://{}", addr)).await.unwrap();
+        drop(leaving);
+        for sentence_id in 0..3 {
+            broadcast.send(&TranscriptionEvent::Final(sentence(sentence_id, "Hi.")));
+        }
+        for sentence_id in 0..3 {
+            let event = received(&mut staying).await.unwrap();
+            assert_eq!(event["sentence_id"], sentence_id);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! Asks which device to capture from when none is given and there's a choice, for people
+//! who don't know the names of their devices yet.
+
+use crate::args::Source;
+use audio::recorder::{DeviceInfo, DeviceKind, default_host_id};
+use std::io::{self, BufRead, Write};
+
+/// Devices `source` can capture from: microphones of the default host, or for system audio
+/// any device that can be captured.
+pub fn candidates(devices: &[DeviceInfo], source: Source) -> Vec<DeviceInfo> {
+    let default_host = default_host_id();
+    devices
+        .iter()
+        .filter(|device| match source {
+            Source::Mic => device.kind == DeviceKind::Input && device.host == default_host,
+            Source::System | Source::Mixed | Source::Separate => device.can_capture(),
+        })
+        .cloned()
+        .collect()
+}
+
+/// The name of `device` with what it captures, and whether it's the default.
+fn label(device: &DeviceInfo) -> String {
+    // Monitors of outputs show up as inputs on Linux.
+    let loopback =
+        device.kind == DeviceKind::Output || device.name.to_lowercase().starts_with("monitor of");
+    let mut hints = vec![if loopback { "loopback" } else { "mic" }];
+    if device.is_default {
+        hints.push("default");
+    }
+    format!("{} ({})", device.name, hints.join(", "))
+}
+
+/// Lists `candidates` on `out` and reads the number of one from `input` until it's valid.
+/// Returns its index, or `None` to go with the default, on an empty line or the end of
+/// `input`.
+pub fn pick(
+    candidates: &[DeviceInfo],
+    mut input: impl BufRead,
+    mut out: impl Write,
+) -> io::Result<Option<usize>> {
+    writeln!(out, "Capture devices:")?;
+    for (i, device) in candidates.iter().enumerate() {
+        writeln!(out, "{:>3}. {}", i + 1, label(device))?;
+    }
+    loop {
+        write!(out, "Device to capture from, Enter for the default: ")?;
+        out.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(out)?;
+            return Ok(None);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+        match line.parse::<usize>() {
+            Ok(number @ 1..) if number <= candidates.len() => return Ok(Some(number - 1)),
+            _ => writeln!(out, "Pick a number from 1 to {}.", candidates.len())?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(name: &str, kind: DeviceKind, is_default: bool) -> DeviceInfo {
+        DeviceInfo {
+            name: name.to_string(),
+            host: default_host_id(),
+            kind,
+            default_sample_rate: Some(48000),
+            channels: Some(2),
+            sample_formats: vec![],
+            is_default,
+        }
+    }
+
+    fn devices() -> Vec<DeviceInfo> {
+        vec![
+            device("Built-in Microphone", DeviceKind::Input, true),
+            device("Monitor of Built-in Audio", DeviceKind::Input, false),
+            device("Speakers", DeviceKind::Output, true),
+        ]
+    }
+
+    /// Picks from `devices` with `typed` as input, returning the choice and what was shown.
+    fn run(typed: &str) -> (Option<usize>, String) {
+        let mut out = vec![];
+        let choice = pick(&devices(), typed.as_bytes(), &mut out).unwrap();
+        (choice, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn lists_the_devices_with_hints() {
+        let (choice, shown) = run("2\n");
+        assert_eq!(choice, Some(1));
+        assert_eq!(
+            shown,
+            "Capture devices:\n  \
+             1. Built-in Microphone (mic, default)\n  \
+             2. Monitor of Built-in Audio (loopback)\n  \
+             3. Speakers (loopback, default)\n\
+             Device to capture from, Enter for the default: "
+        );
+    }
+
+    #[test]
+    fn asks_again_until_the_number_is_valid() {
+        let (choice, shown) = run("speakers\n0\n4\n 3 \n");
+        assert_eq!(choice, Some(2));
+        assert_eq!(shown.matches("Pick a number from 1 to 3.").count(), 3);
+    }
+
+    #[test]
+    fn goes_with_the_default_without_an_answer() {
+        assert_eq!(run("\n").0, None);
+        assert_eq!(run("").0, None);
+        assert_eq!(run("x\n").0, None);
+    }
+
+    #[test]
+    fn lists_microphones_for_the_microphone() {
+        let names = |source| {
+            candidates(&devices(), source)
+                .into_iter()
+                .map(|device| device.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(Source::Mic),
+            ["Built-in Microphone", "Monitor of Built-in Audio"]
+        );
+        assert_eq!(
+            names(Source::System).len(),
+            devices().iter().filter(|d| d.can_capture()).count()
+        );
+    }
+}
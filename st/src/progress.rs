@@ -0,0 +1,181 @@
+//! Progress of transcribing an `--input` file, printed to stderr.
+
+use gummy::{Transcription, TranscriptionEvent};
+use std::collections::HashSet;
+use std::io::{self, IsTerminal, Stderr, Write};
+use std::time::Duration;
+
+/// Shows how much of the file was sent and how many sentences came back. On a terminal
+/// that's a line rewritten as it changes, anywhere else a line every 10 percent.
+pub struct Progress<W: Write> {
+    out: W,
+    live: bool,
+    total: Duration,
+    sent: Duration,
+    sentences: HashSet<u64>,
+    // Percentage and line last written, to only write changes.
+    shown: Option<(u64, String)>,
+}
+
+impl Progress<Stderr> {
+    /// Prints to stderr, live if it's a terminal.
+    pub fn stderr(total: Duration) -> Self {
+        let out = io::stderr();
+        let live = out.is_terminal();
+        Progress::new(out, live, total)
+    }
+}
+
+impl<W: Write> Progress<W> {
+    pub fn new(out: W, live: bool, total: Duration) -> Self {
+        Progress {
+            out,
+            live,
+            total,
+            sent: Duration::ZERO,
+            sentences: HashSet::new(),
+            shown: None,
+        }
+    }
+
+    /// Counts `audio` more of the file as sent.
+    pub fn sent(&mut self, audio: Duration) -> io::Result<()> {
+        self.sent += audio;
+        self.show()
+    }
+
+    pub fn received(&mut self, event: &TranscriptionEvent) -> io::Result<()> {
+        if let TranscriptionEvent::Final(t) = event
+            && self.sentences.insert(t.sentence_id)
+        {
+            self.show()?;
+        }
+        Ok(())
+    }
+
+    /// Counts the sentences of the session result, typically with some finalized while the
+    /// session finished, then ends the live line or writes where things ended up off a
+    /// terminal.
+    pub fn finish(mut self, result: &[Transcription]) -> io::Result<()> {
+        self.sentences
+            .extend(result.iter().map(|transcription| transcription.sentence_id));
+        if self.live {
+            self.show()?;
+            if self.shown.is_some() {
+                writeln!(self.out)?;
+            }
+        } else {
+            let line = self.line();
+            if self.shown.as_ref().is_none_or(|(_, shown)| *shown != line) {
+                writeln!(self.out, "{}", line)?;
+            }
+        }
+        self.out.flush()
+    }
+
+    fn percent(&self) -> u64 {
+        if self.total.is_zero() {
+            return 100;
+        }
+        // Rounded, resampling may leave the last few samples of the file out.
+        let total = self.total.as_millis();
+        ((self.sent.as_millis() * 100 + total / 2) / total).min(100) as u64
+    }
+
+    fn line(&self) -> String {
+        format!(
+            "Sent {}% of the file, {} sentence{} received",
+            self.percent(),
+            self.sentences.len(),
+            if self.sentences.len() == 1 { "" } else { "s" }
+        )
+    }
+
+    fn show(&mut self) -> io::Result<()> {
+        let percent = self.percent();
+        let line = self.line();
+        match &self.shown {
+            Some((_, shown)) if *shown == line => return Ok(()),
+            // Off a terminal, nothing is written until the next step.
+            Some((shown, _)) if !self.live && shown / 10 == percent / 10 => return Ok(()),
+            _ => {}
+        }
+        match self.live {
+            true => write!(self.out, "\r{}", line)?,
+            false => writeln!(self.out, "{}", line)?,
+        }
+        self.shown = Some((percent, line));
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sentence(sentence_id: u64) -> Transcription {
+        Transcription {
+            sentence_id,
+            begin_time: 0,
+            end_time: 1000,
+            text: "Hello.".to_string(),
+            is_final: true,
+            translated_text: None,
+            translations: vec![],
+            words: vec![],
+            confidence: None,
+            source_label: None,
+        }
+    }
+
+    fn run(live: bool) -> String {
+        let mut out = vec![];
+        let mut progress = Progress::new(&mut out, live, Duration::from_secs(2));
+        for _ in 0..4 {
+            progress.sent(Duration::from_millis(100)).unwrap();
+        }
+        let event = TranscriptionEvent::Final(sentence(0));
+        progress.received(&event).unwrap();
+        progress.received(&event).unwrap();
+        for _ in 0..17 {
+            progress.sent(Duration::from_millis(100)).unwrap();
+        }
+        // The last sentence is only in the result.
+        progress.finish(&[sentence(0), sentence(1)]).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn rewrites_the_progress_line_on_a_terminal() {
+        let output = run(true);
+        assert!(output.starts_with(
+            "\rSent 5% of the file, 0 sentences received\
+             \rSent 10% of the file, 0 sentences received"
+        ));
+        assert!(output.contains(
+            "\rSent 20% of the file, 0 sentences received\
+             \rSent 20% of the file, 1 sentence received\
+             \rSent 25% of the file, 1 sentence received"
+        ));
+        assert!(output.ends_with("\rSent 100% of the file, 2 sentences received\n"));
+    }
+
+    #[test]
+    fn prints_every_ten_percent_off_a_terminal() {
+        assert_eq!(
+            run(false),
+            "Sent 5% of the file, 0 sentences received\n\
+             Sent 10% of the file, 0 sentences received\n\
+             Sent 20% of the file, 0 sentences received\n\
+             Sent 30% of the file, 1 sentence received\n\
+             Sent 40% of the file, 1 sentence received\n\
+             Sent 50% of the file, 1 sentence received\n\
+             Sent 60% of the file, 1 sentence received\n\
+             Sent 70% of the file, 1 sentence received\n\
+             Sent 80% of the file, 1 sentence received\n\
+             Sent 90% of the file, 1 sentence received\n\
+             Sent 100% of the file, 1 sentence received\n\
+             Sent 100% of the file, 2 sentences received\n"
+        );
+    }
+}
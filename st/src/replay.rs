@@ -0,0 +1,136 @@
+//! `st replay`: the events a session directory recorded in `events.jsonl`, fed again to the
+//! transcripts of `--output`, to write old sessions anew without audio or the backend, as
+//! after a fix to a format.
+
+use crate::args::Args;
+use crate::failure::{Kind, fail};
+use crate::output::{SessionInfo, Usage};
+use crate::session_dir::{META, Meta, Recorded};
+use crate::sink::Sinks;
+use crate::timeline::Timeline;
+use crate::transcript_sinks;
+use gummy::{Transcription, TranscriptionEvent};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::time::Duration;
+
+/// The events of `events.jsonl`, up to the first line that can't be read, and why that
+/// line can't be, for logs cut short or corrupted.
+pub fn read(events: impl BufRead) -> (Vec<Recorded>, Option<String>) {
+    let mut recorded = vec![];
+    for (n, line) in events.lines().enumerate() {
+        let parsed = line
+            .map_err(|e| e.to_string())
+            .and_then(|line| serde_json::from_str::<Recorded>(&line).map_err(|e| e.to_string()));
+        match parsed {
+            Ok(event) => recorded.push(event),
+            Err(e) => return (recorded, Some(format!("line {}: {}", n + 1, e))),
+        }
+    }
+    (recorded, None)
+}
+
+/// The session as `meta.json` next to `events` recorded it, and the device it captured
+/// from, or what can be told of it from its events without one.
+pub fn session(events: &Path, recorded: &[Recorded]) -> (SessionInfo, String) {
+    let meta = std::fs::read_to_string(events.with_file_name(META))
+        .ok()
+        .and_then(|json| serde_json::from_str::<Meta>(&json).ok());
+    match meta {
+        Some(meta) => (meta.session, meta.device),
+        None => (
+            SessionInfo {
+                started_at_ms: recorded.first().map_or(0, |r| r.received_ms),
+                sample_rate: 0,
+                source_language: None,
+                target_languages: vec![],
+                model: String::new(),
+                task_ids: vec![],
+                usage: Usage::default(),
+                summary: None,
+            },
+            events.display().to_string(),
+        ),
+    }
+}
+
+/// Feeds the events recorded in `events` to the transcripts of `args`, as far apart as they
+/// came in with `realtime`.
+pub async fn run(args: &Args, events: &Path, realtime: bool) {
+    let file = File::open(events).unwrap_or_else(|e| {
+        fail(
+            Kind::Other,
+            format!("Failed to open {}: {}", events.display(), e),
+        )
+    });
+    let (recorded, error) = read(BufReader::new(file));
+    if let Some(error) = error {
+        eprintln!(
+            "Warning: replaying the {} events before {} of {}",
+            recorded.len(),
+            error,
+            events.display()
+        );
+    }
+    let (session, device) = session(events, &recorded);
+    let (sinks, _) = transcript_sinks(args, false, &Timeline::default());
+    feed(sinks, recorded, &session, &device, realtime).await;
+}
+
+/// Feeds `recorded` to `sinks` as the events of `session`, captured from `device`, as far
+/// apart as they came in with `realtime`.
+pub async fn feed(
+    mut sinks: Sinks,
+    recorded: Vec<Recorded>,
+    session: &SessionInfo,
+    device: &str,
+    realtime: bool,
+) {
+    sinks.start(session, device);
+    let mut result: Vec<Transcription> = vec![];
+    let mut previous_ms = recorded.first().map_or(0, |r| r.received_ms);
+    for Recorded { received_ms, event } in recorded {
+        if realtime {
+            let wait = received_ms.saturating_sub(previous_ms);
+            tokio::time::sleep(Duration::from_millis(wait)).await;
+        }
+        previous_ms = received_ms;
+        let event = TranscriptionEvent::from(event);
+        if let TranscriptionEvent::Partial(t) | TranscriptionEvent::Final(t) = &event {
+            gummy::update_result(&mut result, t.clone());
+        }
+        sinks.on_event(&event).await;
+    }
+    sinks.finalize(&result, session).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session_dir::RecordedEvent;
+
+    const EVENTS: &str = concat!(
+        r#"{"received_ms":1000,"type":"partial","sentence":{"sentence_id":0,"begin_time":0,"end_time":300,"text":"Hel","is_final":false,"translated_text":null}}"#,
+        "\n",
+        r#"{"received_ms":1400,"type":"final","sentence":{"sentence_id":0,"begin_time":0,"end_time":800,"text":"Hello.","is_final":true,"translated_text":null}}"#,
+        "\n",
+        r#"{"received_ms":2000,"type":"fin"#,
+        "\n",
+    );
+
+    #[test]
+    fn reads_events_up_to_a_line_cut_short() {
+        let (recorded, error) = read(EVENTS.as_bytes());
+        assert_eq!(recorded.len(), 2);
+        assert_eq!(recorded[1].received_ms, 1400);
+        assert!(matches!(
+            &recorded[1].event,
+            RecordedEvent::Final { sentence } if sentence.text == "Hello."
+        ));
+        assert!(error.unwrap().starts_with("line 3: "));
+
+        let whole = EVENTS.lines().take(2).collect::<Vec<_>>().join("\n");
+        assert_eq!(read(whole.as_bytes()), (recorded, None));
+    }
+}
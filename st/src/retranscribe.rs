@@ -0,0 +1,494 @@
+//! `st retranscribe`: a sentence of a session directory, or a stretch of its time, sent
+//! again as a task of its own, with what comes of it put in place of what came the first
+//! time, in `events.jsonl` and in the transcripts next to it. `meta.json` counts the
+//! revisions.
+//!
+//! The audio is cut out of `audio.pcm`, which holds the audio as it was sent, so the times
+//! of sentences are where they are in it. Without one it's cut out of `audio.wav`, which
+//! only lines up with them when the session never paused nor skipped silence.
+
+use crate::args::Args;
+use crate::failure::{Kind, fail};
+use crate::output::{SessionInfo, TranscriptFormat, track_path};
+use crate::replay;
+use crate::session_dir::{self, AUDIO, EVENTS, META, RAW, Recorded, RecordedEvent};
+use crate::sink::Sinks;
+use crate::timeline::Timeline;
+use crate::{transcriber, transcript_files};
+use audio::raw::RawPcmSink;
+use audio::source::{SampleSource, WavFileSource};
+use clap::ValueEnum;
+use gummy::{StartOptions, Transcription};
+use log::info;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Rate of the audio of sessions that didn't record theirs.
+const DEFAULT_SAMPLE_RATE: u32 = 16000;
+
+/// The samples of `span`, in milliseconds, in audio at `sample_rate` holding `len` of them.
+pub fn sample_range(span: &Range<u64>, sample_rate: u32, len: usize) -> Range<usize> {
+    let at = |ms: u64| ((ms as u128 * sample_rate as u128 / 1000) as usize).min(len);
+    at(span.start)..at(span.end)
+}
+
+/// Where the sentence `id` is in the session, as it last came final.
+pub fn sentence_span(recorded: &[Recorded], id: u64) -> Option<Range<u64>> {
+    recorded.iter().rev().find_map(|r| match &r.event {
+        RecordedEvent::Final { sentence } if sentence.sentence_id == id => {
+            Some(sentence.begin_time..sentence.end_time)
+        }
+        _ => None,
+    })
+}
+
+/// `transcription`, of audio cut from `offset_ms` into the session, at its time in the
+/// session.
+fn shifted(mut transcription: Transcription, offset_ms: u64) -> Transcription {
+    transcription.begin_time += offset_ms;
+    transcription.end_time += offset_ms;
+    for word in &mut transcription.words {
+        word.begin_time += offset_ms;
+        word.end_time += offset_ms;
+    }
+    transcription
+}
+
+/// Whether the middle of `sentence` is in `span`, so sentences merely touching it stay.
+fn within(sentence: &Transcription, span: &Range<u64>) -> bool {
+    let middle = (sentence.begin_time + sentence.end_time) / 2;
+    span.start <= middle && middle <= span.end
+}
+
+fn sentence_of(event: &mut RecordedEvent) -> Option<&mut Transcription> {
+    match event {
+        RecordedEvent::Partial { sentence } | RecordedEvent::Final { sentence } => Some(sentence),
+        RecordedEvent::Finished => None,
+    }
+}
+
+/// `recorded` with the events of the sentences in `span` left out, and the sentences of
+/// `replacement` coming final where the first of them did. The replacements are numbered
+/// from the first sentence they replace, and the sentences after them numbered on from
+/// there.
+pub fn splice(
+    mut recorded: Vec<Recorded>,
+    span: &Range<u64>,
+    replacement: Vec<Transcription>,
+) -> Vec<Recorded> {
+    let mut finals = recorded
+        .iter()
+        .filter_map(|r| match &r.event {
+            RecordedEvent::Final { sentence } => Some(sentence),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    finals.sort_by_key(|sentence| sentence.sentence_id);
+    let removed = finals
+        .iter()
+        .filter(|sentence| within(sentence, span))
+        .map(|sentence| sentence.sentence_id)
+        .collect::<Vec<_>>();
+    // With none replaced, numbered from the first sentence after the span.
+    let first = removed.first().copied().unwrap_or_else(|| {
+        match finals
+            .iter()
+            .find(|sentence| sentence.begin_time >= span.start)
+        {
+            Some(next) => next.sentence_id,
+            None => finals.last().map_or(0, |last| last.sentence_id + 1),
+        }
+    });
+    let (added, dropped) = (replacement.len() as u64, removed.len() as u64);
+
+    let position = recorded
+        .iter_mut()
+        .position(|r| sentence_of(&mut r.event).is_none_or(|s| s.sentence_id >= first))
+        .unwrap_or(recorded.len());
+    let received_ms = (recorded.get(position))
+        .or(recorded.last())
+        .map_or(0, |r| r.received_ms);
+    let replacement = replacement
+        .into_iter()
+        .zip(first..)
+        .map(|(mut sentence, id)| {
+            sentence.sentence_id = id;
+            sentence.is_final = true;
+            Recorded {
+                received_ms,
+                event: RecordedEvent::Final { sentence },
+            }
+        });
+    let after = recorded.split_off(position);
+    recorded.extend(replacement);
+    for mut r in after {
+        if let Some(sentence) = sentence_of(&mut r.event) {
+            if removed.contains(&sentence.sentence_id) {
+                continue;
+            }
+            sentence.sentence_id = (sentence.sentence_id + added).saturating_sub(dropped);
+        }
+        recorded.push(r);
+    }
+    recorded
+}
+
+/// The audio of `span` in the session directory `dir`, with its rate, from `audio.pcm` and
+/// its sidecar, or `audio.wav` at `sample_rate` without them.
+async fn cut(dir: &Path, span: &Range<u64>, sample_rate: u32) -> Result<(Vec<i16>, u32), String> {
+    let raw = dir.join(RAW);
+    if !raw.exists() {
+        let wav = dir.join(AUDIO);
+        info!("No {} in {}, cutting {} instead", RAW, dir.display(), AUDIO);
+        let mut source = WavFileSource::open(&wav, sample_rate, None)
+            .map_err(|e| format!("Failed to open {}: {}", wav.display(), e))?;
+        let mut samples = vec![];
+        while let Some(frame) = source.next_frame().await {
+            samples.extend(frame.data);
+        }
+        let range = sample_range(span, sample_rate, samples.len());
+        return Ok((samples[range].to_vec(), sample_rate));
+    }
+    let read = |e: std::io::Error| format!("Failed to read {}: {}", raw.display(), e);
+    let sidecar = RawPcmSink::sidecar_path(&raw);
+    let sample_rate = std::fs::read_to_string(&sidecar)
+        .ok()
+        .and_then(|json| serde_json::from_str::<serde_json::Value>(&json).ok())
+        .and_then(|sidecar| sidecar["sample_rate"].as_u64())
+        .map_or(sample_rate, |rate| rate as u32);
+    let mut file = File::open(&raw).map_err(read)?;
+    let len = file.metadata().map_err(read)?.len() as usize / 2;
+    let range = sample_range(span, sample_rate, len);
+    let mut bytes = vec![0; range.len() * 2];
+    file.seek(SeekFrom::Start(range.start as u64 * 2))
+        .map_err(read)?;
+    file.read_exact(&mut bytes).map_err(read)?;
+    let samples = bytes
+        .chunks_exact(2)
+        .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    Ok((samples, sample_rate))
+}
+
+/// `samples` at `sample_rate` transcribed by a task of their own, in the languages of
+/// `session`, or of `args` if it didn't record them.
+async fn transcribe(
+    args: &Args,
+    session: &SessionInfo,
+    samples: &[i16],
+    sample_rate: u32,
+) -> anyhow::Result<Vec<Transcription>> {
+    let target_languages = match session.target_languages.is_empty() {
+        true => args.target_lang.clone(),
+        false => session.target_languages.clone(),
+    };
+    let options = StartOptions {
+        format: Some("pcm".to_string()),
+        sample_rate: Some(sample_rate),
+        source_language: (session.source_language.clone()).or(args.source_lang.clone()),
+        target_languages,
+        retained_finals: None,
+    };
+    let mut task = transcriber(args).start(options).await?;
+    // A tenth of a second at a time, as captured audio goes.
+    for chunk in samples.chunks(sample_rate as usize / 10) {
+        task.send_samples(chunk).await?;
+    }
+    task.finish().await
+}
+
+/// The transcripts in `dir`: files named like one of the formats, other than the events,
+/// the metadata and the sidecar of the raw audio, leaving out the translations of one
+/// written to a file per language of `languages` after `template`.
+fn transcripts(
+    dir: &Path,
+    languages: &[String],
+    template: &str,
+) -> std::io::Result<Vec<(PathBuf, TranscriptFormat)>> {
+    let sidecar = RawPcmSink::sidecar_path(RAW);
+    let mut transcripts = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default();
+        if [OsStr::new(EVENTS), OsStr::new(META), sidecar.as_os_str()].contains(&name) {
+            continue;
+        }
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        if let Some(format) = extension.and_then(|e| TranscriptFormat::from_str(e, true).ok()) {
+            transcripts.push((path, format));
+        }
+    }
+    let tracks = transcripts
+        .iter()
+        .flat_map(|(path, _)| (languages.iter()).map(move |lang| track_path(path, template, lang)))
+        .collect::<Vec<_>>();
+    transcripts.retain(|(path, _)| !tracks.contains(path));
+    transcripts.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(transcripts)
+}
+
+/// Transcribes the sentence `id`, or the `range` of time, of the session directory `dir`
+/// again, and writes its events and transcripts anew with the result.
+pub async fn run(args: &Args, dir: &Path, id: Option<u64>, range: Option<Range<u64>>) {
+    let events = dir.join(EVENTS);
+    let file = File::open(&events).unwrap_or_else(|e| {
+        fail(
+            Kind::Other,
+            format!("Failed to open {}: {}", events.display(), e),
+        )
+    });
+    let (recorded, error) = replay::read(BufReader::new(file));
+    if let Some(error) = error {
+        fail(
+            Kind::Other,
+            format!("Failed to read {}: {}", events.display(), error),
+        );
+    }
+    let span = match (id, range) {
+        (Some(id), _) => sentence_span(&recorded, id).unwrap_or_else(|| {
+            fail(
+                Kind::Config,
+                format!("No sentence {} in {}", id, events.display()),
+            )
+        }),
+        (None, Some(range)) => range,
+        (None, None) => unreachable!("the argument parser asks for one"),
+    };
+    let (session, device) = replay::session(&events, &recorded);
+    let sample_rate = match session.sample_rate {
+        0 => DEFAULT_SAMPLE_RATE,
+        sample_rate => sample_rate,
+    };
+    let (samples, sample_rate) = cut(dir, &span, sample_rate)
+        .await
+        .unwrap_or_else(|message| fail(Kind::Other, message));
+    if samples.is_empty() {
+        fail(
+            Kind::Config,
+            format!(
+                "No audio from {} to {} ms in {}",
+                span.start,
+                span.end,
+                dir.display()
+            ),
+        );
+    }
+    info!(
+        "Transcribing {} to {} ms of {} again",
+        span.start,
+        span.end,
+        dir.display()
+    );
+    let replacement = transcribe(args, &session, &samples, sample_rate)
+        .await
+        .unwrap_or_else(|e| {
+            fail(
+                Kind::of_session(&e),
+                format!("Failed to transcribe the audio again: {:#}", e),
+            )
+        });
+    let replacement = (replacement.into_iter())
+        .map(|sentence| shifted(sentence, span.start))
+        .collect();
+    let recorded = splice(recorded, &span, replacement);
+    if let Err(e) = session_dir::write_events(&events, &recorded) {
+        fail(
+            Kind::Other,
+            format!("Failed to write {}: {}", events.display(), e),
+        );
+    }
+
+    let transcripts = transcripts(dir, &session.target_languages, &args.track_template)
+        .unwrap_or_else(|e| {
+            fail(
+                Kind::Other,
+                format!("Failed to read {}: {}", dir.display(), e),
+            )
+        });
+    let mut sinks = Sinks::default();
+    for (path, format) in &transcripts {
+        let files = transcript_files(args, Some(path), *format, &Timeline::default())
+            .unwrap_or_else(|message| fail(Kind::Other, message));
+        for file in files {
+            sinks.push(file);
+        }
+    }
+    if let Some(cleanup) = &args.cleanup {
+        sinks.clean_up(cleanup.clone(), args.source_lang.clone());
+    }
+    replay::feed(sinks, recorded, &session, &device, false).await;
+
+    let meta = dir.join(META);
+    match session_dir::revise(&meta) {
+        Ok(revision) => eprintln!("Patched {}, revision {}", dir.display(), revision),
+        Err(e) => eprintln!(
+            "Warning: patched {}, but failed to count the revision in {}: {}",
+            dir.display(),
+            meta.display(),
+            e
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sentence(id: u64, begin_time: u64, end_time: u64, text: &str) -> Transcription {
+        Transcription {
+            sentence_id: id,
+            begin_time,
+            end_time,
+            text: text.to_string(),
+            is_final: true,
+            translated_text: None,
+            translations: vec![],
+            words: vec![],
+            confidence: None,
+            source_label: None,
+        }
+    }
+
+    fn event(received_ms: u64, event: RecordedEvent) -> Recorded {
+        Recorded { received_ms, event }
+    }
+
+    fn final_(received_ms: u64, sentence: Transcription) -> Recorded {
+        event(received_ms, RecordedEvent::Final { sentence })
+    }
+
+    /// Three sentences of a second each, a second apart, with a partial before each final.
+    fn session() -> Vec<Recorded> {
+        let mut recorded = vec![];
+        for (id, text) in ["One.", "Too garbled.", "Three."].into_iter().enumerate() {
+            let id = id as u64;
+            let begin = id * 2000;
+            let mut partial = sentence(id, begin, begin + 500, &text[..2]);
+            partial.is_final = false;
+            recorded.push(event(
+                begin + 600,
+                RecordedEvent::Partial { sentence: partial },
+            ));
+            recorded.push(final_(
+                begin + 1200,
+                sentence(id, begin, begin + 1000, text),
+            ));
+        }
+        recorded.push(event(6000, RecordedEvent::Finished));
+        recorded
+    }
+
+    /// IDs and texts of the final sentences of `recorded`, in order.
+    fn finals(recorded: &[Recorded]) -> Vec<(u64, &str)> {
+        recorded
+            .iter()
+            .filter_map(|r| match &r.event {
+                RecordedEvent::Final { sentence } => {
+                    Some((sentence.sentence_id, sentence.text.as_str()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cuts_the_samples_of_a_span() {
+        assert_eq!(sample_range(&(2000..3000), 16000, 100_000), 32000..48000);
+        assert_eq!(sample_range(&(1500..2250), 48000, 1_000_000), 72000..108000);
+        // Up to the end of the audio, or none past it.
+        assert_eq!(sample_range(&(5000..7000), 16000, 96000), 80000..96000);
+        assert_eq!(sample_range(&(7000..8000), 16000, 96000), 96000..96000);
+    }
+
+    #[test]
+    fn finds_a_sentence_as_it_came_final() {
+        let mut recorded = session();
+        assert_eq!(sentence_span(&recorded, 1), Some(2000..3000));
+        assert_eq!(sentence_span(&recorded, 3), None);
+        recorded.push(final_(7000, sentence(1, 2000, 3200, "Two.")));
+        assert_eq!(sentence_span(&recorded, 1), Some(2000..3200));
+    }
+
+    #[test]
+    fn splices_in_the_sentence_transcribed_again() {
+        let replacement = vec![shifted(sentence(0, 100, 900, "Two."), 2000)];
+        assert_eq!(
+            (replacement[0].begin_time, replacement[0].end_time),
+            (2100, 2900)
+        );
+        let spliced = splice(session(), &(2000..3000), replacement);
+        assert_eq!(finals(&spliced), [(0, "One."), (1, "Two."), (2, "Three.")]);
+        // Where the old one came in, along with its partial.
+        assert_eq!(spliced.len(), 6);
+        assert!(matches!(
+            &spliced[2],
+            Recorded { received_ms: 2600, event: RecordedEvent::Final { sentence } }
+                if sentence.text == "Two."
+        ));
+        assert!(matches!(spliced[5].event, RecordedEvent::Finished));
+    }
+
+    #[test]
+    fn numbers_the_sentences_after_anew() {
+        // Two sentences in place of one.
+        let replacement = vec![
+            sentence(0, 2000, 2400, "Two,"),
+            sentence(1, 2500, 3000, "and a half."),
+        ];
+        let spliced = splice(session(), &(2000..3000), replacement);
+        assert_eq!(
+            finals(&spliced),
+            [(0, "One."), (1, "Two,"), (2, "and a half."), (3, "Three.")]
+        );
+        let partials = spliced
+            .iter()
+            .filter_map(|r| match &r.event {
+                RecordedEvent::Partial { sentence } => Some(sentence.sentence_id),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(partials, [0, 3]);
+
+        // A stretch holding none, before a sentence or after the last.
+        let spliced = splice(session(), &(1100..1900), vec![sentence(0, 0, 700, "Uh.")]);
+        assert_eq!(
+            finals(&spliced),
+            [(0, "One."), (1, "Uh."), (2, "Too garbled."), (3, "Three.")]
+        );
+        let spliced = splice(session(), &(5000..6000), vec![sentence(0, 0, 700, "Bye.")]);
+        assert_eq!(finals(&spliced)[3], (3, "Bye."));
+        // Nothing came of it: the sentence is gone.
+        let spliced = splice(session(), &(2000..3000), vec![]);
+        assert_eq!(finals(&spliced), [(0, "One."), (1, "Three.")]);
+    }
+
+    #[test]
+    fn finds_the_transcripts_of_a_session() {
+        let dir = std::env::temp_dir().join(format!("st-{}-retranscribe", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "audio.pcm",
+            "audio.pcm.json",
+            "audio.wav",
+            "events.jsonl",
+            "meta.json",
+            "talk.srt",
+            "talk.zh.srt",
+            "transcript.json",
+        ] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let found = transcripts(&dir, &["zh".to_string()], "{stem}.{lang}.{ext}").unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            found,
+            [
+                (dir.join("talk.srt"), TranscriptFormat::Srt),
+                (dir.join("transcript.json"), TranscriptFormat::Json),
+            ]
+        );
+    }
+}
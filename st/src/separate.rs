@@ -0,0 +1,268 @@
+//! `--source separate`: system audio and the microphone transcribed in sessions of their
+//! own, so each side of a conversation gets its own transcript, and a transcript of the
+//! whole conversation tells them apart.
+
+use crate::args::Args;
+use crate::autostop::AutoStop;
+use crate::failure::{Kind, fail};
+use crate::output;
+use crate::transcriber::{Transcriber, TranscriptionSession};
+use crate::{
+    FINISH_TIMEOUT, Resampling, apply_args, exit_on_second_signal, print_capture_devices,
+    shutdown_signal, start_options, system_device, verify_format,
+};
+use audio::multi::{MultiSource, SourceId};
+use audio::recorder::{CaptureSource, RecorderConfig, RecorderEvent};
+use futures_util::future::select_all;
+use gummy::{Transcription, TranscriptionEvent};
+use log::{debug, error, info, warn};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tokio::select;
+use tokio::time::timeout;
+
+/// Names of the sources in `MultiSource` order, which also name their transcripts.
+const SOURCES: [&str; 2] = ["system", "microphone"];
+/// Labels of the sentences of each source in the conversation transcript, in `SOURCES`
+/// order.
+const LABELS: [&str; 2] = ["Others", "Me"];
+
+/// A source's transcription session and the transcript it writes.
+struct Speaker {
+    name: &'static str,
+    label: &'static str,
+    session: Box<dyn TranscriptionSession>,
+    open: bool,
+    resampling: Resampling,
+    path: PathBuf,
+    transcript: File,
+}
+
+impl Speaker {
+    async fn send(&mut self, samples: &[i16]) {
+        if !self.open {
+            return;
+        }
+        let samples = self.resampling.process(samples);
+        if let Err(e) = self.session.send_samples(samples).await {
+            warn!("Failed to send {} audio: {}", self.name, e);
+            self.open = false;
+        }
+    }
+
+    fn write(&mut self, transcription: &Transcription) {
+        if let Err(e) = writeln!(self.transcript, "{}", transcription.text) {
+            warn!("Failed to write {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Finishes the session and rewrites the transcript from its result, which also holds
+    /// the sentences finalized while finishing. Returns the result, labelled.
+    async fn finish(self) -> Vec<Transcription> {
+        match timeout(FINISH_TIMEOUT, self.session.finish()).await {
+            Ok(Ok(mut result)) => {
+                let text = result
+                    .iter()
+                    .map(|transcription| format!("{}\n", transcription.text))
+                    .collect::<String>();
+                match std::fs::write(&self.path, text) {
+                    Ok(()) => info!("Wrote {}", self.path.display()),
+                    Err(e) => warn!("Failed to write {}: {}", self.path.display(), e),
+                }
+                for transcription in &mut result {
+                    transcription.source_label = Some(self.label.to_string());
+                }
+                return result;
+            }
+            Ok(Err(e)) => warn!("Failed to finish the {} session: {}", self.name, e),
+            Err(_) => warn!(
+                "The {} session didn't finish within {} s, the last sentences may be missing",
+                self.name,
+                FINISH_TIMEOUT.as_secs()
+            ),
+        }
+        vec![]
+    }
+}
+
+/// Sends to the speaker of the source, if it started.
+async fn send(speakers: &mut [Option<Speaker>], id: SourceId, samples: &[i16]) {
+    if let Some(speaker) = &mut speakers[id] {
+        speaker.send(samples).await;
+    }
+}
+
+pub async fn run(args: &Args, transcriber: &dyn Transcriber) {
+    if args.skip_silence {
+        warn!("--skip-silence is ignored with --source separate");
+    }
+    let configs = [
+        RecorderConfig {
+            source: CaptureSource::SystemAudio,
+            device: system_device(args),
+            ..Default::default()
+        },
+        RecorderConfig {
+            source: CaptureSource::Microphone { device: None },
+            ..Default::default()
+        },
+    ]
+    .into_iter()
+    .map(|mut config| {
+        apply_args(&mut config, args, transcriber);
+        // Sessions would time out without the keepalive the single-source loop sends.
+        config.skip_silence = false;
+        config
+    })
+    .collect();
+    let (mut sources, failures) = MultiSource::start(configs)
+        .unwrap_or_else(|e| fail(Kind::Device, format!("Failed to start recorder: {}", e)));
+    for (id, e) in failures {
+        eprintln!("Warning: {} recorder failed to start: {}", SOURCES[id], e);
+    }
+
+    // Indexed by source, with no speaker for a source that did not start.
+    let mut speakers = vec![];
+    for (id, (name, label)) in SOURCES.into_iter().zip(LABELS).enumerate() {
+        if let Some(recorder) = sources.source(id).and_then(|source| source.recorder()) {
+            print_capture_devices(recorder.devices());
+        }
+        let Some(recorder_format) = sources.output_format(id) else {
+            speakers.push(None);
+            continue;
+        };
+        let sample_rate = transcriber
+            .preferred_sample_rate()
+            .unwrap_or(recorder_format.sample_rate);
+        let options = start_options(args, sample_rate);
+        verify_format(recorder_format, sample_rate, &options, args.force)
+            .unwrap_or_else(|message| fail(Kind::Config, message));
+        let session = transcriber.start(options).await.unwrap_or_else(|e| {
+            fail(
+                Kind::of_session(&e),
+                format!("Failed to start the {} session: {:#}", name, e),
+            )
+        });
+        if let Some(task_id) = session.task_id() {
+            info!("Started the {} task {}", name, task_id);
+        }
+        let path = Path::new(&args.transcript_dir).join(format!("{}.txt", name));
+        let transcript = File::create(&path).unwrap_or_else(|e| {
+            fail(
+                Kind::Other,
+                format!("Failed to create {}: {}", path.display(), e),
+            )
+        });
+        speakers.push(Some(Speaker {
+            name,
+            label,
+            session,
+            open: true,
+            resampling: Resampling::new(recorder_format.sample_rate, sample_rate),
+            path,
+            transcript,
+        }));
+    }
+    for (id, speaker) in speakers.iter_mut().enumerate() {
+        let Some(speaker) = speaker else {
+            continue;
+        };
+        let preroll = sources
+            .source(id)
+            .and_then(|source| source.recorder())
+            .map(|recorder| recorder.take_preroll())
+            .unwrap_or_default();
+        for sample_data in preroll {
+            speaker.send(&sample_data.data).await;
+        }
+    }
+
+    let mut auto_stop = AutoStop::from_args(args);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        let sessions = speakers
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(id, speaker)| Some((id, speaker.as_mut().filter(|s| s.open)?)))
+            .map(|(id, speaker)| Box::pin(async move { (id, speaker.session.next_event().await) }))
+            .collect::<Vec<_>>();
+        if sessions.is_empty() {
+            break;
+        }
+        select! {
+            event = sources.next_event() => {
+                match event {
+                    Some((id, RecorderEvent::Sample(sample_data))) => {
+                        // Speech from either source keeps both sessions going.
+                        auto_stop.hear(&sample_data.data);
+                        send(&mut speakers, id, &sample_data.data).await;
+                    }
+                    Some((id, RecorderEvent::Error(e))) => {
+                        warn!("{} recorder error: {}", SOURCES[id], e);
+                    }
+                    Some((id, RecorderEvent::Warning(warning))) => {
+                        eprintln!("Warning: {} {}", SOURCES[id], warning);
+                    }
+                    Some((id, RecorderEvent::DeviceChanged { old, new })) => {
+                        info!("{} capture moved from {} to {}", SOURCES[id], old, new);
+                    }
+                    Some((id, RecorderEvent::DeviceLost)) => {
+                        error!("{} capture device lost", SOURCES[id]);
+                    }
+                    Some((id, RecorderEvent::Fatal(e))) => {
+                        error!("{} recorder failed: {}", SOURCES[id], e);
+                    }
+                    Some((_, RecorderEvent::SilenceStarted | RecorderEvent::SilenceEnded)) => {}
+                    None => break,
+                }
+            },
+            (id, event) = async { select_all(sessions).await.0 } => {
+                // Only the speakers of started sources have a session to wait on.
+                let Some(speaker) = &mut speakers[id] else {
+                    continue;
+                };
+                match event {
+                    Ok(Some(TranscriptionEvent::Final(transcription))) => {
+                        speaker.write(&transcription);
+                    }
+                    Ok(Some(TranscriptionEvent::Finished) | None) => speaker.open = false,
+                    Ok(Some(event)) => debug!("{} message: {:?}", SOURCES[id], event),
+                    Err(e) => {
+                        debug!("Error receiving {} event: {}", SOURCES[id], e);
+                        speaker.open = false;
+                    }
+                }
+            },
+            _ = &mut shutdown => {
+                info!("Interrupted, finishing the sessions");
+                break;
+            },
+            reason = auto_stop.wait() => {
+                info!("{}, finishing the sessions", reason);
+                break;
+            },
+        }
+    }
+
+    exit_on_second_signal(None);
+    let remaining = sources.stop().unwrap_or_else(|e| {
+        warn!("Failed to stop recorder: {}", e);
+        vec![]
+    });
+    for (id, sample_data) in remaining {
+        send(&mut speakers, id, &sample_data.data).await;
+    }
+    let mut results = vec![];
+    for speaker in speakers.into_iter().flatten() {
+        results.push(speaker.finish().await);
+    }
+    let conversation = gummy::merge_sessions(results);
+    let path = Path::new(&args.transcript_dir).join("conversation.txt");
+    let text = output::text(&conversation, &[], &args.text_options());
+    match std::fs::write(&path, text) {
+        Ok(()) => info!("Wrote {}", path.display()),
+        Err(e) => warn!("Failed to write {}: {}", path.display(), e),
+    }
+}
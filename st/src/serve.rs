@@ -0,0 +1,357 @@
+//! `--serve`: the live transcript over HTTP, for following a session from another device.
+//!
+//! `/` is a page showing the captions, `/transcript` the transcript so far as JSON and
+//! `/events` a Server-Sent Events stream of the sentences as they are recognized, with
+//! `partial` and `final` events whose data is the sentence as JSON, and `alert` events
+//! when the session looks stuck. `/metrics` has the latency of the latest sentences and
+//! the audio queued for the backend as JSON. `/overlay` is the caption overlay for `--broadcast-ws`.
+
+use crate::latency::LatencyStats;
+use crate::watchdog::Alert;
+use axum::Router;
+use axum::extract::State;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{Html, IntoResponse, Json};
+use axum::routing::get;
+use futures_util::Stream;
+use futures_util::stream;
+use gummy::{Transcription, TranscriptionEvent};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, watch};
+
+const PAGE: &str = include_str!("serve.html");
+const OVERLAY: &str = include_str!("overlay.html");
+
+/// Events kept for subscribers that fall behind, before they miss some.
+const EVENT_BACKLOG: usize = 256;
+
+/// The transcript of `/transcript`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Transcript {
+    /// Final sentences, in the order they came in.
+    pub sentences: Vec<Transcription>,
+    /// The sentence being recognized, if any.
+    pub partial: Option<Transcription>,
+}
+
+impl Transcript {
+    /// Takes in `event`, returning whether it changed the transcript.
+    pub(crate) fn update(&mut self, event: &TranscriptionEvent) -> bool {
+        match event {
+            TranscriptionEvent::Partial(t) => self.partial = Some(t.clone()),
+            TranscriptionEvent::Final(t) => {
+                if self
+                    .partial
+                    .as_ref()
+                    .is_some_and(|partial| partial.sentence_id == t.sentence_id)
+                {
+                    self.partial = None;
+                }
+                if self
+                    .sentences
+                    .iter()
+                    .any(|s| s.sentence_id == t.sentence_id)
+                {
+                    return false;
+                }
+                self.sentences.push(t.clone());
+            }
+            TranscriptionEvent::Finished => return false,
+        }
+        true
+    }
+}
+
+/// The measurements of `/metrics`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Metrics {
+    /// Latency of the latest sentences, once there are some.
+    pub latency: Option<LatencyStats>,
+    /// Audio captured that the connection didn't take yet, in milliseconds.
+    pub queued_ms: u64,
+    /// How long until a new task is caught up with the audio held while reconnecting, while
+    /// it's sent, in milliseconds.
+    pub catch_up_ms: Option<u64>,
+}
+
+/// Feeds the server the events of the session. Streams of `/events` end when it's dropped.
+pub struct Feed {
+    transcript: watch::Sender<Transcript>,
+    metrics: watch::Sender<Metrics>,
+    events: broadcast::Sender<Event>,
+}
+
+impl Feed {
+    pub fn send(&self, event: &TranscriptionEvent) {
+        let mut changed = false;
+        self.transcript.send_if_modified(|transcript| {
+            changed = transcript.update(event);
+            changed
+        });
+        let event = match event {
+            TranscriptionEvent::Partial(t) if changed => sse_event("partial", t),
+            TranscriptionEvent::Final(t) if changed => sse_event("final", t),
+            _ => return,
+        };
+        // Nobody may be listening, which is fine.
+        let _ = self.events.send(event);
+    }
+
+    pub fn alert(&self, alert: &Alert) {
+        let data = serde_json::json!({ "kind": alert.kind(), "message": alert.to_string() });
+        let _ = self
+            .events
+            .send(Event::default().event("alert").data(data.to_string()));
+    }
+
+    pub fn latency(&self, latency: Option<LatencyStats>) {
+        self.metrics.send_if_modified(|metrics| {
+            let changed = metrics.latency != latency;
+            metrics.latency = latency;
+            changed
+        });
+    }
+
+    pub fn queued(&self, queued_ms: u64) {
+        self.metrics.send_if_modified(|metrics| {
+            let changed = metrics.queued_ms != queued_ms;
+            metrics.queued_ms = queued_ms;
+            changed
+        });
+    }
+
+    pub fn catch_up(&self, catch_up_ms: Option<u64>) {
+        self.metrics.send_if_modified(|metrics| {
+            let changed = metrics.catch_up_ms != catch_up_ms;
+            metrics.catch_up_ms = catch_up_ms;
+            changed
+        });
+    }
+
+    /// Sends the sentences of the session result, typically with some finalized while
+    /// the session finished.
+    pub fn finish(self, result: &[Transcription]) {
+        for transcription in result {
+            self.send(&TranscriptionEvent::Final(transcription.clone()));
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Shared {
+    transcript: watch::Receiver<Transcript>,
+    metrics: watch::Receiver<Metrics>,
+    // Subscribed to by each client; holding a sender would keep the streams from ending.
+    events: Arc<broadcast::Receiver<Event>>,
+}
+
+/// Listens on `addr` and serves the transcript in the background. Returns the feed and
+/// the address listened on, which tells the port when `addr` has port 0.
+pub async fn start(addr: SocketAddr) -> io::Result<(Feed, SocketAddr)> {
+    let listener = TcpListener::bind(addr).await?;
+    let addr = listener.local_addr()?;
+    let (transcript, transcript_rx) = watch::channel(Transcript::default());
+    let (metrics, metrics_rx) = watch::channel(Metrics::default());
+    let (events, events_rx) = broadcast::channel(EVENT_BACKLOG);
+    let app = router(Shared {
+        transcript: transcript_rx,
+        metrics: metrics_rx,
+        events: Arc::new(events_rx),
+    });
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            log::warn!("The transcript server stopped: {}", e);
+        }
+    });
+    Ok((
+        Feed {
+            transcript,
+            metrics,
+            events,
+        },
+        addr,
+    ))
+}
+
+fn router(shared: Shared) -> Router {
+    Router::new()
+        .route("/", get(|| async { Html(PAGE) }))
+        .route("/overlay", get(|| async { Html(OVERLAY) }))
+        .route("/transcript", get(transcript))
+        .route("/events", get(events))
+        .route("/metrics", get(metrics))
+        .with_state(shared)
+}
+
+async fn transcript(State(shared): State<Shared>) -> impl IntoResponse {
+    Json(shared.transcript.borrow().clone())
+}
+
+async fn metrics(State(shared): State<Shared>) -> impl IntoResponse {
+    Json(*shared.metrics.borrow())
+}
+
+async fn events(
+    State(shared): State<Shared>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = shared.events.resubscribe();
+    let stream = stream::unfold(events, |mut events| async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => return Some((Ok(event), events)),
+                // A slow client misses what it fell behind on; `/transcript` has it all.
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+fn sse_event(name: &str, transcription: &Transcription) -> Event {
+    Event::default()
+        .event(name)
+        .data(serde_json::to_string(transcription).expect("sentences serialize"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    fn sentence(sentence_id: u64, text: &str, is_final: bool) -> Transcription {
+        Transcription {
+            sentence_id,
+            begin_time: 0,
+            end_time: 1000,
+            text: text.to_string(),
+            is_final,
+            translated_text: None,
+            translations: vec![],
+            words: vec![],
+            confidence: None,
+            source_label: None,
+        }
+    }
+
+    /// Sends a GET for `path` and returns the connection, to read the response from.
+    async fn get(addr: SocketAddr, path: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            path, addr
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        stream
+    }
+
+    async fn read_to_end(mut stream: TcpStream) -> String {
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    /// Reads from `stream` until `expected` shows up in what was read.
+    async fn read_until(stream: &mut TcpStream, read: &mut String, expected: &str) {
+        let mut buffer = [0; 4096];
+        while !read.contains(expected) {
+            let n = stream.read(&mut buffer).await.unwrap();
+            assert!(n > 0, "{:?} doesn't contain {:?}", read, expected);
+            read.push_str(std::str::from_utf8(&buffer[..n]).unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_the_transcript_and_its_events() {
+        let (feed, addr) = start("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        assert!(
+            read_to_end(get(addr, "/").await)
+                .await
+                .contains("EventSource")
+        );
+        assert!(
+            read_to_end(get(addr, "/overlay").await)
+                .await
+                .contains("WebSocket")
+        );
+
+        let mut events = get(addr, "/events").await;
+        let mut read = String::new();
+        read_until(&mut events, &mut read, "\r\n\r\n").await;
+        assert!(read.contains("content-type: text/event-stream"), "{}", read);
+
+        feed.send(&TranscriptionEvent::Partial(sentence(0, "Hel", false)));
+        feed.send(&TranscriptionEvent::Final(sentence(0, "Hello.", true)));
+        // Sentences are only sent once.
+        feed.send(&TranscriptionEvent::Final(sentence(0, "Hello.", true)));
+        feed.send(&TranscriptionEvent::Partial(sentence(1, "Wor", false)));
+        feed.send(&TranscriptionEvent::Finished);
+        feed.alert(&Alert::Unanswered {
+            duration: Duration::from_secs(15),
+        });
+        read_until(&mut events, &mut read, "\"unanswered\"").await;
+        let frames: Vec<_> = read
+            .lines()
+            .filter(|line| line.starts_with("event:") || line.starts_with("data:"))
+            .map(|line| line.split(',').next().unwrap())
+            .collect();
+        assert_eq!(
+            frames,
+            [
+                "event: partial",
+                "data: {\"sentence_id\":0",
+                "event: final",
+                "data: {\"sentence_id\":0",
+                "event: partial",
+                "data: {\"sentence_id\":1",
+                "event: alert",
+                "data: {\"kind\":\"unanswered\"",
+            ]
+        );
+        assert_eq!(read.matches("\"text\":\"Hello.\"").count(), 1);
+
+        let response = read_to_end(get(addr, "/transcript").await).await;
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        let transcript: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(
+            transcript,
+            serde_json::to_value(Transcript {
+                sentences: vec![sentence(0, "Hello.", true)],
+                partial: Some(sentence(1, "Wor", false)),
+            })
+            .unwrap()
+        );
+
+        let metrics = || async { read_to_end(get(addr, "/metrics").await).await };
+        assert!(
+            metrics()
+                .await
+                .ends_with("{\"latency\":null,\"queued_ms\":0,\"catch_up_ms\":null}")
+        );
+        feed.latency(Some(LatencyStats {
+            sentences: 1,
+            p50_ms: 800,
+            p95_ms: 800,
+            max_ms: 800,
+        }));
+        feed.queued(1500);
+        feed.catch_up(Some(4200));
+        assert!(metrics().await.ends_with(
+            "{\"latency\":{\"sentences\":1,\"p50_ms\":800,\"p95_ms\":800,\"max_ms\":800},\"queued_ms\":1500,\"catch_up_ms\":4200}"
+        ));
+
+        // The stream ends with the session.
+        feed.finish(&[sentence(1, "World.", true)]);
+        read.clear();
+        events.read_to_string(&mut read).await.unwrap();
+        assert!(read.contains("event: final\n"), "{:?}", read);
+        assert!(read.contains("\"text\":\"World.\""), "{:?}", read);
+    }
+}
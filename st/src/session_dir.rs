@@ -0,0 +1,253 @@
+//! `--session-dir`: a directory per run, named after when it started, holding everything the
+//! run saved: the audio, the transcript, the events as they came in and what the session
+//! was, for archiving.
+
+use crate::args::{Args, is_stdout};
+use crate::output::{SessionInfo, Usage};
+use crate::summary::Summary;
+use clap::ValueEnum;
+use gummy::{Transcription, TranscriptionEvent};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Audio saved in a session directory unless `--save-audio` names the file.
+pub const AUDIO: &str = "audio.wav";
+/// The audio sent, which unlike a WAV file is whole up to the last frame after a crash.
+pub const RAW: &str = "audio.pcm";
+pub const EVENTS: &str = "events.jsonl";
+pub const META: &str = "meta.json";
+
+/// How a run ended, as far as `meta.json` knows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Still going, or killed without a chance to say so.
+    Running,
+    Finished,
+    /// Exited on a second signal without finishing the session.
+    Interrupted,
+    Crashed,
+}
+
+/// The contents of `meta.json`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Meta {
+    /// Version of st.
+    pub version: String,
+    pub status: Status,
+    /// Capture device, or the file transcribed.
+    pub device: String,
+    #[serde(flatten)]
+    pub session: SessionInfo,
+    /// When the run ended, in milliseconds since the Unix epoch.
+    pub ended_at_ms: Option<u64>,
+    /// Time from the start of the session to its end, in milliseconds.
+    pub duration_ms: Option<u64>,
+    /// Times `st retranscribe` patched the transcript since.
+    #[serde(default)]
+    pub revision: u32,
+}
+
+/// A line of `events.jsonl`: an event of the session and when it came in.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Recorded {
+    /// When the event came in, in milliseconds since the Unix epoch.
+    pub received_ms: u64,
+    #[serde(flatten)]
+    pub event: RecordedEvent,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum RecordedEvent {
+    Partial { sentence: Transcription },
+    Final { sentence: Transcription },
+    Finished,
+}
+
+impl From<&TranscriptionEvent> for RecordedEvent {
+    fn from(event: &TranscriptionEvent) -> Self {
+        match event {
+            TranscriptionEvent::Partial(t) => RecordedEvent::Partial {
+                sentence: t.clone(),
+            },
+            TranscriptionEvent::Final(t) => RecordedEvent::Final {
+                sentence: t.clone(),
+            },
+            TranscriptionEvent::Finished => RecordedEvent::Finished,
+        }
+    }
+}
+
+impl From<RecordedEvent> for TranscriptionEvent {
+    fn from(event: RecordedEvent) -> Self {
+        match event {
+            RecordedEvent::Partial { sentence } => TranscriptionEvent::Partial(sentence),
+            RecordedEvent::Final { sentence } => TranscriptionEvent::Final(sentence),
+            RecordedEvent::Finished => TranscriptionEvent::Finished,
+        }
+    }
+}
+
+/// `meta.json` of a session directory, shared with the hooks that write it on the way out.
+#[derive(Clone)]
+pub struct MetaFile {
+    path: PathBuf,
+    meta: Arc<Mutex<Option<Meta>>>,
+}
+
+impl MetaFile {
+    /// Keeps the usage of the session so far and the tasks it ran, for when the run ends
+    /// abruptly.
+    pub fn record(&self, usage: Usage, task_ids: &[String]) {
+        if let Some(meta) = self.meta.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            meta.session.usage = usage;
+            meta.session.task_ids = task_ids.to_vec();
+        }
+    }
+
+    /// Keeps what the session came to, once it has ended.
+    pub fn summarize(&self, summary: Summary) {
+        if let Some(meta) = self.meta.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            meta.session.summary = Some(summary);
+        }
+    }
+
+    /// Records that the run ended at `now_ms` with `status`, if the session had begun.
+    pub fn end(&self, status: Status, now_ms: u64) -> io::Result<()> {
+        let mut meta = self.meta.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(meta) = meta.as_mut() else {
+            return Ok(());
+        };
+        meta.status = status;
+        meta.ended_at_ms = Some(now_ms);
+        meta.duration_ms = Some(now_ms.saturating_sub(meta.session.started_at_ms));
+        write_json(&self.path, meta)
+    }
+
+    fn set(&self, meta: Meta) -> io::Result<()> {
+        write_json(&self.path, &meta)?;
+        *self.meta.lock().unwrap_or_else(|e| e.into_inner()) = Some(meta);
+        Ok(())
+    }
+}
+
+/// The directory of a run, with the events of its session written to it as they come in.
+pub struct SessionDir {
+    path: PathBuf,
+    events: LineWriter<File>,
+    meta: MetaFile,
+}
+
+impl SessionDir {
+    /// Creates a directory under `base` named after the local time, like
+    /// `2024-06-01_14-03-22`, and points the audio and transcript `args` save into it.
+    /// Files they name keep their name; the others are saved under a default one.
+    pub fn create(base: &Path, args: &mut Args) -> io::Result<Self> {
+        fs::create_dir_all(base)?;
+        let name = chrono::Local::now().format("%Y-%m-%d_%H-%M-%S").to_string();
+        let mut path = base.join(&name);
+        // Runs started within the same second get a directory each.
+        for n in 2.. {
+            match fs::create_dir(&path) {
+                Ok(()) => break,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    path = base.join(format!("{}_{}", name, n));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let inside = |named: Option<&Path>, default: &str| {
+            let name = named.and_then(Path::file_name);
+            path.join(name.unwrap_or(default.as_ref()))
+        };
+        let transcript = format!(
+            "transcript.{}",
+            args.transcript_format(None)
+                .to_possible_value()
+                .expect("formats have names")
+                .get_name()
+        );
+        if args.output.iter().all(|path| is_stdout(path)) {
+            args.output.push(PathBuf::from(&transcript));
+        }
+        for output in args.output.iter_mut().filter(|path| !is_stdout(path)) {
+            *output = inside(Some(output), &transcript);
+        }
+        for (file, default) in [(&mut args.save_audio, AUDIO), (&mut args.save_raw, RAW)] {
+            let path = inside(file.as_deref().map(Path::new), default);
+            *file = Some(path.to_string_lossy().into_owned());
+        }
+        Ok(SessionDir {
+            events: LineWriter::new(File::create(path.join(EVENTS))?),
+            meta: MetaFile {
+                path: path.join(META),
+                meta: Arc::default(),
+            },
+            path,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes `meta.json` for a session that just began, and has it record a crash if the
+    /// process panics.
+    pub fn begin(&self, meta: Meta) -> io::Result<()> {
+        self.meta.set(meta)?;
+        let file = self.meta.clone();
+        let hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let _ = file.end(Status::Crashed, crate::now_ms());
+            hook(info);
+        }));
+        Ok(())
+    }
+
+    /// Appends an event of the session received at `received_ms` to `events.jsonl`.
+    pub fn event(&mut self, event: &TranscriptionEvent, received_ms: u64) -> io::Result<()> {
+        let line = Recorded {
+            received_ms,
+            event: event.into(),
+        };
+        let line = serde_json::to_string(&line).map_err(io::Error::other)?;
+        writeln!(self.events, "{}", line)
+    }
+
+    /// `meta.json`, for recording how the run ends.
+    pub fn meta(&self) -> MetaFile {
+        self.meta.clone()
+    }
+}
+
+/// Writes `recorded` to the `events.jsonl` at `path` in place of what it held, all at once,
+/// so a failure leaves the old events whole.
+pub fn write_events(path: &Path, recorded: &[Recorded]) -> io::Result<()> {
+    let mut lines = String::new();
+    for line in recorded {
+        lines += &serde_json::to_string(line).map_err(io::Error::other)?;
+        lines.push('\n');
+    }
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    fs::write(&temp, lines)?;
+    fs::rename(&temp, path)
+}
+
+/// Counts a revision of the transcript in the `meta.json` at `path`, returning its number.
+pub fn revise(path: &Path) -> io::Result<u32> {
+    let json = fs::read_to_string(path)?;
+    let mut meta = serde_json::from_str::<Meta>(&json).map_err(io::Error::other)?;
+    meta.revision += 1;
+    write_json(path, &meta)?;
+    Ok(meta.revision)
+}
+
+fn write_json(path: &Path, value: &impl Serialize) -> io::Result<()> {
+    let json = serde_json::to_string_pretty(value).map_err(io::Error::other)?;
+    fs::write(path, json + "\n")
+}
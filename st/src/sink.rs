@@ -0,0 +1,496 @@
+//! Where the transcript of a session goes: captions on stdout and each `--output`, fed the
+//! events of the session from one place, so a sink that fails doesn't take the others
+//! down with it.
+
+use crate::cleanup::Cleanup;
+use crate::console::ConsoleRenderer;
+use crate::output::{self, Marker, SessionInfo, TranscriptFormat, TranscriptWriter};
+use crate::timeline::{Clock, Timeline};
+use gummy::{Transcription, TranscriptionEvent};
+use log::warn;
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// Something the transcript is written to.
+pub trait TranscriptSink {
+    /// What the sink writes to, for warnings.
+    fn name(&self) -> String;
+
+    /// Takes in the session as it starts, capturing from `device`.
+    fn start(&mut self, _session: &SessionInfo, _device: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Takes in an event of the session.
+    fn on_event(&mut self, event: &TranscriptionEvent) -> io::Result<()>;
+
+    /// Takes in a moment marked during the session.
+    fn mark(&mut self, _marker: &Marker) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Takes in a warning about the session, of a `kind` like `silent`.
+    fn warning(&mut self, _kind: &str, _message: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Takes in the result of the session once it's over, empty if it failed to finish.
+    /// `session` is what JSON documents record about it.
+    fn finalize(&mut self, result: &[Transcription], session: &SessionInfo) -> io::Result<()>;
+}
+
+/// The sinks of a session. One that fails is warned about and written no more.
+#[derive(Default)]
+pub struct Sinks {
+    sinks: Vec<Box<dyn TranscriptSink>>,
+    // How the sentences fed to the sinks are cleaned up, and the language spoken, if known.
+    cleanup: Option<(Cleanup, Option<String>)>,
+}
+
+impl Sinks {
+    pub fn push(&mut self, sink: impl TranscriptSink + 'static) {
+        self.sinks.push(Box::new(sink));
+    }
+
+    /// Feeds the sinks sentences cleaned up by `cleanup`, spoken in `language`, or in one
+    /// detected when `None`. The sentences passed in are left as they are.
+    pub fn clean_up(&mut self, cleanup: Cleanup, language: Option<String>) {
+        self.cleanup = Some((cleanup, language));
+    }
+
+    pub fn start(&mut self, session: &SessionInfo, device: &str) {
+        self.each(|sink| sink.start(session, device));
+    }
+
+    pub async fn on_event(&mut self, event: &TranscriptionEvent) {
+        let cleaned;
+        let event = match &self.cleanup {
+            Some((cleanup, language)) => {
+                cleaned = cleanup.event(event, language.as_deref()).await;
+                &cleaned
+            }
+            None => event,
+        };
+        self.each(|sink| sink.on_event(event));
+    }
+
+    pub fn mark(&mut self, marker: &Marker) {
+        self.each(|sink| sink.mark(marker));
+    }
+
+    pub fn warning(&mut self, kind: &str, message: &str) {
+        self.each(|sink| sink.warning(kind, message));
+    }
+
+    pub async fn finalize(mut self, result: &[Transcription], session: &SessionInfo) {
+        let cleaned: Vec<Transcription>;
+        let result = match &self.cleanup {
+            Some((cleanup, language)) => {
+                let mut transcriptions = Vec::with_capacity(result.len());
+                for t in result {
+                    transcriptions.push(cleanup.transcription(t, language.as_deref()).await);
+                }
+                cleaned = transcriptions;
+                &cleaned
+            }
+            None => result,
+        };
+        self.each(|sink| sink.finalize(result, session));
+    }
+
+    fn each(&mut self, mut write: impl FnMut(&mut dyn TranscriptSink) -> io::Result<()>) {
+        self.sinks.retain_mut(|sink| match write(sink.as_mut()) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!(
+                    "Failed to write {}, no longer writing it: {}",
+                    sink.name(),
+                    e
+                );
+                false
+            }
+        });
+    }
+}
+
+impl<W: Write> TranscriptSink for ConsoleRenderer<W> {
+    fn name(&self) -> String {
+        "the captions".to_string()
+    }
+
+    fn on_event(&mut self, event: &TranscriptionEvent) -> io::Result<()> {
+        self.render(event)
+    }
+
+    fn finalize(&mut self, result: &[Transcription], _session: &SessionInfo) -> io::Result<()> {
+        self.finish(result)
+    }
+}
+
+/// A transcript written in a format of its own to an `--output` file, or stdout.
+pub struct TranscriptFile {
+    // `None` for stdout.
+    path: Option<PathBuf>,
+    writer: TranscriptWriter<Box<dyn Write>>,
+    // The language of the translations written instead of the sentences.
+    language: Option<String>,
+    // Sentences left out, not translated into `language`.
+    untranslated: HashSet<u64>,
+    // The clock times are written on, and the timeline placing them on it.
+    clock: Clock,
+    timeline: Timeline,
+}
+
+impl TranscriptFile {
+    pub fn new(path: Option<PathBuf>, writer: TranscriptWriter<Box<dyn Write>>) -> Self {
+        TranscriptFile {
+            path,
+            writer,
+            language: None,
+            untranslated: HashSet::new(),
+            clock: Clock::Task,
+            timeline: Timeline::default(),
+        }
+    }
+
+    /// Writes times on `clock`, as `timeline` places them.
+    pub fn on_clock(mut self, clock: Clock, timeline: Timeline) -> Self {
+        self.clock = clock;
+        self.timeline = timeline;
+        self
+    }
+
+    /// Writes the translations into `lang` instead of the sentences, leaving out the
+    /// sentences that aren't translated into it.
+    pub fn in_language(mut self, lang: &str) -> Self {
+        self.language = Some(lang.to_string());
+        self
+    }
+
+    /// `transcription` as written, on the clock of the transcript, or `None` when it's left
+    /// out for want of a translation.
+    fn translate(&mut self, transcription: &Transcription) -> Option<Transcription> {
+        let format = self.writer.format();
+        let shifted = self.clock.shift(&self.timeline, transcription, format);
+        let Some(lang) = &self.language else {
+            return Some(shifted);
+        };
+        let translated = output::translated(&shifted, lang);
+        match translated {
+            Some(_) => self.untranslated.remove(&transcription.sentence_id),
+            None => self.untranslated.insert(transcription.sentence_id),
+        };
+        translated
+    }
+
+    /// Writes `transcription`, with when it was spoken in JSON transcripts.
+    fn write(&mut self, transcription: &Transcription) -> io::Result<()> {
+        let wall_clock = match self.writer.format() {
+            TranscriptFormat::Json | TranscriptFormat::Jsonl => self
+                .timeline
+                .wall_clock(transcription.begin_time, transcription.end_time),
+            TranscriptFormat::Txt | TranscriptFormat::Srt | TranscriptFormat::Vtt => None,
+        };
+        match self.translate(transcription) {
+            Some(transcription) => self.writer.write_at(&transcription, wall_clock),
+            None => Ok(()),
+        }
+    }
+}
+
+impl TranscriptSink for TranscriptFile {
+    fn name(&self) -> String {
+        match &self.path {
+            Some(path) => path.display().to_string(),
+            None => "the transcript to stdout".to_string(),
+        }
+    }
+
+    fn on_event(&mut self, event: &TranscriptionEvent) -> io::Result<()> {
+        match event {
+            TranscriptionEvent::Final(transcription) => self.write(transcription),
+            TranscriptionEvent::Partial(_) | TranscriptionEvent::Finished => Ok(()),
+        }
+    }
+
+    fn mark(&mut self, marker: &Marker) -> io::Result<()> {
+        let format = self.writer.format();
+        self.writer
+            .mark(self.clock.shift_marker(&self.timeline, marker, format))
+    }
+
+    fn finalize(&mut self, result: &[Transcription], session: &SessionInfo) -> io::Result<()> {
+        for transcription in result {
+            self.write(transcription)?;
+        }
+        let format = self.writer.format();
+        let convert = |ms| self.clock.convert(&self.timeline, ms, format);
+        let span = (convert(0), convert(session.usage.audio_ms));
+        self.writer.set_span(span.0, span.1);
+        self.writer.finish(&[], session)?;
+        if let Some(lang) = &self.language {
+            // Translated by the time the session ended, or written translated before.
+            for written in self.writer.sentences() {
+                self.untranslated.remove(&written.sentence_id);
+            }
+            if let Some(path) = &self.path {
+                eprintln!("Saved translation to {}", path.display());
+            }
+            if !self.untranslated.is_empty() {
+                eprintln!(
+                    "Left out {} sentences not translated into {}",
+                    self.untranslated.len(),
+                    lang
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{SubtitleOptions, TRACK_TEMPLATE, Usage};
+    use gummy::Translation;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Records what it's fed, failing from the `fail_at`th call on.
+    struct MockSink {
+        calls: Rc<RefCell<Vec<String>>>,
+        fail_at: Option<usize>,
+    }
+
+    impl MockSink {
+        fn call(&mut self, call: String) -> io::Result<()> {
+            let mut calls = self.calls.borrow_mut();
+            if self.fail_at.is_some_and(|n| calls.len() + 1 >= n) {
+                return Err(io::Error::new(io::ErrorKind::StorageFull, "disk full"));
+            }
+            calls.push(call);
+            Ok(())
+        }
+    }
+
+    impl TranscriptSink for MockSink {
+        fn name(&self) -> String {
+            "the mock".to_string()
+        }
+
+        fn on_event(&mut self, event: &TranscriptionEvent) -> io::Result<()> {
+            self.call(match event {
+                TranscriptionEvent::Partial(t) => format!("partial {}", t.text),
+                TranscriptionEvent::Final(t) => format!("final {}", t.text),
+                TranscriptionEvent::Finished => "finished".to_string(),
+            })
+        }
+
+        fn mark(&mut self, marker: &Marker) -> io::Result<()> {
+            self.call(format!("mark {}", marker.label))
+        }
+
+        fn finalize(&mut self, result: &[Transcription], _: &SessionInfo) -> io::Result<()> {
+            self.call(format!("finalize {}", result.len()))
+        }
+    }
+
+    /// A sink that fails from the `fail_at`th call on, with what it was fed.
+    fn mock(fail_at: Option<usize>) -> (MockSink, Rc<RefCell<Vec<String>>>) {
+        let calls = Rc::default();
+        let sink = MockSink {
+            calls: Rc::clone(&calls),
+            fail_at,
+        };
+        (sink, calls)
+    }
+
+    fn sentence(sentence_id: u64, text: &str, is_final: bool) -> Transcription {
+        Transcription {
+            sentence_id,
+            begin_time: sentence_id * 1_000,
+            end_time: sentence_id * 1_000 + 800,
+            text: text.to_string(),
+            is_final,
+            translated_text: None,
+            translations: vec![],
+            words: vec![],
+            confidence: None,
+            source_label: None,
+        }
+    }
+
+    fn session_info() -> SessionInfo {
+        SessionInfo {
+            started_at_ms: 1_700_000_000_000,
+            sample_rate: 16000,
+            source_language: None,
+            target_languages: vec![],
+            model: "gummy-realtime-v1".to_string(),
+            task_ids: vec![],
+            usage: Usage { audio_ms: 2_000 },
+            summary: None,
+        }
+    }
+
+    /// Feeds `sinks` a short session with a marker.
+    async fn feed(sinks: &mut Sinks) {
+        sinks
+            .on_event(&TranscriptionEvent::Partial(sentence(0, "Hello", false)))
+            .await;
+        sinks
+            .on_event(&TranscriptionEvent::Final(sentence(0, "Hello.", true)))
+            .await;
+        sinks.mark(&Marker {
+            label: "Marker 1".to_string(),
+            time: 1_000,
+            end: None,
+        });
+        sinks
+            .on_event(&TranscriptionEvent::Final(sentence(1, "Bye.", true)))
+            .await;
+        sinks.on_event(&TranscriptionEvent::Finished).await;
+    }
+
+    #[tokio::test]
+    async fn fans_events_out_to_every_sink() {
+        let mut sinks = Sinks::default();
+        let (first, first_calls) = mock(None);
+        let (second, second_calls) = mock(None);
+        sinks.push(first);
+        sinks.push(second);
+        feed(&mut sinks).await;
+        assert_eq!(sinks.sinks.len(), 2);
+        sinks
+            .finalize(&[sentence(1, "Bye.", true)], &session_info())
+            .await;
+        let expected = [
+            "partial Hello",
+            "final Hello.",
+            "mark Marker 1",
+            "final Bye.",
+            "finished",
+            "finalize 1",
+        ];
+        assert_eq!(*first_calls.borrow(), expected);
+        assert_eq!(*second_calls.borrow(), expected);
+    }
+
+    #[tokio::test]
+    async fn leaves_out_a_sink_once_it_fails() {
+        let mut sinks = Sinks::default();
+        let (healthy, healthy_calls) = mock(None);
+        let (failing, failing_calls) = mock(Some(2));
+        sinks.push(failing);
+        sinks.push(healthy);
+        feed(&mut sinks).await;
+        // Disabled at its first failure, and not fed again.
+        assert_eq!(sinks.sinks.len(), 1);
+        assert_eq!(*failing_calls.borrow(), ["partial Hello"]);
+        sinks.finalize(&[], &session_info()).await;
+        assert_eq!(*failing_calls.borrow(), ["partial Hello"]);
+        assert_eq!(healthy_calls.borrow().len(), 6);
+        assert_eq!(healthy_calls.borrow()[5], "finalize 0");
+    }
+
+    #[tokio::test]
+    async fn writes_the_transcript_on_the_side_of_a_failing_sink() {
+        let path = std::env::temp_dir().join(format!("st-sinks-{}.jsonl", std::process::id()));
+        let writer = TranscriptWriter::new(
+            TranscriptFormat::Jsonl,
+            SubtitleOptions {
+                translation: false,
+                max_cue_ms: 7_000,
+                silences: false,
+            },
+            Box::new(std::fs::File::create(&path).unwrap()) as Box<dyn Write>,
+        );
+        let mut sinks = Sinks::default();
+        sinks.push(mock(Some(1)).0);
+        sinks.push(TranscriptFile::new(Some(path.clone()), writer));
+        feed(&mut sinks).await;
+        assert_eq!(sinks.sinks.len(), 1);
+        sinks.finalize(&[], &session_info()).await;
+        let lines = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines = lines.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].contains("Marker 1"), "{}", lines[1]);
+    }
+
+    #[tokio::test]
+    async fn writes_a_file_per_target_language() {
+        let dir = std::env::temp_dir().join(format!("st-languages-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("talk.srt");
+        let file = |path: &PathBuf| {
+            let writer = TranscriptWriter::new(
+                TranscriptFormat::Srt,
+                SubtitleOptions {
+                    translation: false,
+                    max_cue_ms: 7_000,
+                    silences: false,
+                },
+                Box::new(std::fs::File::create(path).unwrap()) as Box<dyn Write>,
+            );
+            TranscriptFile::new(Some(path.clone()), writer)
+        };
+        let mut sinks = Sinks::default();
+        sinks.push(file(&path));
+        for lang in ["zh", "ja", "ko"] {
+            let path = output::track_path(&path, TRACK_TEMPLATE, lang);
+            sinks.push(file(&path).in_language(lang));
+        }
+        let translated = |sentence_id, text, translations: &[(&str, &str)]| Transcription {
+            translations: translations
+                .iter()
+                .map(|(lang, text)| Translation {
+                    lang: lang.to_string(),
+                    text: text.to_string(),
+                })
+                .collect(),
+            ..sentence(sentence_id, text, true)
+        };
+        let sentences = [
+            translated(
+                0,
+                "Hello.",
+                &[
+                    ("zh", "你好。"),
+                    ("ja", "こんにちは。"),
+                    ("ko", "안녕하세요."),
+                ],
+            ),
+            // Not translated into Korean.
+            translated(1, "Bye.", &[("zh", "再见。"), ("ja", "さようなら。")]),
+        ];
+        for sentence in &sentences {
+            sinks
+                .on_event(&TranscriptionEvent::Final(sentence.clone()))
+                .await;
+        }
+        assert_eq!(sinks.sinks.len(), 4);
+        sinks.finalize(&sentences, &session_info()).await;
+
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        let cue = |n, text| {
+            let begin = (n - 1) * 1_000;
+            format!(
+                "{}\n00:00:0{},000 --> 00:00:0{},800\n{}\n\n",
+                n,
+                begin / 1_000,
+                begin / 1_000,
+                text
+            )
+        };
+        assert_eq!(read("talk.srt"), cue(1, "Hello.") + &cue(2, "Bye."));
+        assert_eq!(read("talk.zh.srt"), cue(1, "你好。") + &cue(2, "再见。"));
+        assert_eq!(
+            read("talk.ja.srt"),
+            cue(1, "こんにちは。") + &cue(2, "さようなら。")
+        );
+        assert_eq!(read("talk.ko.srt"), cue(1, "안녕하세요."));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+//! What a session came to, printed as it ends and recorded with it: the audio it took,
+//! what came of it and an estimate of what it cost.
+
+use gummy::{TaskUsage, Transcription};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What a second of audio billed costs, from the config since prices change.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pricing {
+    pub per_second: f64,
+    pub currency: String,
+}
+
+/// A summary of a session.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    /// Audio captured, in milliseconds.
+    pub captured_ms: u64,
+    /// Audio billed, in milliseconds.
+    pub billed_ms: u64,
+    /// Whether `billed_ms` is worked out from the audio sent, the backend not reporting it.
+    pub estimated: bool,
+    /// Final sentences.
+    pub sentences: usize,
+    /// Translations of the final sentences, into every target language.
+    pub translations: usize,
+    /// What the billed audio costs, with a price configured.
+    pub cost: Option<Cost>,
+    /// Average latency of the final sentences, in milliseconds.
+    pub latency_ms: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Cost {
+    pub amount: f64,
+    pub currency: String,
+}
+
+/// Audio billed in milliseconds, and whether it's an estimate: what the backend reported
+/// in `usage`, or else the `sent_bytes` of audio at `bytes_per_second`, rounded up to the
+/// second as it's billed.
+pub fn billed_ms(usage: Option<TaskUsage>, sent_bytes: u64, bytes_per_second: u64) -> (u64, bool) {
+    match usage {
+        Some(usage) => (usage.duration * 1000, false),
+        None => (sent_bytes.div_ceil(bytes_per_second.max(1)) * 1000, true),
+    }
+}
+
+impl Summary {
+    /// Sums up a session that captured `captured_ms` of audio, was billed for what
+    /// `billed_ms` returned and ended with `result`.
+    pub fn new(
+        captured_ms: u64,
+        (billed_ms, estimated): (u64, bool),
+        result: &[Transcription],
+        pricing: Option<&Pricing>,
+        latency_ms: Option<u64>,
+    ) -> Self {
+        let translations = result
+            .iter()
+            .map(|t| {
+                t.translations
+                    .len()
+                    .max(t.translated_text.is_some() as usize)
+            })
+            .sum();
+        Summary {
+            captured_ms,
+            billed_ms,
+            estimated,
+            sentences: result.len(),
+            translations,
+            cost: pricing.map(|pricing| Cost {
+                amount: billed_ms as f64 / 1000.0 * pricing.per_second,
+                currency: pricing.currency.clone(),
+            }),
+            latency_ms,
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = |ms: u64| ms as f64 / 1000.0;
+        // Without a report from the backend, the billed audio is worked out from the audio
+        // sent.
+        let estimate = if self.estimated {
+            ", estimated from the audio sent"
+        } else {
+            ""
+        };
+        let cost = match &self.cost {
+            Some(cost) => format!("{:.4} {}{}", cost.amount, cost.currency, estimate),
+            None => "unknown, set price_per_second in the config".to_string(),
+        };
+        let latency = match self.latency_ms {
+            Some(latency_ms) => format!("{:.1} s on average", seconds(latency_ms)),
+            None => "no sentence came back final".to_string(),
+        };
+        let lines = [
+            (
+                "Audio captured",
+                format!("{:.1} s", seconds(self.captured_ms)),
+            ),
+            (
+                "Audio billed",
+                format!("{:.0} s{}", seconds(self.billed_ms), estimate),
+            ),
+            ("Sentences", self.sentences.to_string()),
+            ("Translations", self.translations.to_string()),
+            ("Estimated cost", cost),
+            ("Latency", latency),
+        ];
+        for (i, (name, value)) in lines.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{:<16}{}", format!("{}:", name), value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gummy::Translation;
+
+    fn sentence(translations: &[&str]) -> Transcription {
+        Transcription {
+            sentence_id: 0,
+            begin_time: 0,
+            end_time: 900,
+            text: "Hello.".to_string(),
+            is_final: true,
+            translated_text: translations.first().map(|text| text.to_string()),
+            translations: translations
+                .iter()
+                .map(|text| Translation {
+                    lang: "zh".to_string(),
+                    text: text.to_string(),
+                })
+                .collect(),
+            words: vec![],
+            confidence: None,
+            source_label: None,
+        }
+    }
+
+    fn pricing() -> Pricing {
+        Pricing {
+            per_second: 0.00015,
+            currency: "CNY".to_string(),
+        }
+    }
+
+    #[test]
+    fn bills_what_the_backend_reports() {
+        let usage = Some(TaskUsage { duration: 62 });
+        assert_eq!(billed_ms(usage, 32_000 * 60, 32_000), (62_000, false));
+    }
+
+    #[test]
+    fn estimates_the_billed_audio_from_the_audio_sent() {
+        // 60.5 s at 16 kHz, billed by the started second.
+        assert_eq!(
+            billed_ms(None, 32_000 * 60 + 16_000, 32_000),
+            (61_000, true)
+        );
+        assert_eq!(billed_ms(None, 0, 32_000), (0, true));
+    }
+
+    #[test]
+    fn prices_the_billed_audio() {
+        let result = [sentence(&["你好。", "こんにちは。"]), sentence(&[])];
+        let billed = billed_ms(None, 32_000 * 60, 32_000);
+        let summary = Summary::new(59_800, billed, &result, Some(&pricing()), Some(1_260));
+        assert_eq!(summary.billed_ms, 60_000);
+        assert!(summary.estimated);
+        assert_eq!(summary.sentences, 2);
+        assert_eq!(summary.translations, 2);
+        let cost = summary.cost.as_ref().unwrap();
+        assert!((cost.amount - 0.009).abs() < 1e-9, "{}", cost.amount);
+        assert_eq!(
+            summary.to_string(),
+            "Audio captured: 59.8 s\n\
+             Audio billed:   60 s, estimated from the audio sent\n\
+             Sentences:      2\n\
+             Translations:   2\n\
+             Estimated cost: 0.0090 CNY, estimated from the audio sent\n\
+             Latency:        1.3 s on average"
+        );
+
+        let summary = Summary::new(0, (3_000, false), &[], None, None);
+        assert_eq!(summary.cost, None);
+        assert!(summary.to_string().contains("Audio billed:   3 s\n"));
+    }
+}
@@ -0,0 +1,583 @@
+//! Keeps a transcription going when the connection to the backend drops: the audio is held
+//! while a new task is started, and the new task picks up where the old one was answered
+//! up to, its sentences numbered and timed on from those before. The audio held is sent to
+//! the new task paced, as `catch_up` tells.
+
+use crate::catch_up::Pacer;
+use crate::transcriber::{Transcriber, TranscriptionSession};
+use gummy::{GummyError, StartOptions, TaskUsage, Transcription, TranscriptionEvent};
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, sleep_until};
+
+/// Attempts at starting a new task once the connection is lost, before giving up.
+const MAX_ATTEMPTS: u32 = 5;
+/// Wait after the first failed attempt, doubled after each one.
+const BACKOFF: Duration = Duration::from_secs(1);
+/// Most audio held for a new task: what wasn't answered with a final sentence yet, and what
+/// comes in while reconnecting.
+const BACKLOG: Duration = Duration::from_secs(60);
+
+/// Whether a new task may get past `error`. Only errors of the Gummy client are known to be
+/// about the connection.
+pub fn is_retryable(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<GummyError>()
+        .is_some_and(GummyError::is_retryable)
+}
+
+type Connecting = JoinHandle<anyhow::Result<Box<dyn TranscriptionSession>>>;
+
+enum State {
+    Connected(Box<dyn TranscriptionSession>),
+    /// Waiting to make `attempt`, counting from 1.
+    Waiting {
+        attempt: u32,
+        at: Instant,
+    },
+    /// Starting a task, to send the backlog to.
+    Connecting {
+        attempt: u32,
+        task: Connecting,
+    },
+}
+
+/// A session of `Transcriber` that starts a new task when the connection is lost, as far
+/// as the errors tell it was, up to `MAX_ATTEMPTS` times in a row. Other errors are
+/// returned, to finish with what was transcribed.
+///
+/// Events and results are those of a single task running from the start: later tasks get
+/// sentence ids after the ones before and times from where their audio starts.
+pub struct SupervisedSession {
+    transcriber: Arc<dyn Transcriber>,
+    options: StartOptions,
+    state: State,
+    bytes_per_second: u64,
+    // Audio not answered by a final sentence, from `backlog_start` bytes into the session on.
+    backlog: Vec<u8>,
+    backlog_start: u64,
+    // Bytes of the backlog the running task has.
+    sent: usize,
+    // Times real time the backlog is sent at after reconnecting, paced by `catching_up`
+    // until the task has all of it.
+    catch_up_speed: f64,
+    catching_up: Option<Pacer>,
+    // Where the running task starts in the session, and its first sentence id.
+    offset_ms: u64,
+    first_id: u64,
+    next_id: u64,
+    // Final sentences of every task, for the result.
+    finals: BTreeMap<u64, Transcription>,
+    warned: bool,
+    // Whether a task was started again, leaving the usage of the ones before unknown.
+    restarted: bool,
+    // Where the first task started again starts in the session.
+    reconnected_at: Option<u64>,
+    // IDs of the tasks started, the running one last.
+    task_ids: Vec<String>,
+}
+
+impl SupervisedSession {
+    /// Supervises `session`, started by `transcriber` with `options` for 16-bit mono audio
+    /// at `sample_rate`. New tasks are sent the audio held at `catch_up_speed` times real
+    /// time.
+    pub fn new(
+        transcriber: Arc<dyn Transcriber>,
+        options: StartOptions,
+        session: Box<dyn TranscriptionSession>,
+        sample_rate: u32,
+        catch_up_speed: f64,
+    ) -> Self {
+        let task_ids = session.task_id().map(str::to_string).into_iter().collect();
+        SupervisedSession {
+            transcriber,
+            options,
+            state: State::Connected(session),
+            bytes_per_second: sample_rate as u64 * 2,
+            backlog: vec![],
+            backlog_start: 0,
+            sent: 0,
+            catch_up_speed,
+            catching_up: None,
+            offset_ms: 0,
+            first_id: 0,
+            next_id: 0,
+            finals: BTreeMap::new(),
+            warned: false,
+            restarted: false,
+            reconnected_at: None,
+            task_ids,
+        }
+    }
+
+    /// Audio sent that the connection didn't take yet, held while reconnecting or queued by
+    /// the task, in bytes.
+    pub fn queued_bytes(&self) -> usize {
+        let queued = match &self.state {
+            State::Connected(session) => session.queued_bytes(),
+            _ => 0,
+        };
+        self.backlog.len() - self.sent + queued
+    }
+
+    /// How long until a new task is sent the audio held for it, at the pace it's sent, while
+    /// it's catching up.
+    pub fn catch_up_remaining(&self) -> Option<Duration> {
+        let pacer = self.catching_up.as_ref()?;
+        Some(pacer.remaining(self.backlog.len() - self.sent))
+    }
+
+    /// IDs the backend gave the tasks started, one for each connection, the running one last.
+    pub fn task_ids(&self) -> &[String] {
+        &self.task_ids
+    }
+
+    /// Where the first task started again after the connection dropped starts in the
+    /// session, in milliseconds, `None` while the first task runs.
+    pub fn reconnected_at(&self) -> Option<u64> {
+        self.reconnected_at
+    }
+
+    /// Whether a task is running, rather than being started again.
+    pub fn is_connected(&self) -> bool {
+        matches!(self.state, State::Connected(_))
+    }
+
+    /// Sends `samples`, or holds on to them while reconnecting, with as much of the audio
+    /// held as a new task may take by now. Fails on errors that aren't retried.
+    pub async fn send_samples(&mut self, samples: &[i16]) -> anyhow::Result<()> {
+        // Encoded straight into the audio held, whose buffer is reused as it's sent.
+        gummy::extend_le_bytes(&mut self.backlog, samples);
+        let limit = (BACKLOG.as_secs() * self.bytes_per_second) as usize;
+        if self.backlog.len() > limit {
+            let mut excess = self.backlog.len() - limit;
+            // A task catching up can't skip audio it wasn't sent yet.
+            if self.catching_up.is_some() {
+                excess = excess.min(self.sent);
+            }
+            if excess > self.sent && !self.warned {
+                warn!(
+                    "Audio was lost while reconnecting, {} s are held at most",
+                    BACKLOG.as_secs()
+                );
+                self.warned = true;
+            }
+            self.drop_front(excess);
+        }
+        self.flush().await
+    }
+
+    /// Returns the next event, reconnecting as needed. Cancelling it leaves the session as
+    /// it was, so it can be raced against other work.
+    pub async fn next_event(&mut self) -> anyhow::Result<Option<TranscriptionEvent>> {
+        loop {
+            if let State::Connected(session) = &mut self.state {
+                match session.next_event().await {
+                    Ok(Some(event)) => return Ok(Some(self.place(event))),
+                    Ok(None) => return Ok(None),
+                    Err(e) => self.lost(e)?,
+                }
+            } else {
+                self.reconnect().await?;
+            }
+        }
+    }
+
+    /// Finishes the running task, after starting it again if the connection is lost, and
+    /// returns the final sentences of every task. What the session is billed for is only
+    /// known when it ran as a single task, as tasks that drop never report it.
+    pub async fn finish(mut self) -> anyhow::Result<(Vec<Transcription>, Option<TaskUsage>)> {
+        loop {
+            if !self.is_connected() {
+                self.reconnect().await?;
+                continue;
+            }
+            match &self.catching_up {
+                Some(pacer) => sleep_until(pacer.next_at()).await,
+                None => break,
+            }
+            self.flush().await?;
+        }
+        self.flush().await?;
+        let State::Connected(session) = self.state else {
+            anyhow::bail!("the connection was lost while finishing");
+        };
+        let (result, usage) = session.finish_with_usage().await?;
+        let mut sentences: Vec<_> = self
+            .finals
+            .range(..self.first_id)
+            .map(|(_, t)| t.clone())
+            .collect();
+        for transcription in result {
+            sentences.push(place(transcription, self.first_id, self.offset_ms));
+        }
+        Ok((sentences, usage.filter(|_| !self.restarted)))
+    }
+
+    /// Sends the running task the backlog it doesn't have yet, as much as it may take by now
+    /// while catching up.
+    async fn flush(&mut self) -> anyhow::Result<()> {
+        let State::Connected(session) = &mut self.state else {
+            return Ok(());
+        };
+        let unsent = self.backlog.len() - self.sent;
+        let bytes = match &mut self.catching_up {
+            Some(pacer) => pacer.allowed(Instant::now()).min(unsent),
+            None => unsent,
+        };
+        if bytes > 0 {
+            let end = self.sent + bytes;
+            match session.send_audio(&self.backlog[self.sent..end]).await {
+                Ok(()) => self.sent = end,
+                Err(e) => return self.lost(e),
+            }
+            if let Some(pacer) = &mut self.catching_up {
+                pacer.spent(bytes);
+            }
+        }
+        if self.sent == self.backlog.len() && self.catching_up.take().is_some() {
+            info!("Caught up with the audio held while reconnecting");
+        }
+        Ok(())
+    }
+
+    /// Takes in a failure of the running task, returning it unless it's retried.
+    fn lost(&mut self, error: anyhow::Error) -> anyhow::Result<()> {
+        if !is_retryable(&error) {
+            return Err(error);
+        }
+        warn!("Connection lost, reconnecting: {}", error);
+        self.state = State::Waiting {
+            attempt: 1,
+            at: Instant::now(),
+        };
+        self.catching_up = None;
+        Ok(())
+    }
+
+    /// Moves reconnecting on by a step: starts an attempt once it's time, or waits for the
+    /// one under way. Fails once the attempts run out.
+    async fn reconnect(&mut self) -> anyhow::Result<()> {
+        match &mut self.state {
+            State::Connected(_) => {}
+            State::Waiting { attempt, at } => {
+                sleep_until(*at).await;
+                let attempt = *attempt;
+                info!("Reconnecting, attempt {} of {}", attempt, MAX_ATTEMPTS);
+                let transcriber = self.transcriber.clone();
+                let options = self.options.clone();
+                // Started apart, so an attempt isn't abandoned when waiting for it is.
+                let task = tokio::spawn(async move { transcriber.start(options).await });
+                self.state = State::Connecting { attempt, task };
+            }
+            State::Connecting { attempt, task } => {
+                let attempt = *attempt;
+                match task.await.map_err(anyhow::Error::from).and_then(|r| r) {
+                    Ok(session) => {
+                        match session.task_id() {
+                            Some(task_id) => {
+                                info!("Reconnected, task {}", task_id);
+                                self.task_ids.push(task_id.to_string());
+                            }
+                            None => info!("Reconnected"),
+                        }
+                        self.state = State::Connected(session);
+                        // The new task is sent the whole backlog, starting where it starts.
+                        self.sent = 0;
+                        self.offset_ms = self.backlog_start * 1000 / self.bytes_per_second;
+                        self.first_id = self.next_id;
+                        self.restarted = true;
+                        self.reconnected_at.get_or_insert(self.offset_ms);
+                        self.catching_up = Some(Pacer::new(
+                            self.catch_up_speed,
+                            self.bytes_per_second,
+                            Instant::now(),
+                        ));
+                        self.flush().await?;
+                    }
+                    Err(e) if is_retryable(&e) && attempt < MAX_ATTEMPTS => {
+                        let backoff = BACKOFF * 2u32.pow(attempt - 1);
+                        warn!(
+                            "Failed to reconnect, trying again in {} s: {}",
+                            backoff.as_secs(),
+                            e
+                        );
+                        self.state = State::Waiting {
+                            attempt: attempt + 1,
+                            at: Instant::now() + backoff,
+                        };
+                    }
+                    Err(e) => return Err(e.context("failed to reconnect")),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Places an event of the running task in the session, and lets go of the audio a
+    /// final sentence answers.
+    fn place(&mut self, event: TranscriptionEvent) -> TranscriptionEvent {
+        let event = match event {
+            TranscriptionEvent::Partial(t) => {
+                TranscriptionEvent::Partial(place(t, self.first_id, self.offset_ms))
+            }
+            TranscriptionEvent::Final(t) => {
+                let t = place(t, self.first_id, self.offset_ms);
+                let answered = t.end_time * self.bytes_per_second / 1000 / 2 * 2;
+                let answered = answered.saturating_sub(self.backlog_start) as usize;
+                self.drop_front(answered.min(self.sent));
+                self.finals.insert(t.sentence_id, t.clone());
+                TranscriptionEvent::Final(t)
+            }
+            TranscriptionEvent::Finished => return TranscriptionEvent::Finished,
+        };
+        if let TranscriptionEvent::Partial(t) | TranscriptionEvent::Final(t) = &event {
+            self.next_id = self.next_id.max(t.sentence_id + 1);
+        }
+        event
+    }
+
+    /// Lets go of the first `bytes` of the backlog.
+    fn drop_front(&mut self, bytes: usize) {
+        self.backlog.drain(..bytes);
+        self.backlog_start += bytes as u64;
+        self.sent = self.sent.saturating_sub(bytes);
+    }
+}
+
+/// `transcription` of a task whose sentence ids start at `first_id` and whose times start
+/// `offset_ms` into the session.
+fn place(mut transcription: Transcription, first_id: u64, offset_ms: u64) -> Transcription {
+    transcription.sentence_id += first_id;
+    transcription.begin_time += offset_ms;
+    transcription.end_time += offset_ms;
+    for word in &mut transcription.words {
+        word.begin_time += offset_ms;
+        word.end_time += offset_ms;
+    }
+    transcription
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    /// 100 ms of audio at 16 kHz.
+    const CHUNK: usize = 3200;
+
+    /// Starts sessions answering each chunk of audio with a final sentence holding its first
+    /// byte, failing as told by `failures`, one per session started.
+    struct FlakyTranscriber {
+        failures: Mutex<VecDeque<Failure>>,
+    }
+
+    #[derive(Clone, Copy)]
+    enum Failure {
+        /// The session starts fine.
+        None,
+        /// The session can't be started, as when the server is down.
+        Start,
+        /// The connection drops when the session is sent this chunk.
+        DropAt(usize),
+        /// The backend rejects this chunk, which a new task wouldn't take either.
+        RejectAt(usize),
+    }
+
+    struct FlakySession {
+        failure: Failure,
+        chunks: usize,
+        pending: VecDeque<TranscriptionEvent>,
+        result: Vec<Transcription>,
+    }
+
+    fn transcriber(failures: &[Failure]) -> Arc<dyn Transcriber> {
+        Arc::new(FlakyTranscriber {
+            failures: Mutex::new(failures.iter().copied().collect()),
+        })
+    }
+
+    #[async_trait]
+    impl Transcriber for FlakyTranscriber {
+        fn model(&self) -> String {
+            "flaky".to_string()
+        }
+
+        async fn start(
+            &self,
+            _options: StartOptions,
+        ) -> anyhow::Result<Box<dyn TranscriptionSession>> {
+            let failure = self
+                .failures
+                .lock()
+                .unwrap()
+                .pop_front()
+                .unwrap_or(Failure::None);
+            if let Failure::Start = failure {
+                return Err(GummyError::ConnectionClosed.into());
+            }
+            Ok(Box::new(FlakySession {
+                failure,
+                chunks: 0,
+                pending: VecDeque::new(),
+                result: vec![],
+            }))
+        }
+    }
+
+    #[async_trait]
+    impl TranscriptionSession for FlakySession {
+        async fn send_audio(&mut self, data: &[u8]) -> anyhow::Result<()> {
+            for chunk in data.chunks(CHUNK) {
+                match self.failure {
+                    Failure::DropAt(at) if at == self.chunks => {
+                        return Err(GummyError::ConnectionClosed.into());
+                    }
+                    Failure::RejectAt(at) if at == self.chunks => {
+                        anyhow::bail!("unsupported audio");
+                    }
+                    _ => {}
+                }
+                let id = self.chunks as u64;
+                let transcription = Transcription {
+                    sentence_id: id,
+                    begin_time: id * 100,
+                    end_time: id * 100 + 100,
+                    text: chunk[0].to_string(),
+                    is_final: true,
+                    translated_text: None,
+                    translations: vec![],
+                    words: vec![],
+                    confidence: None,
+                    source_label: None,
+                };
+                self.result.push(transcription.clone());
+                self.pending
+                    .push_back(TranscriptionEvent::Final(transcription));
+                self.chunks += 1;
+            }
+            Ok(())
+        }
+
+        async fn next_event(&mut self) -> anyhow::Result<Option<TranscriptionEvent>> {
+            Ok(self.pending.pop_front())
+        }
+
+        async fn finish(self: Box<Self>) -> anyhow::Result<Vec<Transcription>> {
+            Ok(self.result)
+        }
+    }
+
+    async fn supervise(transcriber: Arc<dyn Transcriber>) -> SupervisedSession {
+        let session = transcriber.start(StartOptions::default()).await.unwrap();
+        SupervisedSession::new(transcriber, StartOptions::default(), session, 16000, 3.0)
+    }
+
+    /// Samples making up `CHUNK` bytes of `value` each.
+    fn chunk(value: i16) -> Vec<i16> {
+        vec![value * 0x0101; CHUNK / 2]
+    }
+
+    fn sentences(events: &[TranscriptionEvent]) -> Vec<(u64, u64, String)> {
+        events
+            .iter()
+            .map(|event| match event {
+                TranscriptionEvent::Final(t) => (t.sentence_id, t.begin_time, t.text.clone()),
+                event => panic!("unexpected event {:?}", event),
+            })
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn continues_across_reconnections() {
+        // The connection drops at the third chunk, and the server takes two attempts to
+        // come back.
+        let transcriber = transcriber(&[Failure::DropAt(2), Failure::Start]);
+        let mut session = supervise(transcriber).await;
+        let mut events = vec![];
+        for value in 0..2 {
+            session.send_samples(&chunk(value)).await.unwrap();
+        }
+        events.push(session.next_event().await.unwrap().unwrap());
+        assert_eq!(session.reconnected_at(), None);
+        for value in 2..4 {
+            session.send_samples(&chunk(value)).await.unwrap();
+            assert!(!session.is_connected());
+        }
+        // The second chunk was sent but not answered when the connection dropped, so the
+        // new task gets it again, with what came in while reconnecting.
+        for _ in 0..3 {
+            events.push(session.next_event().await.unwrap().unwrap());
+        }
+        assert!(session.is_connected());
+        assert_eq!(session.reconnected_at(), Some(100));
+        session.send_samples(&chunk(4)).await.unwrap();
+        events.push(session.next_event().await.unwrap().unwrap());
+        assert_eq!(
+            sentences(&events),
+            [
+                (0, 0, "0".to_string()),
+                (1, 100, "1".to_string()),
+                (2, 200, "2".to_string()),
+                (3, 300, "3".to_string()),
+                (4, 400, "4".to_string()),
+            ]
+        );
+        let (result, _) = session.finish().await.unwrap();
+        let texts: Vec<_> = result.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(texts, ["0", "1", "2", "3", "4"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn paces_the_audio_held_for_a_new_task() {
+        let mut session = supervise(transcriber(&[Failure::DropAt(0)])).await;
+        // 3 s held while the connection is down.
+        for value in 0..30 {
+            session.send_samples(&chunk(value)).await.unwrap();
+        }
+        assert_eq!(session.catch_up_remaining(), None);
+        // A second of it goes out at once, then 300 ms for each 100 ms captured.
+        session.next_event().await.unwrap().unwrap();
+        assert!(session.is_connected());
+        assert_eq!(session.queued_bytes(), 20 * CHUNK);
+        assert_eq!(session.catch_up_remaining(), Some(Duration::from_secs(1)));
+        tokio::time::advance(Duration::from_millis(100)).await;
+        session.send_samples(&chunk(30)).await.unwrap();
+        assert_eq!(session.queued_bytes(), 18 * CHUNK);
+
+        // Finishing waits for the rest at the same pace.
+        let started = Instant::now();
+        let (result, _) = session.finish().await.unwrap();
+        let elapsed = started.elapsed().as_millis();
+        assert!((600..620).contains(&elapsed), "{}", elapsed);
+        let texts: Vec<_> = result.iter().take(13).map(|t| t.text.clone()).collect();
+        assert_eq!(texts, (0..13).map(|n| n.to_string()).collect::<Vec<_>>());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_on_errors_that_would_happen_again() {
+        let mut session = supervise(transcriber(&[Failure::RejectAt(1)])).await;
+        session.send_samples(&chunk(0)).await.unwrap();
+        assert!(session.send_samples(&chunk(1)).await.is_err());
+
+        // A server that doesn't come back is tried `MAX_ATTEMPTS` times.
+        let mut failures = vec![Failure::DropAt(0)];
+        failures.extend([Failure::Start; MAX_ATTEMPTS as usize]);
+        let mut session = supervise(transcriber(&failures)).await;
+        session.send_samples(&chunk(0)).await.unwrap();
+        let started = Instant::now();
+        let error = session.next_event().await.unwrap_err();
+        assert!(
+            error.to_string().contains("failed to reconnect"),
+            "{}",
+            error
+        );
+        // 1 + 2 + 4 + 8 s between the attempts.
+        assert_eq!(started.elapsed().as_secs(), 15);
+    }
+}
@@ -0,0 +1,99 @@
+//! `--save-audio`: a copy of the audio sent for transcription.
+
+use audio::recorder::SampleData;
+use audio::sink::{AudioSink, SinkResult};
+use log::error;
+use std::path::PathBuf;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use tokio::task::{JoinHandle, spawn_blocking};
+
+/// Writes frames to a sink on a blocking task of its own, so a slow disk or an encoder never
+/// holds up sending.
+pub struct AudioTee {
+    tx: UnboundedSender<SampleData>,
+    /// Buffers of the frames written, handed back to be filled again.
+    spent: UnboundedReceiver<Vec<i16>>,
+    writer: JoinHandle<SinkResult<Saved>>,
+}
+
+/// What an `AudioTee` wrote.
+pub struct Saved {
+    pub samples: usize,
+    pub files: Vec<PathBuf>,
+}
+
+impl AudioTee {
+    /// Starts writing frames to `sink`, which takes them in the format they come in.
+    pub fn start(mut sink: Box<dyn AudioSink>) -> Self {
+        let (tx, mut rx) = unbounded_channel::<SampleData>();
+        let (spent_tx, spent) = unbounded_channel();
+        let writer = spawn_blocking(move || {
+            let mut samples = 0;
+            while let Some(frame) = rx.blocking_recv() {
+                if let Err(e) = sink.write(&frame) {
+                    // Said right away rather than at exit, hours of audio may be at stake.
+                    error!("Failed to save audio, no more is saved: {}", e);
+                    return Err(e);
+                }
+                samples += frame.data.len();
+                let _ = spent_tx.send(frame.data);
+            }
+            let files = sink.files();
+            sink.finalize()?;
+            Ok(Saved { samples, files })
+        });
+        AudioTee { tx, spent, writer }
+    }
+
+    /// Queues `samples`, captured at `timestamp`, for writing, copied into the buffer of a
+    /// frame already written when there is one. Frames after a write error are dropped; the
+    /// error is returned by `finish`.
+    pub fn write(&mut self, samples: &[i16], timestamp: u64) {
+        let mut data = self.spent.try_recv().unwrap_or_default();
+        data.clear();
+        data.extend_from_slice(samples);
+        let _ = self.tx.send(SampleData { data, timestamp });
+    }
+
+    /// Writes what is still queued and completes the output.
+    pub async fn finish(self) -> SinkResult<Saved> {
+        drop(self.tx);
+        self.writer.await.expect("Audio writer panicked")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use audio::source::{SampleSource, SineSource};
+    use audio::wav::{RotatingWav, RotationConfig, WavReader};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn saves_what_the_source_produced() {
+        let path = std::env::temp_dir().join(format!("st-tee-{}.wav", std::process::id()));
+        let rotation = RotationConfig {
+            template: path.to_string_lossy().into_owned(),
+            max_duration: None,
+            max_bytes: None,
+        };
+        let mut source = SineSource::new(16000, 440.0, 0.5, Duration::from_millis(1250));
+        let wav = RotatingWav::new(rotation, &source.output_format());
+        let mut tee = AudioTee::start(Box::new(wav));
+        let mut sent = 0;
+        while let Some(frame) = source.next_frame().await {
+            tee.write(&frame.data, frame.timestamp);
+            sent += frame.data.len();
+        }
+        let saved = tee.finish().await.unwrap();
+        assert_eq!(saved.samples, sent);
+        assert_eq!(saved.files.as_slice(), std::slice::from_ref(&path));
+
+        let reader = WavReader::open(&path).unwrap();
+        assert_eq!(reader.format().sample_rate, 16000);
+        let saved = reader.map(|frame| frame.unwrap().data.len()).sum::<usize>();
+        std::fs::remove_file(&path).unwrap();
+        // 1.25 seconds, give or take a 100 ms frame.
+        assert!((saved as i64 - 20000).abs() <= 1600, "{} samples", saved);
+    }
+}
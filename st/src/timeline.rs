@@ -0,0 +1,348 @@
+//! `--clock`: the times of transcripts on the clock of the task, the wall clock, or the
+//! audio saved, by lining the audio sent up with when it was captured and where it went
+//! in `--save-audio`.
+//!
+//! Times of sentences run with the audio sent, from the start of the session. The audio
+//! of a pause was never captured, so the wall clock jumps ahead where the capture resumes,
+//! and the silence sent meanwhile to keep the connection open isn't in the saved audio.
+//!
+//! JSON transcripts also record when each sentence was spoken by the wall clock, whatever
+//! the clock of their times.
+
+use crate::output::{Marker, TranscriptFormat, WallClock};
+use chrono::{Local, SecondsFormat, TimeZone, Utc};
+use clap::ValueEnum;
+use gummy::Transcription;
+use std::sync::{Arc, Mutex};
+
+/// Difference between the capture clock and the audio sent that is put down to jitter,
+/// rather than to a gap in the audio.
+const JITTER_MS: u64 = 50;
+
+/// The clock the times of transcripts are on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Clock {
+    /// Time into the audio sent for transcription.
+    #[default]
+    Task,
+    /// Time of day the audio was captured, or milliseconds since the Unix epoch in JSON.
+    Wall,
+    /// Time into the audio of `--save-audio`.
+    Audio,
+}
+
+impl Clock {
+    /// `ms` into the audio sent, on this clock for a transcript in `format`. Times the
+    /// timeline can't place, as before any audio was sent, stay as they are.
+    pub fn convert(self, timeline: &Timeline, ms: u64, format: TranscriptFormat) -> u64 {
+        let converted = match self {
+            Clock::Task => None,
+            Clock::Wall => match format {
+                TranscriptFormat::Json | TranscriptFormat::Jsonl => timeline.to_wall_clock(ms),
+                TranscriptFormat::Txt | TranscriptFormat::Srt | TranscriptFormat::Vtt => {
+                    timeline.to_time_of_day(ms)
+                }
+            },
+            Clock::Audio => timeline.to_audio_offset(ms),
+        };
+        converted.unwrap_or(ms)
+    }
+
+    /// `transcription` with its times and those of its words on this clock.
+    pub fn shift(
+        self,
+        timeline: &Timeline,
+        transcription: &Transcription,
+        format: TranscriptFormat,
+    ) -> Transcription {
+        let convert = |ms| self.convert(timeline, ms, format);
+        let mut shifted = transcription.clone();
+        shifted.begin_time = convert(shifted.begin_time);
+        shifted.end_time = convert(shifted.end_time);
+        for word in &mut shifted.words {
+            word.begin_time = convert(word.begin_time);
+            word.end_time = convert(word.end_time);
+        }
+        shifted
+    }
+
+    /// `marker` at its time on this clock.
+    pub fn shift_marker(
+        self,
+        timeline: &Timeline,
+        marker: &Marker,
+        format: TranscriptFormat,
+    ) -> Marker {
+        Marker {
+            time: self.convert(timeline, marker.time, format),
+            end: marker.end.map(|end| self.convert(timeline, end, format)),
+            ..marker.clone()
+        }
+    }
+}
+
+/// A stretch of audio sent that runs in real time from where it starts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Segment {
+    /// Where it starts in the audio sent, in milliseconds.
+    sent_ms: u64,
+    /// When its start was captured, in milliseconds since the Unix epoch.
+    captured_ms: u64,
+    /// Where it starts in the audio saved, in milliseconds.
+    saved_ms: u64,
+    /// Whether it's in the audio saved, unlike silence sent to keep the connection open.
+    saved: bool,
+}
+
+impl Segment {
+    /// Where `sent_ms`, at or past the start, is in the audio saved.
+    fn saved_at(&self, sent_ms: u64) -> u64 {
+        match self.saved {
+            true => self.saved_ms + sent_ms.saturating_sub(self.sent_ms),
+            false => self.saved_ms,
+        }
+    }
+}
+
+/// How the audio sent lines up with the wall clock and the audio saved, shared between the
+/// session feeding it and the transcripts reading it.
+#[derive(Clone, Default)]
+pub struct Timeline {
+    segments: Arc<Mutex<Vec<Segment>>>,
+    // Where the audio of the first task started again after the connection dropped starts.
+    reconnected_at: Arc<Mutex<Option<u64>>>,
+}
+
+impl Timeline {
+    /// Takes in audio sent from `sent_ms` into the session on, captured at `captured_ms`,
+    /// and `saved` along with it unless it's silence sent to keep the connection open.
+    pub fn sent(&self, sent_ms: u64, captured_ms: u64, saved: bool) {
+        let mut segments = self.segments.lock().unwrap();
+        let saved_ms = match segments.last() {
+            Some(last) => {
+                let in_line = last.saved == saved
+                    && (last.captured_ms + sent_ms)
+                        .saturating_sub(last.sent_ms)
+                        .abs_diff(captured_ms)
+                        <= JITTER_MS;
+                if in_line {
+                    return;
+                }
+                last.saved_at(sent_ms)
+            }
+            // The audio saved starts with the first audio sent, pre-roll and all.
+            None => 0,
+        };
+        segments.push(Segment {
+            sent_ms,
+            captured_ms,
+            saved_ms,
+            saved,
+        });
+    }
+
+    /// Takes in that the connection dropped and a new task was sent the audio from `sent_ms`
+    /// into the session on. The times of its sentences are placed in the session by the
+    /// audio sent before rather than by the task, so their wall clock times are estimates.
+    /// The segments carry over, the audio held meanwhile being stamped with when it was
+    /// captured.
+    pub fn reconnected(&self, sent_ms: u64) {
+        self.reconnected_at.lock().unwrap().get_or_insert(sent_ms);
+    }
+
+    /// The segment `sent_ms` falls in, the first for times before it.
+    fn segment(&self, sent_ms: u64) -> Option<Segment> {
+        let segments = self.segments.lock().unwrap();
+        let index = segments.partition_point(|segment| segment.sent_ms <= sent_ms);
+        segments[..index].last().or(segments.first()).copied()
+    }
+
+    /// When the audio `sent_ms` into the session was captured, in milliseconds since the
+    /// Unix epoch, `None` before any audio was sent.
+    pub fn to_wall_clock(&self, sent_ms: u64) -> Option<u64> {
+        let segment = self.segment(sent_ms)?;
+        Some((segment.captured_ms + sent_ms).saturating_sub(segment.sent_ms))
+    }
+
+    /// When the audio from `begin_ms` to `end_ms` into the session was spoken, `None` before
+    /// any audio was sent.
+    pub fn wall_clock(&self, begin_ms: u64, end_ms: u64) -> Option<WallClock> {
+        let at = |sent_ms| {
+            let ms = self.to_wall_clock(sent_ms)?;
+            let time = Utc.timestamp_millis_opt(ms as i64).single()?;
+            Some(time.to_rfc3339_opts(SecondsFormat::Millis, true))
+        };
+        let reconnected_at = *self.reconnected_at.lock().unwrap();
+        Some(WallClock {
+            begin_at: at(begin_ms)?,
+            end_at: at(end_ms)?,
+            estimated: reconnected_at.is_some_and(|sent_ms| end_ms > sent_ms),
+        })
+    }
+
+    /// Where the audio `sent_ms` into the session is in the audio saved, in milliseconds.
+    /// Silence sent while the capture was paused or quiet isn't saved, so times in it fall
+    /// where the audio saved picks up again.
+    pub fn to_audio_offset(&self, sent_ms: u64) -> Option<u64> {
+        Some(self.segment(sent_ms)?.saved_at(sent_ms))
+    }
+
+    /// The time of day the audio `sent_ms` into the session was captured, in milliseconds
+    /// since the local midnight of the day the session started, so sessions running past
+    /// midnight go on counting.
+    pub fn to_time_of_day(&self, sent_ms: u64) -> Option<u64> {
+        let started_ms = self.segments.lock().unwrap().first()?.captured_ms;
+        let started = Local.timestamp_millis_opt(started_ms as i64).single()?;
+        let midnight = started
+            .date_naive()
+            .and_hms_opt(0, 0, 0)?
+            .and_local_timezone(Local)
+            .earliest()?;
+        let captured_ms = self.to_wall_clock(sent_ms)?;
+        Some(captured_ms.saturating_sub(midnight.timestamp_millis() as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Timelike;
+
+    const START: u64 = 1_700_000_000_000;
+
+    /// Sends 100 ms frames captured in real time from `captured_ms`, saved with them,
+    /// from `sent_ms` for `frames` frames. Returns where the audio sent got to.
+    fn capture(timeline: &Timeline, sent_ms: u64, captured_ms: u64, frames: u64) -> u64 {
+        for frame in 0..frames {
+            timeline.sent(sent_ms + frame * 100, captured_ms + frame * 100, true);
+        }
+        sent_ms + frames * 100
+    }
+
+    /// Sends a 100 ms frame of silence every second from `sent_ms`, for `seconds`.
+    fn keepalive(timeline: &Timeline, sent_ms: u64, captured_ms: u64, seconds: u64) -> u64 {
+        for second in 0..seconds {
+            timeline.sent(sent_ms + second * 100, captured_ms + second * 1_000, false);
+        }
+        sent_ms + seconds * 100
+    }
+
+    #[test]
+    fn lines_up_a_steady_capture() {
+        let timeline = Timeline::default();
+        assert_eq!(timeline.to_wall_clock(1_000), None);
+        assert_eq!(timeline.to_audio_offset(1_000), None);
+        // A second of pre-roll, captured before the session started, and a minute after.
+        capture(&timeline, 0, START, 610);
+        assert_eq!(timeline.segments.lock().unwrap().len(), 1);
+        assert_eq!(timeline.to_wall_clock(0), Some(START));
+        assert_eq!(timeline.to_wall_clock(30_250), Some(START + 30_250));
+        assert_eq!(timeline.to_audio_offset(30_250), Some(30_250));
+        // Audio still queued runs on.
+        assert_eq!(timeline.to_wall_clock(70_000), Some(START + 70_000));
+    }
+
+    #[test]
+    fn accounts_for_pauses_and_silence_kept_out_of_the_saved_audio() {
+        let timeline = Timeline::default();
+        // 10 s captured, then paused for 20 s with keepalives sent every second, then
+        // 10 s more captured.
+        let sent_ms = capture(&timeline, 0, START, 100);
+        let sent_ms = keepalive(&timeline, sent_ms, START + 10_000, 20);
+        assert_eq!(sent_ms, 12_000);
+        capture(&timeline, sent_ms, START + 30_000, 100);
+
+        // Before the pause, nothing moved.
+        assert_eq!(timeline.to_wall_clock(5_000), Some(START + 5_000));
+        assert_eq!(timeline.to_audio_offset(5_000), Some(5_000));
+        // In the silence sent, the wall clock runs a second per keepalive while the audio
+        // saved stands still.
+        assert_eq!(timeline.to_wall_clock(10_550), Some(START + 15_050));
+        assert_eq!(timeline.to_audio_offset(10_550), Some(10_000));
+        // After the pause, the wall clock is 20 s ahead of the audio saved, which is 2 s
+        // behind the audio sent.
+        assert_eq!(timeline.to_wall_clock(17_000), Some(START + 35_000));
+        assert_eq!(timeline.to_audio_offset(17_000), Some(15_000));
+    }
+
+    #[test]
+    fn keeps_the_times_of_audio_held_while_reconnecting() {
+        let timeline = Timeline::default();
+        let sent_ms = capture(&timeline, 0, START, 50);
+        // The connection drops; the audio captured meanwhile is held and sent all at once
+        // when it's back, stamped with when it was captured, so the times run on as if it
+        // had never dropped.
+        let sent_ms = capture(&timeline, sent_ms, START + 5_000, 80);
+        assert_eq!(timeline.segments.lock().unwrap().len(), 1);
+        // A frame lost in the capture on the way moves the wall clock ahead, but not the
+        // audio saved, which has no gap either.
+        capture(&timeline, sent_ms, START + 13_100, 10);
+        assert_eq!(timeline.to_wall_clock(12_000), Some(START + 12_000));
+        assert_eq!(timeline.to_wall_clock(13_500), Some(START + 13_600));
+        assert_eq!(timeline.to_audio_offset(13_500), Some(13_500));
+        // Jitter in the timestamps of the capture doesn't.
+        let timeline = Timeline::default();
+        timeline.sent(0, START, true);
+        timeline.sent(100, START + 130, true);
+        timeline.sent(200, START + 190, true);
+        assert_eq!(timeline.segments.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn tells_the_wall_clock_across_a_reconnection() {
+        let timeline = Timeline::default();
+        assert_eq!(timeline.wall_clock(0, 1_000), None);
+        let sent_ms = capture(&timeline, 0, START, 50);
+        let before = timeline.wall_clock(1_200, 3_450).unwrap();
+        assert_eq!(before.begin_at, "2023-11-14T22:13:21.200Z");
+        assert_eq!(before.end_at, "2023-11-14T22:13:23.450Z");
+        assert!(!before.estimated);
+        // The new task is sent the audio from 4 s on, held while reconnecting.
+        timeline.reconnected(4_000);
+        let sent_ms = capture(&timeline, sent_ms, START + 5_000, 80);
+        timeline.reconnected(9_000);
+        capture(&timeline, sent_ms, START + 13_000, 10);
+        // Sentences before stay where they were, and those after run on from them, as
+        // estimates.
+        assert_eq!(timeline.wall_clock(1_200, 3_450), Some(before));
+        let after = timeline.wall_clock(3_900, 12_500).unwrap();
+        assert_eq!(after.begin_at, "2023-11-14T22:13:23.900Z");
+        assert_eq!(after.end_at, "2023-11-14T22:13:32.500Z");
+        assert!(after.estimated);
+    }
+
+    #[test]
+    fn converts_transcripts_to_a_clock() {
+        let timeline = Timeline::default();
+        let started = Local.with_ymd_and_hms(2024, 6, 1, 14, 3, 22).unwrap();
+        let started_ms = started.timestamp_millis() as u64;
+        let sent_ms = capture(&timeline, 0, started_ms, 20);
+        keepalive(&timeline, sent_ms, started_ms + 2_000, 5);
+        capture(&timeline, 2_500, started_ms + 7_000, 20);
+        let sentence = Transcription {
+            sentence_id: 0,
+            begin_time: 1_500,
+            end_time: 3_000,
+            text: "Hello.".to_string(),
+            is_final: true,
+            translated_text: None,
+            translations: vec![],
+            words: vec![],
+            confidence: None,
+            source_label: None,
+        };
+        let times = |clock: Clock, format| {
+            let shifted = clock.shift(&timeline, &sentence, format);
+            (shifted.begin_time, shifted.end_time)
+        };
+        assert_eq!(times(Clock::Task, TranscriptFormat::Srt), (1_500, 3_000));
+        assert_eq!(times(Clock::Audio, TranscriptFormat::Srt), (1_500, 2_500));
+        assert_eq!(
+            times(Clock::Wall, TranscriptFormat::Json),
+            (started_ms + 1_500, started_ms + 7_500)
+        );
+        let (begin, _) = times(Clock::Wall, TranscriptFormat::Srt);
+        let time_of_day = started.num_seconds_from_midnight() as u64;
+        assert_eq!(begin, time_of_day * 1_000 + 1_500);
+    }
+}
@@ -0,0 +1,199 @@
+use async_trait::async_trait;
+use gummy::{
+    ConnectOptions, Converting, Gummy, MODEL, StartOptions, TaskUsage, Transcription,
+    TranscriptionEvent,
+};
+
+/// A speech-to-text backend that can start streaming transcription sessions.
+#[async_trait]
+pub trait Transcriber: Send + Sync {
+    /// Sample rate the backend needs the audio in, or `None` if it accepts any rate.
+    fn preferred_sample_rate(&self) -> Option<u32> {
+        None
+    }
+
+    /// Name of the model transcribing, as recorded in JSON transcripts.
+    fn model(&self) -> String;
+
+    async fn start(&self, options: StartOptions) -> anyhow::Result<Box<dyn TranscriptionSession>>;
+}
+
+/// A running transcription task accepting audio in the format it was started with.
+#[async_trait]
+pub trait TranscriptionSession: Send {
+    async fn send_audio(&mut self, data: &[u8]) -> anyhow::Result<()>;
+
+    /// Sends `samples` as the little-endian 16-bit PCM `send_audio` takes.
+    async fn send_samples(&mut self, samples: &[i16]) -> anyhow::Result<()> {
+        self.send_audio(&gummy::le_bytes(samples)).await
+    }
+
+    /// Audio given to the session that it didn't write to the connection yet, in bytes.
+    fn queued_bytes(&self) -> usize {
+        0
+    }
+
+    /// ID the backend gave the task, as its support asks for it.
+    fn task_id(&self) -> Option<&str> {
+        None
+    }
+
+    /// Returns the next event, or `None` once the session has ended.
+    async fn next_event(&mut self) -> anyhow::Result<Option<TranscriptionEvent>>;
+
+    async fn finish(self: Box<Self>) -> anyhow::Result<Vec<Transcription>>;
+
+    /// Finishes like `finish`, with what the task is billed for if the backend tells.
+    async fn finish_with_usage(
+        self: Box<Self>,
+    ) -> anyhow::Result<(Vec<Transcription>, Option<TaskUsage>)> {
+        Ok((self.finish().await?, None))
+    }
+}
+
+pub struct GummyTranscriber {
+    api_key: String,
+    connect_options: ConnectOptions,
+}
+
+impl GummyTranscriber {
+    pub fn new(api_key: &str, connect_options: ConnectOptions) -> Self {
+        GummyTranscriber {
+            api_key: api_key.to_string(),
+            connect_options,
+        }
+    }
+}
+
+#[async_trait]
+impl Transcriber for GummyTranscriber {
+    fn model(&self) -> String {
+        MODEL.to_string()
+    }
+
+    async fn start(&self, options: StartOptions) -> anyhow::Result<Box<dyn TranscriptionSession>> {
+        let gummy = Gummy::new(&self.api_key)
+            .connect_with(&self.connect_options)
+            .await?
+            .start_with(&options)
+            .await?;
+        Ok(Box::new(gummy))
+    }
+}
+
+#[async_trait]
+impl TranscriptionSession for Gummy<Converting> {
+    // What the connection doesn't take right away is queued, so a slow connection doesn't
+    // hold up the events; it's written while waiting for them.
+    async fn send_audio(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        Ok(self.queue(data.to_vec())?)
+    }
+
+    async fn send_samples(&mut self, samples: &[i16]) -> anyhow::Result<()> {
+        Ok(self.queue_samples(samples)?)
+    }
+
+    fn queued_bytes(&self) -> usize {
+        Gummy::queued_bytes(self)
+    }
+
+    fn task_id(&self) -> Option<&str> {
+        Some(Gummy::<Converting>::task_id(self))
+    }
+
+    async fn next_event(&mut self) -> anyhow::Result<Option<TranscriptionEvent>> {
+        Ok(Gummy::next_event(self).await?)
+    }
+
+    async fn finish(self: Box<Self>) -> anyhow::Result<Vec<Transcription>> {
+        Ok(Gummy::finish(*self).await?.get_result())
+    }
+
+    async fn finish_with_usage(
+        self: Box<Self>,
+    ) -> anyhow::Result<(Vec<Transcription>, Option<TaskUsage>)> {
+        let finished = Gummy::finish(*self).await?;
+        Ok((finished.get_result(), finished.usage()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Answers every chunk of audio with a final sentence containing the chunk as text.
+    struct EchoTranscriber;
+
+    struct EchoSession {
+        pending: VecDeque<TranscriptionEvent>,
+        result: Vec<Transcription>,
+    }
+
+    #[async_trait]
+    impl Transcriber for EchoTranscriber {
+        fn model(&self) -> String {
+            "echo".to_string()
+        }
+
+        async fn start(
+            &self,
+            _options: StartOptions,
+        ) -> anyhow::Result<Box<dyn TranscriptionSession>> {
+            Ok(Box::new(EchoSession {
+                pending: VecDeque::new(),
+                result: vec![],
+            }))
+        }
+    }
+
+    #[async_trait]
+    impl TranscriptionSession for EchoSession {
+        async fn send_audio(&mut self, data: &[u8]) -> anyhow::Result<()> {
+            let sentence_id = self.result.len() as u64;
+            let transcription = Transcription {
+                sentence_id,
+                begin_time: sentence_id * 100,
+                end_time: sentence_id * 100 + 100,
+                text: String::from_utf8_lossy(data).to_string(),
+                is_final: true,
+                translated_text: None,
+                translations: vec![],
+                words: vec![],
+                confidence: None,
+                source_label: None,
+            };
+            self.result.push(transcription.clone());
+            self.pending
+                .push_back(TranscriptionEvent::Final(transcription));
+            Ok(())
+        }
+
+        async fn next_event(&mut self) -> anyhow::Result<Option<TranscriptionEvent>> {
+            Ok(self.pending.pop_front())
+        }
+
+        async fn finish(self: Box<Self>) -> anyhow::Result<Vec<Transcription>> {
+            Ok(self.result)
+        }
+    }
+
+    #[tokio::test]
+    async fn echo_transcriber_through_trait_object() {
+        let transcriber: Box<dyn Transcriber> = Box::new(EchoTranscriber);
+        let mut session = transcriber.start(StartOptions::default()).await.unwrap();
+
+        session.send_audio(b"hello").await.unwrap();
+        session.send_audio(b"world").await.unwrap();
+        match session.next_event().await.unwrap() {
+            Some(TranscriptionEvent::Final(transcription)) => {
+                assert_eq!(transcription.text, "hello")
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        let result = session.finish().await.unwrap();
+        let texts = result.iter().map(|t| t.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(texts, ["hello", "world"]);
+    }
+}
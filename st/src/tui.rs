@@ -0,0 +1,516 @@
+//! `--tui`: a dashboard of the session in the terminal.
+
+use crate::METER_FLOOR_DBFS;
+use crate::console::{DisplayMode, INDENT, ShownLine, timestamp};
+use crate::latency::LatencyStats;
+use audio::level::Level;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use crossterm::cursor::Show;
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{
+    EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode,
+};
+use futures_util::StreamExt;
+use gummy::{Transcription, TranscriptionEvent};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Gauge, Paragraph};
+use ratatui::{Frame, Terminal};
+use std::fmt;
+use std::io::{self, Stdout, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often the dashboard is redrawn.
+pub const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// State of the connection to the backend.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Connection {
+    Connected,
+    /// Starting the session again after the connection dropped.
+    Reconnecting,
+    /// Sending the new session the audio held while reconnecting, this far behind.
+    CatchingUp(Duration),
+    /// Waiting for the last sentences before closing.
+    Finishing,
+    Lost,
+}
+
+impl fmt::Display for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Connection::Connected => f.write_str("connected"),
+            Connection::Reconnecting => f.write_str("reconnecting"),
+            Connection::CatchingUp(behind) => {
+                write!(f, "catching up, {} s behind", behind.as_secs().max(1))
+            }
+            Connection::Finishing => f.write_str("finishing"),
+            Connection::Lost => f.write_str("disconnected"),
+        }
+    }
+}
+
+/// What the dashboard shows, kept up to date by the session and drawn by `draw`.
+#[derive(Clone, Debug)]
+pub struct Dashboard {
+    /// Final sentences, oldest first.
+    pub sentences: Vec<Transcription>,
+    /// Sentence being recognized.
+    pub partial: Option<Transcription>,
+    /// Capture level, `None` without a device to measure.
+    pub level: Option<Level>,
+    pub gain_db: f32,
+    pub device: String,
+    /// Time since the session started.
+    pub elapsed: Duration,
+    /// Bytes of audio sent for transcription.
+    pub sent_bytes: usize,
+    pub connection: Connection,
+    pub paused: bool,
+    /// Latency of the latest sentences, once there are some.
+    pub latency: Option<LatencyStats>,
+    /// Last warning or notice, shown in place of the key bindings.
+    pub message: Option<String>,
+    /// Text of sentences shown.
+    pub display: DisplayMode,
+}
+
+impl Dashboard {
+    pub fn new(device: &str) -> Self {
+        Dashboard {
+            sentences: vec![],
+            partial: None,
+            level: None,
+            gain_db: 0.0,
+            device: device.to_string(),
+            elapsed: Duration::ZERO,
+            sent_bytes: 0,
+            connection: Connection::Connected,
+            paused: false,
+            latency: None,
+            message: None,
+            display: DisplayMode::Both,
+        }
+    }
+
+    /// Takes in an event of the session.
+    pub fn update(&mut self, event: &TranscriptionEvent) {
+        let finalized = |sentences: &[Transcription], t: &Transcription| {
+            sentences.iter().any(|s| s.sentence_id == t.sentence_id)
+        };
+        match event {
+            TranscriptionEvent::Partial(t) if !finalized(&self.sentences, t) => {
+                self.partial = Some(t.clone());
+            }
+            TranscriptionEvent::Partial(_) => {}
+            TranscriptionEvent::Final(t) => {
+                if self
+                    .partial
+                    .as_ref()
+                    .is_some_and(|partial| partial.sentence_id == t.sentence_id)
+                {
+                    self.partial = None;
+                }
+                if !finalized(&self.sentences, t) {
+                    self.sentences.push(t.clone());
+                }
+            }
+            TranscriptionEvent::Finished => self.partial = None,
+        }
+    }
+}
+
+/// Lays `dashboard` out on `frame`: the transcript, scrolled to its end, the sentence
+/// being recognized under it, the capture level and a status bar.
+pub fn draw(frame: &mut Frame, dashboard: &Dashboard) {
+    let [transcript, partial, meter, status] = Layout::vertical([
+        Constraint::Min(3),
+        Constraint::Length(1),
+        Constraint::Length(1),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let block = Block::bordered().title(" Transcript ");
+    let height = block.inner(transcript).height as usize;
+    let mut lines = vec![];
+    let style = |line: &ShownLine| match line {
+        ShownLine::Translation(_) => Style::new().fg(Color::Cyan),
+        ShownLine::Source(_) | ShownLine::Untranslated(_) => Style::new(),
+    };
+    for t in &dashboard.sentences {
+        let shown = dashboard.display.lines(t);
+        let (first, rest) = shown.split_first().expect("a sentence shows a line");
+        lines.push(Line::from(vec![
+            Span::styled(timestamp(t.begin_time), Style::new().fg(Color::DarkGray)),
+            Span::raw(" "),
+            Span::styled(first.to_string(), style(first)),
+        ]));
+        for line in rest {
+            lines.push(Line::styled(format!("{}{}", INDENT, line), style(line)));
+        }
+    }
+    let shown = lines.split_off(lines.len().saturating_sub(height));
+    frame.render_widget(Paragraph::new(shown).block(block), transcript);
+
+    let recognizing = dashboard.partial.as_ref().map_or(String::new(), |t| {
+        format!("{} {}", timestamp(t.begin_time), dashboard.display.line(t))
+    });
+    frame.render_widget(
+        Paragraph::new(recognizing).style(Style::new().add_modifier(Modifier::ITALIC)),
+        partial,
+    );
+
+    let (ratio, level) = match dashboard.level {
+        Some(level) => (
+            ((level.rms_dbfs - METER_FLOOR_DBFS) / -METER_FLOOR_DBFS).clamp(0.0, 1.0),
+            format!("{:.0} dBFS", level.rms_dbfs.max(METER_FLOOR_DBFS)),
+        ),
+        None => (0.0, "no capture".to_string()),
+    };
+    frame.render_widget(
+        Gauge::default()
+            .ratio(ratio as f64)
+            .label(format!("{}, gain {:+.1} dB", level, dashboard.gain_db))
+            .gauge_style(Style::new().fg(Color::Green)),
+        meter,
+    );
+
+    let seconds = dashboard.elapsed.as_secs();
+    let mut fields = vec![
+        dashboard.device.clone(),
+        format!(
+            "{:02}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        ),
+        format!("{} sent", size(dashboard.sent_bytes)),
+        dashboard.connection.to_string(),
+    ];
+    if let Some(latency) = dashboard.latency {
+        fields.push(format!(
+            "latency {:.1} s, p95 {:.1} s",
+            latency.p50_ms as f64 / 1000.0,
+            latency.p95_ms as f64 / 1000.0
+        ));
+    }
+    if dashboard.paused {
+        fields.push("paused".to_string());
+    }
+    fields.push(
+        dashboard
+            .message
+            .clone()
+            .unwrap_or_else(|| "p pause  c copy  a copy all  q quit".to_string()),
+    );
+    frame.render_widget(
+        Paragraph::new(fields.join(" │ ")).style(Style::new().add_modifier(Modifier::REVERSED)),
+        status,
+    );
+}
+
+/// `bytes` in kB or MB.
+fn size(bytes: usize) -> String {
+    match bytes {
+        0..1_000_000 => format!("{:.0} kB", bytes as f64 / 1e3),
+        _ => format!("{:.1} MB", bytes as f64 / 1e6),
+    }
+}
+
+/// What a key asks of the session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Pause the capture, or resume it.
+    Pause,
+    /// Copy the last sentence to the clipboard.
+    Copy,
+    /// Copy the whole transcript to the clipboard.
+    CopyAll,
+    /// Finish the session and exit.
+    Quit,
+}
+
+fn command(key: KeyEvent) -> Option<Command> {
+    match key.code {
+        // Raw mode turns Ctrl+C into a key instead of a signal.
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Some(Command::Quit),
+        KeyCode::Char('p') => Some(Command::Pause),
+        KeyCode::Char('c') => Some(Command::Copy),
+        KeyCode::Char('a') => Some(Command::CopyAll),
+        KeyCode::Char('q') | KeyCode::Esc => Some(Command::Quit),
+        _ => None,
+    }
+}
+
+/// The terminal while it shows the dashboard. It is restored when this is dropped, and on
+/// panics.
+pub struct Tui {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+    keys: EventStream,
+    pub dashboard: Dashboard,
+}
+
+impl Tui {
+    /// Switches the terminal to the dashboard of a capture from `device`.
+    pub fn enter(device: &str) -> io::Result<Self> {
+        let hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore();
+            hook(info);
+        }));
+        enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+        terminal.hide_cursor()?;
+        Ok(Tui {
+            terminal,
+            keys: EventStream::new(),
+            dashboard: Dashboard::new(device),
+        })
+    }
+
+    pub fn draw(&mut self) -> io::Result<()> {
+        self.terminal.draw(|frame| draw(frame, &self.dashboard))?;
+        Ok(())
+    }
+
+    /// Waits for a key bound to a command. Returns `None` once the terminal has no more
+    /// input.
+    pub async fn next_command(&mut self) -> Option<Command> {
+        while let Some(event) = self.keys.next().await {
+            if let Ok(Event::Key(key)) = event
+                && key.kind == KeyEventKind::Press
+                && let Some(command) = command(key)
+            {
+                return Some(command);
+            }
+        }
+        None
+    }
+
+    /// Copies the last final sentence to the clipboard, telling how that went on the
+    /// dashboard.
+    pub fn copy_last_sentence(&mut self) {
+        let Some(last) = self.dashboard.sentences.last() else {
+            self.dashboard.message = Some("Nothing to copy yet".to_string());
+            return;
+        };
+        let text = last.text.trim().to_string();
+        self.dashboard.message = Some(match self.copy(&text) {
+            Ok(()) => "Copied the last sentence".to_string(),
+            Err(e) => format!("Failed to copy: {}", e),
+        });
+    }
+
+    /// Copies `text` to the clipboard with an OSC 52 sequence, which the terminal handles,
+    /// so it works over SSH too.
+    fn copy(&mut self, text: &str) -> io::Result<()> {
+        let out = self.terminal.backend_mut();
+        write!(out, "\x1b]52;c;{}\x07", STANDARD.encode(text))?;
+        out.flush()
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+/// Leaves the dashboard for the shell, ignoring errors since there's nothing left to do
+/// about them.
+fn restore() {
+    let _ = disable_raw_mode();
+    let _ = execute!(io::stdout(), LeaveAlternateScreen, Show);
+}
+
+/// Keeps the last line logged, for the dashboard to show instead of logging over it.
+#[derive(Clone, Default)]
+pub struct LastLine(Arc<Mutex<Option<String>>>);
+
+impl LastLine {
+    /// The line logged since the last call, if any.
+    pub fn take(&self) -> Option<String> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+impl Write for LastLine {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        if let Some(line) = text.lines().rev().find(|line| !line.trim().is_empty()) {
+            *self.0.lock().unwrap() = Some(line.trim().to_string());
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+
+    fn sentence(sentence_id: u64, text: &str, is_final: bool) -> Transcription {
+        Transcription {
+            sentence_id,
+            begin_time: sentence_id * 4_000,
+            end_time: sentence_id * 4_000 + 3_000,
+            text: text.to_string(),
+            is_final,
+            translated_text: is_final.then(|| format!("Fini {}", sentence_id)),
+            translations: vec![],
+            words: vec![],
+            confidence: None,
+            source_label: None,
+        }
+    }
+
+    /// Draws `dashboard` on a terminal of `width` by `height` cells and returns its lines.
+    fn render(dashboard: &Dashboard, width: u16, height: u16) -> Vec<String> {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal.draw(|frame| draw(frame, dashboard)).unwrap();
+        let buffer = terminal.backend().buffer();
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| buffer[(x, y)].symbol())
+                    .collect::<String>()
+                    .trim_end()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn follows_the_session() {
+        let mut dashboard = Dashboard::new("Speakers");
+        dashboard.update(&TranscriptionEvent::Partial(sentence(0, "Hello", false)));
+        assert_eq!(dashboard.partial.as_ref().unwrap().text, "Hello");
+        dashboard.update(&TranscriptionEvent::Final(sentence(0, "Hello.", true)));
+        assert!(dashboard.partial.is_none());
+        // Late updates of a final sentence are dropped.
+        dashboard.update(&TranscriptionEvent::Partial(sentence(0, "Hello", false)));
+        dashboard.update(&TranscriptionEvent::Final(sentence(0, "Hello.", true)));
+        assert!(dashboard.partial.is_none());
+        assert_eq!(dashboard.sentences.len(), 1);
+    }
+
+    #[test]
+    fn shows_the_text_the_display_picks() {
+        let mut dashboard = Dashboard::new("Speakers");
+        dashboard.update(&TranscriptionEvent::Final(sentence(0, "Done.", true)));
+        dashboard.update(&TranscriptionEvent::Partial(sentence(1, "Going on", false)));
+        dashboard.display = DisplayMode::Translation;
+        let lines = render(&dashboard, 40, 7);
+        assert_eq!(lines[1], "│[00:00] Fini 0                        │");
+        assert_eq!(lines[2], "│                                      │");
+        // Without a translation yet, the sentence being recognized is shown as it is.
+        assert_eq!(lines[4], "[00:04] (untranslated) Going on");
+    }
+
+    #[test]
+    fn lays_out_the_dashboard() {
+        let mut dashboard = Dashboard::new("Speakers");
+        for id in 0..3 {
+            dashboard.update(&TranscriptionEvent::Final(sentence(id, "Done.", true)));
+        }
+        dashboard.update(&TranscriptionEvent::Partial(sentence(3, "Going on", false)));
+        dashboard.level = Some(Level {
+            peak_dbfs: -12.0,
+            rms_dbfs: -30.0,
+        });
+        dashboard.elapsed = Duration::from_secs(3_723);
+        dashboard.sent_bytes = 1_234_567;
+        dashboard.paused = true;
+
+        // The transcript shows its last lines, as many as fit.
+        let lines = render(&dashboard, 72, 10);
+        assert_eq!(
+            lines,
+            [
+                "┌ Transcript ──────────────────────────────────────────────────────────┐",
+                "│        Fini 0                                                        │",
+                "│[00:04] Done.                                                         │",
+                "│        Fini 1                                                        │",
+                "│[00:08] Done.                                                         │",
+                "│        Fini 2                                                        │",
+                "└──────────────────────────────────────────────────────────────────────┘",
+                "[00:12] Going on",
+                "█████████████████████████-30 dBFS, gain +0.0 dB",
+                // Cut at the edge of the terminal.
+                "Speakers │ 01:02:03 │ 1.2 MB sent │ connected │ paused │ p pause  c copy",
+            ]
+        );
+    }
+
+    #[test]
+    fn shows_the_level_and_messages() {
+        let mut dashboard = Dashboard::new("in.wav");
+        dashboard.connection = Connection::Lost;
+        dashboard.message = Some("Capture device lost".to_string());
+        let lines = render(&dashboard, 80, 6);
+        assert!(
+            lines[4].contains("no capture, gain +0.0 dB"),
+            "{}",
+            lines[4]
+        );
+        assert_eq!(
+            lines[5],
+            "in.wav │ 00:00:00 │ 0 kB sent │ disconnected │ Capture device lost"
+        );
+        dashboard.latency = Some(LatencyStats {
+            sentences: 12,
+            p50_ms: 1_240,
+            p95_ms: 2_010,
+            max_ms: 3_000,
+        });
+        assert_eq!(
+            render(&dashboard, 100, 6)[5],
+            "in.wav │ 00:00:00 │ 0 kB sent │ disconnected │ latency 1.2 s, p95 2.0 s │ Capture device lost"
+        );
+    }
+
+    #[test]
+    fn maps_keys_to_commands() {
+        let key = |code, modifiers| command(KeyEvent::new(code, modifiers));
+        assert_eq!(
+            key(KeyCode::Char('p'), KeyModifiers::NONE),
+            Some(Command::Pause)
+        );
+        assert_eq!(
+            key(KeyCode::Char('c'), KeyModifiers::NONE),
+            Some(Command::Copy)
+        );
+        assert_eq!(
+            key(KeyCode::Char('a'), KeyModifiers::NONE),
+            Some(Command::CopyAll)
+        );
+        assert_eq!(
+            key(KeyCode::Char('c'), KeyModifiers::CONTROL),
+            Some(Command::Quit)
+        );
+        assert_eq!(
+            key(KeyCode::Char('q'), KeyModifiers::NONE),
+            Some(Command::Quit)
+        );
+        assert_eq!(key(KeyCode::Char('x'), KeyModifiers::NONE), None);
+    }
+
+    #[test]
+    fn keeps_the_last_line_logged() {
+        let mut log = LastLine::default();
+        write!(log, "[WARN] first\n[WARN] second\n").unwrap();
+        assert_eq!(log.take().as_deref(), Some("[WARN] second"));
+        assert_eq!(log.take(), None);
+    }
+}
@@ -0,0 +1,215 @@
+//! Warnings for sessions that run without getting anywhere: silence from the capture, as
+//! when the wrong device is picked, or audio the service doesn't answer.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Frames quieter than this count as silence.
+const SILENCE_DBFS: f32 = -60.0;
+/// Silence from the start of a session that's worth a warning.
+const STARTUP_SILENCE: Duration = Duration::from_secs(5);
+/// Silence later on that's worth a warning; people pause, but rarely for this long.
+const SILENCE: Duration = Duration::from_secs(60);
+/// Time the service has to answer audio with a result.
+const UNANSWERED: Duration = Duration::from_secs(15);
+
+/// Something that looks wrong with a session.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Alert {
+    /// Nothing but silence came from `device` for a while.
+    Silent { device: String, duration: Duration },
+    /// Audio was sent for a while without a result coming back.
+    Unanswered { duration: Duration },
+}
+
+impl Alert {
+    /// Name of the kind of problem, for clients of the server.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Alert::Silent { .. } => "silent",
+            Alert::Unanswered { .. } => "unanswered",
+        }
+    }
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Alert::Silent { device, duration } => write!(
+                f,
+                "no sound from {} for {} s. Is it the right device? `st --list-devices` lists them",
+                device,
+                duration.as_secs()
+            ),
+            Alert::Unanswered { duration } => write!(
+                f,
+                "audio was sent for {} s without the recognition service responding",
+                duration.as_secs()
+            ),
+        }
+    }
+}
+
+/// Watches the levels of the frames captured and the results of the session, and raises
+/// an alert once per episode of each problem.
+///
+/// Time is passed in, so timelines can be replayed.
+pub struct Watchdog {
+    // Capture device, none for files, whose silence is what it is.
+    device: Option<String>,
+    // When the current silence started, or the session if nothing was heard yet.
+    quiet_since: Instant,
+    heard: bool,
+    silence_alerted: bool,
+    // When audio was first heard since the last result.
+    unanswered_since: Option<Instant>,
+    unanswered_alerted: bool,
+}
+
+impl Watchdog {
+    /// Watches a session capturing from `device`, starting `now`.
+    pub fn new(device: Option<&str>, now: Instant) -> Self {
+        Watchdog {
+            device: device.map(str::to_string),
+            quiet_since: now,
+            heard: false,
+            silence_alerted: false,
+            unanswered_since: None,
+            unanswered_alerted: false,
+        }
+    }
+
+    /// Takes in the level of a frame captured.
+    pub fn hear(&mut self, rms_dbfs: f32, now: Instant) {
+        if rms_dbfs < SILENCE_DBFS {
+            return;
+        }
+        self.quiet_since = now;
+        self.heard = true;
+        self.silence_alerted = false;
+        self.unanswered_since.get_or_insert(now);
+    }
+
+    /// Takes in a result from the service.
+    pub fn answered(&mut self) {
+        self.unanswered_since = None;
+        self.unanswered_alerted = false;
+    }
+
+    /// Returns the problems found by `now` that weren't alerted yet. Nothing is expected
+    /// while the capture is `paused`.
+    pub fn check(&mut self, now: Instant, paused: bool) -> Vec<Alert> {
+        if paused {
+            self.quiet_since = now;
+            self.unanswered_since = None;
+            return vec![];
+        }
+        let mut alerts = vec![];
+        let quiet = now.duration_since(self.quiet_since);
+        let limit = if self.heard { SILENCE } else { STARTUP_SILENCE };
+        if let Some(device) = &self.device
+            && !self.silence_alerted
+            && quiet >= limit
+        {
+            self.silence_alerted = true;
+            alerts.push(Alert::Silent {
+                device: device.clone(),
+                duration: quiet,
+            });
+        }
+        if let Some(since) = self.unanswered_since
+            && !self.unanswered_alerted
+            && now.duration_since(since) >= UNANSWERED
+        {
+            self.unanswered_alerted = true;
+            alerts.push(Alert::Unanswered {
+                duration: now.duration_since(since),
+            });
+        }
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOUD: f32 = -20.0;
+    const QUIET: f32 = -75.0;
+
+    /// Replays a timeline of a frame per second at `level(second)` and results at
+    /// `answers`, checking after each second. Returns the alerts with when they were raised.
+    fn replay(
+        seconds: u64,
+        level: impl Fn(u64) -> f32,
+        answers: &[u64],
+        paused: impl Fn(u64) -> bool,
+    ) -> Vec<(u64, Alert)> {
+        let start = Instant::now();
+        let at = |second| start + Duration::from_secs(second);
+        let mut watchdog = Watchdog::new(Some("Speakers"), start);
+        let mut alerts = vec![];
+        for second in 1..=seconds {
+            if !paused(second) {
+                watchdog.hear(level(second), at(second));
+            }
+            if answers.contains(&second) {
+                watchdog.answered();
+            }
+            for alert in watchdog.check(at(second), paused(second)) {
+                alerts.push((second, alert));
+            }
+        }
+        alerts
+    }
+
+    fn silent(seconds: u64) -> Alert {
+        Alert::Silent {
+            device: "Speakers".to_string(),
+            duration: Duration::from_secs(seconds),
+        }
+    }
+
+    #[test]
+    fn alerts_silence_from_the_start_once() {
+        assert_eq!(replay(120, |_| QUIET, &[], |_| false), [(5, silent(5))]);
+    }
+
+    #[test]
+    fn alerts_each_long_silence_after_sound() {
+        // Sound for 10 s, a minute and a half of silence, sound again, then silence.
+        let level = |second| match second {
+            1..=10 | 101..=105 => LOUD,
+            _ => QUIET,
+        };
+        let answers: Vec<_> = (1..=200).collect();
+        assert_eq!(
+            replay(200, level, &answers, |_| false),
+            [(70, silent(60)), (165, silent(60))]
+        );
+    }
+
+    #[test]
+    fn expects_nothing_while_paused() {
+        assert_eq!(
+            replay(100, |_| QUIET, &[], |second| second <= 90),
+            [(95, silent(5))]
+        );
+    }
+
+    #[test]
+    fn alerts_audio_the_service_doesnt_answer() {
+        let unanswered = |seconds| Alert::Unanswered {
+            duration: Duration::from_secs(seconds),
+        };
+        // Results until 20 s, then none while speaking goes on.
+        let answers: Vec<_> = (1..=20).collect();
+        assert_eq!(
+            replay(60, |_| LOUD, &answers, |_| false),
+            [(36, unanswered(15))]
+        );
+        // Without audio, no results are expected.
+        let level = |second| if second == 1 { LOUD } else { QUIET };
+        assert!(replay(30, level, &[1], |_| false).is_empty());
+    }
+}
@@ -0,0 +1,216 @@
+use crate::transcriber::{Transcriber, TranscriptionSession};
+use async_trait::async_trait;
+use gummy::{StartOptions, Transcription, TranscriptionEvent};
+use log::debug;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::{Arc, mpsc};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
+
+/// Whisper models are trained on 16 kHz mono audio.
+pub const SAMPLE_RATE: u32 = 16000;
+
+const DEFAULT_WINDOW_SECONDS: u32 = 8;
+
+/// Offline transcription backend running a whisper.cpp model locally.
+///
+/// Audio is collected into fixed windows which are transcribed one after another on a
+/// blocking worker thread. Each recognized segment is emitted as a final sentence, with
+/// begin/end times counted from the start of the session.
+pub struct WhisperTranscriber {
+    context: Arc<WhisperContext>,
+    // File name of the model.
+    model: String,
+    window_seconds: u32,
+}
+
+impl WhisperTranscriber {
+    pub fn new(model_path: &str) -> anyhow::Result<Self> {
+        let context =
+            WhisperContext::new_with_params(model_path, WhisperContextParameters::default())?;
+        let model = Path::new(model_path).file_name().map_or_else(
+            || model_path.to_string(),
+            |name| name.to_string_lossy().into(),
+        );
+        Ok(WhisperTranscriber {
+            context: Arc::new(context),
+            model,
+            window_seconds: DEFAULT_WINDOW_SECONDS,
+        })
+    }
+}
+
+#[async_trait]
+impl Transcriber for WhisperTranscriber {
+    fn preferred_sample_rate(&self) -> Option<u32> {
+        Some(SAMPLE_RATE)
+    }
+
+    fn model(&self) -> String {
+        self.model.clone()
+    }
+
+    async fn start(&self, options: StartOptions) -> anyhow::Result<Box<dyn TranscriptionSession>> {
+        if let Some(sample_rate) = options.sample_rate.filter(|rate| *rate != SAMPLE_RATE) {
+            anyhow::bail!(
+                "Whisper requires {} Hz audio, got {} Hz",
+                SAMPLE_RATE,
+                sample_rate
+            );
+        }
+        let language = options
+            .source_language
+            .filter(|language| language != "auto");
+        let (window_tx, window_rx) = mpsc::channel();
+        let (segment_tx, segment_rx) = unbounded_channel();
+        let context = self.context.clone();
+        tokio::task::spawn_blocking(move || run_worker(context, language, window_rx, segment_tx));
+        Ok(Box::new(WhisperSession {
+            windows: Some(window_tx),
+            segments: segment_rx,
+            window_samples: (self.window_seconds * SAMPLE_RATE) as usize,
+            buffer: vec![],
+            buffer_start_ms: 0,
+            pending: VecDeque::new(),
+            result: vec![],
+        }))
+    }
+}
+
+struct Window {
+    start_ms: u64,
+    samples: Vec<f32>,
+}
+
+struct Segment {
+    begin_time: u64,
+    end_time: u64,
+    text: String,
+}
+
+fn run_worker(
+    context: Arc<WhisperContext>,
+    language: Option<String>,
+    windows: mpsc::Receiver<Window>,
+    segments: UnboundedSender<anyhow::Result<Vec<Segment>>>,
+) {
+    let mut state = match context.create_state() {
+        Ok(state) => state,
+        Err(e) => {
+            let _ = segments.send(Err(e.into()));
+            return;
+        }
+    };
+    for window in windows {
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        params.set_language(language.as_deref());
+        params.set_print_progress(false);
+        params.set_print_realtime(false);
+        params.set_print_special(false);
+        params.set_print_timestamps(false);
+        let result = state
+            .full(params, &window.samples)
+            .and_then(|_| state.full_n_segments())
+            .and_then(|count| {
+                (0..count)
+                    .map(|i| {
+                        // Segment times are reported in centiseconds.
+                        Ok(Segment {
+                            begin_time: window.start_ms + state.full_get_segment_t0(i)? as u64 * 10,
+                            end_time: window.start_ms + state.full_get_segment_t1(i)? as u64 * 10,
+                            text: state.full_get_segment_text_lossy(i)?.trim().to_string(),
+                        })
+                    })
+                    .collect()
+            });
+        if segments.send(result.map_err(Into::into)).is_err() {
+            break;
+        }
+    }
+}
+
+struct WhisperSession {
+    windows: Option<mpsc::Sender<Window>>,
+    segments: UnboundedReceiver<anyhow::Result<Vec<Segment>>>,
+    window_samples: usize,
+    buffer: Vec<f32>,
+    buffer_start_ms: u64,
+    pending: VecDeque<TranscriptionEvent>,
+    result: Vec<Transcription>,
+}
+
+impl WhisperSession {
+    fn flush_window(&mut self) -> anyhow::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let samples = std::mem::take(&mut self.buffer);
+        let start_ms = self.buffer_start_ms;
+        self.buffer_start_ms += samples.len() as u64 * 1000 / SAMPLE_RATE as u64;
+        debug!(
+            "Transcribing {} samples from {} ms",
+            samples.len(),
+            start_ms
+        );
+        self.windows
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Session already finished"))?
+            .send(Window { start_ms, samples })
+            .map_err(|_| anyhow::anyhow!("Whisper worker stopped"))
+    }
+
+    fn push_segments(&mut self, segments: Vec<Segment>) {
+        for segment in segments.into_iter().filter(|s| !s.text.is_empty()) {
+            let transcription = Transcription {
+                sentence_id: self.result.len() as u64,
+                begin_time: segment.begin_time,
+                end_time: segment.end_time,
+                text: segment.text,
+                is_final: true,
+                translated_text: None,
+                translations: vec![],
+                words: vec![],
+                confidence: None,
+                source_label: None,
+            };
+            self.result.push(transcription.clone());
+            self.pending
+                .push_back(TranscriptionEvent::Final(transcription));
+        }
+    }
+}
+
+#[async_trait]
+impl TranscriptionSession for WhisperSession {
+    async fn send_audio(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        self.buffer.extend(
+            data.chunks_exact(2)
+                .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0),
+        );
+        if self.buffer.len() >= self.window_samples {
+            self.flush_window()?;
+        }
+        Ok(())
+    }
+
+    async fn next_event(&mut self) -> anyhow::Result<Option<TranscriptionEvent>> {
+        while self.pending.is_empty() {
+            match self.segments.recv().await {
+                Some(segments) => self.push_segments(segments?),
+                None => return Ok(None),
+            }
+        }
+        Ok(self.pending.pop_front())
+    }
+
+    async fn finish(mut self: Box<Self>) -> anyhow::Result<Vec<Transcription>> {
+        self.flush_window()?;
+        // Closing the window channel lets the worker exit once it has caught up.
+        self.windows = None;
+        while let Some(segments) = self.segments.recv().await {
+            self.push_segments(segments?);
+        }
+        Ok(self.result)
+    }
+}
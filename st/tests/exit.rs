@@ -0,0 +1,91 @@
+//! Exit statuses and error reports of runs that can't go on, for scripts to tell failures
+//! apart.
+
+use assert_cmd::Command;
+use gummy_mock::{MockServer, Script};
+use std::path::PathBuf;
+
+fn fixture(name: &str) -> String {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// `st` away from any config file and API key of whoever runs the tests.
+fn st() -> Command {
+    let config = std::env::temp_dir().join(format!("st-{}-exit-config", std::process::id()));
+    let mut st = Command::new(env!("CARGO_BIN_EXE_st"));
+    st.env("XDG_CONFIG_HOME", config)
+        .env_remove("API_KEY")
+        .env_remove("OPENAI_API_KEY");
+    st
+}
+
+/// What `st` said on stderr, which should be a single line.
+fn reported(output: &std::process::Output) -> String {
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert_eq!(stderr.lines().count(), 1, "{}", stderr);
+    stderr
+}
+
+#[test]
+fn exits_with_2_without_an_api_key() {
+    let assert = st()
+        .args(["--input", &fixture("input.wav")])
+        .assert()
+        .code(2);
+    assert!(reported(assert.get_output()).starts_with("No API key"));
+
+    let assert = st()
+        .args(["--input", &fixture("input.wav"), "--error-format", "json"])
+        .assert()
+        .code(2);
+    let report: serde_json::Value = serde_json::from_str(&reported(assert.get_output())).unwrap();
+    assert_eq!(report["error"]["kind"], "config");
+    assert_eq!(report["error"]["code"], 2);
+}
+
+#[test]
+fn exits_with_3_when_the_audio_cant_be_read() {
+    let assert = st()
+        .args(["--api-key", "test-key", "--input", "missing.wav"])
+        .assert()
+        .code(3);
+    assert!(reported(assert.get_output()).starts_with("Failed to open missing.wav"));
+    st().args(["--api-key", "test-key", "--device", "No such device"])
+        .assert()
+        .code(3);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn exits_with_4_when_the_key_is_refused() {
+    let server = MockServer::with_script(Script {
+        api_key: Some("right-key".to_string()),
+        ..Script::default()
+    })
+    .await;
+    let args = ["--api-key", "wrong-key", "--url", &server.url(), "--input"];
+    let mut st = st();
+    st.args(args).arg(fixture("input.wav"));
+    let assert = tokio::task::spawn_blocking(move || st.assert().code(4))
+        .await
+        .unwrap();
+    reported(assert.get_output());
+}
+
+#[test]
+fn exits_with_5_when_the_backend_cant_be_reached() {
+    // A port nothing listens on.
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let url = format!("ws://127.0.0.1:{}", port);
+    st().args(["--api-key", "test-key", "--url", &url])
+        .args(["--input", &fixture("input.wav")])
+        .assert()
+        .code(5);
+}
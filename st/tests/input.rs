@@ -0,0 +1,372 @@
+//! Transcribes WAV files end to end, against the scripted mock server in `gummy-mock`.
+
+use gummy_mock::{MockServer, Script};
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn temp(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("st-{}-{}", std::process::id(), name))
+}
+
+/// Writes `seconds` of silence at 16 kHz to a WAV file.
+fn silence(name: &str, seconds: u32) -> PathBuf {
+    let path = temp(name);
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: 16000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+    for _ in 0..seconds * 16000 {
+        writer.write_sample(0i16).unwrap();
+    }
+    writer.finalize().unwrap();
+    path
+}
+
+/// Transcribes `input` into `output` with the scripted results of the `input` fixture.
+async fn transcribe(input: &Path, output: &Path, args: &[&str]) -> Output {
+    transcribe_with(&fixture("input.json"), input, output, args).await
+}
+
+/// Transcribes `input` into `output` with the results scripted in `script`.
+async fn transcribe_with(script: &Path, input: &Path, output: &Path, args: &[&str]) -> Output {
+    let server = MockServer::with_script(Script::from_file(script)).await;
+    let run = Command::new(env!("CARGO_BIN_EXE_st"))
+        .args(["--api-key", "test-key", "--url", &server.url()])
+        .arg("--input")
+        .arg(input)
+        .arg("--output")
+        .arg(output)
+        .args(args)
+        // Away from any config file of whoever runs the tests.
+        .env("XDG_CONFIG_HOME", temp("config"))
+        .output()
+        .await
+        .unwrap();
+    assert!(run.status.success(), "{:?}", run);
+    run
+}
+
+/// The subtitles at `path`, which are removed.
+fn take(path: &Path) -> String {
+    let srt = std::fs::read_to_string(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    srt
+}
+
+#[tokio::test]
+async fn transcribes_a_file_to_subtitles() {
+    let output = temp("input.srt");
+    // The fixture is 8 kHz, resampled to the 16 kHz the session is started at.
+    let run = transcribe(&fixture("input.wav"), &output, &["--speed", "0"]).await;
+    assert_eq!(
+        take(&output),
+        std::fs::read_to_string(fixture("input.srt")).unwrap()
+    );
+
+    // Off a terminal, progress is printed a line at a time, followed by the summary.
+    let progress = String::from_utf8(run.stderr).unwrap();
+    assert!(
+        progress.contains("Sent 100% of the file, 2 sentences received\nAudio captured: "),
+        "{}",
+        progress
+    );
+}
+
+#[tokio::test]
+async fn stops_after_the_duration() {
+    let input = silence("duration.wav", 10);
+    let output = temp("duration.srt");
+    let started = Instant::now();
+    let run = transcribe(&input, &output, &["--duration", "1s"]).await;
+    std::fs::remove_file(&input).unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    // What was recognized before stopping is written all the same.
+    assert_eq!(
+        take(&output),
+        std::fs::read_to_string(fixture("input.srt")).unwrap()
+    );
+    let progress = String::from_utf8(run.stderr).unwrap();
+    assert!(!progress.contains("Sent 100%"), "{}", progress);
+}
+
+#[tokio::test]
+async fn stops_after_prolonged_silence() {
+    let input = silence("silence.wav", 10);
+    let output = temp("silence.srt");
+    let started = Instant::now();
+    let run = transcribe(&input, &output, &["--stop-after-silence", "1"]).await;
+    std::fs::remove_file(&input).unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(
+        take(&output),
+        std::fs::read_to_string(fixture("input.srt")).unwrap()
+    );
+    let progress = String::from_utf8(run.stderr).unwrap();
+    assert!(!progress.contains("Sent 100%"), "{}", progress);
+}
+
+#[tokio::test]
+async fn bundles_a_run_in_a_session_directory() {
+    let base = temp("sessions");
+    let input = fixture("input.wav");
+    // The file named with `--output` is saved in the directory under its name.
+    let args = ["--speed", "0", "--session-dir", base.to_str().unwrap()];
+    transcribe(&input, Path::new("out/captions.srt"), &args).await;
+
+    let runs: Vec<_> = std::fs::read_dir(&base)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(runs.len(), 1);
+    let dir = &runs[0];
+    // Named like 2024-06-01_14-03-22.
+    let name = dir.file_name().unwrap().to_str().unwrap();
+    assert!(
+        name.len() == 19
+            && name.char_indices().all(|(i, c)| match i {
+                4 | 7 | 13 | 16 => c == '-',
+                10 => c == '_',
+                _ => c.is_ascii_digit(),
+            }),
+        "{}",
+        name
+    );
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        [
+            "audio.pcm",
+            "audio.pcm.json",
+            "audio.wav",
+            "captions.srt",
+            "events.jsonl",
+            "meta.json"
+        ]
+    );
+    assert_eq!(
+        std::fs::read_to_string(dir.join("captions.srt")).unwrap(),
+        std::fs::read_to_string(fixture("input.srt")).unwrap()
+    );
+    let events = std::fs::read_to_string(dir.join("events.jsonl")).unwrap();
+    let finals = events
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .filter(|event| event["type"] == "final")
+        .count();
+    assert_eq!(finals, 2, "{}", events);
+
+    let meta: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(dir.join("meta.json")).unwrap()).unwrap();
+    assert_eq!(meta["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(meta["status"], "finished");
+    assert_eq!(meta["device"], input.to_str().unwrap());
+    assert!(meta["target_languages"].is_array(), "{}", meta);
+    assert!(meta["model"].is_string(), "{}", meta);
+    assert_eq!(
+        meta["task_ids"].as_array().map(Vec::len),
+        Some(1),
+        "{}",
+        meta
+    );
+    // The usage is that of the audio sent, which is what the raw file holds.
+    let raw_bytes = std::fs::metadata(dir.join("audio.pcm")).unwrap().len();
+    let sample_rate = meta["sample_rate"].as_u64().unwrap();
+    assert_eq!(
+        meta["usage"]["audio_ms"],
+        raw_bytes / 2 * 1000 / sample_rate
+    );
+    let started = meta["started_at_ms"].as_u64().unwrap();
+    let ended = meta["ended_at_ms"].as_u64().unwrap();
+    assert_eq!(meta["duration_ms"], ended - started);
+    std::fs::remove_dir_all(&base).unwrap();
+}
+
+#[tokio::test]
+async fn retranscribes_a_sentence_of_a_session_directory() {
+    let base = temp("retranscribed");
+    let args = ["--speed", "0", "--session-dir", base.to_str().unwrap()];
+    transcribe(&fixture("input.wav"), Path::new("captions.srt"), &args).await;
+    let dir = std::fs::read_dir(&base)
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+
+    let server = MockServer::with_script(Script::from_file(fixture("retranscribe.json"))).await;
+    let run = Command::new(env!("CARGO_BIN_EXE_st"))
+        .args(["--api-key", "test-key", "--url", &server.url()])
+        .arg("retranscribe")
+        .arg(&dir)
+        .args(["--sentence", "1"])
+        .env("XDG_CONFIG_HOME", temp("config"))
+        .output()
+        .await
+        .unwrap();
+    assert!(run.status.success(), "{:?}", run);
+    assert_eq!(server.run_tasks().len(), 1);
+
+    // The second sentence anew, at its time in the session.
+    assert_eq!(
+        std::fs::read_to_string(dir.join("captions.srt")).unwrap(),
+        std::fs::read_to_string(fixture("input.srt"))
+            .unwrap()
+            .replace("Two, three.", "Two, three!")
+    );
+    let meta: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(dir.join("meta.json")).unwrap()).unwrap();
+    assert_eq!(meta["revision"], 1);
+    std::fs::remove_dir_all(&base).unwrap();
+}
+
+#[tokio::test]
+async fn carries_on_after_the_connection_drops() {
+    let output = temp("reconnect.srt");
+    // The first connection drops after a sentence; the next one runs the whole script again.
+    let run = transcribe_with(
+        &fixture("reconnect.json"),
+        &fixture("input.wav"),
+        &output,
+        &["--speed", "1"],
+    )
+    .await;
+    // Sentences go on from where the first connection left off; the last cue is held for
+    // the shortest a cue lasts.
+    assert_eq!(
+        take(&output),
+        "1\n00:00:00,000 --> 00:00:00,100\nOne.\n\n\
+         2\n00:00:00,100 --> 00:00:00,200\nOne.\n\n\
+         3\n00:00:00,200 --> 00:00:00,300\nTwo.\n\n\
+         4\n00:00:00,300 --> 00:00:00,800\nThree.\n\n"
+    );
+    let log = String::from_utf8(run.stderr).unwrap();
+    assert!(log.contains("4 sentences received"), "{}", log);
+}
+
+#[tokio::test]
+async fn emits_the_events_as_json_lines() {
+    let output = temp("emit.srt");
+    let input = fixture("input.wav");
+    let args = [
+        "--speed",
+        "0",
+        "--emit",
+        "ndjson",
+        "--price-per-second",
+        "0.001",
+    ];
+    let run = transcribe(&input, &output, &args).await;
+    // The transcript is written alongside.
+    assert_eq!(
+        take(&output),
+        std::fs::read_to_string(fixture("input.srt")).unwrap()
+    );
+
+    let stdout = String::from_utf8(run.stdout).unwrap();
+    let lines: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert!(lines.iter().all(|line| line["v"] == 1), "{}", stdout);
+    let types: Vec<_> = lines
+        .iter()
+        .map(|line| line["type"].as_str().unwrap())
+        .filter(|kind| *kind != "partial")
+        .collect();
+    assert_eq!(
+        types,
+        [
+            "session-started",
+            "sentence-final",
+            "sentence-final",
+            "task-finished",
+            "session-ended"
+        ],
+        "{}",
+        stdout
+    );
+    assert_eq!(lines[0]["device"], input.to_str().unwrap());
+    assert!(lines[0]["session"]["sample_rate"].is_u64(), "{}", stdout);
+    let finals: Vec<_> = lines
+        .iter()
+        .filter(|line| line["type"] == "sentence-final")
+        .map(|line| line["sentence"]["text"].as_str().unwrap())
+        .collect();
+    assert_eq!(finals, ["Testing, one.", "Two, three."]);
+    let ended = lines.last().unwrap();
+    assert_eq!(ended["sentences"], 2);
+    assert!(
+        ended["usage"]["audio_ms"].as_u64().unwrap() > 0,
+        "{}",
+        stdout
+    );
+    // The server reports what it bills, by the second.
+    let summary = &ended["summary"];
+    assert_eq!(summary["estimated"], false, "{}", stdout);
+    assert_eq!(
+        summary["billed_ms"].as_u64().unwrap() % 1000,
+        0,
+        "{}",
+        stdout
+    );
+    assert_eq!(summary["sentences"], 2);
+    assert_eq!(summary["cost"]["currency"], "CNY");
+    // Whatever is said for people goes to stderr.
+    let stderr = String::from_utf8(run.stderr).unwrap();
+    assert!(stderr.contains("2 sentences received"), "{}", stderr);
+    assert!(stderr.contains("Estimated cost: 0.00"), "{}", stderr);
+}
+
+#[tokio::test]
+async fn records_when_sentences_were_spoken_across_a_reconnection() {
+    let output = temp("reconnect.jsonl");
+    let before = chrono::Utc::now();
+    transcribe_with(
+        &fixture("reconnect.json"),
+        &fixture("input.wav"),
+        &output,
+        &["--speed", "1"],
+    )
+    .await;
+    let after = chrono::Utc::now();
+    let lines: Vec<serde_json::Value> = take(&output)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 4);
+    let at = |line: &serde_json::Value, field: &str| {
+        let time = line[field].as_str().unwrap();
+        assert!(time.ends_with('Z'), "{}", time);
+        chrono::DateTime::parse_from_rfc3339(time).unwrap()
+    };
+    // Every sentence is placed within the run, in order, the anchor carrying over the
+    // reconnection.
+    let mut last = before.fixed_offset();
+    for line in &lines {
+        let (begin_at, end_at) = (at(line, "begin_at"), at(line, "end_at"));
+        assert!(
+            last <= begin_at && begin_at <= end_at && end_at <= after,
+            "{:?}",
+            lines
+        );
+        last = begin_at;
+    }
+    // Sentences of the new task are placed by the audio sent before, as estimates.
+    assert!(lines[0].get("estimated").is_none());
+    assert_eq!(lines[3]["estimated"], true);
+}
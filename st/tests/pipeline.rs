@@ -0,0 +1,121 @@
+//! The whole pipeline on a tone: audio read from a file, framed and sent by the real client
+//! to the mock server, whose results are keyed off the audio it received, and written out
+//! as subtitles and a JSON document checked against golden files. Nothing here needs audio
+//! hardware or the network.
+
+use gummy_mock::{MockServer, Script};
+use serde_json::Value;
+use std::f32::consts::PI;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn temp(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("st-{}-pipeline-{}", std::process::id(), name))
+}
+
+/// Writes `seconds` of a 440 Hz tone at 16 kHz to a WAV file.
+fn tone(name: &str, seconds: u32) -> PathBuf {
+    let path = temp(name);
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: 16000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+    for i in 0..seconds * 16000 {
+        let t = i as f32 / 16000.0;
+        let sample = (2.0 * PI * 440.0 * t).sin() * 0.5 * i16::MAX as f32;
+        writer.write_sample(sample as i16).unwrap();
+    }
+    writer.finalize().unwrap();
+    path
+}
+
+/// `path` read and removed.
+fn take(path: &Path) -> String {
+    let content = std::fs::read_to_string(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    content
+}
+
+/// `document` with what changes from run to run replaced: when the session and its
+/// sentences started, the latency of its results and the ID of its task.
+fn normalized(document: &str) -> String {
+    let parsed: Value = serde_json::from_str(document).unwrap();
+    let session = &parsed["session"];
+    let task_ids = session["task_ids"].as_array().unwrap();
+    assert_eq!(task_ids.len(), 1);
+    let field = |name: &str, value: &Value| format!("\"{}\": {}", name, value);
+    let mut document = document.to_string();
+    for sentence in parsed["sentences"].as_array().unwrap() {
+        for name in ["begin_at", "end_at"] {
+            document = document.replace(
+                &field(name, &sentence[name]),
+                &field(name, &"1970-01-01T00:00:00.000Z".into()),
+            );
+        }
+    }
+    document
+        .replace(
+            &field("started_at_ms", &session["started_at_ms"]),
+            &field("started_at_ms", &0.into()),
+        )
+        .replace(
+            &field("latency_ms", &session["summary"]["latency_ms"]),
+            &field("latency_ms", &0.into()),
+        )
+        .replace(&task_ids[0].to_string(), "\"task\"")
+}
+
+#[tokio::test]
+async fn transcribes_a_tone_into_golden_files() {
+    let server = MockServer::with_script(Script::from_file(fixture("pipeline.script.json"))).await;
+    let input = tone("tone.wav", 3);
+    let srt = temp("tone.srt");
+    let json = temp("tone.json");
+    let run = Command::new(env!("CARGO_BIN_EXE_st"))
+        .args(["--api-key", "test-key", "--url", &server.url()])
+        .args([
+            "--target-lang",
+            "zh",
+            "--subtitle-translation",
+            "--speed",
+            "0",
+            "--sample-rate",
+            "16000",
+        ])
+        .arg("--input")
+        .arg(&input)
+        .arg("--output")
+        .arg(&srt)
+        .arg("--output")
+        .arg(&json)
+        // Away from any config file of whoever runs the tests.
+        .env("XDG_CONFIG_HOME", temp("config"))
+        .output()
+        .await
+        .unwrap();
+    std::fs::remove_file(&input).unwrap();
+    assert!(run.status.success(), "{:?}", run);
+
+    // The tone is sent as it is, at its own rate.
+    let run_tasks = server.run_tasks();
+    assert_eq!(run_tasks.len(), 1);
+    assert_eq!(run_tasks[0]["payload"]["parameters"]["sample_rate"], 16000);
+
+    assert_eq!(
+        take(&srt),
+        std::fs::read_to_string(fixture("pipeline.srt")).unwrap()
+    );
+    assert_eq!(
+        normalized(&take(&json)),
+        std::fs::read_to_string(fixture("pipeline.json")).unwrap()
+    );
+}
@@ -0,0 +1,68 @@
+//! `st replay` writing transcripts again from the events a session directory recorded,
+//! which pins down what the formats make of a session.
+
+use assert_cmd::Command;
+use std::path::{Path, PathBuf};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// `st` away from any config file of whoever runs the tests.
+fn st() -> Command {
+    let config = std::env::temp_dir().join(format!("st-{}-replay-config", std::process::id()));
+    let mut st = Command::new(env!("CARGO_BIN_EXE_st"));
+    st.env("XDG_CONFIG_HOME", config).env_remove("API_KEY");
+    st
+}
+
+#[test]
+fn writes_subtitles_from_recorded_events() {
+    let output = std::env::temp_dir().join(format!("st-{}-replay.srt", std::process::id()));
+    st().arg("replay")
+        .arg(fixture("replay.jsonl"))
+        .args(["--format", "srt", "-o"])
+        .arg(&output)
+        .assert()
+        .success();
+    let written = std::fs::read_to_string(&output).unwrap();
+    std::fs::remove_file(&output).unwrap();
+    assert_eq!(
+        written,
+        std::fs::read_to_string(fixture("replay.srt")).unwrap()
+    );
+}
+
+#[test]
+fn replays_what_can_be_read_of_a_log_cut_short() {
+    let dir = std::env::temp_dir().join(format!("st-{}-replay-cut", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let events = std::fs::read_to_string(fixture("replay.jsonl")).unwrap();
+    // Cut in the middle of the line finalizing the second sentence.
+    let cut = events.find("\"Let's get started.\"").unwrap();
+    std::fs::write(dir.join("events.jsonl"), &events[..cut]).unwrap();
+    let output = dir.join("transcript.srt");
+    let assert = st()
+        .arg("replay")
+        .arg(dir.join("events.jsonl"))
+        .arg("-o")
+        .arg(&output)
+        .assert()
+        .success();
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr).into_owned();
+    assert!(
+        stderr.starts_with("Warning: replaying the 4 events before line 5"),
+        "{}",
+        stderr
+    );
+    let written = std::fs::read_to_string(&output).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    // The second sentence as it last came in.
+    assert_eq!(
+        written,
+        "1\n00:00:00,200 --> 00:00:02,400\nGood morning, everyone.\n\n\
+         2\n00:00:02,600 --> 00:00:03,300\nLet's get\n\n"
+    );
+}